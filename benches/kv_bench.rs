@@ -15,6 +15,7 @@ struct BenchContext {
   _temp_dir: TempDir,
 }
 
+#[allow(clippy::field_reassign_with_default)]
 fn setup_engine() -> BenchContext {
   let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
   let mut option = Options::default();
@@ -41,6 +42,7 @@ where
   c.bench_function(name, move |b| bench_fn(b, &engine));
 }
 
+#[allow(clippy::field_reassign_with_default)]
 fn bench_put(c: &mut Criterion) {
   let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
   let mut option = Options::default();
@@ -80,6 +82,7 @@ fn bench_get_miss(c: &mut Criterion) {
   });
 }
 
+#[allow(clippy::missing_const_for_thread_local)]
 fn bench_delete_hit(c: &mut Criterion) {
   run_bench_with_context(c, "flash-kv-delete-hit-bench", |b, engine| {
     use std::cell::Cell;
@@ -0,0 +1,172 @@
+use std::{
+  collections::HashMap,
+  future::{ready, Future, Ready},
+  pin::Pin,
+  sync::{Arc, Mutex},
+};
+
+use actix_web::{
+  body::{BodySize, MessageBody},
+  dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+  get,
+  http::header::CONTENT_LENGTH,
+  web, Error, HttpResponse, Responder,
+};
+use serde::Serialize;
+
+/// Header a client can set to be accounted under its own name instead of its address.
+pub const CLIENT_ID_HEADER: &str = "x-client-id";
+
+// clients beyond this are accounted together so the table can't grow without bound
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+const OVERFLOW_CLIENT: &str = "other";
+
+/// Counters of a single client.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct ClientCounters {
+  pub ops: u64,
+  pub bytes_in: u64,
+  pub bytes_out: u64,
+  pub errors: u64,
+}
+
+/// Per client request accounting, shared by all workers.
+#[derive(Default)]
+pub struct ClientStats {
+  clients: Mutex<HashMap<String, ClientCounters>>,
+}
+
+impl ClientStats {
+  fn record(&self, client: String, bytes_in: u64, bytes_out: u64, is_error: bool) {
+    let mut clients = match self.clients.lock() {
+      Ok(clients) => clients,
+      Err(poisoned) => poisoned.into_inner(),
+    };
+    let client = if clients.len() >= MAX_TRACKED_CLIENTS && !clients.contains_key(&client) {
+      OVERFLOW_CLIENT.to_string()
+    } else {
+      client
+    };
+    let counters = clients.entry(client).or_default();
+    counters.ops += 1;
+    counters.bytes_in += bytes_in;
+    counters.bytes_out += bytes_out;
+    if is_error {
+      counters.errors += 1;
+    }
+  }
+
+  /// Returns a copy of the counters of all clients.
+  pub fn snapshot(&self) -> HashMap<String, ClientCounters> {
+    match self.clients.lock() {
+      Ok(clients) => clients.clone(),
+      Err(poisoned) => poisoned.into_inner().clone(),
+    }
+  }
+}
+
+/// Middleware recording ops, bytes and errors of every request into [`ClientStats`].
+pub struct ClientAccounting {
+  stats: Arc<ClientStats>,
+}
+
+impl ClientAccounting {
+  pub fn new(stats: Arc<ClientStats>) -> Self {
+    Self { stats }
+  }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ClientAccounting
+where
+  S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+  B: MessageBody + 'static,
+{
+  type Response = ServiceResponse<B>;
+  type Error = Error;
+  type Transform = ClientAccountingMiddleware<S>;
+  type InitError = ();
+  type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+  fn new_transform(&self, service: S) -> Self::Future {
+    ready(Ok(ClientAccountingMiddleware {
+      service,
+      stats: self.stats.clone(),
+    }))
+  }
+}
+
+pub struct ClientAccountingMiddleware<S> {
+  service: S,
+  stats: Arc<ClientStats>,
+}
+
+impl<S, B> Service<ServiceRequest> for ClientAccountingMiddleware<S>
+where
+  S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+  B: MessageBody + 'static,
+{
+  type Response = ServiceResponse<B>;
+  type Error = Error;
+  #[allow(clippy::type_complexity)]
+  type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+  forward_ready!(service);
+
+  fn call(&self, req: ServiceRequest) -> Self::Future {
+    let client = client_id(&req);
+    let bytes_in = req
+      .headers()
+      .get(CONTENT_LENGTH)
+      .and_then(|v| v.to_str().ok())
+      .and_then(|v| v.parse::<u64>().ok())
+      .unwrap_or(0);
+    let stats = self.stats.clone();
+    let fut = self.service.call(req);
+
+    Box::pin(async move {
+      let res = fut.await;
+      let (bytes_out, is_error) = match &res {
+        Ok(res) => {
+          let bytes_out = match res.response().body().size() {
+            BodySize::Sized(n) => n,
+            _ => 0,
+          };
+          let status = res.status();
+          (
+            bytes_out,
+            status.is_client_error() || status.is_server_error(),
+          )
+        }
+        Err(_) => (0, true),
+      };
+      stats.record(client, bytes_in, bytes_out, is_error);
+      res
+    })
+  }
+}
+
+/// The client id header if present, otherwise the peer address.
+fn client_id(req: &ServiceRequest) -> String {
+  if let Some(id) = req
+    .headers()
+    .get(CLIENT_ID_HEADER)
+    .and_then(|v| v.to_str().ok())
+    .filter(|v| !v.is_empty())
+  {
+    return id.to_string();
+  }
+  match req.peer_addr() {
+    Some(addr) => addr.ip().to_string(),
+    None => String::from("unknown"),
+  }
+}
+
+#[get("/admin/clients")]
+pub async fn clients_handler(stats: web::Data<Arc<ClientStats>>) -> impl Responder {
+  match serde_json::to_string(&stats.snapshot()) {
+    Ok(body) => HttpResponse::Ok()
+      .content_type("application/json")
+      .body(body),
+    Err(_) => HttpResponse::InternalServerError().body("failed to encode client stats"),
+  }
+}
@@ -0,0 +1,97 @@
+use std::{
+  future::{ready, Future, Ready},
+  pin::Pin,
+  sync::Arc,
+};
+
+use actix_web::{
+  body::{EitherBody, MessageBody},
+  dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+  http::{
+    header::{AUTHORIZATION, WWW_AUTHENTICATE},
+    Method,
+  },
+  Error, HttpResponse,
+};
+
+/// Middleware requiring `Authorization: Bearer <token>` on requests which modify the
+/// database, reads stay open. Without a token every request is let through.
+pub struct BearerAuth {
+  token: Option<Arc<str>>,
+}
+
+impl BearerAuth {
+  pub fn new(token: Option<String>) -> Self {
+    Self {
+      token: token.map(Arc::from),
+    }
+  }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for BearerAuth
+where
+  S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+  B: MessageBody + 'static,
+{
+  type Response = ServiceResponse<EitherBody<B>>;
+  type Error = Error;
+  type Transform = BearerAuthMiddleware<S>;
+  type InitError = ();
+  type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+  fn new_transform(&self, service: S) -> Self::Future {
+    ready(Ok(BearerAuthMiddleware {
+      service,
+      token: self.token.clone(),
+    }))
+  }
+}
+
+pub struct BearerAuthMiddleware<S> {
+  service: S,
+  token: Option<Arc<str>>,
+}
+
+impl<S, B> Service<ServiceRequest> for BearerAuthMiddleware<S>
+where
+  S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+  B: MessageBody + 'static,
+{
+  type Response = ServiceResponse<EitherBody<B>>;
+  type Error = Error;
+  #[allow(clippy::type_complexity)]
+  type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+  forward_ready!(service);
+
+  fn call(&self, req: ServiceRequest) -> Self::Future {
+    let authorized = match &self.token {
+      Some(token) if is_mutating(req.method()) => bearer_token(&req)
+        .is_some_and(|presented| constant_time_eq(presented.as_bytes(), token.as_bytes())),
+      _ => true,
+    };
+    if !authorized {
+      let response = HttpResponse::Unauthorized()
+        .insert_header((WWW_AUTHENTICATE, "Bearer"))
+        .body("missing or invalid bearer token");
+      return Box::pin(ready(Ok(req.into_response(response).map_into_right_body())));
+    }
+
+    let fut = self.service.call(req);
+    Box::pin(async move { Ok(fut.await?.map_into_left_body()) })
+  }
+}
+
+fn is_mutating(method: &Method) -> bool {
+  !matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+fn bearer_token(req: &ServiceRequest) -> Option<&str> {
+  let value = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+  value.strip_prefix("Bearer ")
+}
+
+// compares without leaking the length of the matching prefix through timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+  a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
@@ -0,0 +1,287 @@
+use std::{
+  sync::Arc,
+  time::{Duration, Instant},
+};
+
+use actix_web::{
+  http::header::CONTENT_TYPE,
+  post,
+  web::{self, Bytes, BytesMut},
+  HttpRequest, HttpResponse, Responder,
+};
+use flash_kv::{db::Engine, errors::Errors, option::WriteBatchOptions};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+/// Content type of the binary framing: a varint key length, a varint value length, then the
+/// key and value bytes, repeated. Any other content type is read as JSON lines of
+/// `{"key": "...", "value": "..."}`.
+pub const BINARY_CONTENT_TYPE: &str = "application/octet-stream";
+
+// pairs committed by one write batch
+const CHUNK_SIZE: usize = 1024;
+
+// a single line or frame may not buffer more than this
+const MAX_FRAME_LEN: usize = 64 * 1024 * 1024;
+
+// while the engine sheds writes the chunk is retried, reading of the body pauses meanwhile
+const BACKPRESSURE_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+const MAX_BACKPRESSURE_WAIT: Duration = Duration::from_secs(30);
+
+/// Outcome of a bulk load, also returned with the error of a failed one.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct BulkLoadSummary {
+  /// Pairs committed
+  pub entries: u64,
+
+  /// Key and value bytes committed
+  pub bytes: u64,
+
+  /// Write batches committed
+  pub batches: u64,
+
+  pub elapsed_ms: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Framing {
+  JsonLines,
+  Binary,
+}
+
+#[derive(Deserialize)]
+struct JsonPair {
+  key: String,
+  value: String,
+}
+
+enum BulkLoadError {
+  /// The body does not follow its framing
+  Invalid(String),
+  Engine(Errors),
+
+  /// The blocking task writing to the engine did not finish
+  Stopped,
+}
+
+/// Splits the streamed body into key/value pairs, across chunk boundaries.
+struct Decoder {
+  framing: Framing,
+  buf: BytesMut,
+}
+
+impl Decoder {
+  fn extend(&mut self, chunk: &[u8]) {
+    self.buf.extend_from_slice(chunk);
+  }
+
+  /// Next complete pair of the buffered body, `None` until more of the body arrived.
+  fn next_pair(&mut self) -> Result<Option<(Bytes, Bytes)>, BulkLoadError> {
+    match self.framing {
+      Framing::JsonLines => loop {
+        let Some(end) = self.buf.iter().position(|b| *b == b'\n') else {
+          return self.check_buffered();
+        };
+        let line = self.buf.split_to(end + 1);
+        if let Some(pair) = parse_json_line(&line)? {
+          return Ok(Some(pair));
+        }
+      },
+      Framing::Binary => {
+        let Some((key_len, key_len_size)) = read_varint(&self.buf)? else {
+          return Ok(None);
+        };
+        let Some((value_len, value_len_size)) = read_varint(&self.buf[key_len_size..])? else {
+          return Ok(None);
+        };
+        if key_len == 0 {
+          return Err(BulkLoadError::Invalid("key is empty".to_string()));
+        }
+        if key_len.saturating_add(value_len) > MAX_FRAME_LEN {
+          return Err(BulkLoadError::Invalid("frame is too large".to_string()));
+        }
+        let header_len = key_len_size + value_len_size;
+        if self.buf.len() < header_len + key_len + value_len {
+          return Ok(None);
+        }
+        let _ = self.buf.split_to(header_len);
+        let key = self.buf.split_to(key_len).freeze();
+        let value = self.buf.split_to(value_len).freeze();
+        Ok(Some((key, value)))
+      }
+    }
+  }
+
+  /// Last pair once the whole body was read, a JSON line may lack its newline.
+  fn finish(&mut self) -> Result<Option<(Bytes, Bytes)>, BulkLoadError> {
+    if self.buf.is_empty() {
+      return Ok(None);
+    }
+    match self.framing {
+      Framing::JsonLines => {
+        let line = self.buf.split();
+        parse_json_line(&line)
+      }
+      Framing::Binary => Err(BulkLoadError::Invalid(
+        "body ends within a frame".to_string(),
+      )),
+    }
+  }
+
+  fn check_buffered(&self) -> Result<Option<(Bytes, Bytes)>, BulkLoadError> {
+    match self.buf.len() > MAX_FRAME_LEN {
+      true => Err(BulkLoadError::Invalid("line is too long".to_string())),
+      false => Ok(None),
+    }
+  }
+}
+
+// blank lines are skipped
+fn parse_json_line(line: &[u8]) -> Result<Option<(Bytes, Bytes)>, BulkLoadError> {
+  if line.iter().all(u8::is_ascii_whitespace) {
+    return Ok(None);
+  }
+  let pair: JsonPair =
+    serde_json::from_slice(line).map_err(|e| BulkLoadError::Invalid(e.to_string()))?;
+  if pair.key.is_empty() {
+    return Err(BulkLoadError::Invalid("key is empty".to_string()));
+  }
+  Ok(Some((Bytes::from(pair.key), Bytes::from(pair.value))))
+}
+
+// decodes a varint, `None` if it is not complete yet
+fn read_varint(buf: &[u8]) -> Result<Option<(usize, usize)>, BulkLoadError> {
+  let mut value: u64 = 0;
+  for (i, byte) in buf.iter().enumerate().take(10) {
+    value |= u64::from(byte & 0x7f) << (7 * i);
+    if byte & 0x80 == 0 {
+      return Ok(Some((value as usize, i + 1)));
+    }
+  }
+  match buf.len() >= 10 {
+    true => Err(BulkLoadError::Invalid("invalid length".to_string())),
+    false => Ok(None),
+  }
+}
+
+/// Loads the pairs streamed in the request body, see [`BINARY_CONTENT_TYPE`] for the
+/// framings. Pairs are committed in write batches of 1024 as the body arrives, a failed load
+/// keeps the batches committed before the failure.
+#[post("/bulkload")]
+pub async fn bulkload_handler(
+  eng: web::Data<Arc<Engine>>,
+  req: HttpRequest,
+  mut payload: web::Payload,
+) -> impl Responder {
+  let framing = match req.headers().get(CONTENT_TYPE) {
+    Some(content_type)
+      if content_type
+        .as_bytes()
+        .starts_with(BINARY_CONTENT_TYPE.as_bytes()) =>
+    {
+      Framing::Binary
+    }
+    _ => Framing::JsonLines,
+  };
+  let mut decoder = Decoder {
+    framing,
+    buf: BytesMut::new(),
+  };
+  let start = Instant::now();
+  let mut summary = BulkLoadSummary::default();
+  let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+
+  let result = async {
+    while let Some(body) = payload.next().await {
+      let body = body.map_err(|e| BulkLoadError::Invalid(e.to_string()))?;
+      decoder.extend(&body);
+      while let Some(pair) = decoder.next_pair()? {
+        chunk.push(pair);
+        if chunk.len() == CHUNK_SIZE {
+          commit_chunk(&eng, &mut chunk, &mut summary).await?;
+        }
+      }
+    }
+    if let Some(pair) = decoder.finish()? {
+      chunk.push(pair);
+    }
+    commit_chunk(&eng, &mut chunk, &mut summary).await?;
+
+    let engine = eng.get_ref().clone();
+    web::block(move || engine.sync())
+      .await
+      .map_err(|_| BulkLoadError::Stopped)?
+      .map_err(BulkLoadError::Engine)
+  }
+  .await;
+
+  summary.elapsed_ms = start.elapsed().as_millis() as u64;
+  match result {
+    Ok(()) => HttpResponse::Ok().json(summary),
+    Err(e) => {
+      let (mut response, message) = match e {
+        BulkLoadError::Invalid(message) => (HttpResponse::BadRequest(), message),
+        BulkLoadError::Engine(Errors::Backpressure) => {
+          let mut response = HttpResponse::ServiceUnavailable();
+          response.insert_header(("Retry-After", "1"));
+          (response, Errors::Backpressure.to_string())
+        }
+        BulkLoadError::Engine(e) => (HttpResponse::InternalServerError(), e.to_string()),
+        BulkLoadError::Stopped => (
+          HttpResponse::InternalServerError(),
+          "bulk load task stopped".to_string(),
+        ),
+      };
+      response.json(json!({ "error": message, "summary": summary }))
+    }
+  }
+}
+
+// commits the pairs as one write batch on the blocking pool, retrying while the engine sheds
+// writes
+async fn commit_chunk(
+  eng: &web::Data<Arc<Engine>>,
+  chunk: &mut Vec<(Bytes, Bytes)>,
+  summary: &mut BulkLoadSummary,
+) -> Result<(), BulkLoadError> {
+  if chunk.is_empty() {
+    return Ok(());
+  }
+  let pairs = Arc::new(std::mem::take(chunk));
+  let deadline = Instant::now() + MAX_BACKPRESSURE_WAIT;
+  loop {
+    let engine = eng.get_ref().clone();
+    let batch_pairs = pairs.clone();
+    let result = web::block(move || commit_batch(&engine, &batch_pairs))
+      .await
+      .map_err(|_| BulkLoadError::Stopped)?;
+    match result {
+      Ok(()) => break,
+      Err(Errors::Backpressure) if Instant::now() < deadline => {
+        actix_web::rt::time::sleep(BACKPRESSURE_RETRY_INTERVAL).await;
+      }
+      Err(e) => return Err(BulkLoadError::Engine(e)),
+    }
+  }
+
+  summary.entries += pairs.len() as u64;
+  summary.bytes += pairs
+    .iter()
+    .map(|(key, value)| (key.len() + value.len()) as u64)
+    .sum::<u64>();
+  summary.batches += 1;
+  Ok(())
+}
+
+fn commit_batch(engine: &Engine, pairs: &[(Bytes, Bytes)]) -> Result<(), Errors> {
+  let batch = engine.new_write_batch(WriteBatchOptions {
+    max_batch_num: pairs.len(),
+    max_batch_bytes: 0,
+    sync_writes: false,
+  })?;
+  for (key, value) in pairs {
+    batch.put(key.clone(), value.clone())?;
+  }
+  batch.commit()
+}
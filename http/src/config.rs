@@ -0,0 +1,88 @@
+use std::{fs::File, io, io::BufReader, path::PathBuf, time::Duration};
+
+use clap::Parser;
+
+use crate::group_commit::GroupCommitOptions;
+
+/// Configuration of the server, read from the command line or the environment.
+#[derive(Debug, Clone, Parser)]
+#[command(about = "HTTP server for a flash-kv database")]
+pub struct ServerConfig {
+  /// Address the server listens on
+  #[arg(long, env = "FLASH_KV_BIND", default_value = "127.0.0.1:8080")]
+  pub bind: String,
+
+  /// Directory of the database
+  #[arg(long, env = "FLASH_KV_DIR", default_value = "/tmp/flash-kv-http")]
+  pub dir_path: PathBuf,
+
+  /// PEM certificate chain, serves HTTPS together with `tls_key`
+  #[arg(long, env = "FLASH_KV_TLS_CERT", requires = "tls_key")]
+  pub tls_cert: Option<PathBuf>,
+
+  /// PEM private key of the certificate
+  #[arg(long, env = "FLASH_KV_TLS_KEY", requires = "tls_cert")]
+  pub tls_key: Option<PathBuf>,
+
+  /// Bearer token required by requests which modify the database
+  #[arg(long, env = "FLASH_KV_AUTH_TOKEN", hide_env_values = true)]
+  pub auth_token: Option<String>,
+
+  /// Number of worker threads, defaults to the number of CPUs
+  #[arg(long, env = "FLASH_KV_WORKERS")]
+  pub workers: Option<usize>,
+
+  /// Seconds an idle keep-alive connection is kept open
+  #[arg(long, env = "FLASH_KV_KEEP_ALIVE_SECS", default_value_t = 5)]
+  pub keep_alive_secs: u64,
+
+  /// Largest accepted JSON body in bytes
+  #[arg(long, env = "FLASH_KV_MAX_PAYLOAD", default_value_t = 2 * 1024 * 1024)]
+  pub max_payload: usize,
+
+  /// Key/value pairs after which a group of concurrent puts is committed, 0 commits every
+  /// put on its own
+  #[arg(long, env = "FLASH_KV_GROUP_COMMIT_MAX_OPS", default_value_t = 1024)]
+  pub group_commit_max_ops: usize,
+
+  /// Microseconds the first put of a group waits for concurrent puts to join
+  #[arg(long, env = "FLASH_KV_GROUP_COMMIT_DELAY_US", default_value_t = 1000)]
+  pub group_commit_delay_us: u64,
+
+  /// Send a few requests to the server once it is started
+  #[arg(long)]
+  pub demo: bool,
+}
+
+impl ServerConfig {
+  /// Group commit options of the puts, `None` if group commit is disabled.
+  pub fn group_commit(&self) -> Option<GroupCommitOptions> {
+    (self.group_commit_max_ops > 0).then(|| GroupCommitOptions {
+      window: Duration::from_micros(self.group_commit_delay_us),
+      max_batch_ops: self.group_commit_max_ops,
+      ..Default::default()
+    })
+  }
+
+  /// Loads the TLS certificate and key, `None` if the server serves plain HTTP.
+  pub fn tls_config(&self) -> io::Result<Option<rustls::ServerConfig>> {
+    let (Some(cert_path), Some(key_path)) = (&self.tls_cert, &self.tls_key) else {
+      return Ok(None);
+    };
+
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert_path)?))
+      .collect::<io::Result<Vec<_>>>()?;
+    let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(key_path)?))?
+      .ok_or_else(|| invalid_data(format!("no private key in {}", key_path.display())))?;
+
+    let config = rustls::ServerConfig::builder()
+      .with_no_client_auth()
+      .with_single_cert(certs, key)
+      .map_err(|e| invalid_data(e.to_string()))?;
+    Ok(Some(config))
+  }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, msg)
+}
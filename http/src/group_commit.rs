@@ -0,0 +1,153 @@
+use std::{sync::Arc, time::Duration};
+
+use actix_web::web::Bytes;
+use flash_kv::{db::Engine, errors::Errors, option::WriteBatchOptions};
+use tokio::{
+  sync::{mpsc, oneshot},
+  time::{timeout_at, Instant},
+};
+
+/// Configuration of the server side group commit.
+#[derive(Debug, Clone)]
+pub struct GroupCommitOptions {
+  /// How long the first request of a group waits for others to join
+  pub window: Duration,
+
+  /// A group is committed as soon as it holds this many key/value pairs
+  pub max_batch_ops: usize,
+
+  /// Sync the data file once per group
+  pub sync_writes: bool,
+}
+
+impl Default for GroupCommitOptions {
+  fn default() -> Self {
+    Self {
+      window: Duration::from_millis(1),
+      max_batch_ops: 1024,
+      sync_writes: true,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GroupCommitError {
+  /// The batch of the group failed to commit
+  Engine(Errors),
+
+  /// The commit task is not running anymore
+  Stopped,
+}
+
+struct PutRequest {
+  entries: Vec<(Bytes, Bytes)>,
+  reply: oneshot::Sender<Result<(), GroupCommitError>>,
+}
+
+/// Coalesces puts of concurrent requests into shared write batches, so the group pays for a
+/// single commit and sync instead of one per request.
+pub struct GroupCommitter {
+  sender: mpsc::Sender<PutRequest>,
+}
+
+impl GroupCommitter {
+  /// Spawns the commit task on the current tokio runtime.
+  pub fn start(engine: Arc<Engine>, opts: GroupCommitOptions) -> Self {
+    let (sender, receiver) = mpsc::channel(opts.max_batch_ops.max(1));
+    tokio::spawn(run_group_commit(engine, opts, receiver));
+    Self { sender }
+  }
+
+  /// Writes the entries atomically as part of the next group, returns once it is committed.
+  pub async fn put(&self, entries: Vec<(Bytes, Bytes)>) -> Result<(), GroupCommitError> {
+    let (reply, result) = oneshot::channel();
+    self
+      .sender
+      .send(PutRequest { entries, reply })
+      .await
+      .map_err(|_| GroupCommitError::Stopped)?;
+    result.await.map_err(|_| GroupCommitError::Stopped)?
+  }
+}
+
+async fn run_group_commit(
+  engine: Arc<Engine>,
+  opts: GroupCommitOptions,
+  mut receiver: mpsc::Receiver<PutRequest>,
+) {
+  while let Some(first) = receiver.recv().await {
+    let mut ops = first.entries.len();
+    let mut group = vec![first];
+
+    // collect requests arriving within the window, a request is never split across groups
+    let deadline = Instant::now() + opts.window;
+    while ops < opts.max_batch_ops {
+      match timeout_at(deadline, receiver.recv()).await {
+        Ok(Some(request)) => {
+          ops += request.entries.len();
+          group.push(request);
+        }
+        _ => break,
+      }
+    }
+
+    let engine = engine.clone();
+    let sync_writes = opts.sync_writes;
+    let committed = tokio::task::spawn_blocking(move || {
+      let results = commit_requests(&engine, &group, ops, sync_writes);
+      (group, results)
+    })
+    .await;
+
+    // a panicked commit drops the group, its requests observe `Stopped`
+    if let Ok((group, results)) = committed {
+      for (request, result) in group.into_iter().zip(results) {
+        let _ = request.reply.send(result.map_err(GroupCommitError::Engine));
+      }
+    }
+  }
+}
+
+/// Commits the group in one batch. If that fails every request is retried in a batch of its
+/// own, so a request only observes the errors of its own entries.
+fn commit_requests(
+  engine: &Engine,
+  group: &[PutRequest],
+  ops: usize,
+  sync_writes: bool,
+) -> Vec<Result<(), Errors>> {
+  match commit_group(engine, group, ops, sync_writes) {
+    Ok(()) => vec![Ok(()); group.len()],
+    Err(e) if group.len() == 1 => vec![Err(e)],
+    Err(_) => group
+      .iter()
+      .map(|request| {
+        commit_group(
+          engine,
+          std::slice::from_ref(request),
+          request.entries.len(),
+          sync_writes,
+        )
+      })
+      .collect(),
+  }
+}
+
+fn commit_group(
+  engine: &Engine,
+  group: &[PutRequest],
+  ops: usize,
+  sync_writes: bool,
+) -> Result<(), Errors> {
+  let batch = engine.new_write_batch(WriteBatchOptions {
+    max_batch_num: ops.max(1),
+    max_batch_bytes: 0,
+    sync_writes,
+  })?;
+  for request in group {
+    for (key, value) in request.entries.iter() {
+      batch.put(key.clone(), value.clone())?;
+    }
+  }
+  batch.commit()
+}
@@ -1,18 +1,32 @@
+mod accounting;
+mod auth;
+mod bulkload;
+mod config;
+mod group_commit;
 #[cfg(test)]
 mod test;
 
+use accounting::{clients_handler, ClientAccounting, ClientStats};
 use actix_web::{
-  delete, get, post, rt::signal, web, App, HttpResponse, HttpServer, Responder, Scope,
+  delete, dev::Server, get, http::header::ContentType, post, rt::signal, web, App, HttpResponse,
+  HttpServer, Responder, Scope,
 };
+use auth::BearerAuth;
+use bulkload::bulkload_handler;
+use clap::Parser;
+use config::ServerConfig;
 use flash_kv::{db::Engine, errors::Errors, option::Options};
+use futures_util::{future, stream, StreamExt};
+use group_commit::{GroupCommitError, GroupCommitter};
+use serde::Deserialize;
 use serde_json::json;
 use std::{
   collections::HashMap,
-  path::PathBuf,
   sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
   },
+  time::Duration,
 };
 use surf::post as surf_post; // To avoid conflict with actix_web post macro
 use tokio::{
@@ -23,19 +37,43 @@ use tokio::{
 #[post("/put")]
 pub async fn put_handler(
   eng: web::Data<Arc<Engine>>,
+  committer: Option<web::Data<GroupCommitter>>,
   data: web::Json<HashMap<String, String>>,
 ) -> impl Responder {
+  // share a batch with concurrent requests when group commit is enabled
+  if let Some(committer) = committer {
+    if data.keys().any(|key| key.is_empty()) {
+      return HttpResponse::BadRequest().body("key is empty");
+    }
+    let entries = data
+      .into_inner()
+      .into_iter()
+      .map(|(key, val)| (web::Bytes::from(key), web::Bytes::from(val)))
+      .collect();
+    return match committer.put(entries).await {
+      Ok(()) => HttpResponse::Ok().body("成功"),
+      Err(GroupCommitError::Engine(Errors::Backpressure)) => backpressure_response(),
+      Err(_) => HttpResponse::InternalServerError().body("failed to put value into engine"),
+    };
+  }
+
   for (key, val) in data.iter() {
-    if eng
-      .put(web::Bytes::from(key.clone()), web::Bytes::from(val.clone()))
-      .is_err()
-    {
-      return HttpResponse::InternalServerError().body("failed to put value into engine");
+    match eng.put(web::Bytes::from(key.clone()), web::Bytes::from(val.clone())) {
+      Ok(()) => {}
+      Err(Errors::Backpressure) => return backpressure_response(),
+      Err(_) => return HttpResponse::InternalServerError().body("failed to put value into engine"),
     }
   }
   HttpResponse::Ok().body("成功")
 }
 
+// the engine sheds writes until merge catches up, clients should retry later
+fn backpressure_response() -> HttpResponse {
+  HttpResponse::ServiceUnavailable()
+    .insert_header(("Retry-After", "1"))
+    .body("engine is busy merging, retry later")
+}
+
 #[get("/get/{key}")]
 pub async fn get_handler(eng: web::Data<Arc<Engine>>, key: web::Path<String>) -> impl Responder {
   match eng.get(web::Bytes::from(key.to_string())) {
@@ -58,25 +96,215 @@ pub async fn delete_handler(eng: web::Data<Arc<Engine>>, key: web::Path<String>)
   HttpResponse::Ok().body("OK")
 }
 
+// keys listed per request unless the client asks for fewer
+const DEFAULT_LIST_LIMIT: usize = 1000;
+const MAX_LIST_LIMIT: usize = 10_000;
+// keys written per chunk of a listing, and read per page of a full listing
+const LIST_CHUNK_SIZE: usize = 1000;
+
+#[derive(Deserialize)]
+pub struct ListKeysQuery {
+  prefix: Option<String>,
+  start_after: Option<String>,
+  limit: Option<usize>,
+  all: Option<bool>,
+}
+
+/// Lists keys as `{"keys": [...], "next": ...}`, streamed with chunked transfer encoding.
+/// Up to `limit` keys are listed, 1000 by default and at most 10000, pass `next` of the
+/// response as `start_after` to get the following keys. With `all=true` the listing goes on
+/// to the last key, or `limit` without the cap, reading the keys one page at a time. `next`
+/// is the hex encoded key, so keys which are not UTF-8 continue where they stopped.
 #[get("/listkeys")]
-pub async fn listkeys_handler(eng: web::Data<Arc<Engine>>) -> impl Responder {
-  let keys = match eng.list_keys() {
-    Ok(keys) => keys,
+pub async fn listkeys_handler(
+  eng: web::Data<Arc<Engine>>,
+  query: web::Query<ListKeysQuery>,
+) -> impl Responder {
+  let query = query.into_inner();
+  let start_after = match query.start_after.as_deref().map(decode_token) {
+    Some(None) => return HttpResponse::BadRequest().body("start_after is not a valid token"),
+    start_after => start_after.flatten().map(web::Bytes::from),
+  };
+  let all = query.all.unwrap_or(false);
+  let limit = match (query.limit, all) {
+    (Some(0), _) => return HttpResponse::BadRequest().body("limit must be at least 1"),
+    (Some(limit), true) => limit,
+    (Some(limit), false) => limit.min(MAX_LIST_LIMIT),
+    (None, true) => usize::MAX,
+    (None, false) => DEFAULT_LIST_LIMIT,
+  };
+  // a bounded listing is a single page of the engine, a full one continues page by page
+  let page_size = match all {
+    true => LIST_CHUNK_SIZE,
+    false => limit,
+  };
+  let prefix = query.prefix.map(web::Bytes::from);
+  let listing = KeyListing::new(eng.get_ref().clone(), prefix, start_after, limit, page_size);
+  let mut listing = match listing {
+    Ok(listing) => listing,
     Err(_) => return HttpResponse::InternalServerError().body("failed to list keys"),
   };
-
-  let keys = keys
-    .into_iter()
-    .map(|key| String::from_utf8(key.to_vec()).unwrap())
-    .collect::<Vec<String>>();
-  let res = serde_json::to_string(&keys).unwrap();
+  // once the first chunk is out a failure can only cut the response short
+  let first = match listing.next_chunk() {
+    Ok(chunk) => chunk,
+    Err(_) => return HttpResponse::InternalServerError().body("failed to list keys"),
+  };
+  let rest = stream::unfold(listing, |mut listing| async move {
+    if listing.done {
+      return None;
+    }
+    let chunk = listing.next_chunk();
+    listing.done |= chunk.is_err();
+    Some((chunk, listing))
+  });
   HttpResponse::Ok()
-    .content_type("application/json")
-    .body(res)
+    .content_type(ContentType::json())
+    .streaming(stream::once(future::ready(Ok(first))).chain(rest))
+}
+
+// `next` token of a listing, the hex encoded key
+fn encode_token(key: &[u8]) -> String {
+  key.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn decode_token(token: &str) -> Option<Vec<u8>> {
+  if !token.len().is_multiple_of(2) {
+    return None;
+  }
+  (0..token.len())
+    .step_by(2)
+    .map(|i| u8::from_str_radix(token.get(i..i + 2)?, 16).ok())
+    .collect()
+}
+
+// state of a streamed key listing
+struct KeyListing {
+  engine: Arc<Engine>,
+  prefix: Option<web::Bytes>,
+  keys: std::vec::IntoIter<web::Bytes>, // keys of the current page not written yet
+  next: Option<web::Bytes>,             // `next` of the current page
+  page_size: usize,
+  remaining: usize,
+  opened: bool,
+  listed: usize,
+  done: bool,
+}
+
+impl KeyListing {
+  // reads the first page of at most `page_size` keys
+  fn new(
+    engine: Arc<Engine>,
+    prefix: Option<web::Bytes>,
+    start_after: Option<web::Bytes>,
+    limit: usize,
+    page_size: usize,
+  ) -> Result<Self, Errors> {
+    let page = engine.list_keys_paged(prefix.clone(), start_after, page_size.min(limit))?;
+    Ok(Self {
+      engine,
+      prefix,
+      keys: page.keys.into_iter(),
+      next: page.next,
+      page_size,
+      remaining: limit,
+      opened: false,
+      listed: 0,
+      done: false,
+    })
+  }
+
+  // the next part of the JSON object, opened by the first chunk and closed by the last one
+  fn next_chunk(&mut self) -> Result<web::Bytes, Errors> {
+    // the following page is read once the keys of the current one are written
+    if self.keys.len() == 0 && self.remaining > 0 {
+      if let Some(start_after) = self.next.take() {
+        let page = self.engine.list_keys_paged(
+          self.prefix.clone(),
+          Some(start_after),
+          self.page_size.min(self.remaining),
+        )?;
+        self.keys = page.keys.into_iter();
+        self.next = page.next;
+      }
+    }
+    let json_key = |key: &web::Bytes| json!(String::from_utf8_lossy(key)).to_string();
+
+    let mut chunk = String::new();
+    if !self.opened {
+      chunk.push_str("{\"keys\":[");
+      self.opened = true;
+    }
+    for key in self.keys.by_ref().take(LIST_CHUNK_SIZE) {
+      if self.listed > 0 {
+        chunk.push(',');
+      }
+      chunk.push_str(&json_key(&key));
+      self.listed += 1;
+      self.remaining -= 1;
+    }
+
+    if self.keys.len() == 0 && (self.next.is_none() || self.remaining == 0) {
+      let next = (self.next.as_ref()).map_or("null".to_string(), |next| {
+        json!(encode_token(next)).to_string()
+      });
+      chunk.push_str(&format!("],\"next\":{next}}}"));
+      self.done = true;
+    }
+    Ok(web::Bytes::from(chunk))
+  }
+}
+
+// keys listed by a dry run of a prefix delete
+const DRY_RUN_KEY_LIMIT: usize = 1000;
+
+#[derive(Deserialize)]
+pub struct DeletePrefixQuery {
+  dry_run: Option<bool>,
 }
 
+/// Deletes the keys under a prefix, with `dry_run=true` counts the keys that would be
+/// deleted instead and lists the first 1000 of them.
+#[delete("/prefix/{prefix}")]
+pub async fn delete_prefix_handler(
+  eng: web::Data<Arc<Engine>>,
+  prefix: web::Path<String>,
+  query: web::Query<DeletePrefixQuery>,
+) -> impl Responder {
+  if query.dry_run.unwrap_or(false) {
+    let prefix = web::Bytes::copy_from_slice(prefix.as_bytes());
+    let listed = eng.prefix_stat(&prefix).and_then(|stat| {
+      Ok((
+        stat,
+        eng.list_keys_paged(Some(prefix), None, DRY_RUN_KEY_LIMIT)?,
+      ))
+    });
+    let (stat, page) = match listed {
+      Ok(listed) => listed,
+      Err(_) => return HttpResponse::InternalServerError().body("failed to list keys"),
+    };
+    let keys: Vec<String> = (page.keys.iter())
+      .map(|key| String::from_utf8_lossy(key).into_owned())
+      .collect();
+    return HttpResponse::Ok().json(json!({ "count": stat.key_num, "keys": keys }));
+  }
+
+  match eng.delete_prefix(prefix.as_bytes()) {
+    Ok(deleted) => HttpResponse::Ok().json(json!({ "deleted": deleted })),
+    Err(_) => HttpResponse::InternalServerError().body("failed to delete keys in engine"),
+  }
+}
+
+#[derive(Deserialize)]
+pub struct StatQuery {
+  prefix: Option<String>,
+}
+
+/// Engine statistics, with `prefix` also the approximate live bytes of the keys under it.
 #[get("/stat")]
-pub async fn stat_handler(eng: web::Data<Arc<Engine>>) -> impl Responder {
+pub async fn stat_handler(
+  eng: web::Data<Arc<Engine>>,
+  query: web::Query<StatQuery>,
+) -> impl Responder {
   let stat = match eng.get_engine_stat() {
     Ok(stat) => stat,
     Err(_) => return HttpResponse::InternalServerError().body("failed to get stat in engine"),
@@ -86,15 +314,29 @@ pub async fn stat_handler(eng: web::Data<Arc<Engine>>) -> impl Responder {
   res.insert("key_num", stat.key_num);
   res.insert("data_file_num", stat.data_file_num);
   res.insert("reclaim_size", stat.reclaim_size);
+  res.insert("tombstone_size", stat.tombstone_size);
   res.insert("disk_size", stat.disk_size as usize);
+  res.insert("bytes_read", stat.bytes_read as usize);
+  res.insert("bytes_returned", stat.bytes_returned as usize);
+  res.insert("bytes_written", stat.bytes_written as usize);
+  res.insert("bytes_ingested", stat.bytes_ingested as usize);
+  res.insert("bytes_per_sync", stat.bytes_per_sync);
+  res.insert("scrubbed_bytes", stat.scrubbed_bytes as usize);
+  res.insert("scrub_corruptions", stat.scrub_corruptions as usize);
+  if let Some(prefix) = &query.prefix {
+    match eng.approximate_size_of_prefix(prefix.as_bytes()) {
+      Ok(size) => res.insert("prefix_size", size as usize),
+      Err(_) => return HttpResponse::InternalServerError().body("failed to get prefix size"),
+    };
+  }
 
   HttpResponse::Ok()
     .content_type("application/json")
     .body(serde_json::to_string(&res).unwrap())
 }
 
-async fn send_request() -> surf::Result<()> {
-  let uri = "http://127.0.0.1:8080/flash-kv/put";
+async fn send_request(base_url: String) -> surf::Result<()> {
+  let uri = format!("{base_url}/flash-kv/put");
   let data = json!({ "key1": "value1", "key2": "value2" });
   let mut res = surf_post(uri).body_json(&data)?.await?;
 
@@ -102,16 +344,17 @@ async fn send_request() -> surf::Result<()> {
   let body = res.body_string().await?;
   println!("Response: {}", body);
 
-  let uri = "http://127.0.0.1:8080/flash-kv/listkeys";
+  let uri = format!("{base_url}/flash-kv/listkeys");
   let mut res = surf::get(uri).await?;
 
   println!("Status: {}", res.status());
   let body = res.body_string().await?;
   println!("Response: {}", body);
-  let keys: Vec<String> = serde_json::from_str(&body)?;
+  let page: serde_json::Value = serde_json::from_str(&body)?;
+  let keys = page["keys"].as_array().cloned().unwrap_or_default();
 
-  for key in keys {
-    let url = format!("http://127.0.0.1:8080/flash-kv/get/{}", key);
+  for key in keys.iter().filter_map(|key| key.as_str()) {
+    let url = format!("{base_url}/flash-kv/get/{}", key);
     let mut res = surf::get(url).await?;
     println!("Status: {}", res.status());
     let body = res.body_string().await?;
@@ -121,22 +364,50 @@ async fn send_request() -> surf::Result<()> {
   Ok(())
 }
 
-async fn run_server(engine: Arc<Engine>) -> std::io::Result<()> {
-  let server = HttpServer::new(move || {
-    App::new().app_data(web::Data::new(engine.clone())).service(
-      Scope::new("/flash-kv")
-        .service(put_handler)
-        .service(get_handler)
-        .service(delete_handler)
-        .service(listkeys_handler)
-        .service(stat_handler),
-    )
+// the server is built outside of the async fn, its builder is not Send
+fn build_server(engine: Arc<Engine>, config: ServerConfig) -> std::io::Result<Server> {
+  let client_stats = Arc::new(ClientStats::default());
+  let committer = config
+    .group_commit()
+    .map(|opts| web::Data::new(GroupCommitter::start(engine.clone(), opts)));
+  let auth_token = config.auth_token.clone();
+  let max_payload = config.max_payload;
+  let mut server = HttpServer::new(move || {
+    let mut app = App::new().app_data(web::Data::new(engine.clone()));
+    if let Some(committer) = committer.clone() {
+      app = app.app_data(committer);
+    }
+    app
+      .app_data(web::Data::new(client_stats.clone()))
+      .app_data(web::JsonConfig::default().limit(max_payload))
+      .wrap(BearerAuth::new(auth_token.clone()))
+      .wrap(ClientAccounting::new(client_stats.clone()))
+      .service(
+        Scope::new("/flash-kv")
+          .service(put_handler)
+          .service(get_handler)
+          .service(delete_handler)
+          .service(delete_prefix_handler)
+          .service(listkeys_handler)
+          .service(stat_handler)
+          .service(clients_handler)
+          .service(bulkload_handler),
+      )
   })
-  .bind("127.0.0.1:8080")
-  .unwrap()
-  .run();
+  .keep_alive(Duration::from_secs(config.keep_alive_secs));
+  if let Some(workers) = config.workers {
+    server = server.workers(workers);
+  }
 
-  server.await
+  let server = match config.tls_config()? {
+    Some(tls_config) => server.bind_rustls_0_22(&config.bind, tls_config)?,
+    None => server.bind(&config.bind)?,
+  };
+  Ok(server.run())
+}
+
+async fn run_server(engine: Arc<Engine>, config: ServerConfig) -> std::io::Result<()> {
+  build_server(engine, config)?.await
 }
 
 async fn listen_for_enter_key() {
@@ -149,24 +420,33 @@ async fn listen_for_enter_key() {
 
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
+  let config = ServerConfig::parse();
   let engine = Arc::new(
     Engine::open(Options {
-      dir_path: PathBuf::from("/tmp/flash-kv-http"),
+      dir_path: config.dir_path.clone(),
       ..Default::default()
     })
-    .unwrap(),
+    .map_err(std::io::Error::other)?,
   );
 
   let is_shutdown = Arc::new(AtomicBool::new(false));
   let (shutdown_sender, mut shutdown_receiver) = broadcast::channel::<()>(10);
   let engine_for_server = engine.clone();
-  let server_handle = tokio::spawn(async move { run_server(engine_for_server).await });
+  let scheme = match config.tls_cert.is_some() {
+    true => "https",
+    false => "http",
+  };
+  let base_url = format!("{scheme}://{}", config.bind);
+  let demo = config.demo;
+  let server_handle = tokio::spawn(async move { run_server(engine_for_server, config).await });
 
-  tokio::spawn(async move {
-    if let Err(e) = send_request().await {
-      eprintln!("failed to request: {}", e);
-    }
-  });
+  if demo {
+    tokio::spawn(async move {
+      if let Err(e) = send_request(base_url).await {
+        eprintln!("failed to request: {}", e);
+      }
+    });
+  }
 
   let shutdown_handle = tokio::spawn(async move {
     tokio::select! {
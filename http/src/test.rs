@@ -1,7 +1,17 @@
 use super::*;
-use actix_web::{http::StatusCode, test};
+use crate::group_commit::GroupCommitOptions;
+use actix_web::{
+  body::{BodySize, MessageBody},
+  http::StatusCode,
+  test,
+};
 use tempfile::tempdir;
 
+#[allow(
+  clippy::field_reassign_with_default,
+  clippy::needless_borrows_for_generic_args,
+  clippy::unnecessary_mut_passed
+)]
 #[actix_web::test]
 async fn test_put_handler() {
   let temp_dir = tempdir().expect("Failed to create temp dir for put test");
@@ -25,6 +35,7 @@ async fn test_put_handler() {
   assert_eq!(resp.status(), StatusCode::OK);
 }
 
+#[allow(clippy::field_reassign_with_default, clippy::unnecessary_mut_passed)]
 #[actix_web::test]
 async fn test_get_handler() {
   let temp_dir = tempdir().expect("Failed to create temp dir for get test");
@@ -48,6 +59,7 @@ async fn test_get_handler() {
   assert_eq!(resp.status(), StatusCode::OK);
 }
 
+#[allow(clippy::field_reassign_with_default, clippy::unnecessary_mut_passed)]
 #[actix_web::test]
 async fn test_listkeys_handler() {
   let temp_dir = tempdir().expect("Failed to create temp dir for listkeys test");
@@ -72,8 +84,138 @@ async fn test_listkeys_handler() {
   let req = test::TestRequest::with_uri("/flash-kv/listkeys").to_request();
   let resp = test::call_service(&mut app, req).await;
   assert_eq!(resp.status(), StatusCode::OK);
+  let page: serde_json::Value = test::read_body_json(resp).await;
+  assert_eq!(json!({"keys": ["key1", "key2"], "next": null}), page);
+
+  let req = test::TestRequest::with_uri("/flash-kv/listkeys?prefix=key&limit=1").to_request();
+  let page: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+  assert_eq!(json!({"keys": ["key1"], "next": "6b657931"}), page);
+  let req =
+    test::TestRequest::with_uri("/flash-kv/listkeys?limit=1&start_after=6b657931").to_request();
+  let page: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+  assert_eq!(json!({"keys": ["key2"], "next": null}), page);
+  let req = test::TestRequest::with_uri("/flash-kv/listkeys?start_after=key1").to_request();
+  let resp = test::call_service(&mut app, req).await;
+  assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+  // keys which are not UTF-8 keep their bytes in the token
+  engine
+    .put((b"key1\xff" as &[u8]).into(), (b"val" as &[u8]).into())
+    .unwrap();
+  let req = test::TestRequest::with_uri("/flash-kv/listkeys?limit=2").to_request();
+  let page: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+  assert_eq!(json!("6b657931ff"), page["next"]);
+  let req = test::TestRequest::with_uri("/flash-kv/listkeys?start_after=6b657931ff").to_request();
+  let page: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+  assert_eq!(json!({"keys": ["key2"], "next": null}), page);
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[actix_web::test]
+async fn test_listkeys_handler_streams_chunks() {
+  let temp_dir = tempdir().expect("Failed to create temp dir for listkeys test");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  let engine = Arc::new(Engine::open(opts).unwrap());
+  let key = |i: usize| format!("key{i:05}");
+  for i in 0..LIST_CHUNK_SIZE * 2 + 500 {
+    engine
+      .put(web::Bytes::from(key(i)), web::Bytes::from("v"))
+      .unwrap();
+  }
+
+  let app = test::init_service(
+    App::new()
+      .app_data(web::Data::new(engine.clone()))
+      .service(Scope::new("/flash-kv").service(listkeys_handler)),
+  )
+  .await;
+
+  // a full listing goes on to the last key, the body has no length up front
+  let req = test::TestRequest::with_uri("/flash-kv/listkeys?all=true").to_request();
+  let resp = test::call_service(&app, req).await;
+  assert_eq!(BodySize::Stream, resp.response().body().size());
+  let page: serde_json::Value = test::read_body_json(resp).await;
+  let keys: Vec<String> = (0..LIST_CHUNK_SIZE * 2 + 500).map(key).collect();
+  assert_eq!(json!({"keys": keys, "next": null}), page);
+
+  // without a limit the default one applies
+  let req = test::TestRequest::with_uri("/flash-kv/listkeys").to_request();
+  let page: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+  assert_eq!(json!(keys[..DEFAULT_LIST_LIMIT]), page["keys"]);
+  assert_eq!(
+    json!(encode_token(key(DEFAULT_LIST_LIMIT - 1).as_bytes())),
+    page["next"]
+  );
+  let req = test::TestRequest::with_uri("/flash-kv/listkeys?limit=0").to_request();
+  let resp = test::call_service(&app, req).await;
+  assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+  // a limit across chunks continues after `next`
+  let limit = LIST_CHUNK_SIZE + 200;
+  let req = test::TestRequest::with_uri(&format!("/flash-kv/listkeys?limit={limit}")).to_request();
+  let page: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+  assert_eq!(json!(keys[..limit]), page["keys"]);
+  assert_eq!(json!(encode_token(key(limit - 1).as_bytes())), page["next"]);
+  let uri = format!("/flash-kv/listkeys?all=true&limit={limit}");
+  let req = test::TestRequest::with_uri(&uri).to_request();
+  let full: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+  assert_eq!(page, full);
+  let uri = format!(
+    "/flash-kv/listkeys?limit={limit}&start_after={}",
+    encode_token(key(limit - 1).as_bytes())
+  );
+  let req = test::TestRequest::with_uri(&uri).to_request();
+  let page: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+  assert_eq!(json!(keys[limit..limit * 2]), page["keys"]);
+  let req = test::TestRequest::with_uri("/flash-kv/listkeys?prefix=nope").to_request();
+  let page: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+  assert_eq!(json!({"keys": [], "next": null}), page);
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[actix_web::test]
+async fn test_delete_prefix_handler() {
+  let temp_dir = tempdir().expect("Failed to create temp dir for delete prefix test");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  let engine = Arc::new(Engine::open(opts).unwrap());
+  for key in ["tenant-a:1", "tenant-a:2", "tenant-b:1"] {
+    engine
+      .put(web::Bytes::from(key), web::Bytes::from("v"))
+      .unwrap();
+  }
+
+  let app = test::init_service(
+    App::new()
+      .app_data(web::Data::new(engine.clone()))
+      .service(Scope::new("/flash-kv").service(delete_prefix_handler)),
+  )
+  .await;
+
+  // a dry run lists the keys and deletes nothing
+  let req = test::TestRequest::delete()
+    .uri("/flash-kv/prefix/tenant-a:?dry_run=true")
+    .to_request();
+  let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+  assert_eq!(
+    json!({"count": 2, "keys": ["tenant-a:1", "tenant-a:2"]}),
+    body
+  );
+  assert_eq!(3, engine.list_keys().unwrap().len());
+
+  let req = test::TestRequest::delete()
+    .uri("/flash-kv/prefix/tenant-a:")
+    .to_request();
+  let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+  assert_eq!(json!({"deleted": 2}), body);
+  assert_eq!(
+    vec![web::Bytes::from("tenant-b:1")],
+    engine.list_keys().unwrap()
+  );
 }
 
+#[allow(clippy::field_reassign_with_default, clippy::unnecessary_mut_passed)]
 #[actix_web::test]
 async fn test_stat_handler() {
   let temp_dir = tempdir().expect("Failed to create temp dir for stat test");
@@ -91,4 +233,318 @@ async fn test_stat_handler() {
   let req = test::TestRequest::with_uri("/flash-kv/stat").to_request();
   let resp = test::call_service(&mut app, req).await;
   assert_eq!(resp.status(), StatusCode::OK);
+  let body: serde_json::Value = test::read_body_json(resp).await;
+  assert!(body.get("prefix_size").is_none());
+
+  engine
+    .put(
+      web::Bytes::from("tenant-a:1"),
+      web::Bytes::from(vec![b'v'; 100]),
+    )
+    .unwrap();
+  engine
+    .put(
+      web::Bytes::from("tenant-b:1"),
+      web::Bytes::from(vec![b'v'; 500]),
+    )
+    .unwrap();
+  let req = test::TestRequest::with_uri("/flash-kv/stat?prefix=tenant-a:").to_request();
+  let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+  let prefix_size = body["prefix_size"].as_u64().unwrap();
+  assert_eq!(
+    engine.approximate_size_of_prefix(b"tenant-a:").unwrap(),
+    prefix_size
+  );
+  assert!((100..500).contains(&prefix_size));
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[actix_web::test]
+async fn test_client_accounting() {
+  let temp_dir = tempdir().expect("Failed to create temp dir for accounting test");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  let engine = Arc::new(Engine::open(opts).unwrap());
+  let client_stats = Arc::new(ClientStats::default());
+
+  let app = test::init_service(
+    App::new()
+      .app_data(web::Data::new(engine.clone()))
+      .app_data(web::Data::new(client_stats.clone()))
+      .wrap(ClientAccounting::new(client_stats.clone()))
+      .service(
+        Scope::new("/flash-kv")
+          .service(put_handler)
+          .service(get_handler)
+          .service(clients_handler),
+      ),
+  )
+  .await;
+
+  for _ in 0..2 {
+    let req = test::TestRequest::with_uri("/flash-kv/put")
+      .method(actix_web::http::Method::POST)
+      .insert_header((accounting::CLIENT_ID_HEADER, "writer"))
+      .set_json(json!({"key": "value"}))
+      .to_request();
+    test::call_service(&app, req).await;
+  }
+  let req = test::TestRequest::with_uri("/flash-kv/missing")
+    .insert_header((accounting::CLIENT_ID_HEADER, "reader"))
+    .to_request();
+  test::call_service(&app, req).await;
+
+  let req = test::TestRequest::with_uri("/flash-kv/admin/clients").to_request();
+  let clients: HashMap<String, serde_json::Value> = test::call_and_read_body_json(&app, req).await;
+  assert_eq!(json!(2), clients["writer"]["ops"]);
+  assert_eq!(json!(0), clients["writer"]["errors"]);
+  assert!(clients["writer"]["bytes_out"].as_u64().unwrap() > 0);
+  assert_eq!(json!(1), clients["reader"]["errors"]);
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[actix_web::test]
+async fn test_put_handler_group_commit() {
+  let temp_dir = tempdir().expect("Failed to create temp dir for group commit test");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  let engine = Arc::new(Engine::open(opts).unwrap());
+  let committer = web::Data::new(GroupCommitter::start(
+    engine.clone(),
+    GroupCommitOptions {
+      window: std::time::Duration::from_millis(20),
+      ..Default::default()
+    },
+  ));
+
+  let app = test::init_service(
+    App::new()
+      .app_data(web::Data::new(engine.clone()))
+      .app_data(committer.clone())
+      .service(Scope::new("/flash-kv").service(put_handler)),
+  )
+  .await;
+
+  let requests = (0..8).map(|i| {
+    let req = test::TestRequest::with_uri("/flash-kv/put")
+      .method(actix_web::http::Method::POST)
+      .set_json(json!({ format!("key-{i}"): format!("value-{i}"), "shared": "x" }))
+      .to_request();
+    test::call_service(&app, req)
+  });
+  for resp in futures_util::future::join_all(requests).await {
+    assert_eq!(resp.status(), StatusCode::OK);
+  }
+  for i in 0..8 {
+    assert_eq!(
+      web::Bytes::from(format!("value-{i}")),
+      engine.get(web::Bytes::from(format!("key-{i}"))).unwrap()
+    );
+  }
+
+  // a bad request is rejected before it can fail the whole group
+  let req = test::TestRequest::with_uri("/flash-kv/put")
+    .method(actix_web::http::Method::POST)
+    .set_json(json!({"": "empty"}))
+    .to_request();
+  let resp = test::call_service(&app, req).await;
+  assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[actix_web::test]
+async fn test_group_commit_reports_errors_per_request() {
+  let temp_dir = tempdir().expect("Failed to create temp dir for group commit test");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  opts.prefix_quotas = vec![flash_kv::option::PrefixQuota {
+    prefix: b"limited".to_vec(),
+    max_bytes: 1,
+  }];
+  let engine = Arc::new(Engine::open(opts).unwrap());
+  let committer = web::Data::new(GroupCommitter::start(
+    engine.clone(),
+    GroupCommitOptions {
+      window: std::time::Duration::from_millis(20),
+      ..Default::default()
+    },
+  ));
+
+  let app = test::init_service(
+    App::new()
+      .app_data(web::Data::new(engine.clone()))
+      .app_data(committer.clone())
+      .service(Scope::new("/flash-kv").service(put_handler)),
+  )
+  .await;
+
+  // the request over quota fails alone, the rest of its group is committed
+  let requests = ["key-0", "limited-key", "key-1"].map(|key| {
+    let req = test::TestRequest::with_uri("/flash-kv/put")
+      .method(actix_web::http::Method::POST)
+      .set_json(json!({ key: "value" }))
+      .to_request();
+    test::call_service(&app, req)
+  });
+  let statuses: Vec<_> = futures_util::future::join_all(requests)
+    .await
+    .iter()
+    .map(|resp| resp.status())
+    .collect();
+  assert_eq!(
+    vec![
+      StatusCode::OK,
+      StatusCode::INTERNAL_SERVER_ERROR,
+      StatusCode::OK
+    ],
+    statuses
+  );
+  assert!(engine.get(web::Bytes::from("key-0")).is_ok());
+  assert!(engine.get(web::Bytes::from("key-1")).is_ok());
+  assert!(engine.get(web::Bytes::from("limited-key")).is_err());
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[actix_web::test]
+async fn test_bearer_auth() {
+  let temp_dir = tempdir().expect("Failed to create temp dir for auth test");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  let engine = Arc::new(Engine::open(opts).unwrap());
+
+  let app = test::init_service(
+    App::new()
+      .app_data(web::Data::new(engine.clone()))
+      .wrap(BearerAuth::new(Some("secret".to_string())))
+      .service(
+        Scope::new("/flash-kv")
+          .service(put_handler)
+          .service(get_handler),
+      ),
+  )
+  .await;
+
+  let put = |auth: Option<&str>| {
+    let mut req = test::TestRequest::with_uri("/flash-kv/put")
+      .method(actix_web::http::Method::POST)
+      .set_json(json!({"key": "value"}));
+    if let Some(auth) = auth {
+      req = req.insert_header(("Authorization", auth));
+    }
+    req.to_request()
+  };
+  let resp = test::call_service(&app, put(None)).await;
+  assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+  let resp = test::call_service(&app, put(Some("Bearer wrong"))).await;
+  assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+  assert!(engine.get("key".into()).is_err());
+
+  let resp = test::call_service(&app, put(Some("Bearer secret"))).await;
+  assert_eq!(resp.status(), StatusCode::OK);
+
+  // reads need no token
+  let req = test::TestRequest::with_uri("/flash-kv/get/key").to_request();
+  let resp = test::call_service(&app, req).await;
+  assert_eq!(resp.status(), StatusCode::OK);
+  assert_eq!(test::read_body(resp).await, "value");
+}
+
+#[actix_web::test]
+async fn test_server_config() {
+  let config =
+    ServerConfig::try_parse_from(["http", "--bind", "0.0.0.0:9000", "--workers", "2"]).unwrap();
+  assert_eq!("0.0.0.0:9000", config.bind);
+  assert_eq!(Some(2), config.workers);
+  assert!(config.tls_config().unwrap().is_none());
+  let group_commit = config.group_commit().unwrap();
+  assert_eq!(1024, group_commit.max_batch_ops);
+  assert_eq!(std::time::Duration::from_millis(1), group_commit.window);
+
+  let config = ServerConfig::try_parse_from([
+    "http",
+    "--group-commit-max-ops",
+    "64",
+    "--group-commit-delay-us",
+    "250",
+  ])
+  .unwrap();
+  let group_commit = config.group_commit().unwrap();
+  assert_eq!(64, group_commit.max_batch_ops);
+  assert_eq!(std::time::Duration::from_micros(250), group_commit.window);
+  let config = ServerConfig::try_parse_from(["http", "--group-commit-max-ops", "0"]).unwrap();
+  assert!(config.group_commit().is_none());
+
+  // a certificate needs its key
+  assert!(ServerConfig::try_parse_from(["http", "--tls-cert", "cert.pem"]).is_err());
+
+  let config = ServerConfig::try_parse_from([
+    "http",
+    "--tls-cert",
+    "/nonexistent/cert.pem",
+    "--tls-key",
+    "/nonexistent/key.pem",
+  ])
+  .unwrap();
+  assert!(config.tls_config().is_err());
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[actix_web::test]
+async fn test_bulkload_handler() {
+  let temp_dir = tempdir().expect("Failed to create temp dir for bulkload test");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  let engine = Arc::new(Engine::open(opts).unwrap());
+
+  let app = test::init_service(
+    App::new()
+      .app_data(web::Data::new(engine.clone()))
+      .service(Scope::new("/flash-kv").service(bulkload_handler)),
+  )
+  .await;
+
+  // json lines, the last one without a newline
+  let mut body = String::new();
+  for i in 0..2500 {
+    body.push_str(&json!({"key": format!("json-{i}"), "value": format!("{i}")}).to_string());
+    body.push('\n');
+  }
+  body.push_str(r#"{"key": "json-last", "value": "last"}"#);
+  let req = test::TestRequest::with_uri("/flash-kv/bulkload")
+    .method(actix_web::http::Method::POST)
+    .insert_header(("Content-Type", "application/x-ndjson"))
+    .set_payload(body)
+    .to_request();
+  let summary: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+  assert_eq!(2501, summary["entries"]);
+  assert_eq!(3, summary["batches"]);
+  assert_eq!(&b"1234"[..], &engine.get("json-1234".into()).unwrap()[..]);
+
+  // binary framing holds any bytes
+  let mut body = Vec::new();
+  for i in 0..10u8 {
+    body.extend_from_slice(&[2, 3, b'b', i, 0, b'\n', 0xff]);
+  }
+  let req = test::TestRequest::with_uri("/flash-kv/bulkload")
+    .method(actix_web::http::Method::POST)
+    .insert_header(("Content-Type", bulkload::BINARY_CONTENT_TYPE))
+    .set_payload(body)
+    .to_request();
+  let summary: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+  assert_eq!(10, summary["entries"]);
+  assert_eq!(
+    &[0, b'\n', 0xff][..],
+    &engine.get(vec![b'b', 7].into()).unwrap()[..]
+  );
+
+  // the chunk holding an invalid line is not committed
+  let req = test::TestRequest::with_uri("/flash-kv/bulkload")
+    .method(actix_web::http::Method::POST)
+    .set_payload("{\"key\": \"ok\", \"value\": \"1\"}\nnot json\n")
+    .to_request();
+  let resp = test::call_service(&app, req).await;
+  assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+  let body: serde_json::Value = test::read_body_json(resp).await;
+  assert_eq!(0, body["summary"]["entries"]);
+  assert!(engine.get("ok".into()).is_err());
 }
@@ -1,25 +1,32 @@
 use std::{
   collections::HashMap,
   sync::{atomic::Ordering, Arc},
+  time::SystemTime,
 };
 
 use bytes::{BufMut, Bytes, BytesMut};
 use parking_lot::Mutex;
-use prost::{decode_length_delimiter, encode_length_delimiter};
+use prost::{decode_length_delimiter, encode_length_delimiter, length_delimiter_len};
 
 use crate::{
-  data::log_record::{LogRecord, LogRecordType},
+  cdc::CdcOp,
+  data::log_record::{LogRecord, LogRecordPos, LogRecordType, TransactionRecord},
   db::Engine,
   errors::{Errors, Result},
-  option::{IndexType, WriteBatchOptions},
+  expiry::unix_millis,
+  keyspace::record_size,
+  multi_batch::PreparedTxn,
+  option::{ChunkAtomicity, IndexType, WriteBatchOptions},
+  watch::WatchOp,
 };
 
-const TXN_FIN_KEY: &[u8] = "txn-fin".as_bytes();
+pub(crate) const TXN_FIN_KEY: &[u8] = "txn-fin".as_bytes();
 pub(crate) const NON_TXN_SEQ_NO: usize = 0;
 
 /// A batch of write operations ensuring atomicity and consistency.
 pub struct WriteBatch<'a> {
   pending_writes: Arc<Mutex<HashMap<Vec<u8>, LogRecord>>>, // temporarily store the write data
+  savepoints: Mutex<Vec<HashMap<Vec<u8>, Option<LogRecord>>>>, // pending writes changed since each savepoint
   engine: &'a Engine,
   options: WriteBatchOptions,
 }
@@ -27,7 +34,8 @@ pub struct WriteBatch<'a> {
 impl Engine {
   /// Creates a new write batch for grouped operations.
   /// * `options` - Configuration options for the write batch.
-  pub fn new_write_batch(&self, options: WriteBatchOptions) -> Result<WriteBatch> {
+  pub fn new_write_batch(&self, options: WriteBatchOptions) -> Result<WriteBatch<'_>> {
+    self.check_open()?;
     if self.options.index_type == IndexType::BPlusTree && !self.seq_file_exists && !self.is_initial
     {
       return Err(Errors::UnableToUseWriteBatch);
@@ -35,10 +43,103 @@ impl Engine {
 
     Ok(WriteBatch {
       pending_writes: Arc::new(Mutex::new(HashMap::new())),
+      savepoints: Mutex::new(Vec::new()),
       engine: self,
       options,
     })
   }
+
+  /// Deletes the keys in one transaction, so either all of the tombstones are written or
+  /// none. Returns for every key whether it was found, expired keys are not. The keys are
+  /// locked until the tombstones are written, so the result matches them.
+  pub fn delete_many(&self, keys: &[Bytes]) -> Result<Vec<bool>> {
+    if keys.iter().any(|key| key.is_empty()) {
+      return Err(Errors::KeyIsEmpty);
+    }
+    let batch = self.new_write_batch(WriteBatchOptions {
+      max_batch_num: usize::MAX,
+      max_batch_bytes: 0,
+      sync_writes: self.options.sync_writes,
+    })?;
+
+    // the throttle may sleep, so it goes before the locks. Keys are locked in order so
+    // concurrent calls do not deadlock
+    self.throttle_write(keys.iter().map(|key| key.len()).sum(), false)?;
+    let mut locked: Vec<&Bytes> = keys.iter().collect();
+    locked.sort_unstable();
+    locked.dedup();
+    let _guards: Vec<_> = (locked.into_iter())
+      .map(|key| self.lock_key(key.clone()))
+      .collect();
+
+    let now = unix_millis(SystemTime::now());
+    let mut found = Vec::with_capacity(keys.len());
+    for key in keys {
+      found.push(self.index.get(key.to_vec()).is_some() && !self.expiry.is_expired(key, now));
+      batch.delete(key.clone())?;
+    }
+    batch.commit_throttled()?;
+    Ok(found)
+  }
+
+  /// Applies the written records of a transaction to the index and tells the watchers.
+  pub(crate) fn apply_txn_writes<'r, I>(&self, writes: I) -> Result<()>
+  where
+    I: IntoIterator<Item = (&'r LogRecord, LogRecordPos)>,
+  {
+    let writes = writes.into_iter();
+    let mut puts = Vec::with_capacity(writes.size_hint().0);
+    let mut deletes = Vec::new();
+    for (item, record_pos) in writes {
+      match item.rec_type {
+        LogRecordType::Normal => puts.push((item, record_pos)),
+        LogRecordType::Deleted => deletes.push((item, record_pos)),
+        _ => {}
+      }
+    }
+
+    // a transaction writes each key once, so the puts go to the index together and each
+    // index shard is locked once
+    let entries = puts
+      .iter()
+      .map(|(item, record_pos)| (item.key.clone(), *record_pos))
+      .collect();
+    let old_positions = self.index.try_put_batch(entries, false)?;
+    for ((item, record_pos), old_pos) in puts.iter().copied().zip(old_positions) {
+      self.account_quota(&item.key, old_pos, Some(record_pos));
+      if let Some(old_pos) = old_pos {
+        self.mark_stale(old_pos);
+      }
+      self.clear_expiry(&item.key);
+      self
+        .watchers
+        .notify(&item.key, WatchOp::Put, Some(&item.value));
+    }
+
+    for (item, record_pos) in deletes.iter().copied() {
+      self.mark_tombstone(record_pos);
+      let old_pos = self.index.try_delete(item.key.clone())?;
+      self.account_quota(&item.key, old_pos, None);
+      if let Some(old_pos) = old_pos {
+        self.mark_stale(old_pos);
+      }
+      self.clear_expiry(&item.key);
+      self.watchers.notify(&item.key, WatchOp::Delete, None);
+    }
+    self.mark_changed();
+    if self.cdc_enabled() {
+      let changes = puts
+        .iter()
+        .map(|(item, _)| (CdcOp::Put, &item.key[..], &item.value[..]))
+        .chain(
+          deletes
+            .iter()
+            .map(|(item, _)| (CdcOp::Delete, &item.key[..], &[][..])),
+        );
+      self.emit_cdc(changes);
+    }
+    Ok(())
+  }
 }
 
 impl WriteBatch<'_> {
@@ -53,9 +154,11 @@ impl WriteBatch<'_> {
       key: key.to_vec(),
       value: value.to_vec(),
       rec_type: LogRecordType::Normal,
+      timestamp: None,
     };
 
     let mut pending_writes = self.pending_writes.lock();
+    self.save_undo(&pending_writes, &key);
     pending_writes.insert(key.to_vec(), record);
     Ok(())
   }
@@ -67,6 +170,7 @@ impl WriteBatch<'_> {
     }
 
     let mut pending_writes = self.pending_writes.lock();
+    self.save_undo(&pending_writes, &key);
     // if data not exist, just return
     let index_pos = self.engine.index.get(key.to_vec());
     if index_pos.is_none() {
@@ -81,12 +185,23 @@ impl WriteBatch<'_> {
       key: key.to_vec(),
       value: Default::default(),
       rec_type: LogRecordType::Deleted,
+      timestamp: None,
     };
     pending_writes.insert(key.to_vec(), record);
     Ok(())
   }
 
+  #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
   pub fn commit(&self) -> Result<()> {
+    self.commit_pending(true)
+  }
+
+  /// Like `commit`, for callers which ran `Engine::throttle_write` before taking their locks.
+  pub(crate) fn commit_throttled(&self) -> Result<()> {
+    self.commit_pending(false)
+  }
+
+  fn commit_pending(&self, throttle: bool) -> Result<()> {
     let mut pending_writes = self.pending_writes.lock();
     if pending_writes.is_empty() {
       return Ok(());
@@ -94,83 +209,298 @@ impl WriteBatch<'_> {
     if pending_writes.len() > self.options.max_batch_num {
       return Err(Errors::ExceedMaxBatchNum);
     }
+    let items: Vec<&LogRecord> = pending_writes.values().collect();
+    let bytes = items.iter().map(|item| record_bytes(item)).sum();
+    if self.options.max_batch_bytes > 0 && bytes > self.options.max_batch_bytes {
+      return Err(Errors::ExceedMaxBatchBytes);
+    }
 
-    // mutex lock the engine to ensure serial write
-    let _lock = self.engine.batch_commit_lock.lock();
+    self.commit_txn(&items, bytes, None, throttle)?;
 
-    // obtain txn id
-    let seq_no = self.engine.seq_no.fetch_add(1, Ordering::SeqCst);
+    // clear pending writes for next commit
+    pending_writes.clear();
+    self.savepoints.lock().clear();
 
-    let mut positions = HashMap::new();
-    // start write to data file
-    for (_, item) in pending_writes.iter() {
-      let mut record = LogRecord {
-        key: log_record_key_with_seq(item.key.clone(), seq_no),
-        value: item.value.clone(),
-        rec_type: item.rec_type,
+    Ok(())
+  }
+
+  /// Writes the batch as the prepared transaction of the multi-engine transaction `txn_id`,
+  /// see [`crate::multi_batch::MultiEngineBatch`]. The pending writes are kept.
+  pub(crate) fn prepare(&self, txn_id: u64) -> Result<()> {
+    let pending_writes = self.pending_writes.lock();
+    if pending_writes.len() > self.options.max_batch_num {
+      return Err(Errors::ExceedMaxBatchNum);
+    }
+    let items: Vec<&LogRecord> = pending_writes.values().collect();
+    let bytes = items.iter().map(|item| record_bytes(item)).sum();
+    if self.options.max_batch_bytes > 0 && bytes > self.options.max_batch_bytes {
+      return Err(Errors::ExceedMaxBatchBytes);
+    }
+    self.commit_txn(&items, bytes, Some(txn_id), true)
+  }
+
+  pub(crate) fn is_empty(&self) -> bool {
+    self.pending_writes.lock().is_empty()
+  }
+
+  pub(crate) fn discard(&self) {
+    self.pending_writes.lock().clear();
+    self.savepoints.lock().clear();
+  }
+
+  /// Marks the pending writes, `rollback_to_savepoint` drops the writes staged after it.
+  /// Savepoints nest, a commit removes them all.
+  pub fn savepoint(&self) {
+    self.savepoints.lock().push(HashMap::new());
+  }
+
+  /// Restores the pending writes of the latest savepoint and removes it.
+  ///
+  /// # Errors
+  ///
+  /// Returns `NoSavepoint` if there is no savepoint left.
+  pub fn rollback_to_savepoint(&self) -> Result<()> {
+    let mut pending_writes = self.pending_writes.lock();
+    let undo = self.savepoints.lock().pop().ok_or(Errors::NoSavepoint)?;
+    for (key, record) in undo {
+      match record {
+        Some(record) => pending_writes.insert(key, record),
+        None => pending_writes.remove(&key),
       };
+    }
+    Ok(())
+  }
 
-      let pos = self.engine.append_log_record(&mut record)?;
-      positions.insert(item.key.clone(), pos);
+  // keeps the pending write of `key` from before its first change since the latest savepoint
+  fn save_undo(&self, pending_writes: &HashMap<Vec<u8>, LogRecord>, key: &[u8]) {
+    if let Some(undo) = self.savepoints.lock().last_mut() {
+      if !undo.contains_key(key) {
+        undo.insert(key.to_vec(), pending_writes.get(key).cloned());
+      }
     }
+  }
 
-    // last write txn finished record
-    let mut finish_record = LogRecord {
-      key: log_record_key_with_seq(TXN_FIN_KEY.to_vec(), seq_no),
-      value: Default::default(),
-      rec_type: LogRecordType::TxnFinished,
+  /// Commits the batch as several transactions, each within `max_batch_num` and
+  /// `max_batch_bytes`, returns the number of transactions.
+  ///
+  /// Keys are committed in order, every transaction has its own finish record. Writes of
+  /// committed chunks are dropped from the batch, so a failed commit can be retried.
+  pub fn commit_chunked(&self, atomicity: ChunkAtomicity) -> Result<usize> {
+    let mut pending_writes = self.pending_writes.lock();
+    let chunks = self.split_chunks(&pending_writes)?;
+    if chunks.len() > 1 && atomicity == ChunkAtomicity::AllOrNothing {
+      return Err(Errors::BatchNotAtomic);
+    }
+
+    for keys in chunks.iter() {
+      let items: Vec<&LogRecord> = keys
+        .iter()
+        .filter_map(|key| pending_writes.get(key))
+        .collect();
+      let bytes = items.iter().map(|item| record_bytes(item)).sum();
+      self.commit_txn(&items, bytes, None, true)?;
+      for key in keys {
+        pending_writes.remove(key);
+      }
+    }
+    self.savepoints.lock().clear();
+    Ok(chunks.len())
+  }
+
+  /// Groups the keys of the pending writes into chunks within the batch limits.
+  fn split_chunks(
+    &self,
+    pending_writes: &HashMap<Vec<u8>, LogRecord>,
+  ) -> Result<Vec<Vec<Vec<u8>>>> {
+    if !pending_writes.is_empty() && self.options.max_batch_num == 0 {
+      return Err(Errors::ExceedMaxBatchNum);
+    }
+    let max_bytes = match self.options.max_batch_bytes {
+      0 => usize::MAX,
+      max_bytes => max_bytes,
     };
 
-    // if sync writes configs, sync data file
-    self.engine.append_log_record(&mut finish_record)?;
-    if self.options.sync_writes {
-      self.engine.sync()?;
+    let mut keys: Vec<&Vec<u8>> = pending_writes.keys().collect();
+    keys.sort();
+    let mut chunks: Vec<Vec<Vec<u8>>> = Vec::new();
+    let mut chunk_bytes = 0;
+    for key in keys {
+      let bytes = pending_writes.get(key).map_or(0, record_bytes);
+      if bytes > max_bytes {
+        return Err(Errors::ExceedMaxBatchBytes);
+      }
+      match chunks.last_mut() {
+        Some(chunk)
+          if chunk.len() < self.options.max_batch_num && chunk_bytes + bytes <= max_bytes =>
+        {
+          chunk.push(key.clone());
+          chunk_bytes += bytes;
+        }
+        _ => {
+          chunks.push(vec![key.clone()]);
+          chunk_bytes = bytes;
+        }
+      }
+    }
+    Ok(chunks)
+  }
+
+  /// Writes the items as one transaction and applies them to the index.
+  ///
+  /// With `prepare` the items are written as the prepared transaction of that multi-engine
+  /// transaction instead, their records are synced and held back until it is finished.
+  #[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+      level = "debug",
+      skip_all,
+      fields(records = items.len(), bytes = bytes)
+    )
+  )]
+  fn commit_txn(
+    &self,
+    items: &[&LogRecord],
+    bytes: usize,
+    prepare: Option<u64>,
+    throttle: bool,
+  ) -> Result<()> {
+    // throttle before taking the commit lock, batches holding puts respect the backlog
+    let has_puts = items
+      .iter()
+      .any(|item| item.rec_type == LogRecordType::Normal);
+    let puts = items
+      .iter()
+      .filter(|item| item.rec_type == LogRecordType::Normal)
+      .map(|item| (&item.key[..], record_size(item.key.len(), item.value.len())));
+    self.engine.check_quota(puts)?;
+    if throttle {
+      self.engine.throttle_write(bytes, has_puts)?;
     }
 
-    // after write, update index
-    for (_, item) in pending_writes.iter() {
-      let record_pos = positions.get(&item.key).unwrap();
+    // mutex lock the engine to ensure serial write
+    let _gate = self.engine.write_gate.read_recursive();
+    let _lock = self.engine.batch_commit_lock.lock();
+
+    for item in items.iter() {
       if item.rec_type == LogRecordType::Normal {
-        if let Some(old_pos) = self.engine.index.put(item.key.clone(), *record_pos) {
-          self
-            .engine
-            .reclaim_size
-            .fetch_add(old_pos.size as usize, Ordering::SeqCst);
-        }
+        self.engine.check_index_memory(&item.key)?;
       }
-      if item.rec_type == LogRecordType::Deleted {
-        if let Some(old_pos) = self.engine.index.delete(item.key.clone()) {
-          self
-            .engine
-            .reclaim_size
-            .fetch_add(old_pos.size as usize, Ordering::SeqCst);
+    }
+
+    // obtain txn id
+    let seq_no = self.engine.seq_no.fetch_add(1, Ordering::SeqCst);
+    self.engine.reserve_seq_no(seq_no)?;
+
+    let mut records = Vec::with_capacity(items.len() + 1);
+    for item in items.iter() {
+      let value = match item.rec_type {
+        LogRecordType::Deleted => {
+          let deleted_pos = self.engine.index.get(item.key.clone());
+          self.engine.tombstone_value(deleted_pos)
         }
-      }
+        _ => item.value.clone(),
+      };
+      records.push(LogRecord {
+        key: log_record_key_with_seq(&item.key, seq_no),
+        value,
+        rec_type: item.rec_type,
+        timestamp: None,
+      });
     }
 
-    // clear pending writes for next commit
-    pending_writes.clear();
+    let Some(txn_id) = prepare else {
+      // last write txn finished record
+      records.push(LogRecord {
+        key: log_record_key_with_seq(TXN_FIN_KEY, seq_no),
+        value: Default::default(),
+        rec_type: LogRecordType::TxnFinished,
+        timestamp: None,
+      });
+
+      // write the records together, the finished record last
+      let positions = self.engine.append_log_records(&records)?;
+
+      // if sync writes configs, sync data file
+      if self.options.sync_writes {
+        self.engine.sync()?;
+      }
+
+      // after write, update index
+      return self
+        .engine
+        .apply_txn_writes(items.iter().copied().zip(positions));
+    };
+
+    // the prepared record names the multi-engine transaction
+    records.push(LogRecord {
+      key: log_record_key_with_seq(&txn_id.to_be_bytes(), seq_no),
+      value: Default::default(),
+      rec_type: LogRecordType::TxnPrepared,
+      timestamp: None,
+    });
+
+    // registered before the records are written, a merge rotating the file holding them
+    // finds the transaction pending
+    self
+      .engine
+      .prepared_txns
+      .lock()
+      .insert(txn_id, PreparedTxn::new(seq_no, false));
+    let positions = match self
+      .engine
+      .append_log_records(&records)
+      .and_then(|positions| self.engine.sync().map(|_| positions))
+    {
+      Ok(positions) => positions,
+      Err(e) => {
+        self.engine.prepared_txns.lock().remove(&txn_id);
+        return Err(e);
+      }
+    };
 
+    if let Some(txn) = self.engine.prepared_txns.lock().get_mut(&txn_id) {
+      txn.records = items
+        .iter()
+        .zip(positions)
+        .map(|(item, pos)| TransactionRecord {
+          record: LogRecord {
+            key: item.key.clone(),
+            value: item.value.clone(),
+            rec_type: item.rec_type,
+            timestamp: None,
+          },
+          pos,
+        })
+        .collect();
+    }
     Ok(())
   }
 }
 
-pub(crate) fn log_record_key_with_seq(key: Vec<u8>, seq_no: usize) -> Vec<u8> {
-  let mut enc_key = BytesMut::new();
-  encode_length_delimiter(seq_no, &mut enc_key).unwrap();
-  enc_key.extend_from_slice(&key.to_vec());
-  enc_key.to_vec()
+/// Bytes of a pending write counted towards the batch limits and the write throttle.
+fn record_bytes(item: &LogRecord) -> usize {
+  item.key.len() + item.value.len()
 }
 
-pub(crate) fn parse_log_record_key(key: Vec<u8>) -> (Vec<u8>, usize) {
+pub(crate) fn log_record_key_with_seq(key: &[u8], seq_no: usize) -> Vec<u8> {
+  let mut enc_key = Vec::with_capacity(length_delimiter_len(seq_no) + key.len());
+  // the vec grows on demand so it never fails
+  let _ = encode_length_delimiter(seq_no, &mut enc_key);
+  enc_key.extend_from_slice(key);
+  enc_key
+}
+
+pub(crate) fn parse_log_record_key(key: Vec<u8>) -> Result<(Vec<u8>, usize)> {
   let mut buf = BytesMut::new();
   buf.put_slice(&key);
-  let seq_no = decode_length_delimiter(&mut buf).unwrap();
-  (buf.to_vec(), seq_no)
+  let seq_no = decode_length_delimiter(&mut buf).map_err(|_| Errors::InvalidLogRecord)?;
+  Ok((buf.to_vec(), seq_no))
 }
 
 #[cfg(test)]
 mod tests {
+  use std::time::Duration;
+
   use tempfile::tempdir;
 
   use crate::{
@@ -180,6 +510,7 @@ mod tests {
 
   use super::*;
 
+  #[allow(clippy::field_reassign_with_default)]
   #[test]
   fn test_write_batch_1() {
     let temp_dir = tempdir().expect("failed to create temp dir");
@@ -212,6 +543,7 @@ mod tests {
     assert_eq!(2, seq_no);
   }
 
+  #[allow(clippy::field_reassign_with_default)]
   #[test]
   fn test_write_batch_2() {
     let temp_dir = tempdir().expect("failed to create temp dir");
@@ -245,6 +577,7 @@ mod tests {
     assert_eq!(3, seq_no);
   }
 
+  #[allow(clippy::field_reassign_with_default)]
   #[test]
   fn test_write_batch_3() {
     let temp_dir = tempdir().expect("failed to create temp dir");
@@ -267,4 +600,254 @@ mod tests {
     let commit_res1 = wb.commit();
     assert!(commit_res1.is_ok());
   }
+
+  #[allow(clippy::field_reassign_with_default)]
+  #[test]
+  fn test_write_batch_chunked() {
+    let temp_dir = tempdir().expect("failed to create temp dir");
+    let mut opt = Options::default();
+    opt.dir_path = temp_dir.path().to_path_buf();
+    let engine = Engine::open(opt.clone()).expect("fail to open engine");
+
+    let mut wb_opts = WriteBatchOptions::default();
+    wb_opts.max_batch_num = 40;
+    wb_opts.max_batch_bytes = 4096;
+    let wb = engine
+      .new_write_batch(wb_opts)
+      .expect("fail to create write batch");
+    for i in 0..100 {
+      wb.put(get_test_key(i), Bytes::from(vec![b'v'; 187]))
+        .unwrap();
+    }
+    assert_eq!(Err(Errors::ExceedMaxBatchNum), wb.commit());
+    assert_eq!(
+      Err(Errors::BatchNotAtomic),
+      wb.commit_chunked(ChunkAtomicity::AllOrNothing)
+    );
+    assert!(engine.list_keys().unwrap().is_empty());
+
+    // every chunk holds at most 4096 key and value bytes, each write takes 13 + 187
+    let seq_no = engine.seq_no.load(Ordering::SeqCst);
+    assert_eq!(5, wb.commit_chunked(ChunkAtomicity::PerChunk).unwrap());
+    assert_eq!(seq_no + 5, engine.seq_no.load(Ordering::SeqCst));
+    assert_eq!(100, engine.list_keys().unwrap().len());
+    // committed writes leave the batch
+    assert_eq!(0, wb.commit_chunked(ChunkAtomicity::PerChunk).unwrap());
+
+    // a single write over the byte limit never fits
+    wb.put(get_test_key(1), Bytes::from(vec![b'v'; 5000]))
+      .unwrap();
+    assert_eq!(Err(Errors::ExceedMaxBatchBytes), wb.commit());
+    assert_eq!(
+      Err(Errors::ExceedMaxBatchBytes),
+      wb.commit_chunked(ChunkAtomicity::PerChunk)
+    );
+
+    // the chunks are separate transactions, all of them are replayed on open
+    drop(wb);
+    engine.close().unwrap();
+    drop(engine);
+    let engine = Engine::open(opt).expect("fail to open engine");
+    assert_eq!(100, engine.list_keys().unwrap().len());
+  }
+
+  #[allow(clippy::field_reassign_with_default)]
+  #[test]
+  fn test_write_batch_across_files() {
+    let temp_dir = tempdir().expect("failed to create temp dir");
+    let mut opt = Options::default();
+    opt.dir_path = temp_dir.path().to_path_buf();
+    opt.data_file_size = 4096;
+    let engine = Engine::open(opt.clone()).expect("fail to open engine");
+
+    // the records are written with a vectored write per data file they land in
+    let wb = engine
+      .new_write_batch(WriteBatchOptions::default())
+      .expect("fail to create write batch");
+    for i in 0..100 {
+      wb.put(get_test_key(i), Bytes::from(vec![b'v'; 100]))
+        .unwrap();
+    }
+    wb.commit().unwrap();
+    let positions: Vec<_> = (0..100)
+      .map(|i| engine.index.get(get_test_key(i).to_vec()).unwrap())
+      .collect();
+    assert!(positions
+      .iter()
+      .all(|pos| pos.offset + pos.size as u64 <= 4096));
+    let file_ids: std::collections::BTreeSet<u32> =
+      positions.iter().map(|pos| pos.file_id).collect();
+    assert!(file_ids.len() > 1);
+    assert_eq!(
+      Bytes::from(vec![b'v'; 100]),
+      engine.get(get_test_key(99)).unwrap()
+    );
+
+    engine.close().unwrap();
+    drop(engine);
+    let engine = Engine::open(opt).expect("fail to open engine");
+    assert_eq!(100, engine.list_keys().unwrap().len());
+    assert_eq!(
+      positions[0],
+      engine.index.get(get_test_key(0).to_vec()).unwrap()
+    );
+  }
+
+  #[allow(clippy::field_reassign_with_default)]
+  #[test]
+  fn test_delete_many() {
+    let temp_dir = tempdir().expect("failed to create temp dir");
+    let mut opt = Options::default();
+    opt.dir_path = temp_dir.path().to_path_buf();
+    let engine = Engine::open(opt.clone()).expect("fail to open engine");
+    for i in 0..10 {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+
+    // an empty key fails the whole call
+    let mut keys: Vec<Bytes> = (0..5).map(get_test_key).collect();
+    keys.push(Bytes::new());
+    assert_eq!(Err(Errors::KeyIsEmpty), engine.delete_many(&keys));
+    assert_eq!(10, engine.list_keys().unwrap().len());
+
+    // the tombstones are written as one transaction
+    keys.pop();
+    keys.push(get_test_key(100));
+    let seq_no = engine.seq_no.load(Ordering::SeqCst);
+    assert_eq!(
+      vec![true, true, true, true, true, false],
+      engine.delete_many(&keys).unwrap()
+    );
+    assert_eq!(seq_no + 1, engine.seq_no.load(Ordering::SeqCst));
+    assert_eq!(5, engine.list_keys().unwrap().len());
+
+    // a deleted, a missing and an expired key are not found
+    engine
+      .expire(
+        get_test_key(9),
+        SystemTime::now() + Duration::from_millis(20),
+      )
+      .unwrap();
+    std::thread::sleep(Duration::from_millis(40));
+    let keys = vec![
+      get_test_key(0),
+      get_test_key(100),
+      get_test_key(9),
+      get_test_key(8),
+    ];
+    assert_eq!(
+      vec![false, false, false, true],
+      engine.delete_many(&keys).unwrap()
+    );
+    assert_eq!(3, engine.list_keys().unwrap().len());
+
+    engine.close().unwrap();
+    drop(engine);
+    let engine = Engine::open(opt).expect("fail to open engine");
+    assert_eq!(3, engine.list_keys().unwrap().len());
+    assert_eq!(
+      Errors::KeyNotFound,
+      engine.get(get_test_key(0)).unwrap_err()
+    );
+  }
+
+  #[allow(clippy::field_reassign_with_default)]
+  #[test]
+  fn test_write_batch_savepoints() {
+    let temp_dir = tempdir().expect("failed to create temp dir");
+    let mut opt = Options::default();
+    opt.dir_path = temp_dir.path().to_path_buf();
+    let engine = Engine::open(opt.clone()).expect("fail to open engine");
+    engine.put(get_test_key(0), get_test_value(0)).unwrap();
+
+    let wb = engine
+      .new_write_batch(WriteBatchOptions::default())
+      .expect("fail to create write batch");
+    assert_eq!(Err(Errors::NoSavepoint), wb.rollback_to_savepoint());
+    wb.put(get_test_key(1), get_test_value(1)).unwrap();
+
+    wb.savepoint();
+    wb.put(get_test_key(1), get_test_value(100)).unwrap();
+    wb.put(get_test_key(2), get_test_value(2)).unwrap();
+    // nested, rolled back on its own
+    wb.savepoint();
+    wb.delete(get_test_key(0)).unwrap();
+    wb.put(get_test_key(3), get_test_value(3)).unwrap();
+    wb.rollback_to_savepoint().unwrap();
+    wb.put(get_test_key(4), get_test_value(4)).unwrap();
+    wb.savepoint();
+    wb.commit().unwrap();
+
+    assert_eq!(get_test_value(0), engine.get(get_test_key(0)).unwrap());
+    assert_eq!(get_test_value(100), engine.get(get_test_key(1)).unwrap());
+    assert_eq!(get_test_value(2), engine.get(get_test_key(2)).unwrap());
+    assert_eq!(
+      Errors::KeyNotFound,
+      engine.get(get_test_key(3)).unwrap_err()
+    );
+    assert_eq!(get_test_value(4), engine.get(get_test_key(4)).unwrap());
+    // the commit removed the savepoints
+    assert_eq!(Err(Errors::NoSavepoint), wb.rollback_to_savepoint());
+
+    // the outer savepoint restores the writes from before it
+    wb.put(get_test_key(5), get_test_value(5)).unwrap();
+    wb.savepoint();
+    wb.put(get_test_key(5), get_test_value(500)).unwrap();
+    wb.delete(get_test_key(1)).unwrap();
+    wb.savepoint();
+    wb.put(get_test_key(6), get_test_value(6)).unwrap();
+    wb.rollback_to_savepoint().unwrap();
+    wb.rollback_to_savepoint().unwrap();
+    wb.commit().unwrap();
+    assert_eq!(get_test_value(5), engine.get(get_test_key(5)).unwrap());
+    assert_eq!(get_test_value(100), engine.get(get_test_key(1)).unwrap());
+    assert_eq!(
+      Errors::KeyNotFound,
+      engine.get(get_test_key(6)).unwrap_err()
+    );
+  }
+
+  #[allow(clippy::field_reassign_with_default)]
+  #[test]
+  fn test_write_batch_sharded_index() {
+    let temp_dir = tempdir().expect("failed to create temp dir");
+    let mut opt = Options::default();
+    opt.dir_path = temp_dir.path().to_path_buf();
+    opt.index_shards = 4;
+    let engine = Engine::open(opt.clone()).expect("fail to open engine");
+    for i in 0..100 {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    let reclaim_size = engine.get_engine_stat().unwrap().reclaim_size;
+
+    // overwrites and deletes spread over every shard
+    let wb = engine
+      .new_write_batch(WriteBatchOptions::default())
+      .expect("fail to create write batch");
+    for i in 0..100 {
+      match i % 3 {
+        0 => wb.delete(get_test_key(i)).unwrap(),
+        _ => wb.put(get_test_key(i), get_test_value(i + 1000)).unwrap(),
+      }
+    }
+    for i in 100..150 {
+      wb.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    wb.commit().unwrap();
+
+    for i in 0..150 {
+      match (i < 100, i % 3) {
+        (true, 0) => assert_eq!(Err(Errors::KeyNotFound), engine.get(get_test_key(i))),
+        (true, _) => assert_eq!(
+          get_test_value(i + 1000),
+          engine.get(get_test_key(i)).unwrap()
+        ),
+        _ => assert_eq!(get_test_value(i), engine.get(get_test_key(i)).unwrap()),
+      }
+    }
+    let stat = engine.get_engine_stat().unwrap();
+    assert_eq!(116, stat.key_num);
+    // every replaced record became reclaimable
+    assert!(stat.reclaim_size > reclaim_size);
+  }
 }
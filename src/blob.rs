@@ -0,0 +1,200 @@
+use std::collections::{hash_map::Entry, HashMap};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use parking_lot::{Mutex, RwLock};
+
+use crate::{
+  data::{
+    data_file::{get_blob_file_name, DataFile},
+    log_record::{LogRecord, LogRecordPos, LogRecordType},
+  },
+  db::Engine,
+  errors::{Errors, Result},
+  option::CrcImpl,
+};
+
+// offset and size of the record holding the value in the blob file
+const BLOB_POINTER_SIZE: usize = 8 + 4;
+
+/// Blob files of the data files, used when `blob_threshold` is set.
+#[derive(Default)]
+pub(crate) struct BlobFiles {
+  // blob file of the active file, opened on its first blob
+  active: Mutex<Option<DataFile>>,
+  // blob files opened for reads, by the id of their data file
+  readers: RwLock<HashMap<u32, DataFile>>,
+}
+
+/// Location of a value in the blob file of the data file holding the pointer record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BlobPointer {
+  pub(crate) offset: u64,
+  pub(crate) size: u32,
+}
+
+impl BlobPointer {
+  fn encode(&self) -> Vec<u8> {
+    let mut buf = BytesMut::with_capacity(BLOB_POINTER_SIZE);
+    buf.put_u64(self.offset);
+    buf.put_u32(self.size);
+    buf.to_vec()
+  }
+
+  pub(crate) fn decode(mut buf: &[u8]) -> Result<Self> {
+    if buf.len() != BLOB_POINTER_SIZE {
+      return Err(Errors::InvalidBlobPointer);
+    }
+    Ok(Self {
+      offset: buf.get_u64(),
+      size: buf.get_u32(),
+    })
+  }
+}
+
+impl Engine {
+  /// The record written instead of `log_record` when its value goes to a blob file. Its
+  /// pointer is filled in by `write_blob` without changing the size of the encoded record.
+  pub(crate) fn blob_pointer_record(&self, log_record: &LogRecord) -> Option<LogRecord> {
+    let threshold = self.options.blob_threshold;
+    let blob = threshold > 0
+      && log_record.rec_type == LogRecordType::Normal
+      && log_record.value.len() > threshold;
+    blob.then(|| LogRecord {
+      key: log_record.key.clone(),
+      value: vec![0; BLOB_POINTER_SIZE],
+      rec_type: LogRecordType::Blob,
+      timestamp: log_record.timestamp,
+    })
+  }
+
+  /// Appends `log_record` to the blob file of the data file `file_id`, returns its encoded
+  /// pointer record and the bytes written to the blob file. Called holding the active file,
+  /// so the blob file belongs to the data file the pointer record is written to.
+  pub(crate) fn write_blob(
+    &self,
+    file_id: u32,
+    log_record: &LogRecord,
+  ) -> Result<(Vec<u8>, usize)> {
+    let mut active = self.blobs.active.lock();
+    let blob_file = match active.take() {
+      Some(blob_file) if blob_file.get_file_id() == file_id => active.insert(blob_file),
+      _ => {
+        let blob_file = DataFile::new_blob_file(self.data_file_dir(file_id), file_id)?;
+        // the file is reopened after a restart, unreferenced bytes of a torn write stay
+        blob_file.set_write_off(blob_file.file_size());
+        active.insert(blob_file)
+      }
+    };
+
+    let enc_record = self.encode_log_record(log_record)?;
+    let pointer = BlobPointer {
+      offset: blob_file.get_write_off(),
+      size: enc_record.len() as u32,
+    };
+    blob_file.write(&enc_record)?;
+    // the measured directory size only follows the growth of the active data file
+    self.invalidate_disk_size();
+
+    let pointer_record = LogRecord {
+      key: log_record.key.clone(),
+      value: pointer.encode(),
+      rec_type: LogRecordType::Blob,
+      timestamp: log_record.timestamp,
+    };
+    Ok((self.encode_log_record(&pointer_record)?, enc_record.len()))
+  }
+
+  /// Size of the blob the record at `pos` refers to, 0 for records which are no blob
+  /// pointers. Only looked up when `blob_threshold` is set.
+  pub(crate) fn blob_len_at(&self, pos: &LogRecordPos) -> u64 {
+    if self.options.blob_threshold == 0 {
+      return 0;
+    }
+    // called while replaying records holding the data files
+    let record = {
+      let active_file = self.active_data_file.read_recursive();
+      match active_file.get_file_id() == pos.file_id {
+        true => active_file.read_value_with(pos.offset, None),
+        false => match self.old_data_files.read_recursive().get(&pos.file_id) {
+          Some(data_file) => data_file.read_value_with(pos.offset, None),
+          None => Err(Errors::DataFileNotFound),
+        },
+      }
+    };
+    match record {
+      Ok((LogRecordType::Blob, pointer)) => {
+        BlobPointer::decode(&pointer).map_or(0, |pointer| pointer.size as u64)
+      }
+      _ => 0,
+    }
+  }
+
+  /// Syncs the blob file of the active file, if it has one.
+  pub(crate) fn sync_active_blob(&self) -> Result<()> {
+    match &*self.blobs.active.lock() {
+      Some(blob_file) => blob_file.sync(),
+      None => Ok(()),
+    }
+  }
+
+  /// Syncs and closes the blob file of the data file `file_id` when it is sealed.
+  pub(crate) fn seal_blob_file(&self, file_id: u32) -> Result<()> {
+    let mut active = self.blobs.active.lock();
+    match active.take() {
+      Some(blob_file) if blob_file.get_file_id() == file_id => blob_file.sync(),
+      blob_file => {
+        *active = blob_file;
+        Ok(())
+      }
+    }
+  }
+
+  /// Forgets the blob files opened so far, their data files were removed.
+  pub(crate) fn clear_blob_files(&self) {
+    self.blobs.active.lock().take();
+    self.blobs.readers.write().clear();
+  }
+
+  /// Reads the value `pointer`, the value of a blob record of the data file `file_id`,
+  /// refers to. The crc32 of the blob is verified with `crc` unless it is None.
+  pub(crate) fn read_blob(
+    &self,
+    file_id: u32,
+    pointer: &[u8],
+    crc: Option<CrcImpl>,
+  ) -> Result<Bytes> {
+    let pointer = BlobPointer::decode(pointer)?;
+    let read = self.with_blob_file(file_id, |blob_file| {
+      blob_file.read_log_record_with(pointer.offset, crc)
+    })?;
+    if read.size != pointer.size as usize || read.record.rec_type != LogRecordType::Normal {
+      return Err(Errors::InvalidBlobPointer);
+    }
+    Ok(read.record.value.into())
+  }
+
+  /// Runs `f` with the blob file of the data file `file_id`, opened for reads on first use.
+  pub(crate) fn with_blob_file<T>(
+    &self,
+    file_id: u32,
+    f: impl FnOnce(&DataFile) -> Result<T>,
+  ) -> Result<T> {
+    if let Some(blob_file) = self.blobs.readers.read().get(&file_id) {
+      return f(blob_file);
+    }
+
+    let file_dir = self.data_file_dir(file_id);
+    if !get_blob_file_name(&file_dir, file_id).is_file() {
+      return Err(Errors::BlobFileNotFound);
+    }
+    let mut readers = self.blobs.readers.write();
+    let blob_file = match readers.entry(file_id) {
+      Entry::Occupied(entry) => entry.into_mut(),
+      Entry::Vacant(entry) => {
+        let blob_file = DataFile::new_blob_file(&file_dir, file_id)?;
+        entry.insert(blob_file.with_cipher(self.cipher.as_ref()))
+      }
+    };
+    f(blob_file)
+  }
+}
@@ -0,0 +1,227 @@
+use bytes::Bytes;
+
+use crate::{
+  batch::{log_record_key_with_seq, NON_TXN_SEQ_NO},
+  cdc::CdcOp,
+  data::{
+    data_file::DataFile,
+    log_record::{LogRecord, LogRecordPos, LogRecordType},
+  },
+  db::Engine,
+  errors::{Errors, Result},
+  io_stats::ingested_bytes,
+  keyspace::record_size,
+  option::BulkLoadOptions,
+  watch::WatchOp,
+};
+
+impl Engine {
+  /// Loads many key/value pairs, much faster than a `put` per pair.
+  ///
+  /// The records of a batch of `BulkLoadOptions::batch_size` pairs are appended with a single
+  /// write per data file and put into the index together, the data files are synced once at
+  /// the end. Key directories are written for the data files as usual with `startup_manifest`.
+  /// Pairs loaded before a failure are kept. Fails when the index can not be updated, the
+  /// records of that batch are then written but not indexed, like a failed `put`. Returns the
+  /// number of pairs loaded.
+  pub fn bulk_load<I>(&self, pairs: I, opts: BulkLoadOptions) -> Result<usize>
+  where
+    I: IntoIterator<Item = (Bytes, Bytes)>,
+  {
+    if self.reader.is_some() {
+      return Err(Errors::ReadOnlyEngine);
+    }
+
+    let batch_size = opts.batch_size.max(1);
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut last_key: Option<Bytes> = None;
+    let mut loaded = 0;
+    for (key, value) in pairs {
+      if key.is_empty() {
+        return Err(Errors::KeyIsEmpty);
+      }
+      if opts.sorted {
+        if last_key.as_ref().is_some_and(|last_key| *last_key >= key) {
+          return Err(Errors::BulkLoadKeysUnsorted);
+        }
+        last_key = Some(key.clone());
+      }
+
+      batch.push((key, value));
+      if batch.len() == batch_size {
+        loaded += self.load_batch(&mut batch, opts.sorted)?;
+      }
+    }
+    loaded += self.load_batch(&mut batch, opts.sorted)?;
+    self.sync()?;
+    Ok(loaded)
+  }
+
+  fn load_batch(&self, batch: &mut Vec<(Bytes, Bytes)>, sorted: bool) -> Result<usize> {
+    if batch.is_empty() {
+      return Ok(0);
+    }
+    for (key, _) in batch.iter() {
+      self.check_index_memory(key)?;
+    }
+    let puts = batch
+      .iter()
+      .map(|(key, value)| (&key[..], record_size(key.len(), value.len())));
+    self.check_quota(puts)?;
+    let bytes = batch
+      .iter()
+      .map(|(key, value)| key.len() + value.len())
+      .sum();
+    self.throttle_write(bytes, true)?;
+
+    let _gate = self.write_gate.read_recursive();
+    let positions = self.append_bulk_records(batch)?;
+    let entries = batch
+      .iter()
+      .zip(positions.iter())
+      .map(|((key, _), pos)| (key.to_vec(), *pos))
+      .collect();
+    let old_positions = self.index.try_put_batch(entries, sorted)?;
+    for (((key, _), pos), old_pos) in batch.iter().zip(positions).zip(old_positions) {
+      self.account_quota(key, old_pos, Some(pos));
+      if let Some(old_pos) = old_pos {
+        self.mark_stale(old_pos);
+      }
+    }
+
+    self.mark_changed();
+    self.emit_cdc(
+      batch
+        .iter()
+        .map(|(key, value)| (CdcOp::Put, &key[..], &value[..])),
+    );
+    let loaded = batch.len();
+    for (key, value) in batch.drain(..) {
+      self.clear_expiry(&key);
+      self.watchers.notify(&key, WatchOp::Put, Some(&value));
+    }
+    Ok(loaded)
+  }
+
+  // appends the records of a batch with one write per data file instead of one per record
+  fn append_bulk_records(&self, batch: &[(Bytes, Bytes)]) -> Result<Vec<LogRecordPos>> {
+    let mut positions = Vec::with_capacity(batch.len());
+    let mut keys = Vec::new(); // keys of the records in the buffer, for their key directory
+    let mut buf = Vec::new();
+
+    let mut active_file = self.active_data_file.write();
+    self.check_open()?;
+    for (key, value) in batch {
+      let record = LogRecord {
+        key: log_record_key_with_seq(key, NON_TXN_SEQ_NO),
+        value: value.to_vec(),
+        rec_type: LogRecordType::Normal,
+        timestamp: None,
+      };
+      let enc_record = self.encode_log_record(&record)?;
+      let record_len = enc_record.len() as u64;
+
+      let write_off = active_file.get_write_off();
+      if write_off + buf.len() as u64 + record_len > self.options.data_file_size {
+        self.flush_bulk_records(&active_file, &buf, &mut keys, &positions)?;
+        buf.clear();
+        self.rotate_active_file(&mut active_file)?;
+      }
+
+      let pos = LogRecordPos {
+        file_id: active_file.get_file_id(),
+        offset: active_file.get_write_off() + buf.len() as u64,
+        size: record_len as u32,
+      };
+      self
+        .io_stats
+        .record_write(record_len, ingested_bytes(&record));
+      buf.extend_from_slice(&enc_record);
+      keys.push(record.key);
+      positions.push(pos);
+    }
+    self.flush_bulk_records(&active_file, &buf, &mut keys, &positions)?;
+    Ok(positions)
+  }
+
+  // writes the buffered records, the last `keys.len()` positions are theirs
+  fn flush_bulk_records(
+    &self,
+    active_file: &DataFile,
+    buf: &[u8],
+    keys: &mut Vec<Vec<u8>>,
+    positions: &[LogRecordPos],
+  ) -> Result<()> {
+    if buf.is_empty() {
+      return Ok(());
+    }
+    active_file.write_records(buf, keys.len() as u64)?;
+    let buffered = &positions[positions.len() - keys.len()..];
+    for (key, pos) in keys.drain(..).zip(buffered) {
+      self.track_keydir_record(&key, LogRecordType::Normal, *pos);
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::option::Options;
+
+  fn pair(i: usize) -> (Bytes, Bytes) {
+    (
+      Bytes::from(format!("key-{i:08}")),
+      Bytes::from(format!("value-{i}")),
+    )
+  }
+
+  #[test]
+  fn test_bulk_load() {
+    let dir = tempfile::tempdir().unwrap();
+    let opts = Options {
+      dir_path: dir.path().to_path_buf(),
+      data_file_size: 64 * 1024,
+      startup_manifest: true,
+      ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("fail to open engine");
+    engine
+      .put(Bytes::from("key-00000005"), Bytes::from("old"))
+      .unwrap();
+
+    let sorted = BulkLoadOptions {
+      batch_size: 1000,
+      sorted: true,
+    };
+    assert_eq!(
+      10000,
+      engine.bulk_load((0..10000).map(pair), sorted).unwrap()
+    );
+    assert!(engine.old_data_files.read().len() > 1);
+    assert_eq!(pair(5).1, engine.get(pair(5).0).unwrap());
+    assert!(engine.get_engine_stat().unwrap().reclaim_size > 0);
+
+    // unsorted keys overwrite the loaded ones
+    let unsorted = (0..100).rev().map(|i| (pair(i).0, Bytes::from("new")));
+    assert_eq!(
+      100,
+      engine
+        .bulk_load(unsorted, BulkLoadOptions::default())
+        .unwrap()
+    );
+    let err = engine.bulk_load([pair(2), pair(1)], sorted).unwrap_err();
+    assert_eq!(Errors::BulkLoadKeysUnsorted, err);
+    engine.close().unwrap();
+    drop(engine);
+
+    // loaded files are replayed from their key directories and sealed with a valid footer
+    let engine = Engine::open(opts).expect("fail to open engine");
+    for data_file in engine.old_data_files.read().values() {
+      assert!(data_file.verify_footer().unwrap());
+    }
+    assert_eq!(10000, engine.list_keys().unwrap().len());
+    assert_eq!(Bytes::from("new"), engine.get(pair(99).0).unwrap());
+    assert_eq!(pair(9999).1, engine.get(pair(9999).0).unwrap());
+  }
+}
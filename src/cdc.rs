@@ -0,0 +1,487 @@
+//! Change data capture: the committed writes of an engine teed to a stream of frames.
+//!
+//! Set `Options::cdc_output` to a file or a writer to receive the stream. Each frame holds one
+//! change, integers are big endian:
+//!
+//! | field   | size        | content                                        |
+//! |---------|-------------|------------------------------------------------|
+//! | `len`   | 4           | size of the body                               |
+//! | `seq`   | 8           | sequence number, one more than the last frame  |
+//! | `op`    | 1           | 1 put, 2 delete, 3 expire, 4 clear             |
+//! | `flags` | 1           | bit 0 set on the last frame of a commit        |
+//! | key len | varint      | size of the key, prost length delimiter        |
+//! | key     | key len     | the key, empty for clears                      |
+//! | value   | rest of len | the value of puts, the expiration time in unix |
+//! |         |             | milliseconds as 8 bytes for expires, else none |
+//! | `crc`   | 4           | crc32 of the body, `seq` to value              |
+//!
+//! The changes of a write batch come as consecutive frames, the last one flagged. Frames are
+//! written once the change is applied, a crash may lose the frames of the last commits but
+//! never leaves a frame for a change which was not applied. [`CdcReader`] decodes the stream.
+//!
+//! Frames are numbered in the order commits emit them once applied, not the order their
+//! records were appended to the data files. Commits running concurrently may reach the stream
+//! in the other order, consumers must not expect `seq` to follow the data files.
+
+use std::{
+  fs::{File, OpenOptions},
+  io::{self, BufReader, Read},
+};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use log::{error, warn};
+use parking_lot::Mutex;
+use prost::{decode_length_delimiter, encode_length_delimiter, length_delimiter_len};
+
+use crate::{
+  db::Engine,
+  errors::{Errors, Result},
+  option::{CdcOutput, CdcSink},
+};
+
+// length prefix and checksum around the body of a frame
+const FRAME_OVERHEAD: usize = 4 + 4;
+// sequence number, op and flags at the start of the body
+const BODY_HEADER_SIZE: usize = 8 + 1 + 1;
+const FLAG_END_OF_COMMIT: u8 = 1;
+
+/// Kind of change carried by a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CdcOp {
+  Put = 1,
+
+  Delete = 2,
+
+  /// The key expires at the time in the value
+  Expire = 3,
+
+  /// All keys were removed
+  Clear = 4,
+}
+
+impl CdcOp {
+  fn from_u8(op: u8) -> Result<Self> {
+    match op {
+      1 => Ok(CdcOp::Put),
+      2 => Ok(CdcOp::Delete),
+      3 => Ok(CdcOp::Expire),
+      4 => Ok(CdcOp::Clear),
+      _ => Err(Errors::InvalidCdcFrame),
+    }
+  }
+}
+
+/// A change read from the stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CdcFrame {
+  pub seq: u64,
+
+  pub op: CdcOp,
+
+  pub key: Bytes,
+
+  pub value: Bytes,
+
+  /// Whether the frame is the last change of its commit
+  pub end_of_commit: bool,
+}
+
+impl CdcFrame {
+  /// Encodes the frame as written to the stream.
+  pub fn encode(&self) -> Vec<u8> {
+    let mut buf = BytesMut::with_capacity(frame_size(self.key.len(), self.value.len()));
+    encode_frame(
+      &mut buf,
+      self.seq,
+      (self.op, &self.key, &self.value),
+      self.end_of_commit,
+    );
+    buf.to_vec()
+  }
+
+  fn decode(mut body: &[u8]) -> Result<Self> {
+    if body.len() < BODY_HEADER_SIZE {
+      return Err(Errors::InvalidCdcFrame);
+    }
+    let seq = body.get_u64();
+    let op = CdcOp::from_u8(body.get_u8())?;
+    let flags = body.get_u8();
+    let key_len = decode_length_delimiter(&mut body).map_err(|_| Errors::InvalidCdcFrame)?;
+    if key_len > body.len() {
+      return Err(Errors::InvalidCdcFrame);
+    }
+    let (key, value) = body.split_at(key_len);
+    Ok(CdcFrame {
+      seq,
+      op,
+      key: Bytes::copy_from_slice(key),
+      value: Bytes::copy_from_slice(value),
+      end_of_commit: flags & FLAG_END_OF_COMMIT != 0,
+    })
+  }
+}
+
+/// Decodes the frames of a change data capture stream.
+pub struct CdcReader<R> {
+  reader: R,
+  offset: u64, // bytes of the frames read so far
+}
+
+impl<R: Read> CdcReader<R> {
+  pub fn new(reader: R) -> Self {
+    Self { reader, offset: 0 }
+  }
+
+  /// Reads the next frame, `None` at the end of the stream.
+  ///
+  /// A frame cut off by the end of the stream or failing its checksum is `InvalidCdcFrame`,
+  /// a failed read of the stream `FailedToReadCdcStream`.
+  pub fn next_frame(&mut self) -> Result<Option<CdcFrame>> {
+    let mut len_buf = [0; 4];
+    let read = read_full(&mut self.reader, &mut len_buf)?;
+    if read == 0 {
+      return Ok(None);
+    }
+    if read < len_buf.len() {
+      return Err(Errors::InvalidCdcFrame);
+    }
+
+    // a corrupted length must not allocate more than the stream holds
+    let len = u32::from_be_bytes(len_buf) as usize;
+    let mut buf = Vec::new();
+    (&mut self.reader)
+      .take((len + 4) as u64)
+      .read_to_end(&mut buf)
+      .map_err(|e| {
+        error!("failed to read change data capture frame: {e}");
+        Errors::FailedToReadCdcStream
+      })?;
+    if buf.len() < len + 4 {
+      return Err(Errors::InvalidCdcFrame);
+    }
+    let (body, mut crc) = buf.split_at(len);
+    if crc32fast::hash(body) != crc.get_u32() {
+      return Err(Errors::InvalidCdcFrame);
+    }
+    let frame = CdcFrame::decode(body)?;
+    self.offset += (len + FRAME_OVERHEAD) as u64;
+    Ok(Some(frame))
+  }
+
+  /// Bytes of the frames read so far.
+  pub fn offset(&self) -> u64 {
+    self.offset
+  }
+}
+
+/// State of the change data capture stream of an engine, see [`Engine::cdc_status`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CdcStatus {
+  /// Sequence number of the last frame written, 0 before the first
+  pub last_seq: u64,
+
+  /// The write which stopped the stream, later changes are not written
+  pub error: Option<Errors>,
+}
+
+/// The change data capture output of an engine.
+pub(crate) struct CdcStream {
+  sink: CdcSink,
+  state: Mutex<CdcState>,
+}
+
+struct CdcState {
+  last_seq: u64,
+  error: Option<Errors>,
+}
+
+impl CdcStream {
+  /// Opens the output, a file torn by a crash is cut back to its last whole frame.
+  pub(crate) fn open(output: &CdcOutput) -> Result<Self> {
+    let (sink, last_seq) = match output {
+      CdcOutput::Writer(sink) => (sink.clone(), 0),
+      CdcOutput::File(path) => {
+        let file = OpenOptions::new()
+          .create(true)
+          .read(true)
+          .append(true)
+          .open(path)
+          .map_err(|e| {
+            error!("failed to open change data capture file: {e}");
+            Errors::FailedToOpenCdcOutput
+          })?;
+        let last_seq = recover_cdc_file(&file)?;
+        (CdcSink::new(file), last_seq)
+      }
+    };
+    Ok(Self {
+      sink,
+      state: Mutex::new(CdcState {
+        last_seq,
+        error: None,
+      }),
+    })
+  }
+
+  /// Writes the changes of one commit, a failed write stops the stream for good so a
+  /// consumer never misses a frame in the middle. Returns the error stopping it.
+  fn write_commit(&self, changes: &[(CdcOp, &[u8], &[u8])]) -> Option<Errors> {
+    let mut state = self.state.lock();
+    if state.error.is_some() || changes.is_empty() {
+      return None;
+    }
+    let size = changes
+      .iter()
+      .map(|(_, key, value)| frame_size(key.len(), value.len()))
+      .sum();
+    let mut buf = BytesMut::with_capacity(size);
+    for (i, change) in changes.iter().enumerate() {
+      let end_of_commit = i == changes.len() - 1;
+      encode_frame(
+        &mut buf,
+        state.last_seq + 1 + i as u64,
+        *change,
+        end_of_commit,
+      );
+    }
+
+    match self.sink.write_frames(&buf) {
+      Ok(()) => {
+        state.last_seq += changes.len() as u64;
+        None
+      }
+      Err(e) => {
+        error!("failed to write change data capture frames, stopping the stream: {e}");
+        state.error = Some(Errors::FailedToWriteCdcOutput);
+        state.error.clone()
+      }
+    }
+  }
+
+  fn status(&self) -> CdcStatus {
+    let state = self.state.lock();
+    CdcStatus {
+      last_seq: state.last_seq,
+      error: state.error.clone(),
+    }
+  }
+}
+
+impl Engine {
+  /// Tees the changes of one commit to the change data capture output, if there is one.
+  pub(crate) fn emit_cdc<'a, I>(&self, changes: I)
+  where
+    I: IntoIterator<Item = (CdcOp, &'a [u8], &'a [u8])>,
+  {
+    let Some(cdc) = &self.cdc else {
+      return;
+    };
+    let changes: Vec<_> = changes.into_iter().collect();
+    if let Some(e) = cdc.write_commit(&changes) {
+      self.listeners.cdc_stopped(&e);
+    }
+  }
+
+  /// Whether changes are teed to a change data capture output, see `Options::cdc_output`.
+  pub(crate) fn cdc_enabled(&self) -> bool {
+    self.cdc.is_some()
+  }
+
+  /// State of the change data capture stream, `None` without `Options::cdc_output`.
+  pub fn cdc_status(&self) -> Option<CdcStatus> {
+    self.cdc.as_ref().map(CdcStream::status)
+  }
+}
+
+fn frame_size(key_len: usize, value_len: usize) -> usize {
+  FRAME_OVERHEAD + BODY_HEADER_SIZE + length_delimiter_len(key_len) + key_len + value_len
+}
+
+fn encode_frame(
+  buf: &mut BytesMut,
+  seq: u64,
+  (op, key, value): (CdcOp, &[u8], &[u8]),
+  end_of_commit: bool,
+) {
+  let len = frame_size(key.len(), value.len()) - FRAME_OVERHEAD;
+  buf.put_u32(len as u32);
+  let body_start = buf.len();
+  buf.put_u64(seq);
+  buf.put_u8(op as u8);
+  buf.put_u8(match end_of_commit {
+    true => FLAG_END_OF_COMMIT,
+    false => 0,
+  });
+  // BytesMut grows on demand so it never fails
+  let _ = encode_length_delimiter(key.len(), buf);
+  buf.put_slice(key);
+  buf.put_slice(value);
+  let crc = crc32fast::hash(&buf[body_start..]);
+  buf.put_u32(crc);
+}
+
+// reads until `buf` is full or the end of the stream, returns the number of bytes read
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize> {
+  let mut read = 0;
+  while read < buf.len() {
+    match reader.read(&mut buf[read..]) {
+      Ok(0) => break,
+      Ok(n) => read += n,
+      Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+      Err(e) => {
+        error!("failed to read change data capture frame: {e}");
+        return Err(Errors::FailedToReadCdcStream);
+      }
+    }
+  }
+  Ok(read)
+}
+
+// finds the last sequence number of the file, cutting a torn or corrupted frame at its end,
+// failed reads are returned
+fn recover_cdc_file(file: &File) -> Result<u64> {
+  let mut reader = CdcReader::new(BufReader::new(file));
+  let mut last_seq = 0;
+  loop {
+    match reader.next_frame() {
+      Ok(Some(frame)) => last_seq = frame.seq,
+      Ok(None) => return Ok(last_seq),
+      Err(Errors::InvalidCdcFrame) => {
+        let end = reader.offset();
+        warn!("truncating change data capture file at offset {end}: invalid frame");
+        file.set_len(end).map_err(|e| {
+          error!("failed to truncate change data capture file: {e}");
+          Errors::FailedToOpenCdcOutput
+        })?;
+        return Ok(last_seq);
+      }
+      Err(e) => return Err(e),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{fs, sync::Arc};
+
+  use super::*;
+  use crate::{
+    option::{Options, WriteBatchOptions},
+    util::rand_kv::{get_test_key, get_test_value},
+  };
+
+  #[derive(Clone, Default)]
+  struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+  impl io::Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+      self.0.lock().extend_from_slice(buf);
+      Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+      Ok(())
+    }
+  }
+
+  fn read_frames(stream: &[u8]) -> Vec<CdcFrame> {
+    let mut reader = CdcReader::new(stream);
+    let mut frames = Vec::new();
+    while let Some(frame) = reader.next_frame().unwrap() {
+      frames.push(frame);
+    }
+    frames
+  }
+
+  #[test]
+  fn test_cdc_writer_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let buf = SharedBuf::default();
+    let opts = Options {
+      dir_path: dir.path().to_path_buf(),
+      cdc_output: Some(CdcOutput::Writer(CdcSink::new(buf.clone()))),
+      ..Default::default()
+    };
+    let engine = Engine::open(opts).unwrap();
+
+    engine.put(get_test_key(1), get_test_value(1)).unwrap();
+    engine.delete(get_test_key(1)).unwrap();
+    // deleting a missing key changes nothing
+    engine.delete(get_test_key(1)).unwrap();
+    let wb = engine
+      .new_write_batch(WriteBatchOptions::default())
+      .unwrap();
+    wb.put(get_test_key(2), get_test_value(2)).unwrap();
+    wb.put(get_test_key(3), get_test_value(3)).unwrap();
+    wb.commit().unwrap();
+    engine.clear().unwrap();
+
+    let frames = read_frames(&buf.0.lock());
+    let ops: Vec<_> = frames
+      .iter()
+      .map(|frame| (frame.seq, frame.op, frame.end_of_commit))
+      .collect();
+    assert_eq!(
+      vec![
+        (1, CdcOp::Put, true),
+        (2, CdcOp::Delete, true),
+        (3, CdcOp::Put, false),
+        (4, CdcOp::Put, true),
+        (5, CdcOp::Clear, true),
+      ],
+      ops
+    );
+    assert_eq!(get_test_key(1), frames[0].key);
+    assert_eq!(get_test_value(1), frames[0].value);
+    assert!(frames[1].value.is_empty());
+    let mut batch_keys = vec![frames[2].key.clone(), frames[3].key.clone()];
+    batch_keys.sort();
+    assert_eq!(vec![get_test_key(2), get_test_key(3)], batch_keys);
+    assert_eq!(
+      Some(CdcStatus {
+        last_seq: 5,
+        error: None
+      }),
+      engine.cdc_status()
+    );
+  }
+
+  #[test]
+  fn test_cdc_file_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let cdc_path = dir.path().join("changes.cdc");
+    let opts = Options {
+      dir_path: dir.path().join("db"),
+      cdc_output: Some(CdcOutput::File(cdc_path.clone())),
+      ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).unwrap();
+    engine.put(get_test_key(1), get_test_value(1)).unwrap();
+    engine.put(get_test_key(2), get_test_value(2)).unwrap();
+    engine.close().unwrap();
+    drop(engine);
+
+    // a torn frame is cut off and the sequence numbers continue from the last whole frame
+    let mut stream = fs::read(&cdc_path).unwrap();
+    let whole = stream.len();
+    stream.extend_from_slice(&[0, 0, 0, 40, 1, 2]);
+    fs::write(&cdc_path, &stream).unwrap();
+    let engine = Engine::open(opts).unwrap();
+    assert_eq!(whole as u64, fs::metadata(&cdc_path).unwrap().len());
+    engine.put(get_test_key(3), get_test_value(3)).unwrap();
+
+    let frames = read_frames(&fs::read(&cdc_path).unwrap());
+    let seqs: Vec<_> = frames.iter().map(|frame| frame.seq).collect();
+    assert_eq!(vec![1, 2, 3], seqs);
+    assert_eq!(get_test_key(3), frames[2].key);
+
+    // a broken checksum is found by the reader
+    let mut stream = fs::read(&cdc_path).unwrap();
+    let last = stream.len() - 1;
+    stream[last] ^= 0xff;
+    let mut reader = CdcReader::new(&stream[..]);
+    reader.next_frame().unwrap();
+    reader.next_frame().unwrap();
+    assert_eq!(Err(Errors::InvalidCdcFrame), reader.next_frame());
+  }
+}
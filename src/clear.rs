@@ -0,0 +1,221 @@
+use std::{collections::HashMap, fs, path::Path, sync::atomic::Ordering};
+
+use fs2::FileExt;
+use log::{error, warn};
+
+use crate::{
+  cdc::CdcOp,
+  data::{
+    data_file::{
+      DataFile, CLEAR_MARKER_FILE_NAME, CLOSE_HINT_FILE_NAME, GARBAGE_MAP_FILE_NAME,
+      HINT_FILE_NAME, MANIFEST_FILE_NAME, MERGE_FINISHED_FILE_NAME,
+    },
+    log_record::{LogRecord, LogRecordType},
+  },
+  db::{parse_record_value, Engine, FILE_LOCK_NAME},
+  errors::{Errors, Result},
+  iterator::PositionRemap,
+  layout::{data_file_dirs, parse_data_file_id},
+  merge::{get_merge_path, remove_dir},
+  option::{Options, StorageMode},
+  reader::lock_out_readers,
+};
+
+const CLEAR_KEY: &[u8] = "clear.fid".as_bytes();
+
+impl Engine {
+  /// Removes all keys from the engine.
+  ///
+  /// The engine switches to a fresh active file and the old data files are deleted. A clear
+  /// marker naming the new file is written first, so a crash halfway is finished on the next
+  /// open instead of bringing back part of the old keys. Writes from other threads are
+  /// ordered before or after the clear, a write appended to a removed file is never indexed.
+  pub fn clear(&self) -> Result<()> {
+    if self.reader.is_some() {
+      return Err(Errors::ReadOnlyEngine);
+    }
+    let lock = self.merging_lock.try_lock();
+    if lock.is_none() {
+      return Err(Errors::MergeInProgress);
+    }
+    // waits for writes between their append and their index update, held until the old
+    // files are removed
+    let _gate = self.write_gate.write();
+    let _commit_guard = self.batch_commit_lock.lock();
+    let mut active_file = self.active_data_file.write();
+    self.check_open()?;
+    self.check_no_prepared_txns()?;
+    self.check_no_pinned_epochs()?;
+    let mut old_files = self.old_data_files.write();
+
+    let dir_path = &self.options.dir_path;
+    let in_memory = self.options.storage_mode == StorageMode::Memory;
+    let new_file_id = active_file.get_file_id() + 1;
+    if !in_memory {
+      write_clear_marker(dir_path, new_file_id)?;
+    }
+
+    // iterators created before skip the cleared keys instead of failing on the removed files
+    self.replace_data_files(|| {
+      let replaced_files = old_files
+        .keys()
+        .copied()
+        .chain([active_file.get_file_id()])
+        .collect();
+      *active_file = self.new_active_data_file(new_file_id)?;
+      old_files.clear();
+      self.index.clear()?;
+      Ok(PositionRemap {
+        moved: HashMap::new(),
+        replaced_files,
+      })
+    })?;
+    self.expiry.clear();
+    self.mark_changed();
+    self.emit_cdc([(CdcOp::Clear, &[][..], &[][..])]);
+    self.garbage.restore(Vec::new());
+    self.keydir.lock().clear();
+    self.clear_blob_files();
+    self.reclaim_size.store(0, Ordering::SeqCst);
+    self.refresh_quota_usage()?;
+    if in_memory {
+      return Ok(());
+    }
+
+    remove_cleared_files(dir_path, new_file_id)?;
+    remove_file(&dir_path.join(CLEAR_MARKER_FILE_NAME))?;
+    self.invalidate_disk_size();
+    Ok(())
+  }
+
+  /// Deletes the database directory of `opts` with all its files.
+  ///
+  /// The directory lock is taken first, so a database still opened by an engine is never
+  /// deleted. A missing directory is not an error.
+  pub fn destroy(opts: Options) -> Result<()> {
+    let dir_path = &opts.dir_path;
+    let merge_path = get_merge_path(dir_path)?;
+    if !dir_path.exists() {
+      if merge_path.is_dir() {
+        remove_dir(&merge_path)?;
+      }
+      return Ok(());
+    }
+    if !dir_path.is_dir() {
+      return Err(Errors::InvalidDirPath);
+    }
+
+    let lock_file = fs::OpenOptions::new()
+      .read(true)
+      .create(true)
+      .append(true)
+      .open(dir_path.join(FILE_LOCK_NAME))
+      .map_err(|e| {
+        error!("failed to open database lock file: {e}");
+        Errors::FailedToOpenLockFile
+      })?;
+    if lock_file.try_lock_exclusive().is_err() {
+      return Err(Errors::DatabaseIsUsing);
+    }
+    let _reader_lock = lock_out_readers(dir_path)?;
+
+    // the merge directory belongs to the database, it lives next to it
+    if merge_path.is_dir() {
+      remove_dir(&merge_path)?;
+    }
+    remove_dir(dir_path)
+  }
+}
+
+/// Finishes a clear interrupted by a crash, returns whether there was one.
+///
+/// The index has to be cleared by the caller if it is persistent.
+pub(crate) fn recover_clear<P>(dir_path: P) -> Result<bool>
+where
+  P: AsRef<Path>,
+{
+  let dir_path = dir_path.as_ref();
+  let marker = dir_path.join(CLEAR_MARKER_FILE_NAME);
+  if !marker.is_file() {
+    return Ok(false);
+  }
+
+  // the marker is synced before any file is touched, a torn one means nothing was cleared
+  let record = DataFile::new_clear_marker_file(dir_path)?.read_log_record(0);
+  let new_file_id: u32 = match record {
+    Ok(result) if result.record.key == CLEAR_KEY => parse_record_value(result.record.value)?,
+    Ok(_) | Err(_) => {
+      warn!("clear marker is unreadable, keeping the data files");
+      remove_file(&marker)?;
+      return Ok(false);
+    }
+  };
+
+  remove_cleared_files(dir_path, new_file_id)?;
+  remove_file(&marker)?;
+  Ok(true)
+}
+
+fn write_clear_marker(dir_path: &Path, new_file_id: u32) -> Result<()> {
+  remove_file(&dir_path.join(CLEAR_MARKER_FILE_NAME))?;
+  let marker_file = DataFile::new_clear_marker_file(dir_path)?;
+  let record = LogRecord {
+    key: CLEAR_KEY.to_vec(),
+    value: new_file_id.to_string().into(),
+    rec_type: LogRecordType::Normal,
+    timestamp: None,
+  };
+  marker_file.write(&record.encode())?;
+  marker_file.sync()
+}
+
+/// Removes the data files below `file_id` and every file derived from them.
+fn remove_cleared_files(dir_path: &Path, file_id: u32) -> Result<()> {
+  for data_dir in data_file_dirs(dir_path)? {
+    let dir = fs::read_dir(&data_dir).map_err(|_| Errors::FailedToReadDatabaseDir)?;
+    for file in dir.flatten() {
+      let file_os_str = file.file_name();
+      let Some(file_name) = file_os_str.to_str() else {
+        continue;
+      };
+      let cleared = match parse_data_file_id(file_name) {
+        Some(fid) => fid < file_id,
+        None => {
+          data_dir == dir_path
+            && [
+              HINT_FILE_NAME,
+              MERGE_FINISHED_FILE_NAME,
+              MANIFEST_FILE_NAME,
+              CLOSE_HINT_FILE_NAME,
+              GARBAGE_MAP_FILE_NAME,
+            ]
+            .contains(&file_name)
+        }
+      };
+      if cleared {
+        remove_file(&file.path())?;
+      }
+    }
+
+    // only fails if the subdirectory still holds files
+    if data_dir != dir_path {
+      let _ = fs::remove_dir(&data_dir);
+    }
+  }
+
+  let merge_path = get_merge_path(dir_path)?;
+  if merge_path.is_dir() {
+    remove_dir(&merge_path)?;
+  }
+  Ok(())
+}
+
+fn remove_file(file: &Path) -> Result<()> {
+  if file.is_file() {
+    fs::remove_file(file).map_err(|e| {
+      error!("failed to remove {}: {e}", file.display());
+      Errors::FailedToRemoveFile
+    })?;
+  }
+  Ok(())
+}
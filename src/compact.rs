@@ -0,0 +1,167 @@
+use bytes::Bytes;
+
+use crate::{
+  batch::{log_record_key_with_seq, NON_TXN_SEQ_NO},
+  data::log_record::{LogRecord, LogRecordPos, LogRecordType},
+  db::Engine,
+  errors::{Errors, Result},
+  option::IteratorOptions,
+};
+
+/// Records rewritten by [`Engine::compact_range`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompactStats {
+  pub records: usize,
+
+  /// Size of the rewritten records
+  pub bytes: u64,
+}
+
+impl Engine {
+  /// Rewrites the live records of the keys in `[start, end)` into fresh data files, an empty
+  /// `end` reaches to the last key. The range follows `Options::key_comparator` if set. The
+  /// old records become garbage, so the files holding a churning key range can be reclaimed
+  /// by the next merge while the range stays packed.
+  ///
+  /// Keys with an expiration are left in place. Each key is copied holding its key lock,
+  /// which every write of the key takes, and the batch commit lock for batch commits. A
+  /// write of the key meanwhile keeps its record.
+  pub fn compact_range(&self, start: &[u8], end: &[u8]) -> Result<CompactStats> {
+    self.check_open()?;
+    if self.reader.is_some() {
+      return Err(Errors::ReadOnlyEngine);
+    }
+
+    // continue in a fresh file, records already in it are not rewritten
+    let fresh_file_id = {
+      let _lock = self.batch_commit_lock.lock();
+      let mut active_file = self.active_data_file.write();
+      self.check_open()?;
+      if active_file.get_write_off() > 0 {
+        self.rotate_active_file(&mut active_file)?;
+      }
+      active_file.get_file_id()
+    };
+
+    let mut entries = Vec::new();
+    let mut iter = self.index.try_iterator(IteratorOptions::default())?;
+    iter.seek(start.to_vec());
+    while let Some((key, pos)) = iter.next() {
+      let past_end = match &self.options.key_comparator {
+        Some(comparator) => comparator.compare(key, end).is_ge(),
+        None => key >= end,
+      };
+      if !end.is_empty() && past_end {
+        break;
+      }
+      entries.push((key.to_vec(), *pos));
+    }
+    drop(iter);
+
+    let mut stats = CompactStats::default();
+    for (key, pos) in entries {
+      if pos.file_id >= fresh_file_id {
+        continue;
+      }
+      if let Some(new_pos) = self.compact_key(key, pos)? {
+        stats.records += 1;
+        stats.bytes += new_pos.size as u64;
+      }
+    }
+    Ok(stats)
+  }
+
+  // rewrites the record of a key unless it was written since it was listed at `old_pos`,
+  // the key lock keeps writers from moving it between the check and the index update
+  fn compact_key(&self, key: Vec<u8>, old_pos: LogRecordPos) -> Result<Option<LogRecordPos>> {
+    let _guard = self.lock_key(Bytes::copy_from_slice(&key));
+    let _gate = self.write_gate.read_recursive();
+    let _lock = self.batch_commit_lock.lock();
+    if self.index.get(key.clone()) != Some(old_pos) || self.expiry.get(&key).is_some() {
+      return Ok(None);
+    }
+    let record = self.read_log_record_at(&old_pos)?;
+    if record.rec_type != LogRecordType::Normal {
+      return Ok(None);
+    }
+    let mut record = LogRecord {
+      key: log_record_key_with_seq(&key, NON_TXN_SEQ_NO),
+      value: record.value,
+      rec_type: LogRecordType::Normal,
+      timestamp: record.timestamp,
+    };
+    let new_pos = self.append_log_record(&mut record)?;
+    self.index.try_put(key.clone(), new_pos)?;
+    self.account_quota(&key, Some(old_pos), Some(new_pos));
+    self.mark_stale(old_pos);
+    Ok(Some(new_pos))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::{Duration, SystemTime};
+
+  use super::*;
+  use crate::option::Options;
+
+  #[test]
+  fn test_compact_range() {
+    let dir = tempfile::tempdir().unwrap();
+    let opts = Options {
+      dir_path: dir.path().to_path_buf(),
+      data_file_size: 16 * 1024,
+      ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("fail to open engine");
+    for round in 0..5 {
+      for i in 0..100 {
+        let value = Bytes::from(format!("value-{round}-{i:0100}"));
+        engine
+          .put(Bytes::from(format!("hot:{i:03}")), value)
+          .unwrap();
+      }
+      engine
+        .put(Bytes::from(format!("cold:{round}")), Bytes::from("cold"))
+        .unwrap();
+    }
+    for i in 90..100 {
+      engine.delete(Bytes::from(format!("hot:{i:03}"))).unwrap();
+    }
+    engine
+      .expire(
+        Bytes::from("hot:000"),
+        SystemTime::now() + Duration::from_secs(3600),
+      )
+      .unwrap();
+    let cold_pos = engine.index.get(b"cold:0".to_vec());
+    let expiring_pos = engine.index.get(b"hot:000".to_vec());
+    let reclaim_size = engine.get_engine_stat().unwrap().reclaim_size;
+
+    let stats = engine.compact_range(b"hot:", b"hot;").unwrap();
+    assert_eq!(89, stats.records);
+    let stat = engine.get_engine_stat().unwrap();
+    assert_eq!(reclaim_size + stats.bytes as usize, stat.reclaim_size);
+
+    // only the range is moved, the records went to fresh files
+    assert_eq!(cold_pos, engine.index.get(b"cold:0".to_vec()));
+    assert_eq!(expiring_pos, engine.index.get(b"hot:000".to_vec()));
+    let hot_pos = engine.index.get(b"hot:001".to_vec()).unwrap();
+    assert!(hot_pos.file_id > cold_pos.unwrap().file_id);
+
+    let check = |engine: &Engine| {
+      for i in 1..90 {
+        let value = engine.get(Bytes::from(format!("hot:{i:03}"))).unwrap();
+        assert_eq!(Bytes::from(format!("value-4-{i:0100}")), value);
+      }
+      assert!(engine.get(Bytes::from("hot:095")).is_err());
+      assert!(engine.get(Bytes::from("hot:000")).is_ok());
+      assert_eq!(105 - 10, engine.list_keys().unwrap().len());
+    };
+    check(&engine);
+    engine.close().unwrap();
+    drop(engine);
+    let engine = Engine::open(opts).expect("fail to open engine");
+    check(&engine);
+  }
+}
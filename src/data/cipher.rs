@@ -0,0 +1,155 @@
+use bytes::{Buf, BufMut, BytesMut};
+use prost::{decode_length_delimiter, encode_length_delimiter};
+
+#[cfg(feature = "encryption")]
+use aes_gcm::{
+  aead::{Aead, AeadCore, KeyInit, OsRng},
+  Aes256Gcm, Nonce,
+};
+
+use crate::{
+  data::log_record::{LogRecord, LogRecordType},
+  errors::{Errors, Result},
+};
+
+/// Authenticated encryption of log records with AES-256-GCM, see `Options::encryption_key`.
+///
+/// A sealed record is written as a record of type `Encrypted` whose key is the random nonce of
+/// the record, so the nonce directly follows the header. Its value is the ciphertext and tag of
+/// the original type, key and value:
+/// +-------------+---------+-----------------------------------------------------+-------+
+/// |   Header    |  Nonce  |  Ciphertext(Type | Key Length | Key | Value) + Tag  |  Crc  |
+/// +-------------+---------+-----------------------------------------------------+-------+
+///                 12bytes
+///
+/// The timestamp of the record stays in the header of the sealed record.
+pub(crate) struct RecordCipher {
+  #[cfg(feature = "encryption")]
+  aead: Aes256Gcm,
+}
+
+impl RecordCipher {
+  #[cfg(feature = "encryption")]
+  pub(crate) fn new(key: &[u8; 32]) -> Result<Self> {
+    Ok(Self {
+      aead: Aes256Gcm::new(key.into()),
+    })
+  }
+
+  #[cfg(not(feature = "encryption"))]
+  pub(crate) fn new(_key: &[u8; 32]) -> Result<Self> {
+    Err(Errors::EncryptionUnsupported)
+  }
+
+  // encrypts the type, key and value of `record` into an `Encrypted` record
+  pub(crate) fn seal(&self, record: &LogRecord) -> Result<LogRecord> {
+    let mut plaintext = BytesMut::with_capacity(1 + 5 + record.key.len() + record.value.len());
+    plaintext.put_u8(record.rec_type as u8);
+    let _ = encode_length_delimiter(record.key.len(), &mut plaintext);
+    plaintext.extend_from_slice(&record.key);
+    plaintext.extend_from_slice(&record.value);
+
+    let (nonce, ciphertext) = self.encrypt(&plaintext)?;
+    Ok(LogRecord {
+      key: nonce,
+      value: ciphertext,
+      rec_type: LogRecordType::Encrypted,
+      timestamp: record.timestamp,
+    })
+  }
+
+  // decrypts an `Encrypted` record, other records are returned as they are
+  pub(crate) fn open(&self, record: LogRecord) -> Result<LogRecord> {
+    if record.rec_type != LogRecordType::Encrypted {
+      return Ok(record);
+    }
+    let plaintext = self.decrypt(&record.key, &record.value)?;
+
+    let mut buf = plaintext.as_slice();
+    if !buf.has_remaining() {
+      return Err(Errors::InvalidLogRecord);
+    }
+    let rec_type = LogRecordType::from_u8(buf.get_u8())?;
+    let key_size = decode_length_delimiter(&mut buf).map_err(|_| Errors::InvalidLogRecord)?;
+    if key_size > buf.len() || rec_type == LogRecordType::Encrypted {
+      return Err(Errors::InvalidLogRecord);
+    }
+    Ok(LogRecord {
+      key: buf[..key_size].to_vec(),
+      value: buf[key_size..].to_vec(),
+      rec_type,
+      timestamp: record.timestamp,
+    })
+  }
+
+  // returns the random nonce and the ciphertext followed by the tag
+  #[cfg(feature = "encryption")]
+  fn encrypt(&self, plaintext: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = self
+      .aead
+      .encrypt(&nonce, plaintext)
+      .map_err(|_| Errors::EncryptionFailed)?;
+    Ok((nonce.to_vec(), ciphertext))
+  }
+
+  #[cfg(not(feature = "encryption"))]
+  fn encrypt(&self, _plaintext: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    Err(Errors::EncryptionUnsupported)
+  }
+
+  #[cfg(feature = "encryption")]
+  fn decrypt(&self, nonce: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+    if nonce.len() != NONCE_SIZE {
+      return Err(Errors::DecryptionFailed);
+    }
+    self
+      .aead
+      .decrypt(Nonce::from_slice(nonce), ciphertext)
+      .map_err(|_| Errors::DecryptionFailed)
+  }
+
+  #[cfg(not(feature = "encryption"))]
+  fn decrypt(&self, _nonce: &[u8], _ciphertext: &[u8]) -> Result<Vec<u8>> {
+    Err(Errors::EncryptionUnsupported)
+  }
+}
+
+#[cfg(feature = "encryption")]
+const NONCE_SIZE: usize = 12;
+
+#[cfg(all(test, feature = "encryption"))]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_record_cipher() {
+    let cipher = RecordCipher::new(&[7; 32]).unwrap();
+    let record = LogRecord {
+      key: b"key".to_vec(),
+      value: b"value".to_vec(),
+      rec_type: LogRecordType::Expire,
+      timestamp: None,
+    };
+    let sealed = cipher.seal(&record).unwrap();
+    assert_eq!(LogRecordType::Encrypted, sealed.rec_type);
+    assert_eq!(NONCE_SIZE, sealed.key.len());
+    assert!(!sealed.encode().windows(5).any(|w| w == b"value"));
+
+    // every record gets its own nonce
+    assert_ne!(sealed.key, cipher.seal(&record).unwrap().key);
+
+    let opened = cipher.open(sealed).unwrap();
+    assert_eq!(record.key, opened.key);
+    assert_eq!(record.value, opened.value);
+    assert_eq!(record.rec_type, opened.rec_type);
+
+    // another key or a flipped bit fails the tag
+    let sealed = cipher.seal(&record).unwrap();
+    let other = RecordCipher::new(&[8; 32]).unwrap();
+    let mut tampered = cipher.seal(&record).unwrap();
+    tampered.value[0] ^= 1;
+    assert_eq!(Errors::DecryptionFailed, other.open(sealed).unwrap_err());
+    assert_eq!(Errors::DecryptionFailed, cipher.open(tampered).unwrap_err());
+  }
+}
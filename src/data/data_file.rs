@@ -1,49 +1,85 @@
-use bytes::{Buf, BytesMut};
-use parking_lot::RwLock;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use parking_lot::{Mutex, RwLock};
 use prost::{decode_length_delimiter, length_delimiter_len};
 use std::{
   path::{Path, PathBuf},
   sync::Arc,
 };
 
-use super::log_record::{LogRecord, LogRecordPos, LogRecordType, ReadLogRecord};
+use super::cipher::RecordCipher;
+use super::log_record::{
+  LogRecord, LogRecordPos, LogRecordType, ReadLogRecord, TIMESTAMP_FLAG, TIMESTAMP_SIZE,
+};
 use crate::{
   data::log_record::max_log_record_header_size,
   errors::{Errors, Result},
-  fio::{new_io_manager, IOManager},
-  option::IOManagerType,
+  fio::{
+    file_io::FileIO,
+    new_io_manager,
+    pool::{FilePool, PooledFileIO},
+    IOManager,
+  },
+  option::{CrcImpl, IOManagerType, IOManagerWrapper},
 };
 
 pub const DATA_FILE_NAME_SUFFIX: &str = ".data";
 pub const HINT_FILE_NAME: &str = "hint-index";
 pub const MERGE_FINISHED_FILE_NAME: &str = "merge-finished";
 pub const SEQ_NO_FILE_NAME: &str = "seq-no";
+pub const SEQ_NO_TMP_FILE_NAME: &str = "seq-no.tmp";
+pub const MANIFEST_FILE_NAME: &str = "manifest";
+pub const MANIFEST_TMP_FILE_NAME: &str = "manifest.tmp";
+pub const KEYDIR_FILE_NAME_SUFFIX: &str = ".keydir";
+pub const TOMBSTONE_FILE_NAME_SUFFIX: &str = ".tomb";
+pub const BLOB_FILE_NAME_SUFFIX: &str = ".blob";
+pub const CLOSE_HINT_FILE_NAME: &str = "close-hint-index";
+pub const CLOSE_HINT_TMP_FILE_NAME: &str = "close-hint-index.tmp";
+pub const CLEAR_MARKER_FILE_NAME: &str = "clear-marker";
+pub const GARBAGE_MAP_FILE_NAME: &str = "garbage-map";
+pub const GARBAGE_MAP_TMP_FILE_NAME: &str = "garbage-map.tmp";
+pub const TXN_DECISION_FILE_NAME_PREFIX: &str = "txn-decision-";
+pub const FILE_FOOTER_KEY: &[u8] = "file.footer".as_bytes();
+pub const HINT_HEADER_KEY: &[u8] = "hint.header".as_bytes();
+// merge hint files starting with a header are sealed, older ones have neither
+pub const HINT_FORMAT_VERSION: u8 = 2;
+
+// encoded footer: 3 bytes header + key + 4 bytes checksum + 8 bytes record count + 4 bytes crc
+const FILE_FOOTER_SIZE: u64 = 3 + 11 + 12 + 4;
+const CHECKSUM_CHUNK_SIZE: u64 = 1024 * 1024;
+// read ahead window of a scan, made of chunks submitted together
+const SCAN_CHUNK_SIZE: usize = 256 * 1024;
+const SCAN_CHUNKS: usize = 4;
+
+// an empty header is the end of the records when this many bytes following it are zeros, or
+// all bytes up to the end of the file
+const ZERO_TAIL_CHECK_SIZE: usize = SCAN_CHUNK_SIZE * SCAN_CHUNKS;
 
 #[macro_export]
 macro_rules! new_data_file {
   () => {
       pub fn new<P: AsRef<std::path::Path>>(dir_path: P, file_id: u32, io_type: IOManagerType) -> Result<Self> {
           let file_name = get_data_file_name(&dir_path, file_id);
-          let io_manager = new_io_manager(&file_name, &io_type);
+          let io_manager = new_io_manager(&file_name, &io_type).map_err(|e| e.in_data_file(file_id))?;
           Ok(Self {
               file_id: std::sync::Arc::new(parking_lot::RwLock::new(file_id)),
               write_off: std::sync::Arc::new(parking_lot::RwLock::new(0)),
+              digest: std::sync::Arc::new(parking_lot::Mutex::new(WriteDigest::default())),
               io_manager,
+              cipher: None,
           })
       }
   };
   ($($name:ident, $file_id:expr, $io_type:expr, $file_name:expr);*;) => {
       $(
           pub fn $name<P: AsRef<std::path::Path>>(dir_path: P) -> Result<Self> {
-              let file_name = $file_name.map_or_else(
-                  || panic!("File name must be provided"),
-                  |name| dir_path.as_ref().join(name),
-              );
-              let io_manager = new_io_manager(&file_name, &$io_type);
+              let file_name = dir_path.as_ref().join($file_name);
+              let io_manager = new_io_manager(&file_name, &$io_type)?;
               Ok(Self {
                   file_id: std::sync::Arc::new(parking_lot::RwLock::new($file_id)),
                   write_off: std::sync::Arc::new(parking_lot::RwLock::new(0)),
+                  digest: std::sync::Arc::new(parking_lot::Mutex::new(WriteDigest::default())),
                   io_manager,
+                  cipher: None,
               })
           }
       )*
@@ -51,29 +87,161 @@ macro_rules! new_data_file {
 }
 
 pub struct DataFile {
-  file_id: Arc<RwLock<u32>>,      // data file id
+  file_id: Arc<RwLock<u32>>,         // data file id
   write_off: Arc<RwLock<u64>>, // current write offset, used for recording appending write position
+  digest: Arc<Mutex<WriteDigest>>, // running checksum of appended bytes, used when sealing the file
   io_manager: Box<dyn IOManager>, // IO manager interface
+  cipher: Option<Arc<RecordCipher>>, // decrypts the records read, encrypts the hint records written
+}
+
+/// Running checksum and record count of the bytes appended through a data file handle.
+#[derive(Default)]
+pub struct WriteDigest {
+  hasher: crc32fast::Hasher,
+  bytes: u64,
+  records: u64,
 }
 
 impl DataFile {
   // create or open a new data file
   new_data_file!();
 
-  // create or open hint file, merge finished file and sequence number file
+  // open an existing immutable data file whose handle is managed by the file pool
+  pub(crate) fn new_pooled<P>(dir_path: P, file_id: u32, pool: &Arc<FilePool>) -> Result<Self>
+  where
+    P: AsRef<Path>,
+  {
+    let io_manager = PooledFileIO::new(get_data_file_name(&dir_path, file_id), pool)
+      .map_err(|e| e.in_data_file(file_id))?;
+    Ok(Self {
+      file_id: Arc::new(RwLock::new(file_id)),
+      write_off: Arc::new(RwLock::new(0)),
+      digest: Arc::new(Mutex::new(WriteDigest::default())),
+      io_manager: Box::new(io_manager),
+      cipher: None,
+    })
+  }
+
+  // open the commit decision of a multi-engine transaction
+  pub(crate) fn new_txn_decision_file<P>(dir_path: P, txn_id: u64) -> Result<Self>
+  where
+    P: AsRef<Path>,
+  {
+    let io_manager = new_io_manager(
+      &get_txn_decision_file_name(&dir_path, txn_id),
+      &IOManagerType::StandardFileIO,
+    )?;
+    Ok(Self {
+      file_id: Arc::new(RwLock::new(0)),
+      write_off: Arc::new(RwLock::new(0)),
+      digest: Arc::new(Mutex::new(WriteDigest::default())),
+      io_manager,
+      cipher: None,
+    })
+  }
+
+  // open the key directory written for a sealed data file
+  pub(crate) fn new_keydir_file<P>(dir_path: P, file_id: u32) -> Result<Self>
+  where
+    P: AsRef<Path>,
+  {
+    let io_manager = new_io_manager(
+      &get_keydir_file_name(&dir_path, file_id),
+      &IOManagerType::StandardFileIO,
+    )?;
+    Ok(Self {
+      file_id: Arc::new(RwLock::new(file_id)),
+      write_off: Arc::new(RwLock::new(0)),
+      digest: Arc::new(Mutex::new(WriteDigest::default())),
+      io_manager,
+      cipher: None,
+    })
+  }
+
+  // open the tombstone sidecar of a data file
+  pub(crate) fn new_tombstone_file<P>(dir_path: P, file_id: u32) -> Result<Self>
+  where
+    P: AsRef<Path>,
+  {
+    let io_manager = new_io_manager(
+      &get_tombstone_file_name(&dir_path, file_id),
+      &IOManagerType::StandardFileIO,
+    )?;
+    Ok(Self {
+      file_id: Arc::new(RwLock::new(file_id)),
+      write_off: Arc::new(RwLock::new(0)),
+      digest: Arc::new(Mutex::new(WriteDigest::default())),
+      io_manager,
+      cipher: None,
+    })
+  }
+
+  // open the blob file holding the large values of a data file
+  pub(crate) fn new_blob_file<P>(dir_path: P, file_id: u32) -> Result<Self>
+  where
+    P: AsRef<Path>,
+  {
+    let io_manager = new_io_manager(
+      &get_blob_file_name(&dir_path, file_id),
+      &IOManagerType::StandardFileIO,
+    )?;
+    Ok(Self {
+      file_id: Arc::new(RwLock::new(file_id)),
+      write_off: Arc::new(RwLock::new(0)),
+      digest: Arc::new(Mutex::new(WriteDigest::default())),
+      io_manager,
+      cipher: None,
+    })
+  }
+
+  // create or open hint file, merge finished file, sequence number file and its temporary
+  // file, manifest file, the
+  // close hint file, the clear marker and the garbage map with their temporary files
   new_data_file!(
     new_hint_file,
     0,
     IOManagerType::StandardFileIO,
-    Some(HINT_FILE_NAME);
+    HINT_FILE_NAME;
     new_merge_fin_file,
     0,
     IOManagerType::StandardFileIO,
-    Some(MERGE_FINISHED_FILE_NAME);
+    MERGE_FINISHED_FILE_NAME;
     new_seq_no_file,
     0,
     IOManagerType::StandardFileIO,
-    Some(SEQ_NO_FILE_NAME);
+    SEQ_NO_FILE_NAME;
+    new_seq_no_tmp_file,
+    0,
+    IOManagerType::StandardFileIO,
+    SEQ_NO_TMP_FILE_NAME;
+    new_manifest_file,
+    0,
+    IOManagerType::StandardFileIO,
+    MANIFEST_FILE_NAME;
+    new_manifest_tmp_file,
+    0,
+    IOManagerType::StandardFileIO,
+    MANIFEST_TMP_FILE_NAME;
+    new_close_hint_file,
+    0,
+    IOManagerType::StandardFileIO,
+    CLOSE_HINT_FILE_NAME;
+    new_close_hint_tmp_file,
+    0,
+    IOManagerType::StandardFileIO,
+    CLOSE_HINT_TMP_FILE_NAME;
+    new_clear_marker_file,
+    0,
+    IOManagerType::StandardFileIO,
+    CLEAR_MARKER_FILE_NAME;
+    new_garbage_map_file,
+    0,
+    IOManagerType::StandardFileIO,
+    GARBAGE_MAP_FILE_NAME;
+    new_garbage_map_tmp_file,
+    0,
+    IOManagerType::StandardFileIO,
+    GARBAGE_MAP_TMP_FILE_NAME;
   );
   pub fn file_size(&self) -> u64 {
     self.io_manager.size()
@@ -96,82 +264,670 @@ impl DataFile {
 
   // read log record by offset
   pub fn read_log_record(&self, offset: u64) -> Result<ReadLogRecord> {
+    self.read_log_record_with(offset, Some(CrcImpl::Hardware))
+  }
+
+  // read log record by offset, verifying its crc32 with `crc` unless it is None
+  pub(crate) fn read_log_record_with(
+    &self,
+    offset: u64,
+    crc: Option<CrcImpl>,
+  ) -> Result<ReadLogRecord> {
     // read header
-    let mut header_buf = BytesMut::zeroed(max_log_record_header_size());
-    self.io_manager.read(&mut header_buf, offset)?;
+    let header_buf = self.read_header_buf(offset)?;
+    let header = decode_header(&header_buf, offset, self.file_size())?;
+
+    // read actual key and value, last 4 bytes is crc32 checksum
+    let kv_buf = self.read_body_buf(&header, offset)?;
+    let record = decode_body(&header, &header_buf[..header.header_size], &kv_buf, crc)?;
+    self.decrypt(record)
+  }
 
-    // Retrieve first byte of header, which is the type of log record
-    let rec_type = header_buf.get_u8();
+  // read the type and value of the record at `offset`, verifying its crc32 with `crc` unless
+  // it is None. The value is a slice of the file content when the io manager shares it
+  pub(crate) fn read_value_with(
+    &self,
+    offset: u64,
+    crc: Option<CrcImpl>,
+  ) -> Result<(LogRecordType, Bytes)> {
+    let header_buf = self.read_header_buf(offset)?;
+    let header = decode_header(&header_buf, offset, self.file_size())?;
 
-    // Retrieve the length of the key and value
-    let key_size = decode_length_delimiter(&mut header_buf).unwrap();
-    let value_size = decode_length_delimiter(&mut header_buf).unwrap();
+    // encrypted records are decrypted into a new buffer anyway
+    let shared = match header.rec_type {
+      LogRecordType::Encrypted => None,
+      _ => self.io_manager.read_shared(offset, header.record_size()),
+    };
+    let Some(shared) = shared else {
+      let kv_buf = self.read_body_buf(&header, offset)?;
+      let record = decode_body(&header, &header_buf[..header.header_size], &kv_buf, crc)?;
+      let record = self.decrypt(record)?.record;
+      return Ok((record.rec_type, record.value.into()));
+    };
 
-    // if key_size and value_size are 0, EOF then return error
-    if key_size == 0 && value_size == 0 {
-      return Err(Errors::ReadDataFileEOF);
+    let (header_buf, kv_buf) = shared.split_at(header.header_size);
+    if let Some(crc) = crc {
+      verify_crc(&header, header_buf, kv_buf, crc)?;
     }
+    let start = header.header_size + header.key_size;
+    Ok((
+      header.rec_type,
+      shared.slice(start..start + header.value_size),
+    ))
+  }
 
-    // get actual data size
-    let actual_header_size = length_delimiter_len(key_size) + length_delimiter_len(value_size) + 1;
+  // read only the header of the record at `offset`
+  pub(crate) fn read_record_header(&self, offset: u64) -> Result<RecordHeader> {
+    let header_buf = self.read_header_buf(offset)?;
+    decode_header(&header_buf, offset, self.file_size())
+  }
 
-    // read actual key and value, last 4 bytes is crc32 checksum
-    let mut kv_buf = BytesMut::zeroed(key_size + value_size + 4);
-    self
-      .io_manager
-      .read(&mut kv_buf, offset + actual_header_size as u64)?;
+  // read the bytes holding the header of the record at `offset`, fewer than the largest
+  // header near the end of file
+  fn read_header_buf(&self, offset: u64) -> Result<BytesMut> {
+    let mut header_buf = BytesMut::zeroed(max_log_record_header_size());
+    let n = self.io_read(&mut header_buf, offset)?;
+    header_buf.truncate(n);
+    Ok(header_buf)
+  }
 
-    // construct log record
-    let log_record = LogRecord {
-      key: kv_buf.get(..key_size).unwrap().to_vec(),
-      value: kv_buf.get(key_size..kv_buf.len() - 4).unwrap().to_vec(),
-      rec_type: LogRecordType::from_u8(rec_type),
-    };
+  // read the key, value and crc32 following the header of the record at `offset`
+  fn read_body_buf(&self, header: &RecordHeader, offset: u64) -> Result<BytesMut> {
+    let mut kv_buf = BytesMut::zeroed(header.key_size + header.value_size + 4);
+    let body_offset = offset + header.header_size as u64;
+    let n = self.io_read(&mut kv_buf, body_offset)?;
+    if n < kv_buf.len() {
+      return Err(Errors::TruncatedLogRecord {
+        offset,
+        available: (header.header_size + n) as u64,
+      });
+    }
+    Ok(kv_buf)
+  }
 
-    // advance to last 4 bytes, read crc32 checksum
-    kv_buf.advance(key_size + value_size);
+  // read raw bytes at `offset`, returns the number of bytes read
+  pub(crate) fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+    self.io_read(buf, offset)
+  }
 
-    if kv_buf.get_u32() != log_record.get_crc() {
-      return Err(Errors::InvalidLogRecordCrc);
-    }
+  // sequential reader of all records, reading ahead instead of two reads per record
+  pub(crate) fn scan(&self) -> RecordScanner<'_> {
+    self.scan_from(0)
+  }
 
-    Ok(ReadLogRecord {
-      record: log_record,
-      size: actual_header_size + key_size + value_size + 4,
-    })
+  // sequential reader of the records starting at `offset`
+  pub(crate) fn scan_from(&self, offset: u64) -> RecordScanner<'_> {
+    RecordScanner {
+      data_file: self,
+      file_size: self.file_size(),
+      buf: Vec::new(),
+      buf_offset: offset,
+      offset,
+    }
   }
 
+  // every write appends exactly one encoded log record
   pub fn write(&self, buf: &[u8]) -> Result<usize> {
-    let n_bytes = self.io_manager.write(buf)?;
+    self.write_records(buf, 1)
+  }
+
+  // write several encoded records at once, `records` is their number
+  pub(crate) fn write_records(&self, buf: &[u8], records: u64) -> Result<usize> {
+    let n_bytes = self
+      .io_manager
+      .write(buf)
+      .map_err(|e| e.in_data_file(self.get_file_id()))?;
 
     //update write_off
     let mut write_off = self.write_off.write();
     *write_off += n_bytes as u64;
 
+    // update running checksum
+    let mut digest = self.digest.lock();
+    digest.hasher.update(&buf[..n_bytes]);
+    digest.bytes += n_bytes as u64;
+    digest.records += records;
+
     Ok(n_bytes)
   }
 
+  // write several encoded records back to back in a single vectored write
+  pub(crate) fn write_vectored(&self, bufs: &[Vec<u8>]) -> Result<usize> {
+    let slices: Vec<&[u8]> = bufs.iter().map(|buf| &buf[..]).collect();
+    let n_bytes = self
+      .io_manager
+      .write_vectored(&slices)
+      .map_err(|e| e.in_data_file(self.get_file_id()))?;
+
+    let mut write_off = self.write_off.write();
+    *write_off += n_bytes as u64;
+
+    // the checksum covers the bytes written, a short write ends within one of the buffers
+    let mut digest = self.digest.lock();
+    let mut remaining = n_bytes;
+    for buf in slices {
+      let len = buf.len().min(remaining);
+      digest.hasher.update(&buf[..len]);
+      remaining -= len;
+    }
+    digest.bytes += n_bytes as u64;
+    digest.records += bufs.len() as u64;
+
+    Ok(n_bytes)
+  }
+
+  // seal the data file once it becomes immutable, appending a footer which holds
+  // the checksum of the whole file and the number of records in it, returns the checksum
+  pub fn seal(&self) -> Result<u32> {
+    let write_off = self.get_write_off();
+    let tracked = {
+      let digest = self.digest.lock();
+      match digest.bytes == write_off {
+        true => Some((digest.hasher.clone().finalize(), digest.records)),
+        false => None,
+      }
+    };
+
+    // file was reopened with existing content, compute digest from disk
+    let (checksum, record_count) = match tracked {
+      Some(v) => v,
+      None => (
+        self.checksum_range(write_off)?,
+        self.count_records(write_off)?,
+      ),
+    };
+
+    let mut value = BytesMut::new();
+    value.put_u32(checksum);
+    value.put_u64(record_count);
+    let footer = LogRecord {
+      key: FILE_FOOTER_KEY.to_vec(),
+      value: value.to_vec(),
+      rec_type: LogRecordType::FileFooter,
+      timestamp: None,
+    };
+    self.write(&footer.encode())?;
+    self.trim()?;
+    self.sync()?;
+    Ok(checksum)
+  }
+
+  // verify the footer appended by `seal`, returns false if the file is not sealed
+  pub fn verify_footer(&self) -> Result<bool> {
+    let Some(checksum) = self.footer_checksum()? else {
+      return Ok(false);
+    };
+    let footer_off = self.file_size() - FILE_FOOTER_SIZE;
+    if self.checksum_range(footer_off)? != checksum {
+      return Err(Errors::InvalidDataFileChecksum);
+    }
+    Ok(true)
+  }
+
+  // checksum stored in the footer, only the footer itself is read and checked,
+  // returns None if the file is not sealed
+  pub fn footer_checksum(&self) -> Result<Option<u32>> {
+    Ok(self.read_footer()?.map(|(checksum, _)| checksum))
+  }
+
+  // number of records before the footer, returns None if the file is not sealed
+  pub fn footer_record_count(&self) -> Result<Option<u64>> {
+    Ok(self.read_footer()?.map(|(_, record_count)| record_count))
+  }
+
+  fn read_footer(&self) -> Result<Option<(u32, u64)>> {
+    let file_size = self.file_size();
+    if file_size < FILE_FOOTER_SIZE {
+      return Ok(None);
+    }
+    let footer_off = file_size - FILE_FOOTER_SIZE;
+
+    // footer is checked byte by byte, the tail of an unsealed file may hold anything
+    let mut buf = BytesMut::zeroed(FILE_FOOTER_SIZE as usize);
+    self.io_read(&mut buf, footer_off)?;
+    let key_len = FILE_FOOTER_KEY.len();
+    if buf[0] != LogRecordType::FileFooter as u8
+      || buf[1] as usize != key_len
+      || buf[2] != 12
+      || &buf[3..3 + key_len] != FILE_FOOTER_KEY
+    {
+      return Ok(None);
+    }
+
+    buf.advance(3 + key_len);
+    let footer = LogRecord {
+      key: FILE_FOOTER_KEY.to_vec(),
+      value: buf[..12].to_vec(),
+      rec_type: LogRecordType::FileFooter,
+      timestamp: None,
+    };
+    let checksum = buf.get_u32();
+    let record_count = buf.get_u64();
+    if buf.get_u32() != footer.get_crc() {
+      return Ok(None);
+    }
+    Ok(Some((checksum, record_count)))
+  }
+
+  // crc32 checksum of the file content in range [0, end)
+  fn checksum_range(&self, end: u64) -> Result<u32> {
+    let mut hasher = crc32fast::Hasher::new();
+    let mut offset = 0;
+    while offset < end {
+      let len = CHECKSUM_CHUNK_SIZE.min(end - offset);
+      let mut buf = BytesMut::zeroed(len as usize);
+      self.io_read(&mut buf, offset)?;
+      hasher.update(&buf);
+      offset += len;
+    }
+    Ok(hasher.finalize())
+  }
+
+  // number of log records in range [0, end), footers excluded
+  fn count_records(&self, end: u64) -> Result<u64> {
+    let mut count = 0;
+    let mut offset = 0;
+    while offset < end {
+      let read_res = match self.read_log_record(offset) {
+        Ok(res) => res,
+        Err(Errors::ReadDataFileEOF) => break,
+        Err(e) => return Err(e),
+      };
+      if read_res.record.rec_type != LogRecordType::FileFooter {
+        count += 1;
+      }
+      offset += read_res.size as u64;
+    }
+    Ok(count)
+  }
+
   // write hint record into hint file
   pub fn write_hint_record(&self, key: Vec<u8>, pos: LogRecordPos) -> Result<()> {
+    self.write_typed_hint_record(key, pos, LogRecordType::Normal)
+  }
+
+  // hint of an expire record, the expiration time is read from the data file
+  pub fn write_expire_hint_record(&self, key: Vec<u8>, pos: LogRecordPos) -> Result<()> {
+    self.write_typed_hint_record(key, pos, LogRecordType::Expire)
+  }
+
+  // first record of a merge hint file, carrying the format version
+  pub fn write_hint_header(&self) -> Result<()> {
+    let header = LogRecord {
+      key: HINT_HEADER_KEY.to_vec(),
+      value: vec![HINT_FORMAT_VERSION],
+      rec_type: LogRecordType::FileFooter,
+      timestamp: None,
+    };
+    self.write(&header.encode())?;
+    Ok(())
+  }
+
+  fn write_typed_hint_record(
+    &self,
+    key: Vec<u8>,
+    pos: LogRecordPos,
+    rec_type: LogRecordType,
+  ) -> Result<()> {
+    let enc_record = self.encode_hint_record(key, pos, rec_type)?;
+    self.write(&enc_record)?;
+    Ok(())
+  }
+
+  // encode the hint of the record of `key` at `pos`
+  pub(crate) fn encode_hint_record(
+    &self,
+    key: Vec<u8>,
+    pos: LogRecordPos,
+    rec_type: LogRecordType,
+  ) -> Result<Vec<u8>> {
     let hint_record = LogRecord {
       key,
       value: pos.encode(),
-      rec_type: LogRecordType::Normal,
+      rec_type,
+      timestamp: None,
     };
-    let enc_record = hint_record.encode();
-    self.write(&enc_record)?;
-    Ok(())
+    self.encode_record(&hint_record)
+  }
+
+  // encode a record, sealed by the cipher of the file if it has one
+  pub(crate) fn encode_record(&self, record: &LogRecord) -> Result<Vec<u8>> {
+    match &self.cipher {
+      Some(cipher) => Ok(cipher.seal(record)?.encode()),
+      None => Ok(record.encode()),
+    }
+  }
+
+  // open an encrypted record, a file without cipher cannot read it
+  fn decrypt(&self, mut read: ReadLogRecord) -> Result<ReadLogRecord> {
+    if read.record.rec_type == LogRecordType::Encrypted {
+      let cipher = self.cipher.as_ref().ok_or(Errors::DecryptionFailed)?;
+      read.record = cipher.open(read.record)?;
+    }
+    Ok(read)
   }
 
   pub fn sync(&self) -> Result<()> {
-    self.io_manager.sync()
+    self
+      .io_manager
+      .sync()
+      .map_err(|e| e.in_data_file(self.get_file_id()))
+  }
+
+  // errors of the io manager carry the id of the file
+  fn io_read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+    self
+      .io_manager
+      .read_full(buf, offset)
+      .map_err(|e| e.in_data_file(self.get_file_id()))
+  }
+
+  pub fn set_io_manager<P>(
+    &mut self,
+    dir_path: P,
+    io_type: IOManagerType,
+    wrapper: Option<&IOManagerWrapper>,
+  ) -> Result<()>
+  where
+    P: AsRef<Path>,
+  {
+    let file_id = self.get_file_id();
+    let file_name = get_data_file_name(dir_path, file_id);
+    let io_manager = new_io_manager(&file_name, &io_type).map_err(|e| e.in_data_file(file_id))?;
+    self.io_manager = match wrapper {
+      Some(wrapper) => wrapper.wrap(&file_name, io_manager),
+      None => io_manager,
+    };
+    Ok(())
+  }
+
+  // continue writing the file at its write offset through a handle allocating `size` bytes
+  // ahead, see `Options::preallocate_data_file`
+  pub(crate) fn preallocate<P>(
+    &mut self,
+    dir_path: P,
+    size: u64,
+    wrapper: Option<&IOManagerWrapper>,
+  ) -> Result<()>
+  where
+    P: AsRef<Path>,
+  {
+    let file_id = self.get_file_id();
+    let file_name = get_data_file_name(dir_path, file_id);
+    let io_manager: Box<dyn IOManager> = Box::new(
+      FileIO::preallocated(&file_name, self.get_write_off(), size)
+        .map_err(|e| e.in_data_file(file_id))?,
+    );
+    self.io_manager = match wrapper {
+      Some(wrapper) => wrapper.wrap(&file_name, io_manager),
+      None => io_manager,
+    };
+    Ok(())
+  }
+
+  // release the space allocated ahead of the writes
+  pub(crate) fn trim(&self) -> Result<()> {
+    self
+      .io_manager
+      .trim()
+      .map_err(|e| e.in_data_file(self.get_file_id()))
+  }
+
+  // offset past the last record, preallocated space reads as the end of the file
+  pub(crate) fn scan_end(&self) -> Result<u64> {
+    let mut scanner = self.scan();
+    loop {
+      match scanner.next_record() {
+        Ok(_) => {}
+        Err(Errors::ReadDataFileEOF) => return Ok(scanner.offset()),
+        Err(e) => return Err(e),
+      }
+    }
   }
 
-  pub fn set_io_manager<P>(&mut self, dir_path: P, io_type: IOManagerType)
+  // wrap the io manager of a data file, see `Options::io_manager_wrapper`
+  // set the cipher of the records, see `Options::encryption_key`
+  pub(crate) fn with_cipher(mut self, cipher: Option<&Arc<RecordCipher>>) -> Self {
+    self.cipher = cipher.cloned();
+    self
+  }
+
+  pub(crate) fn with_io_wrapper<P>(
+    mut self,
+    dir_path: P,
+    wrapper: Option<&IOManagerWrapper>,
+  ) -> Self
   where
     P: AsRef<Path>,
   {
-    self.io_manager = new_io_manager(&get_data_file_name(dir_path, self.get_file_id()), &io_type);
+    if let Some(wrapper) = wrapper {
+      let file_name = get_data_file_name(dir_path, self.get_file_id());
+      self.io_manager = wrapper.wrap(&file_name, self.io_manager);
+    }
+    self
+  }
+}
+
+/// Sequential reader of the records of an immutable data file.
+///
+/// The file is read ahead in windows of several chunks, submitted together through
+/// [`IOManager::read_batch`].
+pub(crate) struct RecordScanner<'a> {
+  data_file: &'a DataFile,
+  file_size: u64,
+  buf: Vec<u8>,    // file content starting at `buf_offset`
+  buf_offset: u64, // offset of the first buffered byte
+  offset: u64,     // offset of the next record
+}
+
+impl RecordScanner<'_> {
+  // returns the next record and its offset, `ReadDataFileEOF` past the last record
+  pub(crate) fn next_record(&mut self) -> Result<(ReadLogRecord, u64)> {
+    let offset = self.offset;
+    let header = match self.read_header() {
+      // zeros up to the end of the file are preallocated space, zeros followed by data are not
+      Err(Errors::EmptyLogRecord { offset }) => {
+        let zeros = self.fill(ZERO_TAIL_CHECK_SIZE)?;
+        return match zeros.iter().all(|byte| *byte == 0) {
+          true => Err(Errors::ReadDataFileEOF),
+          false => Err(Errors::EmptyLogRecord { offset }),
+        };
+      }
+      header => header?,
+    };
+
+    let record_size = header.record_size();
+    let buffered = self.fill(record_size)?;
+    if buffered.len() < record_size {
+      return Err(Errors::TruncatedLogRecord {
+        offset,
+        available: buffered.len() as u64,
+      });
+    }
+    let (header_buf, kv_buf) = buffered[..record_size].split_at(header.header_size);
+    let record = decode_body(&header, header_buf, kv_buf, Some(CrcImpl::Hardware))?;
+    let record = self.data_file.decrypt(record)?;
+    self.offset += record_size as u64;
+    Ok((record, offset))
+  }
+
+  // offset of the next record
+  pub(crate) fn offset(&self) -> u64 {
+    self.offset
+  }
+
+  // steps over the record at the current offset going by its header, returns the size of the
+  // record or None if the header is unreadable as well
+  pub(crate) fn skip_record(&mut self) -> Result<Option<u64>> {
+    match self.read_header() {
+      Ok(header) => {
+        let record_size = header.record_size() as u64;
+        self.offset += record_size;
+        Ok(Some(record_size))
+      }
+      Err(e) if e.io_error().is_some() => Err(e),
+      Err(_) => Ok(None),
+    }
+  }
+
+  fn read_header(&mut self) -> Result<RecordHeader> {
+    let (offset, file_size) = (self.offset, self.file_size);
+    let buffered = self.fill(max_log_record_header_size())?;
+    let len = buffered.len().min(max_log_record_header_size());
+    decode_header(&buffered[..len], offset, file_size)
+  }
+
+  // buffers at least `len` bytes from the current offset, fewer only at the end of file
+  fn fill(&mut self, len: usize) -> Result<&[u8]> {
+    let start = (self.offset - self.buf_offset) as usize;
+    let remaining = self.file_size.saturating_sub(self.offset);
+    let wanted = (len as u64).min(remaining) as usize;
+    if self.buf.len().saturating_sub(start) < wanted {
+      let window = (len.max(SCAN_CHUNK_SIZE * SCAN_CHUNKS) as u64).min(remaining) as usize;
+      let mut buf = vec![0; window];
+      let mut reads: Vec<(&mut [u8], u64)> = buf
+        .chunks_mut(SCAN_CHUNK_SIZE)
+        .enumerate()
+        .map(|(i, chunk)| (chunk, self.offset + (i * SCAN_CHUNK_SIZE) as u64))
+        .collect();
+      let file_id = self.data_file.get_file_id();
+      let sizes = (self.data_file.io_manager)
+        .read_batch(&mut reads)
+        .map_err(|e| e.in_data_file(file_id))?;
+
+      // keep the content up to the first short read
+      let mut valid = 0;
+      for (size, (chunk, _)) in sizes.iter().zip(reads.iter()) {
+        valid += size;
+        if *size < chunk.len() {
+          break;
+        }
+      }
+      buf.truncate(valid);
+      self.buf = buf;
+      self.buf_offset = self.offset;
+    }
+
+    let start = (self.offset - self.buf_offset) as usize;
+    Ok(self.buf.get(start..).unwrap_or_default())
+  }
+}
+
+// decoded header of a log record
+pub(crate) struct RecordHeader {
+  pub(crate) rec_type: LogRecordType,
+  pub(crate) timestamp: Option<u64>,
+  pub(crate) key_size: usize,
+  pub(crate) value_size: usize,
+  pub(crate) header_size: usize,
+}
+
+impl RecordHeader {
+  fn record_size(&self) -> usize {
+    self.header_size + self.key_size + self.value_size + 4
+  }
+}
+
+// decode the header of the record at `offset` from the bytes read there, fewer than the
+// largest header only where the file ends
+fn decode_header(buf: &[u8], offset: u64, file_size: u64) -> Result<RecordHeader> {
+  if offset >= file_size {
+    return Err(Errors::ReadDataFileEOF);
+  }
+  let available = file_size - offset;
+  // a header cut off by the end of file is truncated, anything else undecodable is corrupted
+  let undecodable = || match buf.len() < max_log_record_header_size() {
+    true => Errors::TruncatedLogRecord { offset, available },
+    false => Errors::InvalidLogRecord,
+  };
+
+  // Retrieve first byte of header, which is the type of log record
+  let Some((&rec_type, mut header_buf)) = buf.split_first() else {
+    return Err(undecodable());
+  };
+  let timestamp = match rec_type & TIMESTAMP_FLAG {
+    0 => None,
+    _ => {
+      // a flagged byte of garbage must not be taken for a timestamped header
+      LogRecordType::from_u8(rec_type & !TIMESTAMP_FLAG)?;
+      let (timestamp, rest) = header_buf
+        .split_first_chunk::<TIMESTAMP_SIZE>()
+        .ok_or_else(undecodable)?;
+      header_buf = rest;
+      Some(u64::from_be_bytes(*timestamp))
+    }
+  };
+
+  // Retrieve the length of the key and value
+  let key_size = decode_length_delimiter(&mut header_buf).map_err(|_| undecodable())?;
+  let value_size = decode_length_delimiter(&mut header_buf).map_err(|_| undecodable())?;
+
+  // every record has a key, an empty header is zeroed space
+  if key_size == 0 && value_size == 0 {
+    return Err(Errors::EmptyLogRecord { offset });
+  }
+
+  // get actual data size
+  let header_size = length_delimiter_len(key_size)
+    + length_delimiter_len(value_size)
+    + timestamp.map_or(1, |_| 1 + TIMESTAMP_SIZE);
+
+  // a corrupted header must not make us allocate or read past the end of file
+  let record_size = header_size
+    .checked_add(key_size)
+    .and_then(|size| size.checked_add(value_size))
+    .and_then(|size| size.checked_add(4))
+    .ok_or(Errors::InvalidLogRecord)?;
+  if record_size as u64 > available {
+    return Err(Errors::TruncatedLogRecord { offset, available });
+  }
+
+  Ok(RecordHeader {
+    rec_type: LogRecordType::from_u8(rec_type & !TIMESTAMP_FLAG)?,
+    timestamp,
+    key_size,
+    value_size,
+    header_size,
+  })
+}
+
+// decode key and value following the header, checking the crc32 in the last 4 bytes with
+// `crc` unless it is None. The checksum covers the encoded header, key and value
+fn decode_body(
+  header: &RecordHeader,
+  header_buf: &[u8],
+  kv_buf: &[u8],
+  crc: Option<CrcImpl>,
+) -> Result<ReadLogRecord> {
+  let (key_size, value_size) = (header.key_size, header.value_size);
+  if let Some(crc) = crc {
+    verify_crc(header, header_buf, kv_buf, crc)?;
+  }
+
+  // construct log record
+  let log_record = LogRecord {
+    key: kv_buf[..key_size].to_vec(),
+    value: kv_buf[key_size..key_size + value_size].to_vec(),
+    rec_type: header.rec_type,
+    timestamp: header.timestamp,
+  };
+
+  Ok(ReadLogRecord {
+    record: log_record,
+    size: header.record_size(),
+  })
+}
+
+// check the crc32 in the last 4 bytes of `kv_buf` against the encoded header, key and value
+fn verify_crc(header: &RecordHeader, header_buf: &[u8], kv_buf: &[u8], crc: CrcImpl) -> Result<()> {
+  let kv_size = header.key_size + header.value_size;
+  let mut hasher = crc.hasher();
+  hasher.update(header_buf);
+  hasher.update(&kv_buf[..kv_size]);
+  let mut crc_buf = &kv_buf[kv_size..];
+  match crc_buf.get_u32() == hasher.finalize() {
+    true => Ok(()),
+    false => Err(Errors::InvalidLogRecordCrc),
   }
 }
 
@@ -184,6 +940,53 @@ where
   dir_path.as_ref().join(name)
 }
 
+/// get the directory of a data file and its key directory, a numbered subdirectory of
+/// `files_per_subdir` files each unless it is 0
+pub fn get_data_file_dir<P>(dir_path: P, file_id: u32, files_per_subdir: u32) -> PathBuf
+where
+  P: AsRef<Path>,
+{
+  match files_per_subdir {
+    0 => dir_path.as_ref().to_path_buf(),
+    n => dir_path.as_ref().join(format!("{:03}", file_id / n)),
+  }
+}
+
+/// get the key directory filename of a data file
+pub fn get_keydir_file_name<P>(dir_path: P, file_id: u32) -> PathBuf
+where
+  P: AsRef<Path>,
+{
+  let name = format!("{file_id:09}") + KEYDIR_FILE_NAME_SUFFIX;
+  dir_path.as_ref().join(name)
+}
+
+/// get the tombstone sidecar filename of a data file
+pub fn get_tombstone_file_name<P>(dir_path: P, file_id: u32) -> PathBuf
+where
+  P: AsRef<Path>,
+{
+  let name = format!("{file_id:09}") + TOMBSTONE_FILE_NAME_SUFFIX;
+  dir_path.as_ref().join(name)
+}
+
+/// get the blob filename of a data file
+pub fn get_blob_file_name<P>(dir_path: P, file_id: u32) -> PathBuf
+where
+  P: AsRef<Path>,
+{
+  let name = format!("{file_id:09}") + BLOB_FILE_NAME_SUFFIX;
+  dir_path.as_ref().join(name)
+}
+
+pub fn get_txn_decision_file_name<P>(dir_path: P, txn_id: u64) -> PathBuf
+where
+  P: AsRef<Path>,
+{
+  let name = format!("{TXN_DECISION_FILE_NAME_PREFIX}{txn_id:016x}");
+  dir_path.as_ref().join(name)
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -217,11 +1020,11 @@ mod tests {
 
     let write_res1 = data_file.write("aaa".as_bytes());
     assert!(write_res1.is_ok());
-    assert_eq!(3 as usize, write_res1.ok().unwrap());
+    assert_eq!(3_usize, write_res1.ok().unwrap());
 
     let write_res2 = data_file.write("bbb".as_bytes());
     assert!(write_res2.is_ok());
-    assert_eq!(3 as usize, write_res2.ok().unwrap());
+    assert_eq!(3_usize, write_res2.ok().unwrap());
   }
 
   #[test]
@@ -248,6 +1051,7 @@ mod tests {
       key: "key-a".as_bytes().to_vec(),
       value: "value-a".as_bytes().to_vec(),
       rec_type: LogRecordType::Normal,
+      timestamp: None,
     };
     let buf1 = enc1.encode();
     let write_res1: std::prelude::v1::Result<usize, Errors> = data_file.write(&buf1);
@@ -266,11 +1070,13 @@ mod tests {
       key: "key-b".as_bytes().to_vec(),
       value: "value-b".as_bytes().to_vec(),
       rec_type: LogRecordType::Normal,
+      timestamp: None,
     };
     let enc3 = LogRecord {
       key: "key-c".as_bytes().to_vec(),
       value: "value-c".as_bytes().to_vec(),
       rec_type: LogRecordType::Normal,
+      timestamp: None,
     };
 
     // Read from current write offset
@@ -300,6 +1106,7 @@ mod tests {
       key: "key-d".as_bytes().to_vec(),
       value: "value-d".as_bytes().to_vec(),
       rec_type: LogRecordType::Deleted,
+      timestamp: None,
     };
 
     let buf4 = enc4.encode();
@@ -314,4 +1121,223 @@ mod tests {
     assert_eq!(enc4.value, read_enc4.record.value);
     assert_eq!(enc4.rec_type, read_enc4.record.rec_type);
   }
+
+  #[test]
+  fn test_data_file_read_timestamped_log_record() {
+    let dir = tempfile::tempdir().unwrap();
+    let data_file = DataFile::new(dir.path(), 0, IOManagerType::StandardFileIO).unwrap();
+
+    // timestamped and plain records share a file
+    let stamped = LogRecord {
+      key: "key-a".as_bytes().to_vec(),
+      value: "value-a".as_bytes().to_vec(),
+      rec_type: LogRecordType::Deleted,
+      timestamp: Some(1_700_000_000_000),
+    };
+    let plain = LogRecord {
+      timestamp: None,
+      rec_type: LogRecordType::Normal,
+      ..stamped.clone()
+    };
+    data_file.write(&stamped.encode()).unwrap();
+    data_file.write(&plain.encode()).unwrap();
+
+    let read = data_file.read_log_record(0).unwrap();
+    assert_eq!(stamped.encode().len(), read.size);
+    assert_eq!(LogRecordType::Deleted, read.record.rec_type);
+    assert_eq!(Some(1_700_000_000_000), read.record.timestamp);
+    assert_eq!(stamped.value, read.record.value);
+
+    let read = data_file.read_log_record(read.size as u64).unwrap();
+    assert_eq!(LogRecordType::Normal, read.record.rec_type);
+    assert_eq!(None, read.record.timestamp);
+    assert_eq!(plain.key, read.record.key);
+  }
+
+  #[test]
+  fn test_data_file_seal() {
+    let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+    let data_file = DataFile::new(temp_dir.path(), 0, IOManagerType::StandardFileIO).unwrap();
+
+    // unsealed file has no footer
+    assert!(!data_file.verify_footer().unwrap());
+    assert_eq!(None, data_file.footer_checksum().unwrap());
+
+    let record = LogRecord {
+      key: "key-a".as_bytes().to_vec(),
+      value: "value-a".as_bytes().to_vec(),
+      rec_type: LogRecordType::Normal,
+      timestamp: None,
+    };
+    data_file.write(&record.encode()).unwrap();
+    data_file.write(&record.encode()).unwrap();
+    let checksum = data_file.seal().unwrap();
+    assert!(data_file.verify_footer().unwrap());
+    assert_eq!(Some(checksum), data_file.footer_checksum().unwrap());
+
+    // footer is readable as a log record
+    let footer = data_file.read_log_record(2 * 19).unwrap().record;
+    assert_eq!(LogRecordType::FileFooter, footer.rec_type);
+    assert_eq!(2, (&footer.value[4..]).get_u64());
+
+    // reopened file computes the same footer from disk
+    let reopened = DataFile::new(temp_dir.path(), 1, IOManagerType::StandardFileIO).unwrap();
+    reopened.write(&record.encode()).unwrap();
+    let reopened = DataFile::new(temp_dir.path(), 1, IOManagerType::StandardFileIO).unwrap();
+    reopened.set_write_off(19);
+    reopened.seal().unwrap();
+    assert!(reopened.verify_footer().unwrap());
+  }
+
+  #[test]
+  fn test_data_file_verify_corrupted() {
+    let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+    let data_file = DataFile::new(temp_dir.path(), 0, IOManagerType::StandardFileIO).unwrap();
+    let record = LogRecord {
+      key: "key-a".as_bytes().to_vec(),
+      value: "value-a".as_bytes().to_vec(),
+      rec_type: LogRecordType::Normal,
+      timestamp: None,
+    };
+    data_file.write(&record.encode()).unwrap();
+    data_file.seal().unwrap();
+
+    // flip one byte of the record value
+    let path = get_data_file_name(temp_dir.path(), 0);
+    let mut content = std::fs::read(&path).unwrap();
+    content[10] ^= 0xff;
+    std::fs::write(&path, content).unwrap();
+
+    let reopened = DataFile::new(temp_dir.path(), 0, IOManagerType::StandardFileIO).unwrap();
+    assert_eq!(
+      Errors::InvalidDataFileChecksum,
+      reopened.verify_footer().err().unwrap()
+    );
+  }
+
+  #[test]
+  fn test_data_file_read_crc() {
+    let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+    let data_file = DataFile::new(temp_dir.path(), 0, IOManagerType::StandardFileIO).unwrap();
+    let record = LogRecord {
+      key: "key-a".as_bytes().to_vec(),
+      value: "value-a".as_bytes().to_vec(),
+      rec_type: LogRecordType::Normal,
+      timestamp: None,
+    };
+    data_file.write(&record.encode()).unwrap();
+    for crc in [CrcImpl::Hardware, CrcImpl::Software] {
+      let read = data_file.read_log_record_with(0, Some(crc)).unwrap();
+      assert_eq!(record.value, read.record.value);
+    }
+
+    // flip one byte of the record value
+    let path = get_data_file_name(temp_dir.path(), 0);
+    let mut content = std::fs::read(&path).unwrap();
+    content[10] ^= 0xff;
+    std::fs::write(&path, content).unwrap();
+
+    let reopened = DataFile::new(temp_dir.path(), 0, IOManagerType::StandardFileIO).unwrap();
+    for crc in [CrcImpl::Hardware, CrcImpl::Software] {
+      assert_eq!(
+        Errors::InvalidLogRecordCrc,
+        reopened.read_log_record_with(0, Some(crc)).unwrap_err()
+      );
+    }
+    let read = reopened.read_log_record_with(0, None).unwrap();
+    assert_eq!(b"key-a".to_vec(), read.record.key);
+    assert_ne!(record.value, read.record.value);
+  }
+
+  #[test]
+  fn test_data_file_scan() {
+    let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+    let data_file = DataFile::new(temp_dir.path(), 0, IOManagerType::StandardFileIO).unwrap();
+
+    // records larger than a chunk and spanning read ahead windows
+    let sizes = [10, SCAN_CHUNK_SIZE + 7, 3, SCAN_CHUNK_SIZE * SCAN_CHUNKS, 1];
+    let mut offsets = Vec::new();
+    for (i, size) in sizes.iter().enumerate() {
+      let record = LogRecord {
+        key: format!("key-{i}").into_bytes(),
+        value: vec![i as u8; *size],
+        rec_type: LogRecordType::Normal,
+        timestamp: None,
+      };
+      offsets.push(data_file.get_write_off());
+      data_file.write(&record.encode()).unwrap();
+    }
+    data_file.seal().unwrap();
+
+    let mut scanner = data_file.scan();
+    for (i, offset) in offsets.iter().enumerate() {
+      let (record, record_offset) = scanner.next_record().unwrap();
+      assert_eq!(*offset, record_offset);
+      let read = data_file.read_log_record(*offset).unwrap();
+      assert_eq!(read.size, record.size);
+      assert_eq!(read.record.key, record.record.key);
+      assert_eq!(vec![i as u8; sizes[i]], record.record.value);
+    }
+    let (footer, _) = scanner.next_record().unwrap();
+    assert_eq!(LogRecordType::FileFooter, footer.record.rec_type);
+    assert_eq!(Errors::ReadDataFileEOF, scanner.next_record().unwrap_err());
+  }
+
+  #[test]
+  fn test_data_file_zeros_and_truncation() {
+    let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+    let data_file = DataFile::new(temp_dir.path(), 0, IOManagerType::StandardFileIO).unwrap();
+    let record = LogRecord {
+      key: b"key".to_vec(),
+      value: b"value".to_vec(),
+      rec_type: LogRecordType::Normal,
+      timestamp: None,
+    };
+    let encoded = record.encode();
+    data_file.write(&encoded).unwrap();
+    let zeros_offset = data_file.get_write_off();
+    data_file.write(&[0; 64]).unwrap();
+
+    // zeros up to the end of the file end the records
+    let mut scanner = data_file.scan();
+    assert_eq!(0, scanner.next_record().unwrap().1);
+    assert_eq!(Errors::ReadDataFileEOF, scanner.next_record().unwrap_err());
+    assert_eq!(
+      Errors::EmptyLogRecord {
+        offset: zeros_offset
+      },
+      data_file.read_log_record(zeros_offset).unwrap_err()
+    );
+
+    // zeros followed by data are corruption
+    data_file.write(&encoded).unwrap();
+    let mut scanner = data_file.scan();
+    scanner.next_record().unwrap();
+    assert_eq!(
+      Errors::EmptyLogRecord {
+        offset: zeros_offset
+      },
+      scanner.next_record().unwrap_err()
+    );
+
+    // a torn record is truncated, read through either io manager
+    let torn_offset = data_file.get_write_off();
+    data_file.write(&encoded[..encoded.len() / 2]).unwrap();
+    data_file.sync().unwrap();
+    let truncated = Errors::TruncatedLogRecord {
+      offset: torn_offset,
+      available: (encoded.len() / 2) as u64,
+    };
+    assert_eq!(
+      truncated,
+      data_file.read_log_record(torn_offset).unwrap_err()
+    );
+    let mmap_file = DataFile::new(temp_dir.path(), 0, IOManagerType::MemoryMap).unwrap();
+    assert_eq!(
+      truncated,
+      mmap_file.read_log_record(torn_offset).unwrap_err()
+    );
+    let mut scanner = mmap_file.scan_from(torn_offset);
+    assert_eq!(truncated, scanner.next_record().unwrap_err());
+  }
 }
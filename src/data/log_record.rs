@@ -1,4 +1,6 @@
 use bytes::{BufMut, BytesMut};
+
+use crate::errors::{Errors, Result};
 use prost::{
   encode_length_delimiter,
   encoding::{decode_varint, encode_varint},
@@ -12,15 +14,40 @@ pub enum LogRecordType {
   Deleted = 2,
 
   TxnFinished = 3,
+
+  FileFooter = 4,
+
+  Expire = 5,
+
+  /// Record sealed by the record cipher, holding one of the other types
+  Encrypted = 6,
+
+  /// Ends the records of a transaction prepared for a multi-engine batch, they are held
+  /// back until its txn finished record
+  TxnPrepared = 7,
+
+  /// Discards the records of a prepared transaction
+  TxnAborted = 8,
+
+  /// Value stored in the blob file of the data file, the record holds its location
+  Blob = 9,
 }
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct LogRecord {
   pub(crate) key: Vec<u8>,
   pub(crate) value: Vec<u8>,
   pub(crate) rec_type: LogRecordType,
+  pub(crate) timestamp: Option<u64>, // write time in unix millis, see `Options::store_timestamps`
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+/// Set in the type byte of a record whose header carries a timestamp. Records written
+/// without it keep the original header, so files of both formats are read alike.
+pub(crate) const TIMESTAMP_FLAG: u8 = 0x80;
+
+/// Size of the timestamp following the type byte of a timestamped record.
+pub(crate) const TIMESTAMP_SIZE: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct LogRecordPos {
   pub(crate) file_id: u32,
   pub(crate) offset: u64,
@@ -40,32 +67,44 @@ pub struct TransactionRecord {
 
 impl LogRecord {
   // Encode for log record, return bytes and its size
-  // +----------+----------------+------------------+---------+-----------+---------+
-  // |   Type   |   Key Length   |   Value Length   |   Key   |   Value   |   Crc   |
-  // +----------+----------------+------------------+---------+-----------+---------+
-  //  1bytes       n(n<=5) bytes     m(m<=5) bytes       x          y        4bytes
+  // +----------+-------------+----------------+------------------+---------+-----------+---------+
+  // |   Type   |  Timestamp  |   Key Length   |   Value Length   |   Key   |   Value   |   Crc   |
+  // +----------+-------------+----------------+------------------+---------+-----------+---------+
+  //  1bytes      0 or 8bytes    n(n<=5) bytes     m(m<=5) bytes       x          y        4bytes
   //
+  // The timestamp is only present with `TIMESTAMP_FLAG` set in the type byte.
   pub fn encode(&self) -> Vec<u8> {
-    let (encode_buf, _) = self.encode_and_get_crc();
+    self.encode_at(self.timestamp)
+  }
+
+  // encode with `timestamp` in place of the timestamp of the record
+  pub(crate) fn encode_at(&self, timestamp: Option<u64>) -> Vec<u8> {
+    let (encode_buf, _) = self.encode_and_get_crc(timestamp);
     encode_buf
   }
 
   pub fn get_crc(&self) -> u32 {
-    let (_, crc_val) = self.encode_and_get_crc();
+    let (_, crc_val) = self.encode_and_get_crc(self.timestamp);
     crc_val
   }
 
-  fn encode_and_get_crc(&self) -> (Vec<u8>, u32) {
+  fn encode_and_get_crc(&self, timestamp: Option<u64>) -> (Vec<u8>, u32) {
     // init bytes array, store encoded log record
     let mut buf = BytesMut::new();
-    buf.reserve(self.encoded_length());
-
-    // write log record type into buffer
-    buf.put_u8(self.rec_type as u8);
+    buf.reserve(self.encoded_length(timestamp));
+
+    // write log record type into buffer, followed by the timestamp if there is one
+    match timestamp {
+      Some(timestamp) => {
+        buf.put_u8(self.rec_type as u8 | TIMESTAMP_FLAG);
+        buf.put_u64(timestamp);
+      }
+      None => buf.put_u8(self.rec_type as u8),
+    }
 
-    // write key length and value length into buffer
-    encode_length_delimiter(self.key.len(), &mut buf).unwrap();
-    encode_length_delimiter(self.value.len(), &mut buf).unwrap();
+    // write key length and value length into buffer, BytesMut grows on demand so it never fails
+    let _ = encode_length_delimiter(self.key.len(), &mut buf);
+    let _ = encode_length_delimiter(self.value.len(), &mut buf);
 
     // write key and value into buffer
 
@@ -82,8 +121,9 @@ impl LogRecord {
   }
 
   // get encoded log record length
-  fn encoded_length(&self) -> usize {
+  fn encoded_length(&self, timestamp: Option<u64>) -> usize {
     std::mem::size_of::<u8>()
+      + timestamp.map_or(0, |_| TIMESTAMP_SIZE)
       + length_delimiter_len(self.key.len())
       + length_delimiter_len(self.value.len())
       + self.key.len()
@@ -103,41 +143,38 @@ impl LogRecordPos {
 }
 
 impl LogRecordType {
-  pub fn from_u8(value: u8) -> Self {
+  pub fn from_u8(value: u8) -> Result<Self> {
     match value {
-      1 => LogRecordType::Normal,
-      2 => LogRecordType::Deleted,
-      3 => LogRecordType::TxnFinished,
-      _ => panic!("unsupported log record type"),
+      1 => Ok(LogRecordType::Normal),
+      2 => Ok(LogRecordType::Deleted),
+      3 => Ok(LogRecordType::TxnFinished),
+      4 => Ok(LogRecordType::FileFooter),
+      5 => Ok(LogRecordType::Expire),
+      6 => Ok(LogRecordType::Encrypted),
+      7 => Ok(LogRecordType::TxnPrepared),
+      8 => Ok(LogRecordType::TxnAborted),
+      9 => Ok(LogRecordType::Blob),
+      _ => Err(Errors::InvalidLogRecord),
     }
   }
 }
 
 pub fn max_log_record_header_size() -> usize {
-  std::mem::size_of::<u8>() + length_delimiter_len(u32::MAX as usize) * 2
+  std::mem::size_of::<u8>() + TIMESTAMP_SIZE + length_delimiter_len(u32::MAX as usize) * 2
 }
 
-pub fn decode_log_record_pos(pos: Vec<u8>) -> LogRecordPos {
+pub fn decode_log_record_pos(pos: Vec<u8>) -> Result<LogRecordPos> {
   let mut buf = BytesMut::new();
   buf.put_slice(&pos);
 
-  let fid = match decode_varint(&mut buf) {
-    Ok(fid) => fid,
-    Err(e) => panic!("decode log record pos error: {e}"),
-  };
-  let offset = match decode_varint(&mut buf) {
-    Ok(offset) => offset,
-    Err(e) => panic!("decode log record pos error: {e}"),
-  };
-  let size = match decode_varint(&mut buf) {
-    Ok(size) => size,
-    Err(e) => panic!("decode log record pos error: {e}"),
-  };
-  LogRecordPos {
+  let fid = decode_varint(&mut buf).map_err(|_| Errors::InvalidLogRecordPos)?;
+  let offset = decode_varint(&mut buf).map_err(|_| Errors::InvalidLogRecordPos)?;
+  let size = decode_varint(&mut buf).map_err(|_| Errors::InvalidLogRecordPos)?;
+  Ok(LogRecordPos {
     file_id: fid as u32,
     offset,
     size: size as u32,
-  }
+  })
 }
 
 #[cfg(test)]
@@ -183,6 +220,7 @@ mod tests {
       key: "key-a".as_bytes().to_vec(),
       value: "value-a".as_bytes().to_vec(),
       rec_type: LogRecordType::Normal,
+      timestamp: None,
     };
     verify_crc(&rec1);
 
@@ -191,6 +229,7 @@ mod tests {
       key: "flash-kv".as_bytes().to_vec(),
       value: vec![],
       rec_type: LogRecordType::Normal,
+      timestamp: None,
     };
     verify_crc(&rec2);
 
@@ -199,7 +238,18 @@ mod tests {
       key: "key-b".as_bytes().to_vec(),
       value: "value-b".as_bytes().to_vec(),
       rec_type: LogRecordType::Deleted,
+      timestamp: None,
     };
     verify_crc(&rec3);
+
+    // Test case 4: timestamped log record
+    let rec4 = LogRecord {
+      timestamp: Some(1_700_000_000_000),
+      ..rec1.clone()
+    };
+    verify_crc(&rec4);
+    let encoded = rec4.encode();
+    assert_eq!(rec1.encode().len() + TIMESTAMP_SIZE, encoded.len());
+    assert_eq!(LogRecordType::Normal as u8 | TIMESTAMP_FLAG, encoded[0]);
   }
 }
@@ -1,2 +1,3 @@
+pub(crate) mod cipher;
 pub mod data_file;
 pub mod log_record;
@@ -1,15 +1,38 @@
 #![allow(clippy::redundant_closure)]
 use crate::{
   batch::{log_record_key_with_seq, parse_log_record_key, NON_TXN_SEQ_NO},
+  blob::BlobFiles,
+  cdc::{CdcOp, CdcStream},
+  clear::recover_clear,
   data::{
-    data_file::{DataFile, DATA_FILE_NAME_SUFFIX, MERGE_FINISHED_FILE_NAME, SEQ_NO_FILE_NAME},
-    log_record::{LogRecord, LogRecordPos, LogRecordType, TransactionRecord},
+    cipher::RecordCipher,
+    data_file::{get_data_file_dir, DataFile, DATA_FILE_NAME_SUFFIX, MERGE_FINISHED_FILE_NAME},
+    log_record::{LogRecord, LogRecordPos, TransactionRecord},
   },
   errors::{Errors, Result},
+  event::EventListeners,
+  expiry::{unix_millis, ExpiryIndex},
+  fio::pool::FilePool,
+  garbage::GarbageTracker,
   index,
+  io_stats::{ingested_bytes, IoStats},
+  iterator::PositionEpoch,
+  keylock::KeyLockTable,
+  keyspace::record_size,
+  layout::{data_file_dirs, relocate_data_files},
+  manifest::{load_manifest, KeyDirBuffer},
   merge::load_merge_files,
-  option::{IOManagerType, IndexType, Options},
+  multi_batch::PreparedTxn,
+  option::{CrcImpl, IOManagerType, IndexType, Options, RuntimeOptions, StatOptions, StorageMode},
+  quota::QuotaManager,
+  reader::{lock_shared_reader, SharedReader},
+  repair::{OpenPhase, OpenReport, ReadRepairIncident},
+  scrub::ScrubCounters,
+  slowlog::{SlowLog, SlowOp, SlowOpTimer},
+  throttle::{DiskSpace, RateLimiter, SyncTuner},
+  tombstone::TombstoneSidecars,
   util,
+  watch::{WatchOp, WatchRegistry},
 };
 use bytes::Bytes;
 use fs2::FileExt;
@@ -18,15 +41,18 @@ use parking_lot::{Mutex, RwLock};
 use std::{
   collections::HashMap,
   fs::{self, File},
-  path::Path,
+  path::{Path, PathBuf},
   sync::{
-    atomic::{AtomicUsize, Ordering},
+    atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
     Arc,
   },
+  thread,
+  time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
+pub use crate::data::log_record::LogRecordType;
+
 const INITIAL_FILE_ID: u32 = 0;
-const SEQ_NO_KEY: &str = "seq.no";
 pub(crate) const FILE_LOCK_NAME: &str = "flock";
 
 /// Represents the sequence number existence state.
@@ -48,19 +74,95 @@ pub struct Engine {
   pub(crate) index: Box<dyn index::Indexer>,          // data cache index
   file_ids: Vec<u32>, // database setup file id list, only used for setup, not allowed to be modified or updated somewhere else
   pub(crate) batch_commit_lock: Mutex<()>, // txn commit lock ensure serializable
+  pub(crate) write_gate: RwLock<()>, // shared by writes from their append to their index update, exclusive for a clear
+  pub(crate) key_locks: KeyLockTable, // keys locked by `lock_key`
   pub(crate) seq_no: Arc<AtomicUsize>, // transaction sequence number
   pub(crate) merging_lock: Mutex<()>, // prevent multiple threads from merging data files at the same time
   pub(crate) seq_file_exists: bool,   // whether the seq_no file exists
+  pub(crate) seq_no_reserved: AtomicUsize, // sequence numbers below are covered by the seq_no file
   pub(crate) is_initial: bool,        // whether the engine is initialized
-  lock_file: File, // file lock, ensure only one engine instance can open the database directory
+  lock_file: Option<File>, // file lock, ensure only one engine instance can open the database directory, none in memory
   bytes_write: Arc<AtomicUsize>, // the add up number of bytes written
+  pub(crate) sync_tuner: SyncTuner, // bytes written between syncs
   pub(crate) reclaim_size: Arc<AtomicUsize>, // the add up number of bytes to be merged
+  pub(crate) garbage: GarbageTracker, // reclaimable bytes per data file
+  pub(crate) watchers: Arc<WatchRegistry>, // key change subscribers
+  pub(crate) listeners: EventListeners, // engine event listeners
+  pub(crate) read_repair_incidents: Mutex<Vec<ReadRepairIncident>>, // reads repaired since last taken
+  pub(crate) file_pool: Option<Arc<FilePool>>, // bounds open old data files when `max_open_files` is set
+  pub(crate) keydir: Mutex<KeyDirBuffer>, // key directory of the active file, kept when `startup_manifest` is set
+  pub(crate) tombstones: TombstoneSidecars, // delete markers of `tombstone_sidecar`
+  pub(crate) blobs: BlobFiles,            // large values of `blob_threshold`
+  pub(crate) write_limiter: RateLimiter,  // bytes per second written by foreground writes
+  pub(crate) disk_space: DiskSpace,       // free space sampled for `reserved_disk_bytes`
+  pub(crate) merge_limiter: RateLimiter,  // bytes per second read by merges
+  pub(crate) merge_threshold: AtomicU32,  // bits of the current `file_merge_threshold`
+  pub(crate) runtime_options: Mutex<RuntimeOptions>, // current tunables, see `update_options`
+  pub(crate) expiry: ExpiryIndex,         // expiration times of keys
+  pub(crate) reader: Option<SharedReader>, // set when opened as one of the `shared_readers`
+  pub(crate) io_stats: IoStats,           // bytes read and written, for amplification
+  pub(crate) scrub_counters: ScrubCounters, // bytes verified and corruptions found by the scrubber
+  closed: AtomicBool,                     // set by `close`, the engine rejects operations after it
+  pub(crate) slow_log: SlowLog,           // operations exceeding `slow_op_threshold`
+  pub(crate) quotas: QuotaManager,        // live bytes of the `prefix_quotas`
+  pub(crate) cipher: Option<Arc<RecordCipher>>, // set when `encryption_key` is
+  pub(crate) prepared_txns: Mutex<HashMap<u64, PreparedTxn>>, // multi-engine transactions waiting for their decision
+  pub(crate) orphaned_txns: Mutex<HashMap<usize, Vec<TransactionRecord>>>, // txns without commit record, resolved on open
+  pub(crate) position_epoch: RwLock<Arc<PositionEpoch>>, // ended when data files are replaced under iterators
+  pub(crate) data_files_version: AtomicU64, // odd while data files are replaced, see `read_indexed`
+  disk_size: Mutex<Option<DiskSizeSample>>, // last walk of the directory, dropped when files are replaced
+  change_id: AtomicU64,                     // bumped by every change of the keys, see `change_id`
+  pub(crate) pinned_epochs: Arc<AtomicUsize>, // read epochs not released yet
+  pub(crate) cdc: Option<CdcStream>,        // tee of the committed changes, see `cdc_output`
+}
+
+// size of the directory when the active file had the given size
+struct DiskSizeSample {
+  active_file_id: u32,
+  active_file_size: u64,
+  disk_size: u64,
+}
+
+/// A value with the location of its record, returned by [`Engine::get_entry`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+  pub value: Bytes,
+
+  /// Data file holding the record
+  pub file_id: u32,
+
+  /// Offset of the record in its data file
+  pub offset: u64,
+
+  /// Size of the encoded record, header and crc included
+  pub size: u32,
+
+  pub record_type: LogRecordType,
+
+  /// Sequence number of the transaction which wrote the record, None for records written
+  /// outside of a transaction or rewritten by a merge
+  pub txn_seq: Option<usize>,
+
+  /// When the record was written, None unless it was written with `Options::store_timestamps`
+  pub timestamp: Option<SystemTime>,
+}
+
+/// A page of keys listed by [`Engine::list_keys_paged`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyPage {
+  pub keys: Vec<Bytes>,
+
+  /// `Engine::change_id` when the listing started, the page may be stale once it moved on
+  pub change_id: u64,
+
+  /// Last key of the page if more keys follow, the `start_after` of the next page
+  pub next: Option<Bytes>,
 }
 
 /// Statistics about the engine state.
 ///
 /// Provides information about the number of keys, data files, and disk usage.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Stat {
   /// Number of keys in the database
   pub key_num: usize,
@@ -71,9 +173,81 @@ pub struct Stat {
   /// Number of bytes that can be reclaimed through merging
   pub reclaim_size: usize,
 
+  /// Number of reclaimable bytes taken by delete records
+  pub tombstone_size: usize,
+
   /// Total size of the database directory on disk in bytes
   pub disk_size: u64,
+
+  /// Record bytes read from data files by lookups and merges since open
+  pub bytes_read: u64,
+
+  /// Value bytes returned by lookups since open
+  pub bytes_returned: u64,
+
+  /// Record bytes written to data files since open, merge output included
+  pub bytes_written: u64,
+
+  /// Key and value bytes written by callers since open
+  pub bytes_ingested: u64,
+
+  /// Bytes appended to the active file between syncs, follows the write rate under
+  /// `SyncPolicy::Adaptive`
+  pub bytes_per_sync: usize,
+
+  /// Record bytes verified by the scrubber since open
+  pub scrubbed_bytes: u64,
+
+  /// Broken records found by the scrubber since open
+  pub scrub_corruptions: u64,
 }
+
+impl Stat {
+  /// Bytes read from disk per byte returned, 0 before the first lookup.
+  pub fn read_amplification(&self) -> f64 {
+    match self.bytes_returned {
+      0 => 0.0,
+      returned => self.bytes_read as f64 / returned as f64,
+    }
+  }
+
+  /// Bytes written to disk per byte written by callers, 0 before the first write.
+  pub fn write_amplification(&self) -> f64 {
+    match self.bytes_ingested {
+      0 => 0.0,
+      ingested => self.bytes_written as f64 / ingested as f64,
+    }
+  }
+
+  /// Name and value of every counter, in the order of the fields.
+  pub(crate) fn fields(&self) -> [(&'static str, u64); 12] {
+    [
+      ("key_num", self.key_num as u64),
+      ("data_file_num", self.data_file_num as u64),
+      ("reclaim_size", self.reclaim_size as u64),
+      ("tombstone_size", self.tombstone_size as u64),
+      ("disk_size", self.disk_size),
+      ("bytes_read", self.bytes_read),
+      ("bytes_returned", self.bytes_returned),
+      ("bytes_written", self.bytes_written),
+      ("bytes_ingested", self.bytes_ingested),
+      ("bytes_per_sync", self.bytes_per_sync as u64),
+      ("scrubbed_bytes", self.scrubbed_bytes),
+      ("scrub_corruptions", self.scrub_corruptions),
+    ]
+  }
+
+  /// Encodes the stat as a flat JSON object led by the `timestamp` in seconds since the epoch.
+  pub(crate) fn to_json(&self, timestamp: u64) -> String {
+    let mut json = format!("{{\"timestamp\":{timestamp}");
+    for (name, value) in self.fields() {
+      json.push_str(&format!(",\"{name}\":{value}"));
+    }
+    json.push('}');
+    json
+  }
+}
+
 impl Engine {
   /// Opens a Flash-KV storage engine instance.
   ///
@@ -95,16 +269,44 @@ impl Engine {
   /// if the database is already being used by another process, or if data files
   /// cannot be loaded.
   pub fn open(opts: Options) -> Result<Self> {
+    Self::open_with_report(opts).map(|(engine, _)| engine)
+  }
+
+  /// Opens the engine like [`Engine::open`] and reports what the recovery did, e.g. the
+  /// records dropped by `Options::repair_on_open` and the time spent per phase.
+  #[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "info", skip_all, fields(dir = %opts.dir_path.display()))
+  )]
+  pub fn open_with_report(opts: Options) -> Result<(Self, OpenReport)> {
+    Self::open_with_pool(opts, None)
+  }
+
+  /// Opens the engine, its old data files are kept open by `file_pool` if given instead of
+  /// a pool of its own `max_open_files`.
+  pub(crate) fn open_with_pool(
+    opts: Options,
+    file_pool: Option<Arc<FilePool>>,
+  ) -> Result<(Self, OpenReport)> {
     // check user options
-    if let Some(e) = check_options(&opts) {
-      return Err(e);
-    };
+    opts.validate()?;
+    let mut report = OpenReport::default();
     let mut is_initial = false;
     let options = Arc::new(opts);
+    let cipher = match &options.encryption_key {
+      Some(key) => Some(Arc::new(RecordCipher::new(key)?)),
+      None => None,
+    };
 
     // determine if dir is valid, dir does not exist, create a new one
     let dir_path = &options.dir_path;
-    if !dir_path.is_dir() {
+    let in_memory = options.storage_mode == StorageMode::Memory;
+    if (options.shared_readers || options.read_only) && !dir_path.is_dir() {
+      return Err(Errors::FailedToReadDatabaseDir);
+    }
+    if in_memory {
+      is_initial = true;
+    } else if !dir_path.is_dir() {
       is_initial = true;
       if let Err(e) = fs::create_dir(dir_path.as_path()) {
         warn!("failed to create database directory error: {e}");
@@ -112,26 +314,80 @@ impl Engine {
       };
     }
 
+    // readers share the database with the process holding the lock
+    let lock_file = match options.shared_readers {
+      _ if in_memory || options.read_only => None,
+      true => Some(lock_shared_reader(dir_path)?),
+      false => {
+        let lock_file = fs::OpenOptions::new()
+          .read(true)
+          .create(true)
+          .append(true)
+          .open(dir_path.join(FILE_LOCK_NAME))
+          .map_err(|e| {
+            error!("failed to open database lock file: {e}");
+            Errors::FailedToOpenLockFile
+          })?;
+        if lock_file.try_lock_exclusive().is_err() {
+          return Err(Errors::DatabaseIsUsing);
+        }
+        Some(lock_file)
+      }
+    };
+    let reader = (options.shared_readers || options.read_only).then(|| SharedReader::new(dir_path));
+
     // determine if dir is empty, if empty, set is_initial to true
-    let lock_file = fs::OpenOptions::new()
-      .read(true)
-      .create(true)
-      .append(true)
-      .open(dir_path.join(FILE_LOCK_NAME))
-      .unwrap();
-    if lock_file.try_lock_exclusive().is_err() {
-      return Err(Errors::DatabaseIsUsing);
-    }
-
-    let entry = fs::read_dir(dir_path).unwrap();
-    if entry.count() == 0 {
-      is_initial = true;
+    if !in_memory {
+      let entry = fs::read_dir(dir_path).map_err(|_| Errors::FailedToReadDatabaseDir)?;
+      if entry.count() == 0 {
+        is_initial = true;
+      }
+    }
+
+    // interrupted clears and merges are finished by the writer
+    let mut cleared = false;
+    if reader.is_none() && !in_memory {
+      let started = Instant::now();
+      // finish a clear interrupted by a crash, it made the merge files stale as well
+      cleared = recover_clear(dir_path)?;
+
+      // files written under another `files_per_subdir` are moved into place first
+      relocate_data_files(dir_path, options.files_per_subdir)?;
+
+      // load merge files
+      load_merge_files(dir_path, options.files_per_subdir)?;
+      report.record_phase(OpenPhase::FinishInterrupted, started);
     }
-    // load merge files
-    load_merge_files(dir_path)?;
 
     // load data files
-    let mut data_files = load_data_files(dir_path, options.mmap_at_startup)?;
+    let started = Instant::now();
+    let file_pool = match options.max_open_files {
+      _ if file_pool.is_some() => file_pool,
+      0 => None,
+      max_open => Some(Arc::new(FilePool::new(max_open))),
+    };
+    let data_files = match in_memory {
+      true => Vec::new(),
+      false => load_data_files(
+        dir_path,
+        options.files_per_subdir,
+        options.startup_file_io_type(),
+        options.file_io_type(),
+        options.sealed_file_io_type(),
+        file_pool.as_ref(),
+      )?,
+    };
+
+    let wrapper = options.io_manager_wrapper.as_ref();
+    let mut data_files: Vec<DataFile> = data_files
+      .into_iter()
+      .map(|file| {
+        let file_dir = get_data_file_dir(dir_path, file.get_file_id(), options.files_per_subdir);
+        file
+          .with_io_wrapper(file_dir, wrapper)
+          .with_cipher(cipher.as_ref())
+      })
+      .collect();
 
     // set file id info
     let mut file_ids = Vec::new();
@@ -143,9 +399,8 @@ impl Engine {
 
     // save old file into older_files
     let mut older_files = HashMap::new();
-    if data_files.len() > 1 {
-      for _ in 0..=data_files.len() - 2 {
-        let file = data_files.pop().unwrap();
+    while data_files.len() > 1 {
+      if let Some(file) = data_files.pop() {
         older_files.insert(file.get_file_id(), file);
       }
     }
@@ -153,46 +408,124 @@ impl Engine {
     // Retrieve the active data file, which is the last one in the data_files
     let active_file = match data_files.pop() {
       Some(v) => v,
-      None => DataFile::new(dir_path, INITIAL_FILE_ID, IOManagerType::StandardFileIO)?,
+      // nothing to read, no file is created either
+      None if options.read_only => DataFile::new(
+        options.dir_path.as_path(),
+        INITIAL_FILE_ID,
+        IOManagerType::Memory,
+      )?,
+      None => {
+        let file_dir = create_data_file_dir(&options, INITIAL_FILE_ID)?;
+        DataFile::new(&file_dir, INITIAL_FILE_ID, options.file_io_type())?
+          .with_io_wrapper(&file_dir, wrapper)
+          .with_cipher(cipher.as_ref())
+      }
     };
+    report.record_phase(OpenPhase::OpenFiles, started);
+
+    let cdc = options
+      .cdc_output
+      .as_ref()
+      .map(CdcStream::open)
+      .transpose()?;
 
     // create a new engine instance
     let mut engine = Self {
       options: options.clone(),
       active_data_file: Arc::new(RwLock::new(active_file)),
       old_data_files: Arc::new(RwLock::new(older_files)),
-      index: index::new_indexer(&options.index_type, &options.dir_path),
+      index: index::new_indexer(&options)?,
       file_ids,
       batch_commit_lock: Mutex::new(()),
+      write_gate: RwLock::new(()),
+      key_locks: KeyLockTable::default(),
       seq_no: Arc::new(AtomicUsize::new(1)),
       merging_lock: Mutex::new(()),
       seq_file_exists: false,
+      seq_no_reserved: AtomicUsize::new(0),
       is_initial,
       lock_file,
       bytes_write: Arc::new(AtomicUsize::new(0)),
+      sync_tuner: SyncTuner::new(&options),
       reclaim_size: Arc::new(AtomicUsize::new(0)),
+      garbage: GarbageTracker::default(),
+      watchers: Arc::new(WatchRegistry::default()),
+      listeners: EventListeners::default(),
+      read_repair_incidents: Mutex::new(Vec::new()),
+      file_pool,
+      keydir: Mutex::new(KeyDirBuffer::default()),
+      tombstones: TombstoneSidecars::default(),
+      blobs: BlobFiles::default(),
+      write_limiter: RateLimiter::new(options.max_write_rate_bytes_per_sec),
+      disk_space: DiskSpace::default(),
+      merge_limiter: RateLimiter::new(options.merge_scheduler.io_limit_bytes_per_sec),
+      merge_threshold: AtomicU32::new(options.file_merge_threshold.to_bits()),
+      runtime_options: Mutex::new(RuntimeOptions::new(&options)),
+      expiry: ExpiryIndex::default(),
+      reader,
+      io_stats: IoStats::default(),
+      scrub_counters: ScrubCounters::default(),
+      closed: AtomicBool::new(false),
+      change_id: AtomicU64::new(0),
+      pinned_epochs: Arc::new(AtomicUsize::new(0)),
+      cdc,
+      slow_log: SlowLog::default(),
+      quotas: QuotaManager::new(options.prefix_quotas.clone()),
+      cipher,
+      prepared_txns: Mutex::new(HashMap::new()),
+      orphaned_txns: Mutex::new(HashMap::new()),
+      position_epoch: RwLock::new(Arc::default()),
+      data_files_version: AtomicU64::new(0),
+      disk_size: Mutex::new(None),
     };
 
+    // a persistent index still holds the keys of the cleared files
+    if cleared {
+      engine.index.clear()?;
+    }
+
     // if not B+Tree index type, load index from hint file and data files
+    let started = Instant::now();
     match engine.options.index_type {
+      // nothing to load in memory
+      _ if in_memory => {}
       IndexType::BPlusTree => {
         // load seq_no from current transaction
-        let (is_exists, seq_no) = engine.load_seq_no();
-        if is_exists {
+        if let Some(seq_no) = engine.load_seq_no()? {
           engine.seq_no.store(seq_no, Ordering::SeqCst);
-          engine.seq_file_exists = is_exists;
+          engine.seq_file_exists = true;
         }
 
-        // update offset of active data file
+        // update offset of active data file, preallocated space is no content
         let active_file = engine.active_data_file.write();
-        active_file.set_write_off(active_file.file_size());
+        let write_off = match engine.options.preallocate_data_file {
+          true => active_file.scan_end()?,
+          false => active_file.file_size(),
+        };
+        active_file.set_write_off(write_off);
+        drop(active_file);
+
+        // restore the reclaim size recorded on close, or the last garbage map after a crash
+        if !engine.load_close_hint()? {
+          engine.load_garbage_map()?;
+        }
+        report.record_phase(OpenPhase::LoadHints, started);
+      }
+      _ if engine.reader.is_none() && engine.load_close_hint()? => {
+        // index was loaded from the close hint
+        if engine.options.startup_file_io_type().is_some() {
+          engine.reset_io_type()?;
+        }
+        report.record_phase(OpenPhase::LoadHints, started);
       }
       _ => {
         // load index from hint file
-        engine.load_index_from_hint_file()?;
+        let hint_loaded = engine.load_index_from_hint_file()?;
+        report.record_phase(OpenPhase::LoadHints, started);
 
         // load index from data files
-        let curr_seq_no = engine.load_index_from_data_files()?;
+        let started = Instant::now();
+        let curr_seq_no = engine.load_index_from_data_files(hint_loaded, &mut report)?;
 
         // update seq_no
         if curr_seq_no > 0 {
@@ -201,14 +534,30 @@ impl Engine {
             .store(curr_seq_no + 1, std::sync::atomic::Ordering::Relaxed);
         }
 
-        // reset io_manager type
-        if engine.options.mmap_at_startup {
-          engine.reset_io_type();
+        // reset io_manager type, a new active file was not mapped
+        if engine.options.startup_file_io_type().is_some() && !engine.file_ids.is_empty() {
+          engine.reset_io_type()?;
         }
+        report.record_phase(OpenPhase::ReplayFiles, started);
       }
     }
+    // the decision about orphaned transactions is written once the data files are writable
+    engine.resolve_orphaned_txns(&mut report)?;
+    engine.refresh_quota_usage()?;
+
+    // entries of data files removed by merges are dropped from the manifest
+    if engine.options.startup_manifest && engine.reader.is_none() && !in_memory {
+      engine.compact_manifest()?;
+    }
 
-    Ok(engine)
+    // the active file continues with preallocated space once its write offset is known
+    if engine.options.preallocate_data_file && engine.reader.is_none() {
+      let mut active_file = engine.active_data_file.write();
+      let file_dir = engine.data_file_dir(active_file.get_file_id());
+      active_file.preallocate(file_dir, engine.options.data_file_size, wrapper)?;
+    }
+
+    Ok((engine, report))
   }
 
   /// Closes the engine and releases resources.
@@ -225,26 +574,52 @@ impl Engine {
   /// Returns an error if the engine fails to write sequence number information
   /// or sync data to disk.
   pub fn close(&self) -> Result<()> {
+    // writers check the flag holding the active file, none of them appends after this point.
+    // A failed close is not retried by a later call or on drop
+    let active_file = self.active_data_file.write();
+    if self.closed.swap(true, Ordering::SeqCst) {
+      return Ok(());
+    }
+    drop(active_file);
+
     // if dir_path doesn't exist, return
+    let Some(lock_file) = &self.lock_file else {
+      return Ok(());
+    };
     if !self.options.dir_path.is_dir() {
       return Ok(());
     }
-    // load seq_no from current transaction
-    let seq_no_file = DataFile::new_seq_no_file(&self.options.dir_path)?;
-    let seq_no = self.seq_no.load(Ordering::SeqCst);
-    let record = LogRecord {
-      key: SEQ_NO_KEY.as_bytes().to_vec(),
-      value: seq_no.to_string().into(),
-      rec_type: LogRecordType::Normal,
-    };
-    seq_no_file.write(&record.encode())?;
-    seq_no_file.sync()?;
-
+    // readers leave the files to the writer
+    if self.reader.is_some() {
+      return fs2::FileExt::unlock(lock_file).map_err(|e| {
+        error!("failed to unlock database directory: {e}");
+        Errors::FailedToUnlockDatabaseDir
+      });
+    }
+    // record the exact seq_no instead of the reserved one, after any commit taking a number
+    let commit_guard = self.batch_commit_lock.lock();
+    self.write_seq_no(self.seq_no.load(Ordering::SeqCst))?;
+    drop(commit_guard);
+
+    self.sync_tombstone_sidecar()?;
+    self.sync_active_blob()?;
+    // the next open finds the active file as large as its content
     let read_guard = self.active_data_file.read();
+    read_guard.trim()?;
     read_guard.sync()?;
+    self.write_garbage_map(read_guard.get_file_id(), read_guard.get_write_off())?;
+    drop(read_guard);
+
+    // prepared transactions are only found by scanning the data files
+    if self.options.fast_reopen && self.prepared_txns.lock().is_empty() {
+      self.write_close_hint()?;
+    }
 
     // release file lock
-    fs2::FileExt::unlock(&self.lock_file).unwrap();
+    fs2::FileExt::unlock(lock_file).map_err(|e| {
+      error!("failed to unlock database directory: {e}");
+      Errors::FailedToUnlockDatabaseDir
+    })?;
 
     Ok(())
   }
@@ -261,11 +636,47 @@ impl Engine {
   /// # Errors
   ///
   /// Returns an error if the sync operation fails.
+  #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
   pub fn sync(&self) -> Result<()> {
+    self.check_open()?;
+    self.sync_tombstone_sidecar()?;
+    self.sync_active_blob()?;
     let read_guard = self.active_data_file.read();
     read_guard.sync()
   }
 
+  /// Seals the active data file and continues writing in a new one, regardless of its size.
+  ///
+  /// The sealed file is synced and never written again, so it can be copied out, e.g. before
+  /// taking a filesystem snapshot or shipping the files to another host.
+  ///
+  /// # Returns
+  ///
+  /// The id of the sealed file, or `None` if the active file holds no records and was
+  /// left as is.
+  ///
+  /// # Errors
+  ///
+  /// Returns `ReadOnlyEngine` for readers, or an error if sealing or creating a file fails.
+  pub fn flush_and_rotate(&self) -> Result<Option<u32>> {
+    self.check_open()?;
+    if self.reader.is_some() {
+      return Err(Errors::ReadOnlyEngine);
+    }
+    self.sync_tombstone_sidecar()?;
+
+    let mut active_file = self.active_data_file.write();
+    self.check_open()?;
+    if active_file.get_write_off() == 0 {
+      return Ok(None);
+    }
+    let file_id = active_file.get_file_id();
+    self.rotate_active_file(&mut active_file)?;
+    // the sealed file was synced, nothing is pending for `bytes_per_sync`
+    self.bytes_write.store(0, Ordering::SeqCst);
+    Ok(Some(file_id))
+  }
+
   /// Retrieves statistics about the engine state.
   ///
   /// This method collects information about the number of keys, data files,
@@ -279,17 +690,63 @@ impl Engine {
   ///
   /// Returns an error if statistics cannot be collected.
   pub fn get_engine_stat(&self) -> Result<Stat> {
+    self.get_engine_stat_with(StatOptions::default())
+  }
+
+  /// Like `get_engine_stat`, `options.approximate` set to false measures `disk_size` by
+  /// walking the database directory.
+  pub fn get_engine_stat_with(&self, options: StatOptions) -> Result<Stat> {
     let keys = self.list_keys()?;
-    let old_files = self.old_data_files.read();
+    let data_file_num = self.old_data_files.read().len() + 1;
 
     Ok(Stat {
       key_num: keys.len(),
-      data_file_num: old_files.len() + 1,
+      data_file_num,
       reclaim_size: self.reclaim_size.load(Ordering::SeqCst),
-      disk_size: util::file::dir_disk_size(&self.options.dir_path),
+      tombstone_size: self.garbage.tombstone_bytes() as usize,
+      disk_size: self.disk_size(options.approximate),
+      bytes_read: self.io_stats.bytes_read(),
+      bytes_returned: self.io_stats.bytes_returned(),
+      bytes_written: self.io_stats.bytes_written(),
+      bytes_ingested: self.io_stats.bytes_ingested(),
+      bytes_per_sync: self.sync_tuner.bytes_per_sync(),
+      scrubbed_bytes: self.scrub_counters.bytes(),
+      scrub_corruptions: self.scrub_counters.corruptions(),
     })
   }
 
+  // the size of the database directory, estimated from the last walk if `approximate`
+  pub(crate) fn disk_size(&self, approximate: bool) -> u64 {
+    let active_file = self.active_data_file.read();
+    let (active_file_id, active_file_size) = (active_file.get_file_id(), active_file.file_size());
+    drop(active_file);
+
+    let mut sample = self.disk_size.lock();
+    match &*sample {
+      Some(last)
+        if approximate
+          && last.active_file_id == active_file_id
+          && last.active_file_size <= active_file_size =>
+      {
+        last.disk_size + (active_file_size - last.active_file_size)
+      }
+      _ => {
+        let disk_size = util::file::dir_disk_size(&self.options.dir_path);
+        *sample = Some(DiskSizeSample {
+          active_file_id,
+          active_file_size,
+          disk_size,
+        });
+        disk_size
+      }
+    }
+  }
+
+  /// Drops the measured directory size after files other than the active one changed.
+  pub(crate) fn invalidate_disk_size(&self) {
+    *self.disk_size.lock() = None;
+  }
+
   /// Creates a backup of the database directory.
   ///
   /// This method copies all database files to the specified directory,
@@ -310,6 +767,9 @@ impl Engine {
   where
     P: AsRef<Path>,
   {
+    if self.options.storage_mode == StorageMode::Memory {
+      return Err(Errors::MemoryModeUnsupported);
+    }
     let exclude = &[FILE_LOCK_NAME];
     if let Err(e) = util::file::copy_dir(
       &self.options.dir_path,
@@ -339,28 +799,58 @@ impl Engine {
   /// # Errors
   ///
   /// Returns an error if the key is empty or if the write operation fails.
+  #[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+      level = "debug",
+      skip_all,
+      fields(key_len = key.len(), value_len = value.len())
+    )
+  )]
   pub fn put(&self, key: Bytes, value: Bytes) -> Result<()> {
+    self.check_open()?;
     // if the key is valid
     if key.is_empty() {
       return Err(Errors::KeyIsEmpty);
     }
 
+    let mut timer = self.slow_op_timer(SlowOp::Put);
+    self.throttle_write(key.len() + value.len(), true)?;
+    timer.phase("throttle");
+    let _guard = self.lock_key(key.clone());
+    self.put_throttled(key, value, timer)
+  }
+
+  // writes `key` once `throttle_write` admitted it, callers hold the key lock
+  fn put_throttled(&self, key: Bytes, value: Bytes, mut timer: SlowOpTimer) -> Result<()> {
+    self.check_index_memory(&key)?;
+    self.check_quota([(&key[..], record_size(key.len(), value.len()))])?;
+
     // construct LogRecord
     let mut record = LogRecord {
-      key: log_record_key_with_seq(key.to_vec(), NON_TXN_SEQ_NO),
+      key: log_record_key_with_seq(&key, NON_TXN_SEQ_NO),
       value: value.to_vec(),
       rec_type: LogRecordType::Normal,
+      timestamp: None,
     };
 
-    // appending write to active file
+    // appending write to active file, a clear waits until the index points to it
+    let _gate = self.write_gate.read_recursive();
     let log_record_pos = self.append_log_record(&mut record)?;
+    timer.phase("append");
 
     // update index
-    if let Some(old_pos) = self.index.put(key.to_vec(), log_record_pos) {
-      self
-        .reclaim_size
-        .fetch_add(old_pos.size as usize, Ordering::SeqCst);
+    let old_pos = self.index.try_put(key.to_vec(), log_record_pos)?;
+    self.account_quota(&key, old_pos, Some(log_record_pos));
+    if let Some(old_pos) = old_pos {
+      self.mark_stale(old_pos);
     }
+    self.clear_expiry(&key);
+    self.mark_changed();
+    self.watchers.notify(&key, WatchOp::Put, Some(&value));
+    self.emit_cdc([(CdcOp::Put, &key[..], &value[..])]);
+    timer.phase("index");
+    self.finish_slow_op(timer, Some(&key));
     Ok(())
   }
 
@@ -380,37 +870,66 @@ impl Engine {
   /// # Errors
   ///
   /// Returns an error if the key is empty or if the delete operation fails.
+  #[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip_all, fields(key_len = key.len()))
+  )]
   pub fn delete(&self, key: Bytes) -> Result<()> {
+    self.check_open()?;
     // if the key is valid
     if key.is_empty() {
       return Err(Errors::KeyIsEmpty);
     }
 
     // retrieve specified data from index if it not exists then return
+    let mut timer = self.slow_op_timer(SlowOp::Delete);
     let pos = self.index.get(key.to_vec());
     if pos.is_none() {
       return Ok(());
     }
 
-    // construct LogRecord
-    let mut record = LogRecord {
-      key: log_record_key_with_seq(key.to_vec(), NON_TXN_SEQ_NO),
-      value: Default::default(),
-      rec_type: LogRecordType::Deleted,
-    };
+    // deletes are never rejected, merge reclaims the space they free
+    self.throttle_write(key.len(), false)?;
+    timer.phase("throttle");
+    let _guard = self.lock_key(key.clone());
+    let _gate = self.write_gate.read_recursive();
+    // the key may have been deleted while waiting for its lock
+    let pos = self.index.get(key.to_vec());
+    if pos.is_none() {
+      return Ok(());
+    }
 
-    // appending write to active file
-    let pos = self.append_log_record(&mut record)?;
-    self
-      .reclaim_size
-      .fetch_add(pos.size as usize, Ordering::SeqCst);
+    let old_pos = match self.options.tombstone_sidecar {
+      // the marker names the record the index dropped
+      true => self.delete_to_sidecar(&key)?,
+      false => {
+        // construct LogRecord
+        let mut record = LogRecord {
+          key: log_record_key_with_seq(&key, NON_TXN_SEQ_NO),
+          value: self.tombstone_value(pos),
+          rec_type: LogRecordType::Deleted,
+          timestamp: None,
+        };
 
-    // delete key in index
-    if let Some(old_pos) = self.index.delete(key.to_vec()) {
-      self
-        .reclaim_size
-        .fetch_add(old_pos.size as usize, Ordering::SeqCst);
+        // appending write to active file
+        let pos = self.append_log_record(&mut record)?;
+        self.mark_tombstone(pos);
+
+        // delete key in index
+        self.index.try_delete(key.to_vec())?
+      }
+    };
+    timer.phase("append");
+    self.account_quota(&key, old_pos, None);
+    if let Some(old_pos) = old_pos {
+      self.mark_stale(old_pos);
     }
+    self.clear_expiry(&key);
+    self.mark_changed();
+    self.watchers.notify(&key, WatchOp::Delete, None);
+    self.emit_cdc([(CdcOp::Delete, &key[..], &[][..])]);
+    timer.phase("index");
+    self.finish_slow_op(timer, Some(&key));
     Ok(())
   }
 
@@ -430,238 +949,737 @@ impl Engine {
   /// # Errors
   ///
   /// Returns an error if the key is empty, not found, or if the read operation fails.
+  #[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip_all, fields(key_len = key.len()))
+  )]
   pub fn get(&self, key: Bytes) -> Result<Bytes> {
+    self.check_open()?;
     // if the key is empty then return
     if key.is_empty() {
       return Err(Errors::KeyIsEmpty);
     }
 
     // Retrieves data for the specified key from the in-memory index.
-    let pos = self.index.get(key.to_vec());
-
     // if key not found then return
-    if pos.is_none() {
-      return Err(Errors::KeyNotFound);
+    let mut timer = self.slow_op_timer(SlowOp::Get);
+    if let Some((at, _)) = self.expiry.get(&key) {
+      if at <= unix_millis(SystemTime::now()) {
+        return Err(Errors::KeyNotFound);
+      }
     }
 
     // Retrieves LogRecord from the specified file data.
-    self.get_value_by_position(&pos.unwrap())
+    let (pos, value) =
+      self.read_indexed(&key, |pos| Ok((pos, self.get_value_by_position(&pos))))?;
+    let value = match value {
+      Err(e @ (Errors::InvalidLogRecordCrc | Errors::DataFileNotFound)) => {
+        self.recover_read(&key, pos, e)
+      }
+      res => res,
+    }?;
+    timer.phase("read");
+    self.finish_slow_op(timer, Some(&key));
+    Ok(value)
+  }
+
+  /// Like `get`, also returning where the record of the value lives and the transaction
+  /// which wrote it.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if the key is empty, not found, or if the read operation fails.
+  pub fn get_entry(&self, key: Bytes) -> Result<Entry> {
+    self.check_open()?;
+    if key.is_empty() {
+      return Err(Errors::KeyIsEmpty);
+    }
+
+    if let Some((at, _)) = self.expiry.get(&key) {
+      if at <= unix_millis(SystemTime::now()) {
+        return Err(Errors::KeyNotFound);
+      }
+    }
+    let crc = match self.options.verify_checksums_on_read {
+      true => Some(self.options.crc_impl),
+      false => None,
+    };
+    let (pos, log_record) =
+      self.read_indexed(&key, |pos| Ok((pos, self.read_log_record_with(&pos, crc)?)))?;
+    if let LogRecordType::Deleted = log_record.rec_type {
+      return Err(Errors::KeyNotFound);
+    };
+    self
+      .io_stats
+      .record_read(pos.size as u64, log_record.value.len() as u64);
+
+    let (_, seq_no) = parse_log_record_key(log_record.key)?;
+    Ok(Entry {
+      value: log_record.value.into(),
+      file_id: pos.file_id,
+      offset: pos.offset,
+      size: pos.size,
+      record_type: log_record.rec_type,
+      txn_seq: (seq_no != NON_TXN_SEQ_NO).then_some(seq_no),
+      timestamp: log_record
+        .timestamp
+        .map(|at| UNIX_EPOCH + Duration::from_millis(at)),
+    })
+  }
+
+  /// Atomically adds `delta` to the counter stored at `key` and returns the new value.
+  ///
+  /// Counters are stored as 8-byte little-endian integers, a missing key counts as 0.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if the key is empty, the stored value is not an 8-byte integer,
+  /// or the addition overflows.
+  pub fn incr(&self, key: Bytes, delta: i64) -> Result<i64> {
+    self.check_open()?;
+    if key.is_empty() {
+      return Err(Errors::KeyIsEmpty);
+    }
+
+    // the throttle may sleep, so it goes before the locks
+    let mut timer = self.slow_op_timer(SlowOp::Put);
+    self.throttle_write(key.len() + std::mem::size_of::<i64>(), true)?;
+    timer.phase("throttle");
+
+    // serialize with other counter updates, holders of the key lock and batch commits
+    let _guard = self.lock_key(key.clone());
+    let _gate = self.write_gate.read_recursive();
+    let _lock = self.batch_commit_lock.lock();
+    let current = match self.get(key.clone()) {
+      Ok(value) => {
+        let bytes: [u8; 8] = value
+          .as_ref()
+          .try_into()
+          .map_err(|_| Errors::InvalidCounterValue)?;
+        i64::from_le_bytes(bytes)
+      }
+      Err(Errors::KeyNotFound) => 0,
+      Err(e) => return Err(e),
+    };
+
+    let new_value = current.checked_add(delta).ok_or(Errors::CounterOverflow)?;
+    let value = Bytes::copy_from_slice(&new_value.to_le_bytes());
+    self.put_throttled(key, value, timer)?;
+    Ok(new_value)
+  }
+
+  /// Atomically subtracts `delta` from the counter stored at `key`, see [`Engine::incr`].
+  pub fn decr(&self, key: Bytes, delta: i64) -> Result<i64> {
+    let delta = delta.checked_neg().ok_or(Errors::CounterOverflow)?;
+    self.incr(key, delta)
+  }
+
+  /// Writes `value` to `key` unless the key exists, returns whether it was written.
+  ///
+  /// The check and the write are atomic against other writes of the key, holders of the key
+  /// lock and batch commits.
+  pub fn put_if_absent(&self, key: Bytes, value: Bytes) -> Result<bool> {
+    self.put_if(key, value, false)
+  }
+
+  /// Writes `value` to `key` if the key exists, returns whether it was written. See
+  /// [`Engine::put_if_absent`].
+  pub fn put_if_present(&self, key: Bytes, value: Bytes) -> Result<bool> {
+    self.put_if(key, value, true)
+  }
+
+  fn put_if(&self, key: Bytes, value: Bytes, present: bool) -> Result<bool> {
+    self.check_open()?;
+    if key.is_empty() {
+      return Err(Errors::KeyIsEmpty);
+    }
+
+    // the throttle may sleep, so it goes before the locks
+    let mut timer = self.slow_op_timer(SlowOp::Put);
+    self.throttle_write(key.len() + value.len(), true)?;
+    timer.phase("throttle");
+
+    let _guard = self.lock_key(key.clone());
+    let _gate = self.write_gate.read_recursive();
+    let _lock = self.batch_commit_lock.lock();
+    let now = unix_millis(SystemTime::now());
+    let exists = self.index.get(key.to_vec()).is_some() && !self.expiry.is_expired(&key, now);
+    if exists != present {
+      return Ok(false);
+    }
+    self.put_throttled(key, value, timer)?;
+    Ok(true)
+  }
+
+  /// Rejects a new key once the index reached `Options::index_memory_limit`,
+  /// existing keys can still be overwritten.
+  pub(crate) fn check_index_memory(&self, key: &[u8]) -> Result<()> {
+    let limit = self.options.index_memory_limit;
+    if limit == 0 {
+      return Ok(());
+    }
+    match self.index.memory_usage() {
+      Some(usage) if usage >= limit && self.index.get(key.to_vec()).is_none() => {
+        Err(Errors::IndexMemoryLimitExceeded)
+      }
+      _ => Ok(()),
+    }
+  }
+
+  /// Looks `key` up in the index and reads its record with `read`, again if data files were
+  /// replaced between the lookup and the read, e.g. by a clear or the reload of a reader. A
+  /// read racing with the replacement may hit a removed file or a merged one reusing its id.
+  pub(crate) fn read_indexed<T, F>(&self, key: &[u8], read: F) -> Result<T>
+  where
+    F: Fn(LogRecordPos) -> Result<T>,
+  {
+    loop {
+      let version = self.data_files_version.load(Ordering::SeqCst);
+      if version % 2 == 1 {
+        // wait for the replacement to finish
+        drop(self.position_epoch.read());
+        continue;
+      }
+      let result = (self.index.get(key.to_vec()))
+        .ok_or(Errors::KeyNotFound)
+        .and_then(&read);
+      if self.data_files_version.load(Ordering::SeqCst) == version {
+        return result;
+      }
+    }
   }
 
   /// Retrieves the data by position.
+  ///
+  /// The file id is resolved under the lock of the active file, a rotation moves the file to
+  /// the old files under it, so it is found in either.
   pub(crate) fn get_value_by_position(&self, log_record_pos: &LogRecordPos) -> Result<Bytes> {
+    let crc = match self.options.verify_checksums_on_read {
+      true => Some(self.options.crc_impl),
+      false => None,
+    };
+    // values of memory mapped files are not copied
+    let (rec_type, value) = {
+      let active_file = self.active_data_file.read();
+      match active_file.get_file_id() == log_record_pos.file_id {
+        true => active_file.read_value_with(log_record_pos.offset, crc)?,
+        false => self
+          .old_data_files
+          .read()
+          .get(&log_record_pos.file_id)
+          .ok_or(Errors::DataFileNotFound)?
+          .read_value_with(log_record_pos.offset, crc)?,
+      }
+    };
+
+    // Determines the type of the log record, large values are kept in the blob file of the
+    // data file.
+    let value = match rec_type {
+      LogRecordType::Deleted => return Err(Errors::KeyNotFound),
+      LogRecordType::Blob => self.read_blob(log_record_pos.file_id, &value, crc)?,
+      _ => value,
+    };
+
+    // return corresponding value
+    self
+      .io_stats
+      .record_read(log_record_pos.size as u64, value.len() as u64);
+    Ok(value)
+  }
+
+  /// Reads the log record at a position of any data file, a blob record is read as a normal
+  /// record holding its value.
+  pub(crate) fn read_log_record_at(&self, log_record_pos: &LogRecordPos) -> Result<LogRecord> {
+    self.read_log_record_with(log_record_pos, Some(self.options.crc_impl))
+  }
+
+  // reads the log record at a position, verifying its crc32 with `crc` unless it is None
+  fn read_log_record_with(
+    &self,
+    log_record_pos: &LogRecordPos,
+    crc: Option<CrcImpl>,
+  ) -> Result<LogRecord> {
     // Retrieves LogRecord from the specified file data.
-    let active_file = self.active_data_file.read();
-    let oldre_files = self.old_data_files.read();
-    let log_record = match active_file.get_file_id() == log_record_pos.file_id {
-      true => active_file.read_log_record(log_record_pos.offset)?.record,
-      false => {
-        let data_file = oldre_files.get(&log_record_pos.file_id);
-        if data_file.is_none() {
+    let mut record = {
+      let active_file = self.active_data_file.read();
+      let oldre_files = self.old_data_files.read();
+      match active_file.get_file_id() == log_record_pos.file_id {
+        true => {
+          active_file
+            .read_log_record_with(log_record_pos.offset, crc)?
+            .record
+        }
+        false => {
           // Returns the error if the corresponding data file is not found.
-          return Err(Errors::DataFileNotFound);
+          let data_file = oldre_files
+            .get(&log_record_pos.file_id)
+            .ok_or(Errors::DataFileNotFound)?;
+          data_file
+            .read_log_record_with(log_record_pos.offset, crc)?
+            .record
         }
-        data_file
-          .unwrap()
-          .read_log_record(log_record_pos.offset)?
-          .record
       }
     };
+    if record.rec_type == LogRecordType::Blob {
+      record.value = self
+        .read_blob(log_record_pos.file_id, &record.value, crc)?
+        .to_vec();
+      record.rec_type = LogRecordType::Normal;
+    }
+    Ok(record)
+  }
 
-    // Determines the type of the log record.
-    if let LogRecordType::Deleted = log_record.rec_type {
-      return Err(Errors::KeyNotFound);
-    };
+  /// Whether `close` was called, the engine rejects operations with `Errors::EngineClosed`
+  /// from then on.
+  pub fn is_closed(&self) -> bool {
+    self.closed.load(Ordering::SeqCst)
+  }
 
-    // return corresponding value
-    Ok(log_record.value.into())
+  /// Id of the current state of the keys, it grows with every put, delete, expire, batch
+  /// commit, bulk load and clear, and with every refresh of a reader finding new records. A
+  /// cache built while the id was `n` is current as long as the id is still `n`. Expirations
+  /// taking effect are not changes, the deletes purging them are. Ids start at 0 on open.
+  pub fn change_id(&self) -> u64 {
+    self.change_id.load(Ordering::SeqCst)
+  }
+
+  // called after the index took a change
+  pub(crate) fn mark_changed(&self) {
+    self.change_id.fetch_add(1, Ordering::SeqCst);
+  }
+
+  pub(crate) fn check_open(&self) -> Result<()> {
+    match self.is_closed() {
+      true => Err(Errors::EngineClosed),
+      false => Ok(()),
+    }
   }
 
   /// append write data to current active data file
   pub(crate) fn append_log_record(&self, log_record: &mut LogRecord) -> Result<LogRecordPos> {
-    let dir_path = &self.options.dir_path;
+    if self.reader.is_some() {
+      return Err(Errors::ReadOnlyEngine);
+    }
 
-    // encode input data
-    let enc_record = log_record.encode();
+    // encode input data, a large value is written to a blob file once the data file is known
+    let blob_record = self.blob_pointer_record(log_record);
+    let mut enc_record = self.encode_log_record(blob_record.as_ref().unwrap_or(log_record))?;
     let record_len = enc_record.len() as u64;
 
     // obtain current active file
     let mut active_file = self.active_data_file.write();
+    self.check_open()?;
     if active_file.get_write_off() + record_len > self.options.data_file_size {
-      // active file persistence
-      active_file.sync()?;
-
-      let current_fid = active_file.get_file_id();
-
-      // insert old data file to hash map
-      let mut old_files = self.old_data_files.write();
-      let old_file = DataFile::new(dir_path, current_fid, IOManagerType::StandardFileIO)?;
-      old_files.insert(current_fid, old_file);
+      self.rotate_active_file(&mut active_file)?;
+    }
 
-      // open a new active data file
-      let new_file = DataFile::new(dir_path, current_fid + 1, IOManagerType::StandardFileIO)?;
-      *active_file = new_file;
+    let mut blob_len = 0;
+    if blob_record.is_some() {
+      (enc_record, blob_len) = self.write_blob(active_file.get_file_id(), log_record)?;
     }
 
     // append write to active file
     let write_off = active_file.get_write_off();
     active_file.write(&enc_record)?;
+    self
+      .io_stats
+      .record_write(record_len + blob_len as u64, ingested_bytes(log_record));
 
-    let previous = self
-      .bytes_write
-      .fetch_add(enc_record.len(), Ordering::SeqCst);
+    self.sync_appended(&active_file, enc_record.len() + blob_len)?;
+
+    // construct log record return info
+    let pos = LogRecordPos {
+      file_id: active_file.get_file_id(),
+      offset: write_off,
+      size: enc_record.len() as u32,
+    };
+    let rec_type = blob_record.map_or(log_record.rec_type, |record| record.rec_type);
+    self.track_keydir_record(&log_record.key, rec_type, pos);
+    Ok(pos)
+  }
+
+  /// append several records to the active file, the records fitting into the same file are
+  /// written with a single vectored write
+  pub(crate) fn append_log_records(&self, log_records: &[LogRecord]) -> Result<Vec<LogRecordPos>> {
+    if self.reader.is_some() {
+      return Err(Errors::ReadOnlyEngine);
+    }
+    let blob_records = log_records
+      .iter()
+      .map(|log_record| self.blob_pointer_record(log_record))
+      .collect::<Vec<_>>();
+    let mut enc_records = log_records
+      .iter()
+      .zip(blob_records.iter())
+      .map(|(log_record, blob_record)| {
+        self.encode_log_record(blob_record.as_ref().unwrap_or(log_record))
+      })
+      .collect::<Result<Vec<_>>>()?;
+
+    let mut active_file = self.active_data_file.write();
+    self.check_open()?;
+    let mut positions = Vec::with_capacity(enc_records.len());
+    let mut bytes = 0;
+    while positions.len() < enc_records.len() {
+      let start = positions.len();
+      if active_file.get_write_off() + enc_records[start].len() as u64 > self.options.data_file_size
+      {
+        self.rotate_active_file(&mut active_file)?;
+      }
+
+      // records up to the file size, at least one like a single append
+      let mut write_off = active_file.get_write_off();
+      for enc_record in enc_records[start..].iter() {
+        let record_len = enc_record.len() as u64;
+        if positions.len() > start && write_off + record_len > self.options.data_file_size {
+          break;
+        }
+        positions.push(LogRecordPos {
+          file_id: active_file.get_file_id(),
+          offset: write_off,
+          size: record_len as u32,
+        });
+        write_off += record_len;
+      }
+      let end = positions.len();
+      let mut blob_lens = vec![0; end - start];
+      for i in start..end {
+        if blob_records[i].is_some() {
+          let file_id = active_file.get_file_id();
+          (enc_records[i], blob_lens[i - start]) = self.write_blob(file_id, &log_records[i])?;
+        }
+      }
+      active_file.write_vectored(&enc_records[start..end])?;
+
+      // the key directory of the file is taken when it is rotated
+      for i in start..end {
+        let log_record = &log_records[i];
+        let record_len = enc_records[i].len() + blob_lens[i - start];
+        self
+          .io_stats
+          .record_write(record_len as u64, ingested_bytes(log_record));
+        let rec_type = blob_records[i]
+          .as_ref()
+          .map_or(log_record.rec_type, |record| record.rec_type);
+        self.track_keydir_record(&log_record.key, rec_type, positions[i]);
+        bytes += record_len;
+      }
+    }
+
+    self.sync_appended(&active_file, bytes)?;
+    Ok(positions)
+  }
+
+  // counts the bytes appended to the active file and syncs it if the options ask for it
+  fn sync_appended(&self, active_file: &DataFile, bytes: usize) -> Result<()> {
+    let previous = self.bytes_write.fetch_add(bytes, Ordering::SeqCst);
+    self.sync_tuner.appended(previous + bytes);
 
     // options to sync or not
     let mut need_sync = self.options.sync_writes;
-    if !need_sync
-      && self.options.bytes_per_sync > 0
-      && previous + enc_record.len() >= self.options.bytes_per_sync
-    {
+    let bytes_per_sync = self.sync_tuner.bytes_per_sync();
+    if !need_sync && bytes_per_sync > 0 && previous + bytes >= bytes_per_sync {
       need_sync = true;
       self.bytes_write.store(0, Ordering::SeqCst);
     }
 
     if need_sync {
+      // the blobs are durable before the records pointing to them
+      self.sync_active_blob()?;
       active_file.sync()?;
 
       self.bytes_write.store(0, Ordering::SeqCst);
+      self.sync_tuner.synced(previous + bytes);
     }
+    Ok(())
+  }
 
-    // construct log record return info
-    Ok(LogRecordPos {
-      file_id: active_file.get_file_id(),
-      offset: write_off,
-      size: enc_record.len() as u32,
-    })
+  /// encode a record for the data files, encrypted when `encryption_key` is set. With
+  /// `store_timestamps` a record without timestamp is stamped with the current time, records
+  /// rewritten by merges keep theirs either way
+  pub(crate) fn encode_log_record(&self, log_record: &LogRecord) -> Result<Vec<u8>> {
+    let timestamp = (log_record.timestamp).or_else(|| {
+      self
+        .options
+        .store_timestamps
+        .then(|| unix_millis(SystemTime::now()))
+    });
+    match &self.cipher {
+      Some(cipher) => Ok(cipher.seal(log_record)?.encode_at(timestamp)),
+      None => Ok(log_record.encode_at(timestamp)),
+    }
+  }
+
+  /// seal the active file and continue in a new one
+  #[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+      level = "debug",
+      skip_all,
+      fields(file_id = active_file.get_file_id(), bytes = active_file.get_write_off())
+    )
+  )]
+  pub(crate) fn rotate_active_file(&self, active_file: &mut DataFile) -> Result<()> {
+    // active file persistence, append footer since it becomes immutable
+    self.seal_active_file(active_file)?;
+
+    let current_fid = active_file.get_file_id();
+
+    // insert old data file to hash map
+    let mut old_files = self.old_data_files.write();
+    let old_file = match self.options.storage_mode {
+      StorageMode::Disk => Some(self.open_old_data_file(current_fid)?),
+      StorageMode::Memory => None,
+    };
+
+    // open a new active data file, in memory the content only lives in the sealed handle
+    let sealed_file = std::mem::replace(active_file, self.new_active_data_file(current_fid + 1)?);
+    old_files.insert(current_fid, old_file.unwrap_or(sealed_file));
+    self.write_garbage_map(current_fid + 1, 0)
   }
 
   /// load memory index from data files
   /// traverse all data files, and process each log record
-  fn load_index_from_data_files(&self) -> Result<usize> {
+  fn load_index_from_data_files(
+    &self,
+    hint_loaded: bool,
+    report: &mut OpenReport,
+  ) -> Result<usize> {
+    self.load_index_from_files(&self.file_ids, hint_loaded, report)
+  }
+
+  /// load memory index from the given data files, the last one is the active file,
+  /// merged files are skipped if their index was loaded from the merge hint file
+  pub(crate) fn load_index_from_files(
+    &self,
+    file_ids: &[u32],
+    hint_loaded: bool,
+    report: &mut OpenReport,
+  ) -> Result<usize> {
     let mut current_seq_no = NON_TXN_SEQ_NO;
     // if data_files is empty then return
-    if self.file_ids.is_empty() {
+    if file_ids.is_empty() {
       return Ok(current_seq_no);
     }
 
     // get latest unmerged file id
     let mut has_merged = false;
     let mut non_merge_fid = 0;
-    let merge_fin_file = self.options.dir_path.join(MERGE_FINISHED_FILE_NAME);
-    if merge_fin_file.is_file() {
-      let merge_file = DataFile::new_merge_fin_file(&self.options.dir_path)?;
-      let merge_fin_record = merge_file.read_log_record(0)?;
-      let v = String::from_utf8(merge_fin_record.record.value).unwrap();
-
-      non_merge_fid = v.parse::<u32>().unwrap();
-      has_merged = true;
+    if let Some(file_id) = read_non_merge_file_id(&self.options.dir_path)? {
+      non_merge_fid = file_id;
+      has_merged = hint_loaded;
     }
 
     // temporary store data related to txn
     let mut transaction_records = HashMap::new();
 
+    let sealed_files = match self.options.startup_manifest {
+      true => load_manifest(&self.options.dir_path)?.0,
+      false => HashMap::new(),
+    };
+
     let active_file = self.active_data_file.read();
     let old_files = self.old_data_files.read();
+    // sealed files decoded ahead by the startup threads
+    let mut decoded = HashMap::new();
 
     // traverse each file_id, retrieve data file and load its data
-    for (i, file_id) in self.file_ids.iter().enumerate() {
-      // if file_id is less than non_merge_fid, then skip
+    for (i, file_id) in file_ids.iter().enumerate() {
+      // if file_id is less than non_merge_fid, index is loaded from hint file, then skip
       if has_merged && *file_id < non_merge_fid {
+        if self.options.verify_file_footer_at_startup {
+          if let Some(data_file) = old_files.get(file_id) {
+            data_file.verify_footer()?;
+          }
+        }
         continue;
       }
 
+      let is_active = *file_id == active_file.get_file_id();
+      let data_file = match is_active {
+        true => &*active_file,
+        false => old_files.get(file_id).ok_or(Errors::DataFileNotFound)?,
+      };
+
+      // sealed files matching the manifest are replayed from their key directory
+      if !is_active && self.options.startup_manifest {
+        if let Some(records) = self.read_keydir(data_file, sealed_files.get(file_id))? {
+          for (log_record, log_record_pos) in records {
+            if log_record.rec_type == LogRecordType::TxnFinished {
+              report.committed_txns += 1;
+            }
+            self.replay_log_record(
+              log_record,
+              log_record_pos,
+              &mut transaction_records,
+              &mut current_seq_no,
+            )?;
+          }
+          continue;
+        }
+      }
+
+      if !is_active && self.options.startup_threads > 1 {
+        if !decoded.contains_key(file_id) {
+          // the next sealed files that are scanned, files with a key directory are not
+          let ahead: Vec<&DataFile> = file_ids[i..]
+            .iter()
+            .filter(|fid| !(has_merged && **fid < non_merge_fid))
+            .filter(|fid| !sealed_files.contains_key(*fid))
+            .filter_map(|fid| old_files.get(fid))
+            .take(self.options.startup_threads)
+            .collect();
+          decoded = decode_data_files(&ahead);
+        }
+        if let Some(records) = decoded.remove(file_id) {
+          for (log_record, log_record_pos) in records {
+            if log_record.rec_type == LogRecordType::TxnFinished {
+              report.committed_txns += 1;
+            }
+            self.replay_log_record(
+              log_record,
+              log_record_pos,
+              &mut transaction_records,
+              &mut current_seq_no,
+            )?;
+          }
+          continue;
+        }
+      }
+
+      // records are read ahead in large chunks instead of two reads per record
+      let mut scanner = data_file.scan();
       let mut offset = 0;
       loop {
         // read data in loop
-        let log_record_res = match *file_id == active_file.get_file_id() {
-          true => active_file.read_log_record(offset),
-          _ => {
-            let data_file = old_files.get(file_id).unwrap();
-            data_file.read_log_record(offset)
+        let (log_record, size) = match scanner.next_record() {
+          Ok((result, record_offset)) => {
+            offset = record_offset;
+            (result.record, result.size)
           }
-        };
-
-        let (mut log_record, size) = match log_record_res {
-          Ok(result) => (result.record, result.size),
           Err(e) => {
             if e == Errors::ReadDataFileEOF {
               break;
             }
+            // a reader may see a record the writer is still appending
+            if is_active && self.reader.is_some() {
+              break;
+            }
+            // IO errors are not repaired, the content may be intact
+            if self.options.repair_on_open && e.io_error().is_none() {
+              match self.repair_on_load(data_file, &mut scanner, is_active, e, report)? {
+                true => continue,
+                false => break,
+              }
+            }
             return Err(e);
           }
         };
 
+        // footer of a sealed file, no data
+        if log_record.rec_type == LogRecordType::FileFooter {
+          offset += size as u64;
+          continue;
+        }
+
         // construct memory index
         let log_record_pos = LogRecordPos {
           file_id: *file_id,
           offset,
           size: size as u32,
         };
-
-        // parse key, obtain actual key and seq_no
-        let (real_key, seq_no) = parse_log_record_key(log_record.key.clone());
-        // non txn log record, update index as usual
-        if seq_no == NON_TXN_SEQ_NO {
-          self.update_index(real_key, log_record.rec_type, log_record_pos)?;
-        } else {
-          // txn log record commit, update index
-          if log_record.rec_type == LogRecordType::TxnFinished {
-            let records: &Vec<TransactionRecord> = transaction_records.get(&seq_no).unwrap();
-            for txn_record in records.iter() {
-              self.update_index(
-                txn_record.record.key.clone(),
-                txn_record.record.rec_type,
-                txn_record.pos,
-              )?;
-            }
-            transaction_records.remove(&seq_no);
-          } else {
-            log_record.key = real_key;
-            transaction_records
-              .entry(seq_no)
-              .or_insert_with(|| Vec::new())
-              .push(TransactionRecord {
-                record: log_record,
-                pos: log_record_pos,
-              });
-          }
+        if is_active {
+          self.track_keydir_record(&log_record.key, log_record.rec_type, log_record_pos);
         }
-
-        // seq_no update
-        if seq_no > current_seq_no {
-          current_seq_no = seq_no;
+        if log_record.rec_type == LogRecordType::TxnFinished {
+          report.committed_txns += 1;
         }
+        self.replay_log_record(
+          log_record,
+          log_record_pos,
+          &mut transaction_records,
+          &mut current_seq_no,
+        )?;
 
         // offset move, read next log record
         offset += size as u64;
       }
 
       // set active file offset
-      if i == self.file_ids.len() - 1 {
+      if i == file_ids.len() - 1 {
         active_file.set_write_off(offset);
       }
     }
+
+    // deletes kept in tombstone sidecars are not part of the scanned records
+    self.apply_sidecar_tombstones(file_ids)?;
+
+    // a reader applies the rest of these transactions on a later refresh
+    match &self.reader {
+      Some(reader) => reader.keep_txn_records(transaction_records),
+      None => {
+        // only the prepared transactions are left without a commit record
+        self.hold_orphaned_txns(&mut transaction_records);
+        report.prepared_txns += transaction_records.len();
+        self.hold_recovered_txns(transaction_records)
+      }
+    }
     Ok(current_seq_no)
   }
 
-  /// load seq_no under B+Tree index type
-  fn load_seq_no(&self) -> (bool, usize) {
-    let file_name = self.options.dir_path.join(SEQ_NO_FILE_NAME);
-    if !file_name.is_file() {
-      return (false, 0);
+  /// apply a log record read at startup to the index, txn records are held back until
+  /// their txn finished record is seen
+  pub(crate) fn replay_log_record(
+    &self,
+    mut log_record: LogRecord,
+    log_record_pos: LogRecordPos,
+    transaction_records: &mut HashMap<usize, Vec<TransactionRecord>>,
+    current_seq_no: &mut usize,
+  ) -> Result<()> {
+    // parse key, obtain actual key and seq_no
+    let (real_key, seq_no) = parse_log_record_key(log_record.key.clone())?;
+    // non txn log record, update index as usual
+    if seq_no == NON_TXN_SEQ_NO {
+      match log_record.rec_type {
+        LogRecordType::Expire => self.replay_expire(real_key, &log_record.value, log_record_pos)?,
+        rec_type => self.update_index(real_key, rec_type, log_record_pos)?,
+      }
+    } else {
+      // txn log record commit, update index
+      if log_record.rec_type == LogRecordType::TxnFinished {
+        // the records may be missing if they were rewritten by a merge
+        let records: Vec<TransactionRecord> =
+          transaction_records.remove(&seq_no).unwrap_or_default();
+        for txn_record in records {
+          self.update_index(
+            txn_record.record.key,
+            txn_record.record.rec_type,
+            txn_record.pos,
+          )?;
+        }
+      } else if log_record.rec_type == LogRecordType::TxnAborted {
+        for txn_record in transaction_records.remove(&seq_no).unwrap_or_default() {
+          self.mark_stale(txn_record.pos);
+        }
+      } else {
+        // records are applied by their position, buffering their values only costs memory
+        log_record.key = real_key;
+        log_record.value = Vec::new();
+        transaction_records
+          .entry(seq_no)
+          .or_insert_with(|| Vec::new())
+          .push(TransactionRecord {
+            record: log_record,
+            pos: log_record_pos,
+          });
+      }
     }
-    let seq_no_file = DataFile::new_seq_no_file(&self.options.dir_path).unwrap();
-    let record = match seq_no_file.read_log_record(0) {
-      Ok(res) => res.record,
-      Err(e) => panic!("failed to read seq_no: {e}"),
-    };
-    let v = String::from_utf8(record.value).unwrap();
-    let seq_no = v.parse::<usize>().unwrap();
-
-    // remove seq_no file, avoiding repeated writing
-    fs::remove_file(file_name).unwrap();
 
-    (true, seq_no)
+    // seq_no update
+    if seq_no > *current_seq_no {
+      *current_seq_no = seq_no;
+    }
+    Ok(())
   }
 
   /// Updates in-memory index upon loading
@@ -671,43 +1689,84 @@ impl Engine {
   /// it increments a counter for reclaimed space size with the old position's size.
   /// For a deleted record, it removes the key from the index and updates the reclaimed space size counter accordingly.
   ///
-  fn update_index(&self, key: Vec<u8>, rec_type: LogRecordType, pos: LogRecordPos) -> Result<()> {
-    if rec_type == LogRecordType::Normal {
-      if let Some(old_pos) = self.index.put(key.clone(), pos) {
-        // Increments the reclaimed space size counter by the size of the old position.
-        self
-          .reclaim_size
-          .fetch_add(old_pos.size as usize, Ordering::SeqCst);
+  pub(crate) fn update_index(
+    &self,
+    key: Vec<u8>,
+    rec_type: LogRecordType,
+    pos: LogRecordPos,
+  ) -> Result<()> {
+    // any later write of a key clears its expiration
+    self.clear_expiry(&key);
+
+    if matches!(rec_type, LogRecordType::Normal | LogRecordType::Blob) {
+      if let Some(old_pos) = self.index.try_put(key.clone(), pos)? {
+        // the old record is reclaimable space now
+        self.mark_stale(old_pos);
       }
     }
 
     if rec_type == LogRecordType::Deleted {
-      // Starts with the current record's size for the reclaimed space.
-      let mut size = pos.size;
+      // the delete record itself holds no data
+      self.mark_tombstone(pos);
       // Attempts to remove the key from the index. If the key exists, returns the old position.
-      if let Some(old_pos) = self.index.delete(key) {
-        // Adds the size of the old position to the reclaimed space size.
-        size += old_pos.size;
+      if let Some(old_pos) = self.index.try_delete(key)? {
+        self.mark_stale(old_pos);
       }
-      // Updates the reclaimed space size counter.
-      self.reclaim_size.fetch_add(size as usize, Ordering::SeqCst);
     }
     Ok(())
   }
 
   /// reset io_manager type for all data files
-  fn reset_io_type(&self) {
+  fn reset_io_type(&self) -> Result<()> {
     let mut active_file = self.active_data_file.write();
-    active_file.set_io_manager(&self.options.dir_path, IOManagerType::StandardFileIO);
+    let io_type = self.options.file_io_type();
+    let wrapper = self.options.io_manager_wrapper.as_ref();
+    let active_file_dir = self.data_file_dir(active_file.get_file_id());
+    active_file.set_io_manager(active_file_dir, io_type, wrapper)?;
+    let sealed_io_type = self.options.sealed_file_io_type();
     let mut old_files = self.old_data_files.write();
-    for (_, file) in old_files.iter_mut() {
-      file.set_io_manager(&self.options.dir_path, IOManagerType::StandardFileIO);
+    for (file_id, file) in old_files.iter_mut() {
+      match self.file_pool {
+        Some(_) => *file = self.open_old_data_file(*file_id)?,
+        None => file.set_io_manager(self.data_file_dir(*file_id), sealed_io_type, wrapper)?,
+      }
     }
+    Ok(())
+  }
+
+  /// Opens an immutable data file, through the file pool when `max_open_files` is set.
+  pub(crate) fn open_old_data_file(&self, file_id: u32) -> Result<DataFile> {
+    let file_dir = self.data_file_dir(file_id);
+    let data_file = match &self.file_pool {
+      Some(pool) => DataFile::new_pooled(&file_dir, file_id, pool)?,
+      None => DataFile::new(&file_dir, file_id, self.options.sealed_file_io_type())?,
+    };
+    Ok(
+      data_file
+        .with_io_wrapper(&file_dir, self.options.io_manager_wrapper.as_ref())
+        .with_cipher(self.cipher.as_ref()),
+    )
+  }
+
+  /// Creates the next active data file.
+  pub(crate) fn new_active_data_file(&self, file_id: u32) -> Result<DataFile> {
+    let file_dir = create_data_file_dir(&self.options, file_id)?;
+    let wrapper = self.options.io_manager_wrapper.as_ref();
+    let mut data_file = DataFile::new(&file_dir, file_id, self.options.file_io_type())?
+      .with_io_wrapper(&file_dir, wrapper)
+      .with_cipher(self.cipher.as_ref());
+    if self.options.preallocate_data_file {
+      data_file.preallocate(&file_dir, self.options.data_file_size, wrapper)?;
+    }
+    Ok(data_file)
   }
 }
 
 impl Drop for Engine {
   fn drop(&mut self) {
+    if self.is_closed() {
+      return;
+    }
     if let Err(e) = self.close() {
       error!("error while closing engine {e}");
     }
@@ -720,83 +1779,150 @@ impl Drop for Engine {
 /// # Arguments
 ///
 /// * `dir_path` - Path to the database directory
+/// * `files_per_subdir` - Layout of the data files, see `Options::files_per_subdir`
+/// * `io_type` - IO type of the last data file, `sealed_io_type` the one of the others
 ///
 /// # Errors
 ///
 /// Returns an error if the directory cannot be read or if data files are corrupted
-fn load_data_files<P>(dir_path: P, use_mmap: bool) -> Result<Vec<DataFile>>
+pub(crate) fn load_data_files<P>(
+  dir_path: P,
+  files_per_subdir: u32,
+  startup_io_type: Option<IOManagerType>,
+  io_type: IOManagerType,
+  sealed_io_type: IOManagerType,
+  file_pool: Option<&Arc<FilePool>>,
+) -> Result<Vec<DataFile>>
 where
   P: AsRef<Path>,
 {
-  // read database directory
-  let dir = fs::read_dir(&dir_path);
-  if dir.is_err() {
-    return Err(Errors::FailedToReadDatabaseDir);
-  }
-
-  let mut file_ids: Vec<u32> = Vec::new();
+  let file_ids = list_data_file_ids(&dir_path)?;
   let mut data_files: Vec<DataFile> = Vec::new();
 
-  for file in dir.unwrap().flatten() {
-    // Retrieve file name
-    let file_os_str = file.file_name();
-    let file_name = file_os_str.to_str().unwrap();
-
-    // determine if file name ends up with .data
-    if file_name.ends_with(DATA_FILE_NAME_SUFFIX) {
-      let splited_names: Vec<&str> = file_name.split('.').collect();
-      let file_id = match splited_names[0].parse::<u32>() {
-        Ok(fid) => fid,
-        Err(_) => {
-          return Err(Errors::DatabaseDirectoryCorrupted);
-        }
-      };
-
-      file_ids.push(file_id);
-    }
-  }
-
-  // if data file is empty then return
-  if file_ids.is_empty() {
-    return Ok(data_files);
-  }
-
-  // sort file_ids, loading from small to large
-  file_ids.sort();
-
   // traverse file_ids, sequentially loading data files
+  let active_file_id = file_ids.last().copied();
   for file_id in file_ids.iter() {
-    let mut io_type = IOManagerType::StandardFileIO;
-    if use_mmap {
-      io_type = IOManagerType::MemoryMap;
-    }
-    let data_file = DataFile::new(&dir_path, *file_id, io_type)?;
+    let file_dir = get_data_file_dir(&dir_path, *file_id, files_per_subdir);
+    let io_type = match startup_io_type {
+      Some(startup_io_type) => startup_io_type,
+      None if Some(*file_id) == active_file_id => io_type,
+      None => sealed_io_type,
+    };
+    let data_file = match file_pool {
+      // old files don't hold a handle each, the active file stays writable
+      Some(pool) if startup_io_type.is_none() && Some(*file_id) != active_file_id => {
+        DataFile::new_pooled(&file_dir, *file_id, pool)?
+      }
+      _ => DataFile::new(&file_dir, *file_id, io_type)?,
+    };
     data_files.push(data_file);
   }
   Ok(data_files)
 }
 
-///
-///
-/// # Arguments
-///
-/// * `opts` - The options to validate
-///
-/// # Returns
-///
-fn check_options(opts: &Options) -> Option<Errors> {
-  let dir_path = opts.dir_path.to_str();
-  if dir_path.is_none() || dir_path.unwrap().is_empty() {
-    return Some(Errors::DirPathIsEmpty);
+/// Decodes the records of sealed data files, one thread per file. Only keys and positions are
+/// kept, besides the values of expirations which are replayed from them. Files failing to
+/// decode are left out, the opening thread scans them again to report or repair the error.
+fn decode_data_files(data_files: &[&DataFile]) -> HashMap<u32, Vec<(LogRecord, LogRecordPos)>> {
+  thread::scope(|scope| {
+    let handles: Vec<_> = data_files
+      .iter()
+      .map(|data_file| scope.spawn(|| (data_file.get_file_id(), decode_data_file(data_file))))
+      .collect();
+    handles
+      .into_iter()
+      .filter_map(|handle| match handle.join() {
+        Ok((file_id, Ok(records))) => Some((file_id, records)),
+        _ => None,
+      })
+      .collect()
+  })
+}
+
+fn decode_data_file(data_file: &DataFile) -> Result<Vec<(LogRecord, LogRecordPos)>> {
+  let mut records = Vec::new();
+  let mut scanner = data_file.scan();
+  loop {
+    let (result, offset) = match scanner.next_record() {
+      Ok(next) => next,
+      Err(Errors::ReadDataFileEOF) => return Ok(records),
+      Err(e) => return Err(e),
+    };
+    let mut record = result.record;
+    match record.rec_type {
+      LogRecordType::FileFooter => continue,
+      LogRecordType::Expire => {}
+      _ => record.value = Vec::new(),
+    }
+    let log_record_pos = LogRecordPos {
+      file_id: data_file.get_file_id(),
+      offset,
+      size: result.size as u32,
+    };
+    records.push((record, log_record_pos));
   }
+}
+
+/// Creates the subdirectory of a new data file if the layout has one, returns the directory.
+fn create_data_file_dir(options: &Options, file_id: u32) -> Result<PathBuf> {
+  let file_dir = get_data_file_dir(&options.dir_path, file_id, options.files_per_subdir);
+  if options.storage_mode == StorageMode::Memory {
+    return Ok(file_dir);
+  }
+  fs::create_dir_all(&file_dir).map_err(|e| {
+    error!("failed to create data file dir {}: {e}", file_dir.display());
+    Errors::FailedToCreateDatabaseDir
+  })?;
+  Ok(file_dir)
+}
+
+/// Lists the ids of the data files in the database directory and its numbered
+/// subdirectories, from small to large.
+pub(crate) fn list_data_file_ids<P: AsRef<Path>>(dir_path: P) -> Result<Vec<u32>> {
+  let mut file_ids: Vec<u32> = Vec::new();
+  for data_dir in data_file_dirs(dir_path.as_ref())? {
+    let dir = fs::read_dir(&data_dir).map_err(|_| Errors::FailedToReadDatabaseDir)?;
+    for file in dir.flatten() {
+      // Retrieve file name, files with non utf-8 names are not ours
+      let file_os_str = file.file_name();
+      let Some(file_name) = file_os_str.to_str() else {
+        continue;
+      };
+
+      // determine if file name ends up with .data
+      if file_name.ends_with(DATA_FILE_NAME_SUFFIX) {
+        let splited_names: Vec<&str> = file_name.split('.').collect();
+        let file_id = match splited_names[0].parse::<u32>() {
+          Ok(fid) => fid,
+          Err(_) => {
+            return Err(Errors::DatabaseDirectoryCorrupted);
+          }
+        };
 
-  if opts.data_file_size == 0 {
-    return Some(Errors::DataFileSizeTooSmall);
+        file_ids.push(file_id);
+      }
+    }
   }
 
-  if opts.file_merge_threshold < 0f32 || opts.file_merge_threshold > 1f32 {
-    return Some(Errors::InvalidMergeThreshold);
+  // sort file_ids, loading from small to large
+  file_ids.sort();
+  Ok(file_ids)
+}
+
+/// Reads the first file id not covered by the merge moved into the directory, if any.
+pub(crate) fn read_non_merge_file_id(dir_path: &Path) -> Result<Option<u32>> {
+  if !dir_path.join(MERGE_FINISHED_FILE_NAME).is_file() {
+    return Ok(None);
   }
+  let merge_file = DataFile::new_merge_fin_file(dir_path)?;
+  let merge_fin_record = merge_file.read_log_record(0)?;
+  parse_record_value(merge_fin_record.record.value).map(Some)
+}
 
-  None
+/// Parses the value of a bookkeeping record (merge finished, seq_no) written as a decimal string.
+pub(crate) fn parse_record_value<T: std::str::FromStr>(value: Vec<u8>) -> Result<T> {
+  String::from_utf8(value)
+    .ok()
+    .and_then(|v| v.parse::<T>().ok())
+    .ok_or(Errors::DatabaseDirectoryCorrupted)
 }
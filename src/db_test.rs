@@ -1,14 +1,31 @@
-use std::{fs, path::PathBuf};
+use std::{
+  fs,
+  path::PathBuf,
+  sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc,
+  },
+  time::{Duration, SystemTime},
+};
 
 use bytes::Bytes;
 
 use crate::{
+  data::{
+    data_file::{
+      get_data_file_name, get_keydir_file_name, get_tombstone_file_name, DataFile,
+      CLEAR_MARKER_FILE_NAME, CLOSE_HINT_FILE_NAME, SEQ_NO_FILE_NAME,
+    },
+    log_record::{LogRecord, LogRecordType},
+  },
   db::Engine,
   errors::Errors,
-  option::{self, Options},
+  index::{btree::BTree, IndexIterator, Indexer, LogRecordPos},
+  option::{self, CustomIndexer, IndexType, KeyComparator, Options, StartupIo, StorageMode},
   util::rand_kv::{get_test_key, get_test_value},
 };
 
+#[allow(clippy::field_reassign_with_default, clippy::len_zero)]
 #[test]
 fn test_engine_put() {
   let mut opt = Options::default();
@@ -62,6 +79,7 @@ fn test_engine_put() {
   std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
 }
 
+#[allow(clippy::field_reassign_with_default, clippy::len_zero)]
 #[test]
 fn test_engine_get() {
   let mut opt = Options::default();
@@ -122,6 +140,7 @@ fn test_engine_get() {
   std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
 }
 
+#[allow(clippy::field_reassign_with_default)]
 #[test]
 fn test_engine_delete() {
   let mut opt = Options::default();
@@ -167,6 +186,7 @@ fn test_engine_delete() {
   std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
 }
 
+#[allow(clippy::field_reassign_with_default)]
 #[test]
 fn test_engine_sync() {
   let mut opt = Options::default();
@@ -184,6 +204,50 @@ fn test_engine_sync() {
   std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
 }
 
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_flush_and_rotate() {
+  let mut opt = Options::default();
+  opt.dir_path = PathBuf::from("/tmp/flash-kv-flush-and-rotate");
+  opt.data_file_size = 64 * 1024 * 1024; // 64MB
+  let engine = Engine::open(opt.clone()).expect("fail to open engine");
+
+  // nothing to seal in an empty active file
+  assert_eq!(None, engine.flush_and_rotate().unwrap());
+
+  engine.put(get_test_key(1), get_test_value(1)).unwrap();
+  assert_eq!(Some(0), engine.flush_and_rotate().unwrap());
+  engine.put(get_test_key(2), get_test_value(2)).unwrap();
+  assert_eq!(Some(1), engine.flush_and_rotate().unwrap());
+  assert_eq!(None, engine.flush_and_rotate().unwrap());
+
+  let file_ids: Vec<_> = engine.data_files().iter().map(|f| f.file_id).collect();
+  assert_eq!(vec![0, 1, 2], file_ids);
+  assert_eq!(get_test_value(1), engine.get(get_test_key(1)).unwrap());
+
+  engine.put(get_test_key(3), get_test_value(3)).unwrap();
+  engine.close().unwrap();
+  drop(engine);
+
+  // the rotated files carry a valid footer
+  let mut verify_opt = opt.clone();
+  verify_opt.verify_file_footer_at_startup = true;
+  let engine = Engine::open(verify_opt).expect("fail to reopen engine");
+  for i in 1..=3 {
+    assert_eq!(get_test_value(i), engine.get(get_test_key(i)).unwrap());
+  }
+  drop(engine);
+
+  let mut reader_opt = opt.clone();
+  reader_opt.read_only = true;
+  let reader = Engine::open(reader_opt).expect("fail to open reader");
+  assert_eq!(Err(Errors::ReadOnlyEngine), reader.flush_and_rotate());
+  drop(reader);
+
+  std::fs::remove_dir_all(opt.dir_path).expect("failed to remove dir");
+}
+
+#[allow(clippy::field_reassign_with_default)]
 #[test]
 fn test_engine_close() {
   let mut opt = Options::default();
@@ -196,11 +260,32 @@ fn test_engine_close() {
 
   let close_res = engine.close();
   assert!(close_res.is_ok());
+  assert!(engine.is_closed());
+
+  // the closed engine rejects operations, closing again rewrites nothing
+  let seq_no_file = opt.dir_path.join(SEQ_NO_FILE_NAME);
+  let seq_no_size = fs::metadata(&seq_no_file).unwrap().len();
+  assert_eq!(
+    Err(Errors::EngineClosed),
+    engine.put(get_test_key(12), get_test_value(12))
+  );
+  assert_eq!(Err(Errors::EngineClosed), engine.get(get_test_key(11)));
+  assert_eq!(Err(Errors::EngineClosed), engine.delete(get_test_key(11)));
+  assert_eq!(Err(Errors::EngineClosed), engine.sync());
+  assert!(engine.close().is_ok());
+  drop(engine);
+  assert_eq!(seq_no_size, fs::metadata(&seq_no_file).unwrap().len());
+
+  let engine = Engine::open(opt.clone()).expect("fail to open engine");
+  assert!(!engine.is_closed());
+  assert!(engine.get(get_test_key(11)).is_ok());
+  drop(engine);
 
   // delete tested files
   std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
 }
 
+#[allow(clippy::field_reassign_with_default)]
 #[test]
 fn test_engine_filelock() {
   // let mut opt = Options::default();
@@ -234,6 +319,7 @@ fn test_engine_filelock() {
   std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
 }
 
+#[allow(clippy::field_reassign_with_default)]
 #[test]
 fn test_engine_stat() {
   let mut opts = option::Options::default();
@@ -259,6 +345,7 @@ fn test_engine_stat() {
   fs::remove_dir_all(opts.clone().dir_path).unwrap();
 }
 
+#[allow(clippy::field_reassign_with_default)]
 #[test]
 fn test_engine_backup() {
   let mut opts = option::Options::default();
@@ -283,3 +370,2199 @@ fn test_engine_backup() {
   fs::remove_dir_all(opts.clone().dir_path).unwrap();
   fs::remove_dir_all(backup_dir.clone()).unwrap();
 }
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_put_if() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  let engine = std::sync::Arc::new(Engine::open(opts).expect("fail to open engine"));
+  let key = get_test_key(1);
+
+  assert!(!engine
+    .put_if_present(key.clone(), get_test_value(1))
+    .unwrap());
+  assert_eq!(Errors::KeyNotFound, engine.get(key.clone()).unwrap_err());
+  assert!(engine
+    .put_if_absent(key.clone(), get_test_value(1))
+    .unwrap());
+  assert!(!engine
+    .put_if_absent(key.clone(), get_test_value(2))
+    .unwrap());
+  assert_eq!(get_test_value(1), engine.get(key.clone()).unwrap());
+  assert!(engine
+    .put_if_present(key.clone(), get_test_value(3))
+    .unwrap());
+  assert_eq!(get_test_value(3), engine.get(key.clone()).unwrap());
+
+  // an expired key is absent
+  engine
+    .expire(key.clone(), SystemTime::now() + Duration::from_millis(20))
+    .unwrap();
+  std::thread::sleep(Duration::from_millis(40));
+  assert!(!engine
+    .put_if_present(key.clone(), get_test_value(4))
+    .unwrap());
+  assert!(engine
+    .put_if_absent(key.clone(), get_test_value(5))
+    .unwrap());
+  assert_eq!(get_test_value(5), engine.get(key.clone()).unwrap());
+
+  // exactly one of the concurrent initializations wins
+  let handles: Vec<_> = (0..8)
+    .map(|i| {
+      let engine = engine.clone();
+      std::thread::spawn(move || {
+        engine
+          .put_if_absent(get_test_key(2), get_test_value(i))
+          .unwrap()
+      })
+    })
+    .collect();
+  let written = handles
+    .into_iter()
+    .map(|handle| handle.join().unwrap())
+    .filter(|written| *written)
+    .count();
+  assert_eq!(1, written);
+  assert_eq!(
+    Errors::KeyIsEmpty,
+    engine
+      .put_if_absent(Bytes::new(), get_test_value(0))
+      .unwrap_err()
+  );
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_blob_values() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  opts.data_file_size = 64 * 1024;
+  opts.blob_threshold = 1024;
+  opts.file_merge_threshold = 0.0;
+  let blob_bytes = |dir: &std::path::Path| -> u64 {
+    fs::read_dir(dir)
+      .unwrap()
+      .flatten()
+      .filter(|entry| entry.file_name().to_string_lossy().ends_with(".blob"))
+      .map(|entry| entry.metadata().unwrap().len())
+      .sum()
+  };
+  let large_value = |i: usize| Bytes::from(vec![i as u8; 8 * 1024]);
+
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  for i in 0..40 {
+    engine.put(get_test_key(i), large_value(i)).unwrap();
+    engine
+      .put(get_test_key(100 + i), get_test_value(i))
+      .unwrap();
+  }
+  let batch = engine
+    .new_write_batch(crate::option::WriteBatchOptions::default())
+    .unwrap();
+  batch.put(get_test_key(200), large_value(200)).unwrap();
+  batch.put(get_test_key(201), get_test_value(201)).unwrap();
+  batch.commit().unwrap();
+
+  // only the pointers are kept in the data files
+  assert!(blob_bytes(temp_dir.path()) > 40 * 8 * 1024);
+  let data_file = get_data_file_name(temp_dir.path(), 0);
+  assert!(fs::metadata(data_file).unwrap().len() < 16 * 1024);
+  assert_eq!(large_value(3), engine.get(get_test_key(3)).unwrap());
+  assert_eq!(get_test_value(3), engine.get(get_test_key(103)).unwrap());
+  assert_eq!(large_value(200), engine.get(get_test_key(200)).unwrap());
+  let mut streamed = Vec::new();
+  std::io::Read::read_to_end(
+    &mut engine.get_reader(get_test_key(7)).unwrap(),
+    &mut streamed,
+  )
+  .unwrap();
+  assert_eq!(large_value(7), streamed);
+  let entry = engine.get_entry(get_test_key(39)).unwrap();
+  assert_eq!(large_value(39), entry.value);
+  assert_eq!(LogRecordType::Normal, entry.record_type);
+
+  // an overwritten blob is reclaimable along with its pointer record
+  let reclaim_size = engine.get_engine_stat().unwrap().reclaim_size;
+  engine.put(get_test_key(39), large_value(39)).unwrap();
+  assert!(engine.get_engine_stat().unwrap().reclaim_size > reclaim_size + 8 * 1024);
+
+  // half of the blobs become garbage
+  for i in 0..20 {
+    engine.delete(get_test_key(i)).unwrap();
+  }
+  engine.close().unwrap();
+  drop(engine);
+
+  let engine = Engine::open(opts.clone()).expect("fail to reopen engine");
+  assert_eq!(
+    Errors::KeyNotFound,
+    engine.get(get_test_key(3)).unwrap_err()
+  );
+  assert_eq!(large_value(30), engine.get(get_test_key(30)).unwrap());
+  let before_merge = blob_bytes(temp_dir.path());
+  engine.merge().unwrap();
+  engine.close().unwrap();
+  drop(engine);
+
+  // the merge rewrote the live blobs and removed the blob files of the merged files
+  let engine = Engine::open(opts).expect("fail to reopen engine");
+  assert!(blob_bytes(temp_dir.path()) < before_merge * 2 / 3);
+  for i in 20..40 {
+    assert_eq!(large_value(i), engine.get(get_test_key(i)).unwrap());
+    assert_eq!(
+      get_test_value(i),
+      engine.get(get_test_key(100 + i)).unwrap()
+    );
+  }
+  assert_eq!(large_value(200), engine.get(get_test_key(200)).unwrap());
+  assert_eq!(get_test_value(201), engine.get(get_test_key(201)).unwrap());
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_merge_timestamped_blobs() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  opts.data_file_size = 16 * 1024;
+  opts.blob_threshold = 100;
+  opts.store_timestamps = true;
+  opts.file_merge_threshold = 0.0;
+  let value = |i: usize, round: usize| Bytes::from(vec![(i + round) as u8; 200]);
+
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  for round in 0..3 {
+    for i in 0..200 {
+      engine.put(get_test_key(i), value(i, round)).unwrap();
+    }
+  }
+  engine.merge().unwrap();
+  engine.close().unwrap();
+  drop(engine);
+
+  // the pointer records of the merged file are as large as their positions say
+  let engine = Engine::open(opts).expect("fail to reopen engine");
+  for i in 0..200 {
+    let entry = engine.get_entry(get_test_key(i)).unwrap();
+    assert_eq!(value(i, 2), entry.value);
+    assert!(entry.timestamp.is_some());
+  }
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_incr() {
+  let mut opts = option::Options::default();
+  opts.dir_path = PathBuf::from("/tmp/flash-kv-incr");
+  let engine = std::sync::Arc::new(Engine::open(opts.clone()).expect("fail to open engine"));
+
+  // missing key starts from zero
+  assert_eq!(5, engine.incr(Bytes::from("counter"), 5).unwrap());
+  assert_eq!(2, engine.decr(Bytes::from("counter"), 3).unwrap());
+
+  // concurrent increments are not lost
+  let mut handles = vec![];
+  for _ in 0..4 {
+    let eng = engine.clone();
+    handles.push(std::thread::spawn(move || {
+      for _ in 0..250 {
+        eng.incr(Bytes::from("counter"), 1).unwrap();
+      }
+    }));
+  }
+  for handle in handles {
+    handle.join().unwrap();
+  }
+  assert_eq!(1002, engine.incr(Bytes::from("counter"), 0).unwrap());
+
+  // non counter value and overflow
+  engine
+    .put(Bytes::from("name"), Bytes::from("flash-kv-engine"))
+    .unwrap();
+  assert_eq!(
+    Errors::InvalidCounterValue,
+    engine.incr(Bytes::from("name"), 1).err().unwrap()
+  );
+  engine.incr(Bytes::from("max"), i64::MAX).unwrap();
+  assert_eq!(
+    Errors::CounterOverflow,
+    engine.incr(Bytes::from("max"), 1).err().unwrap()
+  );
+
+  fs::remove_dir_all(opts.clone().dir_path).unwrap();
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_open_corrupted_files() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let bookkeeping_record = |key: &str, value: &str| {
+    LogRecord {
+      key: key.as_bytes().to_vec(),
+      value: value.as_bytes().to_vec(),
+      rec_type: LogRecordType::Normal,
+      timestamp: None,
+    }
+    .encode()
+  };
+  let open_dir = |name: &str, contents: &[(&str, Vec<u8>)], index_type: IndexType| {
+    let dir_path = temp_dir.path().join(name);
+    fs::create_dir_all(&dir_path).unwrap();
+    for (file_name, data) in contents {
+      let file_path = dir_path.join(file_name);
+      fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+      fs::write(file_path, data).unwrap();
+    }
+    let mut opts = Options::default();
+    opts.dir_path = dir_path;
+    opts.index_type = index_type;
+    Engine::open(opts).err()
+  };
+
+  // unknown log record type
+  let data_file = get_data_file_name("", 0);
+  let data_file = data_file.to_str().unwrap();
+  assert_eq!(
+    Some(Errors::InvalidLogRecord),
+    open_dir(
+      "bad-type",
+      &[(
+        data_file,
+        vec![200, 2, 2, b'k', b'k', b'v', b'v', 0, 0, 0, 0]
+      )],
+      IndexType::BTree
+    )
+  );
+
+  // header claims a huge key, must not allocate or read past the end of file
+  assert_eq!(
+    Some(Errors::TruncatedLogRecord {
+      offset: 0,
+      available: 13
+    }),
+    open_dir(
+      "bad-length",
+      &[(
+        data_file,
+        vec![1, 0xff, 0xff, 0xff, 0xff, 0x0f, 2, 0, 0, 0, 0, 0, 0]
+      )],
+      IndexType::BTree
+    )
+  );
+
+  // merge finished record without a valid file id
+  assert_eq!(
+    Some(Errors::DatabaseDirectoryCorrupted),
+    open_dir(
+      "bad-merge",
+      &[(
+        "../bad-merge-merge/merge-finished",
+        bookkeeping_record("merge.finished", "not-a-file-id"),
+      )],
+      IndexType::BTree
+    )
+  );
+
+  // seq_no record without a valid number under b+ tree index
+  #[cfg(feature = "bptree")]
+  assert_eq!(
+    Some(Errors::DatabaseDirectoryCorrupted),
+    open_dir(
+      "bad-seq-no",
+      &[("seq-no", bookkeeping_record("seq.no", "not-a-seq-no"))],
+      IndexType::BPlusTree
+    )
+  );
+
+  // hint record with an undecodable position, the hint is ignored and the data files scanned
+  assert_eq!(
+    None,
+    open_dir(
+      "bad-hint",
+      &[("hint-index", bookkeeping_record("key", "\u{80}"))],
+      IndexType::BTree
+    )
+  );
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_index_memory_limit() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  opts.index_type = IndexType::SkipList;
+  opts.index_memory_limit = 1024;
+  let engine = Engine::open(opts).expect("fail to open engine");
+
+  let mut i = 0;
+  let err = loop {
+    if let Err(e) = engine.put(get_test_key(i), get_test_value(i)) {
+      break e;
+    }
+    i += 1;
+  };
+  assert_eq!(Errors::IndexMemoryLimitExceeded, err);
+  assert!(i > 0);
+
+  // existing keys can still be updated and deleted keys free memory
+  engine.put(get_test_key(0), get_test_value(100)).unwrap();
+  engine.delete(get_test_key(0)).unwrap();
+  engine.put(get_test_key(i), get_test_value(i)).unwrap();
+}
+
+#[cfg(not(feature = "bptree"))]
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_open_bptree_without_feature() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  opts.index_type = IndexType::BPlusTree;
+  assert_eq!(
+    Errors::IndexTypeUnsupported,
+    Engine::open(opts).err().unwrap()
+  );
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_max_open_files() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  opts.data_file_size = 4 * 1024;
+  opts.max_open_files = 2;
+
+  for startup_io in [
+    StartupIo::Runtime,
+    StartupIo::MemoryMap,
+    StartupIo::StandardFileIO,
+  ] {
+    opts.startup_io = startup_io;
+    let engine = Engine::open(opts.clone()).expect("fail to open engine");
+    for i in 0..1000 {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    assert!(engine.old_data_files.read().len() > 2);
+
+    for i in 0..1000 {
+      assert_eq!(get_test_value(i), engine.get(get_test_key(i)).unwrap());
+      assert!(engine.file_pool.as_ref().unwrap().open_files() <= 2);
+    }
+    engine.close().unwrap();
+  }
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_startup_threads() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  opts.data_file_size = 2 * 1024;
+
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  for i in 0..300 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  for i in 0..50 {
+    engine.delete(get_test_key(i)).unwrap();
+  }
+  for i in [60, 120] {
+    let batch = engine
+      .new_write_batch(option::WriteBatchOptions::default())
+      .unwrap();
+    for j in i..i + 10 {
+      batch.put(get_test_key(j), get_test_value(j + 1)).unwrap();
+    }
+    batch.commit().unwrap();
+  }
+  assert!(engine.old_data_files.read().len() > 4);
+  engine.close().unwrap();
+  drop(engine);
+
+  opts.startup_threads = 0;
+  assert_eq!(
+    Errors::InvalidStartupThreads,
+    Engine::open(opts.clone()).err().unwrap()
+  );
+
+  // records decoded ahead are applied in file order, whatever IO they are read with
+  for startup_io in [
+    StartupIo::Runtime,
+    StartupIo::MemoryMap,
+    StartupIo::StandardFileIO,
+  ] {
+    opts.startup_threads = 4;
+    opts.startup_io = startup_io;
+    let (engine, report) = Engine::open_with_report(opts.clone()).expect("fail to open engine");
+    assert_eq!(2, report.committed_txns);
+    for i in 0..50 {
+      assert_eq!(
+        Errors::KeyNotFound,
+        engine.get(get_test_key(i)).unwrap_err()
+      );
+    }
+    for i in 50..300 {
+      let expected = match (60..70).contains(&i) || (120..130).contains(&i) {
+        true => get_test_value(i + 1),
+        false => get_test_value(i),
+      };
+      assert_eq!(expected, engine.get(get_test_key(i)).unwrap());
+    }
+    engine.put(get_test_key(300), get_test_value(300)).unwrap();
+    engine.close().unwrap();
+  }
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_startup_manifest() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  opts.data_file_size = 4 * 1024;
+  opts.startup_manifest = true;
+
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  for i in 0..300 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  for i in 0..50 {
+    engine.delete(get_test_key(i)).unwrap();
+  }
+  let batch = engine
+    .new_write_batch(option::WriteBatchOptions::default())
+    .unwrap();
+  for i in 300..320 {
+    batch.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  batch.commit().unwrap();
+  let sealed = engine.old_data_files.read().len() as u32;
+  assert!(sealed > 1);
+  engine.close().unwrap();
+  drop(engine);
+
+  let check = |engine: &Engine| {
+    for i in 0..50 {
+      assert_eq!(
+        Errors::KeyNotFound,
+        engine.get(get_test_key(i)).unwrap_err()
+      );
+    }
+    for i in 50..320 {
+      assert_eq!(get_test_value(i), engine.get(get_test_key(i)).unwrap());
+    }
+  };
+
+  // sealed files are loaded from their key directory
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  check(&engine);
+  for fid in 0..sealed {
+    assert!(get_keydir_file_name(temp_dir.path(), fid).is_file());
+  }
+  engine.put(get_test_key(320), get_test_value(320)).unwrap();
+  let pos = engine.index.get(get_test_key(60).to_vec()).unwrap();
+  assert_eq!(0, pos.file_id);
+  engine.close().unwrap();
+  drop(engine);
+  let keydir = fs::read(get_keydir_file_name(temp_dir.path(), 0)).unwrap();
+
+  // an unreadable or truncated key directory falls back to scanning the data file
+  for content in [&b"not a keydir"[..], b"", &keydir[..keydir.len() / 2]] {
+    fs::write(get_keydir_file_name(temp_dir.path(), 0), content).unwrap();
+    let engine = Engine::open(opts.clone()).expect("fail to open engine");
+    check(&engine);
+    assert_eq!(get_test_value(320), engine.get(get_test_key(320)).unwrap());
+    engine.close().unwrap();
+    drop(engine);
+  }
+
+  // without the option, the manifest is ignored
+  opts.startup_manifest = false;
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  check(&engine);
+  engine.close().unwrap();
+  drop(engine);
+
+  // the data file itself is not scanned when the key directory matches, a value corrupted
+  // in place is only noticed on read
+  opts.startup_manifest = true;
+  fs::write(get_keydir_file_name(temp_dir.path(), 0), &keydir).unwrap();
+  {
+    use std::os::unix::fs::FileExt;
+    let file = fs::OpenOptions::new()
+      .write(true)
+      .open(get_data_file_name(temp_dir.path(), 0))
+      .unwrap();
+    file
+      .write_at(b"x", pos.offset + pos.size as u64 - 5)
+      .unwrap();
+  }
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  assert_eq!(
+    Errors::InvalidLogRecordCrc,
+    engine.get(get_test_key(60)).unwrap_err()
+  );
+  assert_eq!(get_test_value(320), engine.get(get_test_key(320)).unwrap());
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_startup_manifest_compacted() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  opts.data_file_size = 4 * 1024;
+  opts.startup_manifest = true;
+  opts.file_merge_threshold = 0.0;
+
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  for i in 0..300 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  for i in 0..300 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  engine.merge().unwrap();
+  engine.close().unwrap();
+  drop(engine);
+  let (_, before) = crate::manifest::load_manifest(temp_dir.path()).unwrap();
+
+  // the entries of the merged files are dropped on the next open
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  let (sealed_files, entries) = crate::manifest::load_manifest(temp_dir.path()).unwrap();
+  assert!(entries < before);
+  assert_eq!(sealed_files.len(), entries);
+  let old_files = engine.old_data_files.read();
+  assert!(sealed_files.keys().all(|fid| old_files.contains_key(fid)));
+  drop(old_files);
+  for i in 0..300 {
+    assert_eq!(get_test_value(i), engine.get(get_test_key(i)).unwrap());
+  }
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_fast_reopen() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  opts.data_file_size = 4 * 1024;
+  opts.fast_reopen = true;
+  opts.startup_manifest = true;
+  opts.file_merge_threshold = 0.0;
+  let close_hint = temp_dir.path().join(CLOSE_HINT_FILE_NAME);
+
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  for i in 0..300 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  for i in 0..50 {
+    engine.delete(get_test_key(i)).unwrap();
+  }
+  let batch = engine
+    .new_write_batch(option::WriteBatchOptions::default())
+    .unwrap();
+  for i in 300..320 {
+    batch.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  batch.commit().unwrap();
+  let expire_at = SystemTime::now() + Duration::from_secs(3600);
+  engine.expire(get_test_key(100), expire_at).unwrap();
+  let reclaim_size = engine.get_engine_stat().unwrap().reclaim_size;
+  engine.close().unwrap();
+  drop(engine);
+  assert!(close_hint.is_file());
+
+  let check = |engine: &Engine| {
+    for i in 0..50 {
+      assert_eq!(
+        Errors::KeyNotFound,
+        engine.get(get_test_key(i)).unwrap_err()
+      );
+    }
+    for i in 50..320 {
+      assert_eq!(get_test_value(i), engine.get(get_test_key(i)).unwrap());
+    }
+    assert!(engine.expiration(get_test_key(100)).unwrap().is_some());
+  };
+
+  // the index is loaded from the close hint, which is consumed by the open
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  assert!(!close_hint.is_file());
+  check(&engine);
+  assert_eq!(reclaim_size, engine.get_engine_stat().unwrap().reclaim_size);
+  engine.close().unwrap();
+  drop(engine);
+  fs::copy(&close_hint, temp_dir.path().join("stale-hint")).unwrap();
+
+  // writes keep the sealed files' key directories complete after a fast reopen
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  for i in 320..400 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  engine.close().unwrap();
+  drop(engine);
+
+  // a hint older than the active file is ignored and the data files are scanned
+  fs::copy(temp_dir.path().join("stale-hint"), &close_hint).unwrap();
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  check(&engine);
+  assert_eq!(get_test_value(399), engine.get(get_test_key(399)).unwrap());
+  engine.close().unwrap();
+  drop(engine);
+
+  // a pending merge replaces the files the hint points into
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  engine.merge().unwrap();
+  engine.close().unwrap();
+  drop(engine);
+  let engine = Engine::open(opts).expect("fail to open engine");
+  check(&engine);
+  assert_eq!(get_test_value(399), engine.get(get_test_key(399)).unwrap());
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_write_backpressure() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  opts.max_reclaim_backlog = 4 * 1024;
+  opts.file_merge_threshold = 0.0;
+  let engine = Engine::open(opts).expect("fail to open engine");
+
+  // overwrites pile up reclaimable bytes until puts are rejected
+  let mut rejected = false;
+  for i in 0..1000 {
+    match engine.put(get_test_key(1), get_test_value(i)) {
+      Ok(()) => {}
+      Err(e) => {
+        assert_eq!(Errors::Backpressure, e);
+        rejected = true;
+        break;
+      }
+    }
+  }
+  assert!(rejected);
+  let batch = engine
+    .new_write_batch(option::WriteBatchOptions::default())
+    .unwrap();
+  batch.put(get_test_key(2), get_test_value(2)).unwrap();
+  assert_eq!(Errors::Backpressure, batch.commit().unwrap_err());
+
+  // deletes still go through, a merge clears the backlog
+  engine.delete(get_test_key(1)).unwrap();
+  engine.merge().unwrap();
+  engine.put(get_test_key(1), get_test_value(1)).unwrap();
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_reserved_disk_bytes() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  opts.file_merge_threshold = 0.0;
+  opts.reserved_disk_bytes = 1;
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  for i in 0..100 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  drop(engine);
+
+  // a reserve beyond the free space rejects puts and merges, not deletes
+  opts.reserved_disk_bytes = u64::MAX;
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  assert_eq!(
+    Err(Errors::DiskFull),
+    engine.put(get_test_key(100), get_test_value(100))
+  );
+  let batch = engine
+    .new_write_batch(option::WriteBatchOptions::default())
+    .unwrap();
+  batch.put(get_test_key(101), get_test_value(101)).unwrap();
+  assert_eq!(Errors::DiskFull, batch.commit().unwrap_err());
+  engine.delete(get_test_key(1)).unwrap();
+  assert_eq!(Err(Errors::DiskFull), engine.merge());
+  assert_eq!(99, engine.list_keys().unwrap().len());
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_write_rate_limit() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  opts.max_write_rate_bytes_per_sec = 64 * 1024;
+  let engine = Engine::open(opts).expect("fail to open engine");
+
+  let value = Bytes::from(vec![0u8; 1024]);
+  let start = std::time::Instant::now();
+  for i in 0..32 {
+    engine.put(get_test_key(i), value.clone()).unwrap();
+  }
+  // 32KB at 64KB/s, the first write is free
+  assert!(start.elapsed() >= std::time::Duration::from_millis(400));
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_expire() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  let engine = Engine::open(opts).expect("fail to open engine");
+
+  let later = SystemTime::now() + Duration::from_secs(3600);
+  assert_eq!(
+    Errors::KeyNotFound,
+    engine.expire(get_test_key(1), later).unwrap_err()
+  );
+
+  engine.put(get_test_key(1), get_test_value(1)).unwrap();
+  assert_eq!(None, engine.expiration(get_test_key(1)).unwrap());
+  engine.expire(get_test_key(1), later).unwrap();
+  let at = engine.expiration(get_test_key(1)).unwrap().unwrap();
+  // stored with millisecond precision
+  assert!(later.duration_since(at).unwrap() < Duration::from_millis(1));
+  assert_eq!(get_test_value(1), engine.get(get_test_key(1)).unwrap());
+
+  // a put clears the expiration
+  engine.put(get_test_key(1), get_test_value(2)).unwrap();
+  assert_eq!(None, engine.expiration(get_test_key(1)).unwrap());
+
+  // expired keys are hidden until purged
+  engine.put(get_test_key(2), get_test_value(2)).unwrap();
+  engine
+    .expire(
+      get_test_key(2),
+      SystemTime::now() + Duration::from_millis(20),
+    )
+    .unwrap();
+  std::thread::sleep(Duration::from_millis(40));
+  assert_eq!(
+    Errors::KeyNotFound,
+    engine.get(get_test_key(2)).unwrap_err()
+  );
+  assert_eq!(vec![get_test_key(1)], engine.list_keys().unwrap());
+  assert_eq!(
+    vec![get_test_key(1)],
+    engine.list_keys_paged(None, None, 10).unwrap().keys
+  );
+  let iter = engine.iter(option::IteratorOptions::default());
+  assert_eq!(get_test_key(1), iter.next().unwrap().0);
+  assert!(iter.next().is_none());
+  assert_eq!(1, engine.purge_expired().unwrap());
+  assert_eq!(1, engine.list_keys().unwrap().len());
+  assert_eq!(0, engine.purge_expired().unwrap());
+
+  // a time in the past deletes the key
+  engine
+    .expire(get_test_key(1), SystemTime::now() - Duration::from_secs(1))
+    .unwrap();
+  assert_eq!(
+    Errors::KeyNotFound,
+    engine.get(get_test_key(1)).unwrap_err()
+  );
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_expire_after_reopen() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  opts.data_file_size = 4 * 1024;
+  opts.file_merge_threshold = 0.0;
+  opts.startup_manifest = true;
+
+  let later = SystemTime::now() + Duration::from_secs(3600);
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  for i in 0..200 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    if i % 2 == 0 {
+      engine.expire(get_test_key(i), later).unwrap();
+    }
+  }
+  // cleared by a put and by a delete
+  engine.put(get_test_key(0), get_test_value(0)).unwrap();
+  engine.delete(get_test_key(2)).unwrap();
+  engine.close().unwrap();
+  drop(engine);
+
+  let check = |engine: &Engine| {
+    assert_eq!(None, engine.expiration(get_test_key(0)).unwrap());
+    assert_eq!(
+      Errors::KeyNotFound,
+      engine.expiration(get_test_key(2)).unwrap_err()
+    );
+    for i in 3..200 {
+      let expiration = engine.expiration(get_test_key(i)).unwrap();
+      assert_eq!(i % 2 == 0, expiration.is_some());
+    }
+  };
+
+  // replayed from the key directories and the active file
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  check(&engine);
+
+  // carried over by merge through the hint file
+  engine.merge().unwrap();
+  engine.close().unwrap();
+  drop(engine);
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  check(&engine);
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_expiry_sweeper() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  let engine = std::sync::Arc::new(Engine::open(opts).expect("fail to open engine"));
+
+  let sweeper = engine
+    .start_expiry_sweeper(Duration::from_millis(10))
+    .unwrap();
+  for i in 0..10 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    engine
+      .expire(
+        get_test_key(i),
+        SystemTime::now() + Duration::from_millis(20),
+      )
+      .unwrap();
+  }
+  for _ in 0..100 {
+    if engine.list_keys().unwrap().is_empty() {
+      break;
+    }
+    std::thread::sleep(Duration::from_millis(10));
+  }
+  assert!(engine.list_keys().unwrap().is_empty());
+  sweeper.stop();
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[cfg(feature = "bptree")]
+#[test]
+fn test_engine_expire_bptree_unsupported() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  opts.index_type = IndexType::BPlusTree;
+  let engine = Engine::open(opts).expect("fail to open engine");
+  engine.put(get_test_key(1), get_test_value(1)).unwrap();
+  assert_eq!(
+    Errors::ExpireUnsupported,
+    engine
+      .expire(get_test_key(1), SystemTime::now() + Duration::from_secs(1))
+      .unwrap_err()
+  );
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_prefix_stat() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  let engine = Engine::open(opts).expect("fail to open engine");
+
+  for i in 0..10 {
+    let key = Bytes::from(format!("tenant-a/{i}"));
+    engine.put(key, Bytes::from(vec![0u8; 100])).unwrap();
+  }
+  for i in 0..5 {
+    let key = Bytes::from(format!("tenant-b/{i}"));
+    engine.put(key, Bytes::from(vec![0u8; 1000])).unwrap();
+  }
+  engine.delete(Bytes::from("tenant-a/0")).unwrap();
+  engine
+    .expire(
+      Bytes::from("tenant-a/1"),
+      SystemTime::now() - Duration::from_secs(1),
+    )
+    .unwrap();
+
+  let stat_a = engine.prefix_stat(b"tenant-a/").unwrap();
+  assert_eq!(8, stat_a.key_num);
+  let stat_b = engine.prefix_stat(b"tenant-b/").unwrap();
+  assert_eq!(5, stat_b.key_num);
+  assert!(stat_b.live_bytes > 5 * 1000 && stat_b.live_bytes < 5 * 1100);
+  assert_eq!(
+    stat_a.key_num + stat_b.key_num,
+    engine.prefix_stat(b"").unwrap().key_num
+  );
+  assert_eq!(0, engine.prefix_stat(b"tenant-c/").unwrap().key_num);
+
+  // 100 byte values fall in [64, 128), 1000 byte values in [512, 1024)
+  let histogram = engine.value_size_histogram().unwrap();
+  assert_eq!(11, histogram.counts.len());
+  assert_eq!(8, histogram.counts[7]);
+  assert_eq!(5, histogram.counts[10]);
+  assert_eq!(13, histogram.counts.iter().sum::<usize>());
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_clear() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  opts.data_file_size = 4 * 1024;
+  opts.startup_manifest = true;
+
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  for i in 0..300 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  for i in 0..50 {
+    engine.delete(get_test_key(i)).unwrap();
+  }
+  let expire_at = SystemTime::now() + Duration::from_secs(3600);
+  engine.expire(get_test_key(100), expire_at).unwrap();
+  assert!(engine.get_engine_stat().unwrap().data_file_num > 1);
+
+  engine.clear().unwrap();
+  let stat = engine.get_engine_stat().unwrap();
+  assert_eq!(0, stat.key_num);
+  assert_eq!(1, stat.data_file_num);
+  assert_eq!(0, stat.reclaim_size);
+  assert!(engine.file_garbage().unwrap().is_empty());
+  assert_eq!(
+    Errors::KeyNotFound,
+    engine.get(get_test_key(100)).unwrap_err()
+  );
+  assert!(!get_data_file_name(temp_dir.path(), 0).is_file());
+
+  // the engine keeps working on the fresh file set
+  for i in 300..320 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  assert_eq!(None, engine.expiration(get_test_key(300)).unwrap());
+  engine.close().unwrap();
+  drop(engine);
+
+  let engine = Engine::open(opts).expect("fail to open engine");
+  assert_eq!(20, engine.list_keys().unwrap().len());
+  assert_eq!(
+    Errors::KeyNotFound,
+    engine.get(get_test_key(200)).unwrap_err()
+  );
+  assert_eq!(get_test_value(310), engine.get(get_test_key(310)).unwrap());
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_clear_recovery() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  opts.data_file_size = 4 * 1024;
+
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  for i in 0..200 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  let active_file_id = engine.active_data_file.read().get_file_id();
+  engine.close().unwrap();
+  drop(engine);
+
+  // a crash right after the clear marker was written leaves all old files in place
+  let marker = DataFile::new_clear_marker_file(temp_dir.path()).unwrap();
+  let record = LogRecord {
+    key: "clear.fid".as_bytes().to_vec(),
+    value: (active_file_id + 1).to_string().into(),
+    rec_type: LogRecordType::Normal,
+    timestamp: None,
+  };
+  marker.write(&record.encode()).unwrap();
+  marker.sync().unwrap();
+
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  assert!(engine.list_keys().unwrap().is_empty());
+  assert!(!temp_dir.path().join(CLEAR_MARKER_FILE_NAME).is_file());
+  engine.put(get_test_key(1), get_test_value(1)).unwrap();
+  engine.close().unwrap();
+  drop(engine);
+
+  let engine = Engine::open(opts).expect("fail to open engine");
+  assert_eq!(1, engine.list_keys().unwrap().len());
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_destroy() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().join("db");
+
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  engine.put(get_test_key(1), get_test_value(1)).unwrap();
+
+  // an opened database is never deleted
+  assert_eq!(
+    Errors::DatabaseIsUsing,
+    Engine::destroy(opts.clone()).unwrap_err()
+  );
+  assert!(opts.dir_path.is_dir());
+
+  engine.close().unwrap();
+  drop(engine);
+  Engine::destroy(opts.clone()).unwrap();
+  assert!(!opts.dir_path.exists());
+  // destroying a missing database is a no-op
+  Engine::destroy(opts).unwrap();
+}
+
+#[test]
+fn test_engine_custom_indexer() {
+  struct CountingIndexer {
+    inner: BTree,
+    puts: Arc<AtomicUsize>,
+  }
+
+  impl Indexer for CountingIndexer {
+    fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> Option<LogRecordPos> {
+      self.puts.fetch_add(1, Ordering::SeqCst);
+      self.inner.put(key, pos)
+    }
+
+    fn get(&self, key: Vec<u8>) -> Option<LogRecordPos> {
+      self.inner.get(key)
+    }
+
+    fn delete(&self, key: Vec<u8>) -> Option<LogRecordPos> {
+      self.inner.delete(key)
+    }
+
+    fn list_keys(&self) -> crate::errors::Result<Vec<Bytes>> {
+      self.inner.list_keys()
+    }
+
+    fn iterator(&self, options: option::IteratorOptions) -> Box<dyn IndexIterator> {
+      self.inner.iterator(options)
+    }
+
+    fn clear(&self) -> crate::errors::Result<()> {
+      self.inner.clear()
+    }
+  }
+
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let puts = Arc::new(AtomicUsize::new(0));
+  let factory_puts = puts.clone();
+  let opts = Options::builder()
+    .dir_path(temp_dir.path())
+    .custom_indexer(CustomIndexer::new(move |_| {
+      Box::new(CountingIndexer {
+        inner: BTree::with_shards(1),
+        puts: factory_puts.clone(),
+      })
+    }))
+    .build()
+    .unwrap();
+
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  for i in 0..10 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  assert_eq!(10, puts.load(Ordering::SeqCst));
+  engine.close().unwrap();
+  drop(engine);
+
+  // the custom index is rebuilt from the data files as well
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  assert_eq!(20, puts.load(Ordering::SeqCst));
+  assert_eq!(get_test_value(5), engine.get(get_test_key(5)).unwrap());
+
+  #[cfg(feature = "bptree")]
+  {
+    let mut opts = opts;
+    opts.index_type = IndexType::BPlusTree;
+    assert_eq!(Err(Errors::CustomIndexerUnsupported), opts.validate());
+  }
+}
+
+#[test]
+fn test_engine_index_failures_reach_caller() {
+  // fails every fallible operation once broken, like a b+ tree whose file can't be written
+  struct BrokenIndexer {
+    inner: BTree,
+    broken: Arc<AtomicBool>,
+  }
+
+  impl BrokenIndexer {
+    fn check(&self) -> crate::errors::Result<()> {
+      match self.broken.load(Ordering::SeqCst) {
+        true => Err(Errors::FailedToUpdateIndex),
+        false => Ok(()),
+      }
+    }
+  }
+
+  impl Indexer for BrokenIndexer {
+    fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> Option<LogRecordPos> {
+      self.inner.put(key, pos)
+    }
+
+    fn try_put(
+      &self,
+      key: Vec<u8>,
+      pos: LogRecordPos,
+    ) -> crate::errors::Result<Option<LogRecordPos>> {
+      self.check()?;
+      Ok(self.inner.put(key, pos))
+    }
+
+    fn get(&self, key: Vec<u8>) -> Option<LogRecordPos> {
+      self.inner.get(key)
+    }
+
+    fn delete(&self, key: Vec<u8>) -> Option<LogRecordPos> {
+      self.inner.delete(key)
+    }
+
+    fn try_delete(&self, key: Vec<u8>) -> crate::errors::Result<Option<LogRecordPos>> {
+      self.check()?;
+      Ok(self.inner.delete(key))
+    }
+
+    fn list_keys(&self) -> crate::errors::Result<Vec<Bytes>> {
+      self.inner.list_keys()
+    }
+
+    fn iterator(&self, options: option::IteratorOptions) -> Box<dyn IndexIterator> {
+      self.inner.iterator(options)
+    }
+
+    fn try_iterator(
+      &self,
+      options: option::IteratorOptions,
+    ) -> crate::errors::Result<Box<dyn IndexIterator>> {
+      self.check()?;
+      Ok(self.inner.iterator(options))
+    }
+
+    fn clear(&self) -> crate::errors::Result<()> {
+      self.inner.clear()
+    }
+  }
+
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let broken = Arc::new(AtomicBool::new(false));
+  let factory_broken = broken.clone();
+  let opts = Options::builder()
+    .dir_path(temp_dir.path())
+    .custom_indexer(CustomIndexer::new(move |_| {
+      Box::new(BrokenIndexer {
+        inner: BTree::with_shards(1),
+        broken: factory_broken.clone(),
+      })
+    }))
+    .build()
+    .unwrap();
+  let engine = Engine::open(opts).expect("fail to open engine");
+  engine.put(get_test_key(1), get_test_value(1)).unwrap();
+  let iter = engine.iter(option::IteratorOptions::default());
+
+  broken.store(true, Ordering::SeqCst);
+  let failed = Err(Errors::FailedToUpdateIndex);
+  assert_eq!(failed, engine.put(get_test_key(2), get_test_value(2)));
+  assert_eq!(failed, engine.delete(get_test_key(1)));
+  let batch = engine
+    .new_write_batch(option::WriteBatchOptions::default())
+    .unwrap();
+  batch.delete(get_test_key(1)).unwrap();
+  assert_eq!(failed, batch.commit());
+  assert_eq!(
+    Errors::FailedToUpdateIndex,
+    engine
+      .bulk_load(
+        vec![(get_test_key(3), get_test_value(3))],
+        option::BulkLoadOptions::default()
+      )
+      .unwrap_err()
+  );
+  assert_eq!(
+    Errors::FailedToUpdateIndex,
+    engine.list_keys_paged(None, None, 10).unwrap_err()
+  );
+  let broken_iter = engine.iter(option::IteratorOptions::default());
+  assert_eq!(
+    Errors::FailedToUpdateIndex,
+    broken_iter.try_next().unwrap_err()
+  );
+  assert_eq!(None, broken_iter.next());
+
+  // an iterator created before keeps its keys
+  assert_eq!(
+    Some((get_test_key(1), get_test_value(1))),
+    iter.try_next().unwrap()
+  );
+  broken.store(false, Ordering::SeqCst);
+  assert_eq!(get_test_value(1), engine.get(get_test_key(1)).unwrap());
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_shared_readers() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().join("db");
+  opts.data_file_size = 1024;
+
+  let mut reader_opts = opts.clone();
+  reader_opts.shared_readers = true;
+  // readers don't create the database
+  assert!(Engine::open(reader_opts.clone()).is_err());
+
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  for i in 0..10 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  let reader = Engine::open(reader_opts.clone()).expect("fail to open reader");
+  assert_eq!(10, reader.list_keys().unwrap().len());
+  assert_eq!(
+    Errors::ReadOnlyEngine,
+    reader
+      .put(get_test_key(10), get_test_value(10))
+      .unwrap_err()
+  );
+  assert_eq!(Errors::ReadOnlyEngine, reader.merge().unwrap_err());
+
+  // writes show up on refresh, across rotated data files
+  for i in 10..200 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  engine.delete(get_test_key(0)).unwrap();
+  assert!(engine.get_engine_stat().unwrap().data_file_num > 2);
+  assert_eq!(10, reader.list_keys().unwrap().len());
+  reader.refresh().unwrap();
+  assert_eq!(199, reader.list_keys().unwrap().len());
+  assert_eq!(
+    Errors::KeyNotFound,
+    reader.get(get_test_key(0)).unwrap_err()
+  );
+  assert_eq!(get_test_value(150), reader.get(get_test_key(150)).unwrap());
+
+  // a batch is only seen once committed
+  let batch = engine
+    .new_write_batch(option::WriteBatchOptions::default())
+    .unwrap();
+  batch.put(get_test_key(500), get_test_value(500)).unwrap();
+  reader.refresh().unwrap();
+  assert_eq!(
+    Errors::KeyNotFound,
+    reader.get(get_test_key(500)).unwrap_err()
+  );
+  batch.commit().unwrap();
+  reader.refresh().unwrap();
+  assert_eq!(get_test_value(500), reader.get(get_test_key(500)).unwrap());
+
+  // a clear removes the loaded files, the reader rebuilds its index
+  engine.clear().unwrap();
+  engine.put(get_test_key(7), get_test_value(7)).unwrap();
+  reader.refresh().unwrap();
+  assert_eq!(1, reader.list_keys().unwrap().len());
+
+  // the database is not deleted while a reader has it opened
+  engine.close().unwrap();
+  drop(engine);
+  assert_eq!(
+    Errors::DatabaseIsUsing,
+    Engine::destroy(opts.clone()).unwrap_err()
+  );
+  drop(reader);
+  Engine::destroy(opts).unwrap();
+}
+
+#[cfg(feature = "bptree")]
+#[test]
+fn test_shared_readers_options() {
+  let opts = Options {
+    shared_readers: true,
+    index_type: IndexType::BPlusTree,
+    ..Default::default()
+  };
+  assert_eq!(Err(Errors::SharedReadersUnsupported), opts.validate());
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_io_stats() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  opts.file_merge_threshold = 0.0;
+  let engine = Engine::open(opts).expect("fail to open engine");
+
+  let mut ingested = 0;
+  for i in 0..100 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    ingested += get_test_key(i).len() + get_test_value(i).len();
+  }
+  for i in 0..50 {
+    engine.delete(get_test_key(i)).unwrap();
+    ingested += get_test_key(i).len();
+  }
+  let stat = engine.get_engine_stat().unwrap();
+  assert_eq!(ingested as u64, stat.bytes_ingested);
+  assert!(stat.write_amplification() > 1.0);
+  assert_eq!(0.0, stat.read_amplification());
+
+  // lookups read whole records to return the values
+  for i in 50..100 {
+    assert_eq!(get_test_value(i), engine.get(get_test_key(i)).unwrap());
+  }
+  let stat = engine.get_engine_stat().unwrap();
+  assert_eq!(50 * get_test_value(0).len() as u64, stat.bytes_returned);
+  assert!(stat.read_amplification() > 1.0);
+
+  // a merge reads the data files and rewrites the live records
+  engine.merge().unwrap();
+  let merged = engine.get_engine_stat().unwrap();
+  assert!(merged.bytes_read > stat.bytes_read);
+  assert!(merged.bytes_written > stat.bytes_written);
+  assert_eq!(stat.bytes_ingested, merged.bytes_ingested);
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[cfg(feature = "bptree")]
+#[test]
+fn test_engine_garbage_map_recovery() {
+  use crate::{data::data_file::GARBAGE_MAP_FILE_NAME, option::TombstoneFormat};
+
+  for format in [TombstoneFormat::Empty, TombstoneFormat::DeletedSize] {
+    let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+    let mut opts = Options::default();
+    opts.dir_path = temp_dir.path().to_path_buf();
+    opts.index_type = IndexType::BPlusTree;
+    opts.data_file_size = 4 * 1024;
+    opts.tombstone_format = format;
+    let map_path = temp_dir.path().join(GARBAGE_MAP_FILE_NAME);
+
+    let engine = Engine::open(opts.clone()).expect("fail to open engine");
+    for i in 0..200 {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    // the map written on the last rotation, the one written on close is lost in a crash
+    let map = fs::read(&map_path).unwrap();
+    for i in 0..50 {
+      engine.delete(get_test_key(i)).unwrap();
+    }
+    let reclaim_size = engine.get_engine_stat().unwrap().reclaim_size;
+    engine.close().unwrap();
+    drop(engine);
+    fs::write(&map_path, map).unwrap();
+
+    // the deletes after the map are accounted again, with the deleted records if their size
+    // is known
+    let engine = Engine::open(opts.clone()).expect("fail to open engine");
+    let recovered = engine.get_engine_stat().unwrap().reclaim_size;
+    match format {
+      TombstoneFormat::Empty => assert!(recovered > 0 && recovered < reclaim_size),
+      TombstoneFormat::DeletedSize => assert_eq!(reclaim_size, recovered),
+    }
+  }
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[cfg(feature = "bptree")]
+#[test]
+fn test_engine_hybrid_index() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  opts.index_type = IndexType::Hybrid;
+  opts.index_hot_keys = 32;
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  for i in 0..500 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  for i in (0..500).step_by(3) {
+    engine.delete(get_test_key(i)).unwrap();
+  }
+  assert_eq!(333, engine.list_keys().unwrap().len());
+  assert_eq!(get_test_value(1), engine.get(get_test_key(1)).unwrap());
+  engine.close().unwrap();
+  drop(engine);
+
+  // the index is rebuilt from the data files, not from the spilled keys
+  let engine = Engine::open(opts).expect("fail to open engine");
+  assert_eq!(333, engine.list_keys().unwrap().len());
+  for i in 0..500 {
+    match i % 3 {
+      0 => assert_eq!(Err(Errors::KeyNotFound), engine.get(get_test_key(i))),
+      _ => assert_eq!(get_test_value(i), engine.get(get_test_key(i)).unwrap()),
+    }
+  }
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_memory_storage() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().join("in-memory");
+  opts.data_file_size = 64 * 1024;
+  opts.storage_mode = StorageMode::Memory;
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  for i in 0..2000 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  for i in (0..2000).step_by(2) {
+    engine.delete(get_test_key(i)).unwrap();
+  }
+
+  // sealed files stay readable after rotating
+  assert!(engine.get_engine_stat().unwrap().data_file_num > 1);
+  assert_eq!(1000, engine.list_keys().unwrap().len());
+  assert_eq!(get_test_value(1), engine.get(get_test_key(1)).unwrap());
+  assert_eq!(Err(Errors::KeyNotFound), engine.get(get_test_key(2)));
+  let batch = engine
+    .new_write_batch(option::WriteBatchOptions::default())
+    .unwrap();
+  batch.put(get_test_key(2), get_test_value(2)).unwrap();
+  batch.commit().unwrap();
+  assert_eq!(get_test_value(2), engine.get(get_test_key(2)).unwrap());
+  assert_eq!(Err(Errors::MemoryModeUnsupported), engine.merge());
+
+  // another engine of the same path neither waits for a lock nor sees the data
+  let other = Engine::open(opts.clone()).expect("fail to open engine");
+  assert!(other.list_keys().unwrap().is_empty());
+
+  engine.clear().unwrap();
+  assert!(engine.list_keys().unwrap().is_empty());
+  engine.put(get_test_key(1), get_test_value(1)).unwrap();
+  engine.close().unwrap();
+  assert!(!opts.dir_path.exists());
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[cfg(feature = "encryption")]
+#[test]
+fn test_engine_encryption() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  opts.data_file_size = 64 * 1024;
+  opts.file_merge_threshold = 0.0;
+  opts.fast_reopen = true;
+
+  // records written before the key was set stay readable
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  engine
+    .put(Bytes::from("plain-key"), Bytes::from("plain-value"))
+    .unwrap();
+  engine.close().unwrap();
+  drop(engine);
+
+  opts.encryption_key = Some([42; 32]);
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  for i in 0..1000 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  for i in (0..1000).step_by(2) {
+    engine.delete(get_test_key(i)).unwrap();
+  }
+  engine.merge().unwrap();
+  engine
+    .put(Bytes::from("secret-key"), Bytes::from("secret-value"))
+    .unwrap();
+  engine.close().unwrap();
+  drop(engine);
+
+  // neither keys nor values are found in the files
+  let mut files = vec![temp_dir.path().to_path_buf()];
+  while let Some(path) = files.pop() {
+    if path.is_dir() {
+      files.extend(fs::read_dir(&path).unwrap().map(|e| e.unwrap().path()));
+      continue;
+    }
+    let content = fs::read(&path).unwrap();
+    assert!(!content.windows(10).any(|w| w == b"secret-key"));
+    assert!(!content.windows(12).any(|w| w == b"secret-value"));
+  }
+
+  // loaded from the close hint, then from the merge hint and the data files
+  for fast_reopen in [true, false] {
+    if !fast_reopen {
+      fs::remove_file(temp_dir.path().join(CLOSE_HINT_FILE_NAME)).unwrap();
+    }
+    opts.fast_reopen = fast_reopen;
+    let engine = Engine::open(opts.clone()).expect("fail to open engine");
+    assert_eq!(502, engine.list_keys().unwrap().len());
+    assert_eq!(get_test_value(1), engine.get(get_test_key(1)).unwrap());
+    assert_eq!(
+      Bytes::from("secret-value"),
+      engine.get(Bytes::from("secret-key")).unwrap()
+    );
+    assert_eq!(
+      Bytes::from("plain-value"),
+      engine.get(Bytes::from("plain-key")).unwrap()
+    );
+    assert_eq!(
+      Some(Errors::EncryptionUnsupported),
+      engine.get_reader(Bytes::from("secret-key")).err()
+    );
+    drop(engine);
+  }
+
+  // the data cannot be read without the key
+  opts.encryption_key = Some([7; 32]);
+  assert_eq!(
+    Some(Errors::DecryptionFailed),
+    Engine::open(opts.clone()).err()
+  );
+  opts.encryption_key = None;
+  assert_eq!(Some(Errors::DecryptionFailed), Engine::open(opts).err());
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_get_entry() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  let engine = Engine::open(opts).expect("fail to open engine");
+  engine.put(get_test_key(1), get_test_value(1)).unwrap();
+  let batch = engine
+    .new_write_batch(option::WriteBatchOptions::default())
+    .unwrap();
+  batch.put(get_test_key(2), get_test_value(2)).unwrap();
+  batch.commit().unwrap();
+
+  let entry = engine.get_entry(get_test_key(1)).unwrap();
+  let pos = engine.index.get(get_test_key(1).to_vec()).unwrap();
+  assert_eq!(get_test_value(1), entry.value);
+  assert_eq!(
+    (pos.file_id, pos.offset, pos.size),
+    (entry.file_id, entry.offset, entry.size)
+  );
+  assert_eq!(LogRecordType::Normal, entry.record_type);
+  assert_eq!(None, entry.txn_seq);
+
+  // the record of the batch follows the first one
+  let entry2 = engine.get_entry(get_test_key(2)).unwrap();
+  assert_eq!(get_test_value(2), entry2.value);
+  assert_eq!(entry.offset + entry.size as u64, entry2.offset);
+  assert_eq!(Some(1), entry2.txn_seq);
+
+  engine.delete(get_test_key(1)).unwrap();
+  assert_eq!(Err(Errors::KeyNotFound), engine.get_entry(get_test_key(1)));
+  assert_eq!(Err(Errors::KeyIsEmpty), engine.get_entry(Bytes::new()));
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_store_timestamps() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  opts.data_file_size = 1024;
+  opts.file_merge_threshold = 0.0;
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  engine.put(get_test_key(0), get_test_value(0)).unwrap();
+  drop(engine);
+
+  // records written before the option was turned on have no timestamp
+  opts.store_timestamps = true;
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  let before = SystemTime::now() - Duration::from_millis(1);
+  for i in 1..50 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  let batch = engine
+    .new_write_batch(option::WriteBatchOptions::default())
+    .unwrap();
+  batch.put(get_test_key(50), get_test_value(50)).unwrap();
+  batch.commit().unwrap();
+  let after = SystemTime::now();
+  assert_eq!(None, engine.get_entry(get_test_key(0)).unwrap().timestamp);
+  let stamped = engine
+    .get_entry(get_test_key(1))
+    .unwrap()
+    .timestamp
+    .unwrap();
+  assert!(before <= stamped && stamped <= after);
+  let batched = engine.get_entry(get_test_key(50)).unwrap();
+  assert!(batched.timestamp.is_some());
+  assert_eq!(get_test_value(50), batched.value);
+
+  // a merge keeps the original write times
+  engine.merge().unwrap();
+  engine.close().unwrap();
+  drop(engine);
+  opts.store_timestamps = false;
+  let engine = Engine::open(opts).expect("fail to open engine");
+  assert_eq!(
+    Some(stamped),
+    engine.get_entry(get_test_key(1)).unwrap().timestamp
+  );
+  assert_eq!(None, engine.get_entry(get_test_key(0)).unwrap().timestamp);
+  engine.put(get_test_key(1), get_test_value(1)).unwrap();
+  let entry = engine.get_entry(get_test_key(1)).unwrap();
+  assert_eq!(None, entry.timestamp);
+  assert_eq!(get_test_value(1), entry.value);
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_iterator_across_reader_reload() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  opts.data_file_size = 1024;
+  opts.file_merge_threshold = 0.0;
+  let mut reader_opts = opts.clone();
+  reader_opts.shared_readers = true;
+
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  for round in 0..2 {
+    for i in 0..100 {
+      let value = Bytes::from(format!("value-{round}-{i:03}"));
+      engine.put(get_test_key(i), value).unwrap();
+    }
+  }
+  engine.delete(get_test_key(99)).unwrap();
+  let reader = Engine::open(reader_opts).expect("fail to open reader");
+  let iter = reader.iter(option::IteratorOptions::default());
+  for i in 0..10 {
+    assert_eq!(Some(get_test_key(i)), iter.next().map(|(key, _)| key));
+  }
+  // a key updated after the iterator was created is read at its position after the merge
+  engine
+    .put(get_test_key(50), Bytes::from("value-2-050"))
+    .unwrap();
+  reader.refresh().unwrap();
+
+  // the merged files replace the read ones under the same ids
+  engine.merge().unwrap();
+  engine.close().unwrap();
+  drop(engine);
+  let engine = Engine::open(opts).expect("fail to open engine");
+  reader.refresh().unwrap();
+  for i in 10..99 {
+    let (key, value) = iter.next().unwrap();
+    assert_eq!(get_test_key(i), key);
+    let round = if i == 50 { 2 } else { 1 };
+    assert_eq!(Bytes::from(format!("value-{round}-{i:03}")), value);
+  }
+  assert!(iter.next().is_none());
+
+  // keys cleared since are skipped
+  let iter = reader.iter(option::IteratorOptions::default());
+  let folded = |reader: &Engine| {
+    reader
+      .fold(option::IteratorOptions::default(), 0, |count, _, _| {
+        count + 1
+      })
+      .unwrap()
+  };
+  assert_eq!(99, folded(&reader));
+  engine.clear().unwrap();
+  reader.refresh().unwrap();
+  assert!(iter.next().is_none());
+  assert_eq!(0, folded(&reader));
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_stat_disk_size() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  opts.data_file_size = 4096;
+  opts.file_merge_threshold = 0.0;
+  let engine = Engine::open(opts).expect("fail to open engine");
+  let exact = option::StatOptions { approximate: false };
+  let disk_size = |options| engine.get_engine_stat_with(options).unwrap().disk_size;
+
+  engine.put(get_test_key(0), get_test_value(0)).unwrap();
+  let size = disk_size(exact);
+  assert_eq!(size, engine.get_engine_stat().unwrap().disk_size);
+
+  // writes to the active file are added to the measured size, rotations measure again
+  for i in 0..10 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    assert_eq!(
+      disk_size(exact),
+      engine.get_engine_stat().unwrap().disk_size
+    );
+  }
+  assert!(disk_size(exact) > size);
+  for i in 0..200 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  assert!(engine.get_engine_stat().unwrap().data_file_num > 1);
+  assert_eq!(disk_size(option::StatOptions::default()), disk_size(exact));
+
+  engine.merge().unwrap();
+  assert_eq!(
+    engine.get_engine_stat().unwrap().disk_size,
+    disk_size(exact)
+  );
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_key_comparator() {
+  let comparator = KeyComparator::new(|a, b| a.to_ascii_lowercase().cmp(&b.to_ascii_lowercase()));
+  for index_type in [IndexType::BTree, IndexType::SkipList] {
+    let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+    let mut opts = Options::default();
+    opts.dir_path = temp_dir.path().to_path_buf();
+    opts.index_type = index_type;
+    opts.key_comparator = Some(comparator.clone());
+    let engine = Engine::open(opts).expect("fail to open engine");
+    for key in ["b", "A", "c", "a", "B", "D"] {
+      engine.put(Bytes::from(key), Bytes::from("value")).unwrap();
+    }
+
+    // keys equal but for the case come in byte order
+    let keys = engine.list_keys().unwrap();
+    assert_eq!(vec!["A", "a", "B", "b", "c", "D"], keys);
+
+    let scan = |options: option::IteratorOptions, seek: &[u8]| {
+      let iter = engine.iter(options);
+      if !seek.is_empty() {
+        iter.seek(seek.to_vec());
+      }
+      let mut keys = Vec::new();
+      while let Some((key, _)) = iter.next() {
+        keys.push(key);
+      }
+      keys
+    };
+    let options = option::IteratorOptions::default();
+    assert_eq!(vec!["b", "c", "D"], scan(options.clone(), b"b"));
+    assert_eq!(vec!["c", "D"], scan(options, b"bb"));
+    let mut options = option::IteratorOptions::default();
+    options.reverse = true;
+    assert_eq!(vec!["B", "a", "A"], scan(options.clone(), b"B"));
+    options.start_after = Some(b"b".to_vec());
+    assert_eq!(vec!["B", "a", "A"], scan(options, b""));
+  }
+
+  let mut opts = Options::default();
+  opts.index_type = IndexType::BPlusTree;
+  opts.key_comparator = Some(comparator);
+  assert!(opts.validate().is_err());
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+#[cfg(feature = "mmap")]
+fn test_engine_mmap_reads() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  opts.data_file_size = 4096;
+  opts.runtime_io = option::RuntimeIo::MemoryMap;
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  for i in 0..300 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  assert!(engine.get_engine_stat().unwrap().data_file_num > 1);
+
+  // values of sealed files are slices of their maps, the active file is read into copies
+  let check = |engine: &Engine| {
+    let sealed = engine.get(get_test_key(0)).unwrap();
+    assert_eq!(get_test_value(0), sealed);
+    assert_eq!(
+      sealed.as_ptr(),
+      engine.get(get_test_key(0)).unwrap().as_ptr()
+    );
+    let active = engine.get(get_test_key(299)).unwrap();
+    assert_eq!(get_test_value(299), active);
+    assert_ne!(
+      active.as_ptr(),
+      engine.get(get_test_key(299)).unwrap().as_ptr()
+    );
+  };
+  check(&engine);
+  engine.close().unwrap();
+  drop(engine);
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  check(&engine);
+
+  let mut opts = opts;
+  opts.max_open_files = 8;
+  assert_eq!(Err(Errors::MmapReadsUnsupported), opts.validate());
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_preallocate_data_file() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  opts.data_file_size = 64 * 1024;
+  opts.preallocate_data_file = true;
+  let physical_size = |file_id| {
+    fs::metadata(get_data_file_name(temp_dir.path(), file_id))
+      .unwrap()
+      .len()
+  };
+
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  for i in 0..100 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  let active_file_id = engine.active_data_file.read().get_file_id();
+  let write_off = engine.active_data_file.read().get_write_off();
+  assert_eq!(64 * 1024, physical_size(active_file_id));
+  assert_eq!(write_off, engine.active_data_file.read().file_size());
+
+  // a crash leaves the allocated space behind, it reads as the end of the file
+  let wal = fs::read(get_data_file_name(temp_dir.path(), active_file_id)).unwrap();
+  engine.close().unwrap();
+  assert_eq!(write_off, physical_size(active_file_id));
+  drop(engine);
+  fs::write(get_data_file_name(temp_dir.path(), active_file_id), &wal).unwrap();
+
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  assert_eq!(write_off, engine.active_data_file.read().get_write_off());
+  assert_eq!(100, engine.list_keys().unwrap().len());
+  for i in 100..2000 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+
+  // sealed files give their space back, their footers are found at the end
+  let old_files = engine.old_data_files.read();
+  assert!(!old_files.is_empty());
+  for data_file in old_files.values() {
+    assert!(data_file.verify_footer().unwrap());
+  }
+  drop(old_files);
+  engine.close().unwrap();
+  drop(engine);
+
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  assert_eq!(2000, engine.list_keys().unwrap().len());
+  assert_eq!(
+    get_test_value(1999),
+    engine.get(get_test_key(1999)).unwrap()
+  );
+
+  let mut opts = opts;
+  opts.storage_mode = StorageMode::Memory;
+  assert_eq!(Err(Errors::PreallocationUnsupported), opts.validate());
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_tombstone_sidecar() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  opts.data_file_size = 8 * 1024;
+  opts.file_merge_threshold = 0.0;
+  opts.tombstone_sidecar = true;
+
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  for i in 0..300 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  for i in (0..300).step_by(2) {
+    engine.delete(get_test_key(i)).unwrap();
+  }
+  // written again after its delete, the marker names the older record
+  engine.put(get_test_key(0), get_test_value(1000)).unwrap();
+  let active_file_id = engine.active_data_file.read().get_file_id();
+  assert!(get_tombstone_file_name(temp_dir.path(), active_file_id).is_file());
+  engine.close().unwrap();
+  drop(engine);
+
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  assert_eq!(151, engine.list_keys().unwrap().len());
+  assert_eq!(get_test_value(1000), engine.get(get_test_key(0)).unwrap());
+  assert_eq!(Err(Errors::KeyNotFound), engine.get(get_test_key(2)));
+  assert_eq!(get_test_value(1), engine.get(get_test_key(1)).unwrap());
+
+  // records of the merged files are deleted with delete records until the merge is loaded
+  engine.merge().unwrap();
+  engine.delete(get_test_key(1)).unwrap();
+  engine.close().unwrap();
+  drop(engine);
+
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  assert_eq!(150, engine.list_keys().unwrap().len());
+  assert_eq!(Err(Errors::KeyNotFound), engine.get(get_test_key(1)));
+  engine.delete(get_test_key(3)).unwrap();
+  engine.close().unwrap();
+  drop(engine);
+
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  assert_eq!(149, engine.list_keys().unwrap().len());
+  assert_eq!(Err(Errors::KeyNotFound), engine.get(get_test_key(3)));
+  assert_eq!(get_test_value(5), engine.get(get_test_key(5)).unwrap());
+
+  let mut opts = opts;
+  opts.storage_mode = StorageMode::Memory;
+  assert_eq!(Err(Errors::TombstoneSidecarUnsupported), opts.validate());
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_data_files() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  opts.data_file_size = 8 * 1024;
+
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  for i in 0..300 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  for i in 0..100 {
+    engine.delete(get_test_key(i)).unwrap();
+  }
+
+  let files = engine.data_files();
+  assert_eq!(engine.get_engine_stat().unwrap().data_file_num, files.len());
+  assert!(files.windows(2).all(|w| w[0].file_id < w[1].file_id));
+  let active = files.last().unwrap();
+  assert_eq!(engine.active_data_file.read().get_file_id(), active.file_id);
+  assert_eq!(engine.active_data_file.read().get_write_off(), active.size);
+  for file in &files {
+    assert_eq!(file.size, file.live_bytes + file.dead_bytes);
+  }
+  let dead_bytes: u64 = files.iter().map(|file| file.dead_bytes).sum();
+  assert_eq!(
+    engine.reclaim_size.load(Ordering::SeqCst) as u64,
+    dead_bytes
+  );
+  assert!(files[0].dead_bytes > 0);
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_open_read_only() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let backup_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  opts.data_file_size = 8 * 1024;
+
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  for i in 0..300 {
+    engine.put(get_test_key(i), get_test_value(i)).unwrap();
+  }
+  engine.backup(backup_dir.path()).unwrap();
+  let list_files = |dir: &std::path::Path| {
+    let mut files: Vec<_> = fs::read_dir(dir)
+      .unwrap()
+      .map(|entry| {
+        let entry = entry.unwrap();
+        (entry.file_name(), entry.metadata().unwrap().len())
+      })
+      .collect();
+    files.sort();
+    files
+  };
+  let backup_files = list_files(backup_dir.path());
+
+  // the primary stays open, the backup is verified next to it
+  let mut read_opts = opts.clone();
+  read_opts.dir_path = backup_dir.path().to_path_buf();
+  read_opts.read_only = true;
+  let backup = Engine::open(read_opts.clone()).expect("fail to open backup");
+  assert_eq!(300, backup.list_keys().unwrap().len());
+  assert_eq!(get_test_value(42), backup.get(get_test_key(42)).unwrap());
+  assert_eq!(
+    Err(Errors::ReadOnlyEngine),
+    backup.put(get_test_key(1), get_test_value(1))
+  );
+  assert_eq!(Err(Errors::ReadOnlyEngine), backup.merge());
+  backup.close().unwrap();
+  drop(backup);
+  assert_eq!(backup_files, list_files(backup_dir.path()));
+
+  // the live database can be opened as well
+  read_opts.dir_path = temp_dir.path().to_path_buf();
+  let reader = Engine::open(read_opts.clone()).expect("fail to open read only");
+  assert_eq!(300, reader.list_keys().unwrap().len());
+  drop(reader);
+
+  // nothing is created for an empty or missing directory
+  let empty_dir = tempfile::tempdir().expect("failed to create temp dir");
+  read_opts.dir_path = empty_dir.path().to_path_buf();
+  let empty = Engine::open(read_opts.clone()).expect("fail to open read only");
+  assert!(empty.list_keys().unwrap().is_empty());
+  drop(empty);
+  assert!(list_files(empty_dir.path()).is_empty());
+  read_opts.dir_path = empty_dir.path().join("missing");
+  assert!(Engine::open(read_opts.clone()).is_err());
+  assert!(!read_opts.dir_path.exists());
+
+  read_opts.repair_on_open = true;
+  assert_eq!(Err(Errors::ReadOnlyUnsupported), read_opts.validate());
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_change_id() {
+  use crate::option::{IteratorOptions, WriteBatchOptions};
+
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().to_path_buf();
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+  assert_eq!(0, engine.change_id());
+
+  engine.put(get_test_key(1), get_test_value(1)).unwrap();
+  engine.put(get_test_key(2), get_test_value(2)).unwrap();
+  assert_eq!(2, engine.change_id());
+
+  // reads and deletes of missing keys change nothing
+  engine.get(get_test_key(1)).unwrap();
+  engine.delete(get_test_key(3)).unwrap();
+  let page = engine.list_keys_paged(None, None, 10).unwrap();
+  assert_eq!(2, page.change_id);
+  let iter = engine.iter(IteratorOptions::default());
+  assert_eq!(2, iter.change_id());
+  drop(iter);
+
+  // a batch is one change
+  let batch = engine
+    .new_write_batch(WriteBatchOptions::default())
+    .unwrap();
+  batch.put(get_test_key(3), get_test_value(3)).unwrap();
+  batch.delete(get_test_key(1)).unwrap();
+  batch.commit().unwrap();
+  assert_eq!(3, engine.change_id());
+
+  engine
+    .expire(get_test_key(2), SystemTime::now() + Duration::from_secs(60))
+    .unwrap();
+  engine.delete(get_test_key(3)).unwrap();
+  assert_eq!(5, engine.change_id());
+  assert_ne!(page.change_id, engine.change_id());
+
+  // a merge moves records without changing them
+  engine.merge().ok();
+  assert_eq!(5, engine.change_id());
+  engine.clear().unwrap();
+  assert_eq!(6, engine.change_id());
+}
+
+// value of `key` written in `round`, reads check the key part
+fn stress_value(key: usize, round: usize) -> Bytes {
+  Bytes::from(format!("{key}-{round}"))
+}
+
+// reads every key until `done`, a read either finds a value of its key or no key at all
+fn stress_reads(engine: &Engine, keys: usize, done: &std::sync::atomic::AtomicBool) -> usize {
+  let mut reads = 0;
+  while !done.load(Ordering::SeqCst) {
+    for i in 0..keys {
+      let prefix = format!("{i}-");
+      match engine.get(get_test_key(i)) {
+        Ok(value) => assert!(value.starts_with(prefix.as_bytes()), "{value:?}"),
+        Err(Errors::KeyNotFound) => {}
+        Err(e) => panic!("read of key {i} failed: {e}"),
+      }
+      match engine.get_entry(get_test_key(i)) {
+        Ok(entry) => assert!(entry.value.starts_with(prefix.as_bytes())),
+        Err(Errors::KeyNotFound) => {}
+        Err(e) => panic!("read of entry {i} failed: {e}"),
+      }
+      reads += 2;
+    }
+  }
+  reads
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_reads_during_rotation() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().join("db");
+  opts.data_file_size = 4 * 1024;
+  let engine = Engine::open(opts).expect("fail to open engine");
+  let keys = 50;
+  for i in 0..keys {
+    engine.put(get_test_key(i), stress_value(i, 0)).unwrap();
+  }
+
+  let done = std::sync::atomic::AtomicBool::new(false);
+  std::thread::scope(|s| {
+    let readers: Vec<_> = (0..4)
+      .map(|_| s.spawn(|| stress_reads(&engine, keys, &done)))
+      .collect();
+    // size based rotations, and explicit ones in between
+    for round in 1..200 {
+      for i in 0..keys {
+        engine.put(get_test_key(i), stress_value(i, round)).unwrap();
+      }
+      if round % 10 == 0 {
+        engine.flush_and_rotate().unwrap();
+      }
+    }
+    done.store(true, Ordering::SeqCst);
+    for reader in readers {
+      assert!(reader.join().unwrap() > 0);
+    }
+  });
+  assert!(engine.get_engine_stat().unwrap().data_file_num > 50);
+  for i in 0..keys {
+    assert_eq!(stress_value(i, 199), engine.get(get_test_key(i)).unwrap());
+  }
+}
+
+#[allow(clippy::field_reassign_with_default)]
+#[test]
+fn test_engine_reads_during_clear() {
+  let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+  let mut opts = Options::default();
+  opts.dir_path = temp_dir.path().join("db");
+  opts.data_file_size = 4 * 1024;
+  let engine = Engine::open(opts.clone()).expect("fail to open engine");
+
+  let mut reader_opts = opts.clone();
+  reader_opts.shared_readers = true;
+  let reader = Engine::open(reader_opts).expect("fail to open reader");
+
+  // the writer removes the files under its own reads, the reader reloads under its reads
+  let keys = 50;
+  let done = std::sync::atomic::AtomicBool::new(false);
+  std::thread::scope(|s| {
+    let readers: Vec<_> = [&engine, &engine, &reader, &reader]
+      .into_iter()
+      .map(|engine| s.spawn(|| stress_reads(engine, keys, &done)))
+      .collect();
+    let refresher = s.spawn(|| {
+      while !done.load(Ordering::SeqCst) {
+        reader.refresh().unwrap();
+      }
+    });
+    for round in 0..50 {
+      for i in 0..keys {
+        engine.put(get_test_key(i), stress_value(i, round)).unwrap();
+      }
+      engine.clear().unwrap();
+      for i in 0..keys / 2 {
+        engine.put(get_test_key(i), stress_value(i, round)).unwrap();
+      }
+    }
+    done.store(true, Ordering::SeqCst);
+    for reader in readers {
+      assert!(reader.join().unwrap() > 0);
+    }
+    refresher.join().unwrap();
+  });
+  reader.refresh().unwrap();
+  assert_eq!(keys / 2, reader.list_keys().unwrap().len());
+}
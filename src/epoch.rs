@@ -0,0 +1,268 @@
+use std::{
+  collections::BTreeMap,
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+  },
+  time::SystemTime,
+};
+
+use bytes::Bytes;
+
+use crate::{
+  data::log_record::LogRecordPos,
+  db::Engine,
+  errors::{Errors, Result},
+  expiry::unix_millis,
+  iterator::PositionEpoch,
+  option::IteratorOptions,
+};
+
+/// A view of the keys pinned by [`Engine::pin_epoch`], read with [`Engine::read_at_epoch`].
+///
+/// The view holds the position of every key, the engine keeps the data files they point
+/// into until the epoch is released by dropping it.
+pub struct ReadEpoch {
+  change_id: u64,
+  keys: BTreeMap<Vec<u8>, LogRecordPos>,
+  positions: Arc<PositionEpoch>, // epoch of `keys`, resolves them after a reader reload
+  pins: Arc<AtomicUsize>,        // epochs pinned on the engine
+}
+
+/// A consistent view of the engine handed to the closure of [`Engine::with_snapshot`].
+pub struct Snapshot<'a> {
+  engine: &'a Engine,
+  epoch: ReadEpoch,
+}
+
+impl Engine {
+  /// Calls `f` with a snapshot of the current keys and releases the snapshot once `f`
+  /// returns, also when it panics.
+  ///
+  /// The snapshot pins a read epoch while `f` runs: reads through it see the values the keys
+  /// had when `f` was called, whatever is written or merged meanwhile, and clears fail with
+  /// `Errors::EpochPinned`. Unlike the epoch of `pin_epoch` it cannot be kept past the call.
+  pub fn with_snapshot<T>(&self, f: impl FnOnce(&Snapshot) -> T) -> Result<T> {
+    let snapshot = Snapshot {
+      engine: self,
+      epoch: self.pin_epoch()?,
+    };
+    Ok(f(&snapshot))
+  }
+
+  /// Pins the current keys of the engine, their values can be read with `read_at_epoch`
+  /// until the returned epoch is dropped, however the keys are written, merged or compacted
+  /// meanwhile. Clears fail with `Errors::EpochPinned` while an epoch is pinned.
+  ///
+  /// Meant for backups and verification jobs reading a stable view: the epoch holds a copy
+  /// of the key directory, keys expired when pinning are left out.
+  pub fn pin_epoch(&self) -> Result<ReadEpoch> {
+    self.check_open()?;
+    // counted first, a clear checking the pins afterwards does not remove the files
+    self.pinned_epochs.fetch_add(1, Ordering::SeqCst);
+    let mut epoch = ReadEpoch {
+      change_id: self.change_id(),
+      keys: BTreeMap::new(),
+      positions: Arc::default(),
+      pins: Arc::clone(&self.pinned_epochs),
+    };
+
+    // the index is not rebuilt while its positions are taken
+    let positions = self.position_epoch.read();
+    let now = unix_millis(SystemTime::now());
+    let mut iter = self.index.try_iterator(IteratorOptions::default())?;
+    while let Some((key, pos)) = iter.next() {
+      if !self.expiry.is_expired(key, now) {
+        epoch.keys.insert(key.to_vec(), *pos);
+      }
+    }
+    epoch.positions = Arc::clone(&positions);
+    Ok(epoch)
+  }
+
+  /// Reads the value `key` had when `epoch` was pinned.
+  ///
+  /// # Errors
+  ///
+  /// Returns `KeyNotFound` if the key did not exist then, and `UnknownReadEpoch` if the
+  /// epoch was pinned on another engine.
+  pub fn read_at_epoch(&self, epoch: &ReadEpoch, key: Bytes) -> Result<Bytes> {
+    self.check_open()?;
+    if !Arc::ptr_eq(&epoch.pins, &self.pinned_epochs) {
+      return Err(Errors::UnknownReadEpoch);
+    }
+    let pos = epoch.keys.get(&key[..]).ok_or(Errors::KeyNotFound)?;
+    // only a reader reloading the files of a merge moves the positions
+    let pos = epoch.positions.resolve(*pos).ok_or(Errors::KeyNotFound)?;
+    self.get_value_by_position(&pos)
+  }
+
+  /// Fails with `Errors::EpochPinned` while epochs are pinned.
+  pub(crate) fn check_no_pinned_epochs(&self) -> Result<()> {
+    match self.pinned_epochs.load(Ordering::SeqCst) {
+      0 => Ok(()),
+      _ => Err(Errors::EpochPinned),
+    }
+  }
+}
+
+impl ReadEpoch {
+  /// `Engine::change_id` when the epoch was pinned.
+  pub fn change_id(&self) -> u64 {
+    self.change_id
+  }
+
+  /// The keys of the epoch in byte order.
+  pub fn keys(&self) -> impl std::iter::Iterator<Item = &[u8]> {
+    self.keys.keys().map(|key| &key[..])
+  }
+
+  pub fn len(&self) -> usize {
+    self.keys.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.keys.is_empty()
+  }
+}
+
+impl Snapshot<'_> {
+  /// Reads the value `key` had when the snapshot was taken.
+  ///
+  /// # Errors
+  ///
+  /// Returns `KeyNotFound` if the key did not exist then.
+  pub fn get(&self, key: Bytes) -> Result<Bytes> {
+    self.engine.read_at_epoch(&self.epoch, key)
+  }
+
+  /// `Engine::change_id` when the snapshot was taken.
+  pub fn change_id(&self) -> u64 {
+    self.epoch.change_id()
+  }
+
+  /// The keys of the snapshot in byte order.
+  pub fn keys(&self) -> impl std::iter::Iterator<Item = &[u8]> {
+    self.epoch.keys()
+  }
+
+  pub fn len(&self) -> usize {
+    self.epoch.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.epoch.is_empty()
+  }
+}
+
+impl Drop for ReadEpoch {
+  fn drop(&mut self) {
+    self.pins.fetch_sub(1, Ordering::SeqCst);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::option::Options;
+
+  #[test]
+  fn test_read_at_epoch() {
+    let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+    let opts = Options {
+      dir_path: temp_dir.path().join("db"),
+      data_file_size: 4096,
+      file_merge_threshold: 0.0,
+      ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    let key = |i: usize| Bytes::from(format!("key-{i:03}"));
+    for i in 0..200 {
+      engine.put(key(i), Bytes::from("old")).unwrap();
+    }
+    let epoch = engine.pin_epoch().unwrap();
+    assert_eq!(200, epoch.len());
+    assert_eq!(engine.change_id(), epoch.change_id());
+
+    // writes and merges after the pin are not seen by the epoch
+    for i in 0..100 {
+      engine.put(key(i), Bytes::from("new")).unwrap();
+      engine.delete(key(i + 100)).unwrap();
+    }
+    engine.put(key(300), Bytes::from("new")).unwrap();
+    engine.merge().unwrap();
+    for i in 0..200 {
+      assert_eq!(
+        Bytes::from("old"),
+        engine.read_at_epoch(&epoch, key(i)).unwrap()
+      );
+    }
+    assert_eq!(
+      Errors::KeyNotFound,
+      engine.read_at_epoch(&epoch, key(300)).unwrap_err()
+    );
+    assert_eq!(Bytes::from("new"), engine.get(key(0)).unwrap());
+
+    assert_eq!(Errors::EpochPinned, engine.clear().unwrap_err());
+    let other = Engine::open(Options {
+      dir_path: temp_dir.path().join("other"),
+      ..Default::default()
+    })
+    .expect("failed to open engine");
+    assert_eq!(
+      Errors::UnknownReadEpoch,
+      other.read_at_epoch(&epoch, key(0)).unwrap_err()
+    );
+
+    drop(epoch);
+    engine.clear().unwrap();
+  }
+
+  #[test]
+  fn test_with_snapshot() {
+    let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+    let opts = Options {
+      dir_path: temp_dir.path().to_path_buf(),
+      data_file_size: 4096,
+      file_merge_threshold: 0.0,
+      ..Default::default()
+    };
+    let engine = Engine::open(opts).expect("failed to open engine");
+    let key = |i: usize| Bytes::from(format!("key-{i:03}"));
+    for i in 0..100 {
+      engine.put(key(i), Bytes::from("old")).unwrap();
+    }
+
+    let values = engine
+      .with_snapshot(|snapshot| {
+        assert_eq!(100, snapshot.len());
+        assert_eq!(engine.change_id(), snapshot.change_id());
+        for i in 0..50 {
+          engine.put(key(i), Bytes::from("new")).unwrap();
+        }
+        engine.put(key(100), Bytes::from("new")).unwrap();
+        engine.merge().unwrap();
+        assert_eq!(Errors::EpochPinned, engine.clear().unwrap_err());
+        assert_eq!(Errors::KeyNotFound, snapshot.get(key(100)).unwrap_err());
+        assert_eq!(key(0).to_vec(), snapshot.keys().next().unwrap());
+        (0..100)
+          .map(|i| snapshot.get(key(i)).unwrap())
+          .collect::<Vec<_>>()
+      })
+      .unwrap();
+    assert!(values.iter().all(|value| value == "old"));
+    assert_eq!(Bytes::from("new"), engine.get(key(0)).unwrap());
+
+    // the snapshot is released when the closure returns or panics
+    let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+      engine.with_snapshot(|_| panic!("closure failed"))
+    }));
+    assert!(panicked.is_err());
+    engine.clear().unwrap();
+    engine.close().unwrap();
+    assert_eq!(
+      Errors::EngineClosed,
+      engine.with_snapshot(|_| ()).unwrap_err()
+    );
+  }
+}
@@ -1,19 +1,21 @@
-use std::{fmt::Debug, result};
+use std::{
+  fmt::{self, Debug, Display},
+  io, result,
+  sync::Arc,
+};
 use thiserror::Error;
 
-#[derive(Error, Debug, PartialEq)]
+#[derive(Error, Debug, Clone, PartialEq)]
 pub enum Errors {
-  #[error("failed to read from data file")]
-  FailedToReadFromDataFile,
-
-  #[error("failed to write to data file")]
-  FailedToWriteToDataFile,
-
-  #[error("failed to sync to data file")]
-  FailedToSyncToDataFile,
-
-  #[error("failed to open data file error")]
-  FailedToOpenDataFile,
+  /// An IO operation on a data file failed. The file id is known once the error passed the
+  /// data file, the offset for reads only
+  #[error("failed to {op} data file{}", location(*file_id, *offset))]
+  DataFileIo {
+    op: DataFileOp,
+    file_id: Option<u32>,
+    offset: Option<u64>,
+    source: IoError,
+  },
 
   #[error("the key is empty")]
   KeyIsEmpty,
@@ -51,6 +53,12 @@ pub enum Errors {
   #[error("exceed max batch number in one batch write")]
   ExceedMaxBatchNum,
 
+  #[error("exceed max batch bytes in one batch write")]
+  ExceedMaxBatchBytes,
+
+  #[error("batch does not fit into one transaction, committing it in chunks is not atomic")]
+  BatchNotAtomic,
+
   #[error("merge is in progress, try again later")]
   MergeInProgress,
 
@@ -63,6 +71,12 @@ pub enum Errors {
   #[error("invalid merge threshold value, must be in range (0, 1)")]
   InvalidMergeThreshold,
 
+  #[error("invalid merge policy, tiered merge needs at least one file")]
+  InvalidMergePolicy,
+
+  #[error("invalid index shards number, must be greater than 0")]
+  InvalidIndexShards,
+
   #[error("merge threshold is unreached")]
   MergeThresholdUnreached,
 
@@ -71,6 +85,387 @@ pub enum Errors {
 
   #[error("failed to copy the database directory")]
   FailedToCopyDirectory,
+
+  #[error("invalid data file checksum, data file maybe corrupted")]
+  InvalidDataFileChecksum,
+
+  #[error("invalid metrics push url, only http:// is supported")]
+  InvalidMetricsPushUrl,
+
+  #[error("failed to start background task")]
+  FailedToStartBackgroundTask,
+
+  #[error("the value is not a valid counter")]
+  InvalidCounterValue,
+
+  #[error("counter value overflow")]
+  CounterOverflow,
+
+  #[error("invalid log record, data file maybe corrupted")]
+  InvalidLogRecord,
+
+  #[error("invalid log record position, hint file or index maybe corrupted")]
+  InvalidLogRecordPos,
+
+  #[error("database dir path is invalid")]
+  InvalidDirPath,
+
+  #[error("failed to open the database lock file")]
+  FailedToOpenLockFile,
+
+  #[error("failed to release the database lock file")]
+  FailedToUnlockDatabaseDir,
+
+  #[error("failed to remove file in database directory")]
+  FailedToRemoveFile,
+
+  #[error("failed to rename file in database directory")]
+  FailedToRenameFile,
+
+  #[error("failed to open the index")]
+  FailedToOpenIndex,
+
+  #[error("failed to read the index")]
+  FailedToReadIndex,
+
+  #[error("failed to update the index")]
+  FailedToUpdateIndex,
+
+  #[error("index memory limit exceeded")]
+  IndexMemoryLimitExceeded,
+
+  #[error("index memory limit is only supported by the skiplist index")]
+  IndexMemoryLimitUnsupported,
+
+  #[error("index shards are only supported by the btree index")]
+  IndexShardsUnsupported,
+
+  #[error("a custom indexer can not replace the b+ tree index")]
+  CustomIndexerUnsupported,
+
+  #[error("invalid engine options config")]
+  InvalidOptionsConfig,
+
+  #[error("index type is not supported, enable its cargo feature")]
+  IndexTypeUnsupported,
+
+  #[error("write rejected, too much data is waiting for merge")]
+  Backpressure,
+
+  #[error("key expiration is not supported by the B+ tree index")]
+  ExpireUnsupported,
+
+  #[error("failed to write export file")]
+  FailedToWriteExport,
+
+  #[error("failed to read import file")]
+  FailedToReadImport,
+
+  #[error("import file is not a valid export")]
+  InvalidImportFile,
+
+  #[error("sharded engine needs at least one directory")]
+  ShardDirsIsEmpty,
+
+  #[error("shard directory belongs to a different shard layout")]
+  ShardLayoutMismatch,
+
+  #[error("failed to write shard file")]
+  FailedToWriteShardFile,
+
+  #[error("key is not a valid composite key encoding")]
+  InvalidKeyEncoding,
+
+  #[error("engine is opened read-only as a shared reader")]
+  ReadOnlyEngine,
+
+  #[error("shared readers are not supported by the B+ tree and hybrid indexes")]
+  SharedReadersUnsupported,
+
+  #[error("failed to read value from reader")]
+  FailedToReadValue,
+
+  #[error("value does not fit into a data file")]
+  ValueTooLarge,
+
+  #[error("bulk load keys are not sorted and unique")]
+  BulkLoadKeysUnsorted,
+
+  #[error("merge window must lie within a day")]
+  InvalidMergeWindow,
+
+  #[error("merge is only allowed within the merge windows")]
+  MergeOutsideWindow,
+
+  #[error("engine is closed")]
+  EngineClosed,
+
+  #[error("hybrid index needs at least one hot key")]
+  InvalidIndexHotKeys,
+
+  #[error("prefix quota exceeded")]
+  QuotaExceeded,
+
+  #[error("not supported in memory storage mode")]
+  MemoryModeUnsupported,
+
+  #[error("encryption is not available in this build or not supported by the operation")]
+  EncryptionUnsupported,
+
+  #[error("failed to encrypt log record")]
+  EncryptionFailed,
+
+  #[error("failed to decrypt log record, the encryption key is wrong or missing")]
+  DecryptionFailed,
+
+  #[error("multi-engine batch needs one or more distinct engines")]
+  InvalidBatchEngines,
+
+  #[error("engine index is out of the range of the multi-engine batch")]
+  InvalidEngineIndex,
+
+  #[error("prepared multi-engine transactions are pending, recover them first")]
+  PreparedTxnPending,
+
+  #[error("key comparator is not supported by the index type")]
+  KeyComparatorUnsupported,
+
+  #[error("repair on open is not supported by shared readers")]
+  RepairOnOpenUnsupported,
+
+  #[error("memory mapped reads need the mmap feature and disk storage without max_open_files")]
+  MmapReadsUnsupported,
+
+  #[error("data file preallocation is not supported in memory storage mode or with io_uring")]
+  PreallocationUnsupported,
+
+  #[error("tombstone sidecars are not supported in memory storage mode, with an on disk index or shared readers")]
+  TombstoneSidecarUnsupported,
+
+  #[error("invalid sync policy, adaptive syncing needs 0 < min <= max")]
+  InvalidSyncPolicy,
+
+  #[error("read only open is not supported in memory storage mode, with an on disk index or repair on open")]
+  ReadOnlyUnsupported,
+
+  #[error("no savepoint to roll back to")]
+  NoSavepoint,
+
+  #[error("merge output does not match the manifest of the merge finished file")]
+  MergeManifestMismatch,
+
+  #[error("startup threads must be at least 1")]
+  InvalidStartupThreads,
+
+  #[error("index dump is not valid")]
+  InvalidIndexDump,
+
+  #[error("blob file not found")]
+  BlobFileNotFound,
+
+  #[error("blob pointer does not match a value of the blob file")]
+  InvalidBlobPointer,
+
+  #[error("engine name is registered for another directory")]
+  EngineNameTaken,
+
+  #[error("transaction without commit record found on open")]
+  OrphanedTxnFound,
+
+  #[error("a read epoch is pinned")]
+  EpochPinned,
+
+  #[error("read epoch was pinned on another engine")]
+  UnknownReadEpoch,
+
+  #[error("no space left on device")]
+  DiskFull,
+
+  /// A log record runs past the end of its data file, `available` bytes are left from `offset`
+  #[error("log record at offset {offset} is truncated, {available} bytes left in data file")]
+  TruncatedLogRecord { offset: u64, available: u64 },
+
+  /// A record header without key and value followed by data, zeros in the middle of a file
+  #[error("log record at offset {offset} has no key and value, data file maybe corrupted")]
+  EmptyLogRecord { offset: u64 },
+
+  #[error("change data capture output is not supported by readers")]
+  CdcOutputUnsupported,
+
+  #[error("failed to open the change data capture output")]
+  FailedToOpenCdcOutput,
+
+  #[error("invalid change data capture frame")]
+  InvalidCdcFrame,
+
+  #[error("failed to read the change data capture stream")]
+  FailedToReadCdcStream,
+
+  #[error("failed to write the change data capture output")]
+  FailedToWriteCdcOutput,
+
+  #[error("a merge thread stopped before the merge finished")]
+  MergeThreadStopped,
+
+  #[error("stats dump path is empty or its interval is zero")]
+  InvalidStatsDump,
+
+  #[error("stats dump is not configured")]
+  StatsDumpNotConfigured,
+
+  #[error("failed to open the stats dump file")]
+  FailedToOpenStatsDump,
 }
 
 pub type Result<T> = result::Result<T, Errors>;
+
+impl Errors {
+  /// A failed IO operation on a data file, see [`Errors::DataFileIo`].
+  /// A full volume is reported as [`Errors::DiskFull`] instead.
+  pub fn data_file_io(op: DataFileOp, source: io::Error) -> Self {
+    if source.kind() == io::ErrorKind::StorageFull {
+      return Errors::DiskFull;
+    }
+    Errors::DataFileIo {
+      op,
+      file_id: None,
+      offset: None,
+      source: IoError::from(source),
+    }
+  }
+
+  /// A failed read at `offset` of a data file.
+  pub fn data_file_read(offset: u64, source: io::Error) -> Self {
+    Errors::DataFileIo {
+      op: DataFileOp::Read,
+      file_id: None,
+      offset: Some(offset),
+      source: IoError::from(source),
+    }
+  }
+
+  /// Attaches the id of the data file whose IO manager raised the error.
+  pub(crate) fn in_data_file(self, id: u32) -> Self {
+    match self {
+      Errors::DataFileIo {
+        op,
+        file_id: None,
+        offset,
+        source,
+      } => Errors::DataFileIo {
+        op,
+        file_id: Some(id),
+        offset,
+        source,
+      },
+      e => e,
+    }
+  }
+
+  /// The IO error behind the error, if it has one.
+  pub fn io_error(&self) -> Option<&io::Error> {
+    match self {
+      Errors::DataFileIo { source, .. } => Some(source.get_ref()),
+      _ => None,
+    }
+  }
+}
+
+fn location(file_id: Option<u32>, offset: Option<u64>) -> String {
+  match (file_id, offset) {
+    (Some(file_id), Some(offset)) => format!(" {file_id} at offset {offset}"),
+    (Some(file_id), None) => format!(" {file_id}"),
+    (None, Some(offset)) => format!(" at offset {offset}"),
+    (None, None) => String::new(),
+  }
+}
+
+/// IO operations on data files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataFileOp {
+  Open,
+  Read,
+  Write,
+  Sync,
+}
+
+impl Display for DataFileOp {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      DataFileOp::Open => "open",
+      DataFileOp::Read => "read",
+      DataFileOp::Write => "write",
+      DataFileOp::Sync => "sync",
+    })
+  }
+}
+
+/// An [`io::Error`] shared by the clones of the error carrying it, compared by its kind.
+#[derive(Debug, Clone)]
+pub struct IoError(Arc<io::Error>);
+
+impl IoError {
+  pub fn kind(&self) -> io::ErrorKind {
+    self.0.kind()
+  }
+
+  pub fn get_ref(&self) -> &io::Error {
+    &self.0
+  }
+}
+
+impl From<io::Error> for IoError {
+  fn from(e: io::Error) -> Self {
+    IoError(Arc::new(e))
+  }
+}
+
+impl PartialEq for IoError {
+  fn eq(&self, other: &Self) -> bool {
+    self.kind() == other.kind()
+  }
+}
+
+impl Display for IoError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    Display::fmt(&self.0, f)
+  }
+}
+
+impl std::error::Error for IoError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    self.0.source()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::error::Error;
+
+  use super::*;
+
+  #[test]
+  fn test_data_file_io_error() {
+    let err = Errors::data_file_read(42, io::Error::from(io::ErrorKind::UnexpectedEof));
+    assert_eq!("failed to read data file at offset 42", err.to_string());
+    let err = err.in_data_file(7);
+    assert_eq!("failed to read data file 7 at offset 42", err.to_string());
+    assert_eq!(
+      Some(io::ErrorKind::UnexpectedEof),
+      err.io_error().map(io::Error::kind)
+    );
+    assert!(err.source().is_some());
+
+    // the id of the innermost data file is kept
+    assert_eq!(err, err.clone().in_data_file(8));
+
+    let err = Errors::data_file_io(DataFileOp::Sync, io::Error::other("disk gone"));
+    assert_eq!("failed to sync data file", err.to_string());
+    assert_eq!("disk gone", err.source().unwrap().to_string());
+    assert_eq!(None, Errors::KeyNotFound.io_error().map(io::Error::kind));
+
+    // ENOSPC
+    let err = Errors::data_file_io(DataFileOp::Write, io::Error::from_raw_os_error(28));
+    assert_eq!(Errors::DiskFull, err);
+  }
+}
@@ -0,0 +1,117 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use parking_lot::RwLock;
+
+use crate::{db::Engine, errors::Errors, option::RuntimeOptions};
+
+/// How a read of a corrupted record was answered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CorruptionRecovery {
+  /// A fresh read through another IO manager returned an intact record
+  Reread,
+
+  /// The record was read from the copy written by the last merge
+  MergeOutput,
+
+  /// The index was repaired to the previous version of the key
+  PreviousVersion,
+
+  /// No intact version was left, the key was removed from the index
+  KeyRemoved,
+}
+
+/// A read which hit a broken record.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CorruptionReport {
+  /// The key being read
+  pub key: Bytes,
+
+  /// The file id of the broken record
+  pub file_id: u32,
+
+  /// The offset of the broken record
+  pub offset: u64,
+
+  /// The error hit by the read
+  pub error: Errors,
+
+  /// How the read was recovered, `None` if the error was returned to the caller
+  pub recovery: Option<CorruptionRecovery>,
+}
+
+/// A broken record found by the scrubber, see [`Engine::scrub`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScrubCorruption {
+  /// The data file holding the broken record
+  pub file_id: u32,
+
+  /// The offset of the broken record
+  pub offset: u64,
+
+  /// The error hit by reading the record
+  pub error: Errors,
+}
+
+/// Tunables before and after [`Engine::update_options`] changed them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OptionsUpdate {
+  pub previous: RuntimeOptions,
+  pub current: RuntimeOptions,
+}
+
+/// Receives engine events, every method has an empty default implementation.
+///
+/// Listeners are called on the thread hitting the event and should return quickly.
+pub trait EventListener: Send + Sync {
+  /// Called after a read hit a corrupted or missing record.
+  fn on_corruption(&self, _report: &CorruptionReport) {}
+
+  /// Called after `Engine::update_options` changed a tunable.
+  fn on_options_updated(&self, _update: &OptionsUpdate) {}
+
+  /// Called after the scrubber found a broken record in a data file.
+  fn on_scrub_corruption(&self, _corruption: &ScrubCorruption) {}
+
+  /// Called after a failed write stopped the change data capture stream.
+  fn on_cdc_stopped(&self, _error: &Errors) {}
+}
+
+/// Listeners registered with an engine.
+#[derive(Default)]
+pub(crate) struct EventListeners {
+  listeners: RwLock<Vec<Arc<dyn EventListener>>>,
+}
+
+impl EventListeners {
+  pub(crate) fn corruption(&self, report: &CorruptionReport) {
+    for listener in self.listeners.read().iter() {
+      listener.on_corruption(report);
+    }
+  }
+
+  pub(crate) fn options_updated(&self, update: &OptionsUpdate) {
+    for listener in self.listeners.read().iter() {
+      listener.on_options_updated(update);
+    }
+  }
+
+  pub(crate) fn scrub_corruption(&self, corruption: &ScrubCorruption) {
+    for listener in self.listeners.read().iter() {
+      listener.on_scrub_corruption(corruption);
+    }
+  }
+
+  pub(crate) fn cdc_stopped(&self, error: &Errors) {
+    for listener in self.listeners.read().iter() {
+      listener.on_cdc_stopped(error);
+    }
+  }
+}
+
+impl Engine {
+  /// Registers a listener for engine events.
+  pub fn add_event_listener(&self, listener: Arc<dyn EventListener>) {
+    self.listeners.listeners.write().push(listener);
+  }
+}
@@ -0,0 +1,310 @@
+use std::{
+  collections::{BTreeSet, HashMap},
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    mpsc::{self, RecvTimeoutError, Sender},
+    Arc,
+  },
+  thread::{self, JoinHandle},
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use log::warn;
+use parking_lot::Mutex;
+
+use crate::{
+  batch::{log_record_key_with_seq, NON_TXN_SEQ_NO},
+  cdc::CdcOp,
+  data::log_record::{LogRecord, LogRecordPos, LogRecordType},
+  db::Engine,
+  errors::{Errors, Result},
+  option::IndexType,
+};
+
+/// Expiration times of keys, ordered by time so due keys are found without a scan.
+#[derive(Default)]
+pub(crate) struct ExpiryIndex {
+  len: AtomicUsize, // lets writers skip the lock while no key expires
+  state: Mutex<ExpiryState>,
+}
+
+#[derive(Default)]
+struct ExpiryState {
+  by_key: HashMap<Vec<u8>, (u64, LogRecordPos)>, // key -> expire at in unix millis, expire record
+  by_time: BTreeSet<(u64, Vec<u8>)>,
+}
+
+impl ExpiryIndex {
+  /// Sets the expiration of a key, returns the expire record it replaces.
+  pub(crate) fn set(&self, key: Vec<u8>, at: u64, pos: LogRecordPos) -> Option<LogRecordPos> {
+    let mut state = self.state.lock();
+    let old = state.by_key.insert(key.clone(), (at, pos));
+    if let Some((old_at, _)) = old {
+      state.by_time.remove(&(old_at, key.clone()));
+    }
+    state.by_time.insert((at, key));
+    self.len.store(state.by_key.len(), Ordering::SeqCst);
+    old.map(|(_, pos)| pos)
+  }
+
+  /// Clears the expiration of a key, returns its expire record.
+  pub(crate) fn remove(&self, key: &[u8]) -> Option<LogRecordPos> {
+    if self.len.load(Ordering::SeqCst) == 0 {
+      return None;
+    }
+    let mut state = self.state.lock();
+    let (at, pos) = state.by_key.remove(key)?;
+    state.by_time.remove(&(at, key.to_vec()));
+    self.len.store(state.by_key.len(), Ordering::SeqCst);
+    Some(pos)
+  }
+
+  pub(crate) fn get(&self, key: &[u8]) -> Option<(u64, LogRecordPos)> {
+    if self.len.load(Ordering::SeqCst) == 0 {
+      return None;
+    }
+    self.state.lock().by_key.get(key).copied()
+  }
+
+  pub(crate) fn is_expired(&self, key: &[u8], now: u64) -> bool {
+    matches!(self.get(key), Some((at, _)) if at <= now)
+  }
+
+  /// Keys with an expiration and their expire records.
+  pub(crate) fn entries(&self) -> Vec<(Vec<u8>, LogRecordPos)> {
+    if self.len.load(Ordering::SeqCst) == 0 {
+      return Vec::new();
+    }
+    let state = self.state.lock();
+    state
+      .by_key
+      .iter()
+      .map(|(key, (_, pos))| (key.clone(), *pos))
+      .collect()
+  }
+
+  /// Forgets all expirations.
+  pub(crate) fn clear(&self) {
+    let mut state = self.state.lock();
+    state.by_key.clear();
+    state.by_time.clear();
+    self.len.store(0, Ordering::SeqCst);
+  }
+
+  /// Keys whose expiration time is not after `now`.
+  fn due(&self, now: u64) -> Vec<Vec<u8>> {
+    if self.len.load(Ordering::SeqCst) == 0 {
+      return Vec::new();
+    }
+    let state = self.state.lock();
+    state
+      .by_time
+      .iter()
+      .take_while(|(at, _)| *at <= now)
+      .map(|(_, key)| key.clone())
+      .collect()
+  }
+}
+
+/// Milliseconds since the unix epoch, times before it count as the epoch.
+pub(crate) fn unix_millis(time: SystemTime) -> u64 {
+  time
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_millis().min(u64::MAX as u128) as u64)
+    .unwrap_or(0)
+}
+
+/// Handle of a running expiry sweeper, the sweeper stops when the handle is dropped.
+pub struct ExpirySweeperHandle {
+  stop_sender: Option<Sender<()>>,
+  worker: Option<JoinHandle<()>>,
+}
+
+impl Engine {
+  /// Sets or changes the expiration time of an existing key.
+  ///
+  /// Expired keys are hidden from reads, listings and iterators right away, their tombstones
+  /// are written by [`Engine::purge_expired`] or the sweeper. A later put of the key clears
+  /// the expiration, a time in the past deletes the key.
+  ///
+  /// # Errors
+  ///
+  /// Returns `KeyNotFound` if the key does not exist, and `ExpireUnsupported` with the
+  /// B+ tree index whose content is not replayed at startup.
+  pub fn expire(&self, key: Bytes, at: SystemTime) -> Result<()> {
+    if key.is_empty() {
+      return Err(Errors::KeyIsEmpty);
+    }
+    if self.options.index_type == IndexType::BPlusTree {
+      return Err(Errors::ExpireUnsupported);
+    }
+
+    // the check and the expire record are atomic like a conditional put
+    let _guard = self.lock_key(key.clone());
+    let _gate = self.write_gate.read_recursive();
+    let _lock = self.batch_commit_lock.lock();
+    let now = unix_millis(SystemTime::now());
+    if self.index.get(key.to_vec()).is_none() || self.expiry.is_expired(&key, now) {
+      return Err(Errors::KeyNotFound);
+    }
+    let at = unix_millis(at);
+    if at <= now {
+      return self.delete(key);
+    }
+
+    let mut record = LogRecord {
+      key: log_record_key_with_seq(&key, NON_TXN_SEQ_NO),
+      value: at.to_be_bytes().to_vec(),
+      rec_type: LogRecordType::Expire,
+      timestamp: None,
+    };
+    let pos = self.append_log_record(&mut record)?;
+    // the expire record itself is stale once it is replaced
+    if let Some(old_pos) = self.expiry.set(key.to_vec(), at, pos) {
+      self.mark_stale(old_pos);
+    }
+    self.mark_changed();
+    self.emit_cdc([(CdcOp::Expire, &key[..], &at.to_be_bytes()[..])]);
+    Ok(())
+  }
+
+  /// Returns the expiration time of a key, `None` if it never expires.
+  pub fn expiration(&self, key: Bytes) -> Result<Option<SystemTime>> {
+    if self.index.get(key.to_vec()).is_none() {
+      return Err(Errors::KeyNotFound);
+    }
+    match self.expiry.get(&key) {
+      Some((at, _)) if at <= unix_millis(SystemTime::now()) => Err(Errors::KeyNotFound),
+      Some((at, _)) => Ok(Some(UNIX_EPOCH + Duration::from_millis(at))),
+      None => Ok(None),
+    }
+  }
+
+  /// Writes tombstones for all expired keys, returns how many keys were removed.
+  pub fn purge_expired(&self) -> Result<usize> {
+    let mut purged = 0;
+    for key in self.expiry.due(unix_millis(SystemTime::now())) {
+      let key = Bytes::from(key);
+      let _guard = self.lock_key(key.clone());
+      let _gate = self.write_gate.read_recursive();
+      let _lock = self.batch_commit_lock.lock();
+      // a put since the lookup cleared the expiration
+      if !self.expiry.is_expired(&key, unix_millis(SystemTime::now())) {
+        continue;
+      }
+      self.delete(key)?;
+      purged += 1;
+    }
+    Ok(purged)
+  }
+
+  /// Starts a background task which purges expired keys every `interval`.
+  ///
+  /// The task only holds a weak reference to the engine and exits once the engine is dropped
+  /// or the returned handle is dropped.
+  pub fn start_expiry_sweeper(self: &Arc<Self>, interval: Duration) -> Result<ExpirySweeperHandle> {
+    let engine = Arc::downgrade(self);
+    let (stop_sender, stop_receiver) = mpsc::channel::<()>();
+
+    let worker = thread::Builder::new()
+      .name("flash-kv-expiry-sweeper".to_string())
+      .spawn(move || loop {
+        match stop_receiver.recv_timeout(interval) {
+          Err(RecvTimeoutError::Timeout) => {}
+          _ => return,
+        }
+        let Some(engine) = engine.upgrade() else {
+          return;
+        };
+        if let Err(e) = engine.purge_expired() {
+          warn!("failed to purge expired keys: {e}");
+        }
+      })
+      .map_err(|e| {
+        warn!("failed to spawn expiry sweeper: {e}");
+        Errors::FailedToStartBackgroundTask
+      })?;
+
+    Ok(ExpirySweeperHandle {
+      stop_sender: Some(stop_sender),
+      worker: Some(worker),
+    })
+  }
+
+  /// Applies an expire record read at startup, `value` holds the expiration time.
+  pub(crate) fn replay_expire(&self, key: Vec<u8>, value: &[u8], pos: LogRecordPos) -> Result<()> {
+    let at: [u8; 8] = value.try_into().map_err(|_| Errors::InvalidLogRecord)?;
+    let stale = match self.index.get(key.clone()) {
+      // the key was deleted after the record was written
+      None => Some(pos),
+      Some(_) => self.expiry.set(key, u64::from_be_bytes(at), pos),
+    };
+    if let Some(stale) = stale {
+      self.mark_stale(stale);
+    }
+    Ok(())
+  }
+
+  /// Clears the expiration of a key which was written or deleted.
+  pub(crate) fn clear_expiry(&self, key: &[u8]) {
+    if let Some(old_pos) = self.expiry.remove(key) {
+      self.mark_stale(old_pos);
+    }
+  }
+}
+
+impl ExpirySweeperHandle {
+  /// Stops the sweeper and waits for it to exit.
+  pub fn stop(mut self) {
+    self.shutdown();
+  }
+
+  fn shutdown(&mut self) {
+    // dropping the sender wakes up the worker
+    self.stop_sender.take();
+    if let Some(worker) = self.worker.take() {
+      let _ = worker.join();
+    }
+  }
+}
+
+impl Drop for ExpirySweeperHandle {
+  fn drop(&mut self) {
+    self.shutdown();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn pos(offset: u64) -> LogRecordPos {
+    LogRecordPos {
+      file_id: 0,
+      offset,
+      size: 10,
+    }
+  }
+
+  #[test]
+  fn test_expiry_index() {
+    let expiry = ExpiryIndex::default();
+    assert!(expiry.due(u64::MAX).is_empty());
+
+    assert_eq!(None, expiry.set(b"a".to_vec(), 30, pos(0)));
+    assert_eq!(None, expiry.set(b"b".to_vec(), 10, pos(1)));
+    assert_eq!(None, expiry.set(b"c".to_vec(), 20, pos(2)));
+    assert_eq!(vec![b"b".to_vec(), b"c".to_vec()], expiry.due(20));
+
+    // moving the expiration reorders the key
+    assert_eq!(Some(pos(1)), expiry.set(b"b".to_vec(), 40, pos(3)));
+    assert_eq!(vec![b"c".to_vec()], expiry.due(20));
+    assert!(expiry.is_expired(b"c", 20));
+    assert!(!expiry.is_expired(b"b", 20));
+
+    assert_eq!(Some(pos(2)), expiry.remove(b"c"));
+    assert_eq!(None, expiry.remove(b"c"));
+    assert_eq!(vec![b"a".to_vec(), b"b".to_vec()], expiry.due(u64::MAX));
+  }
+}
@@ -0,0 +1,420 @@
+use std::{
+  fs::{File, OpenOptions},
+  io::{BufRead, BufReader, BufWriter, Read, Write},
+  path::Path,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use bytes::Bytes;
+use log::error;
+use prost::{decode_length_delimiter, encode_length_delimiter, length_delimiter_len};
+use serde_json::{json, Value};
+
+use crate::{
+  db::Engine,
+  errors::{Errors, Result},
+  option::{IteratorOptions, WriteBatchOptions},
+};
+
+// first bytes of a binary dump, the last byte is the format version
+const BINARY_MAGIC: &[u8] = b"FLASHKV\x01";
+
+// keys are never empty, an empty key marks the trailer holding the entry count
+const TRAILER_KEY_LEN: usize = 0;
+
+// keys written by one import batch
+const IMPORT_BATCH_SIZE: usize = 1024;
+
+// entries between two progress reports
+const PROGRESS_INTERVAL: u64 = 1000;
+
+/// On-disk format of an export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+  /// One JSON object per line, `{"key": <base64>, "value": <base64>}`
+  JsonLines,
+
+  /// Length prefixed keys and values after a magic header, ends with the entry count
+  Binary,
+}
+
+/// Progress of an export or import, reported every 1000 entries and once at the end.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransferProgress {
+  /// Entries transferred so far
+  pub entries: u64,
+
+  /// Key and value bytes transferred so far
+  pub bytes: u64,
+}
+
+impl TransferProgress {
+  fn add(&mut self, key: &[u8], value: &[u8]) {
+    self.entries += 1;
+    self.bytes += (key.len() + value.len()) as u64;
+  }
+}
+
+impl Engine {
+  /// Writes all live keys and values to `path`, returns the number of entries.
+  ///
+  /// The export sees the keys present when it starts, writes running concurrently may or may
+  /// not be included. Expiration times are not exported. Fails if a value can not be read,
+  /// the export file is incomplete then.
+  pub fn export_to<P, F>(&self, path: P, format: ExportFormat, mut progress: F) -> Result<u64>
+  where
+    P: AsRef<Path>,
+    F: FnMut(TransferProgress),
+  {
+    let file = OpenOptions::new()
+      .create(true)
+      .write(true)
+      .truncate(true)
+      .open(&path)
+      .map_err(|e| {
+        error!(
+          "failed to create export file {}: {e}",
+          path.as_ref().display()
+        );
+        Errors::FailedToWriteExport
+      })?;
+    let mut writer = BufWriter::new(file);
+    if format == ExportFormat::Binary {
+      write_all(&mut writer, BINARY_MAGIC)?;
+    }
+
+    let mut stat = TransferProgress::default();
+    let iter = self.iter(IteratorOptions::default());
+    while let Some((key, value)) = iter.try_next()? {
+      match format {
+        ExportFormat::JsonLines => {
+          let line = json!({"key": STANDARD.encode(&key), "value": STANDARD.encode(&value)});
+          write_all(&mut writer, line.to_string().as_bytes())?;
+          write_all(&mut writer, b"\n")?;
+        }
+        ExportFormat::Binary => {
+          write_all(&mut writer, &encode_lengths(key.len(), value.len()))?;
+          write_all(&mut writer, &key)?;
+          write_all(&mut writer, &value)?;
+        }
+      }
+      stat.add(&key, &value);
+      if stat.entries % PROGRESS_INTERVAL == 0 {
+        progress(stat);
+      }
+    }
+
+    if format == ExportFormat::Binary {
+      let count = stat.entries.to_be_bytes();
+      write_all(&mut writer, &encode_lengths(TRAILER_KEY_LEN, count.len()))?;
+      write_all(&mut writer, &count)?;
+    }
+    let file = writer.into_inner().map_err(|e| {
+      error!("failed to flush export file: {e}");
+      Errors::FailedToWriteExport
+    })?;
+    file.sync_all().map_err(|e| {
+      error!("failed to sync export file: {e}");
+      Errors::FailedToWriteExport
+    })?;
+    progress(stat);
+    Ok(stat.entries)
+  }
+
+  /// Puts all entries of an export at `path`, returns the number of entries.
+  ///
+  /// Entries are written in batches of 1024 keys, a failed import leaves the batches
+  /// committed before the failure in place.
+  pub fn import_from<P, F>(&self, path: P, format: ExportFormat, mut progress: F) -> Result<u64>
+  where
+    P: AsRef<Path>,
+    F: FnMut(TransferProgress),
+  {
+    let file = File::open(&path).map_err(|e| {
+      error!(
+        "failed to open import file {}: {e}",
+        path.as_ref().display()
+      );
+      Errors::FailedToReadImport
+    })?;
+    let mut reader = BufReader::new(file);
+
+    let mut stat = TransferProgress::default();
+    let mut entries = Vec::with_capacity(IMPORT_BATCH_SIZE);
+    if format == ExportFormat::Binary {
+      let mut magic = [0u8; BINARY_MAGIC.len()];
+      read_exact(&mut reader, &mut magic)?;
+      if magic != BINARY_MAGIC {
+        return Err(Errors::InvalidImportFile);
+      }
+    }
+
+    loop {
+      let entry = match format {
+        ExportFormat::JsonLines => read_json_line(&mut reader)?,
+        ExportFormat::Binary => read_binary_entry(&mut reader, stat.entries)?,
+      };
+      let Some((key, value)) = entry else {
+        break;
+      };
+      stat.add(&key, &value);
+      entries.push((key, value));
+      if entries.len() == IMPORT_BATCH_SIZE {
+        self.import_batch(&mut entries)?;
+      }
+      if stat.entries % PROGRESS_INTERVAL == 0 {
+        progress(stat);
+      }
+    }
+    self.import_batch(&mut entries)?;
+    self.sync()?;
+    progress(stat);
+    Ok(stat.entries)
+  }
+
+  fn import_batch(&self, entries: &mut Vec<(Bytes, Bytes)>) -> Result<()> {
+    if entries.is_empty() {
+      return Ok(());
+    }
+    let batch = self.new_write_batch(WriteBatchOptions {
+      max_batch_num: entries.len(),
+      max_batch_bytes: 0,
+      sync_writes: false,
+    })?;
+    for (key, value) in entries.drain(..) {
+      batch.put(key, value)?;
+    }
+    batch.commit()
+  }
+}
+
+fn encode_lengths(key_len: usize, value_len: usize) -> Vec<u8> {
+  let mut buf = Vec::with_capacity(length_delimiter_len(key_len) + length_delimiter_len(value_len));
+  // encoding into a growable vec can not fail
+  let _ = encode_length_delimiter(key_len, &mut buf);
+  let _ = encode_length_delimiter(value_len, &mut buf);
+  buf
+}
+
+fn write_all<W: Write>(writer: &mut W, buf: &[u8]) -> Result<()> {
+  writer.write_all(buf).map_err(|e| {
+    error!("failed to write export file: {e}");
+    Errors::FailedToWriteExport
+  })
+}
+
+fn read_exact<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<()> {
+  reader.read_exact(buf).map_err(|e| {
+    error!("failed to read import file: {e}");
+    Errors::InvalidImportFile
+  })
+}
+
+/// Reads the next JSON line, blank lines are skipped.
+fn read_json_line<R: BufRead>(reader: &mut R) -> Result<Option<(Bytes, Bytes)>> {
+  let mut line = String::new();
+  loop {
+    line.clear();
+    let n = reader.read_line(&mut line).map_err(|e| {
+      error!("failed to read import file: {e}");
+      Errors::FailedToReadImport
+    })?;
+    if n == 0 {
+      return Ok(None);
+    }
+    if !line.trim().is_empty() {
+      break;
+    }
+  }
+
+  let entry: Value = serde_json::from_str(&line).map_err(|_| Errors::InvalidImportFile)?;
+  let field = |name: &str| -> Result<Bytes> {
+    entry
+      .get(name)
+      .and_then(Value::as_str)
+      .and_then(|v| STANDARD.decode(v).ok())
+      .map(Bytes::from)
+      .ok_or(Errors::InvalidImportFile)
+  };
+  Ok(Some((field("key")?, field("value")?)))
+}
+
+/// Reads the next binary entry, `None` once the trailer matching `entries` is read.
+fn read_binary_entry<R: BufRead>(reader: &mut R, entries: u64) -> Result<Option<(Bytes, Bytes)>> {
+  let key_len = read_length(reader)?;
+  let value_len = read_length(reader)?;
+  let mut value_buf = |len: usize| -> Result<Vec<u8>> {
+    // lengths come from the file, don't trust them with a large allocation up front
+    let mut buf = Vec::new();
+    let read = reader
+      .by_ref()
+      .take(len as u64)
+      .read_to_end(&mut buf)
+      .map_err(|_| Errors::InvalidImportFile)?;
+    match read == len {
+      true => Ok(buf),
+      false => Err(Errors::InvalidImportFile),
+    }
+  };
+
+  if key_len == TRAILER_KEY_LEN {
+    let count: [u8; 8] = value_buf(value_len)?
+      .try_into()
+      .map_err(|_| Errors::InvalidImportFile)?;
+    return match u64::from_be_bytes(count) == entries {
+      true => Ok(None),
+      false => Err(Errors::InvalidImportFile),
+    };
+  }
+  let key = value_buf(key_len)?;
+  let value = value_buf(value_len)?;
+  Ok(Some((Bytes::from(key), Bytes::from(value))))
+}
+
+/// Reads a varint length byte by byte, the varint is at most 10 bytes long.
+fn read_length<R: BufRead>(reader: &mut R) -> Result<usize> {
+  let mut buf = Vec::with_capacity(10);
+  loop {
+    let mut byte = [0u8; 1];
+    read_exact(reader, &mut byte)?;
+    buf.push(byte[0]);
+    if byte[0] & 0x80 == 0 || buf.len() == 10 {
+      break;
+    }
+  }
+  decode_length_delimiter(buf.as_slice()).map_err(|_| Errors::InvalidImportFile)
+}
+
+#[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
+mod tests {
+  use std::fs;
+
+  use crate::{
+    option::Options,
+    util::rand_kv::{get_test_key, get_test_value},
+  };
+
+  use super::*;
+
+  fn open_engine(dir: &Path) -> Engine {
+    let mut opts = Options::default();
+    opts.dir_path = dir.to_path_buf();
+    Engine::open(opts).expect("failed to open engine")
+  }
+
+  #[test]
+  fn test_export_import_roundtrip() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let source = open_engine(&temp_dir.path().join("source"));
+    for i in 0..2500 {
+      source.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    // binary safe keys and values
+    source
+      .put(Bytes::from(vec![0u8, 0xff, b'\n']), Bytes::new())
+      .unwrap();
+
+    for format in [ExportFormat::JsonLines, ExportFormat::Binary] {
+      let path = temp_dir.path().join(format!("{format:?}.dump"));
+      let mut reports = Vec::new();
+      assert_eq!(
+        2501,
+        source
+          .export_to(&path, format, |p| reports.push(p.entries))
+          .unwrap()
+      );
+      assert_eq!(vec![1000, 2000, 2501], reports);
+
+      let target = open_engine(&temp_dir.path().join(format!("{format:?}")));
+      let mut last = TransferProgress::default();
+      assert_eq!(
+        2501,
+        target.import_from(&path, format, |p| last = p).unwrap()
+      );
+      assert_eq!(2501, last.entries);
+      assert_eq!(source.list_keys().unwrap(), target.list_keys().unwrap());
+      for key in source.list_keys().unwrap() {
+        assert_eq!(source.get(key.clone()).unwrap(), target.get(key).unwrap());
+      }
+    }
+  }
+
+  #[test]
+  fn test_export_fails_on_unreadable_value() {
+    use std::os::unix::fs::FileExt;
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let source = open_engine(&temp_dir.path().join("source"));
+    for i in 0..10 {
+      source.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    let pos = source.index.get(get_test_key(5).to_vec()).unwrap();
+    let file = fs::OpenOptions::new()
+      .write(true)
+      .open(crate::data::data_file::get_data_file_name(
+        temp_dir.path().join("source"),
+        pos.file_id,
+      ))
+      .unwrap();
+    file
+      .write_at(b"x", pos.offset + pos.size as u64 - 5)
+      .unwrap();
+
+    // the key is not left out of the export silently
+    let path = temp_dir.path().join("dump");
+    assert_eq!(
+      Errors::InvalidLogRecordCrc,
+      source
+        .export_to(&path, ExportFormat::Binary, |_| {})
+        .unwrap_err()
+    );
+  }
+
+  #[test]
+  fn test_import_invalid_files() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let engine = open_engine(&temp_dir.path().join("engine"));
+    engine.put(get_test_key(1), get_test_value(1)).unwrap();
+    let path = temp_dir.path().join("dump");
+    engine
+      .export_to(&path, ExportFormat::Binary, |_| {})
+      .unwrap();
+
+    // a truncated binary dump misses its trailer
+    let content = fs::read(&path).unwrap();
+    fs::write(&path, &content[..content.len() - 4]).unwrap();
+    assert_eq!(
+      Errors::InvalidImportFile,
+      engine
+        .import_from(&path, ExportFormat::Binary, |_| {})
+        .unwrap_err()
+    );
+
+    fs::write(&path, b"not a dump").unwrap();
+    assert_eq!(
+      Errors::InvalidImportFile,
+      engine
+        .import_from(&path, ExportFormat::Binary, |_| {})
+        .unwrap_err()
+    );
+
+    fs::write(&path, "{\"key\": \"a2V5\"}\n").unwrap();
+    assert_eq!(
+      Errors::InvalidImportFile,
+      engine
+        .import_from(&path, ExportFormat::JsonLines, |_| {})
+        .unwrap_err()
+    );
+    assert_eq!(
+      Errors::FailedToReadImport,
+      engine
+        .import_from(
+          temp_dir.path().join("missing"),
+          ExportFormat::JsonLines,
+          |_| {}
+        )
+        .unwrap_err()
+    );
+  }
+}
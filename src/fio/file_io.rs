@@ -1,11 +1,11 @@
-use super::IOManager;
+use super::{open_append, open_read, IOManager};
 
-use crate::errors::{Errors, Result};
+use crate::errors::{DataFileOp, Errors, Result};
 use log::error;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use std::{
   fs::{File, OpenOptions},
-  io::Write,
+  io::{self, IoSlice, Write},
   os::unix::fs::FileExt,
   path::Path,
   sync::Arc,
@@ -13,7 +13,8 @@ use std::{
 
 /// FileIO standard system file I/O
 pub struct FileIO {
-  fd: Arc<RwLock<File>>, //system file descriptor
+  fd: Arc<RwLock<File>>,   //system file descriptor
+  end: Option<Mutex<u64>>, // written size of a preallocated file, None in append mode
 }
 
 impl FileIO {
@@ -21,18 +22,75 @@ impl FileIO {
   where
     P: AsRef<Path>,
   {
-    match OpenOptions::new()
-      .create(true)
-      .read(true)
-      .append(true)
-      .open(file_name)
-    {
+    match open_append(file_name) {
+      Ok(file) => Ok(FileIO {
+        fd: Arc::new(RwLock::new(file)),
+        end: None,
+      }),
+      Err(e) => {
+        error!("failed to open data file error: {e}");
+        Err(Errors::data_file_io(DataFileOp::Open, e))
+      }
+    }
+  }
+
+  /// Opens an existing file for reading only, writing to it fails.
+  pub fn read_only<P>(file_name: P) -> Result<Self>
+  where
+    P: AsRef<Path>,
+  {
+    match open_read(file_name) {
       Ok(file) => Ok(FileIO {
         fd: Arc::new(RwLock::new(file)),
+        end: None,
       }),
       Err(e) => {
         error!("failed to open data file error: {e}");
-        Err(Errors::FailedToOpenDataFile)
+        Err(Errors::data_file_io(DataFileOp::Open, e))
+      }
+    }
+  }
+
+  /// Opens the file to continue writing at `end` with the space up to `size` allocated ahead.
+  /// Bytes past `end` are dropped, the allocated space reads as zeros and is not counted in
+  /// the size of the file.
+  pub fn preallocated<P>(file_name: P, end: u64, size: u64) -> Result<Self>
+  where
+    P: AsRef<Path>,
+  {
+    let file = OpenOptions::new()
+      .create(true)
+      .read(true)
+      .write(true)
+      .truncate(false)
+      .open(file_name)
+      .and_then(|file| {
+        file.set_len(end)?;
+        fs2::FileExt::allocate(&file, size.max(end))?;
+        Ok(file)
+      })
+      .map_err(|e| {
+        error!("failed to preallocate data file error: {e}");
+        Errors::data_file_io(DataFileOp::Open, e)
+      })?;
+    Ok(FileIO {
+      fd: Arc::new(RwLock::new(file)),
+      end: Some(Mutex::new(end)),
+    })
+  }
+
+  // writes at the end of a preallocated file, the written size only grows on success
+  fn write_at_end(&self, end: &Mutex<u64>, buf: &[u8]) -> Result<usize> {
+    let read_guard = self.fd.read();
+    let mut end = end.lock();
+    match read_guard.write_all_at(buf, *end) {
+      Ok(()) => {
+        *end += buf.len() as u64;
+        Ok(buf.len())
+      }
+      Err(e) => {
+        error!("write to data file error: {e}");
+        Err(Errors::data_file_io(DataFileOp::Write, e))
       }
     }
   }
@@ -45,34 +103,82 @@ impl IOManager for FileIO {
       Ok(n) => Ok(n),
       Err(e) => {
         error!("read from date file error: {e}");
-        Err(Errors::FailedToReadFromDataFile)
+        Err(Errors::data_file_read(offset, e))
       }
     }
   }
 
   fn write(&self, buf: &[u8]) -> Result<usize> {
+    if let Some(end) = &self.end {
+      return self.write_at_end(end, buf);
+    }
     let mut write_guard = self.fd.write();
     match write_guard.write(buf) {
       Ok(n) => Ok(n),
       Err(e) => {
         error!("write to data file error: {e}");
-        Err(Errors::FailedToWriteToDataFile)
+        Err(Errors::data_file_io(DataFileOp::Write, e))
+      }
+    }
+  }
+
+  fn write_vectored(&self, bufs: &[&[u8]]) -> Result<usize> {
+    if let Some(end) = &self.end {
+      return self.write_at_end(end, &bufs.concat());
+    }
+    let mut slices: Vec<IoSlice> = bufs.iter().map(|buf| IoSlice::new(buf)).collect();
+    let mut slices = &mut slices[..];
+    let mut write_guard = self.fd.write();
+    let mut written = 0;
+    while !slices.is_empty() {
+      match write_guard.write_vectored(slices) {
+        Ok(0) => break,
+        Ok(n) => {
+          written += n;
+          IoSlice::advance_slices(&mut slices, n);
+        }
+        Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+        Err(e) => {
+          error!("write to data file error: {e}");
+          return Err(Errors::data_file_io(DataFileOp::Write, e));
+        }
       }
     }
+    Ok(written)
   }
 
   fn sync(&self) -> Result<()> {
     let read_guard = self.fd.read();
     if let Err(e) = read_guard.sync_all() {
       error!("failed to sync data file err: {e}");
-      return Err(Errors::FailedToSyncToDataFile);
+      return Err(Errors::data_file_io(DataFileOp::Sync, e));
     }
     Ok(())
   }
 
+  fn trim(&self) -> Result<()> {
+    let Some(end) = &self.end else {
+      return Ok(());
+    };
+    let read_guard = self.fd.read();
+    read_guard.set_len(*end.lock()).map_err(|e| {
+      error!("failed to trim data file error: {e}");
+      Errors::data_file_io(DataFileOp::Write, e)
+    })
+  }
+
   fn size(&self) -> u64 {
+    if let Some(end) = &self.end {
+      return *end.lock();
+    }
     let read_guard = self.fd.read();
-    read_guard.metadata().unwrap().len()
+    match read_guard.metadata() {
+      Ok(metadata) => metadata.len(),
+      Err(e) => {
+        error!("failed to read data file metadata error: {e}");
+        0
+      }
+    }
   }
 }
 
@@ -130,6 +236,23 @@ mod tests {
     assert!(res3.is_ok());
   }
 
+  #[test]
+  fn test_file_io_read_only() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let path = temp_dir.path().join("c.data");
+
+    // nothing is created for a missing file
+    assert!(FileIO::read_only(&path).is_err());
+    assert!(!path.exists());
+
+    FileIO::new(&path).unwrap().write(b"key-a").unwrap();
+    let fio = FileIO::read_only(&path).unwrap();
+    let mut buf = [0u8; 5];
+    assert_eq!(Ok(5), fio.read(&mut buf, 0));
+    assert_eq!(b"key-a", &buf);
+    assert!(fio.write(b"key-b").is_err());
+  }
+
   #[test]
   fn test_file_io_sync() {
     let path = PathBuf::from("/tmp/c.data");
@@ -173,4 +296,51 @@ mod tests {
     let res3 = fs::remove_file(path);
     assert!(res3.is_ok());
   }
+
+  #[test]
+  fn test_file_io_write_vectored() {
+    let dir = tempfile::tempdir().unwrap();
+    let fio = FileIO::new(dir.path().join("e.data")).unwrap();
+    fio.write("key-a".as_bytes()).unwrap();
+    let bufs: [&[u8]; 3] = [b"key-b", b"", b"key-c"];
+    assert_eq!(10, fio.write_vectored(&bufs).unwrap());
+    assert_eq!(15, fio.size());
+
+    let mut buf = [0u8; 15];
+    fio.read(&mut buf, 0).unwrap();
+    assert_eq!(b"key-akey-bkey-c", &buf);
+
+    // files are append only
+    assert_eq!(5, fio.write_at(b"key-d", 15).unwrap());
+    assert!(fio.write_at(b"key-e", 0).is_err());
+  }
+
+  #[test]
+  fn test_file_io_preallocated() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("f.data");
+    FileIO::new(&path).unwrap().write(b"key-a").unwrap();
+
+    // writes continue at the end, the allocated space is not part of the size
+    let fio = FileIO::preallocated(&path, 5, 4096).unwrap();
+    assert_eq!(5, fio.size());
+    assert_eq!(4096, fs::metadata(&path).unwrap().len());
+    fio.write(b"key-b").unwrap();
+    let bufs: [&[u8]; 2] = [b"key-c", b"key-d"];
+    assert_eq!(10, fio.write_vectored(&bufs).unwrap());
+    assert_eq!(20, fio.size());
+    let mut buf = [0xffu8; 24];
+    fio.read(&mut buf, 0).unwrap();
+    assert_eq!(b"key-akey-bkey-ckey-d\0\0\0\0", &buf);
+
+    fio.trim().unwrap();
+    assert_eq!(20, fs::metadata(&path).unwrap().len());
+    assert_eq!(20, FileIO::new(&path).unwrap().size());
+
+    // bytes past the end are dropped when reopened
+    let fio = FileIO::preallocated(&path, 10, 4096).unwrap();
+    let mut buf = [0xffu8; 5];
+    fio.read(&mut buf, 10).unwrap();
+    assert_eq!([0u8; 5], buf);
+  }
 }
@@ -0,0 +1,89 @@
+use super::IOManager;
+
+use crate::errors::Result;
+use parking_lot::RwLock;
+
+/// MemoryIO keeps the file content in memory, used by `StorageMode::Memory`
+#[derive(Default)]
+pub struct MemoryIO {
+  data: RwLock<Vec<u8>>,
+}
+
+impl MemoryIO {
+  pub fn new() -> Self {
+    Self::default()
+  }
+}
+
+impl IOManager for MemoryIO {
+  fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+    let data = self.data.read();
+    let start = (offset as usize).min(data.len());
+    let n = buf.len().min(data.len() - start);
+    buf[..n].copy_from_slice(&data[start..start + n]);
+    Ok(n)
+  }
+
+  fn write(&self, buf: &[u8]) -> Result<usize> {
+    self.data.write().extend_from_slice(buf);
+    Ok(buf.len())
+  }
+
+  fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize> {
+    let mut data = self.data.write();
+    let (start, end) = (offset as usize, offset as usize + buf.len());
+    if data.len() < end {
+      data.resize(end, 0);
+    }
+    data[start..end].copy_from_slice(buf);
+    Ok(buf.len())
+  }
+
+  fn write_vectored(&self, bufs: &[&[u8]]) -> Result<usize> {
+    let mut data = self.data.write();
+    let size = data.len();
+    bufs.iter().for_each(|buf| data.extend_from_slice(buf));
+    Ok(data.len() - size)
+  }
+
+  fn sync(&self) -> Result<()> {
+    Ok(())
+  }
+
+  fn size(&self) -> u64 {
+    self.data.read().len() as u64
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_memory_io_read_write() {
+    let mio = MemoryIO::new();
+    assert_eq!(5, mio.write("key-a".as_bytes()).unwrap());
+    assert_eq!(5, mio.write("key-b".as_bytes()).unwrap());
+    assert_eq!(10, mio.size());
+
+    let mut buf = [0u8; 5];
+    assert_eq!(5, mio.read(&mut buf, 5).unwrap());
+    assert_eq!(b"key-b", &buf);
+
+    // reads past the end are short like on a file
+    let mut buf = [0u8; 8];
+    assert_eq!(2, mio.read(&mut buf, 8).unwrap());
+    assert_eq!(b"-b", &buf[..2]);
+    assert_eq!(0, mio.read(&mut buf, 20).unwrap());
+    assert!(mio.sync().is_ok());
+
+    // positioned writes overwrite or extend the content
+    let bufs: [&[u8]; 2] = [b"key-c", b"key-d"];
+    assert_eq!(10, mio.write_vectored(&bufs).unwrap());
+    assert_eq!(5, mio.write_at(b"KEY-B", 5).unwrap());
+    assert_eq!(2, mio.write_at(b"!!", 24).unwrap());
+    let mut buf = [0u8; 26];
+    assert_eq!(26, mio.read(&mut buf, 0).unwrap());
+    assert_eq!(b"key-aKEY-Bkey-ckey-d\0\0\0\0!!", &buf);
+  }
+}
@@ -1,16 +1,25 @@
-use std::{fs::OpenOptions, path::Path, sync::Arc};
+use std::{io, path::Path, sync::Arc};
 
+use bytes::Bytes;
 use log::error;
 use memmap2::Mmap;
-use parking_lot::Mutex;
 
-use crate::errors::{Errors, Result};
+use crate::errors::{DataFileOp, Errors, Result};
 
-use super::IOManager;
+use super::{open_append, open_read, IOManager};
 
 pub struct MMapIO {
-  //
-  map: Arc<Mutex<Mmap>>,
+  // shared with the slices handed out by `read_shared`, which keep the map alive
+  map: Arc<Mmap>,
+}
+
+// owner of the bytes returned by `read_shared`
+struct SharedMap(Arc<Mmap>);
+
+impl AsRef<[u8]> for SharedMap {
+  fn as_ref(&self) -> &[u8] {
+    &self.0
+  }
 }
 
 impl MMapIO {
@@ -18,21 +27,23 @@ impl MMapIO {
   where
     P: AsRef<Path>,
   {
-    match OpenOptions::new()
-      .create(true)
-      .read(true)
-      .append(true)
-      .open(file_name)
-    {
-      Ok(file) => {
-        let map = unsafe { Mmap::map(&file).expect("failed to map file") };
-        Ok(MMapIO {
-          map: Arc::new(Mutex::new(map)),
-        })
-      }
+    // the map is only read, the file is opened for reading and created if missing
+    let file_name = file_name.as_ref();
+    let file = open_read(file_name).or_else(|e| match e.kind() {
+      io::ErrorKind::NotFound => open_append(file_name),
+      _ => Err(e),
+    });
+    match file {
+      Ok(file) => match unsafe { Mmap::map(&file) } {
+        Ok(map) => Ok(MMapIO { map: Arc::new(map) }),
+        Err(e) => {
+          error!("failed to map data file error: {e}");
+          Err(Errors::data_file_io(DataFileOp::Open, e))
+        }
+      },
       Err(e) => {
         error!("failed to open data file error: {e}");
-        Err(Errors::FailedToOpenDataFile)
+        Err(Errors::data_file_io(DataFileOp::Open, e))
       }
     }
   }
@@ -40,35 +51,48 @@ impl MMapIO {
 
 impl IOManager for MMapIO {
   fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
-    let map_arr = self.map.lock();
-    let end = offset + buf.len() as u64;
-    if end > map_arr.len() as u64 {
-      return Err(Errors::ReadDataFileEOF);
-    }
+    // reads crossing the end of the map are short like file reads
+    let map_arr = &self.map;
+    let start = usize::try_from(offset).map_or(map_arr.len(), |start| start.min(map_arr.len()));
+    let end = start + buf.len().min(map_arr.len() - start);
 
-    let val = &map_arr[offset as usize..end as usize];
-    buf.copy_from_slice(val);
+    let val = &map_arr[start..end];
+    buf[..val.len()].copy_from_slice(val);
 
     Ok(val.len())
   }
 
   fn write(&self, _buf: &[u8]) -> Result<usize> {
-    unimplemented!()
+    // memory map is only used for reading at startup
+    error!("write to read-only memory map");
+    Err(Errors::data_file_io(
+      DataFileOp::Write,
+      io::Error::new(io::ErrorKind::Unsupported, "memory maps are read-only"),
+    ))
+  }
+
+  fn read_shared(&self, offset: u64, len: usize) -> Option<Bytes> {
+    let start = usize::try_from(offset).ok()?;
+    let end = start.checked_add(len)?;
+    if end > self.map.len() {
+      return None;
+    }
+    Some(Bytes::from_owner(SharedMap(self.map.clone())).slice(start..end))
   }
 
   fn sync(&self) -> Result<()> {
-    unimplemented!()
+    // read-only map, nothing to flush
+    Ok(())
   }
 
   fn size(&self) -> u64 {
-    let map_arr = self.map.lock();
-    map_arr.len() as u64
+    self.map.len() as u64
   }
 }
 
 #[cfg(test)]
 mod tests {
-  use std::fs;
+  use std::fs::{self, OpenOptions};
   use tempfile::tempdir;
 
   use crate::fio::file_io::FileIO;
@@ -97,7 +121,8 @@ mod tests {
     let mut buf1 = [0u8; 10];
     let read_res1 = mmap_io1.read(&mut buf1, 0);
 
-    assert!(read_res1.is_err());
+    // reads past the end of the map are empty
+    assert_eq!(Ok(0), read_res1);
 
     let fio_res = FileIO::new(&path);
     assert!(fio_res.is_ok());
@@ -113,7 +138,24 @@ mod tests {
 
     let mut buf2 = [0u8; 35];
     let read_res2 = mmap_io2.read(&mut buf2, 0);
-    assert!(read_res2.is_ok());
+    assert_eq!(Ok(35), read_res2);
+
+    // reads crossing the end of the map are short
+    let mut buf3 = [0u8; 10];
+    assert_eq!(Ok(5), mmap_io2.read(&mut buf3, 30));
+    assert_eq!(b"again", &buf3[..5]);
+    assert_eq!(Ok(0), mmap_io2.read(&mut buf3, 40));
+
+    // shared reads point into the map, which outlives the io manager
+    let shared = mmap_io2.read_shared(11, 12).unwrap();
+    assert_eq!(b"good morning", &shared[..]);
+    assert_eq!(
+      shared.as_ptr(),
+      mmap_io2.read_shared(11, 4).unwrap().as_ptr()
+    );
+    assert!(mmap_io2.read_shared(30, 6).is_none());
+    drop(mmap_io2);
+    assert_eq!(b"good morning", &shared[..]);
   }
 
   #[test]
@@ -1,26 +1,140 @@
 pub mod file_io;
+pub mod memory;
+#[cfg(feature = "mmap")]
 pub mod mmap;
+pub(crate) mod pool;
+#[cfg(all(feature = "uring", target_os = "linux"))]
+pub mod uring;
 
-use std::path::PathBuf;
+use std::{
+  fs::{File, OpenOptions},
+  io,
+  path::{Path, PathBuf},
+};
 
-use crate::{errors::Result, option::IOManagerType};
+use bytes::Bytes;
 
-use self::{file_io::FileIO, mmap::MMapIO};
+use crate::{
+  errors::{DataFileOp, Errors, Result},
+  option::IOManagerType,
+};
+
+use self::file_io::FileIO;
+use self::memory::MemoryIO;
+#[cfg(feature = "mmap")]
+use self::mmap::MMapIO;
+#[cfg(all(feature = "uring", target_os = "linux"))]
+use self::uring::UringIO;
+
+/// Opens a file for appending, creating it if needed.
+pub(crate) fn open_append<P>(file_name: P) -> io::Result<File>
+where
+  P: AsRef<Path>,
+{
+  OpenOptions::new()
+    .create(true)
+    .read(true)
+    .append(true)
+    .open(file_name)
+}
+
+/// Opens an existing file for reading only, which works on read-only media as well.
+pub(crate) fn open_read<P>(file_name: P) -> io::Result<File>
+where
+  P: AsRef<Path>,
+{
+  OpenOptions::new().read(true).open(file_name)
+}
 
 /// Abstract I/O management interface for different I/O implementations.
 pub trait IOManager: Sync + Send {
+  /// Reads up to `buf.len()` bytes at `offset`, returns the number of bytes read.
+  /// A read crossing the end of the file returns the bytes before it, a read past it 0,
+  /// neither is an error. See [`IOManager::read_full`] for reads which may also come back
+  /// short in the middle of a file.
   fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize>;
 
+  /// Reads until `buf` is full or the end of the file, returns the number of bytes read.
+  fn read_full(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+      let n = self.read(&mut buf[read..], offset + read as u64)?;
+      if n == 0 {
+        break;
+      }
+      read += n;
+    }
+    Ok(read)
+  }
+
+  /// Reads several ranges, returns the number of bytes read into each buffer.
+  /// Implementations may submit the reads together instead of one by one.
+  fn read_batch(&self, reads: &mut [(&mut [u8], u64)]) -> Result<Vec<usize>> {
+    reads
+      .iter_mut()
+      .map(|(buf, offset)| self.read(buf, *offset))
+      .collect()
+  }
+
   fn write(&self, buf: &[u8]) -> Result<usize>;
 
+  /// Writes `buf` at `offset`, returns the number of bytes written. Data files are append
+  /// only, by default the offset must be the end of the file.
+  fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize> {
+    if offset != self.size() {
+      return Err(Errors::data_file_io(
+        DataFileOp::Write,
+        io::Error::new(io::ErrorKind::Unsupported, "positioned write"),
+      ));
+    }
+    self.write(buf)
+  }
+
+  /// Appends the buffers back to back, returns the number of bytes written.
+  /// Implementations may write them in a single call instead of one by one.
+  fn write_vectored(&self, bufs: &[&[u8]]) -> Result<usize> {
+    let mut written = 0;
+    for buf in bufs {
+      let n = self.write(buf)?;
+      written += n;
+      if n < buf.len() {
+        break;
+      }
+    }
+    Ok(written)
+  }
+
+  /// Returns the `len` bytes at `offset` without copying them, e.g. as a slice of a memory
+  /// map. None if they are out of range or the implementation only reads into buffers.
+  fn read_shared(&self, _offset: u64, _len: usize) -> Option<Bytes> {
+    None
+  }
+
   fn sync(&self) -> Result<()>;
 
+  /// Releases the space allocated ahead of the writes, called once the file is sealed.
+  fn trim(&self) -> Result<()> {
+    Ok(())
+  }
+
+  /// Size of the content written, without space allocated ahead.
   fn size(&self) -> u64;
 }
 
-pub fn new_io_manager(filename: &PathBuf, io_type: &IOManagerType) -> Box<dyn IOManager> {
+pub fn new_io_manager(filename: &PathBuf, io_type: &IOManagerType) -> Result<Box<dyn IOManager>> {
   match *io_type {
-    IOManagerType::StandardFileIO => Box::new(FileIO::new(filename).unwrap()),
-    IOManagerType::MemoryMap => Box::new(MMapIO::new(filename).unwrap()),
+    IOManagerType::StandardFileIO => Ok(Box::new(FileIO::new(filename)?)),
+    IOManagerType::ReadOnlyFileIO => Ok(Box::new(FileIO::read_only(filename)?)),
+    #[cfg(feature = "mmap")]
+    IOManagerType::MemoryMap => Ok(Box::new(MMapIO::new(filename)?)),
+    // memory map only speeds up loading, fall back to standard io without it
+    #[cfg(not(feature = "mmap"))]
+    IOManagerType::MemoryMap => Ok(Box::new(FileIO::new(filename)?)),
+    #[cfg(all(feature = "uring", target_os = "linux"))]
+    IOManagerType::IoUring => Ok(Box::new(UringIO::new(filename)?)),
+    // io_uring is an optimization only, fall back to standard io without it
+    #[cfg(not(all(feature = "uring", target_os = "linux")))]
+    IOManagerType::IoUring => Ok(Box::new(FileIO::new(filename)?)),
+    IOManagerType::Memory => Ok(Box::new(MemoryIO::new())),
   }
 }
@@ -0,0 +1,248 @@
+use std::{
+  collections::{BTreeMap, HashMap},
+  fs::{self, File, OpenOptions},
+  io,
+  os::unix::fs::FileExt,
+  path::{Path, PathBuf},
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Weak,
+  },
+};
+
+use log::error;
+use parking_lot::Mutex;
+
+use crate::errors::{DataFileOp, Errors, Result};
+
+use super::IOManager;
+
+// size of a file which has not been looked up yet
+const UNKNOWN_SIZE: u64 = u64::MAX;
+
+/// Limits the number of data files kept open, the least recently used files are closed
+/// and reopened on their next read.
+pub(crate) struct FilePool {
+  max_open: usize,
+  next_id: AtomicU64,
+  state: Mutex<PoolState>,
+}
+
+#[derive(Default)]
+struct PoolState {
+  tick: u64,
+  lru: BTreeMap<u64, u64>,                   // last use tick -> file id
+  open: HashMap<u64, (u64, Weak<FileSlot>)>, // file id -> last use tick, slot
+}
+
+#[derive(Default)]
+struct FileSlot {
+  fd: Mutex<Option<Arc<File>>>,
+}
+
+impl FilePool {
+  pub(crate) fn new(max_open: usize) -> Self {
+    Self {
+      max_open: max_open.max(1),
+      next_id: AtomicU64::new(0),
+      state: Mutex::new(PoolState::default()),
+    }
+  }
+
+  /// Number of files currently held open by the pool.
+  #[cfg(test)]
+  pub(crate) fn open_files(&self) -> usize {
+    self.state.lock().open.len()
+  }
+
+  /// Marks the file as most recently used and closes files beyond the limit.
+  fn touch(&self, id: u64, slot: &Arc<FileSlot>) {
+    let mut victims = Vec::new();
+    {
+      let mut state = self.state.lock();
+      state.tick += 1;
+      let tick = state.tick;
+      if let Some((old_tick, _)) = state.open.insert(id, (tick, Arc::downgrade(slot))) {
+        state.lru.remove(&old_tick);
+      }
+      state.lru.insert(tick, id);
+
+      while state.open.len() > self.max_open {
+        let Some((_, victim_id)) = state.lru.pop_first() else {
+          break;
+        };
+        if let Some((_, victim)) = state.open.remove(&victim_id) {
+          victims.push(victim);
+        }
+      }
+    }
+
+    // close outside of the pool lock, in-flight reads keep their own handle
+    for victim in victims {
+      if let Some(victim) = victim.upgrade() {
+        victim.fd.lock().take();
+      }
+    }
+  }
+
+  fn remove(&self, id: u64) {
+    let mut state = self.state.lock();
+    if let Some((tick, _)) = state.open.remove(&id) {
+      state.lru.remove(&tick);
+    }
+  }
+}
+
+/// Read only IO of an immutable data file whose handle is managed by a [`FilePool`].
+pub(crate) struct PooledFileIO {
+  id: u64,
+  path: PathBuf,
+  size: AtomicU64,
+  slot: Arc<FileSlot>,
+  pool: Arc<FilePool>,
+}
+
+impl PooledFileIO {
+  pub(crate) fn new<P>(file_name: P, pool: &Arc<FilePool>) -> Result<Self>
+  where
+    P: AsRef<Path>,
+  {
+    let io = Self {
+      id: pool.next_id.fetch_add(1, Ordering::Relaxed),
+      path: file_name.as_ref().to_path_buf(),
+      size: AtomicU64::new(UNKNOWN_SIZE),
+      slot: Arc::new(FileSlot::default()),
+      pool: pool.clone(),
+    };
+    // fail early if the file is missing
+    io.file()?;
+    Ok(io)
+  }
+
+  fn file(&self) -> Result<Arc<File>> {
+    let mut fd = self.slot.fd.lock();
+    let file = match fd.as_ref() {
+      Some(file) => file.clone(),
+      None => {
+        let file = OpenOptions::new()
+          .read(true)
+          .open(&self.path)
+          .map_err(|e| {
+            error!("failed to open data file {}: {e}", self.path.display());
+            Errors::data_file_io(DataFileOp::Open, e)
+          })?;
+        fd.insert(Arc::new(file)).clone()
+      }
+    };
+    drop(fd);
+
+    self.pool.touch(self.id, &self.slot);
+    Ok(file)
+  }
+}
+
+impl IOManager for PooledFileIO {
+  fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+    let file = self.file()?;
+    file.read_at(buf, offset).map_err(|e| {
+      error!("read from data file error: {e}");
+      Errors::data_file_read(offset, e)
+    })
+  }
+
+  fn write(&self, _buf: &[u8]) -> Result<usize> {
+    // only immutable files are pooled
+    error!("write to pooled read-only data file");
+    Err(Errors::data_file_io(
+      DataFileOp::Write,
+      io::Error::new(
+        io::ErrorKind::Unsupported,
+        "pooled data files are read-only",
+      ),
+    ))
+  }
+
+  fn sync(&self) -> Result<()> {
+    // read only, nothing to flush
+    Ok(())
+  }
+
+  fn size(&self) -> u64 {
+    let size = self.size.load(Ordering::Relaxed);
+    if size != UNKNOWN_SIZE {
+      return size;
+    }
+    // the file is immutable so the size is looked up once, without holding a handle
+    match fs::metadata(&self.path) {
+      Ok(metadata) => {
+        self.size.store(metadata.len(), Ordering::Relaxed);
+        metadata.len()
+      }
+      Err(e) => {
+        error!("failed to read data file metadata error: {e}");
+        0
+      }
+    }
+  }
+}
+
+impl Drop for PooledFileIO {
+  fn drop(&mut self) {
+    self.pool.remove(self.id);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use tempfile::tempdir;
+
+  use super::*;
+
+  #[test]
+  fn test_pooled_file_io_lru() {
+    let dir = tempdir().unwrap();
+    let pool = Arc::new(FilePool::new(2));
+    let mut files = Vec::new();
+    for i in 0..4u8 {
+      let path = dir.path().join(format!("{i}.data"));
+      fs::write(&path, [i; 8]).unwrap();
+      files.push(PooledFileIO::new(&path, &pool).unwrap());
+    }
+    assert_eq!(2, pool.open_files());
+
+    // evicted files are reopened transparently
+    for (i, file) in files.iter().enumerate() {
+      let mut buf = [0u8; 4];
+      assert_eq!(4, file.read(&mut buf, 2).unwrap());
+      assert_eq!([i as u8; 4], buf);
+      assert_eq!(8, file.size());
+      assert!(pool.open_files() <= 2);
+    }
+    assert!(files[0].slot.fd.lock().is_none());
+    assert!(files[3].slot.fd.lock().is_some());
+
+    assert!(files[0].write(b"data").is_err());
+    files.clear();
+    assert_eq!(0, pool.open_files());
+  }
+
+  #[test]
+  fn test_pooled_file_io_missing_file() {
+    let dir = tempdir().unwrap();
+    let pool = Arc::new(FilePool::new(2));
+    let err = PooledFileIO::new(dir.path().join("missing.data"), &pool)
+      .err()
+      .unwrap();
+    assert!(matches!(
+      err,
+      Errors::DataFileIo {
+        op: DataFileOp::Open,
+        ..
+      }
+    ));
+    assert_eq!(
+      Some(io::ErrorKind::NotFound),
+      err.io_error().map(io::Error::kind)
+    );
+  }
+}
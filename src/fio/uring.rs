@@ -0,0 +1,230 @@
+use std::{
+  fs::{File, OpenOptions},
+  io::{self, Write},
+  os::unix::{fs::FileExt, io::AsRawFd},
+  path::Path,
+};
+
+use io_uring::{opcode, squeue, types, IoUring};
+use log::{error, warn};
+use parking_lot::Mutex;
+
+use crate::errors::{DataFileOp, Errors, Result};
+
+use super::IOManager;
+
+// submission queue depth, larger batches are submitted in several rounds
+const RING_ENTRIES: u32 = 32;
+
+// offset telling io_uring to use the file position, the file is opened in append mode
+const FILE_POSITION: u64 = u64::MAX;
+
+/// IO through an io_uring instance of its own, batched reads are submitted together.
+///
+/// Kernels without io_uring (or with it disabled) are served with plain positional IO.
+pub struct UringIO {
+  fd: File,
+  ring: Option<Mutex<IoUring>>,
+}
+
+impl UringIO {
+  pub fn new<P>(file_name: P) -> Result<Self>
+  where
+    P: AsRef<Path>,
+  {
+    let fd = OpenOptions::new()
+      .create(true)
+      .read(true)
+      .append(true)
+      .open(file_name)
+      .map_err(|e| {
+        error!("failed to open data file error: {e}");
+        Errors::data_file_io(DataFileOp::Open, e)
+      })?;
+    let ring = match IoUring::new(RING_ENTRIES) {
+      Ok(ring) => Some(Mutex::new(ring)),
+      Err(e) => {
+        warn!("io_uring is not available, using standard file io: {e}");
+        None
+      }
+    };
+    Ok(Self { fd, ring })
+  }
+
+  fn fd(&self) -> types::Fd {
+    types::Fd(self.fd.as_raw_fd())
+  }
+
+  /// Submits the entries and waits for all of them, returns the result of each entry in order.
+  ///
+  /// # Safety
+  ///
+  /// Buffers referenced by the entries must stay valid until this returns.
+  unsafe fn submit(ring: &Mutex<IoUring>, entries: Vec<squeue::Entry>) -> io::Result<Vec<i32>> {
+    let mut ring = ring.lock();
+    let mut results = vec![0; entries.len()];
+    for (round, chunk) in entries.chunks(RING_ENTRIES as usize).enumerate() {
+      let base = round * RING_ENTRIES as usize;
+      for (i, entry) in chunk.iter().enumerate() {
+        let entry = entry.clone().user_data((base + i) as u64);
+        // SAFETY: the queue has room for a full chunk, buffers are kept alive by the caller
+        if ring.submission().push(&entry).is_err() {
+          return Err(io::Error::other("io_uring submission queue is full"));
+        }
+      }
+
+      // every completion of the round is collected, even after a failure, so no buffer is
+      // still in use by the kernel when we return
+      let mut pending = chunk.len();
+      while pending > 0 {
+        match ring.submit_and_wait(pending) {
+          Ok(_) => {}
+          Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+          Err(e) => return Err(e),
+        }
+        for cqe in ring.completion() {
+          if let Some(result) = results.get_mut(cqe.user_data() as usize) {
+            *result = cqe.result();
+          }
+          pending -= 1;
+        }
+      }
+    }
+    Ok(results)
+  }
+
+  fn completed(result: i32) -> io::Result<usize> {
+    match result {
+      n if n >= 0 => Ok(n as usize),
+      n => Err(io::Error::from_raw_os_error(-n)),
+    }
+  }
+}
+
+impl IOManager for UringIO {
+  fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+    let mut reads = [(buf, offset)];
+    let sizes = self.read_batch(&mut reads)?;
+    Ok(sizes.first().copied().unwrap_or(0))
+  }
+
+  fn read_batch(&self, reads: &mut [(&mut [u8], u64)]) -> Result<Vec<usize>> {
+    let read_error = |offset: u64| {
+      move |e: io::Error| {
+        error!("read from data file error: {e}");
+        Errors::data_file_read(offset, e)
+      }
+    };
+    let Some(ring) = self.ring.as_ref() else {
+      return reads
+        .iter_mut()
+        .map(|(buf, offset)| self.fd.read_at(buf, *offset).map_err(read_error(*offset)))
+        .collect();
+    };
+    let offsets: Vec<u64> = reads.iter().map(|(_, offset)| *offset).collect();
+
+    let entries = reads
+      .iter_mut()
+      .map(|(buf, offset)| {
+        opcode::Read::new(self.fd(), buf.as_mut_ptr(), buf.len() as u32)
+          .offset(*offset)
+          .build()
+      })
+      .collect();
+    // SAFETY: the buffers are borrowed from `reads` for the whole call
+    let first_offset = offsets.first().copied().unwrap_or(0);
+    let results = unsafe { Self::submit(ring, entries) }.map_err(read_error(first_offset))?;
+    results
+      .into_iter()
+      .zip(offsets)
+      .map(|(result, offset)| Self::completed(result).map_err(read_error(offset)))
+      .collect()
+  }
+
+  fn write(&self, buf: &[u8]) -> Result<usize> {
+    let write_error = |e: io::Error| {
+      error!("write to data file error: {e}");
+      Errors::data_file_io(DataFileOp::Write, e)
+    };
+    let Some(ring) = self.ring.as_ref() else {
+      return (&self.fd).write(buf).map_err(write_error);
+    };
+
+    let entry = opcode::Write::new(self.fd(), buf.as_ptr(), buf.len() as u32)
+      .offset(FILE_POSITION)
+      .build();
+    // SAFETY: `buf` is borrowed for the whole call
+    let results = unsafe { Self::submit(ring, vec![entry]) }.map_err(write_error)?;
+    Self::completed(results.first().copied().unwrap_or(0)).map_err(write_error)
+  }
+
+  fn sync(&self) -> Result<()> {
+    let sync_error = |e: io::Error| {
+      error!("failed to sync data file err: {e}");
+      Errors::data_file_io(DataFileOp::Sync, e)
+    };
+    let Some(ring) = self.ring.as_ref() else {
+      return self.fd.sync_all().map_err(sync_error);
+    };
+
+    let entry = opcode::Fsync::new(self.fd()).build();
+    // SAFETY: fsync references no buffer
+    let results = unsafe { Self::submit(ring, vec![entry]) }.map_err(sync_error)?;
+    Self::completed(results.first().copied().unwrap_or(0)).map_err(sync_error)?;
+    Ok(())
+  }
+
+  fn size(&self) -> u64 {
+    match self.fd.metadata() {
+      Ok(metadata) => metadata.len(),
+      Err(e) => {
+        error!("failed to read data file metadata error: {e}");
+        0
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_uring_io_read_write() {
+    let dir = tempfile::tempdir().unwrap();
+    let io = UringIO::new(dir.path().join("0.data")).unwrap();
+    assert_eq!(5, io.write(b"key-a").unwrap());
+    assert_eq!(5, io.write(b"key-b").unwrap());
+    io.sync().unwrap();
+    assert_eq!(10, io.size());
+
+    let mut buf = [0u8; 5];
+    assert_eq!(5, io.read(&mut buf, 5).unwrap());
+    assert_eq!(b"key-b", &buf);
+
+    // reads past the end of file are short
+    let mut buf = [0u8; 8];
+    assert_eq!(2, io.read(&mut buf, 8).unwrap());
+  }
+
+  #[test]
+  fn test_uring_io_read_batch() {
+    let dir = tempfile::tempdir().unwrap();
+    let io = UringIO::new(dir.path().join("0.data")).unwrap();
+    let content: Vec<u8> = (0..200u8).collect();
+    io.write(&content).unwrap();
+
+    // more reads than the ring holds are submitted in several rounds
+    let mut bufs = [[0u8; 2]; 100];
+    let mut reads: Vec<(&mut [u8], u64)> = bufs
+      .iter_mut()
+      .enumerate()
+      .map(|(i, buf)| (buf.as_mut_slice(), 2 * i as u64))
+      .collect();
+    let sizes = io.read_batch(&mut reads).unwrap();
+    assert_eq!(vec![2; 100], sizes);
+    for (i, buf) in bufs.iter().enumerate() {
+      assert_eq!([2 * i as u8, 2 * i as u8 + 1], *buf);
+    }
+  }
+}
@@ -0,0 +1,443 @@
+use std::{collections::HashMap, fs, path::Path, sync::atomic::Ordering, time::SystemTime};
+
+use bytes::{Buf, BufMut, BytesMut};
+use log::{error, warn};
+use parking_lot::Mutex;
+use prost::{decode_length_delimiter, encode_length_delimiter};
+
+use crate::{
+  batch::{parse_log_record_key, NON_TXN_SEQ_NO},
+  data::{
+    data_file::{
+      get_blob_file_name, get_data_file_name, DataFile, GARBAGE_MAP_FILE_NAME,
+      GARBAGE_MAP_TMP_FILE_NAME,
+    },
+    log_record::{LogRecord, LogRecordPos, LogRecordType},
+  },
+  db::Engine,
+  errors::{Errors, Result},
+  option::{MergePolicy, StorageMode, TombstoneFormat},
+  reopen::remove_file_if_exists,
+};
+
+const GARBAGE_MAP_KEY: &[u8] = "garbage.map".as_bytes();
+// active file id and offset covered by the map
+const GARBAGE_MAP_STATE_SIZE: usize = 4 + 8;
+// file id, dead bytes and tombstone bytes
+const GARBAGE_ENTRY_SIZE: usize = 4 + 8 + 8;
+
+/// Dead bytes of every data file, kept in step with the engine's reclaim size.
+#[derive(Default)]
+pub(crate) struct GarbageTracker {
+  files: Mutex<HashMap<u32, GarbageBytes>>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct GarbageBytes {
+  pub(crate) dead: u64,      // stale records, tombstones included
+  pub(crate) tombstone: u64, // delete records, dead as soon as they are written
+}
+
+/// Dead data held by a data file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileGarbage {
+  pub file_id: u32,
+
+  /// Creation time of the file, its modification time on file systems without one
+  pub created_at: SystemTime,
+
+  /// Bytes of records which are no longer referenced, tombstones included
+  pub dead_bytes: u64,
+
+  /// Bytes of delete records
+  pub tombstone_bytes: u64,
+}
+
+/// A data file with the part of it which is dead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataFileInfo {
+  pub file_id: u32,
+
+  /// Bytes written to the file and to its blob file
+  pub size: u64,
+
+  /// Bytes not accounted as dead, the footer of a sealed file included
+  pub live_bytes: u64,
+
+  /// Bytes of records which are no longer referenced, tombstones included
+  pub dead_bytes: u64,
+
+  /// Creation time of the file, its modification time on file systems without one
+  pub created_at: SystemTime,
+}
+
+impl GarbageTracker {
+  fn add(&self, file_id: u32, size: u64, tombstone: bool) {
+    let mut files = self.files.lock();
+    let garbage = files.entry(file_id).or_default();
+    garbage.dead += size;
+    if tombstone {
+      garbage.tombstone += size;
+    }
+  }
+
+  /// Forgets the garbage of files below `file_id`, they are replaced by a merge.
+  pub(crate) fn clear_below(&self, file_id: u32) {
+    self.files.lock().retain(|fid, _| *fid >= file_id);
+  }
+
+  pub(crate) fn entries(&self) -> Vec<(u32, GarbageBytes)> {
+    let mut entries: Vec<_> = self.files.lock().iter().map(|(k, v)| (*k, *v)).collect();
+    entries.sort_by_key(|(file_id, _)| *file_id);
+    entries
+  }
+
+  pub(crate) fn restore(&self, entries: Vec<(u32, GarbageBytes)>) {
+    *self.files.lock() = entries.into_iter().collect();
+  }
+
+  pub(crate) fn tombstone_bytes(&self) -> u64 {
+    self.files.lock().values().map(|g| g.tombstone).sum()
+  }
+}
+
+impl Engine {
+  /// Accounts a record which is no longer referenced by the index.
+  pub(crate) fn mark_stale(&self, pos: LogRecordPos) {
+    // a blob pointer leaves its blob behind as well
+    let size = pos.size as u64 + self.blob_len_at(&pos);
+    self.reclaim_size.fetch_add(size as usize, Ordering::SeqCst);
+    self.garbage.add(pos.file_id, size, false);
+  }
+
+  /// Accounts a delete record, which holds no data from the moment it is written.
+  pub(crate) fn mark_tombstone(&self, pos: LogRecordPos) {
+    self
+      .reclaim_size
+      .fetch_add(pos.size as usize, Ordering::SeqCst);
+    self.garbage.add(pos.file_id, pos.size as u64, true);
+  }
+
+  /// Value of a delete record for the record at `deleted_pos`, following the tombstone format.
+  pub(crate) fn tombstone_value(&self, deleted_pos: Option<LogRecordPos>) -> Vec<u8> {
+    match (self.options.tombstone_format, deleted_pos) {
+      (TombstoneFormat::DeletedSize, Some(pos)) => {
+        let mut value = BytesMut::new();
+        // BytesMut grows on demand so encoding never fails
+        let _ = encode_length_delimiter(pos.file_id as usize, &mut value);
+        let _ = encode_length_delimiter(pos.size as usize, &mut value);
+        value.to_vec()
+      }
+      _ => Vec::new(),
+    }
+  }
+
+  /// Persists the garbage of every data file, it covers the records written before `offset`
+  /// of the active file `file_id`.
+  pub(crate) fn write_garbage_map(&self, file_id: u32, offset: u64) -> Result<()> {
+    if self.options.storage_mode == StorageMode::Memory {
+      return Ok(());
+    }
+    let dir_path = &self.options.dir_path;
+    remove_file_if_exists(&dir_path.join(GARBAGE_MAP_TMP_FILE_NAME))?;
+
+    let mut value = BytesMut::new();
+    value.put_u32(file_id);
+    value.put_u64(offset);
+    value.put_slice(&encode_garbage(&self.garbage.entries()));
+    let record = LogRecord {
+      key: GARBAGE_MAP_KEY.to_vec(),
+      value: value.to_vec(),
+      rec_type: LogRecordType::Normal,
+      timestamp: None,
+    };
+    let map_file = DataFile::new_garbage_map_tmp_file(dir_path)?;
+    map_file.write(&record.encode())?;
+    map_file.sync()?;
+
+    fs::rename(
+      dir_path.join(GARBAGE_MAP_TMP_FILE_NAME),
+      dir_path.join(GARBAGE_MAP_FILE_NAME),
+    )
+    .map_err(|e| {
+      error!("failed to rename garbage map file: {e}");
+      Errors::FailedToRenameFile
+    })
+  }
+
+  /// Restores the garbage from the garbage map, for indexes which are not rebuilt from the
+  /// data files on open. Returns false if there is no valid map.
+  ///
+  /// Delete records written after the map are accounted again, together with the records
+  /// they delete when the tombstones carry their size. Overwritten records are not.
+  pub(crate) fn load_garbage_map(&self) -> Result<bool> {
+    let dir_path = &self.options.dir_path;
+    if !dir_path.join(GARBAGE_MAP_FILE_NAME).is_file() {
+      return Ok(false);
+    }
+    let map_file = DataFile::new_garbage_map_file(dir_path)?;
+    let decoded = map_file
+      .read_log_record(0)
+      .ok()
+      .and_then(|result| decode_garbage_map(&result.record));
+    let Some((map_file_id, map_offset, entries)) = decoded else {
+      warn!("garbage map file is invalid, reclaimable bytes start from zero");
+      return Ok(false);
+    };
+    let dead: u64 = entries.iter().map(|(_, garbage)| garbage.dead).sum();
+    self.garbage.restore(entries);
+    self.reclaim_size.store(dead as usize, Ordering::SeqCst);
+
+    let active_file = self.active_data_file.read();
+    let active_file_id = active_file.get_file_id();
+    let old_files = self.old_data_files.read();
+    let on_disk = |file_id: u32| file_id == active_file_id || old_files.contains_key(&file_id);
+    let mut file_ids: Vec<u32> = old_files.keys().copied().collect();
+    file_ids.push(active_file_id);
+    file_ids.retain(|file_id| *file_id >= map_file_id);
+    file_ids.sort();
+
+    // deletes of a transaction only count once it finished
+    let mut txn_deletes: HashMap<usize, Vec<(LogRecordPos, Vec<u8>)>> = HashMap::new();
+    for file_id in file_ids {
+      let data_file = match file_id == active_file_id {
+        true => &*active_file,
+        false => old_files.get(&file_id).ok_or(Errors::DataFileNotFound)?,
+      };
+      let start = if file_id == map_file_id {
+        map_offset
+      } else {
+        0
+      };
+      let mut scanner = data_file.scan_from(start);
+      loop {
+        let (result, offset) = match scanner.next_record() {
+          Ok(v) => v,
+          Err(Errors::ReadDataFileEOF) => break,
+          Err(e) => return Err(e),
+        };
+        let record = result.record;
+        let pos = LogRecordPos {
+          file_id,
+          offset,
+          size: result.size as u32,
+        };
+        match record.rec_type {
+          LogRecordType::Deleted => {
+            let (_, seq_no) = parse_log_record_key(record.key)?;
+            match seq_no {
+              NON_TXN_SEQ_NO => self.account_tombstone(pos, &record.value, on_disk),
+              _ => txn_deletes
+                .entry(seq_no)
+                .or_default()
+                .push((pos, record.value)),
+            }
+          }
+          LogRecordType::TxnFinished => {
+            let (_, seq_no) = parse_log_record_key(record.key)?;
+            for (pos, value) in txn_deletes.remove(&seq_no).unwrap_or_default() {
+              self.account_tombstone(pos, &value, on_disk);
+            }
+          }
+          _ => {}
+        }
+      }
+    }
+    Ok(true)
+  }
+
+  // accounts a replayed delete record and the record it deleted, if it is still on disk
+  fn account_tombstone<F>(&self, pos: LogRecordPos, value: &[u8], on_disk: F)
+  where
+    F: Fn(u32) -> bool,
+  {
+    self.mark_tombstone(pos);
+    if let Some(deleted_pos) = decode_tombstone(value) {
+      if on_disk(deleted_pos.file_id) {
+        self.mark_stale(deleted_pos);
+      }
+    }
+  }
+
+  /// Lists the data files holding dead data, ordered by file id.
+  pub fn file_garbage(&self) -> Result<Vec<FileGarbage>> {
+    Ok(
+      self
+        .garbage
+        .entries()
+        .into_iter()
+        .filter(|(_, garbage)| garbage.dead > 0)
+        .map(|(file_id, garbage)| FileGarbage {
+          file_id,
+          created_at: file_created_at(&self.data_file_dir(file_id), file_id),
+          dead_bytes: garbage.dead,
+          tombstone_bytes: garbage.tombstone,
+        })
+        .collect(),
+    )
+  }
+
+  /// Lists every data file, the active one included, ordered by file id.
+  pub fn data_files(&self) -> Vec<DataFileInfo> {
+    let garbage: HashMap<_, _> = self.garbage.entries().into_iter().collect();
+    let mut sizes: Vec<_> = (self.old_data_files.read().iter())
+      .map(|(file_id, data_file)| (*file_id, data_file.file_size()))
+      .collect();
+    let active_file = self.active_data_file.read();
+    sizes.push((active_file.get_file_id(), active_file.file_size()));
+    drop(active_file);
+    sizes.sort_by_key(|(file_id, _)| *file_id);
+
+    sizes
+      .into_iter()
+      .map(|(file_id, size)| {
+        let size = size + blob_file_size(&self.data_file_dir(file_id), file_id);
+        let dead_bytes = garbage.get(&file_id).map_or(0, |garbage| garbage.dead);
+        DataFileInfo {
+          file_id,
+          size,
+          live_bytes: size.saturating_sub(dead_bytes),
+          dead_bytes,
+          created_at: file_created_at(&self.data_file_dir(file_id), file_id),
+        }
+      })
+      .collect()
+  }
+
+  /// Whether the merge policy asks for a merge, given the reclaimable and the total bytes.
+  pub(crate) fn merge_due(&self, reclaim_size: usize, total_size: u64) -> Result<bool> {
+    let ratio = reclaim_size as f32 / total_size as f32;
+    if ratio >= f32::from_bits(self.merge_threshold.load(Ordering::Relaxed)) {
+      return Ok(true);
+    }
+
+    // the policies below only look at sealed files, the active file is still growing
+    let active_file_id = self.active_data_file.read().get_file_id();
+    let mut sealed_garbage = self.file_garbage()?;
+    sealed_garbage.retain(|garbage| garbage.file_id != active_file_id);
+    Ok(match self.options.merge_policy {
+      MergePolicy::Threshold => false,
+      MergePolicy::Tiered { max_files } => sealed_garbage.len() >= max_files,
+      MergePolicy::TimeWindow { max_age } => {
+        let now = SystemTime::now();
+        sealed_garbage
+          .iter()
+          .any(|garbage| now.duration_since(garbage.created_at).unwrap_or_default() >= max_age)
+      }
+    })
+  }
+}
+
+/// Position of the record deleted by a tombstone written as `TombstoneFormat::DeletedSize`,
+/// the offset is not recorded.
+fn decode_tombstone(mut value: &[u8]) -> Option<LogRecordPos> {
+  if value.is_empty() {
+    return None;
+  }
+  let file_id = decode_length_delimiter(&mut value).ok()?;
+  let size = decode_length_delimiter(&mut value).ok()?;
+  Some(LogRecordPos {
+    file_id: u32::try_from(file_id).ok()?,
+    offset: 0,
+    size: u32::try_from(size).ok()?,
+  })
+}
+
+pub(crate) fn encode_garbage(entries: &[(u32, GarbageBytes)]) -> Vec<u8> {
+  let mut garbage = BytesMut::with_capacity(entries.len() * GARBAGE_ENTRY_SIZE);
+  for (file_id, bytes) in entries {
+    garbage.put_u32(*file_id);
+    garbage.put_u64(bytes.dead);
+    garbage.put_u64(bytes.tombstone);
+  }
+  garbage.to_vec()
+}
+
+pub(crate) fn decode_garbage_entries(value: &[u8]) -> Option<Vec<(u32, GarbageBytes)>> {
+  if !value.len().is_multiple_of(GARBAGE_ENTRY_SIZE) {
+    return None;
+  }
+  let entries = value
+    .chunks(GARBAGE_ENTRY_SIZE)
+    .map(|mut entry| {
+      let file_id = entry.get_u32();
+      let bytes = GarbageBytes {
+        dead: entry.get_u64(),
+        tombstone: entry.get_u64(),
+      };
+      (file_id, bytes)
+    })
+    .collect();
+  Some(entries)
+}
+
+// file id and offset the map was written at, with the garbage up to there
+type GarbageMap = (u32, u64, Vec<(u32, GarbageBytes)>);
+
+fn decode_garbage_map(record: &LogRecord) -> Option<GarbageMap> {
+  if record.key != GARBAGE_MAP_KEY || record.value.len() < GARBAGE_MAP_STATE_SIZE {
+    return None;
+  }
+  let mut value = record.value.as_slice();
+  let file_id = value.get_u32();
+  let offset = value.get_u64();
+  Some((file_id, offset, decode_garbage_entries(value)?))
+}
+
+// size of the blob file of a data file, 0 if it has none
+fn blob_file_size(dir_path: &Path, file_id: u32) -> u64 {
+  (get_blob_file_name(dir_path, file_id).metadata()).map_or(0, |metadata| metadata.len())
+}
+
+fn file_created_at(dir_path: &Path, file_id: u32) -> SystemTime {
+  let file_name = get_data_file_name(dir_path, file_id);
+  match file_name.metadata() {
+    Ok(metadata) => metadata
+      .created()
+      .or_else(|_| metadata.modified())
+      .unwrap_or_else(|_| SystemTime::now()),
+    Err(e) => {
+      warn!("failed to read metadata of data file {file_id}: {e}");
+      SystemTime::now()
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_garbage_tracker() {
+    let tracker = GarbageTracker::default();
+    tracker.add(0, 10, false);
+    tracker.add(0, 5, true);
+    tracker.add(2, 7, true);
+    assert_eq!(12, tracker.tombstone_bytes());
+    assert_eq!(
+      vec![
+        (
+          0,
+          GarbageBytes {
+            dead: 15,
+            tombstone: 5
+          }
+        ),
+        (
+          2,
+          GarbageBytes {
+            dead: 7,
+            tombstone: 7
+          }
+        ),
+      ],
+      tracker.entries()
+    );
+
+    tracker.clear_below(2);
+    assert_eq!(
+      vec![2],
+      tracker.entries().iter().map(|e| e.0).collect::<Vec<_>>()
+    );
+  }
+}
@@ -1,15 +1,16 @@
-use std::{fs, path::Path, sync::Arc};
+use std::{fs, ops::Bound, path::Path, sync::Arc};
 
 use bytes::Bytes;
 use jammdb::DB;
+use log::error;
 
 use crate::{
   data::log_record::{decode_log_record_pos, LogRecordPos},
-  errors::Result,
+  errors::{Errors, Result},
   option::IteratorOptions,
 };
 
-use super::{IndexIterator, Indexer};
+use super::{prefix_end, IndexIterator, Indexer};
 
 const BPTREE_INDEX_FILE_NAME: &str = "bptree-index";
 const BPTREE_BUCKET_NAME: &str = "bitcask-index";
@@ -20,70 +21,219 @@ pub struct BPlusTree {
 }
 
 impl BPlusTree {
-  pub fn new<P>(dir_path: P) -> Self
+  pub fn new<P>(dir_path: P) -> Result<Self>
+  where
+    P: AsRef<Path>,
+  {
+    Self::open(dir_path, BPTREE_INDEX_FILE_NAME)
+  }
+
+  // opens the b+ tree stored in `file_name` of the directory
+  pub(super) fn open<P>(dir_path: P, file_name: &str) -> Result<Self>
   where
     P: AsRef<Path>,
   {
     if !dir_path.as_ref().exists() {
-      fs::create_dir_all(&dir_path).expect("fail to create b+ tree dir");
+      fs::create_dir_all(&dir_path).map_err(|e| {
+        error!("failed to create b+ tree dir: {e}");
+        Errors::FailedToCreateDatabaseDir
+      })?;
     }
-    let path = dir_path.as_ref().join(BPTREE_INDEX_FILE_NAME);
-    let bptree = DB::open(path.as_path()).expect("fail to open b+ tree");
+    let path = dir_path.as_ref().join(file_name);
+    let bptree = DB::open(path.as_path()).map_err(index_error(Errors::FailedToOpenIndex))?;
     let tree = Arc::new(bptree);
-    let tx = tree.tx(true).expect("failed to begin tx");
-    tx.get_or_create_bucket(BPTREE_BUCKET_NAME).unwrap();
-    tx.commit().unwrap();
-    Self { tree }
+    let tx = tree
+      .tx(true)
+      .map_err(index_error(Errors::FailedToOpenIndex))?;
+    tx.get_or_create_bucket(BPTREE_BUCKET_NAME)
+      .map_err(index_error(Errors::FailedToOpenIndex))?;
+    tx.commit()
+      .map_err(index_error(Errors::FailedToOpenIndex))?;
+    Ok(Self { tree })
+  }
+
+  pub(super) fn try_get(&self, key: Vec<u8>) -> Result<Option<LogRecordPos>> {
+    let tx = self
+      .tree
+      .tx(false)
+      .map_err(index_error(Errors::FailedToReadIndex))?;
+    let bucket = tx
+      .get_bucket(BPTREE_BUCKET_NAME)
+      .map_err(index_error(Errors::FailedToReadIndex))?;
+    bucket
+      .get_kv(&key)
+      .map(|kv| decode_log_record_pos(kv.value().to_vec()))
+      .transpose()
+  }
+
+  /// Items starting with `prefix` in key order, the cursor starts at the prefix.
+  pub(super) fn try_items(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, LogRecordPos)>> {
+    self.try_items_from(prefix, Bound::Unbounded, usize::MAX)
+  }
+
+  /// Up to `limit` items starting with `prefix` from `start` on in key order.
+  pub(super) fn try_items_from(
+    &self,
+    prefix: &[u8],
+    start: Bound<&[u8]>,
+    limit: usize,
+  ) -> Result<Vec<(Vec<u8>, LogRecordPos)>> {
+    let from = match start {
+      Bound::Included(key) | Bound::Excluded(key) if key > prefix => key,
+      _ => prefix,
+    };
+    let tx = self
+      .tree
+      .tx(false)
+      .map_err(index_error(Errors::FailedToReadIndex))?;
+    let bucket = tx
+      .get_bucket(BPTREE_BUCKET_NAME)
+      .map_err(index_error(Errors::FailedToReadIndex))?;
+    let mut items = Vec::new();
+    let mut cursor = bucket.cursor();
+    // seeking an empty tree is not supported by the cursor
+    if !from.is_empty() && bucket.cursor().next().is_some() && !cursor.seek(from) {
+      // the cursor stops just before where the key would be, possibly past a leaf's end
+      if cursor.current().is_none_or(|data| data.key() < from) {
+        cursor.next();
+      }
+    }
+    let end = prefix_end(prefix);
+    for data in cursor {
+      if (end.as_deref()).is_some_and(|end| data.key() >= end) || items.len() == limit {
+        break;
+      }
+      if data.key() < from || start == Bound::Excluded(data.key()) {
+        continue;
+      }
+      let key = data.key().to_vec();
+      let pos = decode_log_record_pos(data.kv().value().to_vec())?;
+      items.push((key, pos));
+    }
+    Ok(items)
+  }
+}
+
+fn index_error(err: Errors) -> impl FnOnce(jammdb::Error) -> Errors {
+  move |e| {
+    error!("b+ tree index error: {e}");
+    err
   }
 }
 
+// The infallible methods log failures of the underlying b+ tree and treat the operation as
+// a miss, the engine goes through the fallible ones.
 impl Indexer for BPlusTree {
   fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> Option<LogRecordPos> {
-    let tx = self.tree.tx(true).expect("failed to begin tx");
-    let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).unwrap();
-    let mut result = None;
+    self
+      .try_put(key, pos)
+      .unwrap_or_else(|e| log_miss("put", e))
+  }
+
+  fn try_put(&self, key: Vec<u8>, pos: LogRecordPos) -> Result<Option<LogRecordPos>> {
+    let tx = self
+      .tree
+      .tx(true)
+      .map_err(index_error(Errors::FailedToUpdateIndex))?;
+    let bucket = tx
+      .get_bucket(BPTREE_BUCKET_NAME)
+      .map_err(index_error(Errors::FailedToUpdateIndex))?;
     // get previous value
-    if let Some(kv) = bucket.get_kv(&key) {
-      let prev_pos = decode_log_record_pos(kv.value().to_vec());
-      result = Some(prev_pos);
-    }
+    let result = match bucket.get_kv(&key) {
+      Some(kv) => Some(decode_log_record_pos(kv.value().to_vec())?),
+      None => None,
+    };
 
     // put new value
     bucket
       .put(key, pos.encode())
-      .expect("failed to put k/v pair");
+      .map_err(index_error(Errors::FailedToUpdateIndex))?;
 
-    tx.commit().unwrap();
-    result
+    tx.commit()
+      .map_err(index_error(Errors::FailedToUpdateIndex))?;
+    Ok(result)
   }
 
-  fn get(&self, key: Vec<u8>) -> Option<LogRecordPos> {
-    let tx = self.tree.tx(false).expect("failed to begin tx");
-    let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).unwrap();
-    bucket
-      .get_kv(&key)
-      .map(|kv| decode_log_record_pos(kv.value().to_vec()))
+  fn try_delete(&self, key: Vec<u8>) -> Result<Option<LogRecordPos>> {
+    let tx = self
+      .tree
+      .tx(true)
+      .map_err(index_error(Errors::FailedToUpdateIndex))?;
+    let bucket = tx
+      .get_bucket(BPTREE_BUCKET_NAME)
+      .map_err(index_error(Errors::FailedToUpdateIndex))?;
+
+    // get previous value
+    let result = match bucket.delete(&key) {
+      Ok(kv) => Some(decode_log_record_pos(kv.value().to_vec())?),
+      Err(_) => None,
+    };
+    tx.commit()
+      .map_err(index_error(Errors::FailedToUpdateIndex))?;
+    Ok(result)
   }
 
-  fn delete(&self, key: Vec<u8>) -> Option<LogRecordPos> {
-    let tx = self.tree.tx(true).expect("failed to begin tx");
-    let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).unwrap();
-    let mut result = None;
+  fn put_batch(
+    &self,
+    entries: Vec<(Vec<u8>, LogRecordPos)>,
+    _sorted: bool,
+  ) -> Vec<Option<LogRecordPos>> {
+    let len = entries.len();
+    self.try_put_batch(entries, false).unwrap_or_else(|e| {
+      error!("b+ tree index put batch failed: {e}");
+      vec![None; len]
+    })
+  }
 
-    // get previous value
-    if let Ok(kv) = bucket.delete(&key) {
-      let prev_pos = decode_log_record_pos(kv.value().to_vec());
-      result = Some(prev_pos);
+  // all keys are put in a single transaction instead of one commit per key, a failure
+  // rolls the whole batch back
+  fn try_put_batch(
+    &self,
+    entries: Vec<(Vec<u8>, LogRecordPos)>,
+    _sorted: bool,
+  ) -> Result<Vec<Option<LogRecordPos>>> {
+    let tx = self
+      .tree
+      .tx(true)
+      .map_err(index_error(Errors::FailedToUpdateIndex))?;
+    let bucket = tx
+      .get_bucket(BPTREE_BUCKET_NAME)
+      .map_err(index_error(Errors::FailedToUpdateIndex))?;
+    let mut results = Vec::with_capacity(entries.len());
+    for (key, pos) in entries {
+      let result = match bucket.get_kv(&key) {
+        Some(kv) => Some(decode_log_record_pos(kv.value().to_vec())?),
+        None => None,
+      };
+      bucket
+        .put(key, pos.encode())
+        .map_err(index_error(Errors::FailedToUpdateIndex))?;
+      results.push(result);
     }
-    tx.commit().unwrap();
-    result
+
+    tx.commit()
+      .map_err(index_error(Errors::FailedToUpdateIndex))?;
+    Ok(results)
+  }
+
+  fn get(&self, key: Vec<u8>) -> Option<LogRecordPos> {
+    self.try_get(key).unwrap_or_else(|e| log_miss("get", e))
+  }
+
+  fn delete(&self, key: Vec<u8>) -> Option<LogRecordPos> {
+    self
+      .try_delete(key)
+      .unwrap_or_else(|e| log_miss("delete", e))
   }
 
   fn list_keys(&self) -> Result<Vec<Bytes>> {
-    let tx = self.tree.tx(false).expect("failed to begin tx");
+    let tx = self
+      .tree
+      .tx(false)
+      .map_err(index_error(Errors::FailedToReadIndex))?;
     let bucket = tx
       .get_bucket(BPTREE_BUCKET_NAME)
-      .expect("failed to get bucket");
+      .map_err(index_error(Errors::FailedToReadIndex))?;
     let mut keys = Vec::new();
 
     for data in bucket.cursor() {
@@ -93,30 +243,39 @@ impl Indexer for BPlusTree {
   }
 
   fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator> {
-    let tx = self.tree.tx(false).expect("failed to begin tx");
-    let bucket = tx
-      .get_bucket(BPTREE_BUCKET_NAME)
-      .expect("failed to get bucket");
-    let mut items = Vec::new();
-
-    for data in bucket.cursor() {
-      let key = data.key().to_vec();
-      let pos = decode_log_record_pos(data.kv().value().to_vec());
-      items.push((key, pos));
-    }
+    self.try_iterator(options).unwrap_or_else(|e| {
+      error!("failed to iterate b+ tree index: {e}");
+      Box::new(BPTreeIterator::from_items(
+        Vec::new(),
+        IteratorOptions::default(),
+      ))
+    })
+  }
 
-    if options.reverse {
-      items.reverse();
-    }
+  fn try_iterator(&self, options: IteratorOptions) -> Result<Box<dyn IndexIterator>> {
+    let items = self.try_items(&options.prefix)?;
+    Ok(Box::new(BPTreeIterator::from_items(items, options)))
+  }
 
-    Box::new(BPTreeIterator {
-      items,
-      curr_index: 0,
-      options,
-    })
+  fn clear(&self) -> Result<()> {
+    let tx = self
+      .tree
+      .tx(true)
+      .map_err(index_error(Errors::FailedToUpdateIndex))?;
+    tx.delete_bucket(BPTREE_BUCKET_NAME)
+      .map_err(index_error(Errors::FailedToUpdateIndex))?;
+    tx.create_bucket(BPTREE_BUCKET_NAME)
+      .map_err(index_error(Errors::FailedToUpdateIndex))?;
+    tx.commit()
+      .map_err(index_error(Errors::FailedToUpdateIndex))
   }
 }
 
+fn log_miss(op: &str, e: Errors) -> Option<LogRecordPos> {
+  error!("b+ tree index {op} failed: {e}");
+  None
+}
+
 /// B+ tree Index Iterator
 pub struct BPTreeIterator {
   items: Vec<(Vec<u8>, LogRecordPos)>, // store key and index
@@ -124,6 +283,23 @@ pub struct BPTreeIterator {
   options: IteratorOptions,            // iterator options
 }
 
+impl BPTreeIterator {
+  // iterates items sorted by key in ascending order, already limited to the prefix
+  pub(super) fn from_items(
+    mut items: Vec<(Vec<u8>, LogRecordPos)>,
+    options: IteratorOptions,
+  ) -> Self {
+    if options.reverse {
+      items.reverse();
+    }
+    Self {
+      items,
+      curr_index: 0,
+      options,
+    }
+  }
+}
+
 impl IndexIterator for BPTreeIterator {
   fn rewind(&mut self) {
     self.curr_index = 0;
@@ -142,19 +318,10 @@ impl IndexIterator for BPTreeIterator {
     };
   }
 
-  fn next(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)> {
-    if self.curr_index >= self.items.len() {
-      return None;
-    }
-
-    while let Some(item) = self.items.get(self.curr_index) {
-      self.curr_index += 1;
-      let prefix = &self.options.prefix;
-      if prefix.is_empty() || item.0.starts_with(prefix) {
-        return Some((&item.0, &item.1));
-      }
-    }
-    None
+  fn next(&mut self) -> Option<(&[u8], &LogRecordPos)> {
+    let item = self.items.get(self.curr_index)?;
+    self.curr_index += 1;
+    Some((item.0.as_slice(), &item.1))
   }
 }
 
@@ -168,7 +335,7 @@ mod tests {
   fn test_bptree_put() {
     let path = PathBuf::from("/tmp/bptree-put");
     fs::create_dir_all(&path).unwrap();
-    let bptree = BPlusTree::new(&path);
+    let bptree = BPlusTree::new(&path).unwrap();
     let res1 = bptree.put(
       "aacd".as_bytes().to_vec(),
       LogRecordPos {
@@ -235,7 +402,7 @@ mod tests {
   fn test_bptree_get() {
     let path = PathBuf::from("/tmp/bptree-get");
     fs::create_dir_all(&path).unwrap();
-    let bptree = BPlusTree::new(&path);
+    let bptree = BPlusTree::new(&path).unwrap();
 
     let res = bptree.get(b"not exists".to_vec());
     assert!(res.is_none());
@@ -307,7 +474,7 @@ mod tests {
   fn test_bptree_delete() {
     let path = PathBuf::from("/tmp/bptree-delete");
     fs::create_dir_all(&path).unwrap();
-    let bptree = BPlusTree::new(&path);
+    let bptree = BPlusTree::new(&path).unwrap();
 
     let res = bptree.delete(b"not exists".to_vec());
     assert!(res.is_none());
@@ -344,7 +511,7 @@ mod tests {
   fn test_bptree_list_keys() {
     let path = PathBuf::from("/tmp/bptree-list-keys");
     fs::create_dir_all(&path).unwrap();
-    let bptree = BPlusTree::new(&path);
+    let bptree = BPlusTree::new(&path).unwrap();
 
     let keys = bptree.list_keys().unwrap();
     assert!(keys.is_empty());
@@ -385,11 +552,12 @@ mod tests {
     fs::remove_dir_all(path).unwrap();
   }
 
+  #[allow(clippy::field_reassign_with_default)]
   #[test]
   fn test_bptree_iterator() {
     let path = PathBuf::from("/tmp/bptree-iterator");
     fs::create_dir_all(&path).unwrap();
-    let bptree = BPlusTree::new(&path);
+    let bptree = BPlusTree::new(&path).unwrap();
 
     let res1 = bptree.put(
       "aacd".as_bytes().to_vec(),
@@ -434,7 +602,7 @@ mod tests {
   fn test_bptree_iterator_rewind() {
     let path = PathBuf::from("/tmp/bptree-iterator-rewind");
     fs::create_dir_all(&path).unwrap();
-    let bptree = BPlusTree::new(&path);
+    let bptree = BPlusTree::new(&path).unwrap();
 
     let res1 = bptree.put(
       "aacd".as_bytes().to_vec(),
@@ -483,7 +651,7 @@ mod tests {
   fn test_bptree_iterator_seek() {
     let path = PathBuf::from("/tmp/bptree-iterator-seek");
     fs::create_dir_all(&path).unwrap();
-    let bptree = BPlusTree::new(&path);
+    let bptree = BPlusTree::new(&path).unwrap();
 
     let res1 = bptree.put(
       "aacd".as_bytes().to_vec(),
@@ -527,7 +695,7 @@ mod tests {
   fn test_bptree_iterator_next() {
     let path = PathBuf::from("/tmp/bptree-iterator-next");
     fs::create_dir_all(&path).unwrap();
-    let bptree = BPlusTree::new(&path);
+    let bptree = BPlusTree::new(&path).unwrap();
 
     let res1 = bptree.put(
       "aacd".as_bytes().to_vec(),
@@ -573,4 +741,61 @@ mod tests {
 
     fs::remove_dir_all(path).unwrap();
   }
+
+  #[allow(clippy::field_reassign_with_default)]
+  #[test]
+  fn test_bptree_iterator_prefix() {
+    let path = PathBuf::from("/tmp/bptree-iterator-prefix");
+    fs::create_dir_all(&path).unwrap();
+    let bptree = BPlusTree::new(&path).unwrap();
+
+    let list = |prefix: &[u8]| {
+      let mut opt = IteratorOptions::default();
+      opt.prefix = prefix.to_vec();
+      let mut iter = bptree.iterator(opt);
+      let mut keys = Vec::new();
+      while let Some((key, _)) = iter.next() {
+        keys.push(key.to_vec());
+      }
+      keys
+    };
+    assert!(list(b"key-").is_empty());
+
+    // enough keys to span many leaves
+    let mut keys: Vec<Vec<u8>> = (0..2000u64)
+      .map(|i| format!("key-{i:04}").into_bytes())
+      .collect();
+    keys.push(vec![0xff, 0xff, 1]);
+    keys.push(vec![0xff, 0xff, 2]);
+    let entries = (keys.iter())
+      .map(|key| {
+        let pos = LogRecordPos {
+          file_id: 1,
+          offset: 0,
+          size: 12,
+        };
+        (key.clone(), pos)
+      })
+      .collect();
+    bptree.put_batch(entries, true);
+
+    let prefixes = (0..200u64)
+      .map(|i| format!("key-{i:03}").into_bytes())
+      .chain([
+        b"key-".to_vec(),
+        b"a".to_vec(),
+        b"key-5".to_vec(),
+        b"z".to_vec(),
+      ])
+      .chain([vec![0xff], vec![0xff, 0xff, 2], vec![]]);
+    for prefix in prefixes {
+      let expected: Vec<_> = (keys.iter())
+        .filter(|key| key.starts_with(&prefix))
+        .cloned()
+        .collect();
+      assert_eq!(expected, list(&prefix), "prefix {prefix:?}");
+    }
+
+    fs::remove_dir_all(path).unwrap();
+  }
 }
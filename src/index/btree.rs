@@ -1,70 +1,222 @@
-use crate::{data::log_record::LogRecordPos, errors::Result, option::IteratorOptions};
+use crate::{
+  data::log_record::LogRecordPos,
+  errors::Result,
+  option::{IteratorOptions, KeyComparator},
+};
 use bytes::Bytes;
 use parking_lot::RwLock;
-use std::{collections::BTreeMap, sync::Arc};
+use std::{
+  cmp::Reverse,
+  collections::{hash_map::DefaultHasher, BTreeMap, BinaryHeap},
+  hash::{Hash, Hasher},
+  ops::Bound,
+};
 
-use super::{IndexIterator, Indexer};
+use super::{iterate_keys_after, prefix_bounds, prefix_end, IndexIterator, Indexer};
 
 // BTree Indexer, primarily encapsulates the 'BTreeMap' from std, is used for efficiently storing and querying data in sorted manner,
 // allowing for fast retrieval,insertion,and deletion of items based on their keys.
+// Keys are spread over several shards by hash, each shard has its own lock so writers of
+// different keys don't serialize on a single lock.
 pub struct BTree {
-  tree: Arc<RwLock<BTreeMap<Vec<u8>, LogRecordPos>>>,
+  shards: Vec<Shard>,
+  comparator: Option<KeyComparator>, // order of the iterators, the shards stay in byte order
 }
 
+type Shard = RwLock<BTreeMap<Vec<u8>, LogRecordPos>>;
+
 impl BTree {
-  pub fn new() -> Self {
+  /// Creates a BTree index split into `shards` independently locked trees.
+  pub fn with_shards(shards: usize) -> Self {
+    let shards = (0..shards.max(1))
+      .map(|_| RwLock::new(BTreeMap::new()))
+      .collect();
     Self {
-      tree: Arc::new(RwLock::new(BTreeMap::new())),
+      shards,
+      comparator: None,
     }
   }
+
+  /// Iterates the keys in the order of `comparator` instead of their byte order.
+  pub fn with_comparator(mut self, comparator: Option<KeyComparator>) -> Self {
+    self.comparator = comparator;
+    self
+  }
+
+  fn shard(&self, key: &[u8]) -> &Shard {
+    &self.shards[self.shard_index(key)]
+  }
+
+  fn shard_index(&self, key: &[u8]) -> usize {
+    if self.shards.len() == 1 {
+      return 0;
+    }
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish() as usize % self.shards.len()
+  }
 }
 
 #[allow(clippy::clone_on_copy)]
 impl Indexer for BTree {
   fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> Option<LogRecordPos> {
-    let mut write_guard = self.tree.write();
+    let mut write_guard = self.shard(&key).write();
     write_guard.insert(key, pos)
   }
 
+  fn put_batch(
+    &self,
+    entries: Vec<(Vec<u8>, LogRecordPos)>,
+    sorted: bool,
+  ) -> Vec<Option<LogRecordPos>> {
+    // each shard is locked once for all of its keys
+    let mut shard_entries: Vec<Vec<(usize, Vec<u8>, LogRecordPos)>> =
+      self.shards.iter().map(|_| Vec::new()).collect();
+    let mut results = vec![None; entries.len()];
+    for (i, (key, pos)) in entries.into_iter().enumerate() {
+      let shard = self.shard_index(&key);
+      shard_entries[shard].push((i, key, pos));
+    }
+
+    for (shard, entries) in self.shards.iter().zip(shard_entries) {
+      let Some((_, first_key, _)) = entries.first() else {
+        continue;
+      };
+      let mut write_guard = shard.write();
+      // sorted keys past the end of the shard are all new, the tree is built in one pass
+      let past_end = write_guard
+        .last_key_value()
+        .is_none_or(|(last_key, _)| last_key < first_key);
+      if sorted && past_end {
+        let mut new_entries: BTreeMap<Vec<u8>, LogRecordPos> = entries
+          .into_iter()
+          .map(|(_, key, pos)| (key, pos))
+          .collect();
+        write_guard.append(&mut new_entries);
+        continue;
+      }
+      for (i, key, pos) in entries {
+        results[i] = write_guard.insert(key, pos);
+      }
+    }
+    results
+  }
+
+  fn try_put_batch(
+    &self,
+    entries: Vec<(Vec<u8>, LogRecordPos)>,
+    sorted: bool,
+  ) -> Result<Vec<Option<LogRecordPos>>> {
+    Ok(self.put_batch(entries, sorted))
+  }
+
   fn get(&self, key: Vec<u8>) -> Option<LogRecordPos> {
-    let read_guard = self.tree.read();
+    let read_guard = self.shard(&key).read();
     read_guard.get(&key).copied()
   }
 
   fn delete(&self, key: Vec<u8>) -> Option<LogRecordPos> {
-    let mut write_guard = self.tree.write();
+    let mut write_guard = self.shard(&key).write();
     write_guard.remove(&key)
   }
 
   fn list_keys(&self) -> Result<Vec<Bytes>> {
-    let read_guard = self.tree.read();
-    let mut keys = Vec::with_capacity(read_guard.len());
+    let keys = merge_shards(&self.shards, &[], |key, _| Bytes::copy_from_slice(key));
+    Ok(keys)
+  }
+
+  fn keys_after(
+    &self,
+    prefix: &[u8],
+    start_after: Option<&[u8]>,
+    limit: usize,
+  ) -> Result<Vec<Bytes>> {
+    // the shards are in byte order, a comparator orders the keys only once all are copied
+    if self.comparator.is_some() {
+      return iterate_keys_after(self, prefix, start_after, limit);
+    }
+    let end = prefix_end(prefix);
+    let (mut lower, upper) = prefix_bounds(prefix, &end);
+    if let Some(key) = start_after.filter(|key| *key >= prefix) {
+      if end.as_deref().is_some_and(|end| key >= end) {
+        return Ok(Vec::new());
+      }
+      lower = Bound::Excluded(key);
+    }
 
-    for (k, _) in read_guard.iter() {
-      keys.push(Bytes::copy_from_slice(k));
+    // at most `limit` keys of each shard, the first `limit` of them in key order
+    let mut keys: Vec<Bytes> = Vec::new();
+    for shard in self.shards.iter() {
+      let read_guard = shard.read();
+      keys.extend(
+        (read_guard.range::<[u8], _>((lower, upper)))
+          .take(limit)
+          .map(|(key, _)| Bytes::copy_from_slice(key)),
+      );
     }
+    keys.sort_unstable();
+    keys.truncate(limit);
     Ok(keys)
   }
 
   fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator> {
-    let read_guard = self.tree.read();
-    let mut items = Vec::with_capacity(read_guard.len());
+    // copy the items under the prefix from the shards to Vec in key order
+    let items = merge_shards(&self.shards, &options.prefix, |key, value| {
+      (key.clone(), value.clone())
+    });
+    Box::new(BTreeIterator::new(items, options, self.comparator.clone()))
+  }
 
-    // copy all items from BTreeMap to Vec
-    for (key, value) in read_guard.iter() {
-      items.push((key.clone(), value.clone()));
+  fn clear(&self) -> Result<()> {
+    for shard in self.shards.iter() {
+      shard.write().clear();
     }
+    Ok(())
+  }
+}
 
-    if options.reverse {
-      items.reverse();
-    }
+/// Collects the entries of all shards starting with `prefix` in key order, each shard is locked
+/// only while its range is read.
+fn merge_shards<T, F>(shards: &[Shard], prefix: &[u8], mut f: F) -> Vec<T>
+where
+  F: FnMut(&Vec<u8>, &LogRecordPos) -> T,
+{
+  let end = prefix_end(prefix);
+  let bounds = prefix_bounds(prefix, &end);
+  if let [shard] = shards {
+    let read_guard = shard.read();
+    return (read_guard.range::<[u8], _>(bounds))
+      .map(|(k, v)| f(k, v))
+      .collect();
+  }
 
-    Box::new(BTreeIterator {
-      items,
-      curr_index: 0,
-      options,
+  let snapshots: Vec<Vec<(Vec<u8>, LogRecordPos)>> = shards
+    .iter()
+    .map(|shard| {
+      let read_guard = shard.read();
+      (read_guard.range::<[u8], _>(bounds))
+        .map(|(k, v)| (k.clone(), *v))
+        .collect()
     })
+    .collect();
+
+  // k-way merge, a key lives in exactly one shard so there are no duplicates
+  let total = snapshots.iter().map(Vec::len).sum();
+  let mut result = Vec::with_capacity(total);
+  let mut heap = BinaryHeap::with_capacity(snapshots.len());
+  for (i, snapshot) in snapshots.iter().enumerate() {
+    if let Some((key, _)) = snapshot.first() {
+      heap.push(Reverse((key, i, 0)));
+    }
   }
+  while let Some(Reverse((_, i, idx))) = heap.pop() {
+    let (key, value) = &snapshots[i][idx];
+    result.push(f(key, value));
+    if let Some((next_key, _)) = snapshots[i].get(idx + 1) {
+      heap.push(Reverse((next_key, i, idx + 1)));
+    }
+  }
+  result
 }
 
 /// BTree Index Iterator
@@ -72,6 +224,30 @@ pub struct BTreeIterator {
   items: Vec<(Vec<u8>, LogRecordPos)>, // store key and index
   curr_index: usize,                   //current index
   options: IteratorOptions,            // iterator options
+  comparator: Option<KeyComparator>,   // order of the items, byte order if None
+}
+
+impl BTreeIterator {
+  /// Iterates over `items` given in byte order, they are sorted again by the comparator.
+  /// The items are already limited to `options.prefix`.
+  pub(crate) fn new(
+    mut items: Vec<(Vec<u8>, LogRecordPos)>,
+    options: IteratorOptions,
+    comparator: Option<KeyComparator>,
+  ) -> Self {
+    if let Some(comparator) = &comparator {
+      items.sort_by(|a, b| comparator.compare(&a.0, &b.0));
+    }
+    if options.reverse {
+      items.reverse();
+    }
+    Self {
+      items,
+      curr_index: 0,
+      options,
+      comparator,
+    }
+  }
 }
 
 impl IndexIterator for BTreeIterator {
@@ -81,10 +257,14 @@ impl IndexIterator for BTreeIterator {
 
   fn seek(&mut self, key: Vec<u8>) {
     self.curr_index = match self.items.binary_search_by(|(x, _)| {
+      let ordering = match &self.comparator {
+        Some(comparator) => comparator.compare(x, &key),
+        None => x.cmp(&key),
+      };
       if self.options.reverse {
-        x.cmp(&key).reverse()
+        ordering.reverse()
       } else {
-        x.cmp(&key)
+        ordering
       }
     }) {
       Ok(equal_val) => equal_val,
@@ -92,30 +272,23 @@ impl IndexIterator for BTreeIterator {
     };
   }
 
-  fn next(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)> {
-    if self.curr_index >= self.items.len() {
-      return None;
-    }
-
-    while let Some(item) = self.items.get(self.curr_index) {
-      self.curr_index += 1;
-      let prefix = &self.options.prefix;
-      if prefix.is_empty() || item.0.starts_with(prefix) {
-        return Some((&item.0, &item.1));
-      }
-    }
-    None
+  fn next(&mut self) -> Option<(&[u8], &LogRecordPos)> {
+    let item = self.items.get(self.curr_index)?;
+    self.curr_index += 1;
+    Some((item.0.as_slice(), &item.1))
   }
 }
 
 #[cfg(test)]
 mod tests {
 
+  use std::sync::Arc;
+
   use super::*;
 
   #[test]
   fn test_btree_put() {
-    let bt = BTree::new();
+    let bt = BTree::with_shards(1);
     let res1 = bt.put(
       "".as_bytes().to_vec(),
       LogRecordPos {
@@ -149,7 +322,7 @@ mod tests {
 
   #[test]
   fn test_get() {
-    let bt = BTree::new();
+    let bt = BTree::with_shards(1);
     let res1 = bt.put(
       "".as_bytes().to_vec(),
       LogRecordPos {
@@ -194,7 +367,7 @@ mod tests {
 
   #[test]
   fn test_delete() {
-    let bt = BTree::new();
+    let bt = BTree::with_shards(1);
     let res1 = bt.put(
       "".as_bytes().to_vec(),
       LogRecordPos {
@@ -245,9 +418,10 @@ mod tests {
     assert!(res5.is_none());
   }
 
+  #[allow(clippy::len_zero)]
   #[test]
   fn test_btree_iterator_seek() {
-    let bt = BTree::new();
+    let bt = BTree::with_shards(1);
 
     // no items
     let mut iter1 = bt.iterator(IteratorOptions::default());
@@ -328,9 +502,10 @@ mod tests {
     }
   }
 
+  #[allow(clippy::field_reassign_with_default, clippy::len_zero)]
   #[test]
   fn test_btree_iterator_next() {
-    let bt = BTree::new();
+    let bt = BTree::with_shards(1);
 
     // no items
     let mut iter1 = bt.iterator(IteratorOptions::default());
@@ -392,4 +567,94 @@ mod tests {
       println!("{:?}", String::from_utf8(item.0.to_vec()));
     }
   }
+
+  #[allow(clippy::field_reassign_with_default)]
+  #[test]
+  fn test_btree_shards_ordered_scan() {
+    let bt = BTree::with_shards(8);
+    for i in (0..200u32).rev() {
+      let res = bt.put(
+        format!("key-{i:04}").into_bytes(),
+        LogRecordPos {
+          file_id: 1,
+          offset: i as u64,
+          size: 12,
+        },
+      );
+      assert!(res.is_none());
+    }
+    assert_eq!(Some(10), bt.get(b"key-0010".to_vec()).map(|pos| pos.offset));
+
+    let keys = bt.list_keys().unwrap();
+    assert_eq!(200, keys.len());
+    assert!(keys.windows(2).all(|w| w[0] < w[1]));
+
+    let mut opt = IteratorOptions::default();
+    opt.reverse = true;
+    opt.prefix = b"key-01".to_vec();
+    let mut iter = bt.iterator(opt);
+    iter.seek(b"key-0150".to_vec());
+    let mut offsets = Vec::new();
+    while let Some((_, pos)) = iter.next() {
+      offsets.push(pos.offset);
+    }
+    assert_eq!((100..=150).rev().collect::<Vec<_>>(), offsets);
+  }
+
+  #[test]
+  fn test_btree_shards_concurrent_put() {
+    let bt = Arc::new(BTree::with_shards(4));
+    let handles: Vec<_> = (0..4u64)
+      .map(|t| {
+        let bt = bt.clone();
+        std::thread::spawn(move || {
+          for i in 0..500u64 {
+            bt.put(
+              format!("{t}-{i}").into_bytes(),
+              LogRecordPos {
+                file_id: 1,
+                offset: i,
+                size: 12,
+              },
+            );
+          }
+        })
+      })
+      .collect();
+    for handle in handles {
+      handle.join().unwrap();
+    }
+    assert_eq!(2000, bt.list_keys().unwrap().len());
+  }
+
+  #[test]
+  fn test_btree_put_batch() {
+    let bt = BTree::with_shards(4);
+    let pos = |offset| LogRecordPos {
+      file_id: 1,
+      offset,
+      size: 12,
+    };
+    bt.put(b"key-0005".to_vec(), pos(0));
+
+    // sorted keys, only the existing one has a previous position
+    let entries = (0..100u64)
+      .map(|i| (format!("key-{i:04}").into_bytes(), pos(i)))
+      .collect();
+    let res = bt.put_batch(entries, true);
+    assert_eq!(100, res.len());
+    assert_eq!(
+      vec![5],
+      (0..100).filter(|i| res[*i].is_some()).collect::<Vec<_>>()
+    );
+
+    let entries = vec![
+      (b"key-0050".to_vec(), pos(500)),
+      (b"key-0200".to_vec(), pos(200)),
+    ];
+    let res = bt.put_batch(entries, false);
+    assert_eq!(vec![Some(pos(50)), None], res);
+    assert_eq!(101, bt.list_keys().unwrap().len());
+    assert_eq!(Some(pos(500)), bt.get(b"key-0050".to_vec()));
+  }
 }
@@ -0,0 +1,485 @@
+use std::{
+  cmp::Ordering as KeyOrdering,
+  collections::BTreeMap,
+  fs,
+  ops::Bound,
+  path::Path,
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+  },
+};
+
+use bytes::Bytes;
+use log::error;
+use parking_lot::Mutex;
+
+use crate::{
+  data::log_record::LogRecordPos,
+  errors::{Errors, Result},
+  option::IteratorOptions,
+};
+
+use super::{bptree::BPlusTree, prefix_bounds, prefix_end, IndexIterator, Indexer};
+
+const HYBRID_INDEX_FILE_NAME: &str = "hybrid-index";
+
+// keys read from the b+ tree at once by a forward iterator
+const COLD_CHUNK_SIZE: usize = 1024;
+
+// once the hot keys are full, the least recently used eighth of them is demoted at once so
+// the writes to the b+ tree are batched
+const DEMOTE_DIVISOR: usize = 8;
+
+struct HotEntry {
+  pos: LogRecordPos,
+  tick: u64,     // last access
+  dirty: bool,   // the b+ tree misses this position
+  in_cold: bool, // the b+ tree may hold an entry of the key
+}
+
+#[derive(Default)]
+struct HotKeys {
+  entries: BTreeMap<Vec<u8>, HotEntry>,
+  lru: BTreeMap<u64, Vec<u8>>, // keys by last access, least recent first
+  tick: u64,
+}
+
+impl HotKeys {
+  fn insert(&mut self, key: Vec<u8>, pos: LogRecordPos, dirty: bool, in_cold: bool) {
+    self.tick += 1;
+    let entry = HotEntry {
+      pos,
+      tick: self.tick,
+      dirty,
+      in_cold,
+    };
+    self.lru.insert(self.tick, key.clone());
+    if let Some(old) = self.entries.insert(key, entry) {
+      self.lru.remove(&old.tick);
+    }
+  }
+
+  fn touch(&mut self, key: &[u8]) -> Option<LogRecordPos> {
+    let entry = self.entries.get_mut(key)?;
+    self.tick += 1;
+    if let Some(key) = self.lru.remove(&entry.tick) {
+      self.lru.insert(self.tick, key);
+    }
+    entry.tick = self.tick;
+    Some(entry.pos)
+  }
+
+  fn remove(&mut self, key: &[u8]) -> Option<HotEntry> {
+    let entry = self.entries.remove(key)?;
+    self.lru.remove(&entry.tick);
+    Some(entry)
+  }
+}
+
+/// Index keeping only the most recently used keys in an in-memory BTree, the others are
+/// spilled to a B+ tree on disk and promoted again when they are accessed.
+///
+/// The B+ tree is a spill area rather than a persistent index, it is emptied on open and the
+/// engine rebuilds the index from the data files as it does for the BTree.
+pub struct Hybrid {
+  hot: Mutex<HotKeys>,
+  cold: Arc<BPlusTree>,
+  hot_keys: AtomicUsize,
+}
+
+impl Hybrid {
+  /// Creates a hybrid index keeping up to `hot_keys` keys in memory.
+  pub fn new<P>(dir_path: P, hot_keys: usize) -> Result<Self>
+  where
+    P: AsRef<Path>,
+  {
+    let path = dir_path.as_ref().join(HYBRID_INDEX_FILE_NAME);
+    if path.is_file() {
+      fs::remove_file(&path).map_err(|e| {
+        error!("failed to remove hybrid index {}: {e}", path.display());
+        Errors::FailedToRemoveFile
+      })?;
+    }
+    Ok(Self {
+      hot: Mutex::new(HotKeys::default()),
+      cold: Arc::new(BPlusTree::open(dir_path, HYBRID_INDEX_FILE_NAME)?),
+      hot_keys: AtomicUsize::new(hot_keys.max(1)),
+    })
+  }
+
+  /// Number of keys currently held in memory.
+  pub fn hot_len(&self) -> usize {
+    self.hot.lock().entries.len()
+  }
+
+  // moves the least recently used keys to the b+ tree once there are too many, they stay in
+  // memory if the b+ tree can not be written
+  fn demote(&self, hot: &mut HotKeys) {
+    let hot_keys = self.hot_keys.load(Ordering::Relaxed);
+    if hot.entries.len() <= hot_keys {
+      return;
+    }
+    let keep = hot_keys - hot_keys / DEMOTE_DIVISOR;
+    let demoted: Vec<Vec<u8>> = hot
+      .lru
+      .values()
+      .take(hot.entries.len() - keep)
+      .cloned()
+      .collect();
+    let dirty = demoted
+      .iter()
+      .filter_map(|key| {
+        let entry = hot.entries.get(key)?;
+        entry.dirty.then(|| (key.clone(), entry.pos))
+      })
+      .collect();
+    if let Err(e) = self.cold.try_put_batch(dirty, false) {
+      error!("hybrid index failed to demote keys: {e}");
+      return;
+    }
+    for key in demoted {
+      hot.remove(&key);
+    }
+  }
+
+  // copies the hot keys under the prefix, the b+ tree is read by the iterator without the lock
+  fn new_iterator(&self, options: IteratorOptions) -> HybridIterator {
+    let end = prefix_end(&options.prefix);
+    let mut hot: Vec<_> = {
+      let hot = self.hot.lock();
+      (hot
+        .entries
+        .range::<[u8], _>(prefix_bounds(&options.prefix, &end)))
+      .map(|(key, entry)| (key.clone(), entry.pos))
+      .collect()
+    };
+    if options.reverse {
+      hot.reverse();
+    }
+    let mut iter = HybridIterator {
+      cold: Arc::clone(&self.cold),
+      options,
+      hot,
+      hot_index: 0,
+      cold_items: Vec::new(),
+      cold_index: 0,
+      cold_next: None,
+      error: None,
+    };
+    iter.rewind();
+    iter.fill_cold();
+    iter
+  }
+}
+
+impl Indexer for Hybrid {
+  fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> Option<LogRecordPos> {
+    self.try_put(key, pos).unwrap_or_else(|e| {
+      error!("hybrid index put failed: {e}");
+      None
+    })
+  }
+
+  fn try_put(&self, key: Vec<u8>, pos: LogRecordPos) -> Result<Option<LogRecordPos>> {
+    let mut hot = self.hot.lock();
+    let (old_pos, in_cold) = match hot.entries.get(&key) {
+      Some(entry) => (Some(entry.pos), entry.in_cold),
+      None => {
+        let old_pos = self.cold.try_get(key.clone())?;
+        (old_pos, old_pos.is_some())
+      }
+    };
+    hot.insert(key, pos, true, in_cold);
+    self.demote(&mut hot);
+    Ok(old_pos)
+  }
+
+  fn get(&self, key: Vec<u8>) -> Option<LogRecordPos> {
+    let mut hot = self.hot.lock();
+    if let Some(pos) = hot.touch(&key) {
+      return Some(pos);
+    }
+    let pos = self.cold.try_get(key.clone()).unwrap_or_else(|e| {
+      error!("hybrid index get failed: {e}");
+      None
+    })?;
+    hot.insert(key, pos, false, true);
+    self.demote(&mut hot);
+    Some(pos)
+  }
+
+  fn delete(&self, key: Vec<u8>) -> Option<LogRecordPos> {
+    self.try_delete(key).unwrap_or_else(|e| {
+      error!("hybrid index delete failed: {e}");
+      None
+    })
+  }
+
+  fn try_delete(&self, key: Vec<u8>) -> Result<Option<LogRecordPos>> {
+    let mut hot = self.hot.lock();
+    let in_cold = (hot.entries.get(&key)).is_none_or(|entry| entry.in_cold);
+    // the key stays hot if the b+ tree can not be updated
+    let cold_pos = match in_cold {
+      true => self.cold.try_delete(key.clone())?,
+      false => None,
+    };
+    let hot_pos = hot.remove(&key).map(|entry| entry.pos);
+    Ok(hot_pos.or(cold_pos))
+  }
+
+  fn list_keys(&self) -> Result<Vec<Bytes>> {
+    let mut iter = self.new_iterator(IteratorOptions::default());
+    let mut keys = Vec::new();
+    while let Some((key, _)) = iter.next() {
+      keys.push(Bytes::copy_from_slice(key));
+    }
+    match iter.error() {
+      Some(e) => Err(e),
+      None => Ok(keys),
+    }
+  }
+
+  // a failure to read the b+ tree is reported by `IndexIterator::error`
+  fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator> {
+    Box::new(self.new_iterator(options))
+  }
+
+  fn try_iterator(&self, options: IteratorOptions) -> Result<Box<dyn IndexIterator>> {
+    let iter = self.new_iterator(options);
+    match iter.error() {
+      Some(e) => Err(e),
+      None => Ok(Box::new(iter)),
+    }
+  }
+
+  fn clear(&self) -> Result<()> {
+    let mut hot = self.hot.lock();
+    *hot = HotKeys::default();
+    self.cold.clear()
+  }
+
+  fn set_hot_keys(&self, hot_keys: usize) {
+    let mut hot = self.hot.lock();
+    self.hot_keys.store(hot_keys.max(1), Ordering::Relaxed);
+    self.demote(&mut hot);
+  }
+}
+
+/// Hybrid Index Iterator
+///
+/// Merges a copy of the hot keys with the b+ tree, which is read in chunks as the iteration
+/// goes on, the hot position of a key in both wins. Reverse iterators read the b+ tree at once,
+/// its cursor only moves forward.
+pub struct HybridIterator {
+  cold: Arc<BPlusTree>,
+  options: IteratorOptions,
+  hot: Vec<(Vec<u8>, LogRecordPos)>, // in iteration order
+  hot_index: usize,
+  cold_items: Vec<(Vec<u8>, LogRecordPos)>, // chunk of the b+ tree in iteration order
+  cold_index: usize,
+  cold_next: Option<Bound<Vec<u8>>>, // where the next chunk starts, None once read to the end
+  error: Option<Errors>,             // failure to read the b+ tree, ends the iteration
+}
+
+impl HybridIterator {
+  fn order(&self, a: &[u8], b: &[u8]) -> KeyOrdering {
+    match self.options.reverse {
+      true => b.cmp(a),
+      false => a.cmp(b),
+    }
+  }
+
+  // reads the next chunk of the b+ tree once the current one is used up
+  fn fill_cold(&mut self) {
+    if self.cold_index < self.cold_items.len() || self.error.is_some() {
+      return;
+    }
+    let Some(start) = self.cold_next.take() else {
+      return;
+    };
+    let limit = match self.options.reverse {
+      true => usize::MAX,
+      false => COLD_CHUNK_SIZE,
+    };
+    let start = start.as_ref().map(Vec::as_slice);
+    match self.cold.try_items_from(&self.options.prefix, start, limit) {
+      Ok(mut items) => {
+        if items.len() == limit {
+          self.cold_next = items.last().map(|(key, _)| Bound::Excluded(key.clone()));
+        }
+        if self.options.reverse {
+          items.reverse();
+        }
+        self.cold_items = items;
+        self.cold_index = 0;
+      }
+      Err(e) => self.error = Some(e),
+    }
+  }
+}
+
+impl IndexIterator for HybridIterator {
+  fn rewind(&mut self) {
+    self.hot_index = 0;
+    self.cold_items.clear();
+    self.cold_index = 0;
+    self.cold_next = Some(Bound::Unbounded);
+    self.error = None;
+  }
+
+  fn seek(&mut self, key: Vec<u8>) {
+    if self.options.reverse {
+      // the whole b+ tree range is one chunk
+      self.rewind();
+      self.fill_cold();
+      let before = |item: &(Vec<u8>, LogRecordPos)| item.0 > key;
+      self.hot_index = self.hot.partition_point(before);
+      self.cold_index = self.cold_items.partition_point(before);
+      return;
+    }
+    self.hot_index = self.hot.partition_point(|item| item.0 < key);
+    self.cold_items.clear();
+    self.cold_index = 0;
+    self.cold_next = Some(Bound::Included(key));
+    self.error = None;
+  }
+
+  fn next(&mut self) -> Option<(&[u8], &LogRecordPos)> {
+    self.fill_cold();
+    if self.error.is_some() {
+      return None;
+    }
+    let hot = self.hot.get(self.hot_index);
+    let cold = self.cold_items.get(self.cold_index);
+    let take_hot = match (hot, cold) {
+      (None, None) => return None,
+      (Some(_), None) => true,
+      (None, Some(_)) => false,
+      (Some(hot), Some(cold)) => match self.order(&hot.0, &cold.0) {
+        KeyOrdering::Less => true,
+        KeyOrdering::Greater => false,
+        KeyOrdering::Equal => {
+          self.cold_index += 1;
+          true
+        }
+      },
+    };
+    let (key, pos) = match take_hot {
+      true => {
+        self.hot_index += 1;
+        &self.hot[self.hot_index - 1]
+      }
+      false => {
+        self.cold_index += 1;
+        &self.cold_items[self.cold_index - 1]
+      }
+    };
+    Some((key.as_slice(), pos))
+  }
+
+  fn error(&self) -> Option<Errors> {
+    self.error.clone()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn pos(offset: u64) -> LogRecordPos {
+    LogRecordPos {
+      file_id: 1,
+      offset,
+      size: 10,
+    }
+  }
+
+  #[test]
+  fn test_hybrid_spill_and_promote() {
+    let dir = tempfile::tempdir().unwrap();
+    let index = Hybrid::new(dir.path(), 16).unwrap();
+    for i in 0..100u64 {
+      assert!(index
+        .put(format!("key-{i:03}").into_bytes(), pos(i))
+        .is_none());
+    }
+    assert!(index.hot_len() <= 16);
+
+    // spilled keys are found, promoted, and overwritten with their previous position
+    assert_eq!(Some(pos(3)), index.get(b"key-003".to_vec()));
+    assert_eq!(Some(pos(3)), index.put(b"key-003".to_vec(), pos(1003)));
+    assert_eq!(Some(pos(1003)), index.get(b"key-003".to_vec()));
+    assert_eq!(Some(pos(5)), index.put(b"key-005".to_vec(), pos(1005)));
+
+    // a key deleted while hot is gone from the b+ tree as well
+    assert_eq!(Some(pos(1005)), index.delete(b"key-005".to_vec()));
+    assert_eq!(None, index.get(b"key-005".to_vec()));
+    assert_eq!(Some(pos(7)), index.delete(b"key-007".to_vec()));
+    assert_eq!(None, index.delete(b"key-007".to_vec()));
+
+    // demoted positions are written to the b+ tree
+    for i in 200..300u64 {
+      index.put(format!("key-{i:03}").into_bytes(), pos(i));
+    }
+    assert!(index.hot_len() <= 16);
+    assert_eq!(Some(pos(1003)), index.get(b"key-003".to_vec()));
+
+    // a smaller hot set demotes right away
+    index.set_hot_keys(4);
+    assert!(index.hot_len() <= 4);
+    assert_eq!(Some(pos(250)), index.get(b"key-250".to_vec()));
+
+    let keys = index.list_keys().unwrap();
+    assert_eq!(198, keys.len());
+    assert!(keys.windows(2).all(|w| w[0] < w[1]));
+
+    let mut iter = index.iterator(IteratorOptions {
+      reverse: true,
+      ..Default::default()
+    });
+    iter.seek(b"key-004".to_vec());
+    assert_eq!(b"key-004", iter.next().unwrap().0);
+    assert_eq!(b"key-003", iter.next().unwrap().0);
+
+    // both tiers are scanned from the prefix, key-250 is hot, key-003 is in the b+ tree
+    let mut iter = index.iterator(IteratorOptions {
+      prefix: b"key-2".to_vec(),
+      ..Default::default()
+    });
+    let mut scanned = 0;
+    while let Some((key, _)) = iter.next() {
+      assert!(key.starts_with(b"key-2"));
+      scanned += 1;
+    }
+    assert_eq!(100, scanned);
+
+    // the b+ tree is read in chunks, the hot position of a key in both tiers wins
+    for i in 1000..1000 + 3 * COLD_CHUNK_SIZE as u64 {
+      index.put(format!("big-{i:05}").into_bytes(), pos(i));
+    }
+    index.put(b"big-01500".to_vec(), pos(1));
+    let mut iter = index.iterator(IteratorOptions {
+      prefix: b"big-".to_vec(),
+      ..Default::default()
+    });
+    let mut keys = Vec::new();
+    while let Some((key, pos)) = iter.next() {
+      if key == b"big-01500" {
+        assert_eq!(1, pos.offset);
+      }
+      keys.push(key.to_vec());
+    }
+    assert!(iter.error().is_none());
+    assert_eq!(3 * COLD_CHUNK_SIZE, keys.len());
+    assert!(keys.windows(2).all(|w| w[0] < w[1]));
+    iter.seek(b"big-03000".to_vec());
+    assert_eq!(b"big-03000", iter.next().unwrap().0);
+    assert_eq!(b"big-03001", iter.next().unwrap().0);
+
+    index.clear().unwrap();
+    assert_eq!(0, index.hot_len());
+    assert!(index.list_keys().unwrap().is_empty());
+    assert_eq!(None, index.get(b"key-003".to_vec()));
+  }
+}
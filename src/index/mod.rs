@@ -1,17 +1,77 @@
+#[cfg(feature = "bptree")]
 pub mod bptree;
 pub mod btree;
+#[cfg(feature = "bptree")]
+pub mod hybrid;
 pub mod skiplist;
 
-use std::path::PathBuf;
+use std::ops::Bound;
 
 use bytes::Bytes;
 
 use crate::{
-  data::log_record::LogRecordPos,
-  errors::Result,
-  option::{IndexType, IteratorOptions},
+  errors::{Errors, Result},
+  option::{IndexType, IteratorOptions, Options},
 };
 
+pub use crate::data::log_record::LogRecordPos;
+
+/// The smallest key greater than all keys starting with `prefix`, None if there is none.
+pub(crate) fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+  let mut end = prefix.to_vec();
+  while let Some(last) = end.pop() {
+    if last < u8::MAX {
+      end.push(last + 1);
+      return Some(end);
+    }
+  }
+  None
+}
+
+/// Byte order range of the keys starting with `prefix`, given the end from `prefix_end`,
+/// an empty prefix covers all keys.
+pub(crate) fn prefix_bounds<'a>(
+  prefix: &'a [u8],
+  end: &'a Option<Vec<u8>>,
+) -> (Bound<&'a [u8]>, Bound<&'a [u8]>) {
+  match end {
+    Some(end) if !prefix.is_empty() => (Bound::Included(prefix), Bound::Excluded(end.as_slice())),
+    _ => (Bound::Included(prefix), Bound::Unbounded),
+  }
+}
+
+// `Indexer::keys_after` through an index iterator
+pub(crate) fn iterate_keys_after<I: Indexer + ?Sized>(
+  index: &I,
+  prefix: &[u8],
+  start_after: Option<&[u8]>,
+  limit: usize,
+) -> Result<Vec<Bytes>> {
+  let options = IteratorOptions {
+    prefix: prefix.to_vec(),
+    ..Default::default()
+  };
+  let mut index_iter = index.try_iterator(options)?;
+  if let Some(key) = start_after {
+    index_iter.seek(key.to_vec());
+  }
+  let mut keys = Vec::new();
+  while keys.len() < limit {
+    let Some((key, _)) = index_iter.next() else {
+      break;
+    };
+    if start_after != Some(key) {
+      keys.push(Bytes::copy_from_slice(key));
+    }
+  }
+  match index_iter.error() {
+    Some(e) => Err(e),
+    None => Ok(keys),
+  }
+}
+
+/// In-memory index of the position of every key, see `Options::custom_indexer` to supply
+/// an implementation of your own.
 pub trait Indexer: Sync + Send {
   fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> Option<LogRecordPos>;
 
@@ -21,20 +81,110 @@ pub trait Indexer: Sync + Send {
   /// Deletes a key's position from the index.
   fn delete(&self, key: Vec<u8>) -> Option<LogRecordPos>;
 
+  /// Like `put`, but fails instead of treating the key as missing when an index stored on
+  /// disk can not be updated. The engine writes through this method.
+  fn try_put(&self, key: Vec<u8>, pos: LogRecordPos) -> Result<Option<LogRecordPos>> {
+    Ok(self.put(key, pos))
+  }
+
+  /// Like `delete`, but fails when an index stored on disk can not be updated.
+  fn try_delete(&self, key: Vec<u8>) -> Result<Option<LogRecordPos>> {
+    Ok(self.delete(key))
+  }
+
+  /// Puts the positions of many keys at once, returns the previous position of each key.
+  ///
+  /// `sorted` promises the keys are in ascending order without duplicates, indexes may use
+  /// it to build the new entries in one pass.
+  fn put_batch(
+    &self,
+    entries: Vec<(Vec<u8>, LogRecordPos)>,
+    sorted: bool,
+  ) -> Vec<Option<LogRecordPos>> {
+    let _ = sorted;
+    entries
+      .into_iter()
+      .map(|(key, pos)| self.put(key, pos))
+      .collect()
+  }
+
+  /// Like `put_batch`, but fails when an index stored on disk can not be updated.
+  ///
+  /// The default puts the keys one by one, a failure may leave the keys before it put.
+  fn try_put_batch(
+    &self,
+    entries: Vec<(Vec<u8>, LogRecordPos)>,
+    sorted: bool,
+  ) -> Result<Vec<Option<LogRecordPos>>> {
+    let _ = sorted;
+    entries
+      .into_iter()
+      .map(|(key, pos)| self.try_put(key, pos))
+      .collect()
+  }
+
   fn list_keys(&self) -> Result<Vec<Bytes>>;
 
+  /// Up to `limit` keys starting with `prefix` which follow `start_after` in iteration order.
+  ///
+  /// The default seeks an iterator, indexes whose iterators copy their keys override it to
+  /// copy only the keys returned.
+  fn keys_after(
+    &self,
+    prefix: &[u8],
+    start_after: Option<&[u8]>,
+    limit: usize,
+  ) -> Result<Vec<Bytes>> {
+    iterate_keys_after(self, prefix, start_after, limit)
+  }
+
   /// Creates an iterator for the index with the specified options.
   /// * `options` - Configuration options for the iterator
   fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator>;
+
+  /// Like `iterator`, but fails instead of iterating over nothing when an index stored on
+  /// disk can not be read.
+  fn try_iterator(&self, options: IteratorOptions) -> Result<Box<dyn IndexIterator>> {
+    Ok(self.iterator(options))
+  }
+
+  /// Approximate memory held by the index, `None` if the index does not track it.
+  fn memory_usage(&self) -> Option<usize> {
+    None
+  }
+
+  /// Removes all keys from the index.
+  fn clear(&self) -> Result<()>;
+
+  /// Changes the number of keys kept in memory, indexes holding all keys in memory ignore it.
+  fn set_hot_keys(&self, _hot_keys: usize) {}
 }
 
 /// Creates a new indexer based on the specified index type and directory path.
-pub fn new_indexer(index_type: &IndexType, dir_path: &PathBuf) -> Box<dyn Indexer> {
-  match *index_type {
-    IndexType::BTree => Box::new(btree::BTree::new()),
-    IndexType::SkipList => Box::new(skiplist::SkipList::new()),
-    IndexType::BPlusTree => Box::new(bptree::BPlusTree::new(dir_path)),
+pub fn new_indexer(options: &Options) -> Result<Box<dyn Indexer>> {
+  if let Some(custom_indexer) = &options.custom_indexer {
+    return Ok(custom_indexer.create(&options.dir_path));
   }
+  Ok(match options.index_type {
+    IndexType::BTree => Box::new(
+      btree::BTree::with_shards(options.index_shards)
+        .with_comparator(options.key_comparator.clone()),
+    ),
+    IndexType::SkipList => {
+      Box::new(skiplist::SkipList::new().with_comparator(options.key_comparator.clone()))
+    }
+    #[cfg(feature = "bptree")]
+    IndexType::BPlusTree => Box::new(bptree::BPlusTree::new(&options.dir_path)?),
+    #[cfg(feature = "bptree")]
+    IndexType::Hybrid => Box::new(hybrid::Hybrid::new(
+      &options.dir_path,
+      options.index_hot_keys,
+    )?),
+    #[cfg(not(feature = "bptree"))]
+    IndexType::BPlusTree | IndexType::Hybrid => {
+      return Err(crate::errors::Errors::IndexTypeUnsupported)
+    }
+  })
 }
 
 /// Provides methods for iterating over key-value pairs in the index.
@@ -43,5 +193,11 @@ pub trait IndexIterator: Sync + Send {
 
   fn seek(&mut self, key: Vec<u8>);
 
-  fn next(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)>;
+  fn next(&mut self) -> Option<(&[u8], &LogRecordPos)>;
+
+  /// The read failure which ended the iteration early, for indexes stored on disk which are
+  /// read while iterating.
+  fn error(&self) -> Option<Errors> {
+    None
+  }
 }
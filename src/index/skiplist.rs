@@ -1,46 +1,86 @@
 #![allow(clippy::clone_on_copy)]
-use std::sync::Arc;
+use std::{
+  ops::Bound,
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+  },
+};
 
 use bytes::Bytes;
 use crossbeam_skiplist::SkipMap;
 
-use crate::{data::log_record::LogRecordPos, errors::Result, option::IteratorOptions};
+use crate::{
+  data::log_record::LogRecordPos,
+  errors::Result,
+  option::{IteratorOptions, KeyComparator},
+};
 
-use super::{IndexIterator, Indexer};
+use super::{btree::BTreeIterator, prefix_bounds, prefix_end, IndexIterator, Indexer};
+
+// rough per entry cost of a skiplist node besides the key bytes: tower pointers, refcounts,
+// the Arc header of the key and the position
+const NODE_OVERHEAD: usize = 64 + std::mem::size_of::<LogRecordPos>();
 
 // skiplist index
 pub struct SkipList {
-  skl: Arc<SkipMap<Vec<u8>, LogRecordPos>>,
+  // keys are shared with iterators so scans never copy them
+  skl: Arc<SkipMap<Arc<[u8]>, LogRecordPos>>,
+  memory_usage: AtomicUsize,
+  comparator: Option<KeyComparator>, // order of the iterators, the skiplist stays in byte order
 }
 
 impl SkipList {
   pub fn new() -> Self {
     Self {
       skl: Arc::new(SkipMap::new()),
+      memory_usage: AtomicUsize::new(0),
+      comparator: None,
     }
   }
+
+  /// Iterates the keys in the order of `comparator` instead of their byte order. Such
+  /// iterators work on a snapshot of the keys under their prefix like the BTree ones.
+  pub fn with_comparator(mut self, comparator: Option<KeyComparator>) -> Self {
+    self.comparator = comparator;
+    self
+  }
+}
+
+impl Default for SkipList {
+  fn default() -> Self {
+    Self::new()
+  }
 }
 
 impl Indexer for SkipList {
   fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> Option<LogRecordPos> {
     let mut result = None;
-    if let Some(entry) = self.skl.get(&key) {
+    if let Some(entry) = self.skl.get(key.as_slice()) {
       result = Some(*entry.value());
     }
 
-    self.skl.insert(key, pos);
+    if result.is_none() {
+      self
+        .memory_usage
+        .fetch_add(key.len() + NODE_OVERHEAD, Ordering::Relaxed);
+    }
+    self.skl.insert(Arc::from(key), pos);
     result
   }
 
   fn get(&self, key: Vec<u8>) -> Option<LogRecordPos> {
-    if let Some(entry) = self.skl.get(&key) {
+    if let Some(entry) = self.skl.get(key.as_slice()) {
       return Some(*entry.value());
     }
     None
   }
 
   fn delete(&self, key: Vec<u8>) -> Option<LogRecordPos> {
-    if let Some(entry) = self.skl.remove(&key) {
+    if let Some(entry) = self.skl.remove(key.as_slice()) {
+      self
+        .memory_usage
+        .fetch_sub(key.len() + NODE_OVERHEAD, Ordering::Relaxed);
       return Some(*entry.value());
     }
     None
@@ -55,63 +95,89 @@ impl Indexer for SkipList {
   }
 
   fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator> {
-    let mut items = Vec::with_capacity(self.skl.len());
-
-    // copy all items from SkipList to Vec
-    for entry in self.skl.iter() {
-      items.push((entry.key().clone(), entry.value().clone()));
+    if let Some(comparator) = &self.comparator {
+      // the keys of a prefix are contiguous in byte order, only they are copied
+      let end = prefix_end(&options.prefix);
+      let items = (self
+        .skl
+        .range::<[u8], _>(prefix_bounds(&options.prefix, &end)))
+      .map(|e| (e.key().to_vec(), *e.value()))
+      .collect();
+      return Box::new(BTreeIterator::new(items, options, Some(comparator.clone())));
     }
-
-    if options.reverse {
-      items.reverse();
-    }
-
     Box::new(SkipListIterator {
-      items,
-      curr_index: 0,
+      skl: self.skl.clone(),
+      position: Some(Bound::Unbounded),
+      current: None,
       options,
     })
   }
+
+  fn memory_usage(&self) -> Option<usize> {
+    Some(self.memory_usage.load(Ordering::Relaxed))
+  }
+
+  fn clear(&self) -> Result<()> {
+    self.skl.clear();
+    self.memory_usage.store(0, Ordering::Relaxed);
+    Ok(())
+  }
 }
 
 /// SkipList Index Iterator
+///
+/// The iterator walks the skiplist directly instead of copying it, so it never blocks
+/// writers and observes changes made after it was created.
 pub struct SkipListIterator {
-  items: Vec<(Vec<u8>, LogRecordPos)>, // store key and index
-  curr_index: usize,                   //current index
-  options: IteratorOptions,            // iterator options
+  skl: Arc<SkipMap<Arc<[u8]>, LogRecordPos>>,
+  position: Option<Bound<Arc<[u8]>>>, // where the next lookup starts, None once exhausted
+  current: Option<(Arc<[u8]>, LogRecordPos)>, // last returned item
+  options: IteratorOptions,           // iterator options
+}
+
+impl SkipListIterator {
+  /// Returns the first entry at or after (before in reverse order) the bound.
+  fn lookup(&self, bound: Bound<&[u8]>) -> Option<(Arc<[u8]>, LogRecordPos)> {
+    let entry = match self.options.reverse {
+      false => self.skl.lower_bound(bound),
+      true => self.skl.upper_bound(bound),
+    };
+    entry.map(|e| (e.key().clone(), *e.value()))
+  }
+
+  /// Where to continue when `key` is outside the prefix range, None if the range is behind us.
+  fn skip_to_prefix(&self, key: &[u8]) -> Option<Bound<Arc<[u8]>>> {
+    let prefix = self.options.prefix.as_slice();
+    match self.options.reverse {
+      false if key < prefix => Some(Bound::Included(Arc::from(prefix))),
+      true if key > prefix => prefix_end(prefix).map(|end| Bound::Excluded(Arc::from(end))),
+      _ => None,
+    }
+  }
 }
 
 impl IndexIterator for SkipListIterator {
   fn rewind(&mut self) {
-    self.curr_index = 0;
+    self.position = Some(Bound::Unbounded);
   }
 
   fn seek(&mut self, key: Vec<u8>) {
-    self.curr_index = match self.items.binary_search_by(|(x, _)| {
-      if self.options.reverse {
-        x.cmp(&key).reverse()
-      } else {
-        x.cmp(&key)
-      }
-    }) {
-      Ok(equal_val) => equal_val,
-      Err(insert_val) => insert_val,
-    };
+    self.position = Some(Bound::Included(Arc::from(key)));
   }
 
-  fn next(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)> {
-    if self.curr_index >= self.items.len() {
-      return None;
-    }
+  fn next(&mut self) -> Option<(&[u8], &LogRecordPos)> {
+    loop {
+      let position = self.position.take()?;
+      let (key, pos) = self.lookup(position.as_ref().map(|k| k.as_ref()))?;
 
-    while let Some(item) = self.items.get(self.curr_index) {
-      self.curr_index += 1;
       let prefix = &self.options.prefix;
-      if prefix.is_empty() || item.0.starts_with(prefix) {
-        return Some((&item.0, &item.1));
+      if prefix.is_empty() || key.starts_with(prefix) {
+        self.position = Some(Bound::Excluded(key.clone()));
+        let current = self.current.insert((key, pos));
+        return Some((&current.0, &current.1));
       }
+      self.position = self.skip_to_prefix(&key);
     }
-    None
   }
 }
 
@@ -326,6 +392,7 @@ mod tests {
     assert_eq!(keys.len(), 3);
   }
 
+  #[allow(clippy::field_reassign_with_default)]
   #[test]
   fn test_skl_iterator() {
     let skl = SkipList::new();
@@ -503,4 +570,113 @@ mod tests {
     }
     assert_eq!(count, 3);
   }
+
+  #[allow(clippy::field_reassign_with_default)]
+  #[test]
+  fn test_skl_iterator_prefix() {
+    let skl = SkipList::new();
+    for key in ["aa", "ab1", "ab2", "ab\u{ff}", "ac", "b"] {
+      skl.put(
+        key.as_bytes().to_vec(),
+        LogRecordPos {
+          file_id: 1,
+          offset: 0,
+          size: 12,
+        },
+      );
+    }
+
+    let collect = |reverse: bool, seek: Option<&str>| {
+      let mut opt = IteratorOptions::default();
+      opt.prefix = b"ab".to_vec();
+      opt.reverse = reverse;
+      let mut iter = skl.iterator(opt);
+      if let Some(key) = seek {
+        iter.seek(key.as_bytes().to_vec());
+      }
+      let mut keys = Vec::new();
+      while let Some((key, _)) = iter.next() {
+        keys.push(String::from_utf8_lossy(key).to_string());
+      }
+      keys
+    };
+
+    assert_eq!(vec!["ab1", "ab2", "ab\u{ff}"], collect(false, None));
+    assert_eq!(vec!["ab\u{ff}", "ab2", "ab1"], collect(true, None));
+    assert_eq!(vec!["ab2", "ab\u{ff}"], collect(false, Some("ab2")));
+    assert_eq!(vec!["ab1"], collect(true, Some("ab11")));
+    assert!(collect(false, Some("b")).is_empty());
+  }
+
+  #[test]
+  fn test_skl_iterator_concurrent_writes() {
+    let skl = SkipList::new();
+    let pos = LogRecordPos {
+      file_id: 1,
+      offset: 0,
+      size: 12,
+    };
+    skl.put(b"a".to_vec(), pos);
+    skl.put(b"c".to_vec(), pos);
+
+    let mut iter = skl.iterator(IteratorOptions::default());
+    assert_eq!(b"a", iter.next().unwrap().0);
+
+    // writes after the iterator is created are visible and never blocked
+    skl.put(b"b".to_vec(), pos);
+    skl.delete(b"c".to_vec());
+    assert_eq!(b"b", iter.next().unwrap().0);
+    assert!(iter.next().is_none());
+  }
+
+  #[test]
+  fn test_skl_memory_usage() {
+    let skl = SkipList::new();
+    let pos = LogRecordPos {
+      file_id: 1,
+      offset: 0,
+      size: 12,
+    };
+    assert_eq!(Some(0), skl.memory_usage());
+
+    skl.put(b"key".to_vec(), pos);
+    let usage = skl.memory_usage().unwrap();
+    assert!(usage >= 3);
+
+    // overwriting does not grow the index
+    skl.put(b"key".to_vec(), pos);
+    assert_eq!(Some(usage), skl.memory_usage());
+
+    skl.delete(b"key".to_vec());
+    assert_eq!(Some(0), skl.memory_usage());
+  }
+
+  #[allow(clippy::field_reassign_with_default)]
+  #[test]
+  fn test_skl_iterator_comparator_prefix() {
+    let skl = SkipList::new().with_comparator(Some(KeyComparator::new(|a, b| b.cmp(a))));
+    let pos = LogRecordPos {
+      file_id: 1,
+      offset: 0,
+      size: 12,
+    };
+    for key in ["a", "b1", "b2", "c"] {
+      skl.put(key.as_bytes().to_vec(), pos);
+    }
+    skl.put(vec![b'b', 0xff], pos);
+
+    let mut opts = IteratorOptions::default();
+    opts.prefix = b"b".to_vec();
+    let mut iter = skl.iterator(opts);
+    // the snapshot only holds the prefix, later writes are not seen
+    skl.put(b"b3".to_vec(), pos);
+    let mut keys = Vec::new();
+    while let Some((key, _)) = iter.next() {
+      keys.push(key.to_vec());
+    }
+    assert_eq!(vec![vec![b'b', 0xff], b"b2".to_vec(), b"b1".to_vec()], keys);
+
+    let mut iter = skl.iterator(IteratorOptions::default());
+    assert_eq!(b"c", iter.next().unwrap().0);
+  }
 }
@@ -0,0 +1,367 @@
+//! Dumps of the in-memory index for offline analysis, e.g. comparing replicas or the index
+//! before and after a merge.
+//!
+//! A dump holds one entry per indexed key: the key and the position of its live record,
+//! the data file id, the offset in the file and the record size. Entries are in index order.
+//!
+//! - [`IndexDumpFormat::Csv`]: a `key,file_id,offset,size` header line, then one line per
+//!   entry with the key in standard base64
+//! - [`IndexDumpFormat::Binary`]: the magic `FLASHIDX` and a version byte, then per entry a
+//!   varint key length, the key, the big endian u32 file id, u64 offset and u32 size. A
+//!   varint 0 and the big endian u64 entry count end the dump
+
+use std::{
+  collections::BTreeMap,
+  fs::{File, OpenOptions},
+  io::{BufRead, BufReader, BufWriter, Read, Write},
+  path::Path,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use bytes::Bytes;
+use log::error;
+use prost::{decode_length_delimiter, encode_length_delimiter};
+
+use crate::{
+  db::Engine,
+  errors::{Errors, Result},
+  option::IteratorOptions,
+};
+
+// first bytes of a binary dump, the last byte is the format version
+const BINARY_MAGIC: &[u8] = b"FLASHIDX\x01";
+
+const CSV_HEADER: &str = "key,file_id,offset,size";
+
+/// On-disk format of an index dump, see the module documentation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexDumpFormat {
+  Csv,
+
+  Binary,
+}
+
+/// A key of an index dump and the position of its record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexDumpEntry {
+  pub key: Bytes,
+  pub file_id: u32,
+  pub offset: u64,
+  pub size: u32,
+}
+
+/// Differences between two index dumps, in key order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IndexDumpDiff {
+  /// Entries of keys missing from the right dump
+  pub only_left: Vec<IndexDumpEntry>,
+
+  /// Entries of keys missing from the left dump
+  pub only_right: Vec<IndexDumpEntry>,
+
+  /// Left and right entry of keys whose record is at another position
+  pub changed: Vec<(IndexDumpEntry, IndexDumpEntry)>,
+}
+
+impl IndexDumpDiff {
+  /// Whether both dumps index the same keys at the same positions.
+  pub fn is_empty(&self) -> bool {
+    self.only_left.is_empty() && self.only_right.is_empty() && self.changed.is_empty()
+  }
+}
+
+impl Engine {
+  /// Writes the key and record position of every indexed key to `path`, returns the number
+  /// of entries.
+  ///
+  /// Expired keys that are not yet removed are included. Writes running concurrently may or
+  /// may not be included.
+  pub fn dump_index<P: AsRef<Path>>(&self, path: P, format: IndexDumpFormat) -> Result<u64> {
+    self.check_open()?;
+    let file = OpenOptions::new()
+      .create(true)
+      .write(true)
+      .truncate(true)
+      .open(&path)
+      .map_err(|e| {
+        error!(
+          "failed to create index dump {}: {e}",
+          path.as_ref().display()
+        );
+        Errors::FailedToWriteExport
+      })?;
+    let mut writer = BufWriter::new(file);
+    match format {
+      IndexDumpFormat::Csv => write_all(&mut writer, format!("{CSV_HEADER}\n").as_bytes())?,
+      IndexDumpFormat::Binary => write_all(&mut writer, BINARY_MAGIC)?,
+    }
+
+    let mut entries = 0u64;
+    let mut iter = self.index.try_iterator(IteratorOptions::default())?;
+    while let Some((key, pos)) = iter.next() {
+      match format {
+        IndexDumpFormat::Csv => {
+          let line = format!(
+            "{},{},{},{}\n",
+            STANDARD.encode(key),
+            pos.file_id,
+            pos.offset,
+            pos.size
+          );
+          write_all(&mut writer, line.as_bytes())?;
+        }
+        IndexDumpFormat::Binary => {
+          let mut buf = Vec::with_capacity(key.len() + 26);
+          // encoding into a growable vec can not fail
+          let _ = encode_length_delimiter(key.len(), &mut buf);
+          buf.extend_from_slice(key);
+          buf.extend_from_slice(&pos.file_id.to_be_bytes());
+          buf.extend_from_slice(&pos.offset.to_be_bytes());
+          buf.extend_from_slice(&pos.size.to_be_bytes());
+          write_all(&mut writer, &buf)?;
+        }
+      }
+      entries += 1;
+    }
+    drop(iter);
+
+    if format == IndexDumpFormat::Binary {
+      let mut trailer = Vec::new();
+      let _ = encode_length_delimiter(0, &mut trailer);
+      trailer.extend_from_slice(&entries.to_be_bytes());
+      write_all(&mut writer, &trailer)?;
+    }
+    let file = writer.into_inner().map_err(|e| {
+      error!("failed to flush index dump: {e}");
+      Errors::FailedToWriteExport
+    })?;
+    file.sync_all().map_err(|e| {
+      error!("failed to sync index dump: {e}");
+      Errors::FailedToWriteExport
+    })?;
+    Ok(entries)
+  }
+}
+
+/// Reads the entries of an index dump written by [`Engine::dump_index`].
+pub fn load_index_dump<P: AsRef<Path>>(
+  path: P,
+  format: IndexDumpFormat,
+) -> Result<Vec<IndexDumpEntry>> {
+  let file = File::open(&path).map_err(|e| {
+    error!("failed to open index dump {}: {e}", path.as_ref().display());
+    Errors::FailedToReadImport
+  })?;
+  let mut reader = BufReader::new(file);
+  match format {
+    IndexDumpFormat::Csv => read_csv(&mut reader),
+    IndexDumpFormat::Binary => read_binary(&mut reader),
+  }
+}
+
+/// Compares the entries of two index dumps by key.
+pub fn diff_index_dumps(left: &[IndexDumpEntry], right: &[IndexDumpEntry]) -> IndexDumpDiff {
+  let mut right: BTreeMap<&Bytes, &IndexDumpEntry> =
+    right.iter().map(|entry| (&entry.key, entry)).collect();
+  let mut diff = IndexDumpDiff::default();
+  let left: BTreeMap<&Bytes, &IndexDumpEntry> =
+    left.iter().map(|entry| (&entry.key, entry)).collect();
+  for (key, entry) in left {
+    match right.remove(key) {
+      None => diff.only_left.push(entry.clone()),
+      Some(other) if other != entry => diff.changed.push((entry.clone(), other.clone())),
+      Some(_) => {}
+    }
+  }
+  diff.only_right = right.into_values().cloned().collect();
+  diff
+}
+
+fn write_all<W: Write>(writer: &mut W, buf: &[u8]) -> Result<()> {
+  writer.write_all(buf).map_err(|e| {
+    error!("failed to write index dump: {e}");
+    Errors::FailedToWriteExport
+  })
+}
+
+fn read_csv<R: BufRead>(reader: &mut R) -> Result<Vec<IndexDumpEntry>> {
+  let mut entries = Vec::new();
+  let mut header = true;
+  for line in reader.lines() {
+    let line = line.map_err(|e| {
+      error!("failed to read index dump: {e}");
+      Errors::FailedToReadImport
+    })?;
+    if header {
+      if line != CSV_HEADER {
+        return Err(Errors::InvalidIndexDump);
+      }
+      header = false;
+      continue;
+    }
+    if line.trim().is_empty() {
+      continue;
+    }
+
+    let fields: Vec<&str> = line.split(',').collect();
+    let [key, file_id, offset, size] = fields[..] else {
+      return Err(Errors::InvalidIndexDump);
+    };
+    entries.push(IndexDumpEntry {
+      key: STANDARD
+        .decode(key)
+        .map(Bytes::from)
+        .map_err(|_| Errors::InvalidIndexDump)?,
+      file_id: file_id.parse().map_err(|_| Errors::InvalidIndexDump)?,
+      offset: offset.parse().map_err(|_| Errors::InvalidIndexDump)?,
+      size: size.parse().map_err(|_| Errors::InvalidIndexDump)?,
+    });
+  }
+  match header {
+    true => Err(Errors::InvalidIndexDump),
+    false => Ok(entries),
+  }
+}
+
+fn read_binary<R: BufRead>(reader: &mut R) -> Result<Vec<IndexDumpEntry>> {
+  let mut magic = [0u8; BINARY_MAGIC.len()];
+  read_exact(reader, &mut magic)?;
+  if magic != BINARY_MAGIC {
+    return Err(Errors::InvalidIndexDump);
+  }
+
+  let mut entries = Vec::new();
+  loop {
+    let key_len = read_length(reader)?;
+    if key_len == 0 {
+      let mut count = [0u8; 8];
+      read_exact(reader, &mut count)?;
+      return match u64::from_be_bytes(count) == entries.len() as u64 {
+        true => Ok(entries),
+        false => Err(Errors::InvalidIndexDump),
+      };
+    }
+
+    // lengths come from the file, don't trust them with a large allocation up front
+    let mut key = Vec::new();
+    let read = reader
+      .by_ref()
+      .take(key_len as u64)
+      .read_to_end(&mut key)
+      .map_err(|_| Errors::InvalidIndexDump)?;
+    if read != key_len {
+      return Err(Errors::InvalidIndexDump);
+    }
+    let mut pos = [0u8; 16];
+    read_exact(reader, &mut pos)?;
+    let (file_id, rest) = pos.split_at(4);
+    let (offset, size) = rest.split_at(8);
+    entries.push(IndexDumpEntry {
+      key: Bytes::from(key),
+      file_id: u32::from_be_bytes(file_id.try_into().map_err(|_| Errors::InvalidIndexDump)?),
+      offset: u64::from_be_bytes(offset.try_into().map_err(|_| Errors::InvalidIndexDump)?),
+      size: u32::from_be_bytes(size.try_into().map_err(|_| Errors::InvalidIndexDump)?),
+    });
+  }
+}
+
+fn read_exact<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<()> {
+  reader.read_exact(buf).map_err(|_| Errors::InvalidIndexDump)
+}
+
+/// Reads a varint length byte by byte, the varint is at most 10 bytes long.
+fn read_length<R: BufRead>(reader: &mut R) -> Result<usize> {
+  let mut buf = Vec::with_capacity(10);
+  loop {
+    let mut byte = [0u8; 1];
+    read_exact(reader, &mut byte)?;
+    buf.push(byte[0]);
+    if byte[0] & 0x80 == 0 || buf.len() == 10 {
+      break;
+    }
+  }
+  decode_length_delimiter(buf.as_slice()).map_err(|_| Errors::InvalidIndexDump)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+
+  use super::*;
+  use crate::{
+    option::Options,
+    util::rand_kv::{get_test_key, get_test_value},
+  };
+
+  #[test]
+  fn test_dump_index() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let opts = Options {
+      dir_path: temp_dir.path().join("engine"),
+      data_file_size: 8 * 1024,
+      ..Default::default()
+    };
+    let engine = Engine::open(opts).expect("failed to open engine");
+    for i in 0..300 {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    engine
+      .put(Bytes::from(vec![0u8, b',', b'\n']), Bytes::new())
+      .unwrap();
+    for i in 0..50 {
+      engine.delete(get_test_key(i)).unwrap();
+    }
+
+    for format in [IndexDumpFormat::Csv, IndexDumpFormat::Binary] {
+      let before = temp_dir.path().join(format!("{format:?}.before"));
+      assert_eq!(251, engine.dump_index(&before, format).unwrap());
+      let entries = load_index_dump(&before, format).unwrap();
+      assert_eq!(251, entries.len());
+      let entry = entries
+        .iter()
+        .find(|entry| entry.key == get_test_key(60))
+        .unwrap();
+      let pos = engine.index.get(get_test_key(60).to_vec()).unwrap();
+      assert_eq!(
+        (pos.file_id, pos.offset, pos.size),
+        (entry.file_id, entry.offset, entry.size)
+      );
+      assert!(diff_index_dumps(&entries, &entries).is_empty());
+    }
+
+    // a delete and a put change the key set, an overwrite moves a record
+    let before = load_index_dump(
+      temp_dir.path().join("Binary.before"),
+      IndexDumpFormat::Binary,
+    )
+    .unwrap();
+    engine.delete(get_test_key(100)).unwrap();
+    engine.put(get_test_key(200), get_test_value(201)).unwrap();
+    engine.put(get_test_key(0), get_test_value(0)).unwrap();
+    let path = temp_dir.path().join("after");
+    engine.dump_index(&path, IndexDumpFormat::Csv).unwrap();
+    let after = load_index_dump(&path, IndexDumpFormat::Csv).unwrap();
+    let diff = diff_index_dumps(&before, &after);
+    assert_eq!(vec![get_test_key(100)], keys(&diff.only_left));
+    assert_eq!(vec![get_test_key(0)], keys(&diff.only_right));
+    assert_eq!(1, diff.changed.len());
+    assert_eq!(get_test_key(200), diff.changed[0].1.key);
+
+    // truncated and foreign files
+    let content = fs::read(temp_dir.path().join("Binary.before")).unwrap();
+    fs::write(&path, &content[..content.len() - 4]).unwrap();
+    assert_eq!(
+      Errors::InvalidIndexDump,
+      load_index_dump(&path, IndexDumpFormat::Binary).unwrap_err()
+    );
+    fs::write(&path, "key,value\n").unwrap();
+    assert_eq!(
+      Errors::InvalidIndexDump,
+      load_index_dump(&path, IndexDumpFormat::Csv).unwrap_err()
+    );
+  }
+
+  fn keys(entries: &[IndexDumpEntry]) -> Vec<Bytes> {
+    entries.iter().map(|entry| entry.key.clone()).collect()
+  }
+}
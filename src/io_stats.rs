@@ -0,0 +1,72 @@
+use std::{
+  sync::atomic::{AtomicU64, Ordering},
+  time::Duration,
+};
+
+use prost::{decode_length_delimiter, length_delimiter_len};
+
+use crate::data::log_record::{LogRecord, LogRecordType};
+
+/// Bytes moved between the engine and its data files, to derive read and write amplification.
+#[derive(Default)]
+pub(crate) struct IoStats {
+  bytes_read: AtomicU64,     // record bytes read by lookups and merges
+  bytes_returned: AtomicU64, // value bytes returned to callers
+  bytes_written: AtomicU64,  // encoded record bytes appended, merge output included
+  bytes_ingested: AtomicU64, // key and value bytes written by callers
+  merge_bytes: AtomicU64,    // bytes read and written by finished merges
+  merge_nanos: AtomicU64,    // time spent by finished merges
+}
+
+impl IoStats {
+  pub(crate) fn record_read(&self, read: u64, returned: u64) {
+    self.bytes_read.fetch_add(read, Ordering::Relaxed);
+    self.bytes_returned.fetch_add(returned, Ordering::Relaxed);
+  }
+
+  pub(crate) fn record_write(&self, written: u64, ingested: u64) {
+    self.bytes_written.fetch_add(written, Ordering::Relaxed);
+    self.bytes_ingested.fetch_add(ingested, Ordering::Relaxed);
+  }
+
+  pub(crate) fn record_merge(&self, bytes: u64, elapsed: Duration) {
+    let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+    self.merge_bytes.fetch_add(bytes, Ordering::Relaxed);
+    self.merge_nanos.fetch_add(nanos, Ordering::Relaxed);
+  }
+
+  /// Bytes per second read and written by the merges since open, `None` before the first.
+  pub(crate) fn merge_throughput(&self) -> Option<u64> {
+    let nanos = self.merge_nanos.load(Ordering::Relaxed);
+    if nanos == 0 {
+      return None;
+    }
+    let bytes = self.merge_bytes.load(Ordering::Relaxed) as u128;
+    u64::try_from(bytes * 1_000_000_000 / nanos as u128).ok()
+  }
+
+  pub(crate) fn bytes_read(&self) -> u64 {
+    self.bytes_read.load(Ordering::Relaxed)
+  }
+
+  pub(crate) fn bytes_returned(&self) -> u64 {
+    self.bytes_returned.load(Ordering::Relaxed)
+  }
+
+  pub(crate) fn bytes_written(&self) -> u64 {
+    self.bytes_written.load(Ordering::Relaxed)
+  }
+
+  pub(crate) fn bytes_ingested(&self) -> u64 {
+    self.bytes_ingested.load(Ordering::Relaxed)
+  }
+}
+
+/// Key and value bytes of a record as given by the caller, without the seq_no prefix.
+pub(crate) fn ingested_bytes(log_record: &LogRecord) -> u64 {
+  if log_record.rec_type == LogRecordType::TxnFinished {
+    return 0;
+  }
+  let prefix_len = decode_length_delimiter(&log_record.key[..]).map_or(0, length_delimiter_len);
+  (log_record.key.len().saturating_sub(prefix_len) + log_record.value.len()) as u64
+}
@@ -1,69 +1,362 @@
 use bytes::Bytes;
+use log::error;
 use parking_lot::RwLock;
-use std::sync::Arc;
-
-use crate::{db::Engine, errors::Result, index::IndexIterator, option::IteratorOptions};
+use std::{
+  collections::{HashMap, HashSet},
+  mem,
+  ops::ControlFlow,
+  sync::{atomic::Ordering, Arc, OnceLock},
+  time::SystemTime,
+};
+
+use crate::{
+  data::log_record::LogRecordPos,
+  db::{Engine, KeyPage},
+  errors::{Errors, Result},
+  expiry::unix_millis,
+  index::IndexIterator,
+  option::IteratorOptions,
+};
 
 /// Iterator for traversing key-value pairs in the database.
 pub struct Iterator<'a> {
-  index_iter: Arc<RwLock<Box<dyn IndexIterator>>>,
+  cursor: RwLock<Cursor>,
   engine: &'a Engine,
+  change_id: u64, // change id of the engine when the iterator was created
+}
+
+/// Positions handed out by the index until data files were replaced under them, iterators
+/// created in an older epoch move their positions through the remaps of the later ones.
+#[derive(Default)]
+pub(crate) struct PositionEpoch {
+  next: OnceLock<(PositionRemap, Arc<PositionEpoch>)>,
+}
+
+/// How the positions of replaced data files moved, when a merge is adopted or on clear.
+#[derive(Default)]
+pub(crate) struct PositionRemap {
+  pub(crate) moved: HashMap<LogRecordPos, LogRecordPos>, // old position of a live record to its new one
+  pub(crate) replaced_files: HashSet<u32>, // positions in them which did not move are gone
+}
+
+impl PositionEpoch {
+  // the current position of a record indexed in this epoch, None if its file was replaced
+  // without it
+  pub(crate) fn resolve(&self, mut pos: LogRecordPos) -> Option<LogRecordPos> {
+    let mut epoch = self;
+    while let Some((remap, next)) = epoch.next.get() {
+      pos = match remap.moved.get(&pos) {
+        Some(moved) => *moved,
+        None if remap.replaced_files.contains(&pos.file_id) => return None,
+        None => pos,
+      };
+      epoch = next;
+    }
+    Some(pos)
+  }
+}
+
+// index iterator bounded by the `start_after` and `limit` of the iterator options
+struct Cursor {
+  index_iter: Option<Box<dyn IndexIterator>>, // None if the index could not be read
+  error: Option<Errors>,                      // failure that ended the iteration
+  epoch: Arc<PositionEpoch>,                  // epoch of the positions of `index_iter`
+  start_after: Option<Vec<u8>>,
+  limit: Option<usize>,
+  keys_only: bool, // values are not read
+  at_start: bool,  // positioned at `start_after`, which is skipped if present
+  yielded: usize,  // pairs returned since positioned
+}
+
+impl Cursor {
+  // a failure to read the index ends the iteration at once, see `open` to fail instead
+  fn new(engine: &Engine, options: IteratorOptions) -> Self {
+    // the index is not rebuilt while its positions are taken
+    let epoch = engine.position_epoch.read();
+    let (index_iter, error) = match engine.index.try_iterator(options.clone()) {
+      Ok(index_iter) => (Some(index_iter), None),
+      Err(e) => (None, Some(e)),
+    };
+    let mut cursor = Cursor {
+      index_iter,
+      error,
+      epoch: Arc::clone(&epoch),
+      start_after: options.start_after,
+      limit: options.limit,
+      keys_only: options.keys_only,
+      at_start: false,
+      yielded: 0,
+    };
+    cursor.rewind();
+    cursor
+  }
+
+  fn open(engine: &Engine, options: IteratorOptions) -> Result<Self> {
+    let mut cursor = Self::new(engine, options);
+    match cursor.error.take() {
+      Some(e) => Err(e),
+      None => Ok(cursor),
+    }
+  }
+
+  fn rewind(&mut self) {
+    self.yielded = 0;
+    let Some(index_iter) = &mut self.index_iter else {
+      return;
+    };
+    index_iter.rewind();
+    if let Some(key) = &self.start_after {
+      index_iter.seek(key.clone());
+      self.at_start = true;
+    }
+  }
+
+  fn seek(&mut self, key: Vec<u8>) {
+    if let Some(index_iter) = &mut self.index_iter {
+      index_iter.seek(key);
+    }
+    self.yielded = 0;
+    self.at_start = false;
+  }
+
+  // next candidate pair, callers count the ones they return with `yielded`. Expired keys are
+  // skipped
+  fn next(&mut self, engine: &Engine) -> Option<(Bytes, LogRecordPos)> {
+    if self.limit.is_some_and(|limit| self.yielded >= limit) {
+      return None;
+    }
+    loop {
+      let index_iter = self.index_iter.as_mut()?;
+      let Some((key, pos)) = index_iter.next() else {
+        self.error = index_iter.error();
+        return None;
+      };
+      let key = Bytes::copy_from_slice(key);
+      let pos = *pos;
+      if mem::take(&mut self.at_start) && self.start_after.as_deref() == Some(&key[..]) {
+        continue;
+      }
+      // the record was dropped by a merge or clear since the iterator was created, a key
+      // written since is read at its current position
+      let Some(pos) = (self.epoch.resolve(pos)).or_else(|| engine.index.get(key.to_vec())) else {
+        continue;
+      };
+      if engine
+        .expiry
+        .is_expired(&key, unix_millis(SystemTime::now()))
+      {
+        continue;
+      }
+      return Some((key, pos));
+    }
+  }
+
+  // value of a pair, empty when the iterator only lists keys
+  fn value(&self, engine: &Engine, pos: &LogRecordPos) -> Result<Bytes> {
+    match self.keys_only {
+      true => Ok(Bytes::new()),
+      false => engine.get_value_by_position(pos),
+    }
+  }
 }
 
 impl Engine {
   /// Creates a new iterator with the specified options.
   /// An iterator instance for traversing the database.
-  pub fn iter(&self, options: IteratorOptions) -> Iterator {
+  pub fn iter(&self, options: IteratorOptions) -> Iterator<'_> {
+    let change_id = self.change_id();
     Iterator {
-      index_iter: Arc::new(RwLock::new(self.index.iterator(options))),
+      cursor: RwLock::new(Cursor::new(self, options)),
       engine: self,
+      change_id,
     }
   }
 
+  /// Runs `replace`, which swaps data files under the live iterators and returns how the
+  /// positions moved. Iterators created before resolve their positions through the remap,
+  /// iterators are not created meanwhile.
+  pub(crate) fn replace_data_files<F>(&self, replace: F) -> Result<()>
+  where
+    F: FnOnce() -> Result<PositionRemap>,
+  {
+    let mut epoch = self.position_epoch.write();
+    // point reads racing with `replace` read again, see `read_indexed`
+    self.data_files_version.fetch_add(1, Ordering::SeqCst);
+    let remap = replace();
+    self.data_files_version.fetch_add(1, Ordering::SeqCst);
+    let remap = remap?;
+    let next = Arc::new(PositionEpoch::default());
+    let _ = epoch.next.set((remap, Arc::clone(&next)));
+    *epoch = next;
+    Ok(())
+  }
+
   /// Lists all keys in the database.
   /// A `Result` containing a vector of all keys in the database.
+  ///
+  /// Every key is held in memory at once, use `list_keys_paged` on large databases.
   pub fn list_keys(&self) -> Result<Vec<Bytes>> {
-    self.index.list_keys()
+    self
+      .list_keys_paged(None, None, usize::MAX)
+      .map(|page| page.keys)
+  }
+
+  /// Lists up to `limit` keys in index order, starting with the key following `start_after`.
+  ///
+  /// Only keys starting with `prefix` are listed. The `next` key of the returned page is the
+  /// `start_after` of the following page, it is `None` on the last page. A `limit` of 0 is
+  /// treated as 1.
+  pub fn list_keys_paged(
+    &self,
+    prefix: Option<Bytes>,
+    start_after: Option<Bytes>,
+    limit: usize,
+  ) -> Result<KeyPage> {
+    self.check_open()?;
+    let change_id = self.change_id();
+    let prefix = prefix.unwrap_or_default();
+    let limit = limit.max(1);
+    let now = unix_millis(SystemTime::now());
+
+    // the index is read from `start_after` on, one key past the page tells whether more
+    // follow. Expired keys are skipped, so more may have to be read
+    let mut keys = Vec::new();
+    let mut after = start_after;
+    loop {
+      let wanted = limit.saturating_add(1) - keys.len();
+      let read = self.index.keys_after(&prefix, after.as_deref(), wanted)?;
+      let exhausted = read.len() < wanted;
+      if let Some(last) = read.last() {
+        after = Some(last.clone());
+      }
+      keys.extend(
+        read
+          .into_iter()
+          .filter(|key| !self.expiry.is_expired(key, now)),
+      );
+      if exhausted || keys.len() > limit {
+        break;
+      }
+    }
+    let next = match keys.len() > limit {
+      true => {
+        keys.truncate(limit);
+        keys.last().cloned()
+      }
+      false => None,
+    };
+    Ok(KeyPage {
+      keys,
+      next,
+      change_id,
+    })
   }
 
-  /// Applies a function to all key-value pairs in the database.
-  /// Iterates through all key-value pairs and applies the provided function.
-  pub fn fold<F>(&self, f: F) -> Result<()>
+  /// Folds the key-value pairs selected by `options` into an accumulator, Bitcask style.
+  ///
+  /// Pairs are read one at a time in index order, so neither the key list nor the values
+  /// are materialized. Expired keys are skipped.
+  pub fn fold<B, F>(&self, options: IteratorOptions, init: B, mut f: F) -> Result<B>
   where
-    Self: Sized,
-    F: Fn(Bytes, Bytes) -> bool,
+    F: FnMut(B, Bytes, Bytes) -> B,
   {
-    let iter = self.iter(IteratorOptions::default());
-    while let Some((key, value)) = iter.next() {
-      if !f(key, value) {
-        break;
+    let mut cursor = Cursor::open(self, options)?;
+    let mut acc = init;
+    while let Some((key, pos)) = cursor.next(self) {
+      let value = cursor.value(self, &pos)?;
+      cursor.yielded += 1;
+      acc = f(acc, key, value);
+    }
+    match cursor.error {
+      Some(e) => Err(e),
+      None => Ok(acc),
+    }
+  }
+
+  /// Calls `f` with the key-value pairs selected by `options` until it breaks.
+  ///
+  /// Like `fold`, pairs are read one at a time in index order and expired keys are skipped.
+  pub fn for_each<F>(&self, options: IteratorOptions, mut f: F) -> Result<()>
+  where
+    F: FnMut(Bytes, Bytes) -> ControlFlow<()>,
+  {
+    let mut cursor = Cursor::open(self, options)?;
+    while let Some((key, pos)) = cursor.next(self) {
+      let value = cursor.value(self, &pos)?;
+      cursor.yielded += 1;
+      if f(key, value).is_break() {
+        return Ok(());
       }
     }
-    Ok(())
+    match cursor.error {
+      Some(e) => Err(e),
+      None => Ok(()),
+    }
+  }
+
+  /// Counts the live keys starting with `prefix`.
+  pub fn count_prefix(&self, prefix: &[u8]) -> Result<usize> {
+    let options = IteratorOptions {
+      prefix: prefix.to_vec(),
+      ..Default::default()
+    };
+    self.fold(options, 0, |count, _, _| count + 1)
   }
 }
 
 impl Iterator<'_> {
   pub fn rewind(&self) {
-    let mut index_iter = self.index_iter.write();
-    index_iter.rewind();
+    self.cursor.write().rewind();
   }
 
   pub fn seek(&self, key: Vec<u8>) {
-    let mut index_iter = self.index_iter.write();
-    index_iter.seek(key);
+    self.cursor.write().seek(key);
   }
 
+  /// Returns the next pair, None at the end of the iteration.
+  ///
+  /// The iteration ends early if the index or a value can not be read, `try_next` returns
+  /// the failure instead.
   pub fn next(&self) -> Option<(Bytes, Bytes)> {
-    let mut index_iter = self.index_iter.write();
-    if let Some(item) = index_iter.next() {
-      let val = self
-        .engine
-        .get_value_by_position(item.1)
-        .expect("failed to get value from data file");
-      return Some((Bytes::from(item.0.to_vec()), val));
+    self.try_next().unwrap_or_else(|e| {
+      error!("iteration ended by a read failure: {e}");
+      None
+    })
+  }
+
+  /// Like `next`, but returns the failure which ended the iteration, again on every call.
+  pub fn try_next(&self) -> Result<Option<(Bytes, Bytes)>> {
+    let mut cursor = self.cursor.write();
+    if let Some(e) = &cursor.error {
+      return Err(e.clone());
+    }
+    let Some((key, pos)) = cursor.next(self.engine) else {
+      return cursor.error.clone().map_or(Ok(None), Err);
+    };
+    match cursor.value(self.engine, &pos) {
+      Ok(val) => {
+        cursor.yielded += 1;
+        Ok(Some((key, val)))
+      }
+      Err(e) => {
+        cursor.error = Some(e.clone());
+        Err(e)
+      }
     }
-    None
+  }
+
+  /// Returns the next key with the size of its record, headers included, without reading
+  /// the value.
+  pub fn next_key(&self) -> Option<(Bytes, u32)> {
+    let mut cursor = self.cursor.write();
+    let (key, pos) = cursor.next(self.engine)?;
+    cursor.yielded += 1;
+    Some((key, pos.size))
+  }
+
+  /// `Engine::change_id` when the iterator was created.
+  pub fn change_id(&self) -> u64 {
+    self.change_id
   }
 }
 
@@ -75,6 +368,7 @@ mod tests {
 
   use super::*;
 
+  #[allow(clippy::field_reassign_with_default, clippy::len_zero)]
   #[test]
   fn test_iterator_fold() {
     let mut opt = Options::default();
@@ -103,18 +397,44 @@ mod tests {
     );
     assert!(put_res4.is_ok());
 
-    engine
-      .fold(|key, value| {
+    let total = engine
+      .fold(IteratorOptions::default(), 0, |total, key, value| {
         assert!(key.len() > 0);
         assert!(value.len() > 0);
-        true
+        total + value.len()
       })
       .unwrap();
+    assert!(total > 0);
+
+    // keys come in index order, reversed on request
+    let keys = engine
+      .fold(
+        IteratorOptions {
+          reverse: true,
+          ..Default::default()
+        },
+        Vec::new(),
+        |mut keys, key, _| {
+          keys.push(key);
+          keys
+        },
+      )
+      .unwrap();
+    assert_eq!(
+      vec![
+        Bytes::from("eecc"),
+        Bytes::from("ddce"),
+        Bytes::from("bbcc"),
+        Bytes::from("aade")
+      ],
+      keys
+    );
 
     // delete tested files
     std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
   }
 
+  #[allow(clippy::field_reassign_with_default)]
   #[test]
   fn test_iterator_list_keys() {
     let mut opt = Options::default();
@@ -153,6 +473,7 @@ mod tests {
     std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
   }
 
+  #[allow(clippy::field_reassign_with_default)]
   #[test]
   fn test_iterator_seek() {
     let mut opt = Options::default();
@@ -201,6 +522,7 @@ mod tests {
     std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
   }
 
+  #[allow(clippy::field_reassign_with_default, clippy::len_zero)]
   #[test]
   fn test_iterator_next() {
     let mut opt = Options::default();
@@ -249,6 +571,7 @@ mod tests {
     std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
   }
 
+  #[allow(clippy::field_reassign_with_default, clippy::len_zero)]
   #[test]
   fn test_iterator_prefix() {
     let mut opt = Options::default();
@@ -287,4 +610,237 @@ mod tests {
     // delete tested files
     std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove dir");
   }
+
+  #[allow(clippy::field_reassign_with_default)]
+  #[test]
+  fn test_count_prefix() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut opt = Options::default();
+    opt.dir_path = dir.path().to_path_buf();
+    let engine = Engine::open(opt).expect("fail to open engine");
+
+    for key in ["user:1", "user:2", "user:3", "order:1"] {
+      engine
+        .put(Bytes::from(key), util::rand_kv::get_test_value(1))
+        .unwrap();
+    }
+    assert_eq!(3, engine.count_prefix(b"user:").unwrap());
+    assert_eq!(4, engine.count_prefix(b"").unwrap());
+    assert_eq!(0, engine.count_prefix(b"item:").unwrap());
+
+    // deleted and expired keys are not counted
+    engine.delete(Bytes::from("user:1")).unwrap();
+    engine
+      .expire(
+        Bytes::from("user:2"),
+        SystemTime::now() + std::time::Duration::from_millis(20),
+      )
+      .unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(30));
+    assert_eq!(1, engine.count_prefix(b"user:").unwrap());
+  }
+
+  #[allow(clippy::field_reassign_with_default)]
+  #[test]
+  fn test_iterator_limit_start_after() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut opt = Options::default();
+    opt.dir_path = dir.path().to_path_buf();
+    let engine = Engine::open(opt).expect("fail to open engine");
+    for i in 0..10 {
+      engine
+        .put(
+          util::rand_kv::get_test_key(i),
+          util::rand_kv::get_test_value(i),
+        )
+        .unwrap();
+    }
+
+    // pages of 4 keys, each starting after the last key of the previous one
+    let mut pages = Vec::new();
+    let mut start_after = None;
+    loop {
+      let iter = engine.iter(IteratorOptions {
+        limit: Some(4),
+        start_after: start_after.clone(),
+        ..Default::default()
+      });
+      let mut page = Vec::new();
+      while let Some((key, _)) = iter.next() {
+        page.push(key);
+      }
+      if page.is_empty() {
+        break;
+      }
+      start_after = page.last().map(|key| key.to_vec());
+      pages.push(page);
+    }
+    assert_eq!(
+      vec![4, 4, 2],
+      pages.iter().map(Vec::len).collect::<Vec<_>>()
+    );
+    assert_eq!(util::rand_kv::get_test_key(4), pages[1][0]);
+
+    // a start key which does not exist, in reverse order, and a rewind restarting the page
+    let iter = engine.iter(IteratorOptions {
+      reverse: true,
+      limit: Some(2),
+      start_after: Some(b"key-000000005x".to_vec()),
+      ..Default::default()
+    });
+    assert_eq!(util::rand_kv::get_test_key(5), iter.next().unwrap().0);
+    assert_eq!(util::rand_kv::get_test_key(4), iter.next().unwrap().0);
+    assert!(iter.next().is_none());
+    iter.rewind();
+    assert_eq!(util::rand_kv::get_test_key(5), iter.next().unwrap().0);
+  }
+
+  #[allow(clippy::field_reassign_with_default)]
+  #[test]
+  fn test_iterator_keys_only() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut opt = Options::default();
+    opt.dir_path = dir.path().to_path_buf();
+    let engine = Engine::open(opt).expect("fail to open engine");
+    for i in 0..10 {
+      engine
+        .put(
+          util::rand_kv::get_test_key(i),
+          util::rand_kv::get_test_value(i),
+        )
+        .unwrap();
+    }
+
+    let first_key = util::rand_kv::get_test_key(0);
+    let entry_size = engine.get_entry(first_key.clone()).unwrap().size;
+    let bytes_read = engine.get_engine_stat().unwrap().bytes_read;
+    let options = IteratorOptions {
+      keys_only: true,
+      limit: Some(3),
+      ..Default::default()
+    };
+    let iter = engine.iter(options.clone());
+    let (key, size) = iter.next_key().unwrap();
+    assert_eq!((first_key, entry_size), (key, size));
+    assert_eq!(
+      (util::rand_kv::get_test_key(1), Bytes::new()),
+      iter.next().unwrap()
+    );
+    assert!(iter.next_key().is_some());
+    assert!(iter.next_key().is_none());
+
+    let keys = engine
+      .fold(options, Vec::new(), |mut keys, key, value| {
+        assert!(value.is_empty());
+        keys.push(key);
+        keys
+      })
+      .unwrap();
+    assert_eq!(3, keys.len());
+    // no value was read
+    assert_eq!(bytes_read, engine.get_engine_stat().unwrap().bytes_read);
+  }
+
+  #[allow(clippy::field_reassign_with_default)]
+  #[test]
+  fn test_for_each() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut opt = Options::default();
+    opt.dir_path = dir.path().to_path_buf();
+    let engine = Engine::open(opt).expect("fail to open engine");
+    for i in 0..10 {
+      engine
+        .put(
+          util::rand_kv::get_test_key(i),
+          util::rand_kv::get_test_value(i),
+        )
+        .unwrap();
+    }
+
+    // stops as soon as the callback breaks
+    let mut seen = Vec::new();
+    engine
+      .for_each(IteratorOptions::default(), |key, value| {
+        assert!(!value.is_empty());
+        seen.push(key);
+        match seen.len() < 3 {
+          true => ControlFlow::Continue(()),
+          false => ControlFlow::Break(()),
+        }
+      })
+      .unwrap();
+    assert_eq!(3, seen.len());
+
+    let mut count = 0;
+    let options = IteratorOptions {
+      start_after: Some(util::rand_kv::get_test_key(7).to_vec()),
+      ..Default::default()
+    };
+    engine
+      .for_each(options, |_, _| {
+        count += 1;
+        ControlFlow::Continue(())
+      })
+      .unwrap();
+    assert_eq!(2, count);
+  }
+
+  #[allow(clippy::field_reassign_with_default)]
+  #[test]
+  fn test_list_keys_paged() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut opt = Options::default();
+    opt.dir_path = dir.path().to_path_buf();
+    opt.index_shards = 4;
+    let engine = Engine::open(opt).expect("fail to open engine");
+    for i in 0..25 {
+      engine
+        .put(Bytes::from(format!("a-{i:02}")), Bytes::from("value"))
+        .unwrap();
+    }
+    engine
+      .put(Bytes::from("b-00"), Bytes::from("value"))
+      .unwrap();
+
+    // page through the prefix
+    let mut keys = Vec::new();
+    let mut start_after = None;
+    let mut pages = 0;
+    loop {
+      let page = engine
+        .list_keys_paged(Some(Bytes::from("a-")), start_after, 10)
+        .unwrap();
+      pages += 1;
+      keys.extend(page.keys);
+      match page.next {
+        Some(next) => start_after = Some(next),
+        None => break,
+      }
+    }
+    assert_eq!(3, pages);
+    assert_eq!(
+      (0..25)
+        .map(|i| Bytes::from(format!("a-{i:02}")))
+        .collect::<Vec<_>>(),
+      keys
+    );
+
+    // an exactly full last page has no next key
+    let page = engine
+      .list_keys_paged(None, Some(Bytes::from("a-23")), 2)
+      .unwrap();
+    assert_eq!(vec![Bytes::from("a-24"), Bytes::from("b-00")], page.keys);
+    assert_eq!(None, page.next);
+
+    // `start_after` outside of the prefix
+    let page = engine
+      .list_keys_paged(Some(Bytes::from("a-")), Some(Bytes::from("a")), 1)
+      .unwrap();
+    assert_eq!(vec![Bytes::from("a-00")], page.keys);
+    let page = engine
+      .list_keys_paged(Some(Bytes::from("a-")), Some(Bytes::from("b")), 1)
+      .unwrap();
+    assert!(page.keys.is_empty());
+    assert_eq!(26, engine.list_keys().unwrap().len());
+  }
 }
@@ -0,0 +1,171 @@
+use std::{
+  collections::HashMap,
+  thread::{self, ThreadId},
+};
+
+use bytes::Bytes;
+use parking_lot::{Condvar, Mutex};
+
+use crate::db::Engine;
+
+const KEY_LOCK_SHARDS: usize = 64;
+
+/// Keys locked through `Engine::lock_key`, spread over shards by their hash.
+pub(crate) struct KeyLockTable {
+  shards: Vec<KeyLockShard>,
+}
+
+#[derive(Default)]
+struct KeyLockShard {
+  held: Mutex<HashMap<Vec<u8>, (ThreadId, usize)>>, // owner and lock count of every locked key
+  released: Condvar,
+}
+
+impl Default for KeyLockTable {
+  fn default() -> Self {
+    Self {
+      shards: (0..KEY_LOCK_SHARDS)
+        .map(|_| KeyLockShard::default())
+        .collect(),
+    }
+  }
+}
+
+impl KeyLockTable {
+  fn shard(&self, key: &[u8]) -> &KeyLockShard {
+    &self.shards[crc32fast::hash(key) as usize % self.shards.len()]
+  }
+
+  fn lock(&self, key: &[u8]) {
+    let shard = self.shard(key);
+    let owner = thread::current().id();
+    let mut held = shard.held.lock();
+    loop {
+      match held.get_mut(key) {
+        None => {
+          held.insert(key.to_vec(), (owner, 1));
+          return;
+        }
+        Some((holder, count)) if *holder == owner => {
+          *count += 1;
+          return;
+        }
+        Some(_) => shard.released.wait(&mut held),
+      }
+    }
+  }
+
+  fn unlock(&self, key: &[u8]) {
+    let shard = self.shard(key);
+    let mut held = shard.held.lock();
+    if let Some((_, count)) = held.get_mut(key) {
+      *count -= 1;
+      if *count == 0 {
+        held.remove(key);
+        shard.released.notify_all();
+      }
+    }
+  }
+}
+
+/// Lock of a key taken by `Engine::lock_key`, released when dropped.
+pub struct KeyGuard<'a> {
+  table: &'a KeyLockTable,
+  key: Bytes,
+}
+
+impl KeyGuard<'_> {
+  pub fn key(&self) -> &Bytes {
+    &self.key
+  }
+}
+
+impl Drop for KeyGuard<'_> {
+  fn drop(&mut self) {
+    self.table.unlock(&self.key);
+  }
+}
+
+impl Engine {
+  /// Locks `key` until the guard is dropped, so read-modify-write sequences on the key are
+  /// serialized across threads. Every write of the key through the engine takes the lock,
+  /// reads and batch commits do not wait for it.
+  ///
+  /// The lock is reentrant, a thread holding it may lock the key again or write it.
+  pub fn lock_key(&self, key: Bytes) -> KeyGuard<'_> {
+    self.key_locks.lock(&key);
+    KeyGuard {
+      table: &self.key_locks,
+      key,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{sync::Arc, thread};
+
+  use tempfile::tempdir;
+
+  use super::*;
+  use crate::{errors::Errors, option::Options, util::rand_kv::get_test_key};
+
+  #[test]
+  fn test_lock_key() {
+    let temp_dir = tempdir().expect("failed to create temp dir");
+    let opt = Options {
+      dir_path: temp_dir.path().to_path_buf(),
+      ..Default::default()
+    };
+    let engine = Arc::new(Engine::open(opt).expect("fail to open engine"));
+    let key = get_test_key(1);
+
+    let handles: Vec<_> = (0..4)
+      .map(|_| {
+        let engine = engine.clone();
+        let key = key.clone();
+        thread::spawn(move || {
+          for _ in 0..50 {
+            let _guard = engine.lock_key(key.clone());
+            let value = match engine.get(key.clone()) {
+              Ok(value) => String::from_utf8(value.to_vec()).unwrap().parse().unwrap(),
+              Err(_) => 0u64,
+            };
+            engine
+              .put(key.clone(), Bytes::from((value + 1).to_string()))
+              .unwrap();
+          }
+        })
+      })
+      .collect();
+    for handle in handles {
+      handle.join().unwrap();
+    }
+    assert_eq!(Bytes::from("200"), engine.get(key.clone()).unwrap());
+
+    // reentrant, incr takes the lock as well
+    let counter = get_test_key(2);
+    let guard = engine.lock_key(counter.clone());
+    let nested = engine.lock_key(counter.clone());
+    assert_eq!(5, engine.incr(counter.clone(), 5).unwrap());
+    drop(nested);
+    let engine2 = engine.clone();
+    let counter2 = counter.clone();
+    let waiter = thread::spawn(move || engine2.incr(counter2, 1).unwrap());
+    assert_eq!(&counter, guard.key());
+    drop(guard);
+    assert_eq!(6, waiter.join().unwrap());
+
+    // plain writes wait for the lock too
+    let key = get_test_key(3);
+    let guard = engine.lock_key(key.clone());
+    let engine2 = engine.clone();
+    let key2 = key.clone();
+    let writer = thread::spawn(move || engine2.put(key2, Bytes::from("value")).unwrap());
+    thread::sleep(std::time::Duration::from_millis(20));
+    assert_eq!(Err(Errors::KeyNotFound), engine.get(key.clone()));
+    drop(guard);
+    writer.join().unwrap();
+    assert_eq!(Bytes::from("value"), engine.get(key).unwrap());
+  }
+}
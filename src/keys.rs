@@ -0,0 +1,294 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+
+use crate::errors::{Errors, Result};
+
+// strings are terminated by ESCAPE TERMINATOR, a zero byte inside is written as
+// ESCAPE ESCAPED_ZERO, so a shorter string sorts before every string it is a prefix of
+const ESCAPE: u8 = 0x00;
+const TERMINATOR: u8 = 0x00;
+const ESCAPED_ZERO: u8 = 0xff;
+
+/// A part of a composite key.
+///
+/// The indexes order keys bytewise, so numbers are stored big-endian, signed numbers with
+/// their sign bit flipped and strings with a terminator, for range scans over composite keys
+/// to return them in the order of their parts.
+pub trait KeyPart {
+  /// Appends the order preserving encoding of the part.
+  fn encode_key_part(&self, buf: &mut Vec<u8>);
+}
+
+impl KeyPart for u64 {
+  fn encode_key_part(&self, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&self.to_be_bytes());
+  }
+}
+
+impl KeyPart for i64 {
+  fn encode_key_part(&self, buf: &mut Vec<u8>) {
+    // flipping the sign bit orders negative numbers before positive ones
+    buf.extend_from_slice(&((*self as u64) ^ (1 << 63)).to_be_bytes());
+  }
+}
+
+impl KeyPart for [u8] {
+  fn encode_key_part(&self, buf: &mut Vec<u8>) {
+    for byte in self {
+      match *byte {
+        ESCAPE => buf.extend_from_slice(&[ESCAPE, ESCAPED_ZERO]),
+        byte => buf.push(byte),
+      }
+    }
+    buf.extend_from_slice(&[ESCAPE, TERMINATOR]);
+  }
+}
+
+impl KeyPart for str {
+  fn encode_key_part(&self, buf: &mut Vec<u8>) {
+    self.as_bytes().encode_key_part(buf);
+  }
+}
+
+impl KeyPart for String {
+  fn encode_key_part(&self, buf: &mut Vec<u8>) {
+    self.as_str().encode_key_part(buf);
+  }
+}
+
+impl KeyPart for Vec<u8> {
+  fn encode_key_part(&self, buf: &mut Vec<u8>) {
+    self.as_slice().encode_key_part(buf);
+  }
+}
+
+/// Timestamps are encoded as signed microseconds since the unix epoch.
+impl KeyPart for SystemTime {
+  fn encode_key_part(&self, buf: &mut Vec<u8>) {
+    let micros = match self.duration_since(UNIX_EPOCH) {
+      Ok(since) => i64::try_from(since.as_micros()).unwrap_or(i64::MAX),
+      Err(e) => i64::try_from(e.duration().as_micros()).map_or(i64::MIN, |micros| -micros),
+    };
+    micros.encode_key_part(buf);
+  }
+}
+
+impl<T: KeyPart + ?Sized> KeyPart for &T {
+  fn encode_key_part(&self, buf: &mut Vec<u8>) {
+    (**self).encode_key_part(buf);
+  }
+}
+
+macro_rules! impl_key_part_for_tuple {
+  ($($name:ident),+) => {
+    impl<$($name: KeyPart),+> KeyPart for ($($name,)+) {
+      #[allow(non_snake_case)]
+      fn encode_key_part(&self, buf: &mut Vec<u8>) {
+        let ($($name,)+) = self;
+        $($name.encode_key_part(buf);)+
+      }
+    }
+  };
+}
+
+impl_key_part_for_tuple!(A);
+impl_key_part_for_tuple!(A, B);
+impl_key_part_for_tuple!(A, B, C);
+impl_key_part_for_tuple!(A, B, C, D);
+impl_key_part_for_tuple!(A, B, C, D, E);
+
+/// Encodes a composite key, usually a tuple of its parts.
+pub fn encode_key<T: KeyPart + ?Sized>(key: &T) -> Bytes {
+  let mut buf = Vec::new();
+  key.encode_key_part(&mut buf);
+  buf.into()
+}
+
+/// Builds a composite key part by part.
+///
+/// ```
+/// use flash_kv::keys::{KeyBuilder, KeyReader};
+///
+/// let key = KeyBuilder::new().push("user").push(42u64).push(-7i64).build();
+/// let mut reader = KeyReader::new(&key);
+/// assert_eq!("user", reader.read_str().unwrap());
+/// assert_eq!(42, reader.read_u64().unwrap());
+/// assert_eq!(-7, reader.read_i64().unwrap());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct KeyBuilder {
+  buf: Vec<u8>,
+}
+
+impl KeyBuilder {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn push<T: KeyPart>(mut self, part: T) -> Self {
+    part.encode_key_part(&mut self.buf);
+    self
+  }
+
+  /// The encoded key, also usable as the prefix of a scan over keys starting with its parts.
+  pub fn build(self) -> Bytes {
+    self.buf.into()
+  }
+}
+
+/// Decodes the parts of a composite key in the order they were pushed.
+pub struct KeyReader<'a> {
+  buf: &'a [u8],
+}
+
+impl<'a> KeyReader<'a> {
+  pub fn new(key: &'a [u8]) -> Self {
+    Self { buf: key }
+  }
+
+  /// Whether all parts were read.
+  pub fn is_empty(&self) -> bool {
+    self.buf.is_empty()
+  }
+
+  pub fn read_u64(&mut self) -> Result<u64> {
+    let Some((bytes, rest)) = self.buf.split_first_chunk::<8>() else {
+      return Err(Errors::InvalidKeyEncoding);
+    };
+    self.buf = rest;
+    Ok(u64::from_be_bytes(*bytes))
+  }
+
+  pub fn read_i64(&mut self) -> Result<i64> {
+    Ok((self.read_u64()? ^ (1 << 63)) as i64)
+  }
+
+  pub fn read_bytes(&mut self) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut iter = self.buf.iter().enumerate();
+    while let Some((i, byte)) = iter.next() {
+      if *byte != ESCAPE {
+        bytes.push(*byte);
+        continue;
+      }
+      match iter.next() {
+        Some((_, &TERMINATOR)) => {
+          self.buf = &self.buf[i + 2..];
+          return Ok(bytes);
+        }
+        Some((_, &ESCAPED_ZERO)) => bytes.push(0),
+        _ => return Err(Errors::InvalidKeyEncoding),
+      }
+    }
+    Err(Errors::InvalidKeyEncoding)
+  }
+
+  pub fn read_str(&mut self) -> Result<String> {
+    String::from_utf8(self.read_bytes()?).map_err(|_| Errors::InvalidKeyEncoding)
+  }
+
+  pub fn read_timestamp(&mut self) -> Result<SystemTime> {
+    let micros = self.read_i64()?;
+    let since = Duration::from_micros(micros.unsigned_abs());
+    let time = match micros >= 0 {
+      true => UNIX_EPOCH.checked_add(since),
+      false => UNIX_EPOCH.checked_sub(since),
+    };
+    time.ok_or(Errors::InvalidKeyEncoding)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_key_order() {
+    let numbers = [i64::MIN, -300, -1, 0, 1, 255, 256, i64::MAX];
+    let encoded: Vec<_> = numbers.iter().map(encode_key).collect();
+    assert!(encoded.windows(2).all(|w| w[0] < w[1]));
+
+    let strings = ["", "\0", "\0\0", "a", "a\0", "a\0b", "ab", "b"];
+    let encoded: Vec<_> = strings.iter().map(encode_key).collect();
+    assert!(encoded.windows(2).all(|w| w[0] < w[1]));
+
+    // the first part decides before the second one
+    let tuples = [("a", 2u64), ("a", 10u64), ("ab", 1u64), ("b", 0u64)];
+    let encoded: Vec<_> = tuples.iter().map(encode_key).collect();
+    assert!(encoded.windows(2).all(|w| w[0] < w[1]));
+
+    let before_epoch = UNIX_EPOCH - Duration::from_secs(10);
+    let now = SystemTime::now();
+    assert!(encode_key(&before_epoch) < encode_key(&UNIX_EPOCH));
+    assert!(encode_key(&UNIX_EPOCH) < encode_key(&now));
+  }
+
+  #[test]
+  fn test_key_roundtrip() {
+    let time = UNIX_EPOCH + Duration::from_micros(1_700_000_000_123_456);
+    let before_epoch = UNIX_EPOCH - Duration::from_micros(42);
+    let key = KeyBuilder::new()
+      .push("tenant\0a")
+      .push(7u64)
+      .push(-3i64)
+      .push(time)
+      .push(before_epoch)
+      .push(vec![0u8, 1, 0xff])
+      .build();
+    assert_eq!(
+      key,
+      encode_key(&(
+        "tenant\0a",
+        7u64,
+        -3i64,
+        time,
+        (before_epoch, vec![0u8, 1, 0xff])
+      ))
+    );
+
+    let mut reader = KeyReader::new(&key);
+    assert_eq!("tenant\0a", reader.read_str().unwrap());
+    assert_eq!(7, reader.read_u64().unwrap());
+    assert_eq!(-3, reader.read_i64().unwrap());
+    assert_eq!(time, reader.read_timestamp().unwrap());
+    assert_eq!(before_epoch, reader.read_timestamp().unwrap());
+    assert_eq!(vec![0u8, 1, 0xff], reader.read_bytes().unwrap());
+    assert!(reader.is_empty());
+    assert_eq!(Err(Errors::InvalidKeyEncoding), reader.read_u64());
+
+    // an unterminated string
+    let mut reader = KeyReader::new(b"abc");
+    assert_eq!(Err(Errors::InvalidKeyEncoding), reader.read_str());
+  }
+
+  #[test]
+  fn test_key_prefix_scan() {
+    let dir = tempfile::tempdir().unwrap();
+    let opts = crate::option::Options::builder()
+      .dir_path(dir.path())
+      .build()
+      .unwrap();
+    let engine = crate::db::Engine::open(opts).unwrap();
+    for user in ["alice", "alice2", "bob"] {
+      for day in [1u64, 2, 10] {
+        let key = encode_key(&(user, day));
+        engine
+          .put(key, Bytes::from(format!("{user}-{day}")))
+          .unwrap();
+      }
+    }
+
+    // the prefix of "alice" does not match "alice2", days come back in numeric order
+    let prefix = KeyBuilder::new().push("alice").build();
+    let iter = engine.iter(crate::option::IteratorOptions {
+      prefix: prefix.to_vec(),
+      ..Default::default()
+    });
+    let mut values = Vec::new();
+    while let Some((_, value)) = iter.next() {
+      values.push(value);
+    }
+    assert_eq!(vec!["alice-1", "alice-2", "alice-10"], values);
+  }
+}
@@ -0,0 +1,270 @@
+use std::time::SystemTime;
+
+use bytes::Bytes;
+use prost::length_delimiter_len;
+
+use crate::{db::Engine, errors::Result, expiry::unix_millis, option::IteratorOptions};
+
+// log record overhead besides the key, value and their lengths: type byte and crc32
+const RECORD_OVERHEAD: usize = 1 + 4;
+
+// keys deleted per transaction by `Engine::delete_prefix`
+const DELETE_PREFIX_BATCH: usize = 1024;
+
+/// Number of keys under a prefix and the bytes their latest records take on disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PrefixStat {
+  /// Number of live keys
+  pub key_num: usize,
+
+  /// Size of the live records, including keys and record headers
+  pub live_bytes: u64,
+}
+
+/// Distribution of the value sizes of live keys over power of two buckets.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValueSizeHistogram {
+  /// `counts[0]` holds empty values, `counts[i]` values of `[2^(i-1), 2^i)` bytes.
+  /// Trailing empty buckets are left out
+  pub counts: Vec<usize>,
+}
+
+impl ValueSizeHistogram {
+  /// Exclusive upper bound of the sizes counted in bucket `i`.
+  pub fn upper_bound(i: usize) -> u64 {
+    1u64.checked_shl(i as u32).unwrap_or(u64::MAX)
+  }
+
+  fn record(&mut self, value_size: usize) {
+    let bucket = (usize::BITS - value_size.leading_zeros()) as usize;
+    if self.counts.len() <= bucket {
+      self.counts.resize(bucket + 1, 0);
+    }
+    self.counts[bucket] += 1;
+  }
+}
+
+impl Engine {
+  /// Counts the keys starting with `prefix` and the bytes of their records.
+  ///
+  /// Only the index is read, so this is cheap enough for periodic tenant accounting.
+  pub fn prefix_stat(&self, prefix: &[u8]) -> Result<PrefixStat> {
+    let now = unix_millis(SystemTime::now());
+    let mut stat = PrefixStat::default();
+    let mut iter = self.index.try_iterator(IteratorOptions {
+      prefix: prefix.to_vec(),
+      ..Default::default()
+    })?;
+    while let Some((key, pos)) = iter.next() {
+      if self.expiry.is_expired(key, now) {
+        continue;
+      }
+      stat.key_num += 1;
+      stat.live_bytes += pos.size as u64;
+    }
+    Ok(stat)
+  }
+
+  /// Approximate bytes taken by the live records of the keys starting with `prefix`, the
+  /// `live_bytes` of [`Engine::prefix_stat`].
+  pub fn approximate_size_of_prefix(&self, prefix: &[u8]) -> Result<u64> {
+    Ok(self.prefix_stat(prefix)?.live_bytes)
+  }
+
+  /// Lists the live keys starting with `prefix`, only the index is read.
+  pub fn prefix_keys(&self, prefix: &[u8]) -> Result<Vec<Bytes>> {
+    self.check_open()?;
+    let now = unix_millis(SystemTime::now());
+    let mut keys = Vec::new();
+    let mut iter = self.index.try_iterator(IteratorOptions {
+      prefix: prefix.to_vec(),
+      ..Default::default()
+    })?;
+    while let Some((key, _)) = iter.next() {
+      if !self.expiry.is_expired(key, now) {
+        keys.push(Bytes::copy_from_slice(key));
+      }
+    }
+    Ok(keys)
+  }
+
+  /// Deletes the live keys starting with `prefix`, returns the number of keys deleted.
+  ///
+  /// The keys are deleted in transactions of up to 1024 keys each, so a huge prefix is not
+  /// held in memory at once. A failing transaction leaves the keys of the earlier ones
+  /// deleted. Keys written under the prefix meanwhile may or may not be deleted.
+  pub fn delete_prefix(&self, prefix: &[u8]) -> Result<usize> {
+    let prefix = Bytes::copy_from_slice(prefix);
+    let mut deleted = 0;
+    let mut start_after = None;
+    loop {
+      let page = self.list_keys_paged(Some(prefix.clone()), start_after, DELETE_PREFIX_BATCH)?;
+      if !page.keys.is_empty() {
+        let found = self.delete_many(&page.keys)?;
+        deleted += found.into_iter().filter(|found| *found).count();
+      }
+      match page.next {
+        Some(next) => start_after = Some(next),
+        None => return Ok(deleted),
+      }
+    }
+  }
+
+  /// Builds a histogram of the value sizes of all live keys.
+  ///
+  /// Sizes are derived from the record sizes held by the index, values written in a
+  /// transaction or with `Options::store_timestamps` are counted a few bytes too large.
+  pub fn value_size_histogram(&self) -> Result<ValueSizeHistogram> {
+    let now = unix_millis(SystemTime::now());
+    let mut histogram = ValueSizeHistogram::default();
+    let mut iter = self.index.try_iterator(IteratorOptions::default())?;
+    while let Some((key, pos)) = iter.next() {
+      if self.expiry.is_expired(key, now) {
+        continue;
+      }
+      histogram.record(value_size(key.len(), pos.size as usize));
+    }
+    Ok(histogram)
+  }
+}
+
+/// Size of the record of a key and value written outside a transaction.
+pub(crate) fn record_size(key_len: usize, value_len: usize) -> usize {
+  let key_len = key_len + 1;
+  RECORD_OVERHEAD
+    + length_delimiter_len(key_len)
+    + key_len
+    + length_delimiter_len(value_len)
+    + value_len
+}
+
+/// Size of the value of a record written outside a transaction, whose key carries a one
+/// byte sequence number prefix.
+fn value_size(key_len: usize, record_size: usize) -> usize {
+  let key_len = key_len + 1;
+  let rest = record_size.saturating_sub(RECORD_OVERHEAD + length_delimiter_len(key_len) + key_len);
+  // `rest` holds the value and its varint length
+  (1..=5)
+    .find(|len| rest >= *len && length_delimiter_len(rest - len) == *len)
+    .map(|len| rest - len)
+    .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use bytes::Bytes;
+
+  use crate::{
+    batch::{log_record_key_with_seq, NON_TXN_SEQ_NO},
+    data::log_record::{LogRecord, LogRecordType},
+    option::Options,
+  };
+
+  #[test]
+  fn test_value_size() {
+    for size in [0, 1, 127, 128, 300, 16_383, 16_384, 1 << 20] {
+      let record = LogRecord {
+        key: log_record_key_with_seq(b"key", NON_TXN_SEQ_NO),
+        value: vec![0; size],
+        rec_type: LogRecordType::Normal,
+        timestamp: None,
+      };
+      assert_eq!(size, value_size(3, record.encode().len()));
+      assert_eq!(record.encode().len(), record_size(3, size));
+    }
+  }
+
+  #[test]
+  fn test_value_size_histogram_buckets() {
+    let mut histogram = ValueSizeHistogram::default();
+    for size in [0, 1, 2, 3, 4, 1000] {
+      histogram.record(size);
+    }
+    assert_eq!(vec![1, 1, 2, 1, 0, 0, 0, 0, 0, 0, 1], histogram.counts);
+    assert_eq!(1024, ValueSizeHistogram::upper_bound(10));
+  }
+
+  #[test]
+  fn test_approximate_size_of_prefix() {
+    let dir = tempfile::tempdir().unwrap();
+    let opts = Options {
+      dir_path: dir.path().to_path_buf(),
+      ..Default::default()
+    };
+    let engine = Engine::open(opts).expect("fail to open engine");
+
+    for (key, len) in [("user:1", 100), ("user:2", 200), ("order:1", 300)] {
+      engine
+        .put(Bytes::from(key), Bytes::from(vec![b'v'; len]))
+        .unwrap();
+    }
+    let user_size = engine.approximate_size_of_prefix(b"user:").unwrap();
+    let total_size = engine.approximate_size_of_prefix(b"").unwrap();
+    assert!((300..300 + 2 * 32).contains(&user_size));
+    assert!((600..600 + 3 * 32).contains(&total_size));
+    assert_eq!(0, engine.approximate_size_of_prefix(b"item:").unwrap());
+
+    // overwritten, deleted and expired records are not live
+    engine
+      .put(Bytes::from("user:1"), Bytes::from(vec![b'v'; 10]))
+      .unwrap();
+    engine.delete(Bytes::from("order:1")).unwrap();
+    let size = engine.approximate_size_of_prefix(b"").unwrap();
+    assert!((210..210 + 2 * 32).contains(&size));
+    engine
+      .expire(
+        Bytes::from("user:2"),
+        SystemTime::now() + std::time::Duration::from_millis(20),
+      )
+      .unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(30));
+    let size = engine.approximate_size_of_prefix(b"user:").unwrap();
+    assert!((10..10 + 32).contains(&size));
+  }
+
+  #[test]
+  fn test_delete_prefix() {
+    let dir = tempfile::tempdir().unwrap();
+    let opts = Options {
+      dir_path: dir.path().to_path_buf(),
+      ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("fail to open engine");
+    for key in ["user:1", "user:2", "user:3", "users", "order:1"] {
+      engine.put(Bytes::from(key), Bytes::from("v")).unwrap();
+    }
+
+    assert_eq!(
+      vec![
+        Bytes::from("user:1"),
+        Bytes::from("user:2"),
+        Bytes::from("user:3")
+      ],
+      engine.prefix_keys(b"user:").unwrap()
+    );
+    assert_eq!(3, engine.delete_prefix(b"user:").unwrap());
+    assert_eq!(0, engine.delete_prefix(b"user:").unwrap());
+    assert_eq!(
+      vec![Bytes::from("order:1"), Bytes::from("users")],
+      engine.list_keys().unwrap()
+    );
+
+    // prefixes larger than a transaction are deleted batch by batch
+    for i in 0..DELETE_PREFIX_BATCH * 2 + 10 {
+      let key = format!("tenant:{i:05}");
+      engine.put(Bytes::from(key), Bytes::from("v")).unwrap();
+    }
+    assert_eq!(
+      DELETE_PREFIX_BATCH * 2 + 10,
+      engine.delete_prefix(b"tenant:").unwrap()
+    );
+
+    // the deletes are transactions and survive a restart
+    drop(engine);
+    let engine = Engine::open(opts).expect("fail to open engine");
+    assert!(engine.prefix_keys(b"user:").unwrap().is_empty());
+    assert!(engine.prefix_keys(b"tenant:").unwrap().is_empty());
+    assert_eq!(2, engine.list_keys().unwrap().len());
+  }
+}
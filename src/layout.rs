@@ -0,0 +1,154 @@
+use std::{
+  fs,
+  path::{Path, PathBuf},
+};
+
+use log::error;
+
+use crate::{
+  data::data_file::{
+    get_data_file_dir, BLOB_FILE_NAME_SUFFIX, DATA_FILE_NAME_SUFFIX, KEYDIR_FILE_NAME_SUFFIX,
+    TOMBSTONE_FILE_NAME_SUFFIX,
+  },
+  db::Engine,
+  errors::{Errors, Result},
+};
+
+impl Engine {
+  /// Directory of a data file and its key directory, see `Options::files_per_subdir`.
+  pub(crate) fn data_file_dir(&self, file_id: u32) -> PathBuf {
+    get_data_file_dir(
+      &self.options.dir_path,
+      file_id,
+      self.options.files_per_subdir,
+    )
+  }
+}
+
+/// Directories which may hold data files: the database directory and its numbered
+/// subdirectories.
+pub(crate) fn data_file_dirs(dir_path: &Path) -> Result<Vec<PathBuf>> {
+  let dir = fs::read_dir(dir_path).map_err(|_| Errors::FailedToReadDatabaseDir)?;
+  let mut dirs = vec![dir_path.to_path_buf()];
+  for entry in dir.flatten() {
+    let is_subdir = entry
+      .file_name()
+      .to_str()
+      .is_some_and(|name| !name.is_empty() && name.bytes().all(|b| b.is_ascii_digit()));
+    if is_subdir && entry.path().is_dir() {
+      dirs.push(entry.path());
+    }
+  }
+  Ok(dirs)
+}
+
+/// Id of a data file, key directory, tombstone sidecar or blob file by its file name.
+pub(crate) fn parse_data_file_id(file_name: &str) -> Option<u32> {
+  file_name
+    .strip_suffix(DATA_FILE_NAME_SUFFIX)
+    .or_else(|| file_name.strip_suffix(KEYDIR_FILE_NAME_SUFFIX))
+    .or_else(|| file_name.strip_suffix(TOMBSTONE_FILE_NAME_SUFFIX))
+    .or_else(|| file_name.strip_suffix(BLOB_FILE_NAME_SUFFIX))?
+    .parse()
+    .ok()
+}
+
+/// Moves the data files, key directories, tombstone sidecars and blob files into the layout of
+/// `files_per_subdir`, then removes the subdirectories left empty. An interrupted move is
+/// finished by the next call.
+pub(crate) fn relocate_data_files(dir_path: &Path, files_per_subdir: u32) -> Result<()> {
+  for dir in data_file_dirs(dir_path)? {
+    let entries = fs::read_dir(&dir).map_err(|_| Errors::FailedToReadDatabaseDir)?;
+    for entry in entries.flatten() {
+      let file_name = entry.file_name();
+      let Some(file_id) = file_name.to_str().and_then(parse_data_file_id) else {
+        continue;
+      };
+      let dst_dir = get_data_file_dir(dir_path, file_id, files_per_subdir);
+      if dst_dir == dir {
+        continue;
+      }
+      fs::create_dir_all(&dst_dir).map_err(|e| {
+        error!("failed to create data file dir {}: {e}", dst_dir.display());
+        Errors::FailedToCreateDatabaseDir
+      })?;
+      let src_path = entry.path();
+      fs::rename(&src_path, dst_dir.join(&file_name)).map_err(|e| {
+        error!("failed to move data file {}: {e}", src_path.display());
+        Errors::FailedToRenameFile
+      })?;
+    }
+
+    // only fails if the directory still holds files
+    if dir != dir_path {
+      let _ = fs::remove_dir(&dir);
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use bytes::Bytes;
+
+  use super::*;
+  use crate::{data::data_file::get_data_file_name, option::Options};
+
+  #[test]
+  fn test_files_per_subdir() {
+    let dir = tempfile::tempdir().unwrap();
+    let opts = Options {
+      dir_path: dir.path().to_path_buf(),
+      data_file_size: 4 * 1024,
+      startup_manifest: true,
+      ..Default::default()
+    };
+    let put_keys = |engine: &Engine, range: std::ops::Range<usize>| {
+      for i in range {
+        let key = Bytes::from(format!("key-{i:05}"));
+        engine.put(key, Bytes::from(vec![b'v'; 128])).unwrap();
+      }
+    };
+
+    // flat layout first
+    let engine = Engine::open(opts.clone()).expect("fail to open engine");
+    put_keys(&engine, 0..200);
+    engine.close().unwrap();
+    drop(engine);
+    assert!(get_data_file_name(dir.path(), 1).is_file());
+
+    // the files are moved into subdirectories of 4 files each
+    let sharded = Options {
+      files_per_subdir: 4,
+      ..opts.clone()
+    };
+    let engine = Engine::open(sharded.clone()).expect("fail to open engine");
+    assert!(!get_data_file_name(dir.path(), 1).exists());
+    assert!(get_data_file_name(dir.path().join("000"), 1).is_file());
+    assert!(get_data_file_name(dir.path().join("001"), 5).is_file());
+    put_keys(&engine, 200..400);
+    for i in (0..400).step_by(37) {
+      assert!(engine.get(Bytes::from(format!("key-{i:05}"))).is_ok());
+    }
+
+    // merged files land in their subdirectories as well
+    for i in 0..300 {
+      engine.delete(Bytes::from(format!("key-{i:05}"))).unwrap();
+    }
+    engine.merge().unwrap();
+    engine.close().unwrap();
+    drop(engine);
+    let engine = Engine::open(sharded).expect("fail to open engine");
+    assert_eq!(100, engine.list_keys().unwrap().len());
+    let active_file_id = engine.active_data_file.read().get_file_id();
+    let active_dir = get_data_file_dir(dir.path(), active_file_id, 4);
+    assert!(get_data_file_name(active_dir, active_file_id).is_file());
+    engine.close().unwrap();
+    drop(engine);
+
+    // and back to the flat layout, without empty subdirectories left behind
+    let engine = Engine::open(opts).expect("fail to open engine");
+    assert_eq!(1, data_file_dirs(dir.path()).unwrap().len());
+    assert_eq!(100, engine.list_keys().unwrap().len());
+  }
+}
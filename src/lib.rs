@@ -1,11 +1,11 @@
 //! Flash-KV: A high-performance key-value storage engine inspired by Bitcask.
-//! 
+//!
 //! Flash-KV implements a log-structured storage design optimized for fast reads and writes.
 //! The engine provides efficient storage and retrieval of key-value pairs with durability
 //! guarantees and space management through compaction.
 //!
 //! # Features
-//! 
+//!
 //! * Fast reads and writes with minimal disk I/O
 //! * Durable storage with configurable sync options
 //! * Atomic write batches for transactional operations
@@ -35,18 +35,74 @@
 //! // Delete the key
 //! engine.delete(key).expect("Failed to delete");
 //! ```
+//!
+//! # Panics
+//!
+//! The library does not panic on corrupted files or I/O failures, every such failure is
+//! returned as an [`errors::Errors`] value. This is enforced by the lints below.
+
+#![cfg_attr(
+  not(test),
+  deny(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::unimplemented,
+    clippy::todo
+  )
+)]
 
 mod data;
 
-mod fio;
-mod index;
+pub mod fio;
+pub mod index;
+mod io_stats;
 mod iterator;
+mod layout;
+mod manifest;
 
 pub mod batch;
+mod blob;
+mod bulk;
+pub mod cdc;
+mod clear;
+pub mod compact;
 pub mod db;
 #[cfg(test)]
 mod db_test;
+pub mod epoch;
 pub mod errors;
+pub mod event;
+pub mod expiry;
+#[cfg(feature = "export")]
+pub mod export;
+pub mod garbage;
+#[cfg(feature = "export")]
+pub mod index_dump;
+pub mod keylock;
+pub mod keys;
+pub mod keyspace;
 pub mod merge;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod multi_batch;
 pub mod option;
+pub mod quota;
+mod reader;
+pub mod registry;
+mod reopen;
+pub mod repair;
+pub mod scrub;
+mod seqno;
+pub mod shard;
+pub mod slowlog;
+pub mod stats_dump;
+pub mod stream;
+#[cfg(feature = "test-util")]
+pub mod testing;
+mod throttle;
+mod tombstone;
+pub mod transform;
+mod tuning;
 pub mod util;
+pub mod watch;
@@ -0,0 +1,263 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use bytes::{Buf, BufMut, BytesMut};
+use log::{error, warn};
+
+use crate::{
+  data::{
+    data_file::{get_keydir_file_name, DataFile, MANIFEST_FILE_NAME, MANIFEST_TMP_FILE_NAME},
+    log_record::{decode_log_record_pos, LogRecord, LogRecordPos, LogRecordType},
+  },
+  db::Engine,
+  errors::{DataFileOp, Errors, Result},
+  reopen::remove_file_if_exists,
+  util::file::sync_dir,
+};
+
+// manifest entries written before the key directory summary hold the size and checksum only
+const LEGACY_ENTRY_SIZE: usize = 8 + 4;
+const ENTRY_SIZE: usize = 8 + 4 + 4 + 4;
+
+/// Final size and footer checksum of a sealed data file, as recorded in the manifest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SealedFile {
+  pub(crate) size: u64,
+  pub(crate) checksum: u32,
+
+  /// Entry count and crc of the key directory, None for legacy entries
+  pub(crate) keydir: Option<(u32, u32)>,
+}
+
+impl SealedFile {
+  fn encode(&self) -> Vec<u8> {
+    let (entries, crc) = self.keydir.unwrap_or_default();
+    let mut value = BytesMut::with_capacity(ENTRY_SIZE);
+    value.put_u64(self.size);
+    value.put_u32(self.checksum);
+    value.put_u32(entries);
+    value.put_u32(crc);
+    value.to_vec()
+  }
+}
+
+/// Encoded key directory of the active file, kept when `startup_manifest` is set.
+#[derive(Default)]
+pub(crate) struct KeyDirBuffer {
+  entries: Vec<u8>,
+  count: u32,
+}
+
+impl KeyDirBuffer {
+  pub(crate) fn clear(&mut self) {
+    self.entries.clear();
+    self.count = 0;
+  }
+}
+
+impl Engine {
+  /// Remembers a record appended to the active file, it is written to the key directory of
+  /// the file once the file is sealed.
+  pub(crate) fn track_keydir_record(&self, key: &[u8], rec_type: LogRecordType, pos: LogRecordPos) {
+    if !self.options.startup_manifest {
+      return;
+    }
+    let entry = LogRecord {
+      key: key.to_vec(),
+      value: pos.encode(),
+      rec_type,
+      timestamp: None,
+    };
+    let mut keydir = self.keydir.lock();
+    keydir.entries.extend_from_slice(&entry.encode());
+    keydir.count += 1;
+  }
+
+  /// Seals the active file, then persists its key directory and manifest entry.
+  pub(crate) fn seal_active_file(&self, active_file: &DataFile) -> Result<()> {
+    let checksum = active_file.seal()?;
+    self.seal_blob_file(active_file.get_file_id())?;
+    let keydir = std::mem::take(&mut *self.keydir.lock());
+    if !self.options.startup_manifest {
+      return Ok(());
+    }
+
+    // the manifest entry is only appended once the key directory is durable
+    let file_id = active_file.get_file_id();
+    let file_dir = self.data_file_dir(file_id);
+    remove_keydir_file(&file_dir, file_id)?;
+    let keydir_file = DataFile::new_keydir_file(&file_dir, file_id)?;
+    keydir_file.write(&keydir.entries)?;
+    keydir_file.sync()?;
+
+    let sealed = SealedFile {
+      size: active_file.get_write_off(),
+      checksum,
+      keydir: Some((keydir.count, crc32fast::hash(&keydir.entries))),
+    };
+    let manifest_file = DataFile::new_manifest_file(&self.options.dir_path)?;
+    manifest_file.write(&manifest_record(file_id, &sealed).encode())?;
+    manifest_file.sync()
+  }
+
+  /// Rewrites the manifest without the entries of data files removed since they were sealed,
+  /// e.g. by a merge. Entries only accumulate otherwise.
+  pub(crate) fn compact_manifest(&self) -> Result<()> {
+    let dir_path = &self.options.dir_path;
+    let (mut sealed_files, entries) = load_manifest(dir_path)?;
+    let old_files = self.old_data_files.read();
+    sealed_files.retain(|file_id, _| old_files.contains_key(file_id));
+    drop(old_files);
+    if sealed_files.len() == entries {
+      return Ok(());
+    }
+
+    remove_file_if_exists(&dir_path.join(MANIFEST_TMP_FILE_NAME))?;
+    let mut file_ids: Vec<_> = sealed_files.keys().copied().collect();
+    file_ids.sort();
+    let mut records = Vec::new();
+    for file_id in file_ids {
+      records.extend_from_slice(&manifest_record(file_id, &sealed_files[&file_id]).encode());
+    }
+    let manifest_file = DataFile::new_manifest_tmp_file(dir_path)?;
+    manifest_file.write(&records)?;
+    manifest_file.sync()?;
+
+    fs::rename(
+      dir_path.join(MANIFEST_TMP_FILE_NAME),
+      dir_path.join(MANIFEST_FILE_NAME),
+    )
+    .map_err(|e| {
+      error!("failed to rename manifest file: {e}");
+      Errors::FailedToRenameFile
+    })?;
+    sync_dir(dir_path).map_err(|e| Errors::data_file_io(DataFileOp::Sync, e))
+  }
+
+  /// Reads the key directory of a sealed data file if the file still matches its manifest
+  /// entry, returns None if the file has to be scanned instead.
+  pub(crate) fn read_keydir(
+    &self,
+    data_file: &DataFile,
+    sealed: Option<&SealedFile>,
+  ) -> Result<Option<Vec<(LogRecord, LogRecordPos)>>> {
+    // legacy entries carry no summary to check the key directory against
+    let Some((sealed, Some((count, crc)))) = sealed.map(|sealed| (sealed, sealed.keydir)) else {
+      return Ok(None);
+    };
+    if data_file.file_size() != sealed.size || data_file.footer_checksum()? != Some(sealed.checksum)
+    {
+      return Ok(None);
+    }
+
+    let file_id = data_file.get_file_id();
+    let file_dir = self.data_file_dir(file_id);
+    if !get_keydir_file_name(&file_dir, file_id).is_file() {
+      return Ok(None);
+    }
+    // a truncated or torn key directory would silently drop keys
+    let keydir_path = get_keydir_file_name(&file_dir, file_id);
+    let content = fs::read(&keydir_path).map_err(|e| Errors::data_file_io(DataFileOp::Read, e))?;
+    if crc32fast::hash(&content) != crc {
+      warn!("key directory of data file {file_id} does not match its checksum, scanning the file");
+      return Ok(None);
+    }
+    let keydir_file = DataFile::new_keydir_file(&file_dir, file_id)?;
+
+    // the whole key directory is decoded before any of it reaches the index
+    let mut records = Vec::new();
+    let mut scanner = keydir_file.scan();
+    loop {
+      let record = match scanner.next_record() {
+        Ok((result, _)) => result.record,
+        Err(Errors::ReadDataFileEOF) => break,
+        Err(e) => {
+          warn!("key directory of data file {file_id} is unreadable, scanning the file: {e}");
+          return Ok(None);
+        }
+      };
+      let pos = match decode_log_record_pos(record.value) {
+        Ok(pos) if pos.file_id == file_id => pos,
+        _ => {
+          warn!("key directory of data file {file_id} is invalid, scanning the file");
+          return Ok(None);
+        }
+      };
+      // expiration times are not kept in the key directory
+      let value = match record.rec_type {
+        LogRecordType::Expire => data_file.read_log_record(pos.offset)?.record.value,
+        _ => Vec::new(),
+      };
+      records.push((
+        LogRecord {
+          key: record.key,
+          value,
+          rec_type: record.rec_type,
+          timestamp: None,
+        },
+        pos,
+      ));
+    }
+    if records.len() != count as usize {
+      warn!("key directory of data file {file_id} misses entries, scanning the file");
+      return Ok(None);
+    }
+    Ok(Some(records))
+  }
+}
+
+fn manifest_record(file_id: u32, sealed: &SealedFile) -> LogRecord {
+  LogRecord {
+    key: file_id.to_be_bytes().to_vec(),
+    value: sealed.encode(),
+    rec_type: LogRecordType::Normal,
+    timestamp: None,
+  }
+}
+
+/// Loads the sealed files recorded in the manifest and the number of entries read, a torn
+/// entry at the tail ends the manifest.
+pub(crate) fn load_manifest<P>(dir_path: P) -> Result<(HashMap<u32, SealedFile>, usize)>
+where
+  P: AsRef<Path>,
+{
+  let mut sealed_files = HashMap::new();
+  if !dir_path.as_ref().join(MANIFEST_FILE_NAME).is_file() {
+    return Ok((sealed_files, 0));
+  }
+
+  let manifest_file = DataFile::new_manifest_file(&dir_path)?;
+  let mut offset = 0;
+  let mut entries = 0;
+  while let Ok(result) = manifest_file.read_log_record(offset) {
+    let (key, value) = (result.record.key, result.record.value);
+    if key.len() != 4 || (value.len() != ENTRY_SIZE && value.len() != LEGACY_ENTRY_SIZE) {
+      return Err(Errors::DatabaseDirectoryCorrupted);
+    }
+    let file_id = u32::from_be_bytes([key[0], key[1], key[2], key[3]]);
+    let mut value = value.as_slice();
+    let sealed = SealedFile {
+      size: value.get_u64(),
+      checksum: value.get_u32(),
+      keydir: (value.len() == 8).then(|| (value.get_u32(), value.get_u32())),
+    };
+    sealed_files.insert(file_id, sealed);
+    offset += result.size as u64;
+    entries += 1;
+  }
+  Ok((sealed_files, entries))
+}
+
+/// Removes the key directory of a data file, if it has one.
+pub(crate) fn remove_keydir_file<P>(dir_path: P, file_id: u32) -> Result<()>
+where
+  P: AsRef<Path>,
+{
+  let file = get_keydir_file_name(dir_path, file_id);
+  if file.is_file() {
+    fs::remove_file(&file).map_err(|e| {
+      error!("failed to remove key directory {}: {e}", file.display());
+      Errors::FailedToRemoveFile
+    })?;
+  }
+  Ok(())
+}
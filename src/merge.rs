@@ -1,32 +1,98 @@
 #![allow(clippy::field_reassign_with_default)]
 use std::{
-  fs,
+  collections::BTreeMap,
+  ffi::OsStr,
+  fs, mem,
   path::{Path, PathBuf},
-  sync::atomic::Ordering,
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    mpsc::{self, Receiver, SyncSender},
+    Arc,
+  },
+  thread,
+  time::{Duration, Instant, SystemTime},
 };
 
-use log::error;
+use bytes::{Buf, BufMut, BytesMut};
+use log::{error, warn};
+use parking_lot::{Condvar, Mutex};
 
 use crate::{
   batch::{log_record_key_with_seq, parse_log_record_key, NON_TXN_SEQ_NO},
   data::{
     data_file::{
-      get_data_file_name, DataFile, DATA_FILE_NAME_SUFFIX, HINT_FILE_NAME,
-      MERGE_FINISHED_FILE_NAME, SEQ_NO_FILE_NAME,
+      get_blob_file_name, get_data_file_dir, get_data_file_name, get_tombstone_file_name, DataFile,
+      BLOB_FILE_NAME_SUFFIX, DATA_FILE_NAME_SUFFIX, FILE_FOOTER_KEY, GARBAGE_MAP_FILE_NAME,
+      HINT_FILE_NAME, HINT_FORMAT_VERSION, HINT_HEADER_KEY, MERGE_FINISHED_FILE_NAME,
+      SEQ_NO_FILE_NAME,
     },
     log_record::{decode_log_record_pos, LogRecord, LogRecordType},
   },
-  db::{Engine, FILE_LOCK_NAME},
-  errors::{Errors, Result},
-  option::{IOManagerType, Options},
+  db::{parse_record_value, Engine, FILE_LOCK_NAME},
+  errors::{DataFileOp, Errors, Result},
+  layout::parse_data_file_id,
+  manifest::remove_keydir_file,
+  option::{IOManagerType, Options, StorageMode},
+  reopen::{remove_close_hint, remove_file_if_exists},
+  slowlog::SlowOp,
   util,
 };
 
 const MERGE_DIR_NAME: &str = "merge";
 const MERGE_FIN_KEY: &[u8] = "merge.finished".as_bytes();
+const MERGE_MANIFEST_KEY: &[u8] = "merge.manifest".as_bytes();
+
+// file id, size and footer checksum of a merged data file in the manifest
+const MANIFEST_ENTRY_SIZE: usize = 4 + 8 + 4;
+
+// written to the merge dir once the merged data files are removed, the merge output is being
+// moved into the database directory from then on
+const MERGE_APPLYING_FILE_NAME: &str = "merge-applying";
+
+// files losing a conflict with the merge output are moved here instead of removed
+const CORRUPT_DIR_NAME: &str = "corrupt";
+
+// live records are rewritten in runs of this many records or bytes, each run appended to the
+// merge output and the hint file with a vectored write
+const MERGE_RUN_RECORDS: usize = 128;
+const MERGE_RUN_BYTES: usize = 1024 * 1024;
+
+// scanned bytes of a parallel merge waiting to be written, see `MergeBudget`
+const MERGE_PIPELINE_BYTES: usize = 64 * 1024 * 1024;
+
+/// What a merge started now would gain and cost, see [`Engine::estimate_merge_gain`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MergeEstimate {
+  /// Dead bytes of the data files, freed by the merge
+  pub reclaimable_bytes: u64,
+
+  /// Bytes of the data files read by the merge
+  pub read_bytes: u64,
+
+  /// Live bytes rewritten by the merge
+  pub write_bytes: u64,
+
+  /// Expected duration, from the throughput of the merges since open and the merge IO limit,
+  /// `None` if there is neither
+  pub duration: Option<Duration>,
+
+  /// Whether the merge threshold or policy asks for a merge
+  pub due: bool,
+}
 
 impl Engine {
+  #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
   pub fn merge(&self) -> Result<()> {
+    self.check_open()?;
+    if self.reader.is_some() {
+      return Err(Errors::ReadOnlyEngine);
+    }
+    if self.options.storage_mode == StorageMode::Memory {
+      return Err(Errors::MemoryModeUnsupported);
+    }
+    if !self.options.merge_scheduler.allows(SystemTime::now()) {
+      return Err(Errors::MergeOutsideWindow);
+    }
     if self.is_engine_empty() {
       return Ok(());
     }
@@ -35,11 +101,12 @@ impl Engine {
     if lock.is_none() {
       return Err(Errors::MergeInProgress);
     }
+    let mut timer = self.slow_op_timer(SlowOp::Merge);
+    let started = Instant::now();
 
     let reclaim_size = self.reclaim_size.load(Ordering::SeqCst);
     let total_size = util::file::dir_disk_size(&self.options.dir_path);
-    let ratio = reclaim_size as f32 / total_size as f32;
-    if ratio < self.options.file_merge_threshold {
+    if !self.merge_due(reclaim_size, total_size)? {
       return Err(Errors::MergeThresholdUnreached);
     }
 
@@ -47,11 +114,20 @@ impl Engine {
     if total_size - reclaim_size as u64 >= available_space {
       return Err(Errors::MergeNoEnoughSpace);
     }
+    // the merge output is written before the merged files are removed
+    let reserve = self.options.reserved_disk_bytes;
+    let merge_output = total_size.saturating_sub(reclaim_size as u64);
+    if reserve > 0
+      && util::file::available_disk_space_of(&self.options.dir_path)
+        .is_some_and(|free| free.saturating_sub(merge_output) < reserve)
+    {
+      return Err(Errors::DiskFull);
+    }
 
-    let merge_path = get_merge_path(&self.options.dir_path);
+    let merge_path = get_merge_path(&self.options.dir_path)?;
 
     if merge_path.is_dir() {
-      fs::remove_dir_all(merge_path.clone()).unwrap();
+      remove_dir(&merge_path)?;
     }
 
     if let Err(e) = fs::create_dir(merge_path.clone()) {
@@ -59,20 +135,122 @@ impl Engine {
       return Err(Errors::FailedToCreateDatabaseDir);
     }
 
+    timer.phase("prepare");
     let merge_files = self.rotate_merge_files()?;
+    timer.phase("rotate");
 
     let mut merge_db_opts = Options::default();
     merge_db_opts.dir_path = merge_path.clone();
     merge_db_opts.data_file_size = self.options.data_file_size;
+    merge_db_opts.use_io_uring = self.options.use_io_uring;
+    merge_db_opts.encryption_key = self.options.encryption_key;
+    merge_db_opts.blob_threshold = self.options.blob_threshold;
     let merge_db = Engine::open(merge_db_opts)?;
 
-    let hint_file = DataFile::new_hint_file(&merge_path)?;
+    let hint_file = DataFile::new_hint_file(&merge_path)?.with_cipher(self.cipher.as_ref());
+    hint_file.write_hint_header()?;
+    self.rewrite_merge_files(&merge_files, &merge_db, &hint_file)?;
 
+    merge_db.sync_active_blob()?;
+    hint_file.seal()?;
+    timer.phase("rewrite");
+
+    let non_merge_file_id = match merge_files.last() {
+      Some(file) => file.get_file_id() + 1,
+      None => return Err(Errors::DataFileNotFound),
+    };
+    let manifest = merge_manifest(&merge_db)?;
+    self.finish_merge(&merge_path, non_merge_file_id, &manifest)?;
+
+    // merges read every record of the merged files and rewrite the live ones
+    let merged_bytes = merge_files.iter().map(|file| file.file_size()).sum();
+    self.io_stats.record_read(merged_bytes, 0);
+    let merge_output = merge_db.io_stats.bytes_written() + hint_file.file_size();
+    self.io_stats.record_write(merge_output, 0);
+    self
+      .io_stats
+      .record_merge(merged_bytes + merge_output, started.elapsed());
+
+    // stale bytes of the merged files are dropped on the next open, stop counting them
+    // towards the reclaim backlog once the merge output is durable
+    self.reclaim_size.fetch_sub(reclaim_size, Ordering::SeqCst);
+    self.garbage.clear_below(non_merge_file_id);
+    self.refresh_quota_usage()?;
+    self.invalidate_disk_size();
+    timer.phase("finish");
+    self.finish_slow_op(timer, None);
+
+    Ok(())
+  }
+
+  /// Estimates the disk space a merge started now would reclaim and how long it would take,
+  /// so schedulers can decide whether to merge now.
+  ///
+  /// The merge reads every data file and rewrites the bytes not accounted as dead.
+  pub fn estimate_merge_gain(&self) -> Result<MergeEstimate> {
+    self.check_open()?;
+    if self.options.storage_mode == StorageMode::Memory {
+      return Err(Errors::MemoryModeUnsupported);
+    }
+
+    let files = self.data_files();
+    let read_bytes: u64 = files.iter().map(|file| file.size).sum();
+    let reclaimable_bytes: u64 = files.iter().map(|file| file.dead_bytes).sum();
+    let write_bytes = read_bytes.saturating_sub(reclaimable_bytes);
+
+    let mut duration = self
+      .io_stats
+      .merge_throughput()
+      .filter(|bytes_per_sec| *bytes_per_sec > 0)
+      .map(|bytes_per_sec| {
+        Duration::from_secs_f64((read_bytes + write_bytes) as f64 / bytes_per_sec as f64)
+      });
+    // the IO limit bounds the reads however fast earlier merges were
+    let io_limit = self.merge_limiter.rate();
+    if io_limit > 0 {
+      let limited = Duration::from_secs_f64(read_bytes as f64 / io_limit as f64);
+      duration = Some(duration.map_or(limited, |duration| duration.max(limited)));
+    }
+
+    let total_size = self.disk_size(true);
+    let due = total_size > 0 && self.merge_due(reclaimable_bytes as usize, total_size)?;
+    Ok(MergeEstimate {
+      reclaimable_bytes,
+      read_bytes,
+      write_bytes,
+      duration,
+      due,
+    })
+  }
+
+  /// Appends the live records of the merged files to the merge engine and their positions
+  /// to the hint file.
+  #[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip_all, fields(files = merge_files.len()))
+  )]
+  fn rewrite_merge_files(
+    &self,
+    merge_files: &[DataFile],
+    merge_db: &Engine,
+    hint_file: &DataFile,
+  ) -> Result<()> {
+    let scheduler = &self.options.merge_scheduler;
+    if scheduler.read_threads > 1 || scheduler.filter_threads > 1 {
+      return self.rewrite_merge_files_parallel(merge_files, merge_db, hint_file);
+    }
+
+    let mut run = Vec::new();
+    let mut run_bytes = 0;
     for data_file in merge_files.iter() {
-      let mut offset = 0;
+      // merge reads every record, the scanner reads ahead in large batches
+      let mut scanner = data_file.scan();
       loop {
-        let (mut log_record, size) = match data_file.read_log_record(offset) {
-          Ok(result) => (result.record, result.size),
+        let (log_record, offset) = match scanner.next_record() {
+          Ok((result, offset)) => {
+            self.throttle_merge_read(result.size);
+            (result.record, offset)
+          }
           Err(e) => {
             if e == Errors::ReadDataFileEOF {
               break;
@@ -81,33 +259,225 @@ impl Engine {
           }
         };
 
-        let (real_key, _) = parse_log_record_key(log_record.key.clone());
-        if let Some(index_pos) = self.index.get(real_key.clone()) {
-          if index_pos.file_id == data_file.get_file_id() && index_pos.offset == offset {
-            log_record.key = log_record_key_with_seq(real_key.clone(), NON_TXN_SEQ_NO);
-            let log_record_pos = merge_db.append_log_record(&mut log_record)?;
-            hint_file.write_hint_record(real_key.clone(), log_record_pos)?;
+        let live = self.live_merge_record(data_file.get_file_id(), log_record, offset)?;
+        let Some((real_key, log_record)) = live else {
+          continue;
+        };
+        run_bytes += log_record.key.len() + log_record.value.len();
+        run.push((real_key, log_record));
+        if run.len() >= MERGE_RUN_RECORDS || run_bytes >= MERGE_RUN_BYTES {
+          write_merge_run(merge_db, hint_file, &mut run)?;
+          run_bytes = 0;
+        }
+      }
+    }
+    write_merge_run(merge_db, hint_file, &mut run)
+  }
+
+  /// Rewrites the merged files as a pipeline: `read_threads` scan the files in chunks,
+  /// `filter_threads` keep the live records of each chunk and the merging thread writes the
+  /// chunks in the order of the merged files, the output matches a merge on one thread.
+  fn rewrite_merge_files_parallel(
+    &self,
+    merge_files: &[DataFile],
+    merge_db: &Engine,
+    hint_file: &DataFile,
+  ) -> Result<()> {
+    let scheduler = &self.options.merge_scheduler;
+    let readers = scheduler.read_threads.clamp(1, merge_files.len().max(1));
+    let filters = scheduler.filter_threads.max(1);
+    let budget = MergeBudget::default();
+    let next_file = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+      let result = (|| {
+        let (scanned_sender, scanned_receiver) = mpsc::sync_channel(readers + filters);
+        let (live_sender, live_receiver) = mpsc::sync_channel(filters * 2);
+
+        for _ in 0..readers {
+          let scanned_sender: SyncSender<Result<ScannedChunk>> = scanned_sender.clone();
+          let (budget, next_file) = (&budget, &next_file);
+          spawn_merge_thread(scope, "flash-kv-merge-reader", move || {
+            self.scan_merge_files(merge_files, next_file, budget, &scanned_sender)
+          })?;
+        }
+        drop(scanned_sender);
+
+        // the receiver is gone with the last filter, unblocking the readers
+        let scanned_receiver = Arc::new(Mutex::new(scanned_receiver));
+        for _ in 0..filters {
+          let live_sender: SyncSender<Result<LiveChunk>> = live_sender.clone();
+          let scanned_receiver = scanned_receiver.clone();
+          spawn_merge_thread(scope, "flash-kv-merge-filter", move || loop {
+            let Ok(scanned) = scanned_receiver.lock().recv() else {
+              return;
+            };
+            let live = scanned.and_then(|chunk| self.filter_merge_chunk(chunk));
+            if live_sender.send(live).is_err() {
+              return;
+            }
+          })?;
+        }
+        drop((live_sender, scanned_receiver));
+
+        write_merge_chunks(
+          merge_files.len(),
+          merge_db,
+          hint_file,
+          live_receiver,
+          &budget,
+        )
+      })();
+      // wakes readers waiting for budget once the writer is done or failed
+      budget.stop();
+      result
+    })
+  }
+
+  /// Scans the merged files handed out by `next_file` in chunks, until every file is taken or
+  /// the pipeline stopped.
+  fn scan_merge_files(
+    &self,
+    merge_files: &[DataFile],
+    next_file: &AtomicUsize,
+    budget: &MergeBudget,
+    sender: &SyncSender<Result<ScannedChunk>>,
+  ) {
+    let send = |chunk: ScannedChunk| {
+      budget.acquire(chunk.file, chunk.bytes) && sender.send(Ok(chunk)).is_ok()
+    };
+    loop {
+      let file = next_file.fetch_add(1, Ordering::SeqCst);
+      let Some(data_file) = merge_files.get(file) else {
+        return;
+      };
+      let mut chunk = MergeChunk::new(file, data_file.get_file_id(), 0);
+      let mut scanner = data_file.scan();
+      loop {
+        match scanner.next_record() {
+          Ok((result, offset)) => {
+            self.throttle_merge_read(result.size);
+            chunk.bytes += result.size;
+            chunk.records.push((result.record, offset));
+          }
+          Err(Errors::ReadDataFileEOF) => break,
+          Err(e) => {
+            let _ = sender.send(Err(e));
+            return;
           }
         }
-        offset += size as u64;
+        if chunk.records.len() >= MERGE_RUN_RECORDS || chunk.bytes >= MERGE_RUN_BYTES {
+          let next = MergeChunk::new(file, chunk.file_id, chunk.chunk + 1);
+          if !send(mem::replace(&mut chunk, next)) {
+            return;
+          }
+        }
+      }
+      chunk.last = true;
+      if !send(chunk) {
+        return;
+      }
+    }
+  }
+
+  // keeps the live records of a scanned chunk
+  fn filter_merge_chunk(&self, chunk: ScannedChunk) -> Result<LiveChunk> {
+    let mut live = MergeChunk::new(chunk.file, chunk.file_id, chunk.chunk);
+    live.last = chunk.last;
+    live.bytes = chunk.bytes;
+    for (log_record, offset) in chunk.records {
+      if let Some(record) = self.live_merge_record(chunk.file_id, log_record, offset)? {
+        live.records.push(record);
       }
     }
+    Ok(live)
+  }
 
-    merge_db.sync()?;
-    hint_file.sync()?;
+  /// Returns the record read at `offset` of the merged file `file_id` as written to the merge
+  /// output with its key, `None` if the index points elsewhere.
+  fn live_merge_record(
+    &self,
+    file_id: u32,
+    mut log_record: LogRecord,
+    offset: u64,
+  ) -> Result<Option<(Vec<u8>, LogRecord)>> {
+    if log_record.rec_type == LogRecordType::FileFooter {
+      return Ok(None);
+    }
 
-    let non_merge_file_id = merge_files.last().unwrap().get_file_id() + 1;
-    let merge_fin_file = DataFile::new_merge_fin_file(&merge_path)?;
+    let (real_key, _) = parse_log_record_key(log_record.key.clone())?;
+
+    // expire records are kept while they hold the current expiration of their key
+    let live_pos = match log_record.rec_type {
+      LogRecordType::Expire => self.expiry.get(&real_key).map(|(_, pos)| pos),
+      _ => self.index.get(real_key.clone()),
+    };
+    let Some(live_pos) = live_pos else {
+      return Ok(None);
+    };
+    if live_pos.file_id != file_id || live_pos.offset != offset {
+      return Ok(None);
+    }
+
+    // live blobs are written to the blob files of the merge output, the others are removed
+    // with the merged files
+    if log_record.rec_type == LogRecordType::Blob {
+      let value = self.read_blob(file_id, &log_record.value, Some(self.options.crc_impl))?;
+      self.throttle_merge_read(value.len());
+      log_record.value = value.to_vec();
+      log_record.rec_type = LogRecordType::Normal;
+    }
+
+    log_record.key = log_record_key_with_seq(&real_key, NON_TXN_SEQ_NO);
+    Ok(Some((real_key, log_record)))
+  }
+
+  /// Writes the merge finished file, merged files below `non_merge_file_id` are replaced by
+  /// the merge output on the next open. The manifest follows the non merge file id, older
+  /// finished files hold the id only.
+  #[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+      level = "debug",
+      skip_all,
+      fields(non_merge_file_id = non_merge_file_id)
+    )
+  )]
+  fn finish_merge(
+    &self,
+    merge_path: &Path,
+    non_merge_file_id: u32,
+    manifest: &[(u32, u64, u32)],
+  ) -> Result<()> {
+    let merge_fin_file = DataFile::new_merge_fin_file(merge_path)?;
     let merge_fin_record = LogRecord {
       key: MERGE_FIN_KEY.to_vec(),
       value: non_merge_file_id.to_string().into_bytes(),
       rec_type: LogRecordType::Normal,
+      timestamp: None,
+    };
+    let mut entries = BytesMut::with_capacity(manifest.len() * MANIFEST_ENTRY_SIZE);
+    for (file_id, size, checksum) in manifest {
+      entries.put_u32(*file_id);
+      entries.put_u64(*size);
+      entries.put_u32(*checksum);
+    }
+    let manifest_record = LogRecord {
+      key: MERGE_MANIFEST_KEY.to_vec(),
+      value: entries.to_vec(),
+      rec_type: LogRecordType::Normal,
+      timestamp: None,
     };
-    let enc_record = merge_fin_record.encode();
-    merge_fin_file.write(&enc_record)?;
+    merge_fin_file.write_vectored(&[merge_fin_record.encode(), manifest_record.encode()])?;
     merge_fin_file.sync()?;
 
-    Ok(())
+    // the merge output only replaces the merged files once its directory entries are durable
+    util::file::sync_dir(merge_path)
+      .and_then(|_| match merge_path.parent() {
+        Some(parent) => util::file::sync_dir(parent),
+        None => Ok(()),
+      })
+      .map_err(|e| Errors::data_file_io(DataFileOp::Sync, e))
   }
 
   fn is_engine_empty(&self) -> bool {
@@ -116,6 +486,7 @@ impl Engine {
     active_file.get_write_off() == 0 && old_files.is_empty()
   }
 
+  #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
   fn rotate_merge_files(&self) -> Result<Vec<DataFile>> {
     let mut merge_file_ids = Vec::new();
     let mut old_files = self.old_data_files.write();
@@ -124,21 +495,16 @@ impl Engine {
     }
 
     let mut active_file = self.active_data_file.write();
+    self.check_open()?;
+    // the records of pending transactions are not in the index, they would be dropped
+    self.check_no_prepared_txns()?;
 
-    active_file.sync()?;
+    self.seal_active_file(&active_file)?;
     let active_file_id = active_file.get_file_id();
-    let new_active_file = DataFile::new(
-      &self.options.dir_path,
-      active_file_id + 1,
-      IOManagerType::StandardFileIO,
-    )?;
-    *active_file = new_active_file;
-
-    let old_file = DataFile::new(
-      &self.options.dir_path,
-      active_file_id,
-      IOManagerType::StandardFileIO,
-    )?;
+    *active_file = self.new_active_data_file(active_file_id + 1)?;
+    self.set_tombstone_merge_boundary(active_file_id + 1);
+
+    let old_file = self.open_old_data_file(active_file_id)?;
     old_files.insert(active_file_id, old_file);
 
     merge_file_ids.push(active_file_id);
@@ -147,29 +513,31 @@ impl Engine {
 
     let mut merge_files = Vec::new();
     for file_id in merge_file_ids {
-      let data_file = DataFile::new(
-        &self.options.dir_path,
-        file_id,
-        IOManagerType::StandardFileIO,
-      )?;
-      merge_files.push(data_file);
+      merge_files.push(self.open_old_data_file(file_id)?);
     }
 
     Ok(merge_files)
   }
 
-  pub(crate) fn load_index_from_hint_file(&self) -> Result<()> {
+  /// Loads the index of the merged data files from the merge hint file, returns false if
+  /// there is no valid hint and the merged data files have to be scanned instead.
+  pub(crate) fn load_index_from_hint_file(&self) -> Result<bool> {
     let hint_file_name = self.options.dir_path.join(HINT_FILE_NAME);
 
     if !hint_file_name.is_file() {
-      return Ok(());
+      return Ok(false);
     }
 
-    let hint_file = DataFile::new_hint_file(&self.options.dir_path)?;
-    let mut offset = 0;
+    let hint_file =
+      DataFile::new_hint_file(&self.options.dir_path)?.with_cipher(self.cipher.as_ref());
+    if !validate_hint_file(&hint_file) {
+      warn!("merge hint file is invalid, scanning the merged data files");
+      return Ok(false);
+    }
+    let mut scanner = hint_file.scan();
     loop {
-      let (log_record, size) = match hint_file.read_log_record(offset) {
-        Ok(result) => (result.record, result.size),
+      let log_record = match scanner.next_record() {
+        Ok((result, _)) => result.record,
         Err(e) => {
           if e == Errors::ReadDataFileEOF {
             break;
@@ -178,31 +546,317 @@ impl Engine {
         }
       };
 
-      let log_record_pos = decode_log_record_pos(log_record.value);
-      self.index.put(log_record.key, log_record_pos);
+      // header and footer of the hint file
+      if log_record.rec_type == LogRecordType::FileFooter {
+        continue;
+      }
+      let log_record_pos = decode_log_record_pos(log_record.value)?;
+      match log_record.rec_type {
+        LogRecordType::Expire => {
+          let expire_record = self.read_log_record_at(&log_record_pos)?;
+          self.replay_expire(log_record.key, &expire_record.value, log_record_pos)?;
+        }
+        _ => {
+          self.index.try_put(log_record.key, log_record_pos)?;
+        }
+      }
+    }
 
-      offset += size as u64;
+    Ok(true)
+  }
+}
+
+/// Records of a merged file passed between the merge threads, chunks of a file are numbered
+/// from 0 and the last one is flagged.
+struct MergeChunk<T> {
+  file: usize, // index of the data file in the merged files
+  file_id: u32,
+  chunk: usize,
+  last: bool,
+  bytes: usize, // scanned bytes, counted against the pipeline budget until written
+  records: Vec<T>,
+}
+
+type ScannedChunk = MergeChunk<(LogRecord, u64)>;
+type LiveChunk = MergeChunk<(Vec<u8>, LogRecord)>;
+
+impl<T> MergeChunk<T> {
+  fn new(file: usize, file_id: u32, chunk: usize) -> Self {
+    Self {
+      file,
+      file_id,
+      chunk,
+      last: false,
+      bytes: 0,
+      records: Vec::new(),
     }
+  }
+}
 
-    Ok(())
+/// Bounds the scanned bytes waiting to be written. The file being written is scanned past the
+/// bound, the writer never waits for a chunk held back by it.
+#[derive(Default)]
+struct MergeBudget {
+  state: Mutex<MergeBudgetState>,
+  freed: Condvar,
+}
+
+#[derive(Default)]
+struct MergeBudgetState {
+  writing_file: usize,
+  bytes: usize,
+  stopped: bool,
+}
+
+impl MergeBudget {
+  // waits until `bytes` of `file` fit, false once the pipeline stopped
+  fn acquire(&self, file: usize, bytes: usize) -> bool {
+    let mut state = self.state.lock();
+    while !state.stopped && file != state.writing_file && state.bytes >= MERGE_PIPELINE_BYTES {
+      self.freed.wait(&mut state);
+    }
+    state.bytes += bytes;
+    !state.stopped
+  }
+
+  fn release(&self, bytes: usize, writing_file: usize) {
+    let mut state = self.state.lock();
+    state.bytes = state.bytes.saturating_sub(bytes);
+    state.writing_file = writing_file;
+    self.freed.notify_all();
+  }
+
+  fn stop(&self) {
+    self.state.lock().stopped = true;
+    self.freed.notify_all();
+  }
+}
+
+fn spawn_merge_thread<'scope, 'env, F>(
+  scope: &'scope thread::Scope<'scope, 'env>,
+  name: &str,
+  f: F,
+) -> Result<()>
+where
+  F: FnOnce() + Send + 'scope,
+{
+  thread::Builder::new()
+    .name(name.to_string())
+    .spawn_scoped(scope, f)
+    .map(|_| ())
+    .map_err(|e| {
+      warn!("failed to spawn merge thread: {e}");
+      Errors::FailedToStartBackgroundTask
+    })
+}
+
+// writes the live chunks in the order of the merged files, chunks arriving early wait
+fn write_merge_chunks(
+  files: usize,
+  merge_db: &Engine,
+  hint_file: &DataFile,
+  receiver: Receiver<Result<LiveChunk>>,
+  budget: &MergeBudget,
+) -> Result<()> {
+  let mut pending = BTreeMap::new();
+  let (mut file, mut chunk) = (0, 0);
+  while file < files {
+    let Ok(received) = receiver.recv() else {
+      return Err(Errors::MergeThreadStopped);
+    };
+    let received = received?;
+    pending.insert((received.file, received.chunk), received);
+    while let Some(mut next) = pending.remove(&(file, chunk)) {
+      write_merge_run(merge_db, hint_file, &mut next.records)?;
+      (file, chunk) = match next.last {
+        true => (file + 1, 0),
+        false => (file, chunk + 1),
+      };
+      budget.release(next.bytes, file);
+    }
+  }
+  Ok(())
+}
+
+// appends the records of a run to the merge engine and their hints to the hint file
+fn write_merge_run(
+  merge_db: &Engine,
+  hint_file: &DataFile,
+  run: &mut Vec<(Vec<u8>, LogRecord)>,
+) -> Result<()> {
+  if run.is_empty() {
+    return Ok(());
+  }
+  let (keys, records): (Vec<_>, Vec<_>) = run.drain(..).unzip();
+  let positions = merge_db.append_log_records(&records)?;
+  let hints = keys
+    .into_iter()
+    .zip(records.iter())
+    .zip(positions)
+    .map(|((key, record), pos)| {
+      let hint_type = match record.rec_type {
+        LogRecordType::Expire => LogRecordType::Expire,
+        _ => LogRecordType::Normal,
+      };
+      hint_file.encode_hint_record(key, pos, hint_type)
+    })
+    .collect::<Result<Vec<_>>>()?;
+  hint_file.write_vectored(&hints)?;
+  Ok(())
+}
+
+// file id, size and footer checksum of every non empty data file of the merge output,
+// the merge output is immutable and the last merged file is sealed as well
+fn merge_manifest(merge_db: &Engine) -> Result<Vec<(u32, u64, u32)>> {
+  let mut manifest = Vec::new();
+  for (file_id, file) in merge_db.old_data_files.read().iter() {
+    manifest.push(manifest_entry(*file_id, file)?);
+  }
+  let active_file = merge_db.active_data_file.read();
+  if active_file.get_write_off() > 0 {
+    // the checksum is the one tracked while the file was written
+    let checksum = active_file.seal()?;
+    manifest.push((active_file.get_file_id(), active_file.file_size(), checksum));
+  }
+  manifest.sort();
+  Ok(manifest)
+}
+
+// an unsealed file has no checksum to record or check against
+fn manifest_entry(file_id: u32, file: &DataFile) -> Result<(u32, u64, u32)> {
+  match file.footer_checksum()? {
+    Some(checksum) => Ok((file_id, file.file_size(), checksum)),
+    None => Err(Errors::MergeManifestMismatch),
+  }
+}
+
+// reads the manifest following the non merge file id, None for finished files written
+// before the manifest was added
+fn read_merge_manifest(
+  merge_fin_file: &DataFile,
+  offset: u64,
+) -> Result<Option<Vec<(u32, u64, u32)>>> {
+  let record = match merge_fin_file.read_log_record(offset) {
+    Ok(result) => result.record,
+    Err(Errors::ReadDataFileEOF) => return Ok(None),
+    Err(e) => return Err(e),
+  };
+  if record.key != MERGE_MANIFEST_KEY || record.value.len() % MANIFEST_ENTRY_SIZE != 0 {
+    return Err(Errors::MergeManifestMismatch);
+  }
+  let mut entries = record.value.as_slice();
+  let mut manifest = Vec::new();
+  while entries.has_remaining() {
+    manifest.push((entries.get_u32(), entries.get_u64(), entries.get_u32()));
+  }
+  Ok(Some(manifest))
+}
+
+// checks every file of the manifest against the merge output, a file already moved by an
+// interrupted apply is looked up in the database directory
+fn merge_output_matches(
+  dir_path: &Path,
+  merge_path: &Path,
+  manifest: &[(u32, u64, u32)],
+  files_per_subdir: u32,
+) -> bool {
+  manifest.iter().all(|&(file_id, size, checksum)| {
+    let mut file_dir = merge_path.to_path_buf();
+    if !get_data_file_name(&file_dir, file_id).is_file() {
+      file_dir = get_data_file_dir(dir_path, file_id, files_per_subdir);
+    }
+    if !get_data_file_name(&file_dir, file_id).is_file() {
+      warn!("merge output {file_id} is missing");
+      return false;
+    }
+    let entry = DataFile::new(&file_dir, file_id, IOManagerType::StandardFileIO)
+      .and_then(|file| manifest_entry(file_id, &file));
+    match entry {
+      Ok(entry) if entry == (file_id, size, checksum) => true,
+      _ => {
+        warn!("merge output {file_id} does not match the merge manifest");
+        false
+      }
+    }
+  })
+}
+
+// checks the merge hint file before it is replayed: every record must be intact, a hint
+// starting with a header must also end with a footer matching its checksum and record count
+fn validate_hint_file(hint_file: &DataFile) -> bool {
+  let mut scanner = hint_file.scan();
+  let mut versioned = false;
+  let mut records = 0;
+  let mut footer_found = false;
+  loop {
+    let log_record = match scanner.next_record() {
+      Ok((result, _)) => result.record,
+      Err(Errors::ReadDataFileEOF) => break,
+      Err(e) => {
+        warn!("invalid merge hint record: {e}");
+        return false;
+      }
+    };
+    if footer_found {
+      warn!("merge hint record after the footer");
+      return false;
+    }
+
+    match log_record.rec_type {
+      LogRecordType::FileFooter if records == 0 && log_record.key == HINT_HEADER_KEY => {
+        if log_record.value != [HINT_FORMAT_VERSION] {
+          warn!("unknown merge hint version {:?}", log_record.value);
+          return false;
+        }
+        versioned = true;
+      }
+      LogRecordType::FileFooter if versioned && log_record.key == FILE_FOOTER_KEY => {
+        footer_found = true;
+        continue;
+      }
+      _ if decode_log_record_pos(log_record.value).is_err() => {
+        warn!("invalid merge hint position");
+        return false;
+      }
+      _ => {}
+    }
+    records += 1;
   }
+
+  if !versioned {
+    return true;
+  }
+  footer_found
+    && matches!(hint_file.footer_record_count(), Ok(Some(count)) if count == records)
+    && matches!(hint_file.verify_footer(), Ok(true))
 }
 
-fn get_merge_path<P>(dir_path: P) -> PathBuf
+pub(crate) fn get_merge_path<P>(dir_path: P) -> Result<PathBuf>
 where
   P: AsRef<Path>,
 {
-  let file_name = dir_path.as_ref().file_name().unwrap();
-  let merge_name = format!("{}-{}", file_name.to_str().unwrap(), MERGE_DIR_NAME);
-  let parent = dir_path.as_ref().parent().unwrap();
-  parent.to_path_buf().join(merge_name)
+  let file_name = dir_path
+    .as_ref()
+    .file_name()
+    .and_then(|name| name.to_str())
+    .ok_or(Errors::InvalidDirPath)?;
+  let merge_name = format!("{}-{}", file_name, MERGE_DIR_NAME);
+  let parent = dir_path.as_ref().parent().ok_or(Errors::InvalidDirPath)?;
+  Ok(parent.to_path_buf().join(merge_name))
+}
+
+pub(crate) fn remove_dir(path: &Path) -> Result<()> {
+  fs::remove_dir_all(path).map_err(|e| {
+    error!("failed to remove dir {}: {e}", path.display());
+    Errors::FailedToRemoveFile
+  })
 }
 
-pub(crate) fn load_merge_files<P>(dir_path: P) -> Result<()>
+pub(crate) fn load_merge_files<P>(dir_path: P, files_per_subdir: u32) -> Result<()>
 where
   P: AsRef<Path>,
 {
-  let merge_path = get_merge_path(&dir_path);
+  let merge_path = get_merge_path(&dir_path)?;
   if !merge_path.is_dir() {
     return Ok(());
   }
@@ -217,12 +871,22 @@ where
 
   let mut merge_file_names = Vec::new();
   let mut merge_finished = false;
+  let mut applying = false;
   for file in dir.flatten() {
     let file_os_str = file.file_name();
-    let file_name = file_os_str.to_str().unwrap();
+    let Some(file_name) = file_os_str.to_str() else {
+      continue;
+    };
 
-    if file_name.ends_with(MERGE_FINISHED_FILE_NAME) {
+    // moved last, see below
+    if file_name == MERGE_FINISHED_FILE_NAME {
       merge_finished = true;
+      continue;
+    }
+
+    if file_name == MERGE_APPLYING_FILE_NAME {
+      applying = true;
+      continue;
     }
 
     if file_name.ends_with(SEQ_NO_FILE_NAME) {
@@ -233,7 +897,14 @@ where
       continue;
     }
 
-    let meta = file.metadata().unwrap();
+    // the garbage of the merge output is not tracked
+    if file_name.starts_with(GARBAGE_MAP_FILE_NAME) {
+      continue;
+    }
+
+    let meta = file
+      .metadata()
+      .map_err(|_| Errors::FailedToReadDatabaseDir)?;
     if file_name.ends_with(DATA_FILE_NAME_SUFFIX) && meta.len() == 0 {
       continue;
     }
@@ -242,39 +913,159 @@ where
   }
 
   if !merge_finished {
-    fs::remove_dir_all(merge_path.clone()).unwrap();
+    remove_dir(&merge_path)?;
     return Ok(());
   }
-
   let merge_fin_file = DataFile::new_merge_fin_file(&merge_path)?;
   let merge_fin_record = merge_fin_file.read_log_record(0)?;
-  let v = String::from_utf8(merge_fin_record.record.value).unwrap();
-  let non_merge_file_id = v.parse::<u32>().unwrap();
+  let manifest = read_merge_manifest(&merge_fin_file, merge_fin_record.size as u64)?;
+  let non_merge_file_id: u32 = parse_record_value(merge_fin_record.record.value)?;
+
+  // a torn merge output is dropped while the merged files are still in place, once they
+  // are removed it is all that is left of them
+  if let Some(manifest) = manifest {
+    if !merge_output_matches(dir_path.as_ref(), &merge_path, &manifest, files_per_subdir) {
+      if applying {
+        return Err(Errors::MergeManifestMismatch);
+      }
+      warn!("merge output is torn, keeping the merged data files");
+      return remove_dir(&merge_path);
+    }
+  }
 
-  for fid in 0..non_merge_file_id {
-    let file = get_data_file_name(&dir_path, fid);
-    if file.is_file() {
-      fs::remove_file(file).unwrap();
+  // the close hint and the garbage map point into the data files about to be replaced
+  remove_close_hint(&dir_path)?;
+  remove_file_if_exists(&dir_path.as_ref().join(GARBAGE_MAP_FILE_NAME))?;
+
+  // after a failure while moving the merge output, the data files below the non merge file
+  // id may be merge output already, they are only removed before the first move
+  if !applying {
+    for fid in 0..non_merge_file_id {
+      // key directories, tombstone sidecars and blob files belong to the data files replaced
+      // by the merge
+      let file_dir = get_data_file_dir(&dir_path, fid, files_per_subdir);
+      remove_keydir_file(&file_dir, fid)?;
+      remove_file_if_exists(&get_tombstone_file_name(&file_dir, fid))?;
+      remove_file_if_exists(&get_blob_file_name(&file_dir, fid))?;
+      let file = get_data_file_name(&file_dir, fid);
+      if file.is_file() {
+        fs::remove_file(&file).map_err(|e| {
+          error!("failed to remove merged data file {}: {e}", file.display());
+          Errors::FailedToRemoveFile
+        })?;
+      }
     }
+    fs::File::create(merge_path.join(MERGE_APPLYING_FILE_NAME))
+      .and_then(|file| file.sync_all())
+      .map_err(|e| Errors::data_file_io(DataFileOp::Open, e))?;
   }
 
   for file_name in merge_file_names {
-    let src_path = merge_path.join(&file_name);
-    let dst_path = dir_path.as_ref().join(&file_name);
-    fs::rename(src_path, dst_path).unwrap();
+    move_merge_file(dir_path.as_ref(), &merge_path, &file_name, files_per_subdir)?;
   }
 
-  fs::remove_dir_all(merge_path.clone()).unwrap();
+  // the merge is applied once the finished file is in place, a failure before leaves it in
+  // the merge dir and the next open moves the rest of the output
+  let fin_path = merge_path.join(MERGE_FINISHED_FILE_NAME);
+  fs::rename(&fin_path, dir_path.as_ref().join(MERGE_FINISHED_FILE_NAME)).map_err(|e| {
+    error!("failed to move merge file {}: {e}", fin_path.display());
+    Errors::FailedToRenameFile
+  })?;
+
+  remove_dir(&merge_path)?;
 
   Ok(())
 }
 
+// moves a file of the merge output into the database directory. A data file whose id is
+// taken, e.g. by a copy left behind by an interrupted move, replaces the other file if its
+// footer verifies and is quarantined otherwise
+fn move_merge_file(
+  dir_path: &Path,
+  merge_path: &Path,
+  file_name: &OsStr,
+  files_per_subdir: u32,
+) -> Result<()> {
+  let src_path = merge_path.join(file_name);
+  let name = file_name.to_str().unwrap_or_default();
+  let file_id = Some(name)
+    .filter(|name| name.ends_with(DATA_FILE_NAME_SUFFIX))
+    .and_then(parse_data_file_id);
+  let blob_file_id = Some(name)
+    .filter(|name| name.ends_with(BLOB_FILE_NAME_SUFFIX))
+    .and_then(parse_data_file_id);
+
+  // merged data files and their blob files go into their subdirectory, the merge writes
+  // them all flat
+  let dst_dir = match file_id.or(blob_file_id) {
+    Some(fid) => get_data_file_dir(dir_path, fid, files_per_subdir),
+    None => dir_path.to_path_buf(),
+  };
+  fs::create_dir_all(&dst_dir).map_err(|e| {
+    error!("failed to create data file dir {}: {e}", dst_dir.display());
+    Errors::FailedToCreateDatabaseDir
+  })?;
+  let dst_path = dst_dir.join(file_name);
+
+  if let Some(fid) = file_id.filter(|_| dst_path.exists()) {
+    let verified = DataFile::new(merge_path, fid, IOManagerType::StandardFileIO)
+      .and_then(|file| file.verify_footer())
+      .unwrap_or(false);
+    match verified {
+      true => {
+        warn!("merge output {fid} replaces {}", dst_path.display());
+        quarantine_file(dir_path, &dst_path)?;
+      }
+      false => {
+        error!(
+          "merge output {fid} does not verify, keeping {}",
+          dst_path.display()
+        );
+        return quarantine_file(dir_path, &src_path);
+      }
+    }
+  }
+
+  fs::rename(&src_path, &dst_path).map_err(|e| {
+    error!("failed to move merge file {}: {e}", src_path.display());
+    Errors::FailedToRenameFile
+  })
+}
+
+// moves a file into the corrupt dir of the database, under a free name
+fn quarantine_file(dir_path: &Path, path: &Path) -> Result<()> {
+  let corrupt_dir = dir_path.join(CORRUPT_DIR_NAME);
+  fs::create_dir_all(&corrupt_dir).map_err(|e| {
+    error!("failed to create dir {}: {e}", corrupt_dir.display());
+    Errors::FailedToCreateDatabaseDir
+  })?;
+  let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+  let mut dst_path = corrupt_dir.join(&*file_name);
+  for n in 1.. {
+    if !dst_path.exists() {
+      break;
+    }
+    dst_path = corrupt_dir.join(format!("{file_name}.{n}"));
+  }
+  fs::rename(path, &dst_path).map_err(|e| {
+    error!("failed to quarantine {}: {e}", path.display());
+    Errors::FailedToRenameFile
+  })
+}
+
 #[cfg(test)]
 mod tests {
-  use std::{sync::Arc, thread};
+  use std::{
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+  };
 
   use super::*;
-  use crate::util::rand_kv::{get_test_key, get_test_value};
+  use crate::{
+    option::{MergePolicy, MergeScheduler, MergeWindow, RuntimeOptions},
+    util::rand_kv::{get_test_key, get_test_value},
+  };
   use bytes::Bytes;
 
   #[test]
@@ -313,7 +1104,7 @@ mod tests {
     assert_eq!(keys.len(), 50000);
     for i in 0..50000 {
       let get_res = engine2.get(get_test_key(i));
-      assert!(get_res.ok().unwrap().len() > 0);
+      assert!(!get_res.ok().unwrap().is_empty());
     }
 
     std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove path");
@@ -445,4 +1236,444 @@ mod tests {
 
     std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove path");
   }
+
+  #[test]
+  fn test_merge_verify_file_footer() {
+    let mut opt = Options::default();
+    opt.dir_path = PathBuf::from("/tmp/flash-kv-merge-footer");
+    opt.data_file_size = 64 * 1024;
+    opt.file_merge_threshold = 0 as f32;
+    opt.verify_file_footer_at_startup = true;
+    let engine = Engine::open(opt.clone()).expect("failed to open engine");
+
+    for i in 0..5000 {
+      let put_res = engine.put(get_test_key(i), get_test_value(i));
+      assert!(put_res.is_ok());
+    }
+    let res1 = engine.merge();
+    assert!(res1.is_ok());
+    std::mem::drop(engine);
+
+    // sealed merge output passes verification
+    let engine2 = Engine::open(opt.clone()).expect("failed to open engine");
+    assert_eq!(engine2.list_keys().unwrap().len(), 5000);
+    std::mem::drop(engine2);
+
+    // bit rot in a merged file is detected on open
+    let file = get_data_file_name(&opt.dir_path, 0);
+    let mut content = fs::read(&file).unwrap();
+    content[20] ^= 0xff;
+    fs::write(&file, content).unwrap();
+    let open_res = Engine::open(opt.clone());
+    assert_eq!(Errors::InvalidDataFileChecksum, open_res.err().unwrap());
+
+    std::fs::remove_dir_all(opt.clone().dir_path).expect("failed to remove path");
+  }
+
+  #[test]
+  fn test_merge_interrupted_apply() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut opt = Options::default();
+    opt.dir_path = dir.path().to_path_buf();
+    opt.data_file_size = 64 * 1024;
+    opt.file_merge_threshold = 0 as f32;
+    let engine = Engine::open(opt.clone()).expect("failed to open engine");
+    for i in 0..5000 {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    engine.merge().unwrap();
+    drop(engine);
+
+    // fail after removing the merged files and moving the first output file, with another
+    // file in the way of the second one
+    let merge_path = get_merge_path(dir.path()).unwrap();
+    let fin_record = DataFile::new_merge_fin_file(&merge_path)
+      .unwrap()
+      .read_log_record(0)
+      .unwrap();
+    let non_merge_file_id: u32 = parse_record_value(fin_record.record.value).unwrap();
+    assert!(get_data_file_name(&merge_path, 1).is_file());
+    for fid in 0..non_merge_file_id {
+      fs::remove_file(get_data_file_name(dir.path(), fid)).unwrap();
+    }
+    fs::write(merge_path.join(MERGE_APPLYING_FILE_NAME), b"").unwrap();
+    fs::rename(
+      get_data_file_name(&merge_path, 0),
+      get_data_file_name(dir.path(), 0),
+    )
+    .unwrap();
+    fs::write(get_data_file_name(dir.path(), 1), b"left behind").unwrap();
+
+    let engine = Engine::open(opt).expect("failed to open engine");
+    assert_eq!(5000, engine.list_keys().unwrap().len());
+    assert_eq!(get_test_value(7), engine.get(get_test_key(7)).unwrap());
+    assert!(!merge_path.exists());
+    let quarantined = get_data_file_name(dir.path().join(CORRUPT_DIR_NAME), 1);
+    assert_eq!(b"left behind".to_vec(), fs::read(quarantined).unwrap());
+  }
+
+  #[test]
+  fn test_merge_torn_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut opt = Options::default();
+    opt.dir_path = dir.path().to_path_buf();
+    opt.data_file_size = 64 * 1024;
+    opt.file_merge_threshold = 0 as f32;
+    let engine = Engine::open(opt.clone()).expect("failed to open engine");
+    for i in 0..5000 {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    for i in 0..100 {
+      engine.delete(get_test_key(i)).unwrap();
+    }
+    engine.merge().unwrap();
+    drop(engine);
+
+    // the last merge output file lost its tail, the merged files are kept
+    let merge_path = get_merge_path(dir.path()).unwrap();
+    let merge_fin_file = DataFile::new_merge_fin_file(&merge_path).unwrap();
+    let fin_record = merge_fin_file.read_log_record(0).unwrap();
+    let manifest = read_merge_manifest(&merge_fin_file, fin_record.size as u64)
+      .unwrap()
+      .unwrap();
+    assert!(manifest.len() > 1);
+    let (last_id, last_size, _) = *manifest.last().unwrap();
+    let torn = fs::OpenOptions::new()
+      .write(true)
+      .open(get_data_file_name(&merge_path, last_id))
+      .unwrap();
+    torn.set_len(last_size / 2).unwrap();
+    drop(torn);
+
+    let engine = Engine::open(opt.clone()).expect("failed to open engine");
+    assert!(!merge_path.exists());
+    assert!(get_data_file_name(dir.path(), 0).is_file());
+    assert_eq!(4900, engine.list_keys().unwrap().len());
+    assert_eq!(
+      get_test_value(4999),
+      engine.get(get_test_key(4999)).unwrap()
+    );
+    assert_eq!(
+      Errors::KeyNotFound,
+      engine.get(get_test_key(7)).unwrap_err()
+    );
+
+    // a manifest file missing after the merged files were removed cannot be recovered
+    engine.merge().unwrap();
+    drop(engine);
+    for fid in 0..parse_record_value::<u32>(
+      DataFile::new_merge_fin_file(&merge_path)
+        .unwrap()
+        .read_log_record(0)
+        .unwrap()
+        .record
+        .value,
+    )
+    .unwrap()
+    {
+      fs::remove_file(get_data_file_name(dir.path(), fid)).unwrap();
+    }
+    fs::write(merge_path.join(MERGE_APPLYING_FILE_NAME), b"").unwrap();
+    fs::remove_file(get_data_file_name(&merge_path, 0)).unwrap();
+    assert_eq!(
+      Errors::MergeManifestMismatch,
+      Engine::open(opt).err().unwrap()
+    );
+  }
+
+  #[test]
+  fn test_merge_invalid_hint_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut opt = Options::default();
+    opt.dir_path = dir.path().to_path_buf();
+    opt.data_file_size = 64 * 1024;
+    opt.file_merge_threshold = 0 as f32;
+    let engine = Engine::open(opt.clone()).expect("failed to open engine");
+    for i in 0..5000 {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    for i in 0..100 {
+      engine.delete(get_test_key(i)).unwrap();
+    }
+    engine.merge().unwrap();
+    engine.put(get_test_key(0), get_test_value(1)).unwrap();
+    engine.close().unwrap();
+    drop(engine);
+
+    let check = |engine: &Engine| {
+      assert_eq!(4901, engine.list_keys().unwrap().len());
+      assert_eq!(get_test_value(1), engine.get(get_test_key(0)).unwrap());
+      assert_eq!(
+        Errors::KeyNotFound,
+        engine.get(get_test_key(50)).unwrap_err()
+      );
+      assert_eq!(
+        get_test_value(4999),
+        engine.get(get_test_key(4999)).unwrap()
+      );
+    };
+
+    // the merge output is applied and indexed from its sealed hint file
+    remove_close_hint(dir.path()).unwrap();
+    let engine = Engine::open(opt.clone()).expect("failed to open engine");
+    let hint_file = DataFile::new_hint_file(dir.path()).unwrap();
+    assert!(validate_hint_file(&hint_file));
+    assert!(hint_file.verify_footer().unwrap());
+    check(&engine);
+    drop(engine);
+
+    // a truncated hint falls back to scanning the merged data files
+    remove_close_hint(dir.path()).unwrap();
+    let hint_path = dir.path().join(HINT_FILE_NAME);
+    let content = fs::read(&hint_path).unwrap();
+    fs::write(&hint_path, &content[..content.len() / 2]).unwrap();
+    let hint_file = DataFile::new_hint_file(dir.path()).unwrap();
+    assert!(!validate_hint_file(&hint_file));
+    let engine = Engine::open(opt.clone()).expect("failed to open engine");
+    assert!(!engine.load_index_from_hint_file().unwrap());
+    check(&engine);
+    drop(engine);
+
+    // so does a flipped byte
+    remove_close_hint(dir.path()).unwrap();
+    let mut content = content;
+    content[40] ^= 0xff;
+    fs::write(&hint_path, content).unwrap();
+    let engine = Engine::open(opt).expect("failed to open engine");
+    check(&engine);
+  }
+
+  #[test]
+  fn test_merge_io_uring() {
+    let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+    let mut opt = Options::default();
+    opt.dir_path = temp_dir.path().to_path_buf();
+    opt.data_file_size = 64 * 1024;
+    opt.file_merge_threshold = 0 as f32;
+    opt.use_io_uring = true;
+    let engine = Engine::open(opt.clone()).expect("failed to open engine");
+
+    for i in 0..2000 {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    for i in 0..1000 {
+      engine.delete(get_test_key(i)).unwrap();
+    }
+    engine.merge().unwrap();
+    std::mem::drop(engine);
+
+    let engine2 = Engine::open(opt).expect("failed to open engine");
+    assert_eq!(1000, engine2.list_keys().unwrap().len());
+    for i in 1000..2000 {
+      assert_eq!(get_test_value(i), engine2.get(get_test_key(i)).unwrap());
+    }
+  }
+
+  #[test]
+  fn test_merge_policy() {
+    let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+    let mut opt = Options::default();
+    opt.dir_path = temp_dir.path().to_path_buf();
+    opt.data_file_size = 4 * 1024;
+    opt.file_merge_threshold = 0.9;
+    opt.merge_policy = MergePolicy::Tiered { max_files: 2 };
+    let engine = Engine::open(opt.clone()).expect("failed to open engine");
+
+    for i in 0..1000 {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    assert!(engine.old_data_files.read().len() > 2);
+    assert_eq!(Err(Errors::MergeThresholdUnreached), engine.merge());
+
+    // a key of each of the first two files is deleted, both files now hold a little dead data
+    engine.delete(get_test_key(0)).unwrap();
+    assert_eq!(Err(Errors::MergeThresholdUnreached), engine.merge());
+    let second_file_key = (0..1000)
+      .map(get_test_key)
+      .find(|key| {
+        engine
+          .index
+          .get(key.to_vec())
+          .is_some_and(|pos| pos.file_id == 1)
+      })
+      .unwrap();
+    engine.delete(second_file_key).unwrap();
+
+    let garbage = engine.file_garbage().unwrap();
+    let active_file_id = engine.active_data_file.read().get_file_id();
+    assert!(garbage
+      .iter()
+      .any(|g| g.file_id == 0 && g.tombstone_bytes == 0));
+    // tombstones are dead as soon as they are written to the active file
+    let active = garbage
+      .iter()
+      .find(|g| g.file_id == active_file_id)
+      .unwrap();
+    assert_eq!(active.dead_bytes, active.tombstone_bytes);
+    assert!(engine.get_engine_stat().unwrap().tombstone_size > 0);
+
+    engine.merge().unwrap();
+    assert!(engine
+      .file_garbage()
+      .unwrap()
+      .iter()
+      .all(|g| g.file_id > active_file_id));
+    drop(engine);
+
+    // any sealed file with dead data is merged once it is old enough
+    opt.merge_policy = MergePolicy::TimeWindow {
+      max_age: std::time::Duration::from_secs(3600),
+    };
+    let engine = Engine::open(opt.clone()).expect("failed to open engine");
+    assert_eq!(998, engine.list_keys().unwrap().len());
+    for i in 1000..1500 {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    engine.delete(get_test_key(1000)).unwrap();
+    assert_eq!(Err(Errors::MergeThresholdUnreached), engine.merge());
+    drop(engine);
+
+    opt.merge_policy = MergePolicy::TimeWindow {
+      max_age: std::time::Duration::ZERO,
+    };
+    let engine = Engine::open(opt).expect("failed to open engine");
+    engine.delete(get_test_key(1001)).unwrap();
+    engine.merge().unwrap();
+  }
+
+  #[test]
+  fn test_merge_scheduler() {
+    let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+    let mut opt = Options::default();
+    opt.dir_path = temp_dir.path().to_path_buf();
+    opt.data_file_size = 16 * 1024;
+
+    // a window starting an hour from now
+    let day = 24 * 60 * 60;
+    let since_epoch = SystemTime::now()
+      .duration_since(std::time::UNIX_EPOCH)
+      .unwrap();
+    let hour_from_now = (since_epoch.as_secs() + 3600) % day;
+    opt.merge_scheduler.windows = vec![MergeWindow {
+      start: Duration::from_secs(hour_from_now),
+      end: Duration::from_secs((hour_from_now + 3600) % day),
+    }];
+    let engine = Engine::open(opt.clone()).expect("failed to open engine");
+    for i in 0..1000 {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    for i in 0..1000 {
+      engine.delete(get_test_key(i)).unwrap();
+    }
+    assert_eq!(Err(Errors::MergeOutsideWindow), engine.merge());
+    drop(engine);
+
+    // merge reads the files at the io limit
+    opt.merge_scheduler = MergeScheduler::default();
+    let engine = Engine::open(opt).expect("failed to open engine");
+    let total_size = util::file::dir_disk_size(temp_dir.path());
+    engine.set_merge_io_limit(total_size * 8);
+    let start = Instant::now();
+    engine.merge().unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(100));
+  }
+
+  #[test]
+  fn test_estimate_merge_gain() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut opt = Options::default();
+    opt.dir_path = dir.path().to_path_buf();
+    opt.file_merge_threshold = 0.9;
+    let engine = Engine::open(opt.clone()).expect("failed to open engine");
+
+    for i in 0..1000 {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    let estimate = engine.estimate_merge_gain().unwrap();
+    assert_eq!(0, estimate.reclaimable_bytes);
+    assert_eq!(estimate.read_bytes, estimate.write_bytes);
+    // no merge ran yet and merges are not limited
+    assert_eq!(None, estimate.duration);
+    assert!(!estimate.due);
+
+    for i in 0..500 {
+      engine.delete(get_test_key(i)).unwrap();
+    }
+    let estimate = engine.estimate_merge_gain().unwrap();
+    assert!(estimate.reclaimable_bytes > 0);
+    assert_eq!(
+      estimate.read_bytes - estimate.reclaimable_bytes,
+      estimate.write_bytes
+    );
+
+    // the IO limit bounds the duration
+    engine.set_merge_io_limit(estimate.read_bytes);
+    let estimate = engine.estimate_merge_gain().unwrap();
+    assert!(estimate.duration.unwrap() >= Duration::from_millis(999));
+    engine.set_merge_io_limit(0);
+
+    // a finished merge gives the throughput
+    engine
+      .update_options(RuntimeOptions {
+        file_merge_threshold: Some(0.0),
+        ..Default::default()
+      })
+      .unwrap();
+    engine.merge().unwrap();
+    let estimate = engine.estimate_merge_gain().unwrap();
+    assert!(estimate.duration.is_some());
+    assert!(estimate.due);
+  }
+
+  #[test]
+  fn test_merge_parallel_pipeline() {
+    // the same writes merged on one thread and by the pipeline
+    let merged_files = |read_threads, filter_threads| {
+      let dir = tempfile::tempdir().unwrap();
+      let mut opt = Options::default();
+      opt.dir_path = dir.path().to_path_buf();
+      opt.data_file_size = 16 * 1024;
+      opt.file_merge_threshold = 0.0;
+      opt.merge_scheduler.read_threads = read_threads;
+      opt.merge_scheduler.filter_threads = filter_threads;
+      let engine = Engine::open(opt.clone()).unwrap();
+      for i in 0..3000 {
+        engine.put(get_test_key(i), get_test_value(i)).unwrap();
+      }
+      for i in (0..3000).step_by(3) {
+        engine.delete(get_test_key(i)).unwrap();
+      }
+      for i in (1..3000).step_by(3) {
+        engine.put(get_test_key(i), get_test_value(i * 7)).unwrap();
+      }
+      assert!(engine.data_files().len() > 4);
+      engine.merge().unwrap();
+      engine.close().unwrap();
+      drop(engine);
+
+      let engine = Engine::open(opt).unwrap();
+      assert_eq!(2000, engine.list_keys().unwrap().len());
+      for i in (1..3000).step_by(3) {
+        assert_eq!(get_test_value(i * 7), engine.get(get_test_key(i)).unwrap());
+      }
+      engine.close().unwrap();
+
+      let mut files = Vec::new();
+      for entry in fs::read_dir(dir.path()).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension() == Some(OsStr::new("data")) || path.ends_with(HINT_FILE_NAME) {
+          files.push((
+            path.file_name().unwrap().to_owned(),
+            fs::read(&path).unwrap(),
+          ));
+        }
+      }
+      files.sort();
+      files
+    };
+
+    let sequential = merged_files(1, 1);
+    assert_eq!(sequential, merged_files(3, 2));
+    assert_eq!(sequential, merged_files(1, 4));
+  }
 }
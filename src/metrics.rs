@@ -0,0 +1,254 @@
+use std::{
+  io::{Read, Write},
+  net::{TcpStream, ToSocketAddrs},
+  sync::{
+    mpsc::{self, RecvTimeoutError, Sender},
+    Arc, Weak,
+  },
+  thread::{self, JoinHandle},
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::warn;
+
+use crate::{
+  db::{Engine, Stat},
+  errors::{Errors, Result},
+};
+
+const PUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Configuration of the metrics push task.
+#[derive(Debug, Clone)]
+pub struct MetricsPushOptions {
+  /// Webhook or push gateway url, only plain `http://` is supported
+  pub url: String,
+
+  /// Interval between two pushes
+  pub interval: Duration,
+}
+
+impl Default for MetricsPushOptions {
+  fn default() -> Self {
+    Self {
+      url: String::from("http://127.0.0.1:9091/metrics/job/flash-kv"),
+      interval: Duration::from_secs(10),
+    }
+  }
+}
+
+/// Handle of a running metrics push task, the task stops when the handle is dropped.
+pub struct MetricsPushHandle {
+  stop_sender: Option<Sender<()>>,
+  worker: Option<JoinHandle<()>>,
+}
+
+impl Engine {
+  /// Starts a background task which periodically POSTs the engine stat as JSON to `opts.url`.
+  ///
+  /// The task only holds a weak reference to the engine and exits once the engine is dropped
+  /// or the returned handle is dropped.
+  pub fn start_metrics_push(
+    self: &Arc<Self>,
+    opts: MetricsPushOptions,
+  ) -> Result<MetricsPushHandle> {
+    let target = PushTarget::parse(&opts.url)?;
+    let engine = Arc::downgrade(self);
+    let (stop_sender, stop_receiver) = mpsc::channel::<()>();
+
+    let worker = thread::Builder::new()
+      .name("flash-kv-metrics-push".to_string())
+      .spawn(move || loop {
+        match stop_receiver.recv_timeout(opts.interval) {
+          Err(RecvTimeoutError::Timeout) => {}
+          _ => return,
+        }
+        if !push_once(&engine, &target) {
+          return;
+        }
+      })
+      .map_err(|e| {
+        warn!("failed to spawn metrics push task: {e}");
+        Errors::FailedToStartBackgroundTask
+      })?;
+
+    Ok(MetricsPushHandle {
+      stop_sender: Some(stop_sender),
+      worker: Some(worker),
+    })
+  }
+}
+
+impl MetricsPushHandle {
+  /// Stops the push task and waits for it to exit.
+  pub fn stop(mut self) {
+    self.shutdown();
+  }
+
+  fn shutdown(&mut self) {
+    // dropping the sender wakes up the worker
+    self.stop_sender.take();
+    if let Some(worker) = self.worker.take() {
+      let _ = worker.join();
+    }
+  }
+}
+
+impl Drop for MetricsPushHandle {
+  fn drop(&mut self) {
+    self.shutdown();
+  }
+}
+
+/// Push the current stat, returns false if the engine is gone.
+fn push_once(engine: &Weak<Engine>, target: &PushTarget) -> bool {
+  let stat = match engine.upgrade() {
+    Some(engine) => engine.get_engine_stat(),
+    None => return false,
+  };
+  match stat {
+    Ok(stat) => {
+      if let Err(e) = target.post_json(&stat_to_json(&stat)) {
+        warn!("failed to push engine metrics to {}: {e}", target.host);
+      }
+    }
+    Err(e) => warn!("failed to collect engine stat: {e}"),
+  }
+  true
+}
+
+/// Encode engine stat as a flat JSON object.
+pub fn stat_to_json(stat: &Stat) -> String {
+  let timestamp = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_secs();
+  stat.to_json(timestamp)
+}
+
+/// Parsed `http://host[:port]/path` url.
+struct PushTarget {
+  host: String,
+  port: u16,
+  path: String,
+}
+
+impl PushTarget {
+  fn parse(url: &str) -> Result<Self> {
+    let rest = url
+      .strip_prefix("http://")
+      .ok_or(Errors::InvalidMetricsPushUrl)?;
+    let (authority, path) = match rest.find('/') {
+      Some(i) => (&rest[..i], &rest[i..]),
+      None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+      Some((host, port)) => (
+        host,
+        port
+          .parse::<u16>()
+          .map_err(|_| Errors::InvalidMetricsPushUrl)?,
+      ),
+      None => (authority, 80),
+    };
+    if host.is_empty() {
+      return Err(Errors::InvalidMetricsPushUrl);
+    }
+    Ok(Self {
+      host: host.to_string(),
+      port,
+      path: path.to_string(),
+    })
+  }
+
+  fn post_json(&self, body: &str) -> std::io::Result<()> {
+    let addr = (self.host.as_str(), self.port)
+      .to_socket_addrs()?
+      .next()
+      .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no address"))?;
+    let mut stream = TcpStream::connect_timeout(&addr, PUSH_TIMEOUT)?;
+    stream.set_read_timeout(Some(PUSH_TIMEOUT))?;
+    stream.set_write_timeout(Some(PUSH_TIMEOUT))?;
+
+    let request = format!(
+      "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+      self.path,
+      self.host,
+      self.port,
+      body.len(),
+      body
+    );
+    stream.write_all(request.as_bytes())?;
+
+    // only the status line matters
+    let mut buf = [0u8; 32];
+    let n = stream.read(&mut buf)?;
+    let status_line = String::from_utf8_lossy(&buf[..n]);
+    match status_line.split_whitespace().nth(1) {
+      Some(code) if code.starts_with('2') => Ok(()),
+      _ => Err(std::io::Error::other(format!(
+        "unexpected response: {status_line}"
+      ))),
+    }
+  }
+}
+
+#[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
+mod tests {
+  use std::net::TcpListener;
+
+  use tempfile::tempdir;
+
+  use super::*;
+  use crate::{
+    option::Options,
+    util::rand_kv::{get_test_key, get_test_value},
+  };
+
+  #[test]
+  fn test_push_target_parse() {
+    let target = PushTarget::parse("http://localhost:9091/metrics/job/kv").unwrap();
+    assert_eq!(target.host, "localhost");
+    assert_eq!(target.port, 9091);
+    assert_eq!(target.path, "/metrics/job/kv");
+
+    let target = PushTarget::parse("http://example.com").unwrap();
+    assert_eq!(target.port, 80);
+    assert_eq!(target.path, "/");
+
+    assert!(PushTarget::parse("https://example.com").is_err());
+    assert!(PushTarget::parse("http://:80/").is_err());
+  }
+
+  #[test]
+  fn test_metrics_push() {
+    let temp_dir = tempdir().expect("failed to create temp dir");
+    let mut opts = Options::default();
+    opts.dir_path = temp_dir.path().to_path_buf();
+    let engine = Arc::new(Engine::open(opts).expect("failed to open engine"));
+    engine.put(get_test_key(1), get_test_value(1)).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let handle = engine
+      .start_metrics_push(MetricsPushOptions {
+        url: format!("http://127.0.0.1:{port}/push"),
+        interval: Duration::from_millis(20),
+      })
+      .expect("failed to start metrics push");
+
+    let (mut stream, _) = listener.accept().unwrap();
+    let mut request = String::new();
+    let mut buf = vec![0u8; 1024];
+    while !request.ends_with('}') {
+      let n = stream.read(&mut buf).unwrap();
+      request.push_str(&String::from_utf8_lossy(&buf[..n]));
+    }
+    stream.write_all(b"HTTP/1.1 200 OK\r\n\r\n").unwrap();
+
+    assert!(request.starts_with("POST /push HTTP/1.1"));
+    assert!(request.contains("\"key_num\":1"));
+    handle.stop();
+  }
+}
@@ -0,0 +1,496 @@
+use std::{
+  collections::HashMap,
+  fs,
+  path::Path,
+  sync::atomic::{AtomicU64, Ordering},
+  time::SystemTime,
+};
+
+use bytes::Bytes;
+use log::error;
+
+use crate::{
+  batch::{log_record_key_with_seq, WriteBatch, TXN_FIN_KEY},
+  data::{
+    data_file::{get_txn_decision_file_name, DataFile, TXN_DECISION_FILE_NAME_PREFIX},
+    log_record::{LogRecord, LogRecordType, TransactionRecord},
+  },
+  db::Engine,
+  errors::{DataFileOp, Errors, Result},
+  option::{IndexType, StorageMode, WriteBatchOptions},
+  util,
+};
+
+pub(crate) const TXN_ABORT_KEY: &[u8] = "txn-abort".as_bytes();
+const TXN_DECISION_KEY: &[u8] = "txn.decision".as_bytes();
+
+// last id handed out, ids are unique within the process and grow with the time
+static LAST_TXN_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Write batch spanning several engines, committed atomically with two-phase commit.
+///
+/// Every engine with writes first writes and syncs them as a prepared transaction, whose
+/// records are not applied yet. Then a decision file is written to the directory of the first
+/// engine, the coordinator, which commits the transaction: the engines write their txn
+/// finished records and apply the writes, and the decision is removed once all of them did.
+///
+/// Engines opened after a crash hold the prepared transactions they find without applying
+/// them, until [`MultiEngineBatch::recover`] rolls them forward or back. Merges and clears
+/// fail with `Errors::PreparedTxnPending` meanwhile.
+pub struct MultiEngineBatch<'a> {
+  engines: Vec<&'a Engine>,
+  batches: Vec<WriteBatch<'a>>,
+}
+
+/// Prepared transactions resolved by [`MultiEngineBatch::recover`], counted per engine.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecoveryStats {
+  /// Transactions whose decision was found, they are applied
+  pub rolled_forward: usize,
+
+  /// Transactions without decision, they are discarded
+  pub rolled_back: usize,
+}
+
+/// Transaction of an engine prepared for a multi-engine batch.
+pub(crate) struct PreparedTxn {
+  seq_no: usize,
+  pub(crate) records: Vec<TransactionRecord>,
+  recovered: bool, // found on open, not prepared by a batch of this engine
+}
+
+impl PreparedTxn {
+  pub(crate) fn new(seq_no: usize, recovered: bool) -> Self {
+    Self {
+      seq_no,
+      records: Vec::new(),
+      recovered,
+    }
+  }
+}
+
+impl<'a> MultiEngineBatch<'a> {
+  /// Creates a batch over `engines`, the first one coordinates the commits. The engines have
+  /// to be distinct and writable, and the coordinator on disk. The B+ tree index is not
+  /// supported, its index does not wait for the decision.
+  pub fn new(engines: &[&'a Engine], options: WriteBatchOptions) -> Result<Self> {
+    let coordinator = engines.first().ok_or(Errors::InvalidBatchEngines)?;
+    if coordinator.options.storage_mode == StorageMode::Memory {
+      return Err(Errors::MemoryModeUnsupported);
+    }
+    for (i, engine) in engines.iter().enumerate() {
+      if engines[..i]
+        .iter()
+        .any(|other| other.options.dir_path == engine.options.dir_path)
+      {
+        return Err(Errors::InvalidBatchEngines);
+      }
+      if engine.reader.is_some() {
+        return Err(Errors::ReadOnlyEngine);
+      }
+      if engine.options.index_type == IndexType::BPlusTree {
+        return Err(Errors::IndexTypeUnsupported);
+      }
+    }
+
+    let batches = engines
+      .iter()
+      .map(|engine| {
+        engine.new_write_batch(WriteBatchOptions {
+          max_batch_num: options.max_batch_num,
+          max_batch_bytes: options.max_batch_bytes,
+          sync_writes: options.sync_writes,
+        })
+      })
+      .collect::<Result<Vec<_>>>()?;
+    Ok(Self {
+      engines: engines.to_vec(),
+      batches,
+    })
+  }
+
+  /// Adds a key-value pair to the writes of the engine at `engine`.
+  pub fn put(&self, engine: usize, key: Bytes, value: Bytes) -> Result<()> {
+    self.batch(engine)?.put(key, value)
+  }
+
+  /// Marks a key of the engine at `engine` for deletion.
+  pub fn delete(&self, engine: usize, key: Bytes) -> Result<()> {
+    self.batch(engine)?.delete(key)
+  }
+
+  /// Commits the writes on all engines or on none of them.
+  ///
+  /// A failure before the decision is written rolls the prepared engines back. After it the
+  /// transaction is committed even if an engine fails to finish it, the error is returned and
+  /// the engine applies it on the next `recover`.
+  pub fn commit(&self) -> Result<()> {
+    if self.batches.iter().all(|batch| batch.is_empty()) {
+      return Ok(());
+    }
+    let txn_id = next_txn_id();
+
+    // the coordinator always takes part, it finishes last so its pending transaction tells
+    // `recover` that the decision is still in use
+    let mut prepared = Vec::with_capacity(self.engines.len());
+    for (i, (engine, batch)) in self.engines.iter().zip(&self.batches).enumerate() {
+      if i > 0 && batch.is_empty() {
+        continue;
+      }
+      if let Err(e) = batch.prepare(txn_id) {
+        abort_all(&prepared, txn_id);
+        return Err(e);
+      }
+      prepared.push(*engine);
+    }
+
+    let dir_path = &self.engines[0].options.dir_path;
+    if let Err(e) = write_decision(dir_path, txn_id) {
+      abort_all(&prepared, txn_id);
+      return Err(e);
+    }
+    self.batches.iter().for_each(|batch| batch.discard());
+
+    for engine in prepared.iter().rev() {
+      engine.finish_prepared(txn_id)?;
+    }
+    for engine in prepared.iter() {
+      engine.sync()?;
+    }
+    remove_decision(dir_path, txn_id)
+  }
+
+  /// Rolls the prepared transactions found when opening `engines` forward if their decision
+  /// exists, back otherwise.
+  ///
+  /// `engines` are all the engines the batches were committed on, the coordinator first.
+  /// Decisions are removed once the transaction is applied on every engine, so an engine
+  /// left out would roll its part back on a later call.
+  pub fn recover(engines: &[&Engine]) -> Result<RecoveryStats> {
+    let coordinator = engines.first().ok_or(Errors::InvalidBatchEngines)?;
+    if coordinator.options.storage_mode == StorageMode::Memory {
+      return Err(Errors::MemoryModeUnsupported);
+    }
+    let dir_path = &coordinator.options.dir_path;
+    let mut stats = RecoveryStats::default();
+    for engine in engines {
+      let txn_ids: Vec<u64> = engine
+        .prepared_txns
+        .lock()
+        .iter()
+        .filter(|(_, txn)| txn.recovered)
+        .map(|(txn_id, _)| *txn_id)
+        .collect();
+      for txn_id in txn_ids {
+        if decision_exists(dir_path, txn_id) {
+          engine.finish_prepared(txn_id)?;
+          stats.rolled_forward += 1;
+        } else {
+          engine.abort_prepared(txn_id)?;
+          stats.rolled_back += 1;
+        }
+      }
+      engine.sync()?;
+    }
+
+    // decisions of transactions still finishing are kept
+    for txn_id in list_decisions(dir_path)? {
+      if !coordinator.prepared_txns.lock().contains_key(&txn_id) {
+        remove_decision(dir_path, txn_id)?;
+      }
+    }
+    Ok(stats)
+  }
+
+  fn batch(&self, engine: usize) -> Result<&WriteBatch<'a>> {
+    self.batches.get(engine).ok_or(Errors::InvalidEngineIndex)
+  }
+}
+
+impl Engine {
+  /// Writes the txn finished record of a prepared transaction and applies its records.
+  pub(crate) fn finish_prepared(&self, txn_id: u64) -> Result<()> {
+    self.resolve_prepared(txn_id, LogRecordType::TxnFinished)
+  }
+
+  /// Writes the txn aborted record of a prepared transaction, its records become garbage.
+  pub(crate) fn abort_prepared(&self, txn_id: u64) -> Result<()> {
+    self.resolve_prepared(txn_id, LogRecordType::TxnAborted)
+  }
+
+  fn resolve_prepared(&self, txn_id: u64, rec_type: LogRecordType) -> Result<()> {
+    let _gate = self.write_gate.read_recursive();
+    let _lock = self.batch_commit_lock.lock();
+    let Some(txn) = self.prepared_txns.lock().remove(&txn_id) else {
+      return Ok(());
+    };
+    let key = match rec_type {
+      LogRecordType::TxnFinished => TXN_FIN_KEY,
+      _ => TXN_ABORT_KEY,
+    };
+    let mut record = LogRecord {
+      key: log_record_key_with_seq(key, txn.seq_no),
+      value: Default::default(),
+      rec_type,
+      timestamp: None,
+    };
+    if let Err(e) = self.append_log_record(&mut record) {
+      self.prepared_txns.lock().insert(txn_id, txn);
+      return Err(e);
+    }
+
+    match rec_type {
+      LogRecordType::TxnFinished => {
+        let mut records = txn.records;
+        if txn.recovered {
+          // the replay does not buffer values, watchers and the change feed get them read back
+          for txn_record in records.iter_mut() {
+            if let LogRecordType::Normal | LogRecordType::Blob = txn_record.record.rec_type {
+              txn_record.record.value = self.read_log_record_at(&txn_record.pos)?.value;
+              txn_record.record.rec_type = LogRecordType::Normal;
+            }
+          }
+        }
+        let writes = records
+          .iter()
+          .map(|txn_record| (&txn_record.record, txn_record.pos));
+        self.apply_txn_writes(writes)?;
+      }
+      _ => txn
+        .records
+        .iter()
+        .for_each(|txn_record| self.mark_stale(txn_record.pos)),
+    }
+    Ok(())
+  }
+
+  /// Holds the transactions left unfinished on open which were prepared, the others are
+  /// dropped.
+  pub(crate) fn hold_recovered_txns(
+    &self,
+    transaction_records: HashMap<usize, Vec<TransactionRecord>>,
+  ) {
+    let mut prepared_txns = self.prepared_txns.lock();
+    for (seq_no, records) in transaction_records {
+      let (markers, records): (Vec<_>, Vec<_>) = records
+        .into_iter()
+        .partition(|txn_record| txn_record.record.rec_type == LogRecordType::TxnPrepared);
+      let Some(marker) = markers.first() else {
+        continue;
+      };
+      let Ok(txn_id) = marker.record.key[..].try_into().map(u64::from_be_bytes) else {
+        continue;
+      };
+      let mut txn = PreparedTxn::new(seq_no, true);
+      txn.records = records;
+      prepared_txns.insert(txn_id, txn);
+    }
+  }
+
+  /// Fails with `Errors::PreparedTxnPending` while prepared transactions are not resolved.
+  pub(crate) fn check_no_prepared_txns(&self) -> Result<()> {
+    match self.prepared_txns.lock().is_empty() {
+      true => Ok(()),
+      false => Err(Errors::PreparedTxnPending),
+    }
+  }
+}
+
+fn next_txn_id() -> u64 {
+  let now = SystemTime::now()
+    .duration_since(SystemTime::UNIX_EPOCH)
+    .map_or(0, |d| d.as_nanos() as u64);
+  let next = |last: u64| now.max(last + 1);
+  LAST_TXN_ID
+    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| Some(next(last)))
+    .map_or(now, next)
+}
+
+// rolls back the engines prepared so far, errors are left to `recover`
+fn abort_all(engines: &[&Engine], txn_id: u64) {
+  for engine in engines {
+    if let Err(e) = engine.abort_prepared(txn_id) {
+      error!("failed to abort prepared transaction: {e}");
+    }
+  }
+}
+
+fn write_decision(dir_path: &Path, txn_id: u64) -> Result<()> {
+  let decision_file = DataFile::new_txn_decision_file(dir_path, txn_id)?;
+  let record = LogRecord {
+    key: TXN_DECISION_KEY.to_vec(),
+    value: txn_id.to_be_bytes().to_vec(),
+    rec_type: LogRecordType::Normal,
+    timestamp: None,
+  };
+  decision_file.write(&record.encode())?;
+  decision_file.sync()?;
+  // the participants apply the commit once this returns, the new file must survive a crash
+  util::file::sync_dir(dir_path).map_err(|e| Errors::data_file_io(DataFileOp::Sync, e))
+}
+
+// a decision torn by a crash was never complete, the transaction is not committed
+fn decision_exists(dir_path: &Path, txn_id: u64) -> bool {
+  if !get_txn_decision_file_name(dir_path, txn_id).is_file() {
+    return false;
+  }
+  DataFile::new_txn_decision_file(dir_path, txn_id)
+    .and_then(|decision_file| decision_file.read_log_record(0))
+    .is_ok_and(|read| read.record.key == TXN_DECISION_KEY)
+}
+
+fn list_decisions(dir_path: &Path) -> Result<Vec<u64>> {
+  let dir = fs::read_dir(dir_path).map_err(|e| {
+    error!("failed to read database dir: {e}");
+    Errors::FailedToReadDatabaseDir
+  })?;
+  Ok(
+    dir
+      .flatten()
+      .filter_map(|entry| {
+        let file_name = entry.file_name();
+        let txn_id = file_name
+          .to_str()?
+          .strip_prefix(TXN_DECISION_FILE_NAME_PREFIX)?;
+        u64::from_str_radix(txn_id, 16).ok()
+      })
+      .collect(),
+  )
+}
+
+fn remove_decision(dir_path: &Path, txn_id: u64) -> Result<()> {
+  match fs::remove_file(get_txn_decision_file_name(dir_path, txn_id)) {
+    Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+      error!("failed to remove transaction decision: {e}");
+      Err(Errors::FailedToRemoveFile)
+    }
+    _ => Ok(()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    option::Options,
+    util::rand_kv::{get_test_key, get_test_value},
+  };
+
+  fn open_engines(dirs: &[tempfile::TempDir]) -> Vec<Engine> {
+    dirs
+      .iter()
+      .map(|dir| {
+        Engine::open(Options {
+          dir_path: dir.path().to_path_buf(),
+          fast_reopen: true,
+          ..Default::default()
+        })
+        .expect("fail to open engine")
+      })
+      .collect()
+  }
+
+  #[test]
+  fn test_multi_engine_batch_commit() {
+    let dirs = [tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap()];
+    let engines = open_engines(&dirs);
+    let refs: Vec<&Engine> = engines.iter().collect();
+    engines[1].put(get_test_key(9), get_test_value(9)).unwrap();
+
+    let batch = MultiEngineBatch::new(&refs, WriteBatchOptions::default()).unwrap();
+    batch.put(0, get_test_key(1), get_test_value(1)).unwrap();
+    batch.put(1, get_test_key(2), get_test_value(2)).unwrap();
+    batch.delete(1, get_test_key(9)).unwrap();
+    assert_eq!(
+      Errors::InvalidEngineIndex,
+      batch
+        .put(2, get_test_key(3), get_test_value(3))
+        .unwrap_err()
+    );
+    batch.commit().unwrap();
+
+    assert_eq!(get_test_value(1), engines[0].get(get_test_key(1)).unwrap());
+    assert_eq!(get_test_value(2), engines[1].get(get_test_key(2)).unwrap());
+    assert_eq!(
+      Errors::KeyNotFound,
+      engines[1].get(get_test_key(9)).unwrap_err()
+    );
+    assert!(list_decisions(dirs[0].path()).unwrap().is_empty());
+    assert!(engines[1].prepared_txns.lock().is_empty());
+
+    assert_eq!(
+      Some(Errors::InvalidBatchEngines),
+      MultiEngineBatch::new(&[refs[0], refs[0]], WriteBatchOptions::default()).err()
+    );
+    assert_eq!(
+      Some(Errors::InvalidBatchEngines),
+      MultiEngineBatch::new(&[], WriteBatchOptions::default()).err()
+    );
+  }
+
+  #[test]
+  fn test_multi_engine_batch_recover() {
+    let dirs = [tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap()];
+
+    // both transactions are prepared, the decision of the first one is written
+    for (txn_id, decided) in [(1, true), (2, false)] {
+      let engines = open_engines(&dirs);
+      let refs: Vec<&Engine> = engines.iter().collect();
+      let batch = MultiEngineBatch::new(&refs, WriteBatchOptions::default()).unwrap();
+      batch
+        .put(0, get_test_key(txn_id), get_test_value(txn_id))
+        .unwrap();
+      batch
+        .put(1, get_test_key(txn_id), get_test_value(txn_id))
+        .unwrap();
+      batch.batches[0].prepare(txn_id as u64).unwrap();
+      batch.batches[1].prepare(txn_id as u64).unwrap();
+      if decided {
+        write_decision(dirs[0].path(), txn_id as u64).unwrap();
+      }
+      // the engines go away before the transaction finished
+      drop(refs);
+      drop(engines);
+    }
+
+    let engines = open_engines(&dirs);
+    let refs: Vec<&Engine> = engines.iter().collect();
+    assert!(engines[1].get(get_test_key(1)).is_err());
+    assert_eq!(Err(Errors::PreparedTxnPending), engines[1].clear());
+    let watch = engines[0].watch(Bytes::new());
+    let stats = MultiEngineBatch::recover(&refs).unwrap();
+    assert_eq!(
+      RecoveryStats {
+        rolled_forward: 2,
+        rolled_back: 2,
+      },
+      stats
+    );
+    let check = |engines: &[Engine]| {
+      for engine in engines {
+        assert_eq!(get_test_value(1), engine.get(get_test_key(1)).unwrap());
+        assert_eq!(
+          Errors::KeyNotFound,
+          engine.get(get_test_key(2)).unwrap_err()
+        );
+      }
+    };
+    check(&engines);
+    // the values of the recovered transaction are read back for the watchers
+    let event = watch.try_recv().unwrap();
+    assert_eq!(get_test_key(1), event.key);
+    assert_eq!(Some(get_test_value(1)), event.value);
+    assert!(list_decisions(dirs[0].path()).unwrap().is_empty());
+    drop(refs);
+    drop(engines);
+
+    // the outcome is durable
+    let engines = open_engines(&dirs);
+    let refs: Vec<&Engine> = engines.iter().collect();
+    assert_eq!(
+      RecoveryStats::default(),
+      MultiEngineBatch::recover(&refs).unwrap()
+    );
+    check(&engines);
+  }
+}
@@ -1,52 +1,1208 @@
 use lazy_static::lazy_static;
-use std::path::PathBuf;
+use parking_lot::Mutex;
+use std::{
+  cmp::Ordering,
+  fmt,
+  io::Write,
+  path::{Path, PathBuf},
+  sync::Arc,
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+  errors::{Errors, Result},
+  fio::IOManager,
+  index::Indexer,
+};
 
 lazy_static! {
   pub static ref DEFAULT_DIR_PATH: PathBuf = std::env::temp_dir().join("flash-kv");
 }
 
+/// Engine options, built with [`Options::builder`] or loaded from a config file.
+///
+/// With the `config` feature options can be read from toml or the environment, fields
+/// left out keep their default.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
 pub struct Options {
   pub dir_path: PathBuf,
 
   pub data_file_size: u64,
 
+  /// Allocate the space of `data_file_size` when a data file becomes active instead of
+  /// growing it with every write, so the disk can not fill up mid-file. The space left is
+  /// released when the file is sealed. Not supported with io_uring
+  pub preallocate_data_file: bool,
+
   pub sync_writes: bool,
 
   pub bytes_per_sync: usize,
 
+  /// How the bytes appended between syncs of the active file are chosen when `sync_writes`
+  /// is off
+  pub sync_policy: SyncPolicy,
+
   pub index_type: IndexType,
 
+  /// Number of independently locked shards of the BTree index, more shards reduce lock
+  /// contention between concurrent writers at the cost of merging shards on scans
+  pub index_shards: usize,
+
+  /// Number of most recently used keys the hybrid index keeps in memory, the other keys are
+  /// spilled to its B+ tree on disk
+  pub index_hot_keys: usize,
+
+  /// IO the data files are scanned through while the index is loaded on open
+  pub startup_io: StartupIo,
+
+  /// `false` scans with [`StartupIo::Runtime`] instead of [`StartupIo::MemoryMap`], other
+  /// startup IO is kept
+  #[deprecated(note = "use `startup_io`")]
   pub mmap_at_startup: bool,
 
+  /// Number of threads decoding sealed data files while the index is loaded on open, their
+  /// records are still applied to the index in file order. 1 scans on the opening thread
+  pub startup_threads: usize,
+
   pub file_merge_threshold: f32,
+
+  /// When to merge besides reaching `file_merge_threshold`
+  pub merge_policy: MergePolicy,
+
+  /// When merges may start and how fast they read the merged files
+  pub merge_scheduler: MergeScheduler,
+
+  /// Verify the checksum footer of sealed data files whose index is loaded from hint file
+  pub verify_file_footer_at_startup: bool,
+
+  /// Recover reads failing on a broken record: read it again through another IO manager,
+  /// then from the output of the last merge, then repair the index from other versions
+  /// of the key
+  pub read_repair: bool,
+
+  /// Open despite corrupt records in the data files: the tail of the active file from the
+  /// first corrupt record on is truncated, corrupt records of older files are left out of
+  /// the index. `Engine::open_with_report` lists what was dropped
+  pub repair_on_open: bool,
+
+  /// Verify the crc32 of the records read by `get` and iterators. Merge and startup always
+  /// verify the records they read
+  pub verify_checksums_on_read: bool,
+
+  /// Implementation of the crc32 computed when verifying records
+  pub crc_impl: CrcImpl,
+
+  /// Byte allowances of key prefixes, writes taking a prefix over its allowance fail with
+  /// `Errors::QuotaExceeded`. A key is checked against every quota whose prefix it starts with
+  pub prefix_quotas: Vec<PrefixQuota>,
+
+  /// Operations taking at least this long are recorded with the time spent in each of their
+  /// phases, see `Engine::take_slow_log`. Zero disables the slow log
+  pub slow_op_threshold: Duration,
+
+  /// Maximum number of old data files kept open, the least recently used ones are closed and
+  /// reopened on read. 0 means unlimited
+  pub max_open_files: usize,
+
+  /// IO of the sealed data files once the engine is loaded
+  pub runtime_io: RuntimeIo,
+
+  /// `true` keeps the sealed data files memory mapped like [`RuntimeIo::MemoryMap`]
+  #[deprecated(note = "use `runtime_io`")]
+  pub mmap_reads: bool,
+
+  /// Place data files and their key directories into numbered subdirectories of this many
+  /// files each, e.g. `000/000000001.data`, so huge databases do not end up with tens of
+  /// thousands of files in one directory. 0 keeps them all in `dir_path`. Files of the other
+  /// layout are moved into place when the engine opens
+  pub files_per_subdir: u32,
+
+  /// Upper bound of the index memory in bytes, new keys are rejected once it is reached.
+  /// 0 means unlimited, only enforced by index types which track their memory (SkipList)
+  pub index_memory_limit: usize,
+
+  /// Record the size and checksum of every sealed data file in a manifest, together with a key
+  /// directory of its records, so opening the engine only scans the active file
+  pub startup_manifest: bool,
+
+  /// Write deletes as markers to a sidecar of the active data file instead of delete records.
+  /// A marker only holds the hash of the key and the position of the deleted record, opening
+  /// the engine drops the keys named by the markers without parsing delete records. Deletes
+  /// of records in files being merged are still written as records
+  pub tombstone_sidecar: bool,
+
+  /// Read and write data files through io_uring, requires the `uring` feature on Linux.
+  /// Files handled by the file pool keep using standard IO
+  pub use_io_uring: bool,
+
+  /// Upper bound of the bytes written per second, writers sleep once it is reached.
+  /// 0 means unlimited
+  pub max_write_rate_bytes_per_sec: u64,
+
+  /// Puts fail with `Errors::Backpressure` while this many bytes wait to be reclaimed by a
+  /// merge. 0 means unlimited
+  pub max_reclaim_backlog: usize,
+
+  /// Puts fail with `Errors::DiskFull` and merges refuse to start while the volume of
+  /// `dir_path` has less than this many bytes free, deletes may still use the reserve. The
+  /// free space is sampled at most once a second unless the writes since may have used up
+  /// the margin. 0 disables the check
+  pub reserved_disk_bytes: u64,
+
+  /// Values larger than this many bytes are written to the blob file of the active data
+  /// file, its record only holds their location. Blob files are read and written through
+  /// standard file IO and removed by the merge with their data file, only the pointer
+  /// records count towards `file_merge_threshold`. 0 keeps all values in the data files
+  pub blob_threshold: usize,
+
+  /// Dump the index into a close hint file on close, the next open loads it instead of
+  /// scanning the data files
+  pub fast_reopen: bool,
+
+  /// Index used instead of the one of `index_type`, which must not be `BPlusTree`.
+  /// The index is rebuilt from the data files on open like the other in-memory indexes
+  #[cfg_attr(feature = "config", serde(skip))]
+  pub custom_indexer: Option<CustomIndexer>,
+
+  /// Wraps the IO manager of every data file the engine opens, e.g. to inject faults in
+  /// tests. Bookkeeping files like hints and the manifest are not wrapped
+  #[cfg_attr(feature = "config", serde(skip))]
+  pub io_manager_wrapper: Option<IOManagerWrapper>,
+
+  /// Open the database read-only next to the process writing it, which keeps the exclusive
+  /// lock. `Engine::refresh` picks up the writes made since the open
+  pub shared_readers: bool,
+
+  /// Open the database without writing anything to its directory, e.g. a backup on
+  /// read-only media: no lock file is taken, interrupted merges and clears are left as they
+  /// are and writes fail with `ReadOnlyEngine`. The database may be opened by a writer too
+  pub read_only: bool,
+
+  /// Content of delete records, `DeletedSize` lets the B+ tree index account the bytes freed
+  /// by deletes written after the last garbage map when the engine was not closed
+  pub tombstone_format: TombstoneFormat,
+
+  /// Where the data files are kept, see [`StorageMode`]
+  pub storage_mode: StorageMode,
+
+  /// Encrypt the key and value of every record written with AES-256-GCM under this key,
+  /// including the records of hint files. Records written without a key stay readable.
+  /// Requires the `encryption` feature and does not work with `startup_manifest`, values of
+  /// encrypted records cannot be streamed by `Engine::get_reader`
+  #[cfg_attr(feature = "config", serde(skip))]
+  pub encryption_key: Option<[u8; 32]>,
+
+  /// Order of the keys returned by iterators, seeks follow it as well. Keys are still told
+  /// apart by their bytes, keys the comparator finds equal come in byte order. Supported by
+  /// the BTree and SkipList indexes, custom indexers keep their own order
+  #[cfg_attr(feature = "config", serde(skip))]
+  pub key_comparator: Option<KeyComparator>,
+
+  /// What the open does with transactions whose commit record is missing, e.g. torn by a
+  /// crash. Prepared multi-engine transactions are left to `MultiEngineBatch::recover`
+  pub orphan_txn_policy: OrphanTxnPolicy,
+
+  /// Tee every committed put, delete, expiration and clear to this output in the framed
+  /// format of [`crate::cdc`], e.g. for ETL jobs. Not supported by readers
+  #[cfg_attr(feature = "config", serde(skip))]
+  pub cdc_output: Option<CdcOutput>,
+
+  /// Periodic snapshots of the engine stat appended to a rolling file, for capacity
+  /// tracking. The task is started by `Engine::start_stats_dump`
+  pub stats_dump: Option<StatsDumpConfig>,
+
+  /// Store the write time in the header of every record, returned by `Engine::get_entry`.
+  /// Merges keep the time of the records they rewrite. Files may hold records with and
+  /// without timestamps, so the option can be changed between opens, but versions before it
+  /// can not read the timestamped records
+  pub store_timestamps: bool,
+}
+
+type IndexerFactory = dyn Fn(&PathBuf) -> Box<dyn Indexer> + Send + Sync;
+
+/// Creates the index of an engine from its directory path.
+#[derive(Clone)]
+pub struct CustomIndexer(Arc<IndexerFactory>);
+
+impl CustomIndexer {
+  pub fn new<F>(factory: F) -> Self
+  where
+    F: Fn(&PathBuf) -> Box<dyn Indexer> + Send + Sync + 'static,
+  {
+    Self(Arc::new(factory))
+  }
+
+  pub(crate) fn create(&self, dir_path: &PathBuf) -> Box<dyn Indexer> {
+    (self.0)(dir_path)
+  }
+}
+
+impl fmt::Debug for CustomIndexer {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("CustomIndexer")
+  }
+}
+
+type KeyOrdering = dyn Fn(&[u8], &[u8]) -> Ordering + Send + Sync;
+
+/// Orders the keys of the index iterators, e.g. case-insensitively.
+#[derive(Clone)]
+pub struct KeyComparator(Arc<KeyOrdering>);
+
+impl KeyComparator {
+  pub fn new<F>(compare: F) -> Self
+  where
+    F: Fn(&[u8], &[u8]) -> Ordering + Send + Sync + 'static,
+  {
+    Self(Arc::new(compare))
+  }
+
+  /// Compares two keys, ties are broken by their bytes so distinct keys never compare equal.
+  pub fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+    (self.0)(a, b).then_with(|| a.cmp(b))
+  }
+}
+
+impl fmt::Debug for KeyComparator {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("KeyComparator")
+  }
+}
+
+type IOManagerFactory = dyn Fn(&Path, Box<dyn IOManager>) -> Box<dyn IOManager> + Send + Sync;
+
+/// Wraps the IO manager of a data file, given the path of the file.
+#[derive(Clone)]
+pub struct IOManagerWrapper(Arc<IOManagerFactory>);
+
+impl IOManagerWrapper {
+  pub fn new<F>(wrapper: F) -> Self
+  where
+    F: Fn(&Path, Box<dyn IOManager>) -> Box<dyn IOManager> + Send + Sync + 'static,
+  {
+    Self(Arc::new(wrapper))
+  }
+
+  pub(crate) fn wrap(
+    &self,
+    file_name: &Path,
+    io_manager: Box<dyn IOManager>,
+  ) -> Box<dyn IOManager> {
+    (self.0)(file_name, io_manager)
+  }
+}
+
+impl fmt::Debug for IOManagerWrapper {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("IOManagerWrapper")
+  }
+}
+
+/// Destination of the change data capture stream, see `Options::cdc_output`.
+#[derive(Debug, Clone)]
+pub enum CdcOutput {
+  /// Frames are appended to the file, sequence numbers continue from its last frame
+  File(PathBuf),
+
+  /// Frames are written to the sink, sequence numbers start at 1 on every open
+  Writer(CdcSink),
+}
+
+/// A writer receiving the change data capture stream.
+#[derive(Clone)]
+pub struct CdcSink(Arc<Mutex<dyn Write + Send>>);
+
+impl CdcSink {
+  pub fn new<W>(writer: W) -> Self
+  where
+    W: Write + Send + 'static,
+  {
+    Self(Arc::new(Mutex::new(writer)))
+  }
+
+  pub(crate) fn write_frames(&self, frames: &[u8]) -> std::io::Result<()> {
+    let mut writer = self.0.lock();
+    writer.write_all(frames)?;
+    writer.flush()
+  }
+}
+
+impl fmt::Debug for CdcSink {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("CdcSink")
+  }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+  feature = "config",
+  derive(serde::Deserialize),
+  serde(rename_all = "lowercase")
+)]
 pub enum IndexType {
   BTree,
 
   SkipList,
 
+  /// Requires the `bptree` feature
   BPlusTree,
+
+  /// BTree of the `Options::index_hot_keys` most recently used keys, the others are kept in
+  /// a B+ tree on disk. Rebuilt from the data files on open like the BTree, requires the
+  /// `bptree` feature
+  Hybrid,
+}
+
+/// Decides when a merge runs. Every policy merges once the reclaimable share of the disk size
+/// reaches `Options::file_merge_threshold`, the others also merge earlier so files with a
+/// little dead data do not stay around forever.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+  feature = "config",
+  derive(serde::Deserialize),
+  serde(rename_all = "snake_case")
+)]
+pub enum MergePolicy {
+  /// Only merge once the threshold is reached
+  Threshold,
+
+  /// Also merge once `max_files` sealed files hold dead data
+  Tiered { max_files: usize },
+
+  /// Also merge once a sealed file older than `max_age` holds dead data
+  TimeWindow { max_age: Duration },
+}
+
+/// Decides how many bytes are appended to the active file between two syncs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+  feature = "config",
+  derive(serde::Deserialize),
+  serde(rename_all = "snake_case")
+)]
+pub enum SyncPolicy {
+  /// Sync every `Options::bytes_per_sync` bytes, 0 leaves syncing to the OS
+  Fixed,
+
+  /// Follow the write rate between `min` and `max` bytes: bursts of writes sync rarely for
+  /// throughput, a trickle of writes is synced after few bytes for durability.
+  /// `Engine::get_engine_stat` reports the current value
+  Adaptive { min: usize, max: usize },
+}
+
+/// IO used to scan the data files while the index is loaded on open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+  feature = "config",
+  derive(serde::Deserialize),
+  serde(rename_all = "snake_case")
+)]
+pub enum StartupIo {
+  /// Scan through the runtime IO of every file, nothing is reopened once the index is loaded
+  Runtime,
+
+  /// Map the data files for the scan and reopen them with their runtime IO afterwards.
+  /// Without the `mmap` feature the files are read with standard file IO
+  MemoryMap,
+
+  /// Read the data files with standard file IO for the scan and reopen them with their
+  /// runtime IO afterwards
+  StandardFileIO,
+}
+
+/// IO of the sealed data files once the engine is loaded, the active file is written
+/// through standard file IO or io_uring as `use_io_uring` decides.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+  feature = "config",
+  derive(serde::Deserialize),
+  serde(rename_all = "snake_case")
+)]
+pub enum RuntimeIo {
+  /// The IO of the active file
+  FileIO,
+
+  /// Keep the sealed data files memory mapped, values read from them are slices of the maps
+  /// instead of copies. Requires the `mmap` feature, does not work with `max_open_files`
+  MemoryMap,
+}
+
+/// Restricts when merges may start and how fast they read, so they do not compete with peak
+/// application traffic.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct MergeScheduler {
+  /// Times of day during which merges may start, an empty list allows any time. A merge
+  /// running past the end of its window is not interrupted
+  pub windows: Vec<MergeWindow>,
+
+  /// Upper bound of the bytes per second read from the merged files. 0 means unlimited,
+  /// `Engine::set_merge_io_limit` changes it at runtime
+  pub io_limit_bytes_per_sec: u64,
+
+  /// Threads scanning the merged files, each one file at a time. With this or
+  /// `filter_threads` above 1 the merge runs as a pipeline of scanning, filtering and
+  /// writing threads, otherwise all on the merging thread
+  pub read_threads: usize,
+
+  /// Threads checking the scanned records against the index, see `read_threads`
+  pub filter_threads: usize,
+}
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+impl MergeScheduler {
+  /// Whether a merge may start at `now`.
+  pub fn allows(&self, now: SystemTime) -> bool {
+    let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let time_of_day = Duration::from_secs(since_epoch.as_secs() % SECS_PER_DAY);
+    self.windows.is_empty()
+      || self
+        .windows
+        .iter()
+        .any(|window| window.contains(time_of_day))
+  }
+}
+
+/// A daily window from `start` to `end`, both offsets from midnight UTC. A window whose end
+/// lies before its start spans midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize))]
+pub struct MergeWindow {
+  pub start: Duration,
+  pub end: Duration,
+}
+
+impl MergeWindow {
+  /// Whether `time_of_day`, an offset from midnight UTC, lies within the window.
+  pub fn contains(&self, time_of_day: Duration) -> bool {
+    match self.start <= self.end {
+      true => self.start <= time_of_day && time_of_day < self.end,
+      false => self.start <= time_of_day || time_of_day < self.end,
+    }
+  }
+}
+
+/// Content of the delete records written by the engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+  feature = "config",
+  derive(serde::Deserialize),
+  serde(rename_all = "snake_case")
+)]
+pub enum TombstoneFormat {
+  /// Delete records hold only the key
+  Empty,
+
+  /// Delete records also hold the file id and size of the record they delete
+  DeletedSize,
+}
+
+/// Handling of the transactions found without commit record on open, they are listed in
+/// `OpenReport::orphaned_txns` either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+  feature = "config",
+  derive(serde::Deserialize),
+  serde(rename_all = "lowercase")
+)]
+pub enum OrphanTxnPolicy {
+  /// Leave their records out of the index, they are reclaimed by the next merge
+  Discard,
+
+  /// Apply their records as if they were committed
+  Apply,
+
+  /// Fail the open with `OrphanedTxnFound`
+  Error,
+}
+
+/// Where an engine keeps its data files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+  feature = "config",
+  derive(serde::Deserialize),
+  serde(rename_all = "lowercase")
+)]
+pub enum StorageMode {
+  /// Data files in `Options::dir_path`
+  Disk,
+
+  /// Data files in memory, dropped with the engine. Nothing is created in `dir_path` and
+  /// no lock is taken, so engines of the same path do not share their data. Merges and
+  /// backups are not supported, neither are the options relying on files (the `BPlusTree`
+  /// and `Hybrid` indexes, `startup_manifest`, `shared_readers` and `read_repair`) or on
+  /// merges (`max_reclaim_backlog` and merge policies other than `Threshold`). Overwritten
+  /// and deleted records are never reclaimed, so the memory used only grows until
+  /// `Engine::clear`
+  Memory,
+}
+
+/// Allowance of the live bytes of the keys starting with a prefix, see
+/// `Options::prefix_quotas`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize))]
+pub struct PrefixQuota {
+  pub prefix: Vec<u8>,
+
+  /// Upper bound of the record bytes of the live keys, headers included
+  pub max_bytes: u64,
+}
+
+/// Rolling file of engine stat snapshots, see `Options::stats_dump`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct StatsDumpConfig {
+  /// File the snapshots are appended to, rolled files get a `.1`, `.2`, ... suffix with `.1`
+  /// the most recent
+  pub path: PathBuf,
+
+  pub format: StatsDumpFormat,
+
+  /// Interval between two snapshots, the first one is taken when the task starts
+  pub interval: Duration,
+
+  /// Size the file may reach before it is rolled, 0 never rolls
+  pub max_file_size: u64,
+
+  /// Number of rolled files kept besides the current one, older ones are removed
+  pub max_files: usize,
+}
+
+impl Default for StatsDumpConfig {
+  fn default() -> Self {
+    Self {
+      path: PathBuf::new(),
+      format: StatsDumpFormat::Csv,
+      interval: Duration::from_secs(60),
+      max_file_size: 64 * 1024 * 1024,
+      max_files: 4,
+    }
+  }
+}
+
+/// Encoding of the snapshots of `StatsDumpConfig`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+  feature = "config",
+  derive(serde::Deserialize),
+  serde(rename_all = "lowercase")
+)]
+pub enum StatsDumpFormat {
+  /// A line of comma separated values per snapshot, each file starts with a header line
+  Csv,
+
+  /// A JSON object per line
+  Json,
+}
+
+/// Implementation of the crc32 checksum of log records, both compute the same checksum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+  feature = "config",
+  derive(serde::Deserialize),
+  serde(rename_all = "snake_case")
+)]
+pub enum CrcImpl {
+  /// crc32fast, which uses the CPU crc instructions where it detects them at runtime and its
+  /// own software fallback elsewhere
+  Hardware,
+
+  /// The table driven crc32 of the `crc` crate, never uses CPU specific instructions
+  Software,
+}
+
+// crc32 of `CrcImpl::Software`, the polynomial crc32fast computes
+static SOFTWARE_CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
+impl CrcImpl {
+  pub(crate) fn hasher(self) -> CrcHasher {
+    match self {
+      CrcImpl::Hardware => CrcHasher::Hardware(crc32fast::Hasher::new()),
+      CrcImpl::Software => CrcHasher::Software(SOFTWARE_CRC.digest()),
+    }
+  }
+}
+
+/// Running crc32 of one of the `CrcImpl`s.
+pub(crate) enum CrcHasher {
+  Hardware(crc32fast::Hasher),
+  Software(crc::Digest<'static, u32>),
+}
+
+impl CrcHasher {
+  pub(crate) fn update(&mut self, buf: &[u8]) {
+    match self {
+      CrcHasher::Hardware(hasher) => hasher.update(buf),
+      CrcHasher::Software(digest) => digest.update(buf),
+    }
+  }
+
+  pub(crate) fn finalize(self) -> u32 {
+    match self {
+      CrcHasher::Hardware(hasher) => hasher.finalize(),
+      CrcHasher::Software(digest) => digest.finalize(),
+    }
+  }
 }
 
 impl Default for Options {
+  #[allow(deprecated)]
   fn default() -> Self {
     Self {
       dir_path: DEFAULT_DIR_PATH.clone(),
       data_file_size: 256 * 1024 * 1024, // 256MB
+      preallocate_data_file: false,
       sync_writes: false,
       bytes_per_sync: 0,
+      sync_policy: SyncPolicy::Fixed,
       index_type: IndexType::BTree,
+      index_shards: 1,
+      index_hot_keys: 1 << 20,
+      startup_io: match cfg!(feature = "mmap") {
+        true => StartupIo::MemoryMap,
+        false => StartupIo::Runtime,
+      },
       mmap_at_startup: true,
+      startup_threads: 1,
       file_merge_threshold: 0.6,
+      merge_policy: MergePolicy::Threshold,
+      merge_scheduler: MergeScheduler::default(),
+      verify_file_footer_at_startup: false,
+      read_repair: false,
+      repair_on_open: false,
+      index_memory_limit: 0,
+      verify_checksums_on_read: true,
+      crc_impl: CrcImpl::Hardware,
+      prefix_quotas: Vec::new(),
+      slow_op_threshold: Duration::ZERO,
+      max_open_files: 0,
+      runtime_io: RuntimeIo::FileIO,
+      mmap_reads: false,
+      files_per_subdir: 0,
+      startup_manifest: false,
+      tombstone_sidecar: false,
+      use_io_uring: false,
+      max_write_rate_bytes_per_sec: 0,
+      max_reclaim_backlog: 0,
+      reserved_disk_bytes: 0,
+      blob_threshold: 0,
+      fast_reopen: false,
+      custom_indexer: None,
+      io_manager_wrapper: None,
+      shared_readers: false,
+      read_only: false,
+      tombstone_format: TombstoneFormat::Empty,
+      storage_mode: StorageMode::Disk,
+      encryption_key: None,
+      key_comparator: None,
+      orphan_txn_policy: OrphanTxnPolicy::Discard,
+      cdc_output: None,
+      stats_dump: None,
+      store_timestamps: false,
     }
   }
 }
+
+/// Builder of [`Options`], fields not set keep their default.
+#[derive(Debug, Clone, Default)]
+pub struct OptionsBuilder {
+  opts: Options,
+}
+
+impl Options {
+  pub fn builder() -> OptionsBuilder {
+    OptionsBuilder::default()
+  }
+
+  /// Parses options from a toml document, see [`Options`] for the keys.
+  #[cfg(feature = "config")]
+  pub fn from_toml(content: &str) -> Result<Self> {
+    let opts: Options = toml::from_str(content).map_err(|e| {
+      log::error!("failed to parse options: {e}");
+      Errors::InvalidOptionsConfig
+    })?;
+    opts.validate()?;
+    Ok(opts)
+  }
+
+  /// Reads options from `FLASH_KV_` prefixed environment variables, e.g. `FLASH_KV_DIR_PATH`
+  /// or `FLASH_KV_INDEX_TYPE=skiplist`.
+  #[cfg(feature = "config")]
+  pub fn from_env() -> Result<Self> {
+    Self::from_vars(std::env::vars())
+  }
+
+  #[cfg(feature = "config")]
+  fn from_vars<I>(vars: I) -> Result<Self>
+  where
+    I: IntoIterator<Item = (String, String)>,
+  {
+    let opts: Options = envy::prefixed("FLASH_KV_").from_iter(vars).map_err(|e| {
+      log::error!("failed to read options from environment: {e}");
+      Errors::InvalidOptionsConfig
+    })?;
+    opts.validate()?;
+    Ok(opts)
+  }
+
+  /// Checks the options, including combinations of fields which the engine can not honour.
+  pub fn validate(&self) -> Result<()> {
+    match self.dir_path.to_str() {
+      Some(dir_path) if !dir_path.is_empty() => {}
+      _ => return Err(Errors::DirPathIsEmpty),
+    }
+
+    if self.data_file_size == 0 {
+      return Err(Errors::DataFileSizeTooSmall);
+    }
+
+    if self.index_shards == 0 {
+      return Err(Errors::InvalidIndexShards);
+    }
+
+    if self.file_merge_threshold < 0f32 || self.file_merge_threshold > 1f32 {
+      return Err(Errors::InvalidMergeThreshold);
+    }
+
+    if self.merge_policy == (MergePolicy::Tiered { max_files: 0 }) {
+      return Err(Errors::InvalidMergePolicy);
+    }
+
+    if self.startup_threads == 0 {
+      return Err(Errors::InvalidStartupThreads);
+    }
+
+    if let SyncPolicy::Adaptive { min, max } = self.sync_policy {
+      if min == 0 || min > max {
+        return Err(Errors::InvalidSyncPolicy);
+      }
+    }
+
+    let day = Duration::from_secs(SECS_PER_DAY);
+    if (self.merge_scheduler.windows.iter()).any(|window| window.start >= day || window.end > day) {
+      return Err(Errors::InvalidMergeWindow);
+    }
+
+    let on_disk_index = matches!(self.index_type, IndexType::BPlusTree | IndexType::Hybrid);
+    if on_disk_index && !cfg!(feature = "bptree") {
+      return Err(Errors::IndexTypeUnsupported);
+    }
+
+    if self.index_type == IndexType::Hybrid && self.index_hot_keys == 0 {
+      return Err(Errors::InvalidIndexHotKeys);
+    }
+
+    if self.index_shards > 1 && self.index_type != IndexType::BTree {
+      return Err(Errors::IndexShardsUnsupported);
+    }
+
+    if self.index_memory_limit > 0 && self.index_type != IndexType::SkipList {
+      return Err(Errors::IndexMemoryLimitUnsupported);
+    }
+
+    // the engine relies on the b+ tree index being persisted
+    if self.custom_indexer.is_some() && self.index_type == IndexType::BPlusTree {
+      return Err(Errors::CustomIndexerUnsupported);
+    }
+
+    // the b+ tree keeps its keys in byte order on disk
+    if self.key_comparator.is_some() && on_disk_index {
+      return Err(Errors::KeyComparatorUnsupported);
+    }
+
+    // the b+ tree index file is owned by the writer
+    if self.shared_readers && on_disk_index {
+      return Err(Errors::SharedReadersUnsupported);
+    }
+
+    // pooled files are closed and reopened on demand, memory has no files to map
+    let mappable = cfg!(feature = "mmap") && self.storage_mode == StorageMode::Disk;
+    if self.resolved_runtime_io() == RuntimeIo::MemoryMap && (!mappable || self.max_open_files > 0)
+    {
+      return Err(Errors::MmapReadsUnsupported);
+    }
+
+    // preallocated files are written through standard file IO
+    let preallocatable = self.storage_mode == StorageMode::Disk && !self.use_io_uring;
+    if self.preallocate_data_file && !preallocatable {
+      return Err(Errors::PreallocationUnsupported);
+    }
+
+    // readers only scan the data files written since their last refresh
+    let replayed = self.storage_mode == StorageMode::Disk && !on_disk_index;
+    if self.tombstone_sidecar && (!replayed || self.shared_readers) {
+      return Err(Errors::TombstoneSidecarUnsupported);
+    }
+
+    // nothing may be written besides the data files
+    if self.read_only && (!replayed || self.repair_on_open) {
+      return Err(Errors::ReadOnlyUnsupported);
+    }
+
+    // the data files are repaired by the writer
+    if self.shared_readers && self.repair_on_open {
+      return Err(Errors::RepairOnOpenUnsupported);
+    }
+
+    // only the writer sees the committed writes
+    if self.cdc_output.is_some() && (self.read_only || self.shared_readers) {
+      return Err(Errors::CdcOutputUnsupported);
+    }
+
+    if let Some(stats_dump) = &self.stats_dump {
+      if stats_dump.path.as_os_str().is_empty() || stats_dump.interval.is_zero() {
+        return Err(Errors::InvalidStatsDump);
+      }
+    }
+
+    let needs_files = on_disk_index
+      || self.startup_manifest
+      || self.shared_readers
+      || self.blob_threshold > 0
+      || self.reserved_disk_bytes > 0;
+    if self.storage_mode == StorageMode::Memory && (needs_files || self.read_repair) {
+      return Err(Errors::MemoryModeUnsupported);
+    }
+    // nothing merges in memory, a backlog limit would reject all puts once reached
+    let needs_merge = self.max_reclaim_backlog > 0 || self.merge_policy != MergePolicy::Threshold;
+    if self.storage_mode == StorageMode::Memory && needs_merge {
+      return Err(Errors::MemoryModeUnsupported);
+    }
+
+    // key directories of the manifest hold the keys in the clear
+    let encryption = cfg!(feature = "encryption") && !self.startup_manifest;
+    if self.encryption_key.is_some() && !encryption {
+      return Err(Errors::EncryptionUnsupported);
+    }
+
+    Ok(())
+  }
+
+  /// IO type of data files once the engine is loaded.
+  pub(crate) fn file_io_type(&self) -> IOManagerType {
+    if self.storage_mode == StorageMode::Memory {
+      return IOManagerType::Memory;
+    }
+    if self.read_only {
+      return IOManagerType::ReadOnlyFileIO;
+    }
+    match self.use_io_uring {
+      true => IOManagerType::IoUring,
+      false => IOManagerType::StandardFileIO,
+    }
+  }
+
+  /// IO type of sealed data files once the engine is loaded.
+  pub(crate) fn sealed_file_io_type(&self) -> IOManagerType {
+    match self.resolved_runtime_io() {
+      RuntimeIo::MemoryMap => IOManagerType::MemoryMap,
+      RuntimeIo::FileIO => self.file_io_type(),
+    }
+  }
+
+  /// Startup IO with the deprecated `mmap_at_startup` applied.
+  #[allow(deprecated)]
+  pub(crate) fn resolved_startup_io(&self) -> StartupIo {
+    match self.startup_io {
+      StartupIo::MemoryMap if !self.mmap_at_startup => StartupIo::Runtime,
+      startup_io => startup_io,
+    }
+  }
+
+  /// Runtime IO with the deprecated `mmap_reads` applied.
+  #[allow(deprecated)]
+  pub(crate) fn resolved_runtime_io(&self) -> RuntimeIo {
+    match self.mmap_reads {
+      true => RuntimeIo::MemoryMap,
+      false => self.runtime_io,
+    }
+  }
+
+  /// IO type of all data files while the index is loaded, None if they are opened with
+  /// their runtime IO type right away.
+  pub(crate) fn startup_file_io_type(&self) -> Option<IOManagerType> {
+    match self.resolved_startup_io() {
+      StartupIo::Runtime => None,
+      StartupIo::MemoryMap => Some(IOManagerType::MemoryMap),
+      StartupIo::StandardFileIO if self.read_only => Some(IOManagerType::ReadOnlyFileIO),
+      StartupIo::StandardFileIO => Some(IOManagerType::StandardFileIO),
+    }
+  }
+}
+
+impl OptionsBuilder {
+  pub fn dir_path<P: Into<PathBuf>>(mut self, dir_path: P) -> Self {
+    self.opts.dir_path = dir_path.into();
+    self
+  }
+
+  pub fn data_file_size(mut self, data_file_size: u64) -> Self {
+    self.opts.data_file_size = data_file_size;
+    self
+  }
+
+  pub fn preallocate_data_file(mut self, preallocate_data_file: bool) -> Self {
+    self.opts.preallocate_data_file = preallocate_data_file;
+    self
+  }
+
+  pub fn sync_policy(mut self, sync_policy: SyncPolicy) -> Self {
+    self.opts.sync_policy = sync_policy;
+    self
+  }
+
+  pub fn sync_writes(mut self, sync_writes: bool) -> Self {
+    self.opts.sync_writes = sync_writes;
+    self
+  }
+
+  pub fn bytes_per_sync(mut self, bytes_per_sync: usize) -> Self {
+    self.opts.bytes_per_sync = bytes_per_sync;
+    self
+  }
+
+  pub fn index_type(mut self, index_type: IndexType) -> Self {
+    self.opts.index_type = index_type;
+    self
+  }
+
+  pub fn index_shards(mut self, index_shards: usize) -> Self {
+    self.opts.index_shards = index_shards;
+    self
+  }
+
+  pub fn index_hot_keys(mut self, index_hot_keys: usize) -> Self {
+    self.opts.index_hot_keys = index_hot_keys;
+    self
+  }
+
+  pub fn startup_io(mut self, startup_io: StartupIo) -> Self {
+    self.opts.startup_io = startup_io;
+    self
+  }
+
+  /// Shorthand for [`StartupIo::MemoryMap`] or [`StartupIo::Runtime`].
+  pub fn mmap_at_startup(mut self, mmap_at_startup: bool) -> Self {
+    self.opts.startup_io = match mmap_at_startup {
+      true => StartupIo::MemoryMap,
+      false => StartupIo::Runtime,
+    };
+    self
+  }
+
+  pub fn startup_threads(mut self, startup_threads: usize) -> Self {
+    self.opts.startup_threads = startup_threads;
+    self
+  }
+
+  pub fn file_merge_threshold(mut self, file_merge_threshold: f32) -> Self {
+    self.opts.file_merge_threshold = file_merge_threshold;
+    self
+  }
+
+  pub fn merge_policy(mut self, merge_policy: MergePolicy) -> Self {
+    self.opts.merge_policy = merge_policy;
+    self
+  }
+
+  pub fn merge_scheduler(mut self, merge_scheduler: MergeScheduler) -> Self {
+    self.opts.merge_scheduler = merge_scheduler;
+    self
+  }
+
+  pub fn verify_file_footer_at_startup(mut self, verify: bool) -> Self {
+    self.opts.verify_file_footer_at_startup = verify;
+    self
+  }
+
+  pub fn read_repair(mut self, read_repair: bool) -> Self {
+    self.opts.read_repair = read_repair;
+    self
+  }
+
+  pub fn repair_on_open(mut self, repair_on_open: bool) -> Self {
+    self.opts.repair_on_open = repair_on_open;
+    self
+  }
+
+  pub fn verify_checksums_on_read(mut self, verify_checksums_on_read: bool) -> Self {
+    self.opts.verify_checksums_on_read = verify_checksums_on_read;
+    self
+  }
+
+  pub fn crc_impl(mut self, crc_impl: CrcImpl) -> Self {
+    self.opts.crc_impl = crc_impl;
+    self
+  }
+
+  /// Adds a quota of `max_bytes` live bytes to the keys starting with `prefix`.
+  pub fn prefix_quota(mut self, prefix: impl Into<Vec<u8>>, max_bytes: u64) -> Self {
+    self.opts.prefix_quotas.push(PrefixQuota {
+      prefix: prefix.into(),
+      max_bytes,
+    });
+    self
+  }
+
+  pub fn slow_op_threshold(mut self, slow_op_threshold: Duration) -> Self {
+    self.opts.slow_op_threshold = slow_op_threshold;
+    self
+  }
+
+  pub fn runtime_io(mut self, runtime_io: RuntimeIo) -> Self {
+    self.opts.runtime_io = runtime_io;
+    self
+  }
+
+  /// Shorthand for [`RuntimeIo::MemoryMap`] or [`RuntimeIo::FileIO`].
+  pub fn mmap_reads(mut self, mmap_reads: bool) -> Self {
+    self.opts.runtime_io = match mmap_reads {
+      true => RuntimeIo::MemoryMap,
+      false => RuntimeIo::FileIO,
+    };
+    self
+  }
+
+  pub fn max_open_files(mut self, max_open_files: usize) -> Self {
+    self.opts.max_open_files = max_open_files;
+    self
+  }
+
+  pub fn files_per_subdir(mut self, files_per_subdir: u32) -> Self {
+    self.opts.files_per_subdir = files_per_subdir;
+    self
+  }
+
+  pub fn index_memory_limit(mut self, index_memory_limit: usize) -> Self {
+    self.opts.index_memory_limit = index_memory_limit;
+    self
+  }
+
+  pub fn startup_manifest(mut self, startup_manifest: bool) -> Self {
+    self.opts.startup_manifest = startup_manifest;
+    self
+  }
+
+  pub fn tombstone_sidecar(mut self, tombstone_sidecar: bool) -> Self {
+    self.opts.tombstone_sidecar = tombstone_sidecar;
+    self
+  }
+
+  pub fn use_io_uring(mut self, use_io_uring: bool) -> Self {
+    self.opts.use_io_uring = use_io_uring;
+    self
+  }
+
+  pub fn max_write_rate_bytes_per_sec(mut self, bytes_per_sec: u64) -> Self {
+    self.opts.max_write_rate_bytes_per_sec = bytes_per_sec;
+    self
+  }
+
+  pub fn max_reclaim_backlog(mut self, max_reclaim_backlog: usize) -> Self {
+    self.opts.max_reclaim_backlog = max_reclaim_backlog;
+    self
+  }
+
+  pub fn reserved_disk_bytes(mut self, reserved_disk_bytes: u64) -> Self {
+    self.opts.reserved_disk_bytes = reserved_disk_bytes;
+    self
+  }
+
+  pub fn blob_threshold(mut self, blob_threshold: usize) -> Self {
+    self.opts.blob_threshold = blob_threshold;
+    self
+  }
+
+  pub fn fast_reopen(mut self, fast_reopen: bool) -> Self {
+    self.opts.fast_reopen = fast_reopen;
+    self
+  }
+
+  pub fn custom_indexer(mut self, custom_indexer: CustomIndexer) -> Self {
+    self.opts.custom_indexer = Some(custom_indexer);
+    self
+  }
+
+  pub fn io_manager_wrapper(mut self, io_manager_wrapper: IOManagerWrapper) -> Self {
+    self.opts.io_manager_wrapper = Some(io_manager_wrapper);
+    self
+  }
+
+  pub fn shared_readers(mut self, shared_readers: bool) -> Self {
+    self.opts.shared_readers = shared_readers;
+    self
+  }
+
+  pub fn read_only(mut self, read_only: bool) -> Self {
+    self.opts.read_only = read_only;
+    self
+  }
+
+  pub fn tombstone_format(mut self, tombstone_format: TombstoneFormat) -> Self {
+    self.opts.tombstone_format = tombstone_format;
+    self
+  }
+
+  pub fn storage_mode(mut self, storage_mode: StorageMode) -> Self {
+    self.opts.storage_mode = storage_mode;
+    self
+  }
+
+  pub fn encryption_key(mut self, encryption_key: [u8; 32]) -> Self {
+    self.opts.encryption_key = Some(encryption_key);
+    self
+  }
+
+  pub fn key_comparator(mut self, key_comparator: KeyComparator) -> Self {
+    self.opts.key_comparator = Some(key_comparator);
+    self
+  }
+
+  pub fn orphan_txn_policy(mut self, orphan_txn_policy: OrphanTxnPolicy) -> Self {
+    self.opts.orphan_txn_policy = orphan_txn_policy;
+    self
+  }
+
+  pub fn cdc_output(mut self, cdc_output: CdcOutput) -> Self {
+    self.opts.cdc_output = Some(cdc_output);
+    self
+  }
+
+  pub fn store_timestamps(mut self, store_timestamps: bool) -> Self {
+    self.opts.store_timestamps = store_timestamps;
+    self
+  }
+
+  pub fn stats_dump(mut self, stats_dump: StatsDumpConfig) -> Self {
+    self.opts.stats_dump = Some(stats_dump);
+    self
+  }
+
+  /// Validates and returns the options.
+  pub fn build(self) -> Result<Options> {
+    self.opts.validate()?;
+    Ok(self.opts)
+  }
+}
+
+#[derive(Clone)]
 pub struct IteratorOptions {
   pub prefix: Vec<u8>,
   pub reverse: bool,
+
+  /// Maximum number of pairs returned by the engine iterators, `None` means unlimited
+  pub limit: Option<usize>,
+
+  /// Start with the key following this one in iteration order, e.g. the last key of the
+  /// previous page. Applied by the engine iterators, index iterators ignore it
+  pub start_after: Option<Vec<u8>>,
+
+  /// Leave the values on disk: the engine iterators, `fold` and `for_each` return empty
+  /// values, so listing keys only walks the index. See `Iterator::next_key` for the record
+  /// sizes
+  pub keys_only: bool,
 }
 
 #[allow(clippy::derivable_impls)]
@@ -55,13 +1211,19 @@ impl Default for IteratorOptions {
     Self {
       prefix: Default::default(),
       reverse: false,
+      limit: None,
+      start_after: None,
+      keys_only: false,
     }
   }
 }
 
 pub struct WriteBatchOptions {
   pub max_batch_num: usize,
-  
+
+  /// Upper bound of the key and value bytes committed in one transaction. 0 means unlimited
+  pub max_batch_bytes: usize,
+
   pub sync_writes: bool,
 }
 
@@ -69,14 +1231,332 @@ impl Default for WriteBatchOptions {
   fn default() -> Self {
     Self {
       max_batch_num: 1000,
+      max_batch_bytes: 0,
       sync_writes: true,
     }
   }
 }
 
+/// Tunables of an open engine, changed with [`crate::db::Engine::update_options`]. Fields
+/// left `None` keep their current value.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RuntimeOptions {
+  /// See [`Options::sync_policy`]
+  pub sync_policy: Option<SyncPolicy>,
+
+  /// See [`Options::bytes_per_sync`]
+  pub bytes_per_sync: Option<usize>,
+
+  /// See [`Options::file_merge_threshold`]
+  pub file_merge_threshold: Option<f32>,
+
+  /// See [`Options::max_write_rate_bytes_per_sec`]
+  pub max_write_rate_bytes_per_sec: Option<u64>,
+
+  /// See [`MergeScheduler::io_limit_bytes_per_sec`]
+  pub merge_io_limit_bytes_per_sec: Option<u64>,
+
+  /// See [`Options::index_hot_keys`], only the hybrid index keeps part of its keys in memory
+  pub index_hot_keys: Option<usize>,
+}
+
+impl RuntimeOptions {
+  /// The tunables of `options`, every field is set.
+  pub(crate) fn new(options: &Options) -> Self {
+    Self {
+      sync_policy: Some(options.sync_policy),
+      bytes_per_sync: Some(options.bytes_per_sync),
+      file_merge_threshold: Some(options.file_merge_threshold),
+      max_write_rate_bytes_per_sec: Some(options.max_write_rate_bytes_per_sec),
+      merge_io_limit_bytes_per_sec: Some(options.merge_scheduler.io_limit_bytes_per_sec),
+      index_hot_keys: Some(options.index_hot_keys),
+    }
+  }
+
+  /// Overwrites the fields of `options` which are set here.
+  pub(crate) fn apply_to(&self, options: &mut Options) {
+    if let Some(sync_policy) = self.sync_policy {
+      options.sync_policy = sync_policy;
+    }
+    if let Some(bytes_per_sync) = self.bytes_per_sync {
+      options.bytes_per_sync = bytes_per_sync;
+    }
+    if let Some(threshold) = self.file_merge_threshold {
+      options.file_merge_threshold = threshold;
+    }
+    if let Some(bytes_per_sec) = self.max_write_rate_bytes_per_sec {
+      options.max_write_rate_bytes_per_sec = bytes_per_sec;
+    }
+    if let Some(bytes_per_sec) = self.merge_io_limit_bytes_per_sec {
+      options.merge_scheduler.io_limit_bytes_per_sec = bytes_per_sec;
+    }
+    if let Some(hot_keys) = self.index_hot_keys {
+      options.index_hot_keys = hot_keys;
+    }
+  }
+}
+
+/// Options of [`crate::db::Engine::get_engine_stat_with`].
+#[derive(Debug, Clone, Copy)]
+pub struct StatOptions {
+  /// Estimates `disk_size` from the last measured size and the writes to the active file
+  /// since, the directory is only walked again after a rotation, merge or clear
+  pub approximate: bool,
+}
+
+impl Default for StatOptions {
+  fn default() -> Self {
+    Self { approximate: true }
+  }
+}
+
+/// Options of [`crate::db::Engine::bulk_load`].
+#[derive(Debug, Clone, Copy)]
+pub struct BulkLoadOptions {
+  /// Pairs appended in one write and put into the index together
+  pub batch_size: usize,
+
+  /// The keys are in ascending order without duplicates, the index builds them in one pass.
+  /// A key out of order fails the load
+  pub sorted: bool,
+}
+
+impl Default for BulkLoadOptions {
+  fn default() -> Self {
+    Self {
+      batch_size: 4096,
+      sorted: false,
+    }
+  }
+}
+
+/// Whether [`crate::batch::WriteBatch::commit_chunked`] may split a batch into several
+/// transactions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkAtomicity {
+  /// Each chunk is committed atomically on its own, a failing chunk leaves the chunks
+  /// committed before it in place
+  PerChunk,
+
+  /// Refuse batches which do not fit into a single transaction
+  AllOrNothing,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum IOManagerType {
   StandardFileIO,
 
+  /// Standard file IO over an existing file opened for reading only, used by
+  /// [`Options::read_only`] engines
+  ReadOnlyFileIO,
+
   MemoryMap,
+
+  /// Requires the `uring` feature on Linux, standard file IO otherwise
+  IoUring,
+
+  /// Content kept in memory, the file name is ignored
+  Memory,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_options_builder() {
+    let opts = Options::builder()
+      .dir_path("/tmp/flash-kv-builder")
+      .data_file_size(1024)
+      .index_type(IndexType::SkipList)
+      .index_memory_limit(4096)
+      .build()
+      .unwrap();
+    assert_eq!(PathBuf::from("/tmp/flash-kv-builder"), opts.dir_path);
+    assert_eq!(1024, opts.data_file_size);
+    assert_eq!(0.6, opts.file_merge_threshold);
+
+    assert_eq!(
+      Some(Errors::DataFileSizeTooSmall),
+      Options::builder().data_file_size(0).build().err()
+    );
+    assert_eq!(
+      Some(Errors::InvalidMergeThreshold),
+      Options::builder().file_merge_threshold(1.5).build().err()
+    );
+    let hot_keys_err = match cfg!(feature = "bptree") {
+      true => Errors::InvalidIndexHotKeys,
+      false => Errors::IndexTypeUnsupported,
+    };
+    assert_eq!(
+      Some(hot_keys_err),
+      Options::builder()
+        .index_type(IndexType::Hybrid)
+        .index_hot_keys(0)
+        .build()
+        .err()
+    );
+    // options which are only honoured by some index types
+    assert_eq!(
+      Some(Errors::IndexShardsUnsupported),
+      Options::builder()
+        .index_type(IndexType::SkipList)
+        .index_shards(4)
+        .build()
+        .err()
+    );
+    assert_eq!(
+      Some(Errors::IndexMemoryLimitUnsupported),
+      Options::builder().index_memory_limit(4096).build().err()
+    );
+    // options which need files on disk
+    assert_eq!(
+      Some(Errors::MemoryModeUnsupported),
+      Options::builder()
+        .storage_mode(StorageMode::Memory)
+        .startup_manifest(true)
+        .build()
+        .err()
+    );
+    assert_eq!(
+      Some(Errors::MemoryModeUnsupported),
+      Options::builder()
+        .storage_mode(StorageMode::Memory)
+        .blob_threshold(4096)
+        .build()
+        .err()
+    );
+    // options which need merges
+    assert_eq!(
+      Some(Errors::MemoryModeUnsupported),
+      Options::builder()
+        .storage_mode(StorageMode::Memory)
+        .max_reclaim_backlog(1024)
+        .build()
+        .err()
+    );
+    assert_eq!(
+      Some(Errors::MemoryModeUnsupported),
+      Options::builder()
+        .storage_mode(StorageMode::Memory)
+        .merge_policy(MergePolicy::Tiered { max_files: 4 })
+        .build()
+        .err()
+    );
+    assert_eq!(
+      Some(Errors::EncryptionUnsupported),
+      Options::builder()
+        .encryption_key([1; 32])
+        .startup_manifest(true)
+        .build()
+        .err()
+    );
+  }
+
+  #[test]
+  #[allow(deprecated, clippy::field_reassign_with_default)]
+  fn test_deprecated_mmap_options() {
+    let mut opts = Options::default();
+    opts.startup_io = StartupIo::MemoryMap;
+    assert_eq!(StartupIo::MemoryMap, opts.resolved_startup_io());
+    opts.mmap_at_startup = false;
+    assert_eq!(StartupIo::Runtime, opts.resolved_startup_io());
+    opts.startup_io = StartupIo::StandardFileIO;
+    assert_eq!(StartupIo::StandardFileIO, opts.resolved_startup_io());
+
+    assert_eq!(RuntimeIo::FileIO, opts.resolved_runtime_io());
+    opts.mmap_reads = true;
+    assert_eq!(RuntimeIo::MemoryMap, opts.resolved_runtime_io());
+  }
+
+  #[test]
+  fn test_merge_scheduler_windows() {
+    let hours = |h: u64| Duration::from_secs(h * 60 * 60);
+    let at = |h| UNIX_EPOCH + Duration::from_secs(20 * SECS_PER_DAY) + hours(h);
+    assert!(MergeScheduler::default().allows(at(12)));
+
+    // 01:00 to 05:00 and 22:00 to 02:00 across midnight
+    let scheduler = MergeScheduler {
+      windows: vec![
+        MergeWindow {
+          start: hours(1),
+          end: hours(5),
+        },
+        MergeWindow {
+          start: hours(22),
+          end: hours(2),
+        },
+      ],
+      ..Default::default()
+    };
+    assert!(scheduler.allows(at(0)));
+    assert!(scheduler.allows(at(4)));
+    assert!(scheduler.allows(at(23)));
+    assert!(!scheduler.allows(at(5)));
+    assert!(!scheduler.allows(at(12)));
+
+    let scheduler = MergeScheduler {
+      windows: vec![MergeWindow {
+        start: hours(24),
+        end: hours(1),
+      }],
+      ..Default::default()
+    };
+    assert_eq!(
+      Some(Errors::InvalidMergeWindow),
+      Options::builder().merge_scheduler(scheduler).build().err()
+    );
+  }
+
+  #[cfg(feature = "config")]
+  #[test]
+  fn test_options_from_toml() {
+    let opts = Options::from_toml(
+      r#"
+      dir_path = "/tmp/flash-kv-toml"
+      data_file_size = 4096
+      sync_writes = true
+      index_type = "skiplist"
+      "#,
+    )
+    .unwrap();
+    assert_eq!(PathBuf::from("/tmp/flash-kv-toml"), opts.dir_path);
+    assert_eq!(4096, opts.data_file_size);
+    assert!(opts.sync_writes);
+    assert_eq!(IndexType::SkipList, opts.index_type);
+    assert_eq!(1, opts.index_shards);
+
+    assert_eq!(
+      Some(Errors::InvalidOptionsConfig),
+      Options::from_toml("data_file_size = \"big\"").err()
+    );
+    assert_eq!(
+      Some(Errors::DataFileSizeTooSmall),
+      Options::from_toml("data_file_size = 0").err()
+    );
+  }
+
+  #[cfg(feature = "config")]
+  #[test]
+  fn test_options_from_vars() {
+    let vars = [
+      ("FLASH_KV_DIR_PATH", "/tmp/flash-kv-env"),
+      ("FLASH_KV_INDEX_SHARDS", "8"),
+      ("FLASH_KV_FILE_MERGE_THRESHOLD", "0.3"),
+      ("FLASH_KV_READ_REPAIR", "true"),
+      ("HOME", "/root"),
+    ]
+    .map(|(k, v)| (k.to_string(), v.to_string()));
+    let opts = Options::from_vars(vars).unwrap();
+    assert_eq!(PathBuf::from("/tmp/flash-kv-env"), opts.dir_path);
+    assert_eq!(8, opts.index_shards);
+    assert_eq!(0.3, opts.file_merge_threshold);
+    assert!(opts.read_repair);
+
+    let vars = [("FLASH_KV_INDEX_TYPE", "hashmap")].map(|(k, v)| (k.to_string(), v.to_string()));
+    assert_eq!(
+      Some(Errors::InvalidOptionsConfig),
+      Options::from_vars(vars).err()
+    );
+  }
 }
@@ -0,0 +1,208 @@
+use parking_lot::Mutex;
+
+use crate::{
+  data::log_record::LogRecordPos,
+  db::Engine,
+  errors::{Errors, Result},
+  option::PrefixQuota,
+};
+
+/// Bytes taken by the live records under a quota prefix, see [`Engine::quota_usage`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotaUsage {
+  pub quota: PrefixQuota,
+
+  /// Live bytes of the keys under the prefix, record headers included
+  pub used_bytes: u64,
+}
+
+/// Live bytes of every configured quota, kept up to date by the writes and recounted from
+/// the index on open, merge and clear.
+pub(crate) struct QuotaManager {
+  quotas: Vec<PrefixQuota>,
+  used: Mutex<Vec<u64>>, // live bytes of `quotas[i]`
+}
+
+impl QuotaManager {
+  pub(crate) fn new(quotas: Vec<PrefixQuota>) -> Self {
+    let used = Mutex::new(vec![0; quotas.len()]);
+    Self { quotas, used }
+  }
+
+  // indices of the quotas whose prefix the key starts with
+  fn matching<'a>(&'a self, key: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+    self
+      .quotas
+      .iter()
+      .enumerate()
+      .filter(move |(_, quota)| key.starts_with(&quota.prefix))
+      .map(|(i, _)| i)
+  }
+}
+
+impl Engine {
+  /// Usage of the quotas of `Options::prefix_quotas`.
+  pub fn quota_usage(&self) -> Vec<QuotaUsage> {
+    let used = self.quotas.used.lock();
+    self
+      .quotas
+      .quotas
+      .iter()
+      .zip(used.iter())
+      .map(|(quota, used_bytes)| QuotaUsage {
+        quota: quota.clone(),
+        used_bytes: *used_bytes,
+      })
+      .collect()
+  }
+
+  /// Rejects writes which would take a prefix over its quota, `writes` are the keys written
+  /// with the size of their new records. Overwritten records are deducted.
+  ///
+  /// Concurrent writes are checked independently, together they may exceed a quota by
+  /// their own size.
+  pub(crate) fn check_quota<'a, I>(&self, writes: I) -> Result<()>
+  where
+    I: IntoIterator<Item = (&'a [u8], usize)>,
+  {
+    if self.quotas.quotas.is_empty() {
+      return Ok(());
+    }
+    let mut used = self.quotas.used.lock().clone();
+    for (key, size) in writes {
+      let old_size = match self.quotas.matching(key).next() {
+        Some(_) => self
+          .index
+          .get(key.to_vec())
+          .map_or(0, |pos| pos.size as u64),
+        None => continue,
+      };
+      for i in self.quotas.matching(key) {
+        used[i] = (used[i] + size as u64).saturating_sub(old_size);
+        if used[i] > self.quotas.quotas[i].max_bytes {
+          return Err(Errors::QuotaExceeded);
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// Accounts the index update of a key from `old_pos` to `new_pos`.
+  pub(crate) fn account_quota(
+    &self,
+    key: &[u8],
+    old_pos: Option<LogRecordPos>,
+    new_pos: Option<LogRecordPos>,
+  ) {
+    if self.quotas.quotas.is_empty() {
+      return;
+    }
+    let mut used = self.quotas.used.lock();
+    for i in self.quotas.matching(key) {
+      used[i] += new_pos.map_or(0, |pos| pos.size as u64);
+      used[i] = used[i].saturating_sub(old_pos.map_or(0, |pos| pos.size as u64));
+    }
+  }
+
+  /// Recounts the usage of every quota from the index.
+  pub(crate) fn refresh_quota_usage(&self) -> Result<()> {
+    for (i, quota) in self.quotas.quotas.iter().enumerate() {
+      let live_bytes = self.prefix_stat(&quota.prefix)?.live_bytes;
+      self.quotas.used.lock()[i] = live_bytes;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use bytes::Bytes;
+
+  use super::*;
+  use crate::option::{Options, WriteBatchOptions};
+
+  #[test]
+  fn test_prefix_quota() {
+    let dir = tempfile::tempdir().unwrap();
+    let opts = Options {
+      dir_path: dir.path().to_path_buf(),
+      prefix_quotas: vec![
+        PrefixQuota {
+          prefix: b"tenant-a:".to_vec(),
+          max_bytes: 4096,
+        },
+        PrefixQuota {
+          prefix: b"tenant-a:logs:".to_vec(),
+          max_bytes: 256,
+        },
+      ],
+      ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("fail to open engine");
+    let value = |len: usize| Bytes::from(vec![b'v'; len]);
+
+    engine.put(Bytes::from("tenant-a:1"), value(2000)).unwrap();
+    engine.put(Bytes::from("tenant-a:2"), value(2000)).unwrap();
+    assert_eq!(
+      Errors::QuotaExceeded,
+      engine
+        .put(Bytes::from("tenant-a:3"), value(100))
+        .unwrap_err()
+    );
+    // other prefixes are unlimited
+    engine.put(Bytes::from("tenant-b:1"), value(4096)).unwrap();
+
+    // the overwritten record is deducted, deletes free space
+    engine.put(Bytes::from("tenant-a:2"), value(1000)).unwrap();
+    engine.delete(Bytes::from("tenant-a:1")).unwrap();
+    engine.put(Bytes::from("tenant-a:3"), value(100)).unwrap();
+    let usage = engine.quota_usage();
+    assert_eq!(
+      engine.approximate_size_of_prefix(b"tenant-a:").unwrap(),
+      usage[0].used_bytes
+    );
+
+    // the nested quota is enforced as well, batches are checked as a whole
+    assert_eq!(
+      Errors::QuotaExceeded,
+      engine
+        .put(Bytes::from("tenant-a:logs:1"), value(300))
+        .unwrap_err()
+    );
+    let batch = engine
+      .new_write_batch(WriteBatchOptions::default())
+      .unwrap();
+    batch
+      .put(Bytes::from("tenant-a:logs:1"), value(150))
+      .unwrap();
+    batch
+      .put(Bytes::from("tenant-a:logs:2"), value(150))
+      .unwrap();
+    assert_eq!(Errors::QuotaExceeded, batch.commit().unwrap_err());
+    let batch = engine
+      .new_write_batch(WriteBatchOptions::default())
+      .unwrap();
+    batch
+      .put(Bytes::from("tenant-a:logs:1"), value(50))
+      .unwrap();
+    batch
+      .put(Bytes::from("tenant-a:logs:2"), value(50))
+      .unwrap();
+    batch.commit().unwrap();
+
+    // the usage is recounted on open
+    let usage = engine.quota_usage();
+    engine.close().unwrap();
+    drop(engine);
+    let engine = Engine::open(opts).expect("fail to open engine");
+    let reopened = engine.quota_usage();
+    assert_eq!(
+      engine
+        .approximate_size_of_prefix(b"tenant-a:logs:")
+        .unwrap(),
+      reopened[1].used_bytes
+    );
+    assert!(reopened[0].used_bytes <= usage[0].used_bytes);
+    assert!(reopened[0].used_bytes > 1200);
+  }
+}
@@ -0,0 +1,285 @@
+use std::{
+  collections::{HashMap, HashSet},
+  fs::{self, File},
+  mem,
+  path::Path,
+  sync::atomic::Ordering,
+  time::SystemTime,
+};
+
+use fs2::FileExt;
+use log::error;
+use parking_lot::Mutex;
+
+use crate::{
+  batch::NON_TXN_SEQ_NO,
+  data::{
+    data_file::{DataFile, MERGE_FINISHED_FILE_NAME},
+    log_record::{LogRecordPos, LogRecordType, TransactionRecord},
+  },
+  db::{list_data_file_ids, load_data_files, read_non_merge_file_id, Engine},
+  errors::{Errors, Result},
+  iterator::PositionRemap,
+  option::IteratorOptions,
+  repair::OpenReport,
+};
+
+/// Lock file shared by the readers, taken exclusively to delete the database.
+pub(crate) const READER_LOCK_NAME: &str = "reader-lock";
+
+/// State of an engine opened as one of the `shared_readers`.
+pub(crate) struct SharedReader {
+  refresh_lock: Mutex<()>, // one refresh at a time
+  txn_records: Mutex<HashMap<usize, Vec<TransactionRecord>>>, // txns without their finished record yet
+  merge_mark: Mutex<Option<SystemTime>>, // modified time of the merge finished file of the loaded files
+}
+
+impl SharedReader {
+  pub(crate) fn new(dir_path: &Path) -> Self {
+    Self {
+      refresh_lock: Mutex::new(()),
+      txn_records: Mutex::new(HashMap::new()),
+      merge_mark: Mutex::new(merge_mark(dir_path)),
+    }
+  }
+
+  pub(crate) fn keep_txn_records(&self, txn_records: HashMap<usize, Vec<TransactionRecord>>) {
+    *self.txn_records.lock() = txn_records;
+  }
+}
+
+impl Engine {
+  /// Picks up the writes made by the writer since the open or the last refresh.
+  ///
+  /// Only engines opened with `shared_readers` are refreshed. Data files rotated in by the
+  /// writer are opened and the records appended since are replayed into the index. When the
+  /// writer replaced data files, after a merge or a clear, the index is rebuilt instead and
+  /// point reads racing with the refresh wait for it. Live iterators carry on with the moved
+  /// records, keys dropped meanwhile are skipped.
+  pub fn refresh(&self) -> Result<()> {
+    let Some(reader) = &self.reader else {
+      return Ok(());
+    };
+    let _guard = reader.refresh_lock.lock();
+
+    let dir_path = &self.options.dir_path;
+    let mark = merge_mark(dir_path);
+    let file_ids = list_data_file_ids(dir_path)?;
+    let active_file_id = self.active_data_file.read().get_file_id();
+    let files_removed = !file_ids.contains(&active_file_id)
+      || self
+        .old_data_files
+        .read()
+        .keys()
+        .any(|file_id| !file_ids.contains(file_id));
+    if files_removed || mark != *reader.merge_mark.lock() {
+      return self.reload(reader, mark);
+    }
+
+    let tail = {
+      let active_file = self.active_data_file.read();
+      (active_file.get_file_id(), active_file.get_write_off())
+    };
+    let mut txn_records = mem::take(&mut *reader.txn_records.lock());
+    let mut current_seq_no = NON_TXN_SEQ_NO;
+    let newer_files: Vec<u32> = file_ids
+      .into_iter()
+      .filter(|file_id| *file_id > active_file_id)
+      .collect();
+
+    self.replay_active_tail(
+      !newer_files.is_empty(),
+      &mut txn_records,
+      &mut current_seq_no,
+    )?;
+    for (i, file_id) in newer_files.iter().enumerate() {
+      // both files are opened first, a failure leaves the sealed file active instead of
+      // in neither map, where reads of its keys would not find it
+      let mut active_file = self.active_data_file.write();
+      let sealed_file_id = active_file.get_file_id();
+      let old_file = self.open_old_data_file(sealed_file_id)?;
+      let new_file = self.new_active_data_file(*file_id)?;
+      self.old_data_files.write().insert(sealed_file_id, old_file);
+      drop(mem::replace(&mut *active_file, new_file));
+      drop(active_file);
+
+      let sealed = i + 1 < newer_files.len();
+      self.replay_active_tail(sealed, &mut txn_records, &mut current_seq_no)?;
+    }
+
+    reader.keep_txn_records(txn_records);
+    self.advance_seq_no(current_seq_no);
+    let active_file = self.active_data_file.read();
+    if (active_file.get_file_id(), active_file.get_write_off()) != tail {
+      self.mark_changed();
+    }
+    Ok(())
+  }
+
+  // replays the records appended to the active file since its write offset, a broken record
+  // is only an error once the writer has moved on to a newer file
+  fn replay_active_tail(
+    &self,
+    sealed: bool,
+    txn_records: &mut HashMap<usize, Vec<TransactionRecord>>,
+    current_seq_no: &mut usize,
+  ) -> Result<()> {
+    let active_file = self.active_data_file.read();
+    let file_id = active_file.get_file_id();
+    let mut offset = active_file.get_write_off();
+    let mut scanner = active_file.scan_from(offset);
+    loop {
+      let (read_record, record_offset) = match scanner.next_record() {
+        Ok(v) => v,
+        Err(Errors::ReadDataFileEOF) => break,
+        Err(e) if sealed => return Err(e),
+        Err(_) => break,
+      };
+      offset = record_offset + read_record.size as u64;
+      if read_record.record.rec_type == LogRecordType::FileFooter {
+        continue;
+      }
+
+      let log_record_pos = LogRecordPos {
+        file_id,
+        offset: record_offset,
+        size: read_record.size as u32,
+      };
+      self.replay_log_record(
+        read_record.record,
+        log_record_pos,
+        txn_records,
+        current_seq_no,
+      )?;
+    }
+    active_file.set_write_off(offset);
+    Ok(())
+  }
+
+  // rebuilds the index from the data files currently in the directory, iterators follow
+  // their records into the replaced files
+  fn reload(&self, reader: &SharedReader, mark: Option<SystemTime>) -> Result<()> {
+    self.replace_data_files(|| self.reload_files(reader, mark))?;
+    self.mark_changed();
+    Ok(())
+  }
+
+  fn reload_files(&self, reader: &SharedReader, mark: Option<SystemTime>) -> Result<PositionRemap> {
+    let dir_path = &self.options.dir_path;
+    let wrapper = self.options.io_manager_wrapper.as_ref();
+    let mut data_files: Vec<DataFile> = load_data_files(
+      dir_path,
+      self.options.files_per_subdir,
+      None,
+      self.options.file_io_type(),
+      self.options.sealed_file_io_type(),
+      self.file_pool.as_ref(),
+    )?
+    .into_iter()
+    .map(|file| {
+      let file_dir = self.data_file_dir(file.get_file_id());
+      file.with_io_wrapper(file_dir, wrapper)
+    })
+    .collect();
+    let file_ids: Vec<u32> = data_files.iter().map(|file| file.get_file_id()).collect();
+    let Some(new_active_file) = data_files.pop() else {
+      return Err(Errors::DataFileNotFound);
+    };
+
+    // files merged by the writer keep their ids with another content
+    let non_merge_fid = match mark != *reader.merge_mark.lock() {
+      true => read_non_merge_file_id(dir_path)?.unwrap_or(0),
+      false => 0,
+    };
+    let mut replaced_files = HashSet::new();
+    {
+      let mut active_file = self.active_data_file.write();
+      let mut old_files = self.old_data_files.write();
+      replaced_files.extend(
+        old_files
+          .keys()
+          .copied()
+          .chain([active_file.get_file_id()])
+          .filter(|file_id| *file_id < non_merge_fid || !file_ids.contains(file_id)),
+      );
+      *active_file = new_active_file;
+      *old_files = data_files
+        .into_iter()
+        .map(|file| (file.get_file_id(), file))
+        .collect();
+    }
+    let mut old_positions = Vec::new();
+    let mut iter = self.index.try_iterator(IteratorOptions::default())?;
+    while let Some((key, pos)) = iter.next() {
+      if replaced_files.contains(&pos.file_id) {
+        old_positions.push((key.to_vec(), *pos));
+      }
+    }
+    drop(iter);
+    self.index.clear()?;
+    self.expiry.clear();
+    self.garbage.restore(Vec::new());
+    self.reclaim_size.store(0, Ordering::SeqCst);
+    reader.txn_records.lock().clear();
+    *reader.merge_mark.lock() = mark;
+    self.invalidate_disk_size();
+
+    let hint_loaded = self.load_index_from_hint_file()?;
+    let current_seq_no =
+      self.load_index_from_files(&file_ids, hint_loaded, &mut OpenReport::default())?;
+    self.advance_seq_no(current_seq_no);
+
+    let moved = old_positions
+      .into_iter()
+      .filter_map(|(key, old_pos)| Some((old_pos, self.index.get(key)?)))
+      .collect();
+    Ok(PositionRemap {
+      moved,
+      replaced_files,
+    })
+  }
+
+  // the next seq_no follows the last one replayed, like on open
+  fn advance_seq_no(&self, current_seq_no: usize) {
+    if current_seq_no > NON_TXN_SEQ_NO {
+      self.seq_no.fetch_max(current_seq_no + 1, Ordering::SeqCst);
+    }
+  }
+}
+
+// a merge moved into the directory replaces this file
+fn merge_mark(dir_path: &Path) -> Option<SystemTime> {
+  fs::metadata(dir_path.join(MERGE_FINISHED_FILE_NAME))
+    .and_then(|meta| meta.modified())
+    .ok()
+}
+
+fn open_reader_lock(dir_path: &Path) -> Result<File> {
+  fs::OpenOptions::new()
+    .read(true)
+    .create(true)
+    .append(true)
+    .open(dir_path.join(READER_LOCK_NAME))
+    .map_err(|e| {
+      error!("failed to open database reader lock file: {e}");
+      Errors::FailedToOpenLockFile
+    })
+}
+
+/// Takes the shared lock of a reader, fails while the database is being deleted.
+pub(crate) fn lock_shared_reader(dir_path: &Path) -> Result<File> {
+  let lock_file = open_reader_lock(dir_path)?;
+  if lock_file.try_lock_shared().is_err() {
+    return Err(Errors::DatabaseIsUsing);
+  }
+  Ok(lock_file)
+}
+
+/// Takes the reader lock exclusively, fails while readers have the database opened.
+pub(crate) fn lock_out_readers(dir_path: &Path) -> Result<File> {
+  let lock_file = open_reader_lock(dir_path)?;
+  if lock_file.try_lock_exclusive().is_err() {
+    return Err(Errors::DatabaseIsUsing);
+  }
+  Ok(lock_file)
+}
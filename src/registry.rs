@@ -0,0 +1,286 @@
+use std::{
+  collections::HashMap,
+  sync::{
+    mpsc::{self, RecvTimeoutError, Sender},
+    Arc,
+  },
+  thread::{self, JoinHandle},
+  time::Duration,
+};
+
+use log::warn;
+use parking_lot::{Mutex, RwLock};
+
+use crate::{
+  db::Engine,
+  errors::{Errors, Result},
+  fio::pool::FilePool,
+  option::{Options, RuntimeIo, StorageMode},
+};
+
+type Engines = Arc<RwLock<HashMap<String, Arc<Engine>>>>;
+
+/// Configuration of an [`EngineRegistry`].
+#[derive(Debug, Clone)]
+pub struct RegistryOptions {
+  /// Maximum number of old data files kept open by all engines together, the least recently
+  /// used ones are closed and reopened on read. 0 leaves it to the `max_open_files` of each
+  /// engine. Engines memory mapping their sealed files keep their own handles
+  pub max_open_files: usize,
+
+  /// Interval of the maintenance worker shared by the engines, `None` starts no worker
+  pub maintenance_interval: Option<Duration>,
+
+  /// Write the tombstones of expired keys on every maintenance run, see
+  /// `Engine::purge_expired`
+  pub purge_expired: bool,
+
+  /// Merge the engines due for a merge on every maintenance run, see `Engine::merge`
+  pub auto_merge: bool,
+
+  /// Sync the active file of every engine on every maintenance run
+  pub sync: bool,
+}
+
+impl Default for RegistryOptions {
+  fn default() -> Self {
+    Self {
+      max_open_files: 0,
+      maintenance_interval: Some(Duration::from_secs(10)),
+      purge_expired: true,
+      auto_merge: true,
+      sync: true,
+    }
+  }
+}
+
+/// Engines of a process opened by name, sharing one pool of open files and one maintenance
+/// worker instead of a background thread per engine.
+///
+/// The worker stops when the registry is dropped, the engines are closed once the registry
+/// and every other handle to them is dropped.
+pub struct EngineRegistry {
+  file_pool: Option<Arc<FilePool>>,
+  engines: Engines,
+  worker: Mutex<Option<(Sender<()>, JoinHandle<()>)>>,
+}
+
+impl EngineRegistry {
+  /// Creates an empty registry, starting its maintenance worker.
+  pub fn new(options: RegistryOptions) -> Result<Self> {
+    let engines = Engines::default();
+    let worker = match options.maintenance_interval {
+      Some(interval) => Some(start_worker(&options, interval, Arc::clone(&engines))?),
+      None => None,
+    };
+    Ok(Self {
+      file_pool: (options.max_open_files > 0)
+        .then(|| Arc::new(FilePool::new(options.max_open_files))),
+      engines,
+      worker: Mutex::new(worker),
+    })
+  }
+
+  /// Opens the engine registered as `name`, or returns it if it is open already.
+  ///
+  /// # Errors
+  ///
+  /// Returns `EngineNameTaken` if `name` is registered with another directory, and the
+  /// errors of `Engine::open`, e.g. `DatabaseIsUsing` when another name holds the directory.
+  pub fn open(&self, name: &str, opts: Options) -> Result<Arc<Engine>> {
+    let mut engines = self.engines.write();
+    if let Some(engine) = engines.get(name) {
+      return match engine.options.dir_path == opts.dir_path {
+        true => Ok(Arc::clone(engine)),
+        false => Err(Errors::EngineNameTaken),
+      };
+    }
+
+    // pooled files are read through standard file IO
+    let pooled =
+      opts.resolved_runtime_io() == RuntimeIo::FileIO && opts.storage_mode == StorageMode::Disk;
+    let file_pool = self.file_pool.clone().filter(|_| pooled);
+    let (engine, _) = Engine::open_with_pool(opts, file_pool)?;
+    let engine = Arc::new(engine);
+    engines.insert(name.to_string(), Arc::clone(&engine));
+    Ok(engine)
+  }
+
+  /// Returns the engine registered as `name`.
+  pub fn get(&self, name: &str) -> Option<Arc<Engine>> {
+    self.engines.read().get(name).cloned()
+  }
+
+  /// Names of the registered engines, in no particular order.
+  pub fn names(&self) -> Vec<String> {
+    self.engines.read().keys().cloned().collect()
+  }
+
+  /// Closes the engine registered as `name` and removes it from the registry, returns false
+  /// if there is none. Handles to the engine taken before fail with `EngineClosed`.
+  pub fn close(&self, name: &str) -> Result<bool> {
+    let engine = self.engines.write().remove(name);
+    match engine {
+      Some(engine) => engine.close().map(|_| true),
+      None => Ok(false),
+    }
+  }
+
+  /// Closes every registered engine, stops at the first failing one.
+  pub fn close_all(&self) -> Result<()> {
+    let engines: Vec<_> = self.engines.write().drain().collect();
+    engines.iter().try_for_each(|(_, engine)| engine.close())
+  }
+}
+
+impl Drop for EngineRegistry {
+  fn drop(&mut self) {
+    // dropping the sender wakes up the worker
+    if let Some((stop_sender, worker)) = self.worker.lock().take() {
+      drop(stop_sender);
+      let _ = worker.join();
+    }
+  }
+}
+
+// runs the maintenance of all engines every `interval` until the sender is dropped
+fn start_worker(
+  options: &RegistryOptions,
+  interval: Duration,
+  engines: Engines,
+) -> Result<(Sender<()>, JoinHandle<()>)> {
+  let options = options.clone();
+  let (stop_sender, stop_receiver) = mpsc::channel::<()>();
+  let worker = thread::Builder::new()
+    .name("flash-kv-registry".to_string())
+    .spawn(move || loop {
+      match stop_receiver.recv_timeout(interval) {
+        Err(RecvTimeoutError::Timeout) => {}
+        _ => return,
+      }
+      // engines opened or closed meanwhile are picked up by the next run
+      let registered: Vec<_> = engines
+        .read()
+        .iter()
+        .map(|(name, engine)| (name.clone(), Arc::clone(engine)))
+        .collect();
+      for (name, engine) in registered {
+        maintain(&name, &engine, &options);
+      }
+    })
+    .map_err(|e| {
+      warn!("failed to spawn registry worker: {e}");
+      Errors::FailedToStartBackgroundTask
+    })?;
+  Ok((stop_sender, worker))
+}
+
+// one maintenance run of an engine, failures are logged and retried on the next run
+fn maintain(name: &str, engine: &Engine, options: &RegistryOptions) {
+  if engine.is_closed() || engine.reader.is_some() {
+    return;
+  }
+  if options.purge_expired {
+    if let Err(e) = engine.purge_expired() {
+      warn!("failed to purge expired keys of engine {name}: {e}");
+    }
+  }
+  if options.auto_merge {
+    match engine.merge() {
+      Ok(())
+      | Err(Errors::MergeThresholdUnreached)
+      | Err(Errors::MergeOutsideWindow)
+      | Err(Errors::MergeInProgress)
+      | Err(Errors::MemoryModeUnsupported) => {}
+      Err(e) => warn!("failed to merge engine {name}: {e}"),
+    }
+  }
+  if options.sync {
+    if let Err(e) = engine.sync() {
+      warn!("failed to sync engine {name}: {e}");
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::SystemTime;
+
+  use bytes::Bytes;
+
+  use super::*;
+
+  #[test]
+  fn test_engine_registry() {
+    let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+    let registry = EngineRegistry::new(RegistryOptions {
+      max_open_files: 2,
+      maintenance_interval: Some(Duration::from_millis(10)),
+      ..Default::default()
+    })
+    .unwrap();
+    let opts = |name: &str| Options {
+      dir_path: temp_dir.path().join(name),
+      data_file_size: 4096,
+      ..Default::default()
+    };
+
+    let users = registry.open("users", opts("users")).unwrap();
+    let orders = registry.open("orders", opts("orders")).unwrap();
+    assert!(Arc::ptr_eq(
+      &users,
+      &registry.open("users", opts("users")).unwrap()
+    ));
+    assert_eq!(
+      Errors::EngineNameTaken,
+      registry.open("users", opts("orders")).err().unwrap()
+    );
+    assert_eq!(
+      Errors::DatabaseIsUsing,
+      registry.open("clients", opts("users")).err().unwrap()
+    );
+    let mut names = registry.names();
+    names.sort();
+    assert_eq!(vec!["orders", "users"], names);
+
+    // both engines read their old data files through the shared pool
+    for i in 0..200 {
+      let key = Bytes::from(format!("key-{i:03}"));
+      users.put(key.clone(), Bytes::from("user")).unwrap();
+      orders.put(key, Bytes::from("order")).unwrap();
+    }
+    for i in 0..200 {
+      let key = Bytes::from(format!("key-{i:03}"));
+      assert_eq!(Bytes::from("user"), users.get(key.clone()).unwrap());
+      assert_eq!(Bytes::from("order"), orders.get(key).unwrap());
+    }
+    let file_pool = registry.file_pool.as_ref().unwrap();
+    assert!(Arc::ptr_eq(file_pool, users.file_pool.as_ref().unwrap()));
+    assert!(file_pool.open_files() <= 2);
+
+    // the shared worker writes the tombstones of expired keys
+    users
+      .expire(
+        Bytes::from("key-000"),
+        SystemTime::now() + Duration::from_millis(20),
+      )
+      .unwrap();
+    for _ in 0..100 {
+      if users.index.get(b"key-000".to_vec()).is_none() {
+        break;
+      }
+      thread::sleep(Duration::from_millis(10));
+    }
+    assert!(users.index.get(b"key-000".to_vec()).is_none());
+
+    assert!(registry.close("users").unwrap());
+    assert!(!registry.close("users").unwrap());
+    assert_eq!(
+      Errors::EngineClosed,
+      users.get(Bytes::from("key-001")).unwrap_err()
+    );
+    assert!(registry.get("users").is_none());
+    registry.close_all().unwrap();
+    assert!(registry.names().is_empty());
+  }
+}
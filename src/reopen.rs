@@ -0,0 +1,208 @@
+use std::{fs, path::Path, sync::atomic::Ordering};
+
+use bytes::{Buf, BufMut, BytesMut};
+use log::{error, warn};
+
+use crate::{
+  data::{
+    data_file::{DataFile, CLOSE_HINT_FILE_NAME, CLOSE_HINT_TMP_FILE_NAME},
+    log_record::{decode_log_record_pos, LogRecord, LogRecordPos, LogRecordType},
+  },
+  db::Engine,
+  errors::{Errors, Result},
+  garbage::{decode_garbage_entries, encode_garbage, GarbageBytes},
+  option::{IndexType, IteratorOptions},
+};
+
+const CLOSE_STATE_KEY: &[u8] = "close.state".as_bytes();
+const CLOSE_GARBAGE_KEY: &[u8] = "close.garbage".as_bytes();
+// active file id, active file size, reclaim size and seq_no
+const CLOSE_STATE_SIZE: usize = 4 + 8 + 8 + 8;
+
+/// Engine state recorded on close, the close hint is only valid while the active file
+/// still has the recorded size.
+struct CloseState {
+  active_file_id: u32,
+  active_file_size: u64,
+  reclaim_size: u64,
+  seq_no: u64,
+}
+
+impl Engine {
+  /// Dumps the index into the close hint file, so the next open does not scan the data files.
+  ///
+  /// The B+ tree index is committed on every write, only the engine state is recorded for it.
+  pub(crate) fn write_close_hint(&self) -> Result<()> {
+    let dir_path = &self.options.dir_path;
+    remove_file_if_exists(&dir_path.join(CLOSE_HINT_TMP_FILE_NAME))?;
+
+    // writers wait for the active file, so the dump matches the recorded size
+    let active_file = self.active_data_file.read();
+    let hint_file = DataFile::new_close_hint_tmp_file(dir_path)?.with_cipher(self.cipher.as_ref());
+    let mut state = BytesMut::with_capacity(CLOSE_STATE_SIZE);
+    state.put_u32(active_file.get_file_id());
+    state.put_u64(active_file.file_size());
+    state.put_u64(self.reclaim_size.load(Ordering::SeqCst) as u64);
+    state.put_u64(self.seq_no.load(Ordering::SeqCst) as u64);
+    let record = LogRecord {
+      key: CLOSE_STATE_KEY.to_vec(),
+      value: state.to_vec(),
+      rec_type: LogRecordType::Normal,
+      timestamp: None,
+    };
+    hint_file.write(&hint_file.encode_record(&record)?)?;
+
+    let record = LogRecord {
+      key: CLOSE_GARBAGE_KEY.to_vec(),
+      value: encode_garbage(&self.garbage.entries()),
+      rec_type: LogRecordType::Normal,
+      timestamp: None,
+    };
+    hint_file.write(&hint_file.encode_record(&record)?)?;
+
+    if self.options.index_type != IndexType::BPlusTree {
+      let mut iter = self.index.try_iterator(IteratorOptions::default())?;
+      while let Some((key, pos)) = iter.next() {
+        hint_file.write_hint_record(key.to_vec(), *pos)?;
+      }
+      // expire hints follow the index entries, they are only kept for keys in the index
+      for (key, pos) in self.expiry.entries() {
+        hint_file.write_expire_hint_record(key, pos)?;
+      }
+    }
+    hint_file.sync()?;
+
+    fs::rename(
+      dir_path.join(CLOSE_HINT_TMP_FILE_NAME),
+      dir_path.join(CLOSE_HINT_FILE_NAME),
+    )
+    .map_err(|e| {
+      error!("failed to rename close hint file: {e}");
+      Errors::FailedToRenameFile
+    })
+  }
+
+  /// Loads the index from the close hint file, returns false if there is none or it is stale.
+  ///
+  /// The file is removed afterwards, writes after this open are not covered by it.
+  pub(crate) fn load_close_hint(&self) -> Result<bool> {
+    let dir_path = &self.options.dir_path;
+    if !dir_path.join(CLOSE_HINT_FILE_NAME).is_file() {
+      return Ok(false);
+    }
+    let loaded = self.replay_close_hint()?;
+    remove_close_hint(dir_path)?;
+    Ok(loaded)
+  }
+
+  fn replay_close_hint(&self) -> Result<bool> {
+    let hint_file =
+      DataFile::new_close_hint_file(&self.options.dir_path)?.with_cipher(self.cipher.as_ref());
+    let mut hint_scanner = hint_file.scan();
+    let record = match hint_scanner.next_record() {
+      Ok((result, _)) => result.record,
+      Err(e) => {
+        warn!("close hint file is unreadable, scanning data files: {e}");
+        return Ok(false);
+      }
+    };
+    let garbage = hint_scanner
+      .next_record()
+      .ok()
+      .and_then(|(result, _)| decode_garbage(&result.record));
+    let (Some(state), Some(garbage)) = (decode_close_state(&record), garbage) else {
+      warn!("close hint file is invalid, scanning data files");
+      return Ok(false);
+    };
+    let active_file = self.active_data_file.read();
+    if state.active_file_id != active_file.get_file_id()
+      || state.active_file_size != active_file.file_size()
+    {
+      return Ok(false);
+    }
+
+    if self.options.index_type != IndexType::BPlusTree {
+      loop {
+        let record = match hint_scanner.next_record() {
+          Ok((result, _)) => result.record,
+          Err(Errors::ReadDataFileEOF) => break,
+          Err(e) => return Err(e),
+        };
+        let pos = decode_log_record_pos(record.value)?;
+        match record.rec_type {
+          LogRecordType::Expire => {
+            let expire_record = self.read_log_record_at(&pos)?;
+            self.replay_expire(record.key, &expire_record.value, pos)?;
+          }
+          _ => {
+            self.index.try_put(record.key, pos)?;
+          }
+        }
+      }
+
+      // records of the active file are still needed for its key directory
+      if self.options.startup_manifest {
+        let mut scanner = active_file.scan();
+        loop {
+          let (result, offset) = match scanner.next_record() {
+            Ok(result) => result,
+            Err(Errors::ReadDataFileEOF) => break,
+            Err(e) => return Err(e),
+          };
+          let pos = LogRecordPos {
+            file_id: state.active_file_id,
+            offset,
+            size: result.size as u32,
+          };
+          self.track_keydir_record(&result.record.key, result.record.rec_type, pos);
+        }
+      }
+    }
+
+    active_file.set_write_off(state.active_file_size);
+    self
+      .reclaim_size
+      .store(state.reclaim_size as usize, Ordering::SeqCst);
+    self.seq_no.store(state.seq_no as usize, Ordering::SeqCst);
+    self.garbage.restore(garbage);
+    Ok(true)
+  }
+}
+
+fn decode_close_state(record: &LogRecord) -> Option<CloseState> {
+  if record.key != CLOSE_STATE_KEY || record.value.len() != CLOSE_STATE_SIZE {
+    return None;
+  }
+  let mut value = record.value.as_slice();
+  Some(CloseState {
+    active_file_id: value.get_u32(),
+    active_file_size: value.get_u64(),
+    reclaim_size: value.get_u64(),
+    seq_no: value.get_u64(),
+  })
+}
+
+fn decode_garbage(record: &LogRecord) -> Option<Vec<(u32, GarbageBytes)>> {
+  if record.key != CLOSE_GARBAGE_KEY {
+    return None;
+  }
+  decode_garbage_entries(&record.value)
+}
+
+/// Removes the close hint file, used when the data files change behind it.
+pub(crate) fn remove_close_hint<P>(dir_path: P) -> Result<()>
+where
+  P: AsRef<Path>,
+{
+  remove_file_if_exists(&dir_path.as_ref().join(CLOSE_HINT_FILE_NAME))
+}
+
+pub(crate) fn remove_file_if_exists(file: &Path) -> Result<()> {
+  if file.is_file() {
+    fs::remove_file(file).map_err(|e| {
+      error!("failed to remove {}: {e}", file.display());
+      Errors::FailedToRemoveFile
+    })?;
+  }
+  Ok(())
+}
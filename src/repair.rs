@@ -0,0 +1,945 @@
+use std::{
+  collections::HashMap,
+  fs::OpenOptions,
+  time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use log::warn;
+
+use crate::{
+  batch::{log_record_key_with_seq, parse_log_record_key, NON_TXN_SEQ_NO},
+  data::{
+    data_file::{
+      get_data_file_dir, get_data_file_name, DataFile, RecordScanner, HINT_FILE_NAME,
+      MERGE_FINISHED_FILE_NAME,
+    },
+    log_record::{
+      decode_log_record_pos, LogRecord, LogRecordPos, LogRecordType, TransactionRecord,
+    },
+  },
+  db::{parse_record_value, Engine},
+  errors::{DataFileOp, Errors, Result},
+  event::{CorruptionRecovery, CorruptionReport},
+  merge::get_merge_path,
+  multi_batch::TXN_ABORT_KEY,
+  option::{IOManagerType, OrphanTxnPolicy, WriteBatchOptions},
+};
+
+// fresh handles bypass a stale mapping or a broken cached handle of the data file
+const REREAD_IO_TYPES: &[IOManagerType] = &[
+  IOManagerType::StandardFileIO,
+  #[cfg(feature = "mmap")]
+  IOManagerType::MemoryMap,
+];
+
+// keep only the most recent incidents
+const MAX_READ_REPAIR_INCIDENTS: usize = 1024;
+
+/// A read which hit a broken index entry and the outcome of the repair.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadRepairIncident {
+  /// The key being read
+  pub key: Bytes,
+
+  /// The file id of the broken position
+  pub file_id: u32,
+
+  /// The offset of the broken position
+  pub offset: u64,
+
+  /// The error hit by the read
+  pub error: Errors,
+
+  /// The position the index was repaired to, `None` if the key was removed from index
+  pub repaired_pos: Option<(u32, u64)>,
+}
+
+/// What the recovery did while opening an engine, see [`Engine::open_with_report`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OpenReport {
+  /// Tails cut off the active file by `Options::repair_on_open`
+  pub truncated_tails: Vec<TruncatedTail>,
+
+  /// Records of older files left out of the index by `Options::repair_on_open`
+  pub skipped_records: Vec<SkippedRecord>,
+
+  /// Transactions replayed from the data files
+  pub committed_txns: usize,
+
+  /// Transactions dropped since their commit record is missing
+  pub discarded_txns: usize,
+
+  /// Transactions found without commit record, handled by `Options::orphan_txn_policy`
+  pub orphaned_txns: Vec<OrphanedTxn>,
+
+  /// Prepared multi-engine transactions held until they are recovered
+  pub prepared_txns: usize,
+
+  /// Time spent in the phases of the open, in the order they ran
+  pub phases: Vec<(OpenPhase, Duration)>,
+}
+
+/// A torn or corrupt tail truncated from the active file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TruncatedTail {
+  pub file_id: u32,
+
+  /// The new size of the file
+  pub offset: u64,
+
+  /// The bytes cut off
+  pub bytes: u64,
+
+  /// The error hit by the first record of the tail
+  pub error: Errors,
+}
+
+/// A corrupt record skipped when loading an older data file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SkippedRecord {
+  pub file_id: u32,
+  pub offset: u64,
+
+  /// The bytes skipped, the rest of the file if the header of the record is unreadable
+  pub size: u64,
+
+  /// The error hit by the record
+  pub error: Errors,
+}
+
+/// A transaction whose commit record is missing, found while opening an engine.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrphanedTxn {
+  pub seq_no: usize,
+
+  /// Number of records written by the transaction
+  pub records: usize,
+
+  /// Position of its first record
+  pub file_id: u32,
+  pub offset: u64,
+}
+
+/// Phases of [`Engine::open_with_report`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenPhase {
+  /// Finishing the clears and merges interrupted by a crash
+  FinishInterrupted,
+
+  /// Opening the data files
+  OpenFiles,
+
+  /// Loading the index from the hint files or the close hint
+  LoadHints,
+
+  /// Replaying the data files into the index
+  ReplayFiles,
+}
+
+impl OpenReport {
+  // records the duration of a phase which started at `started`
+  pub(crate) fn record_phase(&mut self, phase: OpenPhase, started: Instant) {
+    self.phases.push((phase, started.elapsed()));
+  }
+}
+
+impl Engine {
+  /// Moves the transactions left without commit record by the replay of the data files out
+  /// of `transaction_records`, they are resolved by `resolve_orphaned_txns` once the data files
+  /// are writable. Prepared transactions are kept in `transaction_records`.
+  pub(crate) fn hold_orphaned_txns(
+    &self,
+    transaction_records: &mut HashMap<usize, Vec<TransactionRecord>>,
+  ) {
+    let mut orphaned_txns = self.orphaned_txns.lock();
+    transaction_records.retain(|seq_no, records| {
+      let prepared = (records.iter()).any(|txn| txn.record.rec_type == LogRecordType::TxnPrepared);
+      if !prepared {
+        orphaned_txns.insert(*seq_no, std::mem::take(records));
+      }
+      prepared
+    });
+  }
+
+  /// Handles the transactions held by `hold_orphaned_txns` as `Options::orphan_txn_policy`
+  /// says, oldest first. The decision is written to the data files: applied records are
+  /// committed again where the index holds no newer version of their key, then the orphaned
+  /// transaction gets a txn aborted record so later opens skip it.
+  pub(crate) fn resolve_orphaned_txns(&self, report: &mut OpenReport) -> Result<()> {
+    let mut orphaned_txns = std::mem::take(&mut *self.orphaned_txns.lock());
+    let mut seq_nos: Vec<usize> = orphaned_txns.keys().copied().collect();
+    seq_nos.sort_unstable();
+
+    for seq_no in seq_nos {
+      let records = orphaned_txns.remove(&seq_no).unwrap_or_default();
+      let Some(first) = records.first() else {
+        continue;
+      };
+      report.orphaned_txns.push(OrphanedTxn {
+        seq_no,
+        records: records.len(),
+        file_id: first.pos.file_id,
+        offset: first.pos.offset,
+      });
+      warn!(
+        "transaction {seq_no} without commit record at data file {} offset {}",
+        first.pos.file_id, first.pos.offset
+      );
+
+      match self.options.orphan_txn_policy {
+        OrphanTxnPolicy::Error => return Err(Errors::OrphanedTxnFound),
+        OrphanTxnPolicy::Apply => self.apply_orphaned_txn(&records)?,
+        OrphanTxnPolicy::Discard => report.discarded_txns += 1,
+      }
+      self.abort_orphaned_txn(seq_no, &records)?;
+    }
+    Ok(())
+  }
+
+  // commits the records of an orphaned transaction again whose key is missing from the index or
+  // indexed at an older position, later writes of the key win
+  fn apply_orphaned_txn(&self, records: &[TransactionRecord]) -> Result<()> {
+    let batch = self.new_write_batch(WriteBatchOptions {
+      max_batch_num: usize::MAX,
+      max_batch_bytes: 0,
+      sync_writes: self.options.sync_writes,
+    })?;
+    for txn_record in records {
+      let key = &txn_record.record.key;
+      let newer = match self.index.get(key.clone()) {
+        Some(pos) => (pos.file_id, pos.offset) < (txn_record.pos.file_id, txn_record.pos.offset),
+        None => true,
+      };
+      if !newer {
+        continue;
+      }
+      match txn_record.record.rec_type {
+        // the replay does not buffer values, a blob is resolved by the read
+        LogRecordType::Normal | LogRecordType::Blob => {
+          let value = self.read_log_record_at(&txn_record.pos)?.value;
+          batch.put(Bytes::copy_from_slice(key), Bytes::from(value))?;
+        }
+        LogRecordType::Deleted => batch.delete(Bytes::copy_from_slice(key))?,
+        _ => {}
+      }
+    }
+    batch.commit()
+  }
+
+  // writes the txn aborted record of an orphaned transaction, its records become garbage
+  fn abort_orphaned_txn(&self, seq_no: usize, records: &[TransactionRecord]) -> Result<()> {
+    let mut record = LogRecord {
+      key: log_record_key_with_seq(TXN_ABORT_KEY, seq_no),
+      value: Default::default(),
+      rec_type: LogRecordType::TxnAborted,
+      timestamp: None,
+    };
+    self.append_log_record(&mut record)?;
+    (records.iter()).for_each(|txn_record| self.mark_stale(txn_record.pos));
+    Ok(())
+  }
+
+  /// Repairs a data file whose record at the position of `scanner` failed to load with
+  /// `error`. A tail of the active file is truncated, a record of an older file skipped.
+  ///
+  /// Returns whether loading the file goes on after the record.
+  pub(crate) fn repair_on_load(
+    &self,
+    data_file: &DataFile,
+    scanner: &mut RecordScanner<'_>,
+    is_active: bool,
+    error: Errors,
+    report: &mut OpenReport,
+  ) -> Result<bool> {
+    let file_id = data_file.get_file_id();
+    let offset = scanner.offset();
+    let file_size = data_file.file_size();
+
+    if is_active {
+      // new records are appended where the tail was
+      let file_dir = get_data_file_dir(
+        &self.options.dir_path,
+        file_id,
+        self.options.files_per_subdir,
+      );
+      OpenOptions::new()
+        .write(true)
+        .open(get_data_file_name(file_dir, file_id))
+        .and_then(|file| {
+          file.set_len(offset)?;
+          file.sync_all()
+        })
+        .map_err(|e| Errors::data_file_io(DataFileOp::Write, e).in_data_file(file_id))?;
+      warn!(
+        "truncated {} bytes of data file {file_id} at offset {offset}: {error}",
+        file_size - offset
+      );
+      report.truncated_tails.push(TruncatedTail {
+        file_id,
+        offset,
+        bytes: file_size - offset,
+        error,
+      });
+      return Ok(false);
+    }
+
+    let skipped = scanner.skip_record()?;
+    let size = skipped.unwrap_or(file_size - offset);
+    warn!("skipped {size} bytes of data file {file_id} at offset {offset}: {error}");
+    report.skipped_records.push(SkippedRecord {
+      file_id,
+      offset,
+      size,
+      error,
+    });
+    Ok(skipped.is_some())
+  }
+
+  /// Takes the read repair incidents recorded since the last call.
+  pub fn take_read_repair_incidents(&self) -> Vec<ReadRepairIncident> {
+    let mut incidents = self.read_repair_incidents.lock();
+    std::mem::take(&mut *incidents)
+  }
+
+  /// Answers a read of `key` after reading `broken_pos` failed with `error`, and reports the
+  /// corruption to the event listeners.
+  ///
+  /// Without `Options::read_repair` the error is returned as is.
+  pub(crate) fn recover_read(
+    &self,
+    key: &[u8],
+    broken_pos: LogRecordPos,
+    error: Errors,
+  ) -> Result<Bytes> {
+    let (result, recovery) = match self.options.read_repair {
+      true => self.try_recover_read(key, broken_pos, error.clone()),
+      false => (Err(error.clone()), None),
+    };
+    self.listeners.corruption(&CorruptionReport {
+      key: Bytes::copy_from_slice(key),
+      file_id: broken_pos.file_id,
+      offset: broken_pos.offset,
+      error,
+      recovery,
+    });
+    result
+  }
+
+  fn try_recover_read(
+    &self,
+    key: &[u8],
+    broken_pos: LogRecordPos,
+    error: Errors,
+  ) -> (Result<Bytes>, Option<CorruptionRecovery>) {
+    // the record itself may still be intact on disk, or have a copy in the merge output
+    if error == Errors::InvalidLogRecordCrc {
+      if let Some(value) = self.reread_value(key, broken_pos) {
+        return (Ok(value), Some(CorruptionRecovery::Reread));
+      }
+      if let Some(value) = self.read_merge_output(key, broken_pos) {
+        return (Ok(value), Some(CorruptionRecovery::MergeOutput));
+      }
+    }
+
+    match self.repair_read(key, broken_pos, error) {
+      Ok(value) => (Ok(value), Some(CorruptionRecovery::PreviousVersion)),
+      Err(Errors::KeyNotFound) => (
+        Err(Errors::KeyNotFound),
+        Some(CorruptionRecovery::KeyRemoved),
+      ),
+      Err(e) => (Err(e), None),
+    }
+  }
+
+  /// Reads the record at `pos` again through fresh handles of every IO manager.
+  fn reread_value(&self, key: &[u8], pos: LogRecordPos) -> Option<Bytes> {
+    for io_type in REREAD_IO_TYPES {
+      let data_file = match DataFile::new(self.data_file_dir(pos.file_id), pos.file_id, *io_type) {
+        Ok(data_file) => data_file.with_cipher(self.cipher.as_ref()),
+        Err(e) => {
+          warn!("failed to reopen data file {} for reread: {e}", pos.file_id);
+          continue;
+        }
+      };
+      if let Some(value) = read_value_of(&data_file, pos.offset, key) {
+        return Some(value);
+      }
+    }
+    None
+  }
+
+  /// Reads the copy of the record at `pos` written by the last merge, whose output stays in
+  /// the merge directory until the next open.
+  ///
+  /// A merge only completes when it read every record of the merged files intact, so the
+  /// copy of a record of a merged file holds the same version.
+  fn read_merge_output(&self, key: &[u8], pos: LogRecordPos) -> Option<Bytes> {
+    let merge_path = get_merge_path(&self.options.dir_path).ok()?;
+    if !merge_path.join(MERGE_FINISHED_FILE_NAME).is_file() {
+      return None;
+    }
+    let merge_fin_file = DataFile::new_merge_fin_file(&merge_path).ok()?;
+    let merge_fin_record = merge_fin_file.read_log_record(0).ok()?;
+    let non_merge_file_id: u32 = parse_record_value(merge_fin_record.record.value).ok()?;
+    if pos.file_id >= non_merge_file_id || !merge_path.join(HINT_FILE_NAME).is_file() {
+      return None;
+    }
+
+    let hint_file = DataFile::new_hint_file(&merge_path)
+      .ok()?
+      .with_cipher(self.cipher.as_ref());
+    let mut scanner = hint_file.scan();
+    let mut merged_pos = None;
+    loop {
+      match scanner.next_record() {
+        Ok((result, _)) => {
+          let record = result.record;
+          if record.rec_type == LogRecordType::Normal && record.key == key {
+            merged_pos = decode_log_record_pos(record.value).ok();
+          }
+        }
+        Err(Errors::ReadDataFileEOF) => break,
+        Err(e) => {
+          warn!("failed to read merge hint file: {e}");
+          return None;
+        }
+      }
+    }
+
+    let merged_pos = merged_pos?;
+    let data_file = DataFile::new(
+      &merge_path,
+      merged_pos.file_id,
+      IOManagerType::StandardFileIO,
+    )
+    .ok()?
+    .with_cipher(self.cipher.as_ref());
+    read_value_of(&data_file, merged_pos.offset, key)
+  }
+
+  /// Repairs the index entry of `key` after reading `broken_pos` failed with `error`.
+  ///
+  /// All data files are scanned for the latest readable version of the key, the index is
+  /// updated to point at it and its value is returned. The index is only updated while it
+  /// still points at `broken_pos`, a write landing during the scan wins.
+  pub(crate) fn repair_read(
+    &self,
+    key: &[u8],
+    broken_pos: LogRecordPos,
+    error: Errors,
+  ) -> Result<Bytes> {
+    let latest = self.find_latest_version(key, broken_pos)?;
+
+    // the key lock orders the comparison with puts and deletes of the key
+    let _guard = self.lock_key(Bytes::copy_from_slice(key));
+    let _gate = self.write_gate.read_recursive();
+    match self.index.get(key.to_vec()) {
+      Some(pos) if pos == broken_pos => {}
+      Some(pos) => return self.get_value_by_position(&pos),
+      None => return Err(Errors::KeyNotFound),
+    }
+
+    let repaired_pos = match latest {
+      Some((LogRecordType::Normal | LogRecordType::Blob, pos)) => {
+        self.index.try_put(key.to_vec(), pos)?;
+        Some(pos)
+      }
+      _ => {
+        self.index.try_delete(key.to_vec())?;
+        None
+      }
+    };
+    warn!(
+      "read repair for broken position file {} offset {}: {error}",
+      broken_pos.file_id, broken_pos.offset
+    );
+
+    let mut incidents = self.read_repair_incidents.lock();
+    if incidents.len() >= MAX_READ_REPAIR_INCIDENTS {
+      incidents.remove(0);
+    }
+    incidents.push(ReadRepairIncident {
+      key: Bytes::copy_from_slice(key),
+      file_id: broken_pos.file_id,
+      offset: broken_pos.offset,
+      error,
+      repaired_pos: repaired_pos.map(|pos| (pos.file_id, pos.offset)),
+    });
+    drop(incidents);
+
+    match repaired_pos {
+      Some(pos) => self.get_value_by_position(&pos),
+      None => Err(Errors::KeyNotFound),
+    }
+  }
+
+  /// Scans all data files for the latest readable version of the key, skipping `exclude`.
+  ///
+  /// The file locks are taken per file, writes and rotations proceed between files. A file
+  /// rotated during the scan is found among the old files under the same id.
+  fn find_latest_version(
+    &self,
+    key: &[u8],
+    exclude: LogRecordPos,
+  ) -> Result<Option<(LogRecordType, LogRecordPos)>> {
+    let mut file_ids: Vec<u32> = self.old_data_files.read().keys().copied().collect();
+    file_ids.push(self.active_data_file.read().get_file_id());
+    file_ids.sort();
+
+    // transactions may span several files
+    let mut txn_records = HashMap::new();
+    let mut latest = None;
+    for file_id in file_ids {
+      if let Some(data_file) = self.old_data_files.read().get(&file_id) {
+        scan_file_for_key(data_file, key, exclude, &mut txn_records, &mut latest)?;
+        continue;
+      }
+      let active_file = self.active_data_file.read();
+      if active_file.get_file_id() == file_id {
+        scan_file_for_key(&active_file, key, exclude, &mut txn_records, &mut latest)?;
+      }
+    }
+
+    Ok(latest)
+  }
+}
+
+/// Reads the value of `key` at `offset`, `None` if the record is unreadable or not a value
+/// of the key.
+fn read_value_of(data_file: &DataFile, offset: u64, key: &[u8]) -> Option<Bytes> {
+  let record = data_file.read_log_record(offset).ok()?.record;
+  let (real_key, _) = parse_log_record_key(record.key).ok()?;
+  match record.rec_type == LogRecordType::Normal && real_key == key {
+    true => Some(record.value.into()),
+    false => None,
+  }
+}
+
+/// Updates `latest` with the versions of `key` found in the data file.
+///
+/// Scanning stops at the first unreadable record since its length can not be trusted.
+fn scan_file_for_key(
+  data_file: &DataFile,
+  key: &[u8],
+  exclude: LogRecordPos,
+  txn_records: &mut HashMap<usize, Vec<(LogRecordType, LogRecordPos)>>,
+  latest: &mut Option<(LogRecordType, LogRecordPos)>,
+) -> Result<()> {
+  let file_id = data_file.get_file_id();
+  let mut scanner = data_file.scan();
+  loop {
+    let (log_record, pos) = match scanner.next_record() {
+      Ok((result, offset)) => (
+        result.record,
+        LogRecordPos {
+          file_id,
+          offset,
+          size: result.size as u32,
+        },
+      ),
+      Err(Errors::ReadDataFileEOF) => break,
+      Err(Errors::InvalidLogRecordCrc) => break,
+      Err(e) => return Err(e),
+    };
+
+    // expire records hold no value
+    if matches!(
+      log_record.rec_type,
+      LogRecordType::FileFooter | LogRecordType::Expire
+    ) {
+      continue;
+    }
+
+    let (real_key, seq_no) = parse_log_record_key(log_record.key)?;
+    if log_record.rec_type == LogRecordType::TxnFinished {
+      if let Some(records) = txn_records.remove(&seq_no) {
+        if let Some(last) = records.last() {
+          *latest = Some(*last);
+        }
+      }
+      continue;
+    }
+    if real_key != key || pos == exclude {
+      continue;
+    }
+
+    if seq_no == NON_TXN_SEQ_NO {
+      *latest = Some((log_record.rec_type, pos));
+    } else {
+      txn_records
+        .entry(seq_no)
+        .or_default()
+        .push((log_record.rec_type, pos));
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
+mod tests {
+  use std::{fs::OpenOptions, os::unix::fs::FileExt, sync::Arc};
+
+  use parking_lot::Mutex;
+  use tempfile::tempdir;
+
+  use super::*;
+  use crate::{
+    event::EventListener,
+    option::{Options, WriteBatchOptions},
+  };
+
+  #[derive(Default)]
+  struct ReportCollector {
+    reports: Mutex<Vec<CorruptionReport>>,
+  }
+
+  impl EventListener for ReportCollector {
+    fn on_corruption(&self, report: &CorruptionReport) {
+      self.reports.lock().push(report.clone());
+    }
+  }
+
+  fn corrupt_record(opts: &Options, pos: LogRecordPos) {
+    let file = OpenOptions::new()
+      .write(true)
+      .open(get_data_file_name(&opts.dir_path, pos.file_id))
+      .unwrap();
+    file
+      .write_at(b"corrupted", pos.offset + pos.size as u64 - 8)
+      .unwrap();
+  }
+
+  #[test]
+  fn test_open_with_report_repair() {
+    let temp_dir = tempdir().expect("failed to create temp dir");
+    let mut opts = Options::default();
+    opts.dir_path = temp_dir.path().to_path_buf();
+    opts.data_file_size = 4096;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..300 {
+      engine
+        .put(Bytes::from(format!("key-{i:03}")), Bytes::from("value"))
+        .unwrap();
+    }
+    let broken_pos = engine.index.get(b"key-010".to_vec()).unwrap();
+    for key in ["txn-1", "txn-2"] {
+      let batch = engine
+        .new_write_batch(WriteBatchOptions::default())
+        .unwrap();
+      batch.put(Bytes::from(key), Bytes::from("value")).unwrap();
+      batch.commit().unwrap();
+    }
+    let active_file_id = engine.active_data_file.read().get_file_id();
+    assert!(active_file_id > broken_pos.file_id);
+    engine.close().unwrap();
+    drop(engine);
+
+    // a bad record in an older file and a torn commit record at the end of the active file
+    OpenOptions::new()
+      .write(true)
+      .open(get_data_file_name(&opts.dir_path, broken_pos.file_id))
+      .unwrap()
+      .write_at(b"!", broken_pos.offset + broken_pos.size as u64 - 1)
+      .unwrap();
+    let active_file = OpenOptions::new()
+      .write(true)
+      .open(get_data_file_name(&opts.dir_path, active_file_id))
+      .unwrap();
+    let size = active_file.metadata().unwrap().len();
+    active_file.set_len(size - 2).unwrap();
+    assert!(Engine::open(opts.clone()).is_err());
+
+    opts.repair_on_open = true;
+    let (engine, report) = Engine::open_with_report(opts.clone()).expect("failed to open engine");
+    assert_eq!(1, report.truncated_tails.len());
+    assert_eq!(active_file_id, report.truncated_tails[0].file_id);
+    assert_eq!(
+      size - 2,
+      report.truncated_tails[0].offset + report.truncated_tails[0].bytes
+    );
+    assert_eq!(
+      vec![SkippedRecord {
+        file_id: broken_pos.file_id,
+        offset: broken_pos.offset,
+        size: broken_pos.size as u64,
+        error: Errors::InvalidLogRecordCrc,
+      }],
+      report.skipped_records
+    );
+    assert_eq!(
+      (1, 1, 0),
+      (
+        report.committed_txns,
+        report.discarded_txns,
+        report.prepared_txns
+      )
+    );
+    let phases: Vec<_> = report.phases.iter().map(|(phase, _)| *phase).collect();
+    assert_eq!(
+      vec![
+        OpenPhase::FinishInterrupted,
+        OpenPhase::OpenFiles,
+        OpenPhase::LoadHints,
+        OpenPhase::ReplayFiles
+      ],
+      phases
+    );
+
+    // the keys around the dropped records are intact, writes go where the tail was
+    assert_eq!(
+      Errors::KeyNotFound,
+      engine.get(Bytes::from("key-010")).unwrap_err()
+    );
+    assert!(engine.get(Bytes::from("key-011")).is_ok());
+    assert!(engine.get(Bytes::from("txn-1")).is_ok());
+    assert_eq!(
+      Errors::KeyNotFound,
+      engine.get(Bytes::from("txn-2")).unwrap_err()
+    );
+    engine
+      .put(Bytes::from("txn-2"), Bytes::from("value"))
+      .unwrap();
+    engine.close().unwrap();
+    drop(engine);
+
+    let (engine, report) = Engine::open_with_report(opts).expect("failed to open engine");
+    assert!(report.truncated_tails.is_empty());
+    assert_eq!(1, report.skipped_records.len());
+    assert_eq!(301, engine.list_keys().unwrap().len());
+  }
+
+  #[test]
+  fn test_open_orphaned_txns() {
+    use std::sync::atomic::Ordering;
+
+    use crate::option::OrphanTxnPolicy;
+
+    let temp_dir = tempdir().expect("failed to create temp dir");
+    let mut opts = Options::default();
+    opts.dir_path = temp_dir.path().to_path_buf();
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    engine
+      .put(Bytes::from("key"), Bytes::from("value"))
+      .unwrap();
+    let seq_no = engine.seq_no.load(Ordering::SeqCst);
+    let batch = engine
+      .new_write_batch(WriteBatchOptions::default())
+      .unwrap();
+    batch.put(Bytes::from("txn"), Bytes::from("value")).unwrap();
+    batch.commit().unwrap();
+    let pos = engine.index.get(b"txn".to_vec()).unwrap();
+    engine.close().unwrap();
+    drop(engine);
+
+    // a crash before the commit record was written
+    OpenOptions::new()
+      .write(true)
+      .open(get_data_file_name(&opts.dir_path, pos.file_id))
+      .unwrap()
+      .set_len(pos.offset + pos.size as u64)
+      .unwrap();
+    let orphaned = vec![OrphanedTxn {
+      seq_no,
+      records: 1,
+      file_id: pos.file_id,
+      offset: pos.offset,
+    }];
+
+    opts.orphan_txn_policy = OrphanTxnPolicy::Error;
+    assert_eq!(
+      Errors::OrphanedTxnFound,
+      Engine::open(opts.clone()).err().unwrap()
+    );
+
+    // the decision is persisted, the other policy runs on a copy of the crashed files
+    let copy_dir = tempdir().expect("failed to create temp dir");
+    for entry in std::fs::read_dir(&opts.dir_path).unwrap() {
+      let entry = entry.unwrap();
+      std::fs::copy(entry.path(), copy_dir.path().join(entry.file_name())).unwrap();
+    }
+
+    opts.orphan_txn_policy = OrphanTxnPolicy::Apply;
+    let (engine, report) = Engine::open_with_report(opts.clone()).expect("failed to open engine");
+    assert_eq!(orphaned, report.orphaned_txns);
+    assert_eq!(0, report.discarded_txns);
+    assert_eq!(
+      Bytes::from("value"),
+      engine.get(Bytes::from("txn")).unwrap()
+    );
+    drop(engine);
+
+    // a later open does not see the orphaned transaction again
+    opts.orphan_txn_policy = OrphanTxnPolicy::Error;
+    let (engine, report) = Engine::open_with_report(opts.clone()).expect("failed to open engine");
+    assert!(report.orphaned_txns.is_empty());
+    assert_eq!(
+      Bytes::from("value"),
+      engine.get(Bytes::from("txn")).unwrap()
+    );
+    drop(engine);
+
+    opts.dir_path = copy_dir.path().to_path_buf();
+    opts.orphan_txn_policy = OrphanTxnPolicy::Discard;
+    let (engine, report) = Engine::open_with_report(opts.clone()).expect("failed to open engine");
+    assert_eq!(orphaned, report.orphaned_txns);
+    assert_eq!(1, report.discarded_txns);
+    assert_eq!(Err(Errors::KeyNotFound), engine.get(Bytes::from("txn")));
+    assert_eq!(
+      Bytes::from("value"),
+      engine.get(Bytes::from("key")).unwrap()
+    );
+    assert!(engine.get_engine_stat().unwrap().reclaim_size >= pos.size as usize);
+    drop(engine);
+
+    opts.orphan_txn_policy = OrphanTxnPolicy::Error;
+    let (engine, report) = Engine::open_with_report(opts.clone()).expect("failed to open engine");
+    assert!(report.orphaned_txns.is_empty());
+    assert_eq!(Err(Errors::KeyNotFound), engine.get(Bytes::from("txn")));
+  }
+
+  #[test]
+  fn test_read_repair_previous_version() {
+    let temp_dir = tempdir().expect("failed to create temp dir");
+    let mut opts = Options::default();
+    opts.dir_path = temp_dir.path().to_path_buf();
+    opts.read_repair = true;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    let key = Bytes::from("repair-key");
+    engine
+      .put(key.clone(), Bytes::from("value-version-1"))
+      .unwrap();
+    engine
+      .put(key.clone(), Bytes::from("value-version-2"))
+      .unwrap();
+    let broken_pos = engine.index.get(key.to_vec()).unwrap();
+    corrupt_record(&opts, broken_pos);
+
+    assert_eq!(
+      Bytes::from("value-version-1"),
+      engine.get(key.clone()).unwrap()
+    );
+    let incidents = engine.take_read_repair_incidents();
+    assert_eq!(1, incidents.len());
+    assert_eq!(Errors::InvalidLogRecordCrc, incidents[0].error);
+    assert_eq!(Some((0, 0)), incidents[0].repaired_pos);
+    assert!(engine.take_read_repair_incidents().is_empty());
+  }
+
+  #[test]
+  fn test_read_repair_keeps_newer_write() {
+    let temp_dir = tempdir().expect("failed to create temp dir");
+    let mut opts = Options::default();
+    opts.dir_path = temp_dir.path().to_path_buf();
+    opts.read_repair = true;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    let key = Bytes::from("repair-key");
+    engine
+      .put(key.clone(), Bytes::from("value-version-1"))
+      .unwrap();
+    engine
+      .put(key.clone(), Bytes::from("value-version-2"))
+      .unwrap();
+    let broken_pos = engine.index.get(key.to_vec()).unwrap();
+
+    // a write landing while the repair scans is not overwritten by the older version
+    engine
+      .put(key.clone(), Bytes::from("value-version-3"))
+      .unwrap();
+    let value = engine
+      .repair_read(&key, broken_pos, Errors::InvalidLogRecordCrc)
+      .unwrap();
+    assert_eq!(Bytes::from("value-version-3"), value);
+    assert_eq!(
+      Bytes::from("value-version-3"),
+      engine.get(key.clone()).unwrap()
+    );
+    assert!(engine.take_read_repair_incidents().is_empty());
+  }
+
+  #[test]
+  fn test_read_repair_disabled() {
+    let temp_dir = tempdir().expect("failed to create temp dir");
+    let mut opts = Options::default();
+    opts.dir_path = temp_dir.path().to_path_buf();
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    let key = Bytes::from("repair-key");
+    engine
+      .put(key.clone(), Bytes::from("value-version-1"))
+      .unwrap();
+    let broken_pos = engine.index.get(key.to_vec()).unwrap();
+    corrupt_record(&opts, broken_pos);
+
+    let collector = Arc::new(ReportCollector::default());
+    engine.add_event_listener(collector.clone());
+
+    assert_eq!(Errors::InvalidLogRecordCrc, engine.get(key).err().unwrap());
+    assert!(engine.take_read_repair_incidents().is_empty());
+    // the corruption is reported even though it is not recovered
+    let reports = collector.reports.lock();
+    assert_eq!(1, reports.len());
+    assert_eq!(Errors::InvalidLogRecordCrc, reports[0].error);
+    assert_eq!(None, reports[0].recovery);
+  }
+
+  #[test]
+  fn test_read_without_checksum() {
+    let temp_dir = tempdir().expect("failed to create temp dir");
+    let mut opts = Options::default();
+    opts.dir_path = temp_dir.path().to_path_buf();
+    opts.verify_checksums_on_read = false;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    let key = Bytes::from("repair-key");
+    engine
+      .put(key.clone(), Bytes::from("value-version-1"))
+      .unwrap();
+    let broken_pos = engine.index.get(key.to_vec()).unwrap();
+    corrupt_record(&opts, broken_pos);
+
+    // the corrupted value is returned as is
+    let value = engine.get(key.clone()).unwrap();
+    assert_ne!(Bytes::from("value-version-1"), value);
+    assert!(engine.take_read_repair_incidents().is_empty());
+  }
+
+  #[test]
+  fn test_read_repair_from_merge_output() {
+    let temp_dir = tempdir().expect("failed to create temp dir");
+    let mut opts = Options::default();
+    opts.dir_path = temp_dir.path().to_path_buf();
+    opts.read_repair = true;
+    opts.file_merge_threshold = 0.0;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    let collector = Arc::new(ReportCollector::default());
+    engine.add_event_listener(collector.clone());
+
+    let key = Bytes::from("repair-key");
+    engine
+      .put(key.clone(), Bytes::from("value-version-1"))
+      .unwrap();
+    engine
+      .put(key.clone(), Bytes::from("value-version-2"))
+      .unwrap();
+    engine.merge().unwrap();
+
+    // the index still points into the merged file until the next open
+    let broken_pos = engine.index.get(key.to_vec()).unwrap();
+    corrupt_record(&opts, broken_pos);
+
+    assert_eq!(
+      Bytes::from("value-version-2"),
+      engine.get(key.clone()).unwrap()
+    );
+    let reports = collector.reports.lock();
+    assert_eq!(1, reports.len());
+    assert_eq!(broken_pos.offset, reports[0].offset);
+    assert_eq!(Some(CorruptionRecovery::MergeOutput), reports[0].recovery);
+    // the index was left alone, the merge output replaces the file on the next open
+    assert!(engine.take_read_repair_incidents().is_empty());
+  }
+}
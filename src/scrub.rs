@@ -0,0 +1,331 @@
+use std::{
+  ops::Deref,
+  sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    mpsc::{self, RecvTimeoutError, Sender},
+    Arc,
+  },
+  thread::{self, JoinHandle},
+  time::Duration,
+};
+
+use log::{error, warn};
+
+use crate::{
+  db::Engine,
+  errors::{Errors, Result},
+  event::ScrubCorruption,
+  option::StorageMode,
+  throttle::RateLimiter,
+};
+
+/// Configuration of the data scrubber, see [`Engine::start_scrubber`].
+#[derive(Debug, Clone)]
+pub struct ScrubOptions {
+  /// Bytes per second read from the data files, 0 means unlimited
+  pub io_limit_bytes_per_sec: u64,
+
+  /// Pause between two passes over the data files
+  pub interval: Duration,
+}
+
+impl Default for ScrubOptions {
+  fn default() -> Self {
+    Self {
+      io_limit_bytes_per_sec: 4 * 1024 * 1024,
+      interval: Duration::from_secs(60 * 60),
+    }
+  }
+}
+
+/// Outcome of a pass of the scrubber over the sealed data files.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScrubReport {
+  /// Number of data files read
+  pub files: usize,
+
+  /// Number of records whose checksum was verified
+  pub records: u64,
+
+  /// Bytes of the records verified
+  pub bytes: u64,
+
+  /// Broken records found, at most one per file
+  pub corruptions: Vec<ScrubCorruption>,
+}
+
+/// Bytes verified and corruptions found by the scrubber since open.
+#[derive(Default)]
+pub(crate) struct ScrubCounters {
+  bytes: AtomicU64,
+  corruptions: AtomicU64,
+}
+
+impl ScrubCounters {
+  pub(crate) fn bytes(&self) -> u64 {
+    self.bytes.load(Ordering::Relaxed)
+  }
+
+  pub(crate) fn corruptions(&self) -> u64 {
+    self.corruptions.load(Ordering::Relaxed)
+  }
+}
+
+/// Handle of a running scrubber, the scrubber stops when the handle is dropped.
+pub struct ScrubberHandle {
+  stop: Arc<AtomicBool>,
+  stop_sender: Option<Sender<()>>,
+  worker: Option<JoinHandle<()>>,
+}
+
+impl Engine {
+  /// Reads every sealed data file once, verifying the checksum of each record, and reports
+  /// the broken records to the event listeners.
+  ///
+  /// Reads go through the file pool and are limited to `io_limit_bytes_per_sec`, 0 means
+  /// unlimited. The records of a file following a broken one are not checked, the length of
+  /// the broken record can not be trusted. The active file is left to the reads of the
+  /// writes, blob files are checked when their values are read.
+  pub fn scrub(&self, io_limit_bytes_per_sec: u64) -> Result<ScrubReport> {
+    scrub_pass(
+      || Some(self),
+      &RateLimiter::new(io_limit_bytes_per_sec),
+      &AtomicBool::new(false),
+    )
+  }
+
+  /// Starts a background task scrubbing the data files every `opts.interval`, see
+  /// [`Engine::scrub`]. The first pass starts right away.
+  ///
+  /// The task only holds a weak reference to the engine between the files it scrubs and exits
+  /// once the engine is dropped or closed, or the returned handle is dropped.
+  pub fn start_scrubber(self: &Arc<Self>, opts: ScrubOptions) -> Result<ScrubberHandle> {
+    if self.options.storage_mode == StorageMode::Memory {
+      return Err(Errors::MemoryModeUnsupported);
+    }
+    let engine = Arc::downgrade(self);
+    let stop = Arc::new(AtomicBool::new(false));
+    let worker_stop = Arc::clone(&stop);
+    let (stop_sender, stop_receiver) = mpsc::channel::<()>();
+
+    let worker = thread::Builder::new()
+      .name("flash-kv-scrubber".to_string())
+      .spawn(move || {
+        let limiter = RateLimiter::new(opts.io_limit_bytes_per_sec);
+        loop {
+          match scrub_pass(|| engine.upgrade(), &limiter, &worker_stop) {
+            Ok(_) => {}
+            Err(Errors::EngineClosed) => return,
+            Err(e) => warn!("failed to scrub data files: {e}"),
+          }
+
+          match stop_receiver.recv_timeout(opts.interval) {
+            Err(RecvTimeoutError::Timeout) => {}
+            _ => return,
+          }
+        }
+      })
+      .map_err(|e| {
+        warn!("failed to spawn scrubber: {e}");
+        Errors::FailedToStartBackgroundTask
+      })?;
+
+    Ok(ScrubberHandle {
+      stop,
+      stop_sender: Some(stop_sender),
+      worker: Some(worker),
+    })
+  }
+
+  // sealed data files to scrub, by file id
+  fn scrub_file_ids(&self) -> Result<Vec<u32>> {
+    self.check_open()?;
+    if self.options.storage_mode == StorageMode::Memory {
+      return Err(Errors::MemoryModeUnsupported);
+    }
+    let mut file_ids: Vec<u32> = self.old_data_files.read().keys().copied().collect();
+    file_ids.sort_unstable();
+    Ok(file_ids)
+  }
+
+  // scrubs a sealed data file into `report`, returns false if `stop` was set meanwhile
+  fn scrub_file(
+    &self,
+    file_id: u32,
+    limiter: &RateLimiter,
+    stop: &AtomicBool,
+    report: &mut ScrubReport,
+  ) -> Result<bool> {
+    self.check_open()?;
+    // a clear removes the files holding the lock, files cleared meanwhile are skipped
+    let data_file = {
+      let old_files = self.old_data_files.read();
+      if !old_files.contains_key(&file_id) {
+        return Ok(true);
+      }
+      self.open_old_data_file(file_id)?
+    };
+    report.files += 1;
+
+    let mut scanner = data_file.scan();
+    loop {
+      if stop.load(Ordering::Relaxed) {
+        return Ok(false);
+      }
+      match scanner.next_record() {
+        Ok((result, _)) => {
+          limiter.throttle(result.size);
+          report.records += 1;
+          report.bytes += result.size as u64;
+          self
+            .scrub_counters
+            .bytes
+            .fetch_add(result.size as u64, Ordering::Relaxed);
+        }
+        Err(Errors::ReadDataFileEOF) => return Ok(true),
+        Err(e) => {
+          let corruption = ScrubCorruption {
+            file_id,
+            offset: scanner.offset(),
+            error: e,
+          };
+          error!(
+            "scrubber found a broken record in data file {} at offset {}: {}",
+            corruption.file_id, corruption.offset, corruption.error
+          );
+          self
+            .scrub_counters
+            .corruptions
+            .fetch_add(1, Ordering::Relaxed);
+          self.listeners.scrub_corruption(&corruption);
+          report.corruptions.push(corruption);
+          return Ok(true);
+        }
+      }
+    }
+  }
+}
+
+// scrubs the sealed data files until done or `stop` is set, the engine is taken from
+// `engine` for every file and dropped in between
+fn scrub_pass<E, F>(engine: F, limiter: &RateLimiter, stop: &AtomicBool) -> Result<ScrubReport>
+where
+  E: Deref<Target = Engine>,
+  F: Fn() -> Option<E>,
+{
+  let file_ids = engine().ok_or(Errors::EngineClosed)?.scrub_file_ids()?;
+  let mut report = ScrubReport::default();
+  for file_id in file_ids {
+    let engine = engine().ok_or(Errors::EngineClosed)?;
+    if !engine.scrub_file(file_id, limiter, stop, &mut report)? {
+      break;
+    }
+  }
+  Ok(report)
+}
+
+impl ScrubberHandle {
+  /// Stops the scrubber, interrupting a running pass, and waits for it to exit.
+  pub fn stop(mut self) {
+    self.shutdown();
+  }
+
+  fn shutdown(&mut self) {
+    // dropping the sender wakes up the worker between passes
+    self.stop.store(true, Ordering::Relaxed);
+    self.stop_sender.take();
+    if let Some(worker) = self.worker.take() {
+      let _ = worker.join();
+    }
+  }
+}
+
+impl Drop for ScrubberHandle {
+  fn drop(&mut self) {
+    self.shutdown();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{fs::OpenOptions, os::unix::fs::FileExt};
+
+  use bytes::Bytes;
+  use parking_lot::Mutex;
+
+  use super::*;
+  use crate::{data::data_file::get_data_file_name, event::EventListener, option::Options};
+
+  #[derive(Default)]
+  struct CorruptionCollector {
+    corruptions: Mutex<Vec<ScrubCorruption>>,
+  }
+
+  impl EventListener for CorruptionCollector {
+    fn on_scrub_corruption(&self, corruption: &ScrubCorruption) {
+      self.corruptions.lock().push(corruption.clone());
+    }
+  }
+
+  #[test]
+  fn test_scrub() {
+    let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+    let opts = Options {
+      dir_path: temp_dir.path().to_path_buf(),
+      data_file_size: 4096,
+      ..Default::default()
+    };
+    let engine = Arc::new(Engine::open(opts.clone()).expect("failed to open engine"));
+    let collector = Arc::new(CorruptionCollector::default());
+    engine.add_event_listener(collector.clone());
+    for i in 0..300 {
+      engine
+        .put(Bytes::from(format!("key-{i:03}")), Bytes::from("value"))
+        .unwrap();
+    }
+
+    let report = engine.scrub(0).unwrap();
+    assert_eq!(engine.old_data_files.read().len(), report.files);
+    assert!(report.records > 0);
+    assert!(report.corruptions.is_empty());
+
+    // a broken record goes unnoticed by reads of other keys
+    let broken_pos = engine.index.get(b"key-010".to_vec()).unwrap();
+    OpenOptions::new()
+      .write(true)
+      .open(get_data_file_name(&opts.dir_path, broken_pos.file_id))
+      .unwrap()
+      .write_at(b"!", broken_pos.offset + broken_pos.size as u64 - 1)
+      .unwrap();
+    let report = engine.scrub(0).unwrap();
+    let expected = ScrubCorruption {
+      file_id: broken_pos.file_id,
+      offset: broken_pos.offset,
+      error: Errors::InvalidLogRecordCrc,
+    };
+    assert_eq!(vec![expected.clone()], report.corruptions);
+    assert_eq!(vec![expected.clone()], *collector.corruptions.lock());
+    let stat = engine.get_engine_stat().unwrap();
+    assert_eq!(1, stat.scrub_corruptions);
+    assert!(stat.scrubbed_bytes > report.bytes);
+
+    // the background task scrubs right away
+    let scrubber = engine
+      .start_scrubber(ScrubOptions {
+        io_limit_bytes_per_sec: 0,
+        interval: Duration::from_secs(60),
+      })
+      .unwrap();
+    for _ in 0..100 {
+      if collector.corruptions.lock().len() == 2 {
+        break;
+      }
+      thread::sleep(Duration::from_millis(10));
+    }
+    scrubber.stop();
+    assert_eq!(
+      vec![expected.clone(), expected],
+      *collector.corruptions.lock()
+    );
+  }
+}
@@ -0,0 +1,177 @@
+use std::{fs, sync::atomic::Ordering};
+
+use log::error;
+
+use crate::{
+  data::{
+    data_file::{DataFile, SEQ_NO_FILE_NAME, SEQ_NO_TMP_FILE_NAME},
+    log_record::{LogRecord, LogRecordType},
+  },
+  db::{parse_record_value, Engine},
+  errors::{DataFileOp, Errors, Result},
+  option::{IndexType, StorageMode},
+  reopen::remove_file_if_exists,
+  util::file::sync_dir,
+};
+
+const SEQ_NO_KEY: &str = "seq.no";
+
+/// Sequence numbers reserved by one write of the seq_no file, at most this many are skipped
+/// after a crash.
+pub(crate) const SEQ_NO_RESERVE: usize = 1024;
+
+impl Engine {
+  /// Loads the seq_no recorded for the B+ tree index, None if there is no seq_no file.
+  ///
+  /// The file is replaced as a whole and never removed, after a crash it holds the end of
+  /// the sequence numbers reserved before, so no number handed out is used again.
+  pub(crate) fn load_seq_no(&self) -> Result<Option<usize>> {
+    let dir_path = &self.options.dir_path;
+    remove_file_if_exists(&dir_path.join(SEQ_NO_TMP_FILE_NAME))?;
+    if !dir_path.join(SEQ_NO_FILE_NAME).is_file() {
+      return Ok(None);
+    }
+    let seq_no_file = DataFile::new_seq_no_file(dir_path)?;
+    let record = seq_no_file.read_log_record(0)?.record;
+    parse_record_value(record.value).map(Some)
+  }
+
+  /// Makes sure the seq_no file covers `seq_no`, which is about to be used by a transaction.
+  /// Called holding the batch commit lock.
+  pub(crate) fn reserve_seq_no(&self, seq_no: usize) -> Result<()> {
+    if self.options.index_type != IndexType::BPlusTree
+      || self.options.storage_mode == StorageMode::Memory
+    {
+      return Ok(());
+    }
+    if seq_no < self.seq_no_reserved.load(Ordering::SeqCst) {
+      return Ok(());
+    }
+    let reserved = seq_no + SEQ_NO_RESERVE;
+    self.write_seq_no(reserved)?;
+    self.seq_no_reserved.store(reserved, Ordering::SeqCst);
+    Ok(())
+  }
+
+  /// Records the next seq_no in the seq_no file. It is written to a temporary file renamed
+  /// over the old one, so a crash leaves either of them intact. Called holding the batch
+  /// commit lock, a concurrent write would lower the mark below a reserved number.
+  pub(crate) fn write_seq_no(&self, seq_no: usize) -> Result<()> {
+    let dir_path = &self.options.dir_path;
+    remove_file_if_exists(&dir_path.join(SEQ_NO_TMP_FILE_NAME))?;
+
+    let record = LogRecord {
+      key: SEQ_NO_KEY.as_bytes().to_vec(),
+      value: seq_no.to_string().into(),
+      rec_type: LogRecordType::Normal,
+      timestamp: None,
+    };
+    let seq_no_file = DataFile::new_seq_no_tmp_file(dir_path)?;
+    seq_no_file.write(&record.encode())?;
+    seq_no_file.sync()?;
+
+    fs::rename(
+      dir_path.join(SEQ_NO_TMP_FILE_NAME),
+      dir_path.join(SEQ_NO_FILE_NAME),
+    )
+    .map_err(|e| {
+      error!("failed to rename seq_no file: {e}");
+      Errors::FailedToRenameFile
+    })?;
+    // the reservation only holds once the rename is durable
+    sync_dir(dir_path).map_err(|e| Errors::data_file_io(DataFileOp::Sync, e))
+  }
+}
+
+#[cfg(all(test, feature = "bptree"))]
+mod tests {
+  use bytes::Bytes;
+
+  use super::*;
+  use crate::option::{Options, StartupIo, WriteBatchOptions};
+
+  #[test]
+  fn test_seq_no_file_replaced_atomically() {
+    let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+    let opts = Options {
+      dir_path: temp_dir.path().join("db"),
+      index_type: IndexType::BPlusTree,
+      startup_io: StartupIo::Runtime,
+      ..Default::default()
+    };
+    let seq_no_path = opts.dir_path.join(SEQ_NO_FILE_NAME);
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    let wb = engine
+      .new_write_batch(WriteBatchOptions::default())
+      .unwrap();
+    wb.put(Bytes::from("a"), Bytes::from("value")).unwrap();
+    wb.commit().unwrap();
+    engine.close().unwrap();
+    drop(engine);
+    let written = fs::read(&seq_no_path).unwrap();
+
+    // a crash while writing the next seq_no leaves the temporary file only
+    fs::write(opts.dir_path.join(SEQ_NO_TMP_FILE_NAME), &written[..3]).unwrap();
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(2, engine.seq_no.load(Ordering::SeqCst));
+    assert_eq!(written, fs::read(&seq_no_path).unwrap());
+    assert!(!opts.dir_path.join(SEQ_NO_TMP_FILE_NAME).is_file());
+  }
+
+  #[test]
+  fn test_seq_no_survives_crash() {
+    let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+    let opts = Options {
+      dir_path: temp_dir.path().join("db"),
+      index_type: IndexType::BPlusTree,
+      startup_io: StartupIo::Runtime,
+      ..Default::default()
+    };
+    let seq_no_path = opts.dir_path.join(SEQ_NO_FILE_NAME);
+    let commit = |engine: &Engine, key: &str| {
+      let wb = engine
+        .new_write_batch(WriteBatchOptions::default())
+        .unwrap();
+      wb.put(Bytes::from(key.to_string()), Bytes::from("value"))
+        .unwrap();
+      wb.commit().unwrap();
+    };
+
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    commit(&engine, "a");
+    // the first transaction reserved the following sequence numbers
+    let reserved = fs::read(&seq_no_path).unwrap();
+    commit(&engine, "b");
+    let used = engine.seq_no.load(Ordering::SeqCst);
+    assert_eq!(reserved, fs::read(&seq_no_path).unwrap());
+    engine.close().unwrap();
+    drop(engine);
+
+    // a crash loses the seq_no written on close, not the reservation
+    fs::write(&seq_no_path, reserved).unwrap();
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    let seq_no = engine.seq_no.load(Ordering::SeqCst);
+    assert!(seq_no >= used);
+    assert_eq!(1 + SEQ_NO_RESERVE, seq_no);
+    commit(&engine, "c");
+    assert!(seq_no_path.is_file());
+    engine.close().unwrap();
+    drop(engine);
+
+    let engine = Engine::open(opts).expect("failed to open engine");
+    assert_eq!(seq_no + 1, engine.seq_no.load(Ordering::SeqCst));
+    assert_eq!(3, engine.list_keys().unwrap().len());
+
+    // the file is written once per block of sequence numbers, not per commit
+    let seq_no = engine.seq_no.load(Ordering::SeqCst);
+    for i in 0..SEQ_NO_RESERVE {
+      commit(&engine, &format!("block-{i}"));
+      assert_eq!(Some(seq_no + SEQ_NO_RESERVE), engine.load_seq_no().unwrap());
+    }
+    commit(&engine, "next-block");
+    assert_eq!(
+      Some(seq_no + 2 * SEQ_NO_RESERVE),
+      engine.load_seq_no().unwrap()
+    );
+  }
+}
@@ -0,0 +1,408 @@
+use std::{
+  cmp, fs,
+  path::{Path, PathBuf},
+  sync::atomic::{AtomicUsize, Ordering},
+};
+
+use bytes::Bytes;
+use log::error;
+use parking_lot::Mutex;
+
+use crate::{
+  batch::WriteBatch,
+  db::{Engine, Stat},
+  errors::{Errors, Result},
+  iterator::Iterator,
+  option::{IteratorOptions, KeyComparator, Options, WriteBatchOptions},
+};
+
+// records the position of a directory in the shard layout, keys would be looked up in the
+// wrong shard if the directories were reordered or their number changed
+const SHARD_FILE_NAME: &str = "shard";
+
+/// Engine partitioning keys by hash over several engines, each in a directory of its own.
+///
+/// Every shard has its own active file and write lock, so writes to different shards do not
+/// contend and the directories may live on different disks. The directories have to be passed
+/// in the same order on every open.
+pub struct ShardedEngine {
+  shards: Vec<Engine>,
+}
+
+/// Write batch spanning several shards.
+///
+/// The writes of each shard are committed atomically, but a commit failing on one shard
+/// leaves the shards committed before it in place.
+pub struct ShardedWriteBatch<'a> {
+  engine: &'a ShardedEngine,
+  batches: Vec<WriteBatch<'a>>,
+}
+
+/// Iterator merging the iterators of all shards in key order.
+pub struct ShardedIterator<'a> {
+  iters: Vec<Iterator<'a>>,
+  heads: Mutex<Vec<Option<(Bytes, Bytes)>>>, // next item of every shard
+  reverse: bool,
+  comparator: Option<KeyComparator>, // order of the keys, byte order if None
+  limit: Option<usize>,              // bounds the merged items, every shard applies it as well
+  yielded: AtomicUsize,              // items returned since positioned
+}
+
+impl ShardedEngine {
+  /// Opens one engine per directory, `opts` is used for every shard with its `dir_path`
+  /// replaced.
+  pub fn open(opts: Options, dirs: Vec<PathBuf>) -> Result<Self> {
+    if dirs.is_empty() {
+      return Err(Errors::ShardDirsIsEmpty);
+    }
+
+    let count = dirs.len() as u32;
+    let mut shards = Vec::with_capacity(dirs.len());
+    for (index, dir_path) in dirs.into_iter().enumerate() {
+      let engine = Engine::open(Options {
+        dir_path,
+        ..opts.clone()
+      })?;
+      check_shard_file(&engine.options.dir_path, index as u32, count)?;
+      shards.push(engine);
+    }
+    Ok(Self { shards })
+  }
+
+  /// The underlying engines, in the order of their directories.
+  pub fn shards(&self) -> &[Engine] {
+    &self.shards
+  }
+
+  /// The engine holding `key`.
+  pub fn shard(&self, key: &[u8]) -> &Engine {
+    &self.shards[self.shard_index(key)]
+  }
+
+  fn shard_index(&self, key: &[u8]) -> usize {
+    // crc32 is stable across releases, unlike the std hasher
+    crc32fast::hash(key) as usize % self.shards.len()
+  }
+
+  pub fn put(&self, key: Bytes, value: Bytes) -> Result<()> {
+    self.shard(&key).put(key, value)
+  }
+
+  pub fn get(&self, key: Bytes) -> Result<Bytes> {
+    self.shard(&key).get(key)
+  }
+
+  pub fn delete(&self, key: Bytes) -> Result<()> {
+    self.shard(&key).delete(key)
+  }
+
+  /// Creates a write batch routing each write to the shard of its key.
+  pub fn new_write_batch(&self, options: WriteBatchOptions) -> Result<ShardedWriteBatch<'_>> {
+    let batches = self
+      .shards
+      .iter()
+      .map(|shard| {
+        shard.new_write_batch(WriteBatchOptions {
+          max_batch_num: options.max_batch_num,
+          max_batch_bytes: options.max_batch_bytes,
+          sync_writes: options.sync_writes,
+        })
+      })
+      .collect::<Result<Vec<_>>>()?;
+    Ok(ShardedWriteBatch {
+      engine: self,
+      batches,
+    })
+  }
+
+  /// Creates an iterator over all shards, keys are returned in order as with a single engine.
+  pub fn iter(&self, options: IteratorOptions) -> ShardedIterator<'_> {
+    let iters: Vec<_> = self
+      .shards
+      .iter()
+      .map(|shard| shard.iter(options.clone()))
+      .collect();
+    let heads = iters.iter().map(|iter| iter.next()).collect();
+    ShardedIterator {
+      iters,
+      heads: Mutex::new(heads),
+      reverse: options.reverse,
+      comparator: self.shards[0].options.key_comparator.clone(),
+      limit: options.limit,
+      yielded: AtomicUsize::new(0),
+    }
+  }
+
+  /// Lists the keys of all shards in order.
+  pub fn list_keys(&self) -> Result<Vec<Bytes>> {
+    let mut keys = Vec::new();
+    for shard in self.shards.iter() {
+      keys.extend(shard.list_keys()?);
+    }
+    match &self.shards[0].options.key_comparator {
+      Some(comparator) => keys.sort_by(|a, b| comparator.compare(a, b)),
+      None => keys.sort(),
+    }
+    Ok(keys)
+  }
+
+  /// Sums up the stat of all shards.
+  pub fn get_engine_stat(&self) -> Result<Stat> {
+    let mut total = Stat {
+      key_num: 0,
+      data_file_num: 0,
+      reclaim_size: 0,
+      tombstone_size: 0,
+      disk_size: 0,
+      bytes_read: 0,
+      bytes_returned: 0,
+      bytes_written: 0,
+      bytes_ingested: 0,
+      bytes_per_sync: 0,
+      scrubbed_bytes: 0,
+      scrub_corruptions: 0,
+    };
+    for shard in self.shards.iter() {
+      let stat = shard.get_engine_stat()?;
+      total.key_num += stat.key_num;
+      total.data_file_num += stat.data_file_num;
+      total.reclaim_size += stat.reclaim_size;
+      total.tombstone_size += stat.tombstone_size;
+      total.disk_size += stat.disk_size;
+      total.bytes_read += stat.bytes_read;
+      total.bytes_returned += stat.bytes_returned;
+      total.bytes_written += stat.bytes_written;
+      total.bytes_ingested += stat.bytes_ingested;
+      total.bytes_per_sync = total.bytes_per_sync.max(stat.bytes_per_sync);
+      total.scrubbed_bytes += stat.scrubbed_bytes;
+      total.scrub_corruptions += stat.scrub_corruptions;
+    }
+    Ok(total)
+  }
+
+  /// Merges every shard, shards below their merge threshold are skipped.
+  pub fn merge(&self) -> Result<()> {
+    for shard in self.shards.iter() {
+      match shard.merge() {
+        Ok(()) | Err(Errors::MergeThresholdUnreached) => {}
+        Err(e) => return Err(e),
+      }
+    }
+    Ok(())
+  }
+
+  pub fn sync(&self) -> Result<()> {
+    self.shards.iter().try_for_each(|shard| shard.sync())
+  }
+
+  pub fn close(&self) -> Result<()> {
+    self.shards.iter().try_for_each(|shard| shard.close())
+  }
+}
+
+impl ShardedWriteBatch<'_> {
+  pub fn put(&self, key: Bytes, value: Bytes) -> Result<()> {
+    self.batches[self.engine.shard_index(&key)].put(key, value)
+  }
+
+  pub fn delete(&self, key: Bytes) -> Result<()> {
+    self.batches[self.engine.shard_index(&key)].delete(key)
+  }
+
+  /// Marks the pending writes of every shard, see [`WriteBatch::savepoint`].
+  pub fn savepoint(&self) {
+    self.batches.iter().for_each(|batch| batch.savepoint());
+  }
+
+  /// Restores the pending writes of every shard to the latest savepoint.
+  pub fn rollback_to_savepoint(&self) -> Result<()> {
+    (self.batches.iter()).try_for_each(|batch| batch.rollback_to_savepoint())
+  }
+
+  /// Commits the writes of every shard, stops at the first shard failing.
+  pub fn commit(&self) -> Result<()> {
+    self.batches.iter().try_for_each(|batch| batch.commit())
+  }
+}
+
+impl ShardedIterator<'_> {
+  pub fn rewind(&self) {
+    let mut heads = self.heads.lock();
+    for (iter, head) in self.iters.iter().zip(heads.iter_mut()) {
+      iter.rewind();
+      *head = iter.next();
+    }
+    self.yielded.store(0, Ordering::SeqCst);
+  }
+
+  pub fn seek(&self, key: Vec<u8>) {
+    let mut heads = self.heads.lock();
+    for (iter, head) in self.iters.iter().zip(heads.iter_mut()) {
+      iter.seek(key.clone());
+      *head = iter.next();
+    }
+    self.yielded.store(0, Ordering::SeqCst);
+  }
+
+  pub fn next(&self) -> Option<(Bytes, Bytes)> {
+    // a key lives in exactly one shard, so the heads never hold the same key twice
+    let reverse = self.reverse;
+    let mut heads = self.heads.lock();
+    if self
+      .limit
+      .is_some_and(|limit| self.yielded.load(Ordering::SeqCst) >= limit)
+    {
+      return None;
+    }
+    let index = heads
+      .iter()
+      .enumerate()
+      .filter_map(|(i, head)| head.as_ref().map(|(key, _)| (i, key)))
+      .reduce(|a, b| match (self.compare(b.1, a.1).is_lt()) != reverse {
+        true => b,
+        false => a,
+      })?
+      .0;
+    let item = heads[index].take();
+    heads[index] = self.iters[index].next();
+    self.yielded.fetch_add(1, Ordering::SeqCst);
+    item
+  }
+
+  fn compare(&self, a: &[u8], b: &[u8]) -> cmp::Ordering {
+    match &self.comparator {
+      Some(comparator) => comparator.compare(a, b),
+      None => a.cmp(b),
+    }
+  }
+}
+
+/// Writes the position of a new shard directory, or checks the one written before.
+fn check_shard_file(dir_path: &Path, index: u32, count: u32) -> Result<()> {
+  let path = dir_path.join(SHARD_FILE_NAME);
+  let mut layout = [0u8; 8];
+  layout[..4].copy_from_slice(&index.to_be_bytes());
+  layout[4..].copy_from_slice(&count.to_be_bytes());
+
+  if !path.is_file() {
+    return fs::write(&path, layout).map_err(|e| {
+      error!("failed to write shard file: {e}");
+      Errors::FailedToWriteShardFile
+    });
+  }
+  let existing = fs::read(&path).map_err(|e| {
+    error!("failed to read shard file: {e}");
+    Errors::FailedToReadDatabaseDir
+  })?;
+  if existing != layout {
+    return Err(Errors::ShardLayoutMismatch);
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::util::rand_kv::{get_test_key, get_test_value};
+
+  fn open_shards(dirs: &[PathBuf]) -> Result<ShardedEngine> {
+    ShardedEngine::open(Options::default(), dirs.to_vec())
+  }
+
+  #[test]
+  fn test_sharded_engine_put_get_delete() {
+    let dir = tempfile::tempdir().unwrap();
+    let dirs: Vec<_> = (0..3).map(|i| dir.path().join(i.to_string())).collect();
+    let engine = open_shards(&dirs).unwrap();
+
+    for i in 0..300 {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    for i in 0..100 {
+      engine.delete(get_test_key(i)).unwrap();
+    }
+    assert_eq!(Err(Errors::KeyNotFound), engine.get(get_test_key(1)));
+    assert_eq!(get_test_value(200), engine.get(get_test_key(200)).unwrap());
+
+    // every shard got its share of the keys
+    for shard in engine.shards() {
+      assert!(shard.get_engine_stat().unwrap().key_num > 0);
+    }
+    assert_eq!(200, engine.get_engine_stat().unwrap().key_num);
+
+    let batch = engine
+      .new_write_batch(WriteBatchOptions::default())
+      .unwrap();
+    for i in 300..400 {
+      batch.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    batch.delete(get_test_key(200)).unwrap();
+    batch.commit().unwrap();
+    assert_eq!(299, engine.list_keys().unwrap().len());
+    assert_eq!(Err(Errors::KeyNotFound), engine.get(get_test_key(200)));
+
+    engine.close().unwrap();
+    drop(engine);
+    let engine = open_shards(&dirs).unwrap();
+    assert_eq!(get_test_value(399), engine.get(get_test_key(399)).unwrap());
+  }
+
+  #[test]
+  fn test_sharded_engine_iterator() {
+    let dir = tempfile::tempdir().unwrap();
+    let dirs: Vec<_> = (0..4).map(|i| dir.path().join(i.to_string())).collect();
+    let engine = open_shards(&dirs).unwrap();
+    for i in 0..200 {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+
+    let mut expected = engine.list_keys().unwrap();
+    assert!(expected.windows(2).all(|w| w[0] < w[1]));
+    let iter = engine.iter(IteratorOptions::default());
+    let mut keys = Vec::new();
+    while let Some((key, value)) = iter.next() {
+      assert_eq!(engine.get(key.clone()).unwrap(), value);
+      keys.push(key);
+    }
+    assert_eq!(expected, keys);
+
+    let iter = engine.iter(IteratorOptions {
+      reverse: true,
+      ..Default::default()
+    });
+    let mut keys = Vec::new();
+    while let Some((key, _)) = iter.next() {
+      keys.push(key);
+    }
+    expected.reverse();
+    assert_eq!(expected, keys);
+
+    // seek lands on the first key not before the target in every shard
+    let iter = engine.iter(IteratorOptions::default());
+    iter.seek(expected[10].to_vec());
+    assert_eq!(Some(expected[10].clone()), iter.next().map(|(key, _)| key));
+  }
+
+  #[test]
+  fn test_sharded_engine_layout_mismatch() {
+    let dir = tempfile::tempdir().unwrap();
+    let dirs: Vec<_> = (0..3).map(|i| dir.path().join(i.to_string())).collect();
+    assert_eq!(Some(Errors::ShardDirsIsEmpty), open_shards(&[]).err());
+
+    let engine = open_shards(&dirs).unwrap();
+    engine.close().unwrap();
+    drop(engine);
+
+    // fewer shards or a different order would route keys to the wrong directory
+    assert_eq!(
+      Some(Errors::ShardLayoutMismatch),
+      open_shards(&dirs[..2]).err()
+    );
+    let reordered = vec![dirs[1].clone(), dirs[0].clone(), dirs[2].clone()];
+    assert_eq!(
+      Some(Errors::ShardLayoutMismatch),
+      open_shards(&reordered).err()
+    );
+    assert!(open_shards(&dirs).is_ok());
+  }
+}
@@ -0,0 +1,171 @@
+use std::{
+  collections::VecDeque,
+  time::{Duration, Instant, SystemTime},
+};
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+
+use crate::db::Engine;
+
+// keep only the most recent slow operations
+const MAX_SLOW_LOG_ENTRIES: usize = 1024;
+
+/// Operations timed by the slow log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlowOp {
+  Put,
+  Get,
+  Delete,
+  Merge,
+}
+
+/// An operation which took at least `Options::slow_op_threshold`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlowLogEntry {
+  pub op: SlowOp,
+
+  /// The key of a put, get or delete
+  pub key: Option<Bytes>,
+
+  pub started_at: SystemTime,
+
+  pub elapsed: Duration,
+
+  /// Time spent in each phase of the operation, in the order they ran
+  pub phases: Vec<(&'static str, Duration)>,
+}
+
+/// Ring buffer of the most recent slow operations.
+#[derive(Default)]
+pub(crate) struct SlowLog {
+  entries: Mutex<VecDeque<SlowLogEntry>>,
+}
+
+impl SlowLog {
+  fn push(&self, entry: SlowLogEntry) {
+    let mut entries = self.entries.lock();
+    if entries.len() >= MAX_SLOW_LOG_ENTRIES {
+      entries.pop_front();
+    }
+    entries.push_back(entry);
+  }
+}
+
+/// Times the phases of an operation, does nothing unless the slow log is enabled.
+pub(crate) struct SlowOpTimer {
+  op: SlowOp,
+  enabled: bool,
+  started: Instant,
+  phase_started: Instant,
+  phases: Vec<(&'static str, Duration)>,
+}
+
+impl SlowOpTimer {
+  /// Ends the current phase of the operation.
+  pub(crate) fn phase(&mut self, name: &'static str) {
+    if !self.enabled {
+      return;
+    }
+    let now = Instant::now();
+    self.phases.push((name, now - self.phase_started));
+    self.phase_started = now;
+  }
+}
+
+impl Engine {
+  /// Takes the operations which exceeded `Options::slow_op_threshold` since the last call,
+  /// oldest first. Only the most recent 1024 are kept.
+  pub fn take_slow_log(&self) -> Vec<SlowLogEntry> {
+    let mut entries = self.slow_log.entries.lock();
+    entries.drain(..).collect()
+  }
+
+  pub(crate) fn slow_op_timer(&self, op: SlowOp) -> SlowOpTimer {
+    let now = Instant::now();
+    SlowOpTimer {
+      op,
+      enabled: !self.options.slow_op_threshold.is_zero(),
+      started: now,
+      phase_started: now,
+      phases: Vec::new(),
+    }
+  }
+
+  /// Records the operation if it took at least the threshold.
+  pub(crate) fn finish_slow_op(&self, timer: SlowOpTimer, key: Option<&Bytes>) {
+    if !timer.enabled {
+      return;
+    }
+    let elapsed = timer.started.elapsed();
+    if elapsed < self.options.slow_op_threshold {
+      return;
+    }
+    self.slow_log.push(SlowLogEntry {
+      op: timer.op,
+      key: key.cloned(),
+      started_at: SystemTime::now() - elapsed,
+      elapsed,
+      phases: timer.phases,
+    });
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::option::Options;
+
+  #[test]
+  fn test_slow_log() {
+    let dir = tempfile::tempdir().unwrap();
+    let opts = Options {
+      dir_path: dir.path().to_path_buf(),
+      slow_op_threshold: Duration::from_nanos(1),
+      file_merge_threshold: 0.0,
+      ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("fail to open engine");
+    engine
+      .put(Bytes::from("key"), Bytes::from("value"))
+      .unwrap();
+    engine.get(Bytes::from("key")).unwrap();
+    engine.delete(Bytes::from("key")).unwrap();
+    engine.merge().unwrap();
+
+    let entries = engine.take_slow_log();
+    let ops: Vec<SlowOp> = entries.iter().map(|entry| entry.op).collect();
+    let expected = vec![SlowOp::Put, SlowOp::Get, SlowOp::Delete, SlowOp::Merge];
+    assert_eq!(expected, ops);
+    let put = &entries[0];
+    assert_eq!(Some(Bytes::from("key")), put.key);
+    let phases: Vec<&str> = put.phases.iter().map(|(name, _)| *name).collect();
+    assert_eq!(vec!["throttle", "append", "index"], phases);
+    assert!(put.phases.iter().map(|(_, d)| *d).sum::<Duration>() <= put.elapsed);
+    let phases: Vec<&str> = entries[3].phases.iter().map(|(name, _)| *name).collect();
+    assert_eq!(vec!["prepare", "rotate", "rewrite", "finish"], phases);
+    assert!(engine.take_slow_log().is_empty());
+
+    // the ring buffer keeps the most recent operations
+    for i in 0..MAX_SLOW_LOG_ENTRIES + 10 {
+      engine
+        .put(Bytes::from(format!("key-{i}")), Bytes::from("value"))
+        .unwrap();
+    }
+    let entries = engine.take_slow_log();
+    assert_eq!(MAX_SLOW_LOG_ENTRIES, entries.len());
+    assert_eq!(Some(Bytes::from("key-10")), entries[0].key);
+    drop(engine);
+
+    // a zero threshold disables the slow log
+    let engine = Engine::open(Options {
+      slow_op_threshold: Duration::ZERO,
+      ..opts
+    })
+    .expect("fail to open engine");
+    engine
+      .put(Bytes::from("key"), Bytes::from("value"))
+      .unwrap();
+    assert!(engine.take_slow_log().is_empty());
+  }
+}
@@ -0,0 +1,305 @@
+use std::{
+  fs::{self, File, OpenOptions},
+  io::{self, Write},
+  path::{Path, PathBuf},
+  sync::{
+    mpsc::{self, RecvTimeoutError, Sender},
+    Arc, Weak,
+  },
+  thread::{self, JoinHandle},
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::warn;
+
+use crate::{
+  db::{Engine, Stat},
+  errors::{Errors, Result},
+  option::{StatsDumpConfig, StatsDumpFormat},
+};
+
+/// Handle of a running stats dump task, the task stops when the handle is dropped.
+pub struct StatsDumpHandle {
+  stop_sender: Option<Sender<()>>,
+  worker: Option<JoinHandle<()>>,
+}
+
+impl Engine {
+  /// Starts the background task configured by `Options::stats_dump`, which appends a snapshot
+  /// of the engine stat to its file every `interval`. The first snapshot is taken right away.
+  ///
+  /// The task only holds a weak reference to the engine and exits once the engine is dropped
+  /// or closed, or the returned handle is dropped.
+  pub fn start_stats_dump(self: &Arc<Self>) -> Result<StatsDumpHandle> {
+    let Some(config) = self.options.stats_dump.clone() else {
+      return Err(Errors::StatsDumpNotConfigured);
+    };
+    let mut file = StatsDumpFile::open(config)?;
+    let interval = file.config.interval;
+    let engine = Arc::downgrade(self);
+    let (stop_sender, stop_receiver) = mpsc::channel::<()>();
+
+    let worker = thread::Builder::new()
+      .name("flash-kv-stats-dump".to_string())
+      .spawn(move || loop {
+        if !dump_once(&engine, &mut file) {
+          return;
+        }
+        match stop_receiver.recv_timeout(interval) {
+          Err(RecvTimeoutError::Timeout) => {}
+          _ => return,
+        }
+      })
+      .map_err(|e| {
+        warn!("failed to spawn stats dump task: {e}");
+        Errors::FailedToStartBackgroundTask
+      })?;
+
+    Ok(StatsDumpHandle {
+      stop_sender: Some(stop_sender),
+      worker: Some(worker),
+    })
+  }
+}
+
+impl StatsDumpHandle {
+  /// Stops the dump task and waits for it to exit.
+  pub fn stop(mut self) {
+    self.shutdown();
+  }
+
+  fn shutdown(&mut self) {
+    // dropping the sender wakes up the worker
+    self.stop_sender.take();
+    if let Some(worker) = self.worker.take() {
+      let _ = worker.join();
+    }
+  }
+}
+
+impl Drop for StatsDumpHandle {
+  fn drop(&mut self) {
+    self.shutdown();
+  }
+}
+
+/// Append the current stat, returns false if the engine is gone.
+fn dump_once(engine: &Weak<Engine>, file: &mut StatsDumpFile) -> bool {
+  let stat = match engine.upgrade() {
+    Some(engine) => engine.get_engine_stat(),
+    None => return false,
+  };
+  match stat {
+    Ok(stat) => {
+      let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+      if let Err(e) = file.append(&stat, timestamp) {
+        warn!(
+          "failed to dump engine stat to {}: {e}",
+          file.config.path.display()
+        );
+      }
+      true
+    }
+    Err(Errors::EngineClosed) => false,
+    Err(e) => {
+      warn!("failed to collect engine stat: {e}");
+      true
+    }
+  }
+}
+
+/// The current file of a stats dump, rolled once it reaches `max_file_size`.
+struct StatsDumpFile {
+  config: StatsDumpConfig,
+  file: File,
+  size: u64,
+}
+
+impl StatsDumpFile {
+  fn open(config: StatsDumpConfig) -> Result<Self> {
+    let (file, size) = open_append(&config.path).map_err(|e| {
+      warn!("failed to open stats dump {}: {e}", config.path.display());
+      Errors::FailedToOpenStatsDump
+    })?;
+    Ok(Self { config, file, size })
+  }
+
+  fn append(&mut self, stat: &Stat, timestamp: u64) -> io::Result<()> {
+    let line = match self.config.format {
+      StatsDumpFormat::Csv => csv_row(stat, timestamp),
+      StatsDumpFormat::Json => stat.to_json(timestamp),
+    };
+    let max_file_size = self.config.max_file_size;
+    if max_file_size > 0 && self.size > 0 && self.size + line.len() as u64 + 1 > max_file_size {
+      self.roll()?;
+    }
+
+    let mut buf = String::new();
+    if self.size == 0 && self.config.format == StatsDumpFormat::Csv {
+      buf.push_str(&csv_header());
+      buf.push('\n');
+    }
+    buf.push_str(&line);
+    buf.push('\n');
+    self.file.write_all(buf.as_bytes())?;
+    self.size += buf.len() as u64;
+    Ok(())
+  }
+
+  // shifts the rolled files by one, the current file becomes `.1`
+  fn roll(&mut self) -> io::Result<()> {
+    let path = &self.config.path;
+    match self.config.max_files {
+      0 => remove_if_exists(path)?,
+      max_files => {
+        remove_if_exists(&rolled_path(path, max_files))?;
+        for i in (1..max_files).rev() {
+          match fs::rename(rolled_path(path, i), rolled_path(path, i + 1)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+          }
+        }
+        fs::rename(path, rolled_path(path, 1))?;
+      }
+    }
+    (self.file, self.size) = open_append(path)?;
+    Ok(())
+  }
+}
+
+fn open_append(path: &Path) -> io::Result<(File, u64)> {
+  let file = OpenOptions::new().create(true).append(true).open(path)?;
+  let size = file.metadata()?.len();
+  Ok((file, size))
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+  match fs::remove_file(path) {
+    Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+    _ => Ok(()),
+  }
+}
+
+fn rolled_path(path: &Path, i: usize) -> PathBuf {
+  let mut rolled = path.as_os_str().to_owned();
+  rolled.push(format!(".{i}"));
+  PathBuf::from(rolled)
+}
+
+fn csv_header() -> String {
+  let names = Stat::default().fields().map(|(name, _)| name);
+  format!("timestamp,{}", names.join(","))
+}
+
+fn csv_row(stat: &Stat, timestamp: u64) -> String {
+  let values = stat.fields().map(|(_, value)| value.to_string());
+  format!("{timestamp},{}", values.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::*;
+  use crate::option::Options;
+
+  fn read_lines(path: &Path) -> Vec<String> {
+    let content = fs::read_to_string(path).unwrap();
+    content.lines().map(str::to_string).collect()
+  }
+
+  #[test]
+  fn test_stats_dump_file_roll() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("stats.csv");
+    let config = StatsDumpConfig {
+      path: path.clone(),
+      max_file_size: 300,
+      max_files: 2,
+      ..Default::default()
+    };
+    let mut file = StatsDumpFile::open(config).unwrap();
+    let stat = Stat {
+      key_num: 3,
+      ..Default::default()
+    };
+    for timestamp in 0..20 {
+      file.append(&stat, timestamp).unwrap();
+    }
+
+    // every file starts with the header, the oldest ones are gone
+    let lines = read_lines(&path);
+    assert_eq!(csv_header(), lines[0]);
+    assert!(lines.last().unwrap().starts_with("19,3,"));
+    let rolled = read_lines(&rolled_path(&path, 1));
+    assert_eq!(csv_header(), rolled[0]);
+    assert!(fs::metadata(rolled_path(&path, 2)).unwrap().len() <= 300);
+    assert!(!rolled_path(&path, 3).exists());
+
+    // a reopened file continues without a second header
+    drop(file);
+    let config = StatsDumpConfig {
+      path: path.clone(),
+      max_file_size: 0,
+      ..Default::default()
+    };
+    let mut file = StatsDumpFile::open(config).unwrap();
+    file.append(&stat, 20).unwrap();
+    let lines = read_lines(&path);
+    assert_eq!(
+      1,
+      lines.iter().filter(|line| **line == csv_header()).count()
+    );
+    assert!(lines.last().unwrap().starts_with("20,3,"));
+  }
+
+  #[test]
+  fn test_start_stats_dump() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("stats.json");
+    let opts = Options {
+      dir_path: dir.path().join("db"),
+      ..Default::default()
+    };
+    let engine = Arc::new(Engine::open(opts.clone()).unwrap());
+    assert_eq!(
+      Errors::StatsDumpNotConfigured,
+      engine.start_stats_dump().err().unwrap()
+    );
+    drop(engine);
+
+    let opts = Options {
+      stats_dump: Some(StatsDumpConfig {
+        path: path.clone(),
+        format: StatsDumpFormat::Json,
+        interval: Duration::from_secs(60),
+        ..Default::default()
+      }),
+      ..opts
+    };
+    let engine = Arc::new(Engine::open(opts).unwrap());
+    engine
+      .put(bytes::Bytes::from("key"), bytes::Bytes::from("value"))
+      .unwrap();
+    let handle = engine.start_stats_dump().unwrap();
+    handle.stop();
+
+    let lines = read_lines(&path);
+    assert_eq!(1, lines.len());
+    assert!(lines[0].starts_with("{\"timestamp\":"));
+    assert!(lines[0].contains(",\"key_num\":1,"));
+
+    let invalid = StatsDumpConfig {
+      interval: Duration::ZERO,
+      ..Default::default()
+    };
+    let opts = Options {
+      stats_dump: Some(invalid),
+      ..Default::default()
+    };
+    assert_eq!(Err(Errors::InvalidStatsDump), opts.validate());
+  }
+}
@@ -0,0 +1,296 @@
+use std::{
+  io::{self, Read},
+  time::SystemTime,
+};
+
+use bytes::Bytes;
+use log::error;
+
+use crate::{
+  blob::BlobPointer,
+  data::log_record::{LogRecordPos, LogRecordType},
+  db::Engine,
+  errors::{Errors, Result},
+  expiry::unix_millis,
+};
+
+// initial buffer of `put_from_reader`, a short reader does not allocate its whole `len`
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Reads a value from its data file in chunks, see [`Engine::get_reader`].
+///
+/// The checksum of the record is verified once the last byte was read, a broken record fails
+/// the final read with `io::ErrorKind::InvalidData` instead of returning its last chunk.
+pub struct ValueReader<'a> {
+  engine: &'a Engine,
+  file_id: u32,
+  blob: bool,        // the value is in the blob file of the data file
+  value_offset: u64, // offset of the value in the data file
+  value_size: u64,
+  read_size: u64,            // bytes of the value read so far
+  hasher: crc32fast::Hasher, // checksum of the record up to the bytes read
+  expected_crc: u32,
+}
+
+impl Engine {
+  /// Returns a reader streaming the value of `key` from disk instead of loading it at once.
+  ///
+  /// Meant for large values. Unlike `get`, a broken record is not recovered. The reader looks
+  /// the data file up on every read, so it fails once the file was removed by a clear.
+  pub fn get_reader(&self, key: Bytes) -> Result<ValueReader<'_>> {
+    if key.is_empty() {
+      return Err(Errors::KeyIsEmpty);
+    }
+    if let Some((at, _)) = self.expiry.get(&key) {
+      if at <= unix_millis(SystemTime::now()) {
+        return Err(Errors::KeyNotFound);
+      }
+    }
+    self.read_indexed(&key, |pos| ValueReader::new(self, pos))
+  }
+
+  /// Stores the next `len` bytes of `reader` as the value of `key`.
+  ///
+  /// The record is appended in a single write like any other, so the value is buffered once
+  /// here instead of by the caller. A `len` larger than `data_file_size` is rejected up front,
+  /// the buffer grows with the bytes actually read instead of being sized by `len`.
+  pub fn put_from_reader<R: Read>(&self, key: Bytes, reader: R, len: usize) -> Result<()> {
+    if len as u64 > self.options.data_file_size {
+      return Err(Errors::ValueTooLarge);
+    }
+    let mut value = Vec::with_capacity(len.min(READ_CHUNK_SIZE));
+    let read_size = reader
+      .take(len as u64)
+      .read_to_end(&mut value)
+      .map_err(|e| {
+        error!("failed to read value: {e}");
+        Errors::FailedToReadValue
+      })?;
+    if read_size < len {
+      return Err(Errors::FailedToReadValue);
+    }
+    self.put(key, Bytes::from(value))
+  }
+
+  // reads raw bytes of a data file or its blob file
+  fn read_data_at(&self, file_id: u32, blob: bool, buf: &mut [u8], offset: u64) -> Result<usize> {
+    if blob {
+      return self.with_blob_file(file_id, |blob_file| blob_file.read_at(buf, offset));
+    }
+    let active_file = self.active_data_file.read();
+    if active_file.get_file_id() == file_id {
+      return active_file.read_at(buf, offset);
+    }
+    let old_files = self.old_data_files.read();
+    let data_file = old_files.get(&file_id).ok_or(Errors::DataFileNotFound)?;
+    data_file.read_at(buf, offset)
+  }
+
+  // fills `buf` entirely, the record is known to be complete
+  fn read_data_exact(&self, file_id: u32, blob: bool, buf: &mut [u8], offset: u64) -> Result<()> {
+    let mut filled = 0;
+    while filled < buf.len() {
+      let n = self.read_data_at(file_id, blob, &mut buf[filled..], offset + filled as u64)?;
+      if n == 0 {
+        return Err(Errors::InvalidLogRecord);
+      }
+      filled += n;
+    }
+    Ok(())
+  }
+}
+
+impl<'a> ValueReader<'a> {
+  fn new(engine: &'a Engine, pos: LogRecordPos) -> Result<Self> {
+    let header = {
+      let active_file = engine.active_data_file.read();
+      match active_file.get_file_id() == pos.file_id {
+        true => active_file.read_record_header(pos.offset)?,
+        false => engine
+          .old_data_files
+          .read()
+          .get(&pos.file_id)
+          .ok_or(Errors::DataFileNotFound)?
+          .read_record_header(pos.offset)?,
+      }
+    };
+    let (blob, offset, header) = match header.rec_type {
+      LogRecordType::Deleted => return Err(Errors::KeyNotFound),
+      // the tag covers the whole value, it cannot be checked chunk by chunk
+      LogRecordType::Encrypted => return Err(Errors::EncryptionUnsupported),
+      // the value is streamed from the record the pointer refers to
+      LogRecordType::Blob => {
+        let mut pointer = vec![0; header.value_size];
+        let pointer_offset = pos.offset + (header.header_size + header.key_size) as u64;
+        engine.read_data_exact(pos.file_id, false, &mut pointer, pointer_offset)?;
+        let pointer = BlobPointer::decode(&pointer)?;
+        let blob_header = engine.with_blob_file(pos.file_id, |blob_file| {
+          blob_file.read_record_header(pointer.offset)
+        })?;
+        (true, pointer.offset, blob_header)
+      }
+      _ => (false, pos.offset, header),
+    };
+
+    // the checksum covers the header and the key in front of the value
+    let mut prefix = vec![0; header.header_size + header.key_size];
+    engine.read_data_exact(pos.file_id, blob, &mut prefix, offset)?;
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&prefix);
+
+    let value_offset = offset + prefix.len() as u64;
+    let mut crc_buf = [0; 4];
+    engine.read_data_exact(
+      pos.file_id,
+      blob,
+      &mut crc_buf,
+      value_offset + header.value_size as u64,
+    )?;
+
+    Ok(ValueReader {
+      engine,
+      file_id: pos.file_id,
+      blob,
+      value_offset,
+      value_size: header.value_size as u64,
+      read_size: 0,
+      hasher,
+      expected_crc: u32::from_be_bytes(crc_buf),
+    })
+  }
+
+  /// Size of the whole value in bytes.
+  pub fn len(&self) -> u64 {
+    self.value_size
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.value_size == 0
+  }
+}
+
+impl Read for ValueReader<'_> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let remaining = self.value_size - self.read_size;
+    let len = buf.len().min(remaining as usize);
+    if len == 0 {
+      return Ok(0);
+    }
+
+    let n = self
+      .engine
+      .read_data_at(
+        self.file_id,
+        self.blob,
+        &mut buf[..len],
+        self.value_offset + self.read_size,
+      )
+      .map_err(io::Error::other)?;
+    if n == 0 {
+      return Err(io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        Errors::InvalidLogRecord,
+      ));
+    }
+    self.hasher.update(&buf[..n]);
+    self.read_size += n as u64;
+
+    if self.read_size == self.value_size && self.hasher.clone().finalize() != self.expected_crc {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        Errors::InvalidLogRecordCrc,
+      ));
+    }
+    Ok(n)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Cursor;
+
+  use super::*;
+  use crate::{data::data_file::get_data_file_name, option::Options};
+
+  #[test]
+  fn test_value_reader() {
+    let dir = tempfile::tempdir().unwrap();
+    let opts = Options {
+      dir_path: dir.path().to_path_buf(),
+      ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("fail to open engine");
+
+    let value: Vec<u8> = (0..3 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    engine
+      .put_from_reader(Bytes::from("blob"), Cursor::new(&value), value.len())
+      .unwrap();
+    assert_eq!(value, engine.get(Bytes::from("blob")).unwrap());
+
+    // read in small chunks
+    let mut reader = engine.get_reader(Bytes::from("blob")).unwrap();
+    assert_eq!(value.len() as u64, reader.len());
+    let mut streamed = Vec::new();
+    let mut chunk = [0; 4096];
+    loop {
+      let n = reader.read(&mut chunk).unwrap();
+      if n == 0 {
+        break;
+      }
+      streamed.extend_from_slice(&chunk[..n]);
+    }
+    assert_eq!(value, streamed);
+
+    // a short reader stores nothing
+    assert_eq!(
+      Errors::FailedToReadValue,
+      engine
+        .put_from_reader(Bytes::from("short"), Cursor::new(b"abc"), 4)
+        .unwrap_err()
+    );
+    assert!(matches!(
+      engine.get_reader(Bytes::from("short")),
+      Err(Errors::KeyNotFound)
+    ));
+    // a length beyond a data file fails before reading anything
+    assert_eq!(
+      Errors::ValueTooLarge,
+      engine
+        .put_from_reader(Bytes::from("huge"), Cursor::new(b"abc"), usize::MAX)
+        .unwrap_err()
+    );
+    engine.delete(Bytes::from("blob")).unwrap();
+    assert!(matches!(
+      engine.get_reader(Bytes::from("blob")),
+      Err(Errors::KeyNotFound)
+    ));
+  }
+
+  #[test]
+  fn test_value_reader_checksum() {
+    let dir = tempfile::tempdir().unwrap();
+    let opts = Options {
+      dir_path: dir.path().to_path_buf(),
+      ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("fail to open engine");
+    engine
+      .put(Bytes::from("key"), Bytes::from(vec![7u8; 64 * 1024]))
+      .unwrap();
+
+    // flip a byte in the middle of the value
+    let pos = engine.index.get(b"key".to_vec()).unwrap();
+    let path = get_data_file_name(&opts.dir_path, pos.file_id);
+    let mut content = std::fs::read(&path).unwrap();
+    content[pos.offset as usize + 32 * 1024] ^= 0xff;
+    std::fs::write(&path, content).unwrap();
+
+    let mut streamed = Vec::new();
+    let err = engine
+      .get_reader(Bytes::from("key"))
+      .unwrap()
+      .read_to_end(&mut streamed)
+      .unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+  }
+}
@@ -0,0 +1,227 @@
+use std::{
+  collections::BTreeMap,
+  io,
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+  },
+};
+
+use parking_lot::Mutex;
+
+use crate::{
+  errors::{DataFileOp, Errors, Result},
+  fio::IOManager,
+  option::IOManagerWrapper,
+};
+
+/// What happens to a write hit by a fault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+  /// The write is reported as done but nothing reaches the file
+  Drop,
+
+  /// Only the first bytes of the write reach the file, it is reported as done
+  Truncate(usize),
+
+  /// The write reaches the file with its middle byte flipped
+  Garble,
+
+  /// The write fails with `Errors::DataFileIo`
+  Fail,
+}
+
+/// Decides which writes of the wrapped IO managers hit a fault.
+///
+/// Writes are numbered from 0 in the order they reach any of the data files, so a test can
+/// first count the writes of a workload and then replay it with a fault at every one of them.
+#[derive(Debug, Default)]
+pub struct FaultInjector {
+  writes: AtomicUsize,
+  faults: Mutex<BTreeMap<usize, Fault>>,
+  crash_at: Mutex<Option<usize>>,
+}
+
+impl FaultInjector {
+  pub fn new() -> Arc<Self> {
+    Arc::new(Self::default())
+  }
+
+  /// Makes the write with the given number hit `fault`.
+  pub fn inject(&self, write: usize, fault: Fault) {
+    self.faults.lock().insert(write, fault);
+  }
+
+  /// Drops the write with the given number and every later one, like a process dying before
+  /// its page cache reached the disk. Syncs after the crash do nothing.
+  pub fn crash_at(&self, write: usize) {
+    *self.crash_at.lock() = Some(write);
+  }
+
+  /// Number of writes seen so far.
+  pub fn writes(&self) -> usize {
+    self.writes.load(Ordering::SeqCst)
+  }
+
+  /// Whether the crash point was reached.
+  pub fn crashed(&self) -> bool {
+    matches!(*self.crash_at.lock(), Some(write) if self.writes() > write)
+  }
+
+  /// Wrapper for `Options::io_manager_wrapper` injecting the faults into every data file.
+  pub fn io_manager_wrapper(self: &Arc<Self>) -> IOManagerWrapper {
+    let injector = self.clone();
+    IOManagerWrapper::new(move |_, inner| {
+      Box::new(FaultInjectingIOManager::new(inner, injector.clone()))
+    })
+  }
+
+  fn next_fault(&self) -> Option<Fault> {
+    let write = self.writes.fetch_add(1, Ordering::SeqCst);
+    match *self.crash_at.lock() {
+      Some(crash_at) if write >= crash_at => Some(Fault::Drop),
+      _ => self.faults.lock().remove(&write),
+    }
+  }
+}
+
+/// IO manager passing reads through and applying the faults of a [`FaultInjector`] to writes.
+pub struct FaultInjectingIOManager {
+  inner: Box<dyn IOManager>,
+  injector: Arc<FaultInjector>,
+}
+
+impl FaultInjectingIOManager {
+  pub fn new(inner: Box<dyn IOManager>, injector: Arc<FaultInjector>) -> Self {
+    Self { inner, injector }
+  }
+}
+
+impl IOManager for FaultInjectingIOManager {
+  fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+    self.inner.read(buf, offset)
+  }
+
+  fn read_batch(&self, reads: &mut [(&mut [u8], u64)]) -> Result<Vec<usize>> {
+    self.inner.read_batch(reads)
+  }
+
+  fn write(&self, buf: &[u8]) -> Result<usize> {
+    match self.injector.next_fault() {
+      None => self.inner.write(buf),
+      Some(Fault::Drop) => Ok(buf.len()),
+      Some(Fault::Truncate(len)) => {
+        self.inner.write(&buf[..len.min(buf.len())])?;
+        Ok(buf.len())
+      }
+      Some(Fault::Garble) => {
+        let mut garbled = buf.to_vec();
+        if let Some(byte) = garbled.get_mut(buf.len() / 2) {
+          *byte ^= 0xff;
+        }
+        self.inner.write(&garbled)
+      }
+      Some(Fault::Fail) => Err(Errors::data_file_io(
+        DataFileOp::Write,
+        io::Error::other("injected write fault"),
+      )),
+    }
+  }
+
+  fn sync(&self) -> Result<()> {
+    match self.injector.crashed() {
+      true => Ok(()),
+      false => self.inner.sync(),
+    }
+  }
+
+  fn size(&self) -> u64 {
+    self.inner.size()
+  }
+}
+
+#[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
+mod tests {
+  use super::*;
+  use crate::{
+    db::Engine,
+    option::Options,
+    util::rand_kv::{get_test_key, get_test_value},
+  };
+
+  const KEYS: usize = 40;
+
+  fn open(dir: &std::path::Path, injector: Option<&Arc<FaultInjector>>) -> Result<Engine> {
+    let mut opts = Options::default();
+    opts.dir_path = dir.to_path_buf();
+    opts.data_file_size = 1024;
+    opts.io_manager_wrapper = injector.map(|injector| injector.io_manager_wrapper());
+    Engine::open(opts)
+  }
+
+  /// Writes the workload, returns the number of puts acknowledged.
+  fn write_workload(engine: &Engine) -> usize {
+    (0..KEYS)
+      .take_while(|i| engine.put(get_test_key(*i), get_test_value(*i)).is_ok())
+      .count()
+  }
+
+  #[test]
+  fn test_recovery_after_crash_at_every_write() {
+    let dir = tempfile::tempdir().unwrap();
+    let injector = FaultInjector::new();
+    let engine = open(dir.path(), Some(&injector)).unwrap();
+    assert_eq!(KEYS, write_workload(&engine));
+    let total_writes = injector.writes();
+    assert!(total_writes >= KEYS);
+
+    for crash_at in 0..total_writes {
+      for fault in [None, Some(Fault::Truncate(5)), Some(Fault::Garble)] {
+        let dir = tempfile::tempdir().unwrap();
+        let injector = FaultInjector::new();
+        if let Some(fault) = fault {
+          injector.inject(crash_at, fault);
+        }
+        injector.crash_at(crash_at + fault.map_or(0, |_| 1));
+        let engine = open(dir.path(), Some(&injector)).unwrap();
+        write_workload(&engine);
+        drop(engine);
+
+        // the keys written before the crash survive and nothing after it, a torn or garbled
+        // record is reported as corruption instead of being read back
+        match open(dir.path(), None) {
+          Ok(engine) => {
+            let keys = engine.list_keys().unwrap();
+            for (i, key) in keys.iter().enumerate() {
+              assert_eq!(get_test_key(i), key);
+              assert_eq!(get_test_value(i), engine.get(key.clone()).unwrap());
+            }
+            assert!(keys.len() <= crash_at);
+          }
+          Err(e) => {
+            assert!(fault.is_some(), "crash at {crash_at} failed the open: {e}");
+            assert!(matches!(
+              e,
+              Errors::InvalidLogRecord
+                | Errors::InvalidLogRecordCrc
+                | Errors::TruncatedLogRecord { .. }
+            ));
+          }
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn test_failed_write() {
+    let dir = tempfile::tempdir().unwrap();
+    let injector = FaultInjector::new();
+    injector.inject(3, Fault::Fail);
+    let engine = open(dir.path(), Some(&injector)).unwrap();
+    // the fourth put fails, the fault hits a single write
+    assert_eq!(3, write_workload(&engine));
+    engine.put(get_test_key(3), get_test_value(3)).unwrap();
+    assert_eq!(get_test_value(3), engine.get(get_test_key(3)).unwrap());
+  }
+}
@@ -0,0 +1,306 @@
+use std::{
+  path::Path,
+  sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+  thread,
+  time::{Duration, Instant},
+};
+
+use parking_lot::{Mutex, RwLock};
+
+use crate::{
+  db::Engine,
+  errors::{Errors, Result},
+  option::{Options, SyncPolicy},
+  util::file::available_disk_space_of,
+};
+
+// the adaptive sync policy aims at one sync per interval at the current write rate
+const ADAPTIVE_SYNC_INTERVAL: Duration = Duration::from_millis(100);
+
+// free disk space is sampled again after this long
+const DISK_SPACE_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Limits a rate of bytes, callers beyond the rate sleep until their turn.
+pub(crate) struct RateLimiter {
+  bytes_per_sec: AtomicU64,  // 0 means unlimited
+  next_free: Mutex<Instant>, // point in time at which all admitted bytes are paid for
+}
+
+impl RateLimiter {
+  pub(crate) fn new(bytes_per_sec: u64) -> Self {
+    Self {
+      bytes_per_sec: AtomicU64::new(bytes_per_sec),
+      next_free: Mutex::new(Instant::now()),
+    }
+  }
+
+  /// Changes the rate, the bytes admitted at the old rate are forgiven.
+  pub(crate) fn set_rate(&self, bytes_per_sec: u64) {
+    let mut next_free = self.next_free.lock();
+    self.bytes_per_sec.store(bytes_per_sec, Ordering::Relaxed);
+    *next_free = Instant::now();
+  }
+
+  /// Bytes per second admitted, 0 means unlimited.
+  pub(crate) fn rate(&self) -> u64 {
+    self.bytes_per_sec.load(Ordering::Relaxed)
+  }
+
+  /// Admits `bytes`, returns how long the caller has to wait before using them.
+  fn admit(&self, bytes: usize) -> Duration {
+    // unlimited callers don't contend on the lock
+    if self.bytes_per_sec.load(Ordering::Relaxed) == 0 {
+      return Duration::ZERO;
+    }
+    let mut next_free = self.next_free.lock();
+    let bytes_per_sec = self.bytes_per_sec.load(Ordering::Relaxed);
+    if bytes_per_sec == 0 {
+      return Duration::ZERO;
+    }
+    let cost = Duration::from_secs_f64(bytes as f64 / bytes_per_sec as f64);
+    let now = Instant::now();
+    // idle time is not saved up, a burst after a pause is still throttled
+    let start = (*next_free).max(now);
+    *next_free = start + cost;
+    start - now
+  }
+
+  pub(crate) fn throttle(&self, bytes: usize) {
+    let wait = self.admit(bytes);
+    if !wait.is_zero() {
+      thread::sleep(wait);
+    }
+  }
+}
+
+/// Bytes appended to the active file between syncs, moved with the write rate under
+/// `SyncPolicy::Adaptive`.
+pub(crate) struct SyncTuner {
+  policy: RwLock<SyncPolicy>,
+  bytes_per_sync: AtomicUsize, // 0 leaves syncing to the OS
+  last_sync: Mutex<Instant>,
+  last_tune: Mutex<Instant>, // last retune, on a sync or on the write path
+}
+
+impl SyncTuner {
+  pub(crate) fn new(options: &Options) -> Self {
+    let bytes_per_sync = match options.sync_policy {
+      SyncPolicy::Fixed => options.bytes_per_sync,
+      SyncPolicy::Adaptive { min, .. } => min,
+    };
+    Self {
+      policy: RwLock::new(options.sync_policy),
+      bytes_per_sync: AtomicUsize::new(bytes_per_sync),
+      last_sync: Mutex::new(Instant::now()),
+      last_tune: Mutex::new(Instant::now()),
+    }
+  }
+
+  pub(crate) fn bytes_per_sync(&self) -> usize {
+    self.bytes_per_sync.load(Ordering::Relaxed)
+  }
+
+  /// Switches to `policy`, an adaptive policy continues from the current value.
+  pub(crate) fn reconfigure(&self, policy: SyncPolicy, bytes_per_sync: usize) {
+    let mut current = self.policy.write();
+    *current = policy;
+    let next = match policy {
+      SyncPolicy::Fixed => bytes_per_sync,
+      SyncPolicy::Adaptive { min, max } => self.bytes_per_sync().clamp(min, max),
+    };
+    self.bytes_per_sync.store(next, Ordering::Relaxed);
+  }
+
+  /// Retunes after a sync of the `bytes` appended since the previous one.
+  pub(crate) fn synced(&self, bytes: usize) {
+    let mut last_sync = self.last_sync.lock();
+    let now = Instant::now();
+    self.retune(bytes, now.duration_since(*last_sync));
+    *last_sync = now;
+    *self.last_tune.lock() = now;
+  }
+
+  /// Retunes on the write path with the `pending` bytes not synced yet once an interval
+  /// passed without a sync, so the value comes down again when writes turn sparse after a
+  /// burst instead of waiting for a sync which may be far off.
+  pub(crate) fn appended(&self, pending: usize) {
+    if !matches!(*self.policy.read(), SyncPolicy::Adaptive { .. }) {
+      return;
+    }
+    let now = Instant::now();
+    {
+      let mut last_tune = self.last_tune.lock();
+      if now.duration_since(*last_tune) < ADAPTIVE_SYNC_INTERVAL {
+        return;
+      }
+      *last_tune = now;
+    }
+    let since_sync = now.duration_since(*self.last_sync.lock());
+    self.retune(pending, since_sync);
+  }
+
+  // moves the value towards the bytes written per `ADAPTIVE_SYNC_INTERVAL` at the rate of
+  // `bytes` in `elapsed`
+  fn retune(&self, bytes: usize, elapsed: Duration) {
+    let SyncPolicy::Adaptive { min, max } = *self.policy.read() else {
+      return;
+    };
+    let rate = bytes as f64 / elapsed.as_secs_f64();
+
+    // halfway to the target, a single burst does not swing the value
+    let target = ((rate * ADAPTIVE_SYNC_INTERVAL.as_secs_f64()) as usize).clamp(min, max);
+    let current = self.bytes_per_sync.load(Ordering::Relaxed).clamp(min, max);
+    let next = match target > current {
+      true => current + (target - current).div_ceil(2),
+      false => current - (current - target).div_ceil(2),
+    };
+    self.bytes_per_sync.store(next, Ordering::Relaxed);
+  }
+}
+
+/// Free space of the volume of the database, sampled for `reserved_disk_bytes`.
+#[derive(Default)]
+pub(crate) struct DiskSpace {
+  sample: Mutex<Option<(Instant, u64)>>, // time and free bytes of the last sample
+  written: AtomicU64,                    // bytes admitted since the last sample
+}
+
+impl DiskSpace {
+  /// Admits a write of `bytes` if at least `reserve` bytes stay free after it. The sample
+  /// is refreshed once it is old or the writes since may have used up the margin.
+  fn admit(&self, dir_path: &Path, reserve: u64, bytes: u64) -> bool {
+    let written = self.written.fetch_add(bytes, Ordering::Relaxed) + bytes;
+    let mut sample = self.sample.lock();
+    if let Some((sampled_at, free)) = *sample {
+      if sampled_at.elapsed() < DISK_SPACE_SAMPLE_INTERVAL
+        && free.saturating_sub(written) >= reserve
+      {
+        return true;
+      }
+    }
+
+    // a volume whose free space is unknown is not held against the writes
+    let Some(free) = available_disk_space_of(dir_path) else {
+      return true;
+    };
+    *sample = Some((Instant::now(), free));
+    self.written.store(bytes, Ordering::Relaxed);
+    free.saturating_sub(bytes) >= reserve
+  }
+}
+
+impl Engine {
+  /// Fails with `Errors::DiskFull` if a write of `bytes` leaves less than
+  /// `reserved_disk_bytes` free on the volume of the database.
+  fn check_disk_reserve(&self, bytes: u64) -> Result<()> {
+    let reserve = self.options.reserved_disk_bytes;
+    match reserve == 0 || (self.disk_space).admit(&self.options.dir_path, reserve, bytes) {
+      true => Ok(()),
+      false => Err(Errors::DiskFull),
+    }
+  }
+
+  /// Throttles a write of `bytes`, optionally rejecting it while merge is behind or the disk
+  /// reserve is reached.
+  ///
+  /// Must be called before any engine lock is taken, it may sleep.
+  pub(crate) fn throttle_write(&self, bytes: usize, check_backlog: bool) -> Result<()> {
+    let backlog = self.options.max_reclaim_backlog;
+    if check_backlog && backlog > 0 && self.reclaim_size.load(Ordering::SeqCst) >= backlog {
+      return Err(Errors::Backpressure);
+    }
+    if check_backlog {
+      self.check_disk_reserve(bytes as u64)?;
+    }
+
+    self.write_limiter.throttle(bytes);
+    Ok(())
+  }
+
+  /// Changes the bytes per second merges read from the merged files, 0 means unlimited.
+  ///
+  /// Takes effect on the running merge as well, see `MergeScheduler::io_limit_bytes_per_sec`.
+  pub fn set_merge_io_limit(&self, bytes_per_sec: u64) {
+    let mut runtime_options = self.runtime_options.lock();
+    runtime_options.merge_io_limit_bytes_per_sec = Some(bytes_per_sec);
+    self.merge_limiter.set_rate(bytes_per_sec);
+  }
+
+  /// Throttles a merge which read `bytes` from the merged files.
+  pub(crate) fn throttle_merge_read(&self, bytes: usize) {
+    self.merge_limiter.throttle(bytes);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_write_rate_limiter() {
+    let limiter = RateLimiter::new(1000);
+    assert!(limiter.admit(500) < Duration::from_millis(1));
+
+    // the second half second is owed by the next writer
+    let wait = limiter.admit(500);
+    assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500));
+    let wait = limiter.admit(1);
+    assert!(wait > Duration::from_millis(900) && wait <= Duration::from_secs(1));
+  }
+
+  #[test]
+  fn test_sync_tuner_adaptive() {
+    let mut options = Options {
+      sync_policy: SyncPolicy::Adaptive {
+        min: 4 * 1024,
+        max: 1024 * 1024,
+      },
+      ..Default::default()
+    };
+    let tuner = SyncTuner::new(&options);
+    assert_eq!(4 * 1024, tuner.bytes_per_sync());
+
+    // a burst syncs rarely
+    for _ in 0..32 {
+      tuner.synced(64 * 1024 * 1024);
+    }
+    assert_eq!(1024 * 1024, tuner.bytes_per_sync());
+
+    // sparse writes after the burst retune without waiting for the next sync
+    tuner.appended(1);
+    assert_eq!(1024 * 1024, tuner.bytes_per_sync());
+    thread::sleep(ADAPTIVE_SYNC_INTERVAL);
+    tuner.appended(1);
+    assert!(tuner.bytes_per_sync() < 1024 * 1024);
+    for _ in 0..32 {
+      tuner.synced(64 * 1024 * 1024);
+    }
+
+    // a trickle syncs early again
+    for _ in 0..32 {
+      thread::sleep(Duration::from_millis(1));
+      tuner.synced(1);
+    }
+    assert_eq!(4 * 1024, tuner.bytes_per_sync());
+
+    // the fixed policy keeps `bytes_per_sync`
+    options.sync_policy = SyncPolicy::Fixed;
+    options.bytes_per_sync = 100;
+    let tuner = SyncTuner::new(&options);
+    tuner.synced(64 * 1024 * 1024);
+    assert_eq!(100, tuner.bytes_per_sync());
+  }
+
+  #[test]
+  fn test_rate_limiter_set_rate() {
+    let limiter = RateLimiter::new(0);
+    assert!(limiter.admit(1 << 30).is_zero());
+
+    limiter.set_rate(1000);
+    limiter.admit(1000);
+    assert!(limiter.admit(1) > Duration::from_millis(900));
+    // the debt of the old rate is dropped
+    limiter.set_rate(1_000_000);
+    assert!(limiter.admit(1000) < Duration::from_millis(1));
+  }
+}
@@ -0,0 +1,183 @@
+use std::{
+  collections::HashMap,
+  fs,
+  path::Path,
+  sync::atomic::{AtomicU32, Ordering},
+};
+
+use bytes::{Buf, BufMut, BytesMut};
+use log::{error, warn};
+use parking_lot::Mutex;
+
+use crate::{
+  batch::{log_record_key_with_seq, NON_TXN_SEQ_NO},
+  data::{
+    data_file::{get_tombstone_file_name, DataFile},
+    log_record::{LogRecord, LogRecordPos, LogRecordType},
+  },
+  db::Engine,
+  errors::{DataFileOp, Errors, Result},
+  option::IteratorOptions,
+};
+
+// key hash, file id, offset and size of the deleted record, then the crc of the entry
+const TOMBSTONE_ENTRY_SIZE: usize = 4 + 4 + 8 + 4 + 4;
+
+/// Tombstone sidecars of the data files, used when `tombstone_sidecar` is set.
+#[derive(Default)]
+pub(crate) struct TombstoneSidecars {
+  // sidecar of the active file, opened on its first delete
+  active: Mutex<Option<DataFile>>,
+  // records in files below it are deleted with delete records until the merge output is
+  // loaded, the merge output takes over their positions
+  merge_boundary: AtomicU32,
+}
+
+impl Engine {
+  /// Removes a key from the index and writes a marker naming its record to the tombstone
+  /// sidecar of the active file, returns the position of the deleted record.
+  pub(crate) fn delete_to_sidecar(&self, key: &[u8]) -> Result<Option<LogRecordPos>> {
+    // a merge rotates the active file before it reads the index
+    let active_file = self.active_data_file.read();
+    let Some(deleted_pos) = self.index.try_delete(key.to_vec())? else {
+      return Ok(None);
+    };
+
+    if deleted_pos.file_id < self.tombstones.merge_boundary.load(Ordering::SeqCst) {
+      drop(active_file);
+      let mut record = LogRecord {
+        key: log_record_key_with_seq(key, NON_TXN_SEQ_NO),
+        value: self.tombstone_value(Some(deleted_pos)),
+        rec_type: LogRecordType::Deleted,
+        timestamp: None,
+      };
+      match self.append_log_record(&mut record) {
+        Ok(pos) => self.mark_tombstone(pos),
+        Err(e) => {
+          self.restore_deleted(key, deleted_pos);
+          return Err(e);
+        }
+      }
+      return Ok(Some(deleted_pos));
+    }
+
+    if let Err(e) = self.append_sidecar_entry(active_file.get_file_id(), key, deleted_pos) {
+      // the key stays as long as its marker is not written
+      self.restore_deleted(key, deleted_pos);
+      return Err(e);
+    }
+    Ok(Some(deleted_pos))
+  }
+
+  // puts back a key whose delete failed, the failure of the delete is the one reported
+  fn restore_deleted(&self, key: &[u8], deleted_pos: LogRecordPos) {
+    if let Err(e) = self.index.try_put(key.to_vec(), deleted_pos) {
+      error!("failed to restore the index entry of a failed delete: {e}");
+    }
+  }
+
+  fn append_sidecar_entry(
+    &self,
+    file_id: u32,
+    key: &[u8],
+    deleted_pos: LogRecordPos,
+  ) -> Result<()> {
+    let mut active = self.tombstones.active.lock();
+    let sidecar = match active.take() {
+      Some(sidecar) if sidecar.get_file_id() == file_id => active.insert(sidecar),
+      _ => active.insert(DataFile::new_tombstone_file(
+        self.data_file_dir(file_id),
+        file_id,
+      )?),
+    };
+
+    let mut entry = BytesMut::with_capacity(TOMBSTONE_ENTRY_SIZE);
+    entry.put_u32(crc32fast::hash(key));
+    entry.put_u32(deleted_pos.file_id);
+    entry.put_u64(deleted_pos.offset);
+    entry.put_u32(deleted_pos.size);
+    entry.put_u32(crc32fast::hash(&entry));
+    sidecar.write(&entry)?;
+    if self.options.sync_writes {
+      sidecar.sync()?;
+    }
+    Ok(())
+  }
+
+  /// Syncs the tombstone sidecar of the active file, if it has one.
+  pub(crate) fn sync_tombstone_sidecar(&self) -> Result<()> {
+    match &*self.tombstones.active.lock() {
+      Some(sidecar) => sidecar.sync(),
+      None => Ok(()),
+    }
+  }
+
+  /// Lets deletes of records in files below `file_id` write delete records, the files are
+  /// replaced by a merge.
+  pub(crate) fn set_tombstone_merge_boundary(&self, file_id: u32) {
+    self
+      .tombstones
+      .merge_boundary
+      .fetch_max(file_id, Ordering::SeqCst);
+  }
+
+  /// Drops the keys whose record is named by a marker in the tombstone sidecars of
+  /// `file_ids`. Keys written again after their delete point to a newer record and stay.
+  pub(crate) fn apply_sidecar_tombstones(&self, file_ids: &[u32]) -> Result<()> {
+    let mut tombstones = HashMap::new();
+    for file_id in file_ids {
+      read_sidecar(&self.data_file_dir(*file_id), *file_id, &mut tombstones)?;
+    }
+    if tombstones.is_empty() {
+      return Ok(());
+    }
+
+    let mut deleted = Vec::new();
+    let mut iter = self.index.try_iterator(IteratorOptions::default())?;
+    while let Some((key, pos)) = iter.next() {
+      if tombstones.get(pos) == Some(&crc32fast::hash(key)) {
+        deleted.push((key.to_vec(), *pos));
+      }
+    }
+    drop(iter);
+
+    for (key, pos) in deleted {
+      self.index.try_delete(key.clone())?;
+      self.mark_stale(pos);
+      self.clear_expiry(&key);
+    }
+    Ok(())
+  }
+}
+
+/// Reads the markers of a tombstone sidecar by the position of the deleted record, the
+/// entries from the first torn one on are ignored.
+fn read_sidecar(
+  dir_path: &Path,
+  file_id: u32,
+  tombstones: &mut HashMap<LogRecordPos, u32>,
+) -> Result<()> {
+  let file_name = get_tombstone_file_name(dir_path, file_id);
+  if !file_name.is_file() {
+    return Ok(());
+  }
+  let buf = fs::read(&file_name).map_err(|e| Errors::data_file_io(DataFileOp::Read, e))?;
+
+  for mut entry in buf.chunks(TOMBSTONE_ENTRY_SIZE) {
+    if entry.len() < TOMBSTONE_ENTRY_SIZE
+      || crc32fast::hash(&entry[..TOMBSTONE_ENTRY_SIZE - 4])
+        != u32::from_be_bytes([entry[20], entry[21], entry[22], entry[23]])
+    {
+      warn!("tombstone sidecar of data file {file_id} has a torn entry, ignoring the rest");
+      break;
+    }
+    let key_hash = entry.get_u32();
+    let pos = LogRecordPos {
+      file_id: entry.get_u32(),
+      offset: entry.get_u64(),
+      size: entry.get_u32(),
+    };
+    tombstones.insert(pos, key_hash);
+  }
+  Ok(())
+}
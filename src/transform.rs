@@ -0,0 +1,181 @@
+use std::ops::ControlFlow;
+
+use bytes::Bytes;
+
+use crate::{
+  db::Engine,
+  errors::Result,
+  option::{ChunkAtomicity, IteratorOptions, WriteBatchOptions},
+};
+
+/// Progress of [`Engine::transform`], reported after every committed chunk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransformProgress {
+  /// Keys passed to the transform function
+  pub scanned: usize,
+
+  /// Keys rewritten with a new value
+  pub updated: usize,
+
+  pub deleted: usize,
+
+  /// Transactions committed
+  pub chunks: usize,
+}
+
+impl Engine {
+  /// Rewrites the values of the keys starting with `prefix`: `f` returns the new value of a
+  /// key, `None` deletes it. Values returned unchanged are not written.
+  ///
+  /// Keys are visited in order and their writes committed in chunks of at most
+  /// `WriteBatchOptions::default().max_batch_num` keys, each chunk atomically. Chunks
+  /// committed before a failure are kept. Writes to the same keys made concurrently may be
+  /// overwritten.
+  pub fn transform<F>(&self, prefix: &[u8], f: F) -> Result<TransformProgress>
+  where
+    F: FnMut(&[u8], Bytes) -> Option<Bytes>,
+  {
+    self.transform_with_progress(prefix, WriteBatchOptions::default(), f, |_| {})
+  }
+
+  /// Like `transform`, with the batch limits of the chunks in `options` and `progress`
+  /// called after every committed chunk.
+  pub fn transform_with_progress<F, P>(
+    &self,
+    prefix: &[u8],
+    options: WriteBatchOptions,
+    mut f: F,
+    mut progress: P,
+  ) -> Result<TransformProgress>
+  where
+    F: FnMut(&[u8], Bytes) -> Option<Bytes>,
+    P: FnMut(&TransformProgress),
+  {
+    self.check_open()?;
+    let chunk_keys = options.max_batch_num.max(1);
+    let mut stats = TransformProgress::default();
+    let mut start_after = None;
+    loop {
+      // read a chunk before writing, the writes do not disturb the iteration
+      let mut pairs = Vec::with_capacity(chunk_keys);
+      let iter_opts = IteratorOptions {
+        prefix: prefix.to_vec(),
+        limit: Some(chunk_keys),
+        start_after: start_after.take(),
+        ..Default::default()
+      };
+      self.for_each(iter_opts, |key, value| {
+        pairs.push((key, value));
+        ControlFlow::Continue(())
+      })?;
+      let Some((last_key, _)) = pairs.last() else {
+        break;
+      };
+      start_after = Some(last_key.to_vec());
+
+      let batch = self.new_write_batch(WriteBatchOptions {
+        max_batch_num: options.max_batch_num,
+        max_batch_bytes: options.max_batch_bytes,
+        sync_writes: options.sync_writes,
+      })?;
+      for (key, value) in pairs.iter() {
+        stats.scanned += 1;
+        match f(key, value.clone()) {
+          Some(new_value) if new_value == *value => {}
+          Some(new_value) => {
+            batch.put(key.clone(), new_value)?;
+            stats.updated += 1;
+          }
+          None => {
+            batch.delete(key.clone())?;
+            stats.deleted += 1;
+          }
+        }
+      }
+      stats.chunks += batch.commit_chunked(ChunkAtomicity::PerChunk)?;
+      progress(&stats);
+
+      if pairs.len() < chunk_keys {
+        break;
+      }
+    }
+    Ok(stats)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{errors::Errors, option::Options};
+
+  #[test]
+  fn test_transform() {
+    let dir = tempfile::tempdir().unwrap();
+    let opts = Options {
+      dir_path: dir.path().to_path_buf(),
+      ..Default::default()
+    };
+    let engine = Engine::open(opts).expect("fail to open engine");
+    for i in 0..25 {
+      let value = Bytes::from(i.to_string());
+      engine
+        .put(Bytes::from(format!("user:{i:02}")), value.clone())
+        .unwrap();
+      engine
+        .put(Bytes::from(format!("order:{i:02}")), value)
+        .unwrap();
+    }
+
+    // double even values, delete multiples of five, keep the rest
+    let transform = |_: &[u8], value: Bytes| {
+      let n: u32 = std::str::from_utf8(&value).ok()?.parse().ok()?;
+      match n {
+        n if n % 5 == 0 => None,
+        n if n % 2 == 0 => Some(Bytes::from((n * 2).to_string())),
+        _ => Some(value),
+      }
+    };
+    let mut reports = Vec::new();
+    let wb_opts = WriteBatchOptions {
+      max_batch_num: 10,
+      ..Default::default()
+    };
+    let stats = engine
+      .transform_with_progress(b"user:", wb_opts, transform, |p| reports.push(*p))
+      .unwrap();
+    let expected = TransformProgress {
+      scanned: 25,
+      updated: 10,
+      deleted: 5,
+      chunks: 3,
+    };
+    assert_eq!(expected, stats);
+    assert_eq!(3, reports.len());
+    assert_eq!(10, reports[0].scanned);
+    assert_eq!(Some(&expected), reports.last());
+
+    assert_eq!(
+      Bytes::from("8"),
+      engine.get(Bytes::from("user:04")).unwrap()
+    );
+    assert_eq!(
+      Bytes::from("7"),
+      engine.get(Bytes::from("user:07")).unwrap()
+    );
+    assert_eq!(
+      Errors::KeyNotFound,
+      engine.get(Bytes::from("user:10")).unwrap_err()
+    );
+    assert_eq!(20, engine.count_prefix(b"user:").unwrap());
+    // other prefixes are left alone
+    assert_eq!(
+      Bytes::from("4"),
+      engine.get(Bytes::from("order:04")).unwrap()
+    );
+    assert_eq!(25, engine.count_prefix(b"order:").unwrap());
+
+    // a prefix without keys commits nothing
+    let stats = engine.transform(b"missing:", |_, _| None).unwrap();
+    assert_eq!(TransformProgress::default(), stats);
+  }
+}
@@ -0,0 +1,158 @@
+use std::sync::atomic::Ordering;
+
+use crate::{
+  db::Engine,
+  errors::Result,
+  event::OptionsUpdate,
+  option::{Options, RuntimeOptions},
+};
+
+impl Engine {
+  /// Current values of the tunables changed by [`Engine::update_options`], every field is
+  /// set.
+  pub fn runtime_options(&self) -> RuntimeOptions {
+    *self.runtime_options.lock()
+  }
+
+  /// Changes tunables of the open engine without reopening it, fields of `update` left
+  /// `None` keep their value.
+  ///
+  /// The new values are checked like [`crate::option::Options::validate`] checks them on
+  /// open, an invalid update changes nothing. The changes are not persisted, the next open
+  /// uses its `Options` again. Event listeners are told about updates changing a value.
+  pub fn update_options(&self, update: RuntimeOptions) -> Result<()> {
+    self.check_open()?;
+    let mut runtime_options = self.runtime_options.lock();
+    let mut options = Options::clone(&self.options);
+    runtime_options.apply_to(&mut options);
+    update.apply_to(&mut options);
+    options.validate()?;
+
+    let previous = *runtime_options;
+    let current = RuntimeOptions::new(&options);
+    if current == previous {
+      return Ok(());
+    }
+    if (current.sync_policy, current.bytes_per_sync)
+      != (previous.sync_policy, previous.bytes_per_sync)
+    {
+      self
+        .sync_tuner
+        .reconfigure(options.sync_policy, options.bytes_per_sync);
+    }
+    self
+      .merge_threshold
+      .store(options.file_merge_threshold.to_bits(), Ordering::Relaxed);
+    if current.max_write_rate_bytes_per_sec != previous.max_write_rate_bytes_per_sec {
+      self
+        .write_limiter
+        .set_rate(options.max_write_rate_bytes_per_sec);
+    }
+    if current.merge_io_limit_bytes_per_sec != previous.merge_io_limit_bytes_per_sec {
+      self
+        .merge_limiter
+        .set_rate(options.merge_scheduler.io_limit_bytes_per_sec);
+    }
+    if current.index_hot_keys != previous.index_hot_keys {
+      self.index.set_hot_keys(options.index_hot_keys);
+    }
+    *runtime_options = current;
+    drop(runtime_options);
+
+    // listeners may read the options again
+    self
+      .listeners
+      .options_updated(&OptionsUpdate { previous, current });
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use parking_lot::Mutex;
+
+  use super::*;
+  use crate::{
+    errors::Errors,
+    event::EventListener,
+    option::SyncPolicy,
+    util::rand_kv::{get_test_key, get_test_value},
+  };
+
+  #[derive(Default)]
+  struct UpdateCollector {
+    updates: Mutex<Vec<OptionsUpdate>>,
+  }
+
+  impl EventListener for UpdateCollector {
+    fn on_options_updated(&self, update: &OptionsUpdate) {
+      self.updates.lock().push(*update);
+    }
+  }
+
+  #[test]
+  fn test_update_options() {
+    let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+    let opts = Options {
+      dir_path: temp_dir.path().to_path_buf(),
+      data_file_size: 8 * 1024,
+      ..Default::default()
+    };
+    let engine = Engine::open(opts.clone()).expect("fail to open engine");
+    let collector = Arc::new(UpdateCollector::default());
+    engine.add_event_listener(collector.clone());
+    for i in 0..300 {
+      engine.put(get_test_key(i), get_test_value(i)).unwrap();
+    }
+    for i in 0..30 {
+      engine.delete(get_test_key(i)).unwrap();
+    }
+    assert_eq!(RuntimeOptions::new(&opts), engine.runtime_options());
+    assert_eq!(Err(Errors::MergeThresholdUnreached), engine.merge());
+
+    // an invalid update changes nothing
+    let update = RuntimeOptions {
+      file_merge_threshold: Some(0.0),
+      sync_policy: Some(SyncPolicy::Adaptive { min: 8, max: 4 }),
+      ..Default::default()
+    };
+    assert_eq!(
+      Err(Errors::InvalidSyncPolicy),
+      engine.update_options(update)
+    );
+    assert_eq!(RuntimeOptions::new(&opts), engine.runtime_options());
+    assert!(collector.updates.lock().is_empty());
+
+    let update = RuntimeOptions {
+      file_merge_threshold: Some(0.0),
+      sync_policy: Some(SyncPolicy::Adaptive {
+        min: 4096,
+        max: 65536,
+      }),
+      ..Default::default()
+    };
+    engine.update_options(update).unwrap();
+    assert_eq!(4096, engine.get_engine_stat().unwrap().bytes_per_sync);
+    let current = engine.runtime_options();
+    assert_eq!(Some(0.0), current.file_merge_threshold);
+    assert_eq!(
+      vec![OptionsUpdate {
+        previous: RuntimeOptions::new(&opts),
+        current,
+      }],
+      *collector.updates.lock()
+    );
+    engine.merge().unwrap();
+
+    // an update to the current values is no change
+    engine.update_options(update).unwrap();
+    engine.set_merge_io_limit(1 << 20);
+    assert_eq!(
+      Some(1 << 20),
+      engine.runtime_options().merge_io_limit_bytes_per_sec
+    );
+    assert_eq!(1, collector.updates.lock().len());
+  }
+}
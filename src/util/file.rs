@@ -8,11 +8,25 @@ pub fn available_disk_space() -> u64 {
   fs2::available_space(PathBuf::from("/")).unwrap_or_default()
 }
 
+/// Available space of the volume holding `path`, None if it can not be determined.
+pub fn available_disk_space_of<P: AsRef<Path>>(path: P) -> Option<u64> {
+  fs2::available_space(path).ok()
+}
+
 // calculate the total size of directory in disk
 pub fn dir_disk_size<P: AsRef<Path>>(dir_path: P) -> u64 {
   fs_extra::dir::get_size(dir_path).unwrap_or_default()
 }
 
+/// Flushes the entries of a directory, making renames and removals in it durable.
+pub fn sync_dir<P: AsRef<Path>>(dir_path: P) -> io::Result<()> {
+  #[cfg(unix)]
+  fs::File::open(dir_path)?.sync_all()?;
+  #[cfg(not(unix))]
+  let _ = dir_path;
+  Ok(())
+}
+
 pub fn copy_dir<P: AsRef<Path>>(src: P, dst: P, exclude: &[&str]) -> io::Result<()> {
   //
   if !dst.as_ref().exists() {
@@ -27,7 +41,7 @@ pub fn copy_dir<P: AsRef<Path>>(src: P, dst: P, exclude: &[&str]) -> io::Result<
       continue;
     }
 
-    let dst_path = dst.as_ref().join(src_path.file_name().unwrap());
+    let dst_path = dst.as_ref().join(entry.file_name());
     if entry.file_type()?.is_dir() {
       copy_dir(src_path, dst_path, exclude)?;
     } else {
@@ -0,0 +1,267 @@
+use std::{
+  collections::HashMap,
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TryRecvError, TrySendError},
+    Arc,
+  },
+  time::Duration,
+};
+
+use bytes::Bytes;
+use parking_lot::RwLock;
+
+use crate::db::Engine;
+
+/// Number of events buffered per watcher by `Engine::watch`.
+pub const DEFAULT_WATCH_BUFFER: usize = 1024;
+
+/// Type of change delivered to a watcher.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchOp {
+  Put,
+
+  Delete,
+}
+
+/// A change notification for a watched key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchEvent {
+  /// The changed key
+  pub key: Bytes,
+
+  /// The kind of change
+  pub op: WatchOp,
+
+  /// The new value, `None` for deletes
+  pub value: Option<Bytes>,
+}
+
+struct Watcher {
+  prefix: Bytes,
+  sender: SyncSender<WatchEvent>,
+  lagged: Arc<AtomicU64>, // events dropped because the buffer was full
+}
+
+/// Registry of active watchers of an engine.
+#[derive(Default)]
+pub(crate) struct WatchRegistry {
+  watchers: RwLock<HashMap<u64, Watcher>>,
+  next_id: AtomicU64,
+}
+
+impl WatchRegistry {
+  /// Notify all watchers whose prefix matches the key.
+  pub(crate) fn notify(&self, key: &[u8], op: WatchOp, value: Option<&[u8]>) {
+    let mut disconnected = Vec::new();
+    {
+      let watchers = self.watchers.read();
+      if watchers.is_empty() {
+        return;
+      }
+      for (id, watcher) in watchers.iter() {
+        if !key.starts_with(&watcher.prefix) {
+          continue;
+        }
+        let event = WatchEvent {
+          key: Bytes::copy_from_slice(key),
+          op,
+          value: value.map(Bytes::copy_from_slice),
+        };
+        // a slow watcher never blocks writers, it loses events and sees the lag count grow
+        match watcher.sender.try_send(event) {
+          Ok(()) => {}
+          Err(TrySendError::Full(_)) => {
+            watcher.lagged.fetch_add(1, Ordering::SeqCst);
+          }
+          Err(TrySendError::Disconnected(_)) => disconnected.push(*id),
+        }
+      }
+    }
+
+    // receiver is gone, remove watcher
+    if !disconnected.is_empty() {
+      let mut watchers = self.watchers.write();
+      for id in disconnected {
+        watchers.remove(&id);
+      }
+    }
+  }
+
+  fn register(&self, prefix: Bytes, sender: SyncSender<WatchEvent>, lagged: Arc<AtomicU64>) -> u64 {
+    let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+    self.watchers.write().insert(
+      id,
+      Watcher {
+        prefix,
+        sender,
+        lagged,
+      },
+    );
+    id
+  }
+
+  fn unregister(&self, id: u64) {
+    self.watchers.write().remove(&id);
+  }
+}
+
+/// Receiving end of a watch, the watch is cancelled when the handle is dropped.
+pub struct WatchHandle {
+  id: u64,
+  receiver: Receiver<WatchEvent>,
+  lagged: Arc<AtomicU64>,
+  registry: Arc<WatchRegistry>,
+}
+
+impl Engine {
+  /// Watches changes of keys starting with `prefix`, an empty prefix watches all keys.
+  ///
+  /// Puts, deletes and committed write batches are delivered in the order they are applied
+  /// to the index. At most `DEFAULT_WATCH_BUFFER` events are buffered, see `watch_with_buffer`.
+  pub fn watch(&self, prefix: Bytes) -> WatchHandle {
+    self.watch_with_buffer(prefix, DEFAULT_WATCH_BUFFER)
+  }
+
+  /// Watches changes of keys starting with `prefix`, buffering at most `buffer` events.
+  ///
+  /// Events arriving while the buffer is full are dropped and counted by `WatchHandle::lagged`.
+  pub fn watch_with_buffer(&self, prefix: Bytes, buffer: usize) -> WatchHandle {
+    let (sender, receiver) = mpsc::sync_channel(buffer.max(1));
+    let lagged = Arc::new(AtomicU64::new(0));
+    let id = self.watchers.register(prefix, sender, lagged.clone());
+    WatchHandle {
+      id,
+      receiver,
+      lagged,
+      registry: self.watchers.clone(),
+    }
+  }
+}
+
+impl WatchHandle {
+  /// Blocks until the next event arrives.
+  pub fn recv(&self) -> Option<WatchEvent> {
+    self.receiver.recv().ok()
+  }
+
+  /// Returns the next event if one is pending.
+  pub fn try_recv(&self) -> Option<WatchEvent> {
+    match self.receiver.try_recv() {
+      Ok(event) => Some(event),
+      Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => None,
+    }
+  }
+
+  /// Waits for the next event at most `timeout`.
+  pub fn recv_timeout(&self, timeout: Duration) -> Option<WatchEvent> {
+    match self.receiver.recv_timeout(timeout) {
+      Ok(event) => Some(event),
+      Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => None,
+    }
+  }
+
+  /// Returns the number of events dropped since the last call because the buffer was full.
+  pub fn lagged(&self) -> u64 {
+    self.lagged.swap(0, Ordering::SeqCst)
+  }
+}
+
+impl Drop for WatchHandle {
+  fn drop(&mut self) {
+    self.registry.unregister(self.id);
+  }
+}
+
+#[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
+mod tests {
+  use tempfile::tempdir;
+
+  use super::*;
+  use crate::option::{Options, WriteBatchOptions};
+
+  #[test]
+  fn test_watch_prefix() {
+    let temp_dir = tempdir().expect("failed to create temp dir");
+    let mut opts = Options::default();
+    opts.dir_path = temp_dir.path().to_path_buf();
+    let engine = Engine::open(opts).expect("failed to open engine");
+
+    let watch = engine.watch(Bytes::from("user:"));
+    engine
+      .put(Bytes::from("user:1"), Bytes::from("alice"))
+      .unwrap();
+    engine
+      .put(Bytes::from("order:1"), Bytes::from("book"))
+      .unwrap();
+    engine.delete(Bytes::from("user:1")).unwrap();
+
+    assert_eq!(
+      Some(WatchEvent {
+        key: Bytes::from("user:1"),
+        op: WatchOp::Put,
+        value: Some(Bytes::from("alice")),
+      }),
+      watch.try_recv()
+    );
+    assert_eq!(
+      Some(WatchEvent {
+        key: Bytes::from("user:1"),
+        op: WatchOp::Delete,
+        value: None,
+      }),
+      watch.try_recv()
+    );
+    assert!(watch.try_recv().is_none());
+
+    // dropped watcher is unregistered
+    drop(watch);
+    assert!(engine.watchers.watchers.read().is_empty());
+  }
+
+  #[test]
+  fn test_watch_batch_commit() {
+    let temp_dir = tempdir().expect("failed to create temp dir");
+    let mut opts = Options::default();
+    opts.dir_path = temp_dir.path().to_path_buf();
+    let engine = Engine::open(opts).expect("failed to open engine");
+
+    let watch = engine.watch(Bytes::new());
+    let wb = engine
+      .new_write_batch(WriteBatchOptions::default())
+      .unwrap();
+    wb.put(Bytes::from("a"), Bytes::from("1")).unwrap();
+    wb.put(Bytes::from("b"), Bytes::from("2")).unwrap();
+
+    // nothing is delivered before commit
+    assert!(watch.try_recv().is_none());
+    wb.commit().unwrap();
+
+    let mut keys = vec![watch.try_recv().unwrap().key, watch.try_recv().unwrap().key];
+    keys.sort();
+    assert_eq!(vec![Bytes::from("a"), Bytes::from("b")], keys);
+  }
+
+  #[test]
+  fn test_watch_slow_watcher_lags() {
+    let temp_dir = tempdir().expect("failed to create temp dir");
+    let mut opts = Options::default();
+    opts.dir_path = temp_dir.path().to_path_buf();
+    let engine = Engine::open(opts).expect("failed to open engine");
+
+    let watch = engine.watch_with_buffer(Bytes::new(), 2);
+    for i in 0..5 {
+      engine
+        .put(Bytes::from(format!("key-{i}")), Bytes::from("v"))
+        .unwrap();
+    }
+
+    // writes are not blocked, the overflow is dropped and counted
+    assert_eq!(3, watch.lagged());
+    assert_eq!(0, watch.lagged());
+    assert_eq!(Bytes::from("key-0"), watch.try_recv().unwrap().key);
+    assert_eq!(Bytes::from("key-1"), watch.try_recv().unwrap().key);
+    assert!(watch.try_recv().is_none());
+  }
+}